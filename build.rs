@@ -0,0 +1,50 @@
+//! Embeds build metadata (git commit, build time, rustc version, enabled
+//! features) as `RELAYER_*` environment variables read by `src/build_info.rs`.
+
+use std::{
+    env,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+fn main() {
+    let commit = output("git", &["rev-parse", "HEAD"]).unwrap_or_else(|| "unknown".to_string());
+    // 工作区有未提交的修改时在提交后加 -dirty
+    let dirty = output("git", &["status", "--porcelain", "--untracked-files=no"])
+        .is_some_and(|status| !status.is_empty());
+    let commit = if dirty {
+        format!("{}-dirty", commit)
+    } else {
+        commit
+    };
+    let built_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc = output(&rustc, &["--version"]).unwrap_or_else(|| "unknown".to_string());
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(key, _)| {
+            let feature = key.strip_prefix("CARGO_FEATURE_")?;
+            Some(feature.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+
+    println!("cargo:rustc-env=RELAYER_GIT_COMMIT={}", commit);
+    println!("cargo:rustc-env=RELAYER_BUILT_AT={}", built_at);
+    println!("cargo:rustc-env=RELAYER_RUSTC_VERSION={}", rustc);
+    println!("cargo:rustc-env=RELAYER_FEATURES={}", features.join(","));
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+}
+
+/// Trimmed stdout of a successful command
+fn output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
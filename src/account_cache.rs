@@ -0,0 +1,327 @@
+//! Account-index cache in front of the L1 RPC.
+//! With `[l1_account_cache]` set, the watched account is read from the cache
+//! service first: `GET {url}/accounts/{pubkey}` answers
+//! `{"slot": u64, "owner": base58, "data": base64}`, or 404 when the service
+//! does not hold the account. A cached read is used only if its slot is not
+//! below the last read of the watched account and trails the L1 RPC's slot,
+//! read every `slot_check_secs`, by at most `max_staleness_slots`. When the
+//! cache is stale, does not hold the account or fails, the account is read
+//! from the RPC as without a cache. Transfer-info PDAs are always read from
+//! the RPC, in a snapshot with the watched account.
+
+use crate::{config::AccountCacheConfig, Relayer};
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use solana_sdk::pubkey::Pubkey;
+use std::{
+    mem,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// An account as the cache holds it
+#[derive(Debug, Clone)]
+pub struct CachedAccount {
+    /// L1 slot of the data
+    pub slot: u64,
+    pub owner: Pubkey,
+    pub data: Vec<u8>,
+}
+
+/// A source of cached L1 accounts
+#[async_trait]
+pub trait AccountCache: Send + Sync {
+    /// The cached `address`, or `None` if the cache does not hold it
+    async fn get_account(&self, address: &Pubkey) -> Result<Option<CachedAccount>>;
+}
+
+#[derive(Deserialize)]
+struct CacheResponse {
+    slot: u64,
+    owner: String,
+    data: String,
+}
+
+/// HTTP client of the account-index service
+pub struct HttpAccountCache {
+    url: String,
+    token: Option<String>,
+    http: reqwest::Client,
+}
+
+impl HttpAccountCache {
+    pub fn new(config: &AccountCacheConfig) -> Result<Self> {
+        Ok(Self {
+            url: config.url.expose().trim_end_matches('/').to_string(),
+            token: config.token.as_ref().map(|token| token.expose().clone()),
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_millis(config.timeout_ms.max(1)))
+                .build()?,
+        })
+    }
+}
+
+#[async_trait]
+impl AccountCache for HttpAccountCache {
+    async fn get_account(&self, address: &Pubkey) -> Result<Option<CachedAccount>> {
+        let mut request = self.http.get(format!("{}/accounts/{}", self.url, address));
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        // 错误信息不带 URL，避免泄露其中的密钥
+        let response = request
+            .send()
+            .await
+            .map_err(|e| anyhow::anyhow!("Account cache request failed: {}", e.without_url()))?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response: CacheResponse = response
+            .error_for_status()
+            .map_err(|e| anyhow::anyhow!("Account cache request failed: {}", e.without_url()))?
+            .json()
+            .await
+            .map_err(|e| anyhow::anyhow!("Invalid account cache response: {}", e.without_url()))?;
+        Ok(Some(CachedAccount {
+            slot: response.slot,
+            owner: Pubkey::from_str(&response.owner)
+                .map_err(|e| anyhow::anyhow!("Invalid owner in account cache response: {}", e))?,
+            data: base64::decode(&response.data)
+                .map_err(|e| anyhow::anyhow!("Invalid data in account cache response: {}", e))?,
+        }))
+    }
+}
+
+/// Why a cached read was not used
+#[derive(Debug, Clone)]
+enum Fallback {
+    Failed(String),
+    NotCached,
+    Stale { slot: u64, rpc_slot: u64 },
+}
+
+/// The cache with its freshness settings and the RPC slot it is judged by
+pub struct AccountCacheReader {
+    cache: Arc<dyn AccountCache>,
+    max_staleness_slots: u64,
+    slot_check: Duration,
+    /// Last slot read from the RPC and the Unix time of that read
+    rpc_slot: Mutex<Option<(u64, u64)>>,
+    /// Last fallback, so a fallback is logged when it starts, not every poll
+    fallback: Mutex<Option<Fallback>>,
+}
+
+impl AccountCacheReader {
+    pub fn new(
+        cache: Arc<dyn AccountCache>,
+        max_staleness_slots: u64,
+        slot_check: Duration,
+    ) -> Self {
+        Self {
+            cache,
+            max_staleness_slots,
+            slot_check,
+            rpc_slot: Mutex::new(None),
+            fallback: Mutex::new(None),
+        }
+    }
+
+    pub fn from_config(config: &AccountCacheConfig) -> Result<Self> {
+        Ok(Self::new(
+            Arc::new(HttpAccountCache::new(config)?),
+            config.max_staleness_slots,
+            Duration::from_secs(config.slot_check_secs),
+        ))
+    }
+
+    /// Records a slot the RPC reported, e.g. of a fallback read
+    pub fn observe_rpc_slot(&self, slot: u64, now: u64) {
+        let mut rpc_slot = self.rpc_slot.lock().unwrap();
+        if rpc_slot.is_none_or(|(seen, _)| slot >= seen) {
+            *rpc_slot = Some((slot, now));
+        }
+    }
+
+    /// Logs `fallback` if it is of another kind than the previous read's
+    fn fall_back(&self, fallback: Option<Fallback>) {
+        let mut last = self.fallback.lock().unwrap();
+        if last.as_ref().map(mem::discriminant) == fallback.as_ref().map(mem::discriminant) {
+            return;
+        }
+        match &fallback {
+            Some(Fallback::Failed(error)) => tracing::warn!(
+                "account cache unavailable, reading the watched account from the L1 RPC: {}",
+                error
+            ),
+            Some(Fallback::NotCached) => tracing::warn!(
+                "account cache does not hold the watched account, reading it from the L1 RPC"
+            ),
+            Some(Fallback::Stale { slot, rpc_slot }) => tracing::warn!(
+                "account cache is at slot {}, {} behind the L1 RPC; reading the watched account from the RPC",
+                slot,
+                rpc_slot.saturating_sub(*slot)
+            ),
+            None => tracing::info!("Reading the watched account from the account cache again"),
+        }
+        *last = fallback;
+    }
+}
+
+impl Relayer {
+    /// The L1 RPC's slot, read again once the last read is `slot_check` old
+    async fn reference_slot(&self, cache: &AccountCacheReader) -> Option<u64> {
+        let now = self.clock.unix_timestamp();
+        let known = *cache.rpc_slot.lock().unwrap();
+        if known.is_some_and(|(_, at)| now < at + cache.slot_check.as_secs()) {
+            return known.map(|(slot, _)| slot);
+        }
+        match self
+            .l1_client
+            .get_slot_with_commitment(self.l1_client.commitment())
+            .await
+        {
+            Ok(slot) => {
+                cache.observe_rpc_slot(slot, now);
+                Some(slot.max(known.map_or(0, |(slot, _)| slot)))
+            }
+            // RPC 不可用时沿用上次的 slot
+            Err(_) => known.map(|(slot, _)| slot),
+        }
+    }
+
+    /// The watched account from the account cache, if one is configured and
+    /// its copy is fresh enough; `None` means the RPC must be read
+    pub(crate) async fn cached_watched_account(&self) -> Option<CachedAccount> {
+        let cache = self.account_cache.as_ref()?;
+        let fallback = match cache.cache.get_account(self.watched_account.pubkey()).await {
+            Err(e) => Fallback::Failed(e.to_string()),
+            Ok(None) => Fallback::NotCached,
+            Ok(Some(account)) => {
+                let rpc_slot = self.reference_slot(cache).await.unwrap_or(account.slot);
+                let lag = rpc_slot.saturating_sub(account.slot);
+                self.metrics.l1_cache_staleness_slots.set(lag);
+                // 不能比上次读到的 watched account 更旧，否则会被当作回退
+                let behind_last = self
+                    .watched
+                    .last_slot()
+                    .is_some_and(|last| account.slot < last);
+                if lag <= cache.max_staleness_slots && !behind_last {
+                    self.metrics.l1_cache_hits_total.inc();
+                    cache.fall_back(None);
+                    return Some(account);
+                }
+                self.metrics.l1_cache_stale_total.inc();
+                Fallback::Stale {
+                    slot: account.slot,
+                    rpc_slot: rpc_slot.max(self.watched.last_slot().unwrap_or_default()),
+                }
+            }
+        };
+        if matches!(fallback, Fallback::Failed(_) | Fallback::NotCached) {
+            self.metrics.l1_cache_failures_total.inc();
+        }
+        cache.fall_back(Some(fallback));
+        None
+    }
+
+    /// Feeds the slot of an RPC read of the watched account to the cache's
+    /// freshness check
+    pub(crate) fn observe_l1_rpc_slot(&self, slot: u64) {
+        if let Some(cache) = &self.account_cache {
+            cache.observe_rpc_slot(slot, self.clock.unix_timestamp());
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use crate::{
+        testing::{self, FakeAccountCache, Harness},
+        Relayer,
+    };
+    use solana_sdk::pubkey::Pubkey;
+
+    /// A relayer reading the watched account, at nonce 3 on the RPC, through
+    /// `cache` with `max_staleness_slots`
+    async fn cached_relayer(
+        cache: &FakeAccountCache,
+        max_staleness_slots: u64,
+    ) -> (Harness, Relayer) {
+        let harness = Harness::new(testing::config()).unwrap();
+        harness.l1.set_nonce(3);
+        let mut relayer = harness.relayer().await.unwrap();
+        relayer.account_cache = Some(cache.reader(max_staleness_slots));
+        (harness, relayer)
+    }
+
+    fn cache_nonce(cache: &FakeAccountCache, relayer: &Relayer, slot: u64, nonce: u64) {
+        cache.set_account(
+            *relayer.watched_account.pubkey(),
+            slot,
+            Pubkey::default(),
+            nonce.to_le_bytes().to_vec(),
+        );
+    }
+
+    #[tokio::test]
+    async fn a_fresh_cached_account_is_read_instead_of_the_rpc() {
+        let cache = FakeAccountCache::default();
+        let (harness, relayer) = cached_relayer(&cache, 10).await;
+        let slot = harness.l1.slot();
+        cache_nonce(&cache, &relayer, slot, 5);
+
+        assert_eq!(relayer.read_l1_nonce().await.unwrap(), 5);
+        assert_eq!(cache.reads(), 1);
+        assert_eq!(relayer.metrics.l1_cache_hits_total.get(), 1);
+        // 只向 RPC 读了 slot
+        assert_eq!(
+            relayer.metrics.l1_cache_staleness_slots.get(),
+            harness.l1.slot() - slot
+        );
+    }
+
+    #[tokio::test]
+    async fn a_stale_cached_account_falls_back_to_the_rpc() {
+        let cache = FakeAccountCache::default();
+        let (harness, relayer) = cached_relayer(&cache, 0).await;
+        cache_nonce(&cache, &relayer, harness.l1.slot(), 5);
+
+        assert_eq!(relayer.read_l1_nonce().await.unwrap(), 3);
+        assert_eq!(relayer.metrics.l1_cache_hits_total.get(), 0);
+        assert_eq!(relayer.metrics.l1_cache_stale_total.get(), 1);
+        assert_eq!(relayer.metrics.l1_cache_failures_total.get(), 0);
+    }
+
+    #[tokio::test]
+    async fn a_cached_account_behind_the_last_read_is_not_used() {
+        let cache = FakeAccountCache::default();
+        let (harness, relayer) = cached_relayer(&cache, 1_000).await;
+        let before = harness.l1.slot();
+        assert_eq!(relayer.read_l1_nonce().await.unwrap(), 3);
+
+        // 缓存中的副本比上次 RPC 读到的更旧
+        cache_nonce(&cache, &relayer, before, 5);
+        assert_eq!(relayer.read_l1_nonce().await.unwrap(), 3);
+        assert_eq!(relayer.metrics.l1_cache_stale_total.get(), 1);
+
+        cache_nonce(&cache, &relayer, harness.l1.slot() + 1, 5);
+        assert_eq!(relayer.read_l1_nonce().await.unwrap(), 5);
+    }
+
+    #[tokio::test]
+    async fn a_missing_or_failing_cache_falls_back_to_the_rpc() {
+        let cache = FakeAccountCache::default();
+        let (_harness, relayer) = cached_relayer(&cache, 10).await;
+        assert_eq!(relayer.read_l1_nonce().await.unwrap(), 3);
+
+        cache_nonce(&cache, &relayer, 0, 5);
+        cache.set_unavailable(true);
+        assert_eq!(relayer.read_l1_nonce().await.unwrap(), 3);
+        assert_eq!(cache.reads(), 2);
+        assert_eq!(relayer.metrics.l1_cache_failures_total.get(), 2);
+        assert_eq!(relayer.metrics.l1_cache_hits_total.get(), 0);
+    }
+}
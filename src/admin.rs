@@ -0,0 +1,118 @@
+//! Operator controls shared by the admin API and the CLI.
+//! A pause set through the admin API is persisted under `ADMIN_PAUSE_KEY`,
+//! so a restarted relayer stays paused until resumed, and holds new batches
+//! only. `replay_nonce` backs `POST /replay/{nonce}` and `dlq replay`: it
+//! resolves a dead letter as `replayed` or clears a failed nonce's backoff,
+//! and never sends a nonce with a receipt again. The HTTP API itself is in
+//! `admin_api`, built with the `admin` feature.
+
+use crate::{
+    dlq::{DeadLetterQueue, Resolution, ResolutionKind},
+    queue::PendingQueue,
+    receipts::Receipt,
+    state::StateStore,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+/// State key holding the pause set through the admin API
+pub const ADMIN_PAUSE_KEY: &str = "admin_pause";
+
+/// Who paused submissions through the admin API, and when
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminPause {
+    pub by: String,
+    pub at: u64,
+}
+
+/// Submissions held by an operator, persisted across restarts
+pub struct ManualPause {
+    pause: Mutex<Option<AdminPause>>,
+}
+
+impl ManualPause {
+    pub fn load(store: &StateStore) -> Result<Self> {
+        let pause: Option<AdminPause> = store.get(ADMIN_PAUSE_KEY)?;
+        if let Some(pause) = &pause {
+            tracing::info!(
+                "Submissions paused by {} since {}; POST /resume to resume",
+                pause.by,
+                pause.at
+            );
+        }
+        Ok(Self {
+            pause: Mutex::new(pause),
+        })
+    }
+
+    pub fn paused(&self) -> bool {
+        self.pause.lock().unwrap().is_some()
+    }
+
+    #[cfg(feature = "admin")]
+    pub fn status(&self) -> Option<AdminPause> {
+        self.pause.lock().unwrap().clone()
+    }
+
+    /// Persists `pause` before applying it; none resumes
+    #[cfg(feature = "admin")]
+    pub(crate) fn set(&self, store: &StateStore, pause: Option<AdminPause>) -> Result<()> {
+        let mut current = self.pause.lock().unwrap();
+        match &pause {
+            Some(pause) => store.put(ADMIN_PAUSE_KEY, pause)?,
+            None => store.remove(ADMIN_PAUSE_KEY)?,
+        }
+        *current = pause;
+        Ok(())
+    }
+}
+
+/// `replay_nonce` resolved the nonce's dead letter as `replayed`
+pub const DEAD_LETTER_RESOLVED: &str = "dead_letter_resolved";
+/// `replay_nonce` cleared the nonce's backoff
+pub const BACKOFF_CLEARED: &str = "backoff_cleared";
+
+/// Queues `nonce` to be sent again, resolving its dead letter as `replayed`
+/// (with `note`) or clearing its backoff; returns which, or why it is not
+/// relayed again. A nonce with a receipt is never sent again
+pub(crate) fn replay_nonce(
+    state: &StateStore,
+    queue: &PendingQueue,
+    dead_letters: &DeadLetterQueue,
+    nonce: u64,
+    by: &str,
+    at: u64,
+    note: &str,
+) -> Result<Result<&'static str, String>> {
+    if let Some(receipt) = Receipt::load(state, nonce)? {
+        return Ok(Err(if receipt.signature.is_empty() {
+            format!("Nonce {} was closed without relaying", nonce)
+        } else {
+            format!(
+                "Nonce {} was already relayed in {}",
+                nonce, receipt.signature
+            )
+        }));
+    }
+    if dead_letters.contains(nonce) {
+        let resolution = Resolution {
+            kind: ResolutionKind::Replayed,
+            by: by.to_string(),
+            at,
+            note: Some(note.to_string()),
+            l2_signature: None,
+            applied: false,
+        };
+        dead_letters.resolve(state, nonce, resolution)?;
+        return Ok(Ok(DEAD_LETTER_RESOLVED));
+    }
+    if queue.retry_at(nonce).is_some() {
+        queue.clear_retry(state, nonce)?;
+        return Ok(Ok(BACKOFF_CLEARED));
+    }
+    Ok(Err(format!(
+        "Nonce {} is neither dead-lettered nor backing off",
+        nonce
+    )))
+}
@@ -0,0 +1,1156 @@
+//! Admin HTTP API, built with the `admin` feature.
+//! With `admin_listen` set (e.g. `"127.0.0.1:9200"`) the relayer answers up
+//! to 16 connections at once:
+//! - `GET /health`: 200 while every source runs, 503 once one has stopped or
+//!   a shutdown was requested
+//! - `GET /readyz`: 200 while every source could relay: active ones that
+//!   run, and standbys whose last check is recent and whose mirror is within
+//!   `standby_ready_max_lag` nonces of L2; 503 otherwise
+//! - `GET /version`: the build, as `--version` prints it
+//! - `GET /status`: uptime and, per source, the nonces read at the last poll,
+//!   the pending, retrying, in-flight and dead-lettered nonces, and today's
+//!   fee ledger
+//! - `POST /pause` and `POST /resume`: hold and resume submissions; resume
+//!   also resumes a tripped anomaly circuit breaker
+//! - `POST /replay/{nonce}`: relays `nonce` again, before the next batch
+//! - `POST /v1/promote`: makes standbys active at once, as `promote` does
+//! - `GET /v1/scale`: the last autoscaling signal of each source with
+//!   `scale_target_drain_secs` set
+//! - `GET /v1/snapshots?from=<unix time>`: the reconciliation snapshots of
+//!   each source taken at or after `from` (all by default), oldest first
+//! - `GET /v1/transfers/{nonce}`: what became of one nonce: its state, the
+//!   receipt with the L1 sender, relay proof and failed attempts once
+//!   relayed or recorded without a transaction (`skipped`: dust, cancelled,
+//!   expired), the transaction in flight, the attempts of a nonce backing
+//!   off and its dead letter
+//! - `GET /approvals`: the transfers parked for manual approval, with their
+//!   approvals and the message approvers sign
+//! - `POST /approve/{nonce}?approver=&signature=`: records an approver's
+//!   base58 signature of that message
+//! - `GET /log-level` and `POST /log-level?filter=<directives>` (or
+//!   `?reset=true`): the log filter set at runtime, and changes to it, which
+//!   are persisted as `set-log-level` does
+//! - `GET /chaos` and `POST /chaos?<setting>=<value>`: the fault injection
+//!   settings and faults injected, and changes to them, in builds with the
+//!   `chaos` feature
+//!
+//! POST endpoints require `admin_token` as a bearer token and are refused
+//! without one configured. `?source=` (and `?bridge=`) select the sources;
+//! `pause`, `resume` and `promote` apply to all sources by default, `replay`,
+//! `approve` and `transfers` need exactly one. A pause is persisted, so a restarted relayer stays paused
+//! until resumed, and only holds new batches: the transaction being
+//! confirmed finishes. A replayed nonce is either a dead letter, resolved as
+//! `replayed` as `dlq resolve` does, or a failed nonce backing off, which is
+//! retried at once; a nonce with a receipt is never sent again.
+
+use crate::{
+    admin::{replay_nonce, AdminPause, DEAD_LETTER_RESOLVED},
+    anomaly::{self, BreakerTrip, ANOMALY_BREAKER_KEY},
+    approval::PendingApproval,
+    attempts::AttemptRecord,
+    build_info::BuildInfo,
+    dlq::DeadLetter,
+    fees::{self, FeeDay},
+    inflight::InFlightTransfer,
+    receipts::Receipt,
+    scaling::ScaleSignal,
+    snapshots::MetricsSnapshot,
+    standby::{Readiness, Role},
+    telemetry::{self, TelemetryGuard, LOG_LEVEL_KEY},
+    Relayer,
+};
+use anyhow::Result;
+use futures::stream::{FuturesUnordered, StreamExt};
+use percent_encoding::percent_decode_str;
+use serde::Serialize;
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use std::{
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::{Semaphore, SemaphorePermit},
+};
+
+/// How long one request may take before the connection is dropped
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Longest request head read
+const MAX_REQUEST_BYTES: usize = 8192;
+/// Connections answered at once; further ones wait to be accepted
+const MAX_CONNECTIONS: usize = 16;
+
+/// One parsed HTTP request
+struct Request {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    authorization: Option<String>,
+}
+
+impl Request {
+    fn query(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Status line and JSON body of a response
+struct Reply {
+    status: &'static str,
+    body: String,
+}
+
+impl Reply {
+    fn json<T: Serialize>(status: &'static str, body: &T) -> Self {
+        Self {
+            status,
+            body: serde_json::to_string_pretty(body).unwrap_or_default(),
+        }
+    }
+
+    fn error(status: &'static str, message: impl Into<String>) -> Self {
+        Self::json(status, &serde_json::json!({ "error": message.into() }))
+    }
+}
+
+#[derive(Serialize)]
+struct Health {
+    status: &'static str,
+    shutting_down: bool,
+    /// Sources whose relayer has stopped
+    stopped: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct SourceReadiness {
+    source: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bridge: Option<String>,
+    stopped: bool,
+    #[serde(flatten)]
+    readiness: Readiness,
+}
+
+#[derive(Serialize)]
+struct Status {
+    uptime_secs: u64,
+    shutting_down: bool,
+    sources: Vec<SourceStatus>,
+}
+
+#[derive(Serialize)]
+struct SourceStatus {
+    source: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bridge: Option<String>,
+    role: Role,
+    stopped: bool,
+    paused: Option<AdminPause>,
+    /// Nonce of the L1 watched account at the last poll
+    last_observed_nonce: Option<u64>,
+    /// Nonce of the L2 nonce account at the last poll
+    last_relayed_nonce: Option<u64>,
+    pending: u64,
+    /// Pending nonces that failed at least once
+    pending_retries: usize,
+    in_flight: usize,
+    dead_letters: usize,
+    /// Trip of the anomaly circuit breaker holding submissions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    anomaly_breaker: Option<BreakerTrip>,
+    /// Today's fee ledger (see `fees`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fees_today: Option<FeeDay>,
+}
+
+#[derive(Serialize)]
+struct SourceSnapshots {
+    source: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bridge: Option<String>,
+    snapshots: Vec<MetricsSnapshot>,
+}
+
+/// Everything the relayer knows about one nonce
+#[derive(Serialize)]
+pub(crate) struct TransferDetail {
+    source: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bridge: Option<String>,
+    nonce: u64,
+    /// `relayed`, `skipped`, `in_flight`, `dead_lettered`, `retrying` or
+    /// `pending`
+    state: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    receipt: Option<Receipt>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    in_flight: Option<InFlightTransfer>,
+    /// Failed attempts of a nonce not yet relayed, most recent last
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    failures: Vec<AttemptRecord>,
+    /// Dead letter of the nonce, open or resolved
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dead_letter: Option<DeadLetter>,
+}
+
+#[derive(Serialize)]
+struct SourceApprovals {
+    source: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bridge: Option<String>,
+    pending: Vec<PendingApproval>,
+}
+
+/// The admin API over the relayers of one process
+pub struct AdminApi<'a> {
+    relayers: &'a [Relayer],
+    token: Option<&'a str>,
+    started_at: u64,
+    /// Whether each relayer's monitor has returned, by index
+    stopped: &'a [AtomicBool],
+    telemetry: Option<&'a TelemetryGuard>,
+}
+
+impl<'a> AdminApi<'a> {
+    /// API over `relayers`, whose monitors set `stopped` as they return
+    pub fn new(relayers: &'a [Relayer], stopped: &'a [AtomicBool], token: Option<&'a str>) -> Self {
+        Self {
+            relayers,
+            token,
+            started_at: relayers[0].clock.unix_timestamp(),
+            stopped,
+            telemetry: None,
+        }
+    }
+
+    /// Changes the log filter of `telemetry` through `/log-level`
+    pub fn with_telemetry(mut self, telemetry: &'a TelemetryGuard) -> Self {
+        self.telemetry = Some(telemetry);
+        self
+    }
+
+    /// Answers requests on `listener`, up to `MAX_CONNECTIONS` at once;
+    /// never returns
+    pub async fn serve(&self, listener: TcpListener) -> Result<()> {
+        let slots = Semaphore::new(MAX_CONNECTIONS);
+        let mut connections = FuturesUnordered::new();
+        loop {
+            // 每个连接单独推进，慢连接不阻塞其他请求
+            let accepted = async {
+                let slot = slots
+                    .acquire()
+                    .await
+                    .expect("the semaphore is never closed");
+                (listener.accept().await, slot)
+            };
+            tokio::select! {
+                (accepted, slot) = accepted => match accepted {
+                    Ok((stream, _)) => connections.push(self.connection(stream, slot)),
+                    Err(e) => tracing::warn!("failed to accept an admin connection: {}", e),
+                },
+                Some(()) = connections.next(), if !connections.is_empty() => {}
+            }
+        }
+    }
+
+    /// Answers the request on `stream`, holding `_slot` until it is done
+    async fn connection(&self, mut stream: TcpStream, _slot: SemaphorePermit<'_>) {
+        match tokio::time::timeout(REQUEST_TIMEOUT, self.answer(&mut stream)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => tracing::warn!("failed to answer an admin request: {}", e),
+            Err(_) => tracing::warn!("admin request timed out"),
+        }
+    }
+
+    async fn answer(&self, stream: &mut TcpStream) -> std::io::Result<()> {
+        let mut head = Vec::new();
+        let mut buffer = [0u8; 1024];
+        while head.len() < MAX_REQUEST_BYTES && !head.windows(4).any(|w| w == b"\r\n\r\n") {
+            let read = stream.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            head.extend_from_slice(&buffer[..read]);
+        }
+        let reply = self.route(&parse_request(&String::from_utf8_lossy(&head)));
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            reply.status,
+            reply.body.len(),
+            reply.body
+        );
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await
+    }
+
+    fn route(&self, request: &Request) -> Reply {
+        let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+        match (request.method.as_str(), segments.as_slice()) {
+            ("GET", ["health"]) => self.health(),
+            ("GET", ["readyz"]) => self.readyz(request),
+            ("GET", ["version"]) => Reply::json("200 OK", &BuildInfo::current()),
+            ("GET", ["status"]) => self.status(request),
+            ("GET", ["approvals"]) => self.approvals(request),
+            ("GET", ["v1", "scale"]) => self.scale(request),
+            ("GET", ["v1", "snapshots"]) => self.snapshots(request),
+            ("GET", ["v1", "transfers", nonce]) => match nonce.parse() {
+                Ok(nonce) => self.transfer(request, nonce),
+                Err(_) => Reply::error("400 Bad Request", format!("Invalid nonce {}", nonce)),
+            },
+            ("GET", ["chaos"]) => chaos(request),
+            ("GET", ["log-level"]) => self.log_level(),
+            (
+                "POST",
+                ["pause"] | ["resume"] | ["replay", _] | ["approve", _] | ["chaos"] | ["log-level"]
+                | ["v1", "promote"],
+            ) => {
+                if let Err(reply) = self.authorize(request) {
+                    return reply;
+                }
+                match segments.as_slice() {
+                    ["pause"] => self.set_paused(request, true),
+                    ["resume"] => self.set_paused(request, false),
+                    ["chaos"] => chaos(request),
+                    ["log-level"] => self.set_log_level(request),
+                    ["v1", "promote"] => self.promote(request),
+                    [endpoint, nonce] => match nonce.parse() {
+                        Ok(nonce) if *endpoint == "approve" => self.approve(request, nonce),
+                        Ok(nonce) => self.replay(request, nonce),
+                        Err(_) => Reply::error(
+                            "400 Bad Request",
+                            format!("Invalid nonce {}", nonce),
+                        ),
+                    },
+                    _ => unreachable!("matched above"),
+                }
+            }
+            (
+                _,
+                ["health"] | ["readyz"] | ["version"] | ["status"] | ["approvals"] | ["chaos"] | ["log-level"]
+                | ["v1", "scale"]
+                | ["v1", "snapshots"]
+                | ["v1", "transfers", _],
+            ) => {
+                Reply::error("405 Method Not Allowed", "Use GET")
+            }
+            (_, ["pause"] | ["resume"] | ["replay", _] | ["approve", _] | ["v1", "promote"]) => {
+                Reply::error("405 Method Not Allowed", "Use POST")
+            }
+            _ => Reply::error(
+                "404 Not Found",
+                "Endpoints: GET /health, GET /readyz, GET /version, GET /status, GET /approvals, GET /v1/scale, GET /v1/snapshots, GET /v1/transfers/{nonce}, GET /chaos, GET /log-level, POST /pause, POST /resume, POST /replay/{nonce}, POST /approve/{nonce}, POST /v1/promote, POST /chaos, POST /log-level",
+            ),
+        }
+    }
+
+    fn authorize(&self, request: &Request) -> Result<(), Reply> {
+        let Some(token) = self.token else {
+            return Err(Reply::error(
+                "403 Forbidden",
+                "Admin endpoints are disabled without admin_token",
+            ));
+        };
+        let given = request
+            .authorization
+            .as_deref()
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .unwrap_or_default();
+        if !same_token(given.trim(), token) {
+            return Err(Reply::error("401 Unauthorized", "Invalid admin token"));
+        }
+        Ok(())
+    }
+
+    fn stopped(&self, index: usize) -> bool {
+        self.stopped[index].load(Ordering::SeqCst)
+    }
+
+    fn shutting_down(&self) -> bool {
+        self.relayers
+            .iter()
+            .any(|relayer| relayer.shutdown.requested())
+    }
+
+    fn health(&self) -> Reply {
+        let stopped: Vec<String> = self
+            .relayers
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| self.stopped(*index))
+            .map(|(_, relayer)| relayer.name())
+            .collect();
+        let shutting_down = self.shutting_down();
+        let healthy = stopped.is_empty() && !shutting_down;
+        Reply::json(
+            if healthy {
+                "200 OK"
+            } else {
+                "503 Service Unavailable"
+            },
+            &Health {
+                status: if healthy { "ok" } else { "unavailable" },
+                shutting_down,
+                stopped,
+            },
+        )
+    }
+
+    fn readyz(&self, request: &Request) -> Reply {
+        let selected = match self.select(request) {
+            Ok(selected) => selected,
+            Err(reply) => return reply,
+        };
+        let mut sources = Vec::new();
+        for index in selected {
+            let relayer = &self.relayers[index];
+            let mut readiness = match relayer.readiness() {
+                Ok(readiness) => readiness,
+                Err(e) => return Reply::error("500 Internal Server Error", format!("{:#}", e)),
+            };
+            let stopped = self.stopped(index);
+            readiness.ready &= !stopped;
+            sources.push(SourceReadiness {
+                source: relayer.source_id.clone(),
+                bridge: relayer.bridge.clone(),
+                stopped,
+                readiness,
+            });
+        }
+        let ready = !self.shutting_down() && sources.iter().all(|source| source.readiness.ready);
+        Reply::json(
+            if ready {
+                "200 OK"
+            } else {
+                "503 Service Unavailable"
+            },
+            &serde_json::json!({
+                "status": if ready { "ready" } else { "not_ready" },
+                "sources": sources,
+            }),
+        )
+    }
+
+    fn status(&self, request: &Request) -> Reply {
+        let selected = match self.select(request) {
+            Ok(selected) => selected,
+            Err(reply) => return reply,
+        };
+        let sources = selected
+            .into_iter()
+            .map(|index| {
+                let relayer = &self.relayers[index];
+                let (queued, spilled) = relayer.queue.depth();
+                let today = fees::day_of(relayer.clock.unix_timestamp());
+                SourceStatus {
+                    source: relayer.source_id.clone(),
+                    bridge: relayer.bridge.clone(),
+                    role: *relayer.role.lock().unwrap(),
+                    stopped: self.stopped(index),
+                    paused: relayer.manual_pause.status(),
+                    last_observed_nonce: *relayer.last_l1_nonce.lock().unwrap(),
+                    last_relayed_nonce: *relayer.last_nonce.lock().unwrap(),
+                    pending: queued + spilled,
+                    pending_retries: relayer.queue.retry_count(),
+                    in_flight: relayer.in_flight.len(),
+                    dead_letters: relayer.dead_letters.len(),
+                    anomaly_breaker: relayer.state.get(ANOMALY_BREAKER_KEY).ok().flatten(),
+                    fees_today: FeeDay::load(&relayer.state, &today).ok(),
+                }
+            })
+            .collect();
+        let now = self.relayers[0].clock.unix_timestamp();
+        Reply::json(
+            "200 OK",
+            &Status {
+                uptime_secs: now.saturating_sub(self.started_at),
+                shutting_down: self.shutting_down(),
+                sources,
+            },
+        )
+    }
+
+    fn set_paused(&self, request: &Request, paused: bool) -> Reply {
+        let selected = match self.select(request) {
+            Ok(selected) => selected,
+            Err(reply) => return reply,
+        };
+        let by = operator(request);
+        let mut changed = Vec::new();
+        for index in selected {
+            let relayer = &self.relayers[index];
+            // resume 也复位异常熔断
+            let breaker = !paused && anomaly::resume(&relayer.state).unwrap_or(false);
+            if breaker {
+                tracing::info!(
+                    "{}: anomaly circuit breaker resumed by {}",
+                    relayer.name(),
+                    by
+                );
+            }
+            if relayer.manual_pause.paused() == paused {
+                if breaker {
+                    changed.push(relayer.source_id.clone());
+                }
+                continue;
+            }
+            if let Err(e) = relayer.set_manual_pause(paused, &by) {
+                return Reply::error("500 Internal Server Error", format!("{:#}", e));
+            }
+            changed.push(relayer.source_id.clone());
+        }
+        Reply::json(
+            "200 OK",
+            &serde_json::json!({ "paused": paused, "changed": changed }),
+        )
+    }
+
+    fn promote(&self, request: &Request) -> Reply {
+        let selected = match self.select(request) {
+            Ok(selected) => selected,
+            Err(reply) => return reply,
+        };
+        let by = operator(request);
+        let mut promoted = Vec::new();
+        for index in selected {
+            let relayer = &self.relayers[index];
+            match relayer.promote(&by) {
+                Ok(true) => promoted.push(relayer.source_id.clone()),
+                Ok(false) => {}
+                Err(e) => return Reply::error("500 Internal Server Error", format!("{:#}", e)),
+            }
+        }
+        Reply::json("200 OK", &serde_json::json!({ "promoted": promoted }))
+    }
+
+    fn replay(&self, request: &Request, nonce: u64) -> Reply {
+        let selected = match self.select(request) {
+            Ok(selected) => selected,
+            Err(reply) => return reply,
+        };
+        let [index] = selected.as_slice() else {
+            return Reply::error(
+                "400 Bad Request",
+                "Select one source with ?source= to replay a nonce",
+            );
+        };
+        match self.relayers[*index].queue_replay(nonce, &operator(request)) {
+            Ok(Ok(replay)) => Reply::json(
+                "202 Accepted",
+                &serde_json::json!({ "nonce": nonce, "replay": replay }),
+            ),
+            Ok(Err(refused)) => Reply::error("409 Conflict", refused),
+            Err(e) => Reply::error("500 Internal Server Error", format!("{:#}", e)),
+        }
+    }
+
+    fn approvals(&self, request: &Request) -> Reply {
+        let selected = match self.select(request) {
+            Ok(selected) => selected,
+            Err(reply) => return reply,
+        };
+        let mut sources = Vec::new();
+        for index in selected {
+            let relayer = &self.relayers[index];
+            let Some(policy) = &relayer.approval else {
+                continue;
+            };
+            match policy.pending(&relayer.state, &relayer.approval_scope()) {
+                Ok(pending) => sources.push(SourceApprovals {
+                    source: relayer.source_id.clone(),
+                    bridge: relayer.bridge.clone(),
+                    pending,
+                }),
+                Err(e) => return Reply::error("500 Internal Server Error", format!("{:#}", e)),
+            }
+        }
+        Reply::json("200 OK", &serde_json::json!({ "sources": sources }))
+    }
+
+    fn scale(&self, request: &Request) -> Reply {
+        let selected = match self.select(request) {
+            Ok(selected) => selected,
+            Err(reply) => return reply,
+        };
+        let signals: Vec<ScaleSignal> = selected
+            .into_iter()
+            .filter_map(|index| self.relayers[index].scaler.as_ref()?.latest())
+            .collect();
+        Reply::json("200 OK", &serde_json::json!({ "sources": signals }))
+    }
+
+    fn snapshots(&self, request: &Request) -> Reply {
+        let selected = match self.select(request) {
+            Ok(selected) => selected,
+            Err(reply) => return reply,
+        };
+        let from = match request.query("from").map(str::parse::<u64>) {
+            None => 0,
+            Some(Ok(from)) => from,
+            Some(Err(_)) => return Reply::error("400 Bad Request", "Pass ?from= as a unix time"),
+        };
+        let mut sources = Vec::new();
+        for index in selected {
+            let relayer = &self.relayers[index];
+            match MetricsSnapshot::list(&relayer.state, from) {
+                Ok(snapshots) => sources.push(SourceSnapshots {
+                    source: relayer.source_id.clone(),
+                    bridge: relayer.bridge.clone(),
+                    snapshots,
+                }),
+                Err(e) => return Reply::error("500 Internal Server Error", format!("{:#}", e)),
+            }
+        }
+        Reply::json("200 OK", &serde_json::json!({ "sources": sources }))
+    }
+
+    fn transfer(&self, request: &Request, nonce: u64) -> Reply {
+        let selected = match self.select(request) {
+            Ok(selected) => selected,
+            Err(reply) => return reply,
+        };
+        let [index] = selected.as_slice() else {
+            return Reply::error(
+                "400 Bad Request",
+                "Select one source with ?source= to look up a nonce",
+            );
+        };
+        match self.relayers[*index].transfer_detail(nonce) {
+            Ok(Some(detail)) => Reply::json("200 OK", &detail),
+            Ok(None) => Reply::error(
+                "404 Not Found",
+                format!("Nonce {} has not been observed on L1", nonce),
+            ),
+            Err(e) => Reply::error("500 Internal Server Error", format!("{:#}", e)),
+        }
+    }
+
+    fn approve(&self, request: &Request, nonce: u64) -> Reply {
+        let selected = match self.select(request) {
+            Ok(selected) => selected,
+            Err(reply) => return reply,
+        };
+        let [index] = selected.as_slice() else {
+            return Reply::error(
+                "400 Bad Request",
+                "Select one source with ?source= to approve a nonce",
+            );
+        };
+        let relayer = &self.relayers[*index];
+        let Some(policy) = &relayer.approval else {
+            return Reply::error(
+                "409 Conflict",
+                "manual_approval_threshold is not configured",
+            );
+        };
+        let approver = request.query("approver").map(Pubkey::from_str);
+        let signature = request.query("signature").map(Signature::from_str);
+        let (Some(Ok(approver)), Some(Ok(signature))) = (approver, signature) else {
+            return Reply::error(
+                "400 Bad Request",
+                "Pass the approver's pubkey as ?approver= and its base58 signature as ?signature=",
+            );
+        };
+        let now = relayer.clock.unix_timestamp();
+        match policy.add_signature(
+            &relayer.state,
+            &relayer.approval_scope(),
+            nonce,
+            &approver,
+            &signature,
+            now,
+        ) {
+            Ok(Ok((_, approvals))) => {
+                tracing::info!(
+                    "{}: nonce {} approved by {} through the admin API: {} of {} approvals",
+                    relayer.name(),
+                    nonce,
+                    approver,
+                    approvals,
+                    policy.required
+                );
+                Reply::json(
+                    "200 OK",
+                    &serde_json::json!({
+                        "nonce": nonce,
+                        "approvals": approvals,
+                        "required": policy.required,
+                    }),
+                )
+            }
+            Ok(Err(refused)) => Reply::error("409 Conflict", refused),
+            Err(e) => Reply::error("500 Internal Server Error", format!("{:#}", e)),
+        }
+    }
+
+    fn log_level(&self) -> Reply {
+        let Some(telemetry) = self.telemetry else {
+            return Reply::error("404 Not Found", "The log filter is not set up");
+        };
+        Reply::json(
+            "200 OK",
+            &serde_json::json!({ "filter": telemetry.runtime_filter() }),
+        )
+    }
+
+    fn set_log_level(&self, request: &Request) -> Reply {
+        let Some(telemetry) = self.telemetry else {
+            return Reply::error("404 Not Found", "The log filter is not set up");
+        };
+        let filter = match (request.query("filter"), request.query("reset")) {
+            (_, Some("true")) => None,
+            (Some(filter), _) => {
+                if let Err(e) = telemetry::parse_filter(filter) {
+                    return Reply::error("400 Bad Request", format!("{:#}", e));
+                }
+                Some(filter)
+            }
+            (None, _) => {
+                return Reply::error(
+                    "400 Bad Request",
+                    "Pass the directives as ?filter= or ?reset=true",
+                )
+            }
+        };
+        // 写入每个 source 的状态，重启后和 set-log-level 一样保持
+        for relayer in self.relayers {
+            let stored = match filter {
+                Some(filter) => relayer.state.put(LOG_LEVEL_KEY, &filter),
+                None => relayer.state.remove(LOG_LEVEL_KEY),
+            };
+            if let Err(e) = stored {
+                return Reply::error("500 Internal Server Error", format!("{:#}", e));
+            }
+        }
+        if let Err(e) = telemetry.apply(filter) {
+            return Reply::error("500 Internal Server Error", format!("{:#}", e));
+        }
+        tracing::info!(
+            "Log filter {} by {}",
+            filter.map_or("restored".to_string(), |filter| format!(
+                "set to {}",
+                filter
+            )),
+            operator(request)
+        );
+        Reply::json("200 OK", &serde_json::json!({ "filter": filter }))
+    }
+
+    /// Indices of the relayers matching `?source=` and `?bridge=`
+    fn select(&self, request: &Request) -> Result<Vec<usize>, Reply> {
+        let source = request.query("source");
+        let bridge = request.query("bridge");
+        let selected: Vec<usize> = self
+            .relayers
+            .iter()
+            .enumerate()
+            .filter(|(_, relayer)| source.is_none_or(|id| relayer.source_id == id))
+            .filter(|(_, relayer)| {
+                bridge.is_none_or(|name| relayer.bridge.as_deref() == Some(name))
+            })
+            .map(|(index, _)| index)
+            .collect();
+        if selected.is_empty() {
+            return Err(Reply::error("404 Not Found", "No source matches"));
+        }
+        Ok(selected)
+    }
+}
+
+impl Relayer {
+    /// What became of `nonce`; none while it is beyond the L1 nonce read at
+    /// the last poll and unknown otherwise
+    pub(crate) fn transfer_detail(&self, nonce: u64) -> Result<Option<TransferDetail>> {
+        let receipt = Receipt::load(&self.state, nonce)?;
+        let in_flight = self.in_flight.get(nonce);
+        let failures = self.queue.failures(nonce);
+        let dead_letter = self
+            .dead_letters
+            .entries()
+            .into_iter()
+            .find(|letter| letter.nonce == nonce);
+        let relayed = self
+            .last_nonce
+            .lock()
+            .unwrap()
+            .is_some_and(|next| nonce < next);
+        let observed = self
+            .last_l1_nonce
+            .lock()
+            .unwrap()
+            .is_some_and(|next| nonce < next);
+        let state = if receipt.as_ref().is_some_and(Receipt::is_skipped) {
+            "skipped"
+        } else if receipt.is_some() || relayed {
+            "relayed"
+        } else if in_flight.is_some() {
+            "in_flight"
+        } else if self.dead_letters.contains(nonce) {
+            "dead_lettered"
+        } else if !failures.is_empty() {
+            "retrying"
+        } else if observed {
+            "pending"
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(TransferDetail {
+            source: self.source_id.clone(),
+            bridge: self.bridge.clone(),
+            nonce,
+            state,
+            receipt,
+            in_flight,
+            failures,
+            dead_letter,
+        }))
+    }
+
+    /// Pauses or resumes submissions on behalf of `by`
+    pub(crate) fn set_manual_pause(&self, paused: bool, by: &str) -> Result<()> {
+        let pause = paused.then(|| AdminPause {
+            by: by.to_string(),
+            at: self.clock.unix_timestamp(),
+        });
+        self.manual_pause.set(&self.state, pause)?;
+        tracing::info!(
+            "{}: submissions {} by {}",
+            self.name(),
+            if paused { "paused" } else { "resumed" },
+            by
+        );
+        Ok(())
+    }
+
+    /// Queues `nonce` to be sent again before the next batch; returns how, or
+    /// why it is not relayed again
+    pub(crate) fn queue_replay(
+        &self,
+        nonce: u64,
+        by: &str,
+    ) -> Result<Result<&'static str, String>> {
+        let replay = replay_nonce(
+            &self.state,
+            &self.queue,
+            &self.dead_letters,
+            nonce,
+            by,
+            self.clock.unix_timestamp(),
+            "admin API",
+        )?;
+        match replay {
+            Ok(DEAD_LETTER_RESOLVED) => {
+                tracing::info!("{}: dead letter {} replayed by {}", self.name(), nonce, by)
+            }
+            Ok(_) => tracing::info!(
+                "{}: backoff of nonce {} cleared by {}",
+                self.name(),
+                nonce,
+                by
+            ),
+            Err(_) => {}
+        }
+        Ok(replay)
+    }
+}
+
+/// Shows the fault injection settings, changed first by a POST
+#[cfg(feature = "chaos")]
+fn chaos(request: &Request) -> Reply {
+    if request.method != "POST" {
+        return match crate::chaos::status() {
+            Some(status) => Reply::json("200 OK", &status),
+            None => Reply::error("404 Not Found", "Fault injection is not set up"),
+        };
+    }
+    let changes: Vec<(String, String)> = request
+        .query
+        .iter()
+        .filter(|(name, _)| name != "by")
+        .cloned()
+        .collect();
+    match crate::chaos::update(&changes) {
+        Ok(status) => {
+            tracing::info!(
+                "Fault injection settings changed by {}: {:?}",
+                operator(request),
+                changes
+            );
+            Reply::json("200 OK", &status)
+        }
+        Err(e) => Reply::error("400 Bad Request", e),
+    }
+}
+
+#[cfg(not(feature = "chaos"))]
+fn chaos(_request: &Request) -> Reply {
+    Reply::error(
+        "404 Not Found",
+        "Fault injection needs a build with --features chaos",
+    )
+}
+
+/// Operator named by `?by=`, `admin-api` by default
+fn operator(request: &Request) -> String {
+    request.query("by").unwrap_or("admin-api").to_string()
+}
+
+/// Compares tokens without stopping at the first differing byte
+fn same_token(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Decodes a query key or value: `+` is a space and `%XX` a byte
+fn decode_query(encoded: &str) -> String {
+    percent_decode_str(&encoded.replace('+', " "))
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
+fn parse_request(head: &str) -> Request {
+    let mut lines = head.lines();
+    let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+    let method = request_line.next().unwrap_or_default().to_string();
+    let target = request_line.next().unwrap_or_default();
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let query = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode_query(key), decode_query(value))
+        })
+        .collect();
+    let authorization = lines
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("authorization"))
+        .map(|(_, value)| value.trim().to_string());
+    Request {
+        method,
+        path: path.to_string(),
+        query,
+        authorization,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "testing")]
+    use crate::pipeline::FetchedTransfer;
+    #[cfg(feature = "testing")]
+    use crate::testing::{self, Harness};
+    #[cfg(feature = "testing")]
+    use serde_json::{json, Value};
+
+    /// Status and JSON body of `GET target`
+    #[cfg(feature = "testing")]
+    fn get(api: &AdminApi, target: &str) -> (&'static str, Value) {
+        let reply = api.route(&parse_request(&format!("GET {} HTTP/1.1\r\n\r\n", target)));
+        (reply.status, serde_json::from_str(&reply.body).unwrap())
+    }
+
+    #[test]
+    fn query_values_are_percent_decoded() {
+        let request = parse_request(
+            "POST /log-level?filter=info%2Csol_bridge_relayer%3A%3Apipeline%3Ddebug&by=ops+team HTTP/1.1\r\n\r\n",
+        );
+        assert_eq!(
+            request.query("filter"),
+            Some("info,sol_bridge_relayer::pipeline=debug")
+        );
+        assert_eq!(request.query("by"), Some("ops team"));
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test(start_paused = true)]
+    async fn the_scaling_signal_is_served_once_computed() {
+        let mut config = testing::config();
+        config.scale_target_drain_secs = Some(60);
+        let harness = Harness::new(config).unwrap();
+        let relayers = [harness.relayer().await.unwrap()];
+        let stopped = [AtomicBool::new(false)];
+        let api = AdminApi::new(&relayers, &stopped, None);
+
+        assert_eq!(get(&api, "/v1/scale"), ("200 OK", json!({ "sources": [] })));
+        // 第一次采样只记录基准，第二次才有速率
+        let _ = tokio::time::timeout(Duration::from_secs(20), relayers[0].publish_scaling()).await;
+        let (status, body) = get(&api, "/v1/scale");
+        assert_eq!(status, "200 OK");
+        let signal = &body["sources"][0];
+        assert_eq!(signal["source"], json!(relayers[0].source_id));
+        assert_eq!(signal["backlog"], json!(0));
+        assert_eq!(signal["drain_secs"], json!(0));
+        assert_eq!(signal["recommended_replicas"], json!(1));
+        assert_eq!(signal["target_drain_secs"], json!(60));
+        assert_eq!(get(&api, "/v1/scale?source=other").0, "404 Not Found");
+        let post = api.route(&parse_request("POST /v1/scale HTTP/1.1\r\n\r\n"));
+        assert_eq!(post.status, "405 Method Not Allowed");
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test(start_paused = true)]
+    async fn snapshots_are_served_from_a_unix_time() {
+        let mut config = testing::config();
+        config.snapshot_interval_secs = Some(60);
+        let harness = Harness::new(config).unwrap();
+        let relayers = [harness.relayer().await.unwrap()];
+        let stopped = [AtomicBool::new(false)];
+        let api = AdminApi::new(&relayers, &stopped, None);
+
+        relayers[0].record_snapshot(5, 3).unwrap();
+        tokio::time::advance(Duration::from_secs(60)).await;
+        relayers[0].record_snapshot(7, 5).unwrap();
+
+        let (status, body) = get(&api, "/v1/snapshots");
+        assert_eq!(status, "200 OK");
+        let nonces = |body: &Value| -> Vec<Value> {
+            body["sources"][0]["snapshots"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|snapshot| snapshot["l1_nonce"].clone())
+                .collect()
+        };
+        assert_eq!(nonces(&body), [json!(5), json!(7)]);
+        let (_, body) = get(&api, "/v1/snapshots?from=30");
+        assert_eq!(nonces(&body), [json!(7)]);
+        assert_eq!(body["sources"][0]["snapshots"][0]["taken_at"], json!(60));
+        assert_eq!(get(&api, "/v1/snapshots?from=soon").0, "400 Bad Request");
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test(start_paused = true)]
+    async fn a_standby_is_ready_once_its_mirror_is_caught_up_and_promoted_with_the_token() {
+        use crate::standby::{StandbyStatus, STANDBY_STATUS_KEY};
+
+        let mut config = testing::config();
+        config.role = Role::Standby;
+        let harness = Harness::new(config).unwrap();
+        let relayers = [harness.relayer().await.unwrap()];
+        let stopped = [AtomicBool::new(false)];
+        let api = AdminApi::new(&relayers, &stopped, Some("secret"));
+
+        // 尚未完成一次检查
+        let (status, body) = get(&api, "/readyz");
+        assert_eq!(status, "503 Service Unavailable");
+        assert_eq!(body["sources"][0]["role"], json!("standby"));
+        let check = |lag| StandbyStatus {
+            l2_nonce: 40,
+            mirrored_nonce: 40 - lag,
+            lag,
+            ..StandbyStatus::default()
+        };
+        relayers[0]
+            .state
+            .put(STANDBY_STATUS_KEY, &check(25))
+            .unwrap();
+        assert_eq!(get(&api, "/readyz").0, "503 Service Unavailable");
+        relayers[0]
+            .state
+            .put(STANDBY_STATUS_KEY, &check(2))
+            .unwrap();
+        let (status, body) = get(&api, "/readyz");
+        assert_eq!(status, "200 OK");
+        assert_eq!(body["sources"][0]["standby"]["lag"], json!(2));
+        // 检查过旧时不再视为就绪
+        tokio::time::advance(Duration::from_secs(31)).await;
+        assert_eq!(get(&api, "/readyz").0, "503 Service Unavailable");
+
+        let promote = |authorization: &str| {
+            api.route(&parse_request(&format!(
+                "POST /v1/promote HTTP/1.1\r\nAuthorization: {}\r\n\r\n",
+                authorization
+            )))
+        };
+        assert_eq!(promote("Bearer wrong").status, "401 Unauthorized");
+        assert_eq!(*relayers[0].role.lock().unwrap(), Role::Standby);
+        let reply = promote("Bearer secret");
+        assert_eq!(reply.status, "200 OK");
+        assert!(reply.body.contains(&relayers[0].source_id));
+        assert_eq!(*relayers[0].role.lock().unwrap(), Role::Active);
+        assert_eq!(get(&api, "/readyz").0, "200 OK");
+        let again: Value = serde_json::from_str(&promote("Bearer secret").body).unwrap();
+        assert_eq!(again, json!({ "promoted": [] }));
+    }
+    #[cfg(feature = "testing")]
+    #[tokio::test(start_paused = true)]
+    async fn a_transfer_is_served_with_its_receipt_or_failed_attempts() {
+        use crate::{attempts::FailureClass, queue::RetryBackoff};
+        use solana_sdk::signer::{keypair::Keypair, Signer};
+
+        let harness = Harness::new(testing::config()).unwrap();
+        harness.add_transfers(0..5, 1_000_000_000, &Keypair::new().pubkey());
+        let relayers = [harness.relayer().await.unwrap()];
+        let stopped = [AtomicBool::new(false)];
+        let api = AdminApi::new(&relayers, &stopped, None);
+        let relayer = &relayers[0];
+
+        relayer.process_nonce_range(0, 2, 0).await.unwrap();
+        *relayer.last_l1_nonce.lock().unwrap() = Some(5);
+        // 未发送交易就记下的回执不算已中继
+        Receipt::cancelled(
+            &FetchedTransfer::native(4, 1_000_000_000, Pubkey::new_unique()),
+            "refunded".to_string(),
+            0,
+        )
+        .save(&relayer.state)
+        .unwrap();
+        let failure = AttemptRecord {
+            attempt: 1,
+            started_at: 0,
+            duration_ms: 20,
+            class: FailureClass::Send,
+            error: "connection reset".to_string(),
+            endpoint: "http://fake-l2".to_string(),
+            blockhash: None,
+            signature: None,
+            expires_at: None,
+        };
+        relayer
+            .queue
+            .record_failure(
+                &relayer.state,
+                2,
+                "connection reset",
+                0,
+                RetryBackoff::IMMEDIATE,
+                Some(failure),
+            )
+            .unwrap();
+
+        let (status, body) = get(&api, "/v1/transfers/1");
+        assert_eq!(status, "200 OK");
+        assert_eq!(body["state"], json!("relayed"));
+        assert_eq!(body["receipt"]["nonce"], json!(1));
+        assert!(body["receipt"]["proof"].is_object());
+        let (_, body) = get(&api, "/v1/transfers/2");
+        assert_eq!(body["state"], json!("retrying"));
+        assert_eq!(body["failures"][0]["error"], json!("connection reset"));
+        assert!(body.get("receipt").is_none());
+        assert_eq!(get(&api, "/v1/transfers/3").1["state"], json!("pending"));
+        let (_, body) = get(&api, "/v1/transfers/4");
+        assert_eq!(body["state"], json!("skipped"));
+        assert_eq!(body["receipt"]["cancelled"], json!("refunded"));
+        assert_eq!(get(&api, "/v1/transfers/5").0, "404 Not Found");
+        assert_eq!(get(&api, "/v1/transfers/soon").0, "400 Bad Request");
+        assert_eq!(
+            get(
+                &api,
+                &format!("/v1/transfers/1?source={}", relayer.source_id)
+            )
+            .0,
+            "200 OK"
+        );
+    }
+}
@@ -0,0 +1,253 @@
+//! Operator alerts.
+//! Alerts are conditions that need a human to look at the relayer. Besides
+//! the log, every alert is posted to each `[[alert_webhooks]]` table: a Slack
+//! incoming webhook (`kind = "slack"`), a Telegram bot's `sendMessage` URL
+//! with its `chat_id` (`kind = "telegram"`) or any HTTP endpoint
+//! (`kind = "http"`, a JSON body with `status`, `message`, `raised_at` and
+//! the build's `version` and `git_commit`).
+//! Posts are made in the background (`webhooks`), so a slow webhook never
+//! holds the relayer; a failed post is logged and not retried. Builds
+//! without the `webhooks` feature refuse `[[alert_webhooks]]` at startup.
+//!
+//! Stalled relaying is paged by four conditions, each raised once when it
+//! starts and posted as resolved when it ends: `alert_consecutive_failures`
+//! submission batches failing in a row, `alert_consecutive_read_failures`
+//! monitor reads failing in a row, the L2 nonce account more than
+//! `alert_nonce_lag` nonces behind L1, and the signer's L2 balance below
+//! `alert_min_balance`.
+
+use crate::{secrets::Redacted, webhooks, Relayer};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+/// Alerts kept for crash reports
+const RECENT_ALERTS: usize = 20;
+
+static RECENT: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// Service an alert webhook posts to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookKind {
+    /// Slack incoming webhook, `{"text": ...}`
+    Slack,
+    /// Telegram bot `sendMessage`, `{"chat_id": ..., "text": ...}`
+    Telegram,
+    /// Any HTTP endpoint, `{"status": ..., "message": ..., "raised_at": ...,
+    /// "version": ..., "git_commit": ...}`
+    Http,
+}
+
+/// One `[[alert_webhooks]]` table
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertWebhook {
+    pub kind: WebhookKind,
+    /// Webhook URL; for Telegram `https://api.telegram.org/bot<token>/sendMessage`
+    pub url: Redacted<String>,
+    /// Chat the Telegram bot posts to
+    #[serde(default)]
+    pub chat_id: Option<String>,
+}
+
+/// Starts posting alerts to `webhooks`; alerts raised before are not posted.
+/// Fails on webhooks in a build without the `webhooks` feature
+pub fn init(webhooks: &[AlertWebhook]) -> Result<()> {
+    if webhooks.is_empty() {
+        return Ok(());
+    }
+    for (index, webhook) in webhooks.iter().enumerate() {
+        if webhook.kind == WebhookKind::Telegram && webhook.chat_id.is_none() {
+            return Err(anyhow::anyhow!(
+                "alert_webhooks[{}]: kind = \"telegram\" requires chat_id",
+                index
+            ));
+        }
+    }
+    webhooks::start(webhooks)
+}
+
+/// Waits up to `timeout` for the alerts raised so far to be posted, e.g.
+/// before the process exits
+pub async fn flush(timeout: Duration) {
+    webhooks::flush(timeout).await;
+}
+
+/// Raises an operator alert
+pub fn raise(message: &str) {
+    tracing::error!("[ALERT] {}", message);
+    let mut recent = RECENT.lock().unwrap_or_else(|e| e.into_inner());
+    if recent.len() >= RECENT_ALERTS {
+        recent.pop_front();
+    }
+    recent.push_back(message.to_string());
+    drop(recent);
+    webhooks::notify(message, false);
+}
+
+/// Reports that the condition of an earlier alert ended
+pub fn resolve(message: &str) {
+    tracing::info!("[RESOLVED] {}", message);
+    webhooks::notify(message, true);
+}
+
+/// The most recent alerts, oldest first
+pub fn recent() -> Vec<String> {
+    RECENT
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .cloned()
+        .collect()
+}
+
+/// Thresholds of the stall conditions and whether each is raised
+#[derive(Debug, Default)]
+pub struct StallAlerts {
+    /// Failed batches in a row that raise an alert (0 never does)
+    pub consecutive_failures: u32,
+    /// Failed monitor reads in a row that raise an alert (0 never does)
+    pub read_failures: u32,
+    /// Nonces the L2 nonce account may be behind L1 before an alert is raised
+    pub nonce_lag: Option<u64>,
+    /// Signer balance in lamports below which an alert is raised
+    pub min_balance: Option<u64>,
+    failing: AtomicBool,
+    unreadable: AtomicBool,
+    lagging: AtomicBool,
+    low_balance: AtomicBool,
+}
+
+impl StallAlerts {
+    pub fn new(
+        consecutive_failures: u32,
+        read_failures: u32,
+        nonce_lag: Option<u64>,
+        min_balance: Option<u64>,
+    ) -> Self {
+        Self {
+            consecutive_failures,
+            read_failures,
+            nonce_lag,
+            min_balance,
+            ..Self::default()
+        }
+    }
+}
+
+/// Records whether a condition holds; true when it just started or ended
+fn changed(raised: &AtomicBool, holds: bool) -> bool {
+    raised.swap(holds, Ordering::Relaxed) != holds
+}
+
+impl Relayer {
+    /// Alerts once `consecutive` batches failed in a row, the last with
+    /// `error`, and resolves the alert when a batch goes through
+    pub(crate) fn alert_failures(&self, consecutive: u32, error: Option<&anyhow::Error>) {
+        let threshold = self.stall_alerts.consecutive_failures;
+        if threshold == 0 {
+            return;
+        }
+        let holds = consecutive >= threshold;
+        if !changed(&self.stall_alerts.failing, holds) {
+            return;
+        }
+        match error {
+            Some(error) if holds => raise(&format!(
+                "{}: {} submission batches failed in a row, last: {}",
+                self.name(),
+                consecutive,
+                error
+            )),
+            _ => resolve(&format!(
+                "{}: submission batches succeed again",
+                self.name()
+            )),
+        }
+    }
+
+    /// Alerts once `consecutive` monitor reads failed in a row, the last with
+    /// `error`, and resolves the alert when a read succeeds
+    pub(crate) fn alert_read_failures(&self, consecutive: u32, error: Option<&anyhow::Error>) {
+        let threshold = self.stall_alerts.read_failures;
+        if threshold == 0 {
+            return;
+        }
+        let holds = consecutive >= threshold;
+        if !changed(&self.stall_alerts.unreadable, holds) {
+            return;
+        }
+        match error {
+            Some(error) if holds => raise(&format!(
+                "{}: {} monitor reads failed in a row, last: {:#}",
+                self.name(),
+                consecutive,
+                error
+            )),
+            _ => resolve(&format!("{}: monitor reads succeed again", self.name())),
+        }
+    }
+
+    /// Alerts while the L2 nonce account is more than `alert_nonce_lag`
+    /// nonces behind L1
+    pub(crate) fn alert_nonce_lag(&self, lag: u64) {
+        let Some(threshold) = self.stall_alerts.nonce_lag else {
+            return;
+        };
+        let holds = lag > threshold;
+        if !changed(&self.stall_alerts.lagging, holds) {
+            return;
+        }
+        if holds {
+            raise(&format!(
+                "{}: L2 nonce account {} nonces behind L1, above alert_nonce_lag {}",
+                self.name(),
+                lag,
+                threshold
+            ));
+        } else {
+            resolve(&format!(
+                "{}: L2 nonce account {} nonces behind L1, back within alert_nonce_lag {}",
+                self.name(),
+                lag,
+                threshold
+            ));
+        }
+    }
+
+    /// Alerts while the balance of `signer` is below `alert_min_balance`
+    pub(crate) fn alert_balance(&self, signer: &Pubkey, balance: u64) {
+        let Some(minimum) = self.stall_alerts.min_balance else {
+            return;
+        };
+        let holds = balance < minimum;
+        if !changed(&self.stall_alerts.low_balance, holds) {
+            return;
+        }
+        if holds {
+            raise(&format!(
+                "{}: signer {} holds {} lamports, below alert_min_balance {}",
+                self.name(),
+                signer,
+                balance,
+                minimum
+            ));
+        } else {
+            resolve(&format!(
+                "{}: signer {} holds {} lamports, back above alert_min_balance {}",
+                self.name(),
+                signer,
+                balance,
+                minimum
+            ));
+        }
+    }
+}
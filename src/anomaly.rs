@@ -0,0 +1,212 @@
+//! Circuit breaker on anomalous activity.
+//! Over a sliding `anomaly_interval_secs`, counts the nonces the L1 watched
+//! account advances by between polls and the amounts of the transfers
+//! admitted for submission (each nonce once, however often it is retried).
+//! Once either passes its maximum (`anomaly_max_nonces`,
+//! `anomaly_max_amount`), the breaker trips: an alert is raised, the trip is
+//! saved in the state (`anomaly_breaker`) and nothing is submitted, restarts
+//! included, until an operator resumes with `resume-breaker` or
+//! `POST /resume`. The transfer that tripped it is held, not failed. The first
+//! poll of a run only sets the baseline, since the backlog of a stopped
+//! relayer is no sudden activity; `max_expected_jump` covers that gap.
+//! Only native transfers count towards `anomaly_max_amount`: token and NFT
+//! amounts are in each mint's base units and do not add up with lamports.
+//! They still count as nonces and are held while the breaker is tripped.
+
+use crate::{alerts, state::StateStore};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, fmt, sync::Mutex};
+
+/// State key of a tripped breaker, removed when resumed
+pub const ANOMALY_BREAKER_KEY: &str = "anomaly_breaker";
+
+/// Why and when the breaker tripped
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BreakerTrip {
+    pub reason: String,
+    pub at: u64,
+}
+
+/// Error of the transfer held by a tripped breaker
+#[derive(Debug)]
+pub struct BreakerTripped(pub BreakerTrip);
+
+impl fmt::Display for BreakerTripped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Anomaly circuit breaker tripped at {}: {}; resume with resume-breaker or POST /resume",
+            self.0.at, self.0.reason
+        )
+    }
+}
+
+impl std::error::Error for BreakerTripped {}
+
+#[derive(Default)]
+struct Window {
+    /// Time and count of each observed nonce advance
+    nonces: VecDeque<(u64, u64)>,
+    /// Time, nonce and amount of each admitted transfer
+    amounts: VecDeque<(u64, u64, u64)>,
+    tripped: Option<BreakerTrip>,
+}
+
+impl Window {
+    fn expire(&mut self, now: u64, interval: u64) {
+        let cutoff = now.saturating_sub(interval);
+        while self.nonces.front().is_some_and(|(at, _)| *at <= cutoff) {
+            self.nonces.pop_front();
+        }
+        while self.amounts.front().is_some_and(|(at, _, _)| *at <= cutoff) {
+            self.amounts.pop_front();
+        }
+    }
+}
+
+pub struct AnomalyBreaker {
+    interval: u64,
+    /// 0 disables the check
+    max_nonces: u64,
+    max_amount: u64,
+    window: Mutex<Window>,
+}
+
+impl AnomalyBreaker {
+    pub fn new(interval_secs: u64, max_nonces: u64, max_amount: u64) -> Self {
+        Self {
+            interval: interval_secs.max(1),
+            max_nonces,
+            max_amount,
+            window: Mutex::new(Window::default()),
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.max_nonces > 0 || self.max_amount > 0
+    }
+
+    /// The saved trip, if any. Re-read before each batch so that
+    /// `resume-breaker` reaches a running relayer; a resume clears the
+    /// counted activity
+    pub fn tripped(&self, store: &StateStore) -> Result<Option<BreakerTrip>> {
+        let trip: Option<BreakerTrip> = store.get(ANOMALY_BREAKER_KEY)?;
+        let mut window = self.window.lock().unwrap();
+        if trip.is_none() && window.tripped.is_some() {
+            tracing::info!("Anomaly circuit breaker resumed");
+            *window = Window::default();
+        }
+        window.tripped = trip.clone();
+        Ok(trip)
+    }
+
+    /// Counts an L1 nonce advance of `advance` nonces
+    pub fn observe_nonces(&self, store: &StateStore, now: u64, advance: u64) -> Result<()> {
+        if self.max_nonces == 0 || advance == 0 {
+            return Ok(());
+        }
+        let mut window = self.window.lock().unwrap();
+        window.expire(now, self.interval);
+        window.nonces.push_back((now, advance));
+        let observed: u64 = window.nonces.iter().map(|(_, count)| count).sum();
+        if observed > self.max_nonces && window.tripped.is_none() {
+            let reason = format!(
+                "L1 nonce advanced by {} in {}s (max {})",
+                observed, self.interval, self.max_nonces
+            );
+            trip(store, &mut window, reason, now)?;
+        }
+        Ok(())
+    }
+
+    /// Counts the transfer of `nonce` admitted for submission, with its
+    /// amount in lamports if native; fails with `BreakerTripped` when it trips
+    /// the breaker or the breaker is tripped
+    pub fn admit(
+        &self,
+        store: &StateStore,
+        now: u64,
+        nonce: u64,
+        lamports: Option<u64>,
+    ) -> Result<()> {
+        if !self.enabled() {
+            return Ok(());
+        }
+        let mut window = self.window.lock().unwrap();
+        if let Some(trip) = &window.tripped {
+            return Err(BreakerTripped(trip.clone()).into());
+        }
+        let Some(amount) = lamports.filter(|_| self.max_amount > 0) else {
+            return Ok(());
+        };
+        window.expire(now, self.interval);
+        if window
+            .amounts
+            .iter()
+            .any(|(_, counted, _)| *counted == nonce)
+        {
+            return Ok(());
+        }
+        let total = window.amounts.iter().fold(amount, |total, (_, _, amount)| {
+            total.saturating_add(*amount)
+        });
+        if total > self.max_amount {
+            let reason = format!(
+                "nonce {} would bring the amount admitted in {}s to {} (max {})",
+                nonce, self.interval, total, self.max_amount
+            );
+            let trip = trip(store, &mut window, reason, now)?;
+            return Err(BreakerTripped(trip).into());
+        }
+        window.amounts.push_back((now, nonce, amount));
+        Ok(())
+    }
+}
+
+/// Saves and alerts the trip
+fn trip(store: &StateStore, window: &mut Window, reason: String, now: u64) -> Result<BreakerTrip> {
+    let trip = BreakerTrip { reason, at: now };
+    store.put(ANOMALY_BREAKER_KEY, &trip)?;
+    alerts::raise(&format!(
+        "Anomaly circuit breaker tripped, submissions paused until resumed: {}",
+        trip.reason
+    ));
+    window.tripped = Some(trip.clone());
+    Ok(trip)
+}
+
+/// Resumes a tripped breaker; false if it was not tripped
+pub fn resume(store: &StateStore) -> Result<bool> {
+    if store.get::<BreakerTrip>(ANOMALY_BREAKER_KEY)?.is_none() {
+        return Ok(false);
+    }
+    store.remove(ANOMALY_BREAKER_KEY)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_native_amounts_count_but_every_transfer_is_held_once_tripped() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = StateStore::open(dir.path()).unwrap();
+        let breaker = AnomalyBreaker::new(60, 0, 1_000);
+        // 代币数量不与 lamports 相加
+        breaker.admit(&store, 100, 1, Some(900)).unwrap();
+        breaker.admit(&store, 101, 2, None).unwrap();
+        breaker.admit(&store, 102, 2, None).unwrap();
+        let error = breaker.admit(&store, 103, 3, Some(200)).unwrap_err();
+        assert!(error.is::<BreakerTripped>(), "{}", error);
+        assert!(breaker
+            .admit(&store, 104, 4, None)
+            .unwrap_err()
+            .is::<BreakerTripped>());
+        assert!(breaker.tripped(&store).unwrap().is_some());
+        assert!(resume(&store).unwrap());
+        assert!(breaker.tripped(&store).unwrap().is_none());
+        breaker.admit(&store, 105, 3, Some(200)).unwrap();
+    }
+}
@@ -0,0 +1,654 @@
+//! Manual approval of large transfers.
+//! With `manual_approval_threshold` set, a transfer above it is parked before
+//! its L2 transaction is built: the relayer records an approval request,
+//! notifies the approvers (`approval_webhook_url`, Slack-compatible) and holds
+//! the nonce until `required_approvals` of the `approver_pubkeys` have signed
+//! it with `approve`. A request still short of approvals after
+//! `approval_timeout_secs` raises an alert. Waiting does not count as a failed
+//! attempt, so a parked nonce is never dead-lettered.
+//!
+//! `approvals` and the admin API's `GET /approvals` list the parked requests
+//! with the message to sign, so an approver without access to `state_dir`
+//! can sign it with their key elsewhere and submit the signature with
+//! `POST /approve/{nonce}`.
+//!
+//! `manual_approval_threshold` is in lamports and applies to native
+//! transfers. A token or NFT transfer is parked above its mint mapping's
+//! `approval_threshold`, in the mint's base units, and never without one.
+//! The approval message of a token transfer names its L2 mint, so a signature
+//! for one mint does not approve another at the same nonce.
+//!
+//! Every approval message also names the bridge program and the watched
+//! account (`ApprovalScope`), so a signature collected for one deployment
+//! does not approve the same nonce on another that shares approvers.
+
+use crate::{
+    alerts,
+    pipeline::FetchedTransfer,
+    pubkeys::{DestinationProgramId, SourceAccount},
+    state::StateStore,
+    webhooks, Relayer,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+};
+use std::{fmt, str::FromStr, time::Duration};
+
+/// A parked transfer waiting for approvals
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApprovalRequest {
+    pub nonce: u64,
+    pub amount: u64,
+    /// Recipient (base58)
+    pub to: String,
+    /// L2 mint of a token or NFT transfer (base58), none for a native one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mint: Option<String>,
+    pub requested_at: u64,
+    /// Whether the timeout alert was raised
+    #[serde(default)]
+    pub escalated: bool,
+}
+
+/// The source an approval is for, with the bridge program its transfers
+/// are relayed to and its watched account
+#[derive(Debug, Clone, Copy)]
+pub struct ApprovalScope<'a> {
+    pub source_id: &'a str,
+    pub program_id: DestinationProgramId,
+    pub watched_account: SourceAccount,
+}
+
+impl ApprovalRequest {
+    /// Bytes an approver signs; binds the bridge program, the watched
+    /// account, the source, nonce, amount, recipient and, for a token or NFT
+    /// transfer, the L2 mint
+    pub fn message(&self, scope: &ApprovalScope) -> Vec<u8> {
+        let prefix = format!(
+            "sol-bridge-relayer approval: program {} account {} source {} nonce {} amount {}",
+            scope.program_id, scope.watched_account, scope.source_id, self.nonce, self.amount
+        );
+        match &self.mint {
+            None => format!("{} to {}", prefix, self.to),
+            Some(mint) => format!("{} of mint {} to {}", prefix, mint, self.to),
+        }
+        .into_bytes()
+    }
+
+    /// The amount with its unit, for notifications
+    fn describe_amount(&self) -> String {
+        match &self.mint {
+            None => format!("{} lamports", self.amount),
+            Some(mint) => format!("{} base units of mint {}", self.amount, mint),
+        }
+    }
+}
+
+/// One approver's signature over an approval request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Approval {
+    pub approver: String,
+    pub signature: String,
+    pub approved_at: u64,
+}
+
+/// A parked request with the approvers who signed it, as listed for
+/// operators
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingApproval {
+    #[serde(flatten)]
+    pub request: ApprovalRequest,
+    /// Approvers whose signature verifies
+    pub approved_by: Vec<String>,
+    pub required: usize,
+    /// Bytes an approver signs, as UTF-8
+    pub message: String,
+}
+
+/// A transfer held until enough approvals are recorded
+#[derive(Debug)]
+pub struct AwaitingApproval {
+    pub nonce: u64,
+    pub approvals: usize,
+    pub required: usize,
+}
+
+impl fmt::Display for AwaitingApproval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Nonce {} awaits approval ({} of {} approvals)",
+            self.nonce, self.approvals, self.required
+        )
+    }
+}
+
+impl std::error::Error for AwaitingApproval {}
+
+/// Prefix of the approval request keys
+const REQUESTS_PREFIX: &str = "approvals/requests/";
+
+pub fn request_key(nonce: u64) -> String {
+    format!("{}{}", REQUESTS_PREFIX, nonce)
+}
+
+fn signatures_prefix(nonce: u64) -> String {
+    format!("approvals/signatures/{}/", nonce)
+}
+
+fn signature_key(nonce: u64, approver: &Pubkey) -> String {
+    format!("{}{}", signatures_prefix(nonce), approver)
+}
+
+pub struct ApprovalPolicy {
+    /// Native transfers above this many lamports need approval
+    pub threshold: u64,
+    pub approvers: Vec<Pubkey>,
+    pub required: usize,
+    /// How long a request may wait before it is escalated
+    pub timeout: Duration,
+    pub webhook_url: Option<String>,
+}
+
+impl ApprovalPolicy {
+    /// Policy for `threshold`, or `None` when manual approval is disabled
+    pub fn new(
+        threshold: Option<u64>,
+        approvers: &[String],
+        required: usize,
+        timeout: Duration,
+        webhook_url: Option<String>,
+    ) -> Result<Option<Self>> {
+        let Some(threshold) = threshold else {
+            return Ok(None);
+        };
+        let approvers = approvers
+            .iter()
+            .map(|approver| {
+                Pubkey::from_str(approver)
+                    .map_err(|e| anyhow::anyhow!("Invalid approver pubkey {}: {}", approver, e))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if required == 0 || required > approvers.len() {
+            return Err(anyhow::anyhow!(
+                "manual_approval_threshold requires 1 to {} required_approvals (one per approver_pubkeys entry), got {}",
+                approvers.len(),
+                required
+            ));
+        }
+
+        Ok(Some(Self {
+            threshold,
+            approvers,
+            required,
+            timeout,
+            webhook_url,
+        }))
+    }
+
+    /// Approvals of `request` in `store` signed by distinct configured approvers
+    pub fn valid_approvals(
+        &self,
+        store: &StateStore,
+        scope: &ApprovalScope,
+        request: &ApprovalRequest,
+    ) -> Result<Vec<Approval>> {
+        let message = request.message(scope);
+        let mut approvals = Vec::new();
+        for key in store.list(&signatures_prefix(request.nonce))? {
+            let Some(approval) = store.get::<Approval>(&key)? else {
+                continue;
+            };
+            let valid = Pubkey::from_str(&approval.approver)
+                .ok()
+                .filter(|approver| self.approvers.contains(approver))
+                .zip(Signature::from_str(&approval.signature).ok())
+                .is_some_and(|(approver, signature)| signature.verify(approver.as_ref(), &message));
+            // 签名针对旧请求（金额、收款人或代币已变）时不计入
+            if valid
+                && !approvals
+                    .iter()
+                    .any(|counted: &Approval| counted.approver == approval.approver)
+            {
+                approvals.push(approval);
+            }
+        }
+        Ok(approvals)
+    }
+
+    /// Signs the request of `nonce` in `store` with `approver`'s key; returns
+    /// the request and its number of valid approvals
+    pub fn approve(
+        &self,
+        store: &StateStore,
+        scope: &ApprovalScope,
+        nonce: u64,
+        approver: &Keypair,
+        now: u64,
+    ) -> Result<(ApprovalRequest, usize)> {
+        let request = store
+            .get::<ApprovalRequest>(&request_key(nonce))?
+            .ok_or_else(|| anyhow::anyhow!("Nonce {} is not awaiting approval", nonce))?;
+        let signature = approver.sign_message(&request.message(scope));
+        self.add_signature(store, scope, nonce, &approver.pubkey(), &signature, now)?
+            .map_err(anyhow::Error::msg)
+    }
+
+    /// Records `approver`'s `signature` of the request of `nonce`, made
+    /// elsewhere; returns the request and its number of valid approvals, or
+    /// why the signature is refused
+    pub fn add_signature(
+        &self,
+        store: &StateStore,
+        scope: &ApprovalScope,
+        nonce: u64,
+        approver: &Pubkey,
+        signature: &Signature,
+        now: u64,
+    ) -> Result<Result<(ApprovalRequest, usize), String>> {
+        let Some(request) = store.get::<ApprovalRequest>(&request_key(nonce))? else {
+            return Ok(Err(format!("Nonce {} is not awaiting approval", nonce)));
+        };
+        if !self.approvers.contains(approver) {
+            return Ok(Err(format!(
+                "{} is not one of the configured approver_pubkeys",
+                approver
+            )));
+        }
+        if !signature.verify(approver.as_ref(), &request.message(scope)) {
+            return Ok(Err(format!(
+                "Signature does not verify for {} over the approval message of nonce {}",
+                approver, nonce
+            )));
+        }
+        let approval = Approval {
+            approver: approver.to_string(),
+            signature: signature.to_string(),
+            approved_at: now,
+        };
+        store.put(&signature_key(nonce, approver), &approval)?;
+        let approvals = self.valid_approvals(store, scope, &request)?.len();
+        Ok(Ok((request, approvals)))
+    }
+
+    /// Parked requests in `store`, by nonce
+    pub fn pending(
+        &self,
+        store: &StateStore,
+        scope: &ApprovalScope,
+    ) -> Result<Vec<PendingApproval>> {
+        let mut pending = Vec::new();
+        for key in store.list(REQUESTS_PREFIX)? {
+            let Some(request) = store.get::<ApprovalRequest>(&key)? else {
+                continue;
+            };
+            let approved_by = self
+                .valid_approvals(store, scope, &request)?
+                .into_iter()
+                .map(|approval| approval.approver)
+                .collect();
+            pending.push(PendingApproval {
+                message: String::from_utf8_lossy(&request.message(scope)).into_owned(),
+                request,
+                approved_by,
+                required: self.required,
+            });
+        }
+        pending.sort_by_key(|pending| pending.request.nonce);
+        Ok(pending)
+    }
+
+    /// Whether `fetched` is above its threshold: the lamport threshold for a
+    /// native transfer, its mint mapping's for a token or NFT transfer
+    pub fn needs_approval(&self, fetched: &FetchedTransfer) -> bool {
+        let threshold = match &fetched.token {
+            None => Some(self.threshold),
+            Some(token) => token.approval_threshold,
+        };
+        threshold.is_some_and(|threshold| fetched.amount > threshold)
+    }
+
+    /// Posts `text` to the approval webhook, if one is configured
+    async fn notify(&self, text: &str) {
+        tracing::info!("{}", text);
+        let Some(url) = &self.webhook_url else {
+            return;
+        };
+        webhooks::post_json(
+            url,
+            &serde_json::json!({ "text": text }),
+            "notify approvers",
+        )
+        .await;
+    }
+}
+
+impl Relayer {
+    /// What this relayer's approval messages are scoped to
+    pub(crate) fn approval_scope(&self) -> ApprovalScope<'_> {
+        ApprovalScope {
+            source_id: &self.source_id,
+            program_id: self.transaction_builder.program_id,
+            watched_account: self.watched_account,
+        }
+    }
+
+    /// Fails with `AwaitingApproval` while `fetched` is above the approval
+    /// threshold and short of approvals; records the request and notifies the
+    /// approvers the first time, and escalates it once it times out
+    pub(crate) async fn check_approval(&self, fetched: &FetchedTransfer) -> Result<()> {
+        let Some(policy) = &self.approval else {
+            return Ok(());
+        };
+        if !policy.needs_approval(fetched) {
+            return Ok(());
+        }
+
+        let now = self.clock.unix_timestamp();
+        let to = fetched.to_address.to_string();
+        let mint = fetched.token.map(|token| token.l2_mint.to_string());
+        let key = request_key(fetched.nonce);
+        let mut request = match self.state.get::<ApprovalRequest>(&key)? {
+            Some(request)
+                if request.amount == fetched.amount && request.to == to && request.mint == mint =>
+            {
+                request
+            }
+            _ => {
+                let request = ApprovalRequest {
+                    nonce: fetched.nonce,
+                    amount: fetched.amount,
+                    to,
+                    mint,
+                    requested_at: now,
+                    escalated: false,
+                };
+                self.state.put(&key, &request)?;
+                policy
+                    .notify(&format!(
+                        "Source {} nonce {} ({} to {}) needs {} approval(s) before it is relayed: run `approve {} --keypair <approver keypair>`",
+                        self.source_id,
+                        request.nonce,
+                        request.describe_amount(),
+                        request.to,
+                        policy.required,
+                        request.nonce
+                    ))
+                    .await;
+                request
+            }
+        };
+
+        let approvals = policy
+            .valid_approvals(&self.state, &self.approval_scope(), &request)?
+            .len();
+        if approvals >= policy.required {
+            return Ok(());
+        }
+        if !request.escalated && now >= request.requested_at + policy.timeout.as_secs() {
+            let message = format!(
+                "Source {} nonce {} has waited {}s for approval with {} of {} approvals",
+                self.source_id,
+                request.nonce,
+                now - request.requested_at,
+                approvals,
+                policy.required
+            );
+            alerts::raise(&message);
+            policy.notify(&message).await;
+            request.escalated = true;
+            self.state.put(&key, &request)?;
+        }
+        Err(AwaitingApproval {
+            nonce: fetched.nonce,
+            approvals,
+            required: policy.required,
+        }
+        .into())
+    }
+
+    /// Whether `nonce` has a request still short of approvals
+    pub(crate) fn awaiting_approval(&self, nonce: u64) -> Result<bool> {
+        let Some(policy) = &self.approval else {
+            return Ok(false);
+        };
+        let Some(request) = self.state.get::<ApprovalRequest>(&request_key(nonce))? else {
+            return Ok(false);
+        };
+        Ok(policy
+            .valid_approvals(&self.state, &self.approval_scope(), &request)?
+            .len()
+            < policy.required)
+    }
+
+    /// Removes the request of the relayed `nonce`, returning its valid approvals
+    pub(crate) fn take_approvals(&self, nonce: u64) -> Result<Vec<Approval>> {
+        let Some(policy) = &self.approval else {
+            return Ok(Vec::new());
+        };
+        let Some(request) = self.state.get::<ApprovalRequest>(&request_key(nonce))? else {
+            return Ok(Vec::new());
+        };
+        let approvals = policy.valid_approvals(&self.state, &self.approval_scope(), &request)?;
+        for key in self.state.list(&signatures_prefix(nonce))? {
+            self.state.remove(&key)?;
+        }
+        self.state.remove(&request_key(nonce))?;
+        Ok(approvals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::TokenTransfer;
+
+    fn scope() -> ApprovalScope<'static> {
+        ApprovalScope {
+            source_id: "main",
+            program_id: Pubkey::new_from_array([1; 32]).into(),
+            watched_account: Pubkey::new_from_array([2; 32]).into(),
+        }
+    }
+
+    fn policy(approvers: &[&Keypair]) -> ApprovalPolicy {
+        let approvers: Vec<String> = approvers
+            .iter()
+            .map(|approver| approver.pubkey().to_string())
+            .collect();
+        ApprovalPolicy::new(Some(1_000), &approvers, 1, Duration::from_secs(60), None)
+            .unwrap()
+            .unwrap()
+    }
+
+    fn request(nonce: u64, amount: u64) -> ApprovalRequest {
+        ApprovalRequest {
+            nonce,
+            amount,
+            to: Pubkey::new_unique().to_string(),
+            mint: None,
+            requested_at: 0,
+            escalated: false,
+        }
+    }
+
+    #[test]
+    fn amounts_up_to_the_threshold_pass_without_approval() {
+        let policy = policy(&[&Keypair::new()]);
+        let to = Pubkey::new_unique();
+        assert!(!policy.needs_approval(&FetchedTransfer::native(1, 999, to)));
+        assert!(!policy.needs_approval(&FetchedTransfer::native(1, 1_000, to)));
+        assert!(policy.needs_approval(&FetchedTransfer::native(1, 1_001, to)));
+    }
+
+    #[test]
+    fn only_configured_approvers_with_verifying_signatures_are_recorded() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = StateStore::open(dir.path()).unwrap();
+        let (approver, stranger) = (Keypair::new(), Keypair::new());
+        let policy = policy(&[&approver]);
+        let parked = request(1, 5_000);
+        store.put(&request_key(1), &parked).unwrap();
+
+        let refused = policy
+            .add_signature(
+                &store,
+                &scope(),
+                1,
+                &stranger.pubkey(),
+                &stranger.sign_message(&parked.message(&scope())),
+                0,
+            )
+            .unwrap()
+            .unwrap_err();
+        assert!(refused.contains("not one of the configured"), "{}", refused);
+
+        // 其他审批人的签名不能冒充
+        let refused = policy
+            .add_signature(
+                &store,
+                &scope(),
+                1,
+                &approver.pubkey(),
+                &stranger.sign_message(&parked.message(&scope())),
+                0,
+            )
+            .unwrap()
+            .unwrap_err();
+        assert!(refused.contains("does not verify"), "{}", refused);
+        assert!(policy
+            .valid_approvals(&store, &scope(), &parked)
+            .unwrap()
+            .is_empty());
+
+        let (_, approvals) = policy
+            .add_signature(
+                &store,
+                &scope(),
+                1,
+                &approver.pubkey(),
+                &approver.sign_message(&parked.message(&scope())),
+                0,
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(approvals, 1);
+    }
+
+    #[test]
+    fn an_approval_of_another_nonce_or_amount_does_not_count() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = StateStore::open(dir.path()).unwrap();
+        let approver = Keypair::new();
+        let policy = policy(&[&approver]);
+        let parked = request(1, 5_000);
+        store.put(&request_key(1), &parked).unwrap();
+
+        let other_nonce = ApprovalRequest {
+            nonce: 2,
+            ..parked.clone()
+        };
+        let refused = policy
+            .add_signature(
+                &store,
+                &scope(),
+                1,
+                &approver.pubkey(),
+                &approver.sign_message(&other_nonce.message(&scope())),
+                0,
+            )
+            .unwrap()
+            .unwrap_err();
+        assert!(refused.contains("does not verify"), "{}", refused);
+
+        policy.approve(&store, &scope(), 1, &approver, 0).unwrap();
+        assert_eq!(
+            policy
+                .valid_approvals(&store, &scope(), &parked)
+                .unwrap()
+                .len(),
+            1
+        );
+        // 金额变化后旧签名不再有效
+        let changed = ApprovalRequest {
+            amount: 6_000,
+            ..parked
+        };
+        store.put(&request_key(1), &changed).unwrap();
+        assert!(policy
+            .valid_approvals(&store, &scope(), &changed)
+            .unwrap()
+            .is_empty());
+        assert!(policy.pending(&store, &scope()).unwrap()[0]
+            .approved_by
+            .is_empty());
+    }
+
+    fn token(approval_threshold: Option<u64>) -> TokenTransfer {
+        TokenTransfer {
+            l2_mint: Pubkey::new_unique(),
+            decimals: 6,
+            nft: false,
+            named: None,
+            approval_threshold,
+            dual_read_threshold: 0,
+            dust_threshold: 0,
+        }
+    }
+
+    #[test]
+    fn token_transfers_use_their_mint_threshold_and_sign_the_mint() {
+        let approver = Keypair::new();
+        let policy = policy(&[&approver]);
+        let mut fetched = FetchedTransfer::native(1, 5_000, Pubkey::new_unique());
+        assert!(policy.needs_approval(&fetched));
+        // 代币数量不按 lamports 阈值计算
+        fetched.token = Some(token(None));
+        assert!(!policy.needs_approval(&fetched));
+        fetched.token = Some(token(Some(5_000)));
+        assert!(!policy.needs_approval(&fetched));
+        fetched.token = Some(token(Some(4_999)));
+        assert!(policy.needs_approval(&fetched));
+
+        let request = |mint: Option<Pubkey>| ApprovalRequest {
+            nonce: 1,
+            amount: 5_000,
+            to: fetched.to_address.to_string(),
+            mint: mint.map(|mint| mint.to_string()),
+            requested_at: 0,
+            escalated: false,
+        };
+        let (one, other) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let signature = approver.sign_message(&request(Some(one)).message(&scope()));
+        assert!(signature.verify(
+            approver.pubkey().as_ref(),
+            &request(Some(one)).message(&scope())
+        ));
+        assert!(!signature.verify(
+            approver.pubkey().as_ref(),
+            &request(Some(other)).message(&scope())
+        ));
+        assert!(!signature.verify(approver.pubkey().as_ref(), &request(None).message(&scope())));
+    }
+
+    #[test]
+    fn an_approval_for_another_program_or_watched_account_does_not_count() {
+        let approver = Keypair::new();
+        let parked = request(1, 5_000);
+        let signature = approver.sign_message(&parked.message(&scope()));
+        assert!(signature.verify(approver.pubkey().as_ref(), &parked.message(&scope())));
+        let other_program = ApprovalScope {
+            program_id: Pubkey::new_unique().into(),
+            ..scope()
+        };
+        assert!(!signature.verify(approver.pubkey().as_ref(), &parked.message(&other_program)));
+        let other_account = ApprovalScope {
+            watched_account: Pubkey::new_unique().into(),
+            ..scope()
+        };
+        assert!(!signature.verify(approver.pubkey().as_ref(), &parked.message(&other_account)));
+    }
+}
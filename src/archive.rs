@@ -0,0 +1,264 @@
+//! Receipt archives.
+//! `compact` moves old receipts out of the state directory into one
+//! zstd-compressed JSON-lines file per UTC month of `relayed_at`
+//! (`archive/receipts-2024-03.jsonl.zst`, next to the source's state). The
+//! manifest (`archive/manifest` in the state) records each file's receipt
+//! count, the SHA-256 of its uncompressed lines and the range of nonces and
+//! times it covers.
+//!
+//! A month is rewritten as a whole: the archived receipts and the new ones
+//! are written to a temporary file, which is read back and checked against
+//! the count and hash before it replaces the old file. Receipts are removed
+//! from the hot store only after the manifest names them and the file has
+//! been verified again, so an interrupted run leaves receipts in both places
+//! and the next run merges them.
+//!
+//! Only receipts below the completed prefix of the persisted queue (minus
+//! `replay_window_nonces`), older than the retention and any
+//! `replay_window_days`, and already enriched are archived, so a running
+//! relayer never reads or writes a receipt that is being moved.
+
+use crate::{receipts::Receipt, state::StateStore};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_sdk::hash::hashv;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// State key of the archive manifest
+pub const ARCHIVE_MANIFEST_KEY: &str = "archive/manifest";
+/// zstd compression level of the monthly files
+const COMPRESSION_LEVEL: i32 = 19;
+
+/// One monthly archive file as recorded in the manifest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveFile {
+    /// `YYYY-MM` of the receipts' `relayed_at`
+    pub month: String,
+    pub count: u64,
+    /// SHA-256 of the uncompressed lines (base58)
+    pub sha256: String,
+    pub first_nonce: u64,
+    pub last_nonce: u64,
+    /// Earliest and latest `relayed_at` in the file
+    pub first_at: u64,
+    pub last_at: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ArchiveManifest {
+    /// Files by month
+    pub files: BTreeMap<String, ArchiveFile>,
+}
+
+/// What `compact` did
+#[derive(Debug, Default, Serialize)]
+pub struct CompactionReport {
+    /// Receipts moved into archives
+    pub archived: u64,
+    /// Months written
+    pub months: Vec<String>,
+    /// Receipts at or above this nonce were not considered
+    pub nonce_floor: u64,
+    /// Receipts relayed at or after this time were kept
+    pub cutoff: u64,
+    /// Older receipts kept because they still wait for enrichment
+    pub awaiting_enrichment: u64,
+}
+
+/// Monthly receipt files of one source
+pub struct Archive {
+    dir: PathBuf,
+}
+
+impl Archive {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, month: &str) -> PathBuf {
+        self.dir.join(format!("receipts-{}.jsonl.zst", month))
+    }
+
+    /// Reads the receipts of `file`, failing if the count or hash differ
+    /// from the manifest
+    pub fn read(&self, file: &ArchiveFile) -> Result<Vec<Receipt>> {
+        read_verified(&self.path(&file.month), file)
+    }
+
+    /// Archived receipts of every month that has one relayed at or after
+    /// `since`, verified against the manifest in `store`
+    pub fn receipts(&self, store: &StateStore, since: Option<u64>) -> Result<Vec<Receipt>> {
+        let manifest: ArchiveManifest = store.get(ARCHIVE_MANIFEST_KEY)?.unwrap_or_default();
+        let mut receipts = Vec::new();
+        for file in manifest.files.values() {
+            if since.is_some_and(|since| file.last_at < since) {
+                continue;
+            }
+            receipts.extend(self.read(file)?);
+        }
+        Ok(receipts)
+    }
+
+    /// Writes `receipts` as the file of `month` and returns its manifest entry
+    fn write(&self, month: &str, receipts: &BTreeMap<u64, Receipt>) -> Result<ArchiveFile> {
+        let mut lines = Vec::new();
+        for receipt in receipts.values() {
+            serde_json::to_writer(&mut lines, receipt)?;
+            lines.push(b'\n');
+        }
+        let times = receipts.values().map(|receipt| receipt.relayed_at);
+        let file = ArchiveFile {
+            month: month.to_string(),
+            count: receipts.len() as u64,
+            sha256: hashv(&[&lines]).to_string(),
+            first_nonce: receipts.keys().next().copied().unwrap_or_default(),
+            last_nonce: receipts.keys().next_back().copied().unwrap_or_default(),
+            first_at: times.clone().min().unwrap_or_default(),
+            last_at: times.max().unwrap_or_default(),
+        };
+
+        fs::create_dir_all(&self.dir).map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to create archive directory {}: {}",
+                self.dir.display(),
+                e
+            )
+        })?;
+        let path = self.path(month);
+        let tmp = path.with_extension("zst.tmp");
+        let compressed = zstd::stream::encode_all(&lines[..], COMPRESSION_LEVEL)?;
+        fs::File::create(&tmp)
+            .and_then(|mut out| {
+                out.write_all(&compressed)?;
+                out.sync_all()
+            })
+            .map_err(|e| anyhow::anyhow!("Failed to write archive {}: {}", tmp.display(), e))?;
+        // 替换前先读回校验
+        read_verified(&tmp, &file)?;
+        fs::rename(&tmp, &path)
+            .map_err(|e| anyhow::anyhow!("Failed to replace archive {}: {}", path.display(), e))?;
+        Ok(file)
+    }
+}
+
+fn read_verified(path: &Path, file: &ArchiveFile) -> Result<Vec<Receipt>> {
+    let compressed = fs::read(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read archive {}: {}", path.display(), e))?;
+    let lines = zstd::stream::decode_all(&compressed[..])
+        .map_err(|e| anyhow::anyhow!("Archive {} does not decompress: {}", path.display(), e))?;
+    let sha256 = hashv(&[&lines]).to_string();
+    if sha256 != file.sha256 {
+        return Err(anyhow::anyhow!(
+            "Archive {} has hash {}, the manifest records {}",
+            path.display(),
+            sha256,
+            file.sha256
+        ));
+    }
+    let receipts = lines
+        .split(|byte| *byte == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(serde_json::from_slice)
+        .collect::<Result<Vec<Receipt>, _>>()
+        .map_err(|e| {
+            anyhow::anyhow!("Archive {} holds an invalid receipt: {}", path.display(), e)
+        })?;
+    if receipts.len() as u64 != file.count {
+        return Err(anyhow::anyhow!(
+            "Archive {} holds {} receipts, the manifest records {}",
+            path.display(),
+            receipts.len(),
+            file.count
+        ));
+    }
+    Ok(receipts)
+}
+
+/// `YYYY-MM` of a Unix time, in UTC
+fn month_of(unix: u64) -> String {
+    let (year, month, _) = crate::report::civil_from_days((unix / 86_400) as i64);
+    format!("{:04}-{:02}", year, month)
+}
+
+/// Moves the receipts in `store` below `nonce_floor` and relayed before
+/// `cutoff` into `archive`
+pub fn compact(
+    store: &StateStore,
+    archive: &Archive,
+    nonce_floor: u64,
+    cutoff: u64,
+) -> Result<CompactionReport> {
+    let mut report = CompactionReport {
+        nonce_floor,
+        cutoff,
+        ..CompactionReport::default()
+    };
+    // 合并的回执保存在每个 nonce 下，归档中每笔交易只存一次
+    let mut months: BTreeMap<String, (Vec<u64>, BTreeMap<u64, Receipt>)> = BTreeMap::new();
+    for nonce in Receipt::stored_nonces(store)? {
+        if nonce >= nonce_floor {
+            break;
+        }
+        // 列出后被清理的回执直接跳过
+        let Some(receipt) = Receipt::load(store, nonce)? else {
+            continue;
+        };
+        if receipt.relayed_at >= cutoff {
+            continue;
+        }
+        if receipt.needs_enrichment() {
+            report.awaiting_enrichment += 1;
+            continue;
+        }
+        let (stored, fresh) = months.entry(month_of(receipt.relayed_at)).or_default();
+        stored.push(nonce);
+        fresh.insert(receipt.nonce, receipt);
+    }
+
+    for (month, (stored, fresh)) in months {
+        let mut manifest: ArchiveManifest = store.get(ARCHIVE_MANIFEST_KEY)?.unwrap_or_default();
+        let mut receipts: BTreeMap<u64, Receipt> = match manifest.files.get(&month) {
+            Some(file) => archive
+                .read(file)?
+                .into_iter()
+                .map(|receipt| (receipt.nonce, receipt))
+                .collect(),
+            None => BTreeMap::new(),
+        };
+        receipts.extend(fresh);
+        let file = archive.write(&month, &receipts)?;
+        manifest.files.insert(month.clone(), file.clone());
+        store.put(ARCHIVE_MANIFEST_KEY, &manifest)?;
+
+        // 删除前确认归档覆盖每一个要删除的 nonce
+        let covered: BTreeSet<u64> = archive
+            .read(&file)?
+            .iter()
+            .flat_map(|receipt| std::iter::once(receipt.nonce).chain(receipt.coalesced.clone()))
+            .collect();
+        if let Some(missing) = stored.iter().find(|nonce| !covered.contains(nonce)) {
+            return Err(anyhow::anyhow!(
+                "Archive of {} is missing nonce {}; nothing of that month was removed",
+                month,
+                missing
+            ));
+        }
+        for nonce in &stored {
+            Receipt::remove(store, *nonce)?;
+        }
+        tracing::info!(
+            "Archived {} receipt(s) of {} ({} in the file)",
+            stored.len(),
+            month,
+            file.count
+        );
+        report.archived += stored.len() as u64;
+        report.months.push(month);
+    }
+    Ok(report)
+}
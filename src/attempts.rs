@@ -0,0 +1,194 @@
+//! Failure history of pending nonces.
+//! Every submission attempt keeps a context per nonce: when it started, the
+//! stage it reached, the RPC endpoint of that stage, and the blockhash and
+//! signature of the transaction once built and sent. When the attempt fails, the context of the failed
+//! nonce becomes an `AttemptRecord` in its retry state, keeping the most
+//! recent `FAILURE_HISTORY_LIMIT`. The history is copied into the receipt
+//! once the nonce lands and into the dead letter if it is given up on, and
+//! the attempts each relayed transfer took are exported as the
+//! `relayer_attempts_per_transfer` histogram.
+
+use serde::{Deserialize, Serialize};
+use solana_sdk::{hash::Hash, signature::Signature};
+use std::{collections::HashMap, fmt, sync::Mutex, time::SystemTime};
+
+/// Failed attempts kept per nonce
+pub const FAILURE_HISTORY_LIMIT: usize = 10;
+
+/// Stage at which an attempt failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureClass {
+    /// Reading the transfer from L1
+    Fetch,
+    /// Building, signing or cost-checking the L2 transaction
+    Build,
+    /// Sending the transaction to L2
+    Send,
+    /// The L2 program rejected the transaction
+    Rejected,
+    /// Awaiting the confirmation of a sent transaction
+    Confirm,
+    /// The blockhash expired before the transaction landed
+    Expired,
+}
+
+impl fmt::Display for FailureClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            FailureClass::Fetch => "fetch",
+            FailureClass::Build => "build",
+            FailureClass::Send => "send",
+            FailureClass::Rejected => "rejected",
+            FailureClass::Confirm => "confirm",
+            FailureClass::Expired => "expired",
+        })
+    }
+}
+
+/// One failed attempt of a nonce
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttemptRecord {
+    /// 1 for the first attempt
+    pub attempt: u32,
+    pub started_at: u64,
+    pub duration_ms: u64,
+    pub class: FailureClass,
+    pub error: String,
+    /// RPC endpoint of the failed stage
+    pub endpoint: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blockhash: Option<String>,
+    /// Transaction sent in the attempt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// Deadline of the transfer, from its PDA
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+impl fmt::Display for AttemptRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "attempt {} at {} ({} ms): {} failed at {}",
+            self.attempt, self.started_at, self.duration_ms, self.class, self.endpoint
+        )?;
+        if let Some(blockhash) = &self.blockhash {
+            write!(f, " (blockhash {})", blockhash)?;
+        }
+        if let Some(signature) = &self.signature {
+            write!(f, " (signature {})", signature)?;
+        }
+        write!(f, ": {}", self.error)
+    }
+}
+
+struct AttemptContext {
+    started: SystemTime,
+    class: FailureClass,
+    endpoint: String,
+    blockhash: Option<Hash>,
+    signature: Option<Signature>,
+    expires_at: Option<u64>,
+}
+
+#[derive(Default)]
+pub struct AttemptTracker {
+    current: Mutex<HashMap<u64, AttemptContext>>,
+}
+
+impl AttemptTracker {
+    /// Starts an attempt of `nonces` at `now`, reading L1 from `endpoint`
+    pub fn start(&self, nonces: &[u64], now: SystemTime, endpoint: &str) {
+        let mut current = self.current.lock().unwrap();
+        current.clear();
+        for &nonce in nonces {
+            current.insert(
+                nonce,
+                AttemptContext {
+                    started: now,
+                    class: FailureClass::Fetch,
+                    endpoint: endpoint.to_string(),
+                    blockhash: None,
+                    signature: None,
+                    expires_at: None,
+                },
+            );
+        }
+    }
+
+    /// Moves the attempt of `nonce` and the nonces merged into it to
+    /// `class`, at `endpoint`, recording `blockhash` when given
+    pub fn advance(
+        &self,
+        nonce: u64,
+        coalesced: &[u64],
+        class: FailureClass,
+        endpoint: &str,
+        blockhash: Option<Hash>,
+    ) {
+        let mut current = self.current.lock().unwrap();
+        for nonce in std::iter::once(&nonce).chain(coalesced) {
+            if let Some(context) = current.get_mut(nonce) {
+                context.class = class;
+                context.endpoint = endpoint.to_string();
+                if blockhash.is_some() {
+                    context.blockhash = blockhash;
+                }
+            }
+        }
+    }
+
+    /// Records `signature`, sent for `nonce` and the nonces merged into it
+    pub fn sent(&self, nonce: u64, coalesced: &[u64], signature: Signature) {
+        let mut current = self.current.lock().unwrap();
+        for nonce in std::iter::once(&nonce).chain(coalesced) {
+            if let Some(context) = current.get_mut(nonce) {
+                context.signature = Some(signature);
+            }
+        }
+    }
+
+    /// Records the deadline `expires_at` of the transfer of `nonce`
+    pub fn deadline(&self, nonce: u64, expires_at: u64) {
+        if let Some(context) = self.current.lock().unwrap().get_mut(&nonce) {
+            context.expires_at = Some(expires_at);
+        }
+    }
+
+    /// Ends the attempt of `nonce` as failed with `error` at `now`; none when
+    /// no attempt of it was started
+    pub fn fail(
+        &self,
+        nonce: u64,
+        attempt: u32,
+        error: &str,
+        now: SystemTime,
+    ) -> Option<AttemptRecord> {
+        let context = self.current.lock().unwrap().remove(&nonce)?;
+        let started_at = context
+            .started
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        Some(AttemptRecord {
+            attempt,
+            started_at: started_at.as_secs(),
+            duration_ms: now
+                .duration_since(context.started)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            class: context.class,
+            error: error.to_string(),
+            endpoint: context.endpoint,
+            blockhash: context.blockhash.map(|hash| hash.to_string()),
+            signature: context.signature.map(|signature| signature.to_string()),
+            expires_at: context.expires_at,
+        })
+    }
+
+    /// Ends the attempt of `nonce` as landed
+    pub fn finish(&self, nonce: u64) {
+        self.current.lock().unwrap().remove(&nonce);
+    }
+}
@@ -0,0 +1,163 @@
+//! Attestation checks for L1 transfers.
+//! The L1 program appends an ed25519 signature by a known attestor after the
+//! versioned body of each transfer-info PDA, over every field of the body and
+//! the nonce; transfers are only relayed once that signature verifies against
+//! a configured attestor key.
+
+use crate::models::account::{TransferInfoAccount, DISCRIMINATOR_LEN};
+use anyhow::Result;
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use std::str::FromStr;
+
+/// Result of checking a transfer-info PDA
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Attestation {
+    /// Signed by this attestor
+    Verified(Pubkey),
+    /// Legacy message without a signature, accepted during the migration window
+    Unsigned,
+}
+
+pub struct AttestationVerifier {
+    attestors: Vec<Pubkey>,
+    required: bool,
+}
+
+impl AttestationVerifier {
+    pub fn new(attestors: &[String], required: bool) -> Result<Self> {
+        let attestors = attestors
+            .iter()
+            .map(|attestor| {
+                Pubkey::from_str(attestor)
+                    .map_err(|e| anyhow::anyhow!("Invalid attestor pubkey {}: {}", attestor, e))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if required && attestors.is_empty() {
+            return Err(anyhow::anyhow!(
+                "require_attestation is set but no attestor_pubkeys are configured"
+            ));
+        }
+
+        Ok(Self {
+            attestors,
+            required,
+        })
+    }
+
+    /// Checks the attestation of the transfer-info PDA `data` for `nonce`.
+    /// Errors describe why the transfer must not be relayed.
+    pub fn verify(&self, nonce: u64, data: &[u8]) -> Result<Attestation> {
+        let (body, signature) = TransferInfoAccount::split_attested(data)?;
+        let Some(signature) = signature else {
+            if self.required {
+                return Err(anyhow::anyhow!(
+                    "Nonce {} has no attestation signature and require_attestation is set",
+                    nonce
+                ));
+            }
+            return Ok(Attestation::Unsigned);
+        };
+        let signature = Signature::new(signature);
+
+        let message = Self::signed_message(nonce, body);
+        self.attestors
+            .iter()
+            .find(|attestor| signature.verify(attestor.as_ref(), &message))
+            .map(|attestor| Attestation::Verified(*attestor))
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Attestation signature {} for nonce {} does not match any configured attestor",
+                    signature,
+                    nonce
+                )
+            })
+    }
+
+    /// The body after the discriminator (from, to, amount, message type,
+    /// reserved, then `expires_at` from V2 and `mint` in V3) || nonce (u64 LE).
+    /// Its length tells the versions apart
+    fn signed_message(nonce: u64, body: &[u8]) -> Vec<u8> {
+        let mut message = Vec::with_capacity(body.len() - DISCRIMINATOR_LEN + 8);
+        message.extend_from_slice(&body[DISCRIMINATOR_LEN..]);
+        message.extend_from_slice(&nonce.to_le_bytes());
+        message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        message_decoder::{AttestedBorshDecoder, MessageDecoder},
+        models::account::TransferInfoVersion,
+    };
+    use solana_sdk::signature::{Keypair, Signer};
+
+    /// A V3 body: sender, recipient, amount 500, type 1, `expires_at` 1700 and a mint
+    fn v3_body(mint: &Pubkey) -> Vec<u8> {
+        let mut body = vec![7u8; DISCRIMINATOR_LEN];
+        body.extend_from_slice(Pubkey::new_unique().as_ref());
+        body.extend_from_slice(Pubkey::new_unique().as_ref());
+        body.extend_from_slice(&500u64.to_le_bytes());
+        body.push(1);
+        body.extend_from_slice(&[0; 6]);
+        body.extend_from_slice(&1700i64.to_le_bytes());
+        body.extend_from_slice(mint.as_ref());
+        body
+    }
+
+    fn signed(attestor: &Keypair, nonce: u64, body: &[u8]) -> Vec<u8> {
+        let signature = attestor.sign_message(&AttestationVerifier::signed_message(nonce, body));
+        [body, signature.as_ref()].concat()
+    }
+
+    #[test]
+    fn the_signature_follows_the_versioned_body() {
+        let attestor = Keypair::new();
+        let verifier = AttestationVerifier::new(&[attestor.pubkey().to_string()], true).unwrap();
+        let mint = Pubkey::new_unique();
+        let data = signed(&attestor, 9, &v3_body(&mint));
+        assert_eq!(data.len(), TransferInfoVersion::V3_SIZE + 64);
+
+        assert_eq!(
+            verifier.verify(9, &data).unwrap(),
+            Attestation::Verified(attestor.pubkey())
+        );
+        let info = AttestedBorshDecoder.decode(&data).unwrap();
+        assert_eq!(info.version, TransferInfoVersion::V3);
+        assert_eq!(info.expires_at, Some(1700));
+        assert_eq!(info.mint, Some(mint));
+        // 签名的 V1 与 V3 大小不再重合
+        let v1_signed = signed(&attestor, 9, &data[..TransferInfoVersion::V1_SIZE]);
+        assert_eq!(
+            AttestedBorshDecoder.decode(&v1_signed).unwrap().version,
+            TransferInfoVersion::V1
+        );
+    }
+
+    #[test]
+    fn every_decoded_field_and_the_nonce_are_signed() {
+        let attestor = Keypair::new();
+        let verifier = AttestationVerifier::new(&[attestor.pubkey().to_string()], false).unwrap();
+        let data = signed(&attestor, 9, &v3_body(&Pubkey::new_unique()));
+        assert!(verifier.verify(10, &data).is_err());
+        // message_type、expires_at、mint 各改一个字节
+        for offset in [80, 87, 95] {
+            let mut tampered = data.clone();
+            tampered[offset] ^= 1;
+            assert!(verifier.verify(9, &tampered).is_err(), "offset {}", offset);
+        }
+    }
+
+    #[test]
+    fn unsigned_bodies_pass_only_while_attestation_is_optional() {
+        let attestors = [Keypair::new().pubkey().to_string()];
+        let body = &v3_body(&Pubkey::new_unique())[..TransferInfoVersion::V2_SIZE];
+        let optional = AttestationVerifier::new(&attestors, false).unwrap();
+        assert_eq!(optional.verify(9, body).unwrap(), Attestation::Unsigned);
+        let required = AttestationVerifier::new(&attestors, true).unwrap();
+        assert!(required.verify(9, body).is_err());
+        // 既非正文大小也非正文加签名
+        assert!(optional.verify(9, &[0; 100]).is_err());
+    }
+}
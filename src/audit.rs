@@ -0,0 +1,136 @@
+//! Audit mode.
+//! Simulates L2 transfer transactions and compares the simulated account
+//! effects against what the L1 transfer info says should happen.
+
+use anyhow::Result;
+use serde::Serialize;
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    nonblocking::rpc_client::RpcClient,
+    rpc_config::{RpcSimulateTransactionAccountsConfig, RpcSimulateTransactionConfig},
+};
+use solana_sdk::{pubkey::Pubkey, transaction::VersionedTransaction};
+use std::{fs, path::Path};
+
+/// Outcome of auditing a single nonce
+#[derive(Debug, Serialize)]
+pub struct AuditEntry {
+    pub nonce: u64,
+    pub recipient: String,
+    pub expected_credit: u64,
+    pub simulated_credit: Option<i128>,
+    pub units_consumed: Option<u64>,
+    pub passed: bool,
+    pub error: Option<String>,
+}
+
+impl AuditEntry {
+    /// Entry for a nonce whose transaction could not even be prepared
+    pub fn failed(nonce: u64, error: String) -> Self {
+        Self {
+            nonce,
+            recipient: String::new(),
+            expected_credit: 0,
+            simulated_credit: None,
+            units_consumed: None,
+            passed: false,
+            error: Some(error),
+        }
+    }
+}
+
+/// Per-nonce pass/fail diff report written at the end of an audit run
+#[derive(Debug, Default, Serialize)]
+pub struct AuditReport {
+    pub passed: usize,
+    pub failed: usize,
+    pub entries: Vec<AuditEntry>,
+}
+
+impl AuditReport {
+    pub fn push(&mut self, entry: AuditEntry) {
+        if entry.passed {
+            self.passed += 1;
+        } else {
+            self.failed += 1;
+        }
+        self.entries.push(entry);
+    }
+
+    pub fn all_passed(&self) -> bool {
+        self.failed == 0
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path.as_ref(), json).map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to write audit report {}: {}",
+                path.as_ref().display(),
+                e
+            )
+        })
+    }
+}
+
+/// Simulates `transaction` and checks that `recipient` is credited by exactly `expected_credit`
+pub async fn audit_transfer(
+    client: &RpcClient,
+    nonce: u64,
+    transaction: &VersionedTransaction,
+    recipient: &Pubkey,
+    expected_credit: u64,
+) -> Result<AuditEntry> {
+    let pre_balance = client
+        .get_account_with_commitment(recipient, client.commitment())
+        .await?
+        .value
+        .map(|account| account.lamports)
+        .unwrap_or(0);
+
+    let config = RpcSimulateTransactionConfig {
+        commitment: Some(client.commitment()),
+        accounts: Some(RpcSimulateTransactionAccountsConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            addresses: vec![recipient.to_string()],
+        }),
+        ..RpcSimulateTransactionConfig::default()
+    };
+    let result = client
+        .simulate_transaction_with_config(transaction, config)
+        .await?
+        .value;
+
+    let mut entry = AuditEntry {
+        nonce,
+        recipient: recipient.to_string(),
+        expected_credit,
+        simulated_credit: None,
+        units_consumed: result.units_consumed,
+        passed: false,
+        error: None,
+    };
+
+    if let Some(err) = result.err {
+        entry.error = Some(format!("Simulation failed: {:?}", err));
+        return Ok(entry);
+    }
+
+    let post_balance = result
+        .accounts
+        .and_then(|accounts| accounts.into_iter().next().flatten())
+        .map(|account| account.lamports)
+        .unwrap_or(0);
+    let simulated_credit = post_balance as i128 - pre_balance as i128;
+
+    entry.simulated_credit = Some(simulated_credit);
+    entry.passed = simulated_credit == expected_credit as i128;
+    if !entry.passed {
+        entry.error = Some(format!(
+            "Recipient credited {} lamports, expected {}",
+            simulated_credit, expected_credit
+        ));
+    }
+
+    Ok(entry)
+}
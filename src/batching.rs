@@ -0,0 +1,200 @@
+//! Fee-aware batch sizing.
+//! With `batch_strategy = "fee_aware"` the submitter picks the size of each
+//! batch instead of always taking `submit_batch_size` nonces. The inputs are
+//! the L2 fee market (the median of `getRecentPrioritizationFees` for the L2
+//! program, re-read at most every `batch_fee_refresh_secs`), the pending
+//! backlog and `batch_latency_target_ms`:
+//!
+//! - fees below `batch_high_fee_micro_lamports` and at most
+//!   `batch_small_backlog` nonces pending: one nonce per batch, sent at once
+//! - fees below the threshold otherwise: up to `submit_batch_size`, at once
+//! - fees at or above it: waits up to the latency target for a full batch
+//!
+//! Every nonce is still its own L2 transaction, so a larger batch amortizes
+//! the reads made once per batch (relayed nonce, paused flag, signer
+//! balance) and, with coalescing enabled, lets more transfers to the same
+//! recipient merge into one transaction paying one fee. The decision replaces
+//! the coalescing wait. When the fee price cannot be read the configured size
+//! is used. `run --once` always uses the configured size.
+
+use crate::Relayer;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+/// How the number of nonces per batch is chosen
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStrategy {
+    /// Always `submit_batch_size`
+    #[default]
+    Static,
+    /// From the L2 fee market, the backlog and the latency target
+    FeeAware,
+}
+
+/// Which rule chose a batch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchRegime {
+    /// Low fees and a small backlog: one nonce, at once
+    PerNonce,
+    /// Low fees: the whole batch, at once
+    Drain,
+    /// High fees: wait for a full batch
+    Fill,
+    /// The fee price could not be read: the configured size
+    Unpriced,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BatchDecision {
+    pub size: usize,
+    /// Pause before the batch is taken from the queue
+    pub wait: Duration,
+    pub regime: BatchRegime,
+    /// Median prioritization fee in micro-lamports per compute unit
+    pub fee: Option<u64>,
+    pub backlog: u64,
+}
+
+impl fmt::Display for BatchDecision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let fee = self.fee.map_or("unknown".to_string(), |fee| {
+            format!("{} micro-lamports/CU", fee)
+        });
+        let reason = match self.regime {
+            BatchRegime::PerNonce => "fees low and backlog small, relaying nonce by nonce",
+            BatchRegime::Drain => "fees low, relaying the backlog at once",
+            BatchRegime::Fill => "fees high, waiting for a full batch",
+            BatchRegime::Unpriced => "fee price unavailable, using submit_batch_size",
+        };
+        write!(
+            f,
+            "Batch of up to {} nonce(s) after {:?}: {} (fee {}, backlog {})",
+            self.size, self.wait, reason, fee, self.backlog
+        )
+    }
+}
+
+pub struct FeeAwareBatching {
+    latency_target: Duration,
+    high_fee: u64,
+    small_backlog: u64,
+    refresh: Duration,
+    /// Last fee price read and when
+    price: Mutex<Option<(u64, SystemTime)>>,
+    /// Size of the batch being taken
+    current: AtomicUsize,
+}
+
+impl FeeAwareBatching {
+    pub fn new(
+        latency_target: Duration,
+        high_fee: u64,
+        small_backlog: u64,
+        refresh: Duration,
+        batch_size: usize,
+    ) -> Self {
+        Self {
+            latency_target,
+            high_fee,
+            small_backlog,
+            refresh,
+            price: Mutex::new(None),
+            current: AtomicUsize::new(batch_size),
+        }
+    }
+
+    /// Decides the next batch for `backlog` pending nonces at the fee price `fee`
+    pub fn decide(&self, fee: Option<u64>, backlog: u64, batch_size: usize) -> BatchDecision {
+        let batch_size = batch_size.max(1);
+        let (size, wait, regime) = match fee {
+            None => (batch_size, Duration::ZERO, BatchRegime::Unpriced),
+            Some(fee) if fee >= self.high_fee => {
+                let wait = if backlog < batch_size as u64 {
+                    self.latency_target
+                } else {
+                    Duration::ZERO
+                };
+                (batch_size, wait, BatchRegime::Fill)
+            }
+            Some(_) if backlog <= self.small_backlog => (1, Duration::ZERO, BatchRegime::PerNonce),
+            Some(_) => (batch_size, Duration::ZERO, BatchRegime::Drain),
+        };
+        BatchDecision {
+            size,
+            wait,
+            regime,
+            fee,
+            backlog,
+        }
+    }
+}
+
+impl Relayer {
+    /// Nonces taken from the queue for the next batch
+    pub(crate) fn batch_size(&self) -> usize {
+        self.batching
+            .as_ref()
+            .map_or(self.submitter.batch_size, |batching| {
+                batching.current.load(Ordering::Relaxed)
+            })
+    }
+
+    /// Decides the size of the next batch, logs and exports the decision and
+    /// returns it
+    pub(crate) async fn plan_batch(&self, batching: &FeeAwareBatching) -> BatchDecision {
+        let fee = match self.fee_price(batching).await {
+            Ok(fee) => Some(fee),
+            Err(e) => {
+                tracing::warn!("failed to read the L2 fee price: {}", e);
+                None
+            }
+        };
+        let (queued, spilled) = self.queue.depth();
+        let decision = batching.decide(fee, queued + spilled, self.submitter.batch_size);
+        batching.current.store(decision.size, Ordering::Relaxed);
+        tracing::info!("{}", decision);
+
+        self.metrics.batch_size.set(decision.size as u64);
+        if let Some(fee) = fee {
+            self.metrics.fee_price.set(fee);
+        }
+        match decision.regime {
+            BatchRegime::PerNonce => self.metrics.batches_per_nonce_total.inc(),
+            BatchRegime::Drain => self.metrics.batches_drain_total.inc(),
+            BatchRegime::Fill => self.metrics.batches_fill_total.inc(),
+            BatchRegime::Unpriced => self.metrics.batches_unpriced_total.inc(),
+        }
+        decision
+    }
+
+    /// Median recent prioritization fee of the L2 program, re-read once the
+    /// cached value is older than `batch_fee_refresh_secs`
+    pub(crate) async fn fee_price(&self, batching: &FeeAwareBatching) -> Result<u64> {
+        let now = self.clock.now();
+        if let Some((fee, read_at)) = *batching.price.lock().unwrap() {
+            if now.duration_since(read_at).unwrap_or_default() < batching.refresh {
+                return Ok(fee);
+            }
+        }
+        let mut fees: Vec<u64> = self
+            .l2_client
+            .get_recent_prioritization_fees(&[*self.transaction_builder.program_id.pubkey()])
+            .await?
+            .into_iter()
+            .map(|fee| fee.prioritization_fee)
+            .collect();
+        fees.sort_unstable();
+        let fee = fees.get(fees.len() / 2).copied().unwrap_or_default();
+        *batching.price.lock().unwrap() = Some((fee, now));
+        Ok(fee)
+    }
+}
@@ -0,0 +1,314 @@
+//! CPU benchmarks of the relay path.
+//! `bench` measures, in-process and without RPC, what each relayed nonce
+//! costs before it reaches the network: PDA derivation (`find_address`, and a
+//! lookup in a precomputed address map for comparison; the relayer itself
+//! keeps no such cache), watched-account decoding in the legacy and
+//! two-counter layouts, transfer-info decoding in the 87-byte and deadline
+//! layouts, and building and signing the relay transaction with the
+//! configured instruction version and accounts. Each result gives ops/sec
+//! and the heap allocations per op, counted by the allocator below.
+//! `startup_self_test = true` runs a short version of the same measurements
+//! before relaying and warns if the host cannot prepare one round of
+//! `send_concurrency` × `submit_batch_size` nonces within
+//! `poll_interval_min_ms`.
+
+use crate::{
+    config::{RelayerConfig, SourceConfig},
+    instruction_accounts::InstructionAccounts,
+    models::message::{NonceStatus, TWO_COUNTER_STATUS_SIZE},
+    pda::PdaManager,
+    pipeline::FetchedTransfer,
+    transaction::TransactionBuilder,
+};
+use anyhow::Result;
+use serde::Serialize;
+use solana_sdk::{
+    hash::Hash,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    collections::HashMap,
+    hint::black_box,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+use tracing::Span;
+
+/// Size of a transfer-info PDA without a deadline
+const TRANSFER_INFO_SIZE: usize = 87;
+/// Ops of each measurement of the startup self-test
+const SELF_TEST_OPS: u64 = 200;
+
+/// System allocator that counts allocations, so `bench` can report them
+pub struct CountingAllocator;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// 只在 System 之上计数，不改变分配行为
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(new_size as u64, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+/// One measurement
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchResult {
+    pub name: String,
+    pub ops: u64,
+    pub secs: f64,
+    pub ops_per_sec: f64,
+    pub allocations_per_op: f64,
+    pub bytes_per_op: f64,
+}
+
+impl BenchResult {
+    fn per_op(&self) -> Duration {
+        Duration::from_secs_f64(self.secs / self.ops.max(1) as f64)
+    }
+}
+
+/// Renders `results` as a table
+pub fn to_text(results: &[BenchResult]) -> String {
+    let width = results
+        .iter()
+        .map(|result| result.name.len())
+        .max()
+        .unwrap_or_default();
+    let mut text = format!(
+        "{:width$}  {:>12}  {:>10}  {:>10}  {:>10}\n",
+        "benchmark",
+        "ops/sec",
+        "ns/op",
+        "allocs/op",
+        "bytes/op",
+        width = width
+    );
+    for result in results {
+        text.push_str(&format!(
+            "{:width$}  {:>12.0}  {:>10}  {:>10.1}  {:>10.0}\n",
+            result.name,
+            result.ops_per_sec,
+            result.per_op().as_nanos(),
+            result.allocations_per_op,
+            result.bytes_per_op,
+            width = width
+        ));
+    }
+    text
+}
+
+/// Runs `op` for 0..`ops` and measures its time and allocations
+fn measure(name: &str, ops: u64, mut op: impl FnMut(u64)) -> BenchResult {
+    let (allocations, bytes) = (
+        ALLOCATIONS.load(Ordering::Relaxed),
+        ALLOCATED_BYTES.load(Ordering::Relaxed),
+    );
+    let started = Instant::now();
+    for i in 0..ops {
+        op(black_box(i));
+    }
+    let secs = started.elapsed().as_secs_f64();
+    let per_op = |total: u64| total as f64 / ops.max(1) as f64;
+    BenchResult {
+        name: name.to_string(),
+        ops,
+        secs,
+        ops_per_sec: if secs > 0.0 { ops as f64 / secs } else { 0.0 },
+        allocations_per_op: per_op(ALLOCATIONS.load(Ordering::Relaxed) - allocations),
+        bytes_per_op: per_op(ALLOCATED_BYTES.load(Ordering::Relaxed) - bytes),
+    }
+}
+
+/// What the benchmarks of one source work on
+struct Fixture {
+    pdas: PdaManager,
+    builder: TransactionBuilder,
+    payer: Keypair,
+    recipient: Pubkey,
+}
+
+impl Fixture {
+    fn new(config: &RelayerConfig, source: &SourceConfig) -> Result<Self> {
+        let l2_program_id = config.l2_program_id;
+        Ok(Self {
+            pdas: PdaManager::new(
+                source.l1_program_id,
+                source.watched_account,
+                source.seeds.clone(),
+            ),
+            builder: TransactionBuilder::new(
+                l2_program_id,
+                source.nonce_account,
+                config.l2_instruction_version,
+                config.bridge_domain,
+                InstructionAccounts::from_config(&config.l2_instruction_accounts, &l2_program_id)?,
+            )?,
+            payer: Keypair::new(),
+            recipient: Keypair::new().pubkey(),
+        })
+    }
+
+    fn transfer_info(&self, nonce: u64) -> Vec<u8> {
+        let mut data = vec![0u8; TRANSFER_INFO_SIZE];
+        data[40..72].copy_from_slice(self.recipient.as_ref());
+        data[72..80].copy_from_slice(&(1_000_000 + nonce).to_le_bytes());
+        data
+    }
+
+    fn transfer(&self, nonce: u64) -> FetchedTransfer {
+        FetchedTransfer {
+            nonce,
+            amount: 1_000_000 + nonce,
+            to_address: self.recipient,
+            l1_slot: nonce,
+            message_type: Some(0),
+            coalesced: Vec::new(),
+            proof: None,
+            expires_at: None,
+            digest: None,
+            sender: None,
+            token: None,
+            dust: Vec::new(),
+            create_token_account: false,
+            entry: None,
+            span: Span::none(),
+        }
+    }
+
+    fn build(&self, nonce: u64) -> Result<()> {
+        let transaction = self.builder.build_transfer_transaction(
+            &self.transfer(nonce),
+            None,
+            None,
+            &self.payer,
+            Hash::new_unique(),
+        )?;
+        black_box(transaction);
+        Ok(())
+    }
+}
+
+/// Runs every benchmark on the first source of `config`: PDA derivation over
+/// `nonces` nonces, the rest `iterations` times each
+pub fn run(config: &RelayerConfig, nonces: u64, iterations: u64) -> Result<Vec<BenchResult>> {
+    let source = config.sources()?.remove(0);
+    let fixture = Fixture::new(config, &source)?;
+    // 预先分配，避免把结果存储计入被测分配
+    let mut addresses = Vec::with_capacity(nonces as usize);
+    let mut results = vec![measure("pda find_address", nonces, |nonce| {
+        addresses.push(fixture.pdas.find_address(nonce).0)
+    })];
+    let cache: HashMap<u64, Pubkey> = addresses
+        .into_iter()
+        .enumerate()
+        .map(|(nonce, address)| (nonce as u64, address))
+        .collect();
+    results.push(measure("pda cached lookup", nonces, |nonce| {
+        black_box(cache.get(&nonce));
+    }));
+    drop(cache);
+
+    let legacy = 42u64.to_le_bytes().repeat(4);
+    let mut two_counter = vec![0u8; TWO_COUNTER_STATUS_SIZE];
+    two_counter[16..24].copy_from_slice(&42u64.to_le_bytes());
+    results.push(measure("nonce status legacy", iterations, |_| {
+        black_box(NonceStatus::from_bytes(black_box(&legacy)).ok());
+    }));
+    results.push(measure("nonce status two-counter", iterations, |_| {
+        black_box(NonceStatus::from_bytes(black_box(&two_counter)).ok());
+    }));
+
+    let info = fixture.transfer_info(0);
+    let mut deadline = info.clone();
+    deadline.extend_from_slice(&1_900_000_000i64.to_le_bytes());
+    results.push(measure("transfer info 87-byte", iterations, |_| {
+        let data = black_box(&info);
+        black_box((
+            fixture.pdas.parse_transfer_info(data).ok(),
+            fixture.pdas.message_type_id(data),
+        ));
+    }));
+    results.push(measure("transfer info deadline", iterations, |_| {
+        let data = black_box(&deadline);
+        black_box((
+            fixture.pdas.parse_transfer_info(data).ok(),
+            fixture.pdas.message_type_id(data),
+            fixture.pdas.expires_at(data),
+        ));
+    }));
+
+    let mut failed = None;
+    results.push(measure("build + sign transaction", iterations, |nonce| {
+        if let Err(e) = fixture.build(nonce) {
+            failed.get_or_insert(e);
+        }
+    }));
+    match failed {
+        Some(e) => Err(anyhow::anyhow!(
+            "Failed to build a relay transaction: {}",
+            e
+        )),
+        None => Ok(results),
+    }
+}
+
+/// Measures the CPU cost of one nonce (derivation, decoding, build and sign)
+/// and warns if a round of `send_concurrency` × `submit_batch_size` nonces
+/// takes longer than `poll_interval_min_ms` on this host
+pub fn self_test(config: &RelayerConfig, source: &SourceConfig) -> Result<()> {
+    let fixture = Fixture::new(config, source)?;
+    let info = fixture.transfer_info(0);
+    let mut failed = None;
+    let result = measure("self-test", SELF_TEST_OPS, |nonce| {
+        black_box(fixture.pdas.find_address(nonce));
+        black_box(fixture.pdas.parse_transfer_info(black_box(&info)).ok());
+        if let Err(e) = fixture.build(nonce) {
+            failed.get_or_insert(e);
+        }
+    });
+    if let Some(e) = failed {
+        return Err(anyhow::anyhow!(
+            "Self-test failed to build a relay transaction: {}",
+            e
+        ));
+    }
+    let round = (config.send_concurrency.max(1) * config.submit_batch_size.max(1)) as u32;
+    let round_cost = result.per_op() * round;
+    let budget = Duration::from_millis(config.poll_interval_min_ms);
+    tracing::info!(
+        "Self-test: {:.0} nonces/s of PDA derivation, decoding and signing; a round of {} nonces takes {:?}",
+        result.ops_per_sec, round, round_cost
+    );
+    if round_cost > budget {
+        tracing::warn!(
+            "this host needs {:?} to prepare a round of {} nonces (send_concurrency {} × submit_batch_size {}), longer than poll_interval_min_ms ({:?}); lower send_concurrency or submit_batch_size, or use a faster host",
+            round_cost, round, config.send_concurrency, config.submit_batch_size, budget
+        );
+    }
+    Ok(())
+}
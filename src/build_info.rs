@@ -0,0 +1,74 @@
+//! Build metadata.
+//! The git commit, build time, rustc version and enabled features are
+//! embedded by `build.rs`, so every environment can tell which build it runs:
+//! they are logged at startup, printed by `--version`, written to crash
+//! reports, served at the admin API's `GET /version`, sent with webhook
+//! alerts and exported as `relayer_build_info`.
+
+use crate::report::civil_from_days;
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    /// Commit the binary was built from, with `-dirty` for uncommitted changes
+    pub git_commit: &'static str,
+    /// Unix time of the build
+    pub built_at: u64,
+    pub rustc: &'static str,
+    pub features: Vec<&'static str>,
+}
+
+impl BuildInfo {
+    pub fn current() -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            git_commit: env!("RELAYER_GIT_COMMIT"),
+            built_at: env!("RELAYER_BUILT_AT").parse().unwrap_or_default(),
+            rustc: env!("RELAYER_RUSTC_VERSION"),
+            features: env!("RELAYER_FEATURES")
+                .split(',')
+                .filter(|feature| !feature.is_empty())
+                .collect(),
+        }
+    }
+
+    /// `relayer_build_info` sample in the Prometheus text exposition format
+    pub fn to_prometheus(&self) -> String {
+        format!(
+            "# HELP relayer_build_info Build of the running relayer\n\
+             # TYPE relayer_build_info gauge\n\
+             relayer_build_info{{version=\"{}\",git_commit=\"{}\",rustc=\"{}\",features=\"{}\"}} 1\n",
+            self.version,
+            self.git_commit,
+            self.rustc,
+            self.features.join(",")
+        )
+    }
+}
+
+impl fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (year, month, day) = civil_from_days((self.built_at / 86_400) as i64);
+        let seconds = self.built_at % 86_400;
+        let features = if self.features.is_empty() {
+            "none".to_string()
+        } else {
+            self.features.join(", ")
+        };
+        write!(
+            f,
+            "sol-bridge-relayer {} (commit {}, built {:04}-{:02}-{:02} {:02}:{:02} UTC, {}, features: {})",
+            self.version,
+            self.git_commit,
+            year,
+            month,
+            day,
+            seconds / 3_600,
+            seconds % 3_600 / 60,
+            self.rustc,
+            features
+        )
+    }
+}
@@ -0,0 +1,400 @@
+//! L1 cancellation registry.
+//! A source's L1 program can let users cancel a pending transfer by writing
+//! its nonce into a registry account. With `cancellation_registry` set, the
+//! registry is read in the same `getMultipleAccounts` snapshot as the
+//! transfer-info PDAs, and read again with the watched account right before
+//! each transaction is sent; a listed nonce is not relayed and gets a receipt
+//! without a transaction (`cancelled_on_l1`). A cancellation that shows up
+//! only once the transaction has landed is recorded on its receipt
+//! (`cancelled_in_flight`) and alerted, for reconciliation.
+//!
+//! Two layouts are parsed after `offset` bytes: `list`, a Borsh `Vec<u64>`
+//! (u32 LE count, then u64 LE nonces), and `bitmap`, the first nonce (u64
+//! LE), a u32 LE byte count, then bytes whose bit i (least significant first)
+//! of byte j marks nonce first + 8j + i. A registry account that does not
+//! exist cancels nothing; one that cannot be parsed fails the read, so no
+//! transfer is sent unchecked.
+
+use crate::{
+    alerts, config::CancellationRegistryConfig, decode, inflight::InFlightTransfer,
+    journal::JournalEvent, pubkeys::SourceAccount, receipts::Receipt, PreparedTransfer, Relayer,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_sdk::{account::Account, pubkey::Pubkey};
+use std::{collections::BTreeSet, fmt};
+
+/// How the registry stores cancelled nonces
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RegistryLayout {
+    #[default]
+    List,
+    Bitmap,
+}
+
+/// A source's cancellation registry account and its layout
+#[derive(Debug, Clone)]
+pub struct CancellationRegistry {
+    pub address: SourceAccount,
+    pub layout: RegistryLayout,
+    /// Bytes before the list or bitmap
+    pub offset: usize,
+}
+
+/// Nonces a registry lists as cancelled
+#[derive(Debug, Clone)]
+pub enum CancelledNonces {
+    List(BTreeSet<u64>),
+    Bitmap { first: u64, bits: Vec<u8> },
+}
+
+impl Default for CancelledNonces {
+    fn default() -> Self {
+        Self::List(BTreeSet::new())
+    }
+}
+
+impl CancelledNonces {
+    pub fn contains(&self, nonce: u64) -> bool {
+        match self {
+            Self::List(nonces) => nonces.contains(&nonce),
+            Self::Bitmap { first, bits } => {
+                let Some(index) = nonce.checked_sub(*first) else {
+                    return false;
+                };
+                bits.get((index / 8) as usize)
+                    .is_some_and(|byte| byte & (1 << (index % 8)) != 0)
+            }
+        }
+    }
+}
+
+/// A coalesced transfer with nonces the registry lists, held so the next
+/// batch reads its nonces again and leaves the cancelled ones out
+#[derive(Debug)]
+pub struct CancelledBeforeSend {
+    pub nonces: Vec<u64>,
+    pub slot: u64,
+}
+
+impl fmt::Display for CancelledBeforeSend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Coalesced nonces {:?} were cancelled on L1 at slot {}; reading them again",
+            self.nonces, self.slot
+        )
+    }
+}
+
+impl std::error::Error for CancelledBeforeSend {}
+
+/// `N` bytes of `data` at `at`
+fn field<const N: usize>(data: &[u8], at: usize) -> Option<[u8; N]> {
+    data.get(at..at.checked_add(N)?)?.try_into().ok()
+}
+
+impl CancellationRegistry {
+    pub fn from_config(config: &CancellationRegistryConfig) -> Self {
+        Self {
+            address: config.account,
+            layout: config.layout,
+            offset: config.offset,
+        }
+    }
+
+    /// Decodes the cancelled nonces from raw registry account data
+    pub fn parse(&self, data: &[u8]) -> Result<CancelledNonces> {
+        let truncated = || {
+            anyhow::anyhow!(
+                "Cancellation registry {} is too short for its {:?} layout: {} bytes ({})",
+                self.address,
+                self.layout,
+                data.len(),
+                decode::hex_prefix(data)
+            )
+        };
+        let body = data.get(self.offset..).ok_or_else(truncated)?;
+        match self.layout {
+            RegistryLayout::List => {
+                let count = u32::from_le_bytes(field(body, 0).ok_or_else(truncated)?) as usize;
+                let nonces = count
+                    .checked_mul(8)
+                    .and_then(|len| body.get(4..len.checked_add(4)?))
+                    .ok_or_else(truncated)?;
+                Ok(CancelledNonces::List(
+                    nonces
+                        .chunks_exact(8)
+                        .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+                        .collect(),
+                ))
+            }
+            RegistryLayout::Bitmap => {
+                let first = u64::from_le_bytes(field(body, 0).ok_or_else(truncated)?);
+                let len = u32::from_le_bytes(field(body, 8).ok_or_else(truncated)?) as usize;
+                let bits = len
+                    .checked_add(12)
+                    .and_then(|end| body.get(12..end))
+                    .ok_or_else(truncated)?;
+                Ok(CancelledNonces::Bitmap {
+                    first,
+                    bits: bits.to_vec(),
+                })
+            }
+        }
+    }
+}
+
+impl Relayer {
+    /// Address of the source's registry, appended to reads that must see it
+    /// in the same snapshot
+    pub(crate) fn registry_address(&self) -> Option<Pubkey> {
+        self.pda_manager
+            .registry()
+            .map(|registry| *registry.address.pubkey())
+    }
+
+    /// Nonces cancelled in `data`, the registry read of a snapshot; `None`
+    /// without a registry
+    pub(crate) fn cancellations(&self, data: Option<&[u8]>) -> Result<Option<CancelledNonces>> {
+        let Some(registry) = self.pda_manager.registry() else {
+            return Ok(None);
+        };
+        match data {
+            Some(data) => registry.parse(data).map(Some),
+            // 登记账户尚未创建，没有取消
+            None => Ok(Some(CancelledNonces::default())),
+        }
+    }
+
+    /// Removes the registry from `accounts`, read with `registry_address`
+    /// appended, and parses it
+    pub(crate) fn take_cancellations(
+        &self,
+        accounts: &mut Vec<Option<Account>>,
+    ) -> Result<Option<CancelledNonces>> {
+        if self.pda_manager.registry().is_none() {
+            return Ok(None);
+        }
+        let data = accounts.pop().flatten().map(|account| account.data);
+        self.cancellations(data.as_deref())
+    }
+
+    /// Reads the registry in a snapshot with the watched account, with the
+    /// snapshot's slot
+    async fn read_cancellations(&self) -> Result<Option<(u64, CancelledNonces)>> {
+        if self.pda_manager.registry().is_none() {
+            return Ok(None);
+        }
+        let snapshot = self
+            .pda_manager
+            .read_snapshot(&self.l1_client, &[], self.context_slots.l1.get())
+            .await
+            .map_err(|e| self.l1_read_error(e))?;
+        self.context_slots.l1.observe(snapshot.slot);
+        Ok(self
+            .cancellations(snapshot.registry.as_deref())?
+            .map(|cancelled| (snapshot.slot, cancelled)))
+    }
+
+    /// Records a receipt for `nonce`, not relayed because the registry read at
+    /// `registry_slot` lists it
+    pub(crate) fn skip_cancelled_on_l1(
+        &self,
+        nonce: u64,
+        l1_slot: u64,
+        amount: u64,
+        to: String,
+        registry_slot: u64,
+    ) -> Result<()> {
+        // 同批次后面的 nonce 失败时，已取消的 nonce 会被再次读取
+        if Receipt::load(&self.state, nonce)?
+            .is_some_and(|receipt| receipt.cancelled_on_l1.is_some())
+        {
+            return Ok(());
+        }
+        tracing::info!(
+            "Skipping nonce {}: cancelled in the L1 cancellation registry at slot {}",
+            nonce,
+            registry_slot
+        );
+        Receipt::cancelled_on_l1(
+            nonce,
+            l1_slot,
+            amount,
+            to,
+            registry_slot,
+            self.clock.unix_timestamp(),
+        )
+        .save(&self.state)?;
+        self.schedule_shipping(nonce);
+        self.attempts.finish(nonce);
+        self.journal(
+            Some(l1_slot),
+            JournalEvent::Skipped {
+                nonce,
+                reason: format!("cancelled on L1 at slot {}", registry_slot),
+            },
+        );
+        self.metrics.l1_cancelled_transfers_total.inc();
+        Ok(())
+    }
+
+    /// Reads the registry right before `prepared` is sent; returns whether the
+    /// transfer was skipped as cancelled. A coalesced transfer with cancelled
+    /// nonces fails with `CancelledBeforeSend` instead
+    pub(crate) async fn check_l1_cancellation(&self, prepared: &PreparedTransfer) -> Result<bool> {
+        let Some((slot, cancelled)) = self.read_cancellations().await? else {
+            return Ok(false);
+        };
+        if prepared.coalesced.is_empty() {
+            if !cancelled.contains(prepared.nonce) {
+                return Ok(false);
+            }
+            self.skip_cancelled_on_l1(
+                prepared.nonce,
+                prepared.l1_slot,
+                prepared.amount,
+                prepared.to_address.to_string(),
+                slot,
+            )?;
+            return Ok(true);
+        }
+        let nonces: Vec<u64> = prepared
+            .coalesced
+            .iter()
+            .copied()
+            .filter(|nonce| cancelled.contains(*nonce))
+            .collect();
+        if nonces.is_empty() {
+            return Ok(false);
+        }
+        Err(CancelledBeforeSend { nonces, slot }.into())
+    }
+
+    /// Slot at which the registry lists a nonce of the landed `transfer`,
+    /// cancelled while its transaction was in flight; alerted for
+    /// reconciliation
+    pub(crate) async fn cancellation_race(&self, transfer: &InFlightTransfer) -> Option<u64> {
+        let (slot, cancelled) = match self.read_cancellations().await {
+            Ok(read) => read?,
+            Err(e) => {
+                tracing::warn!(
+                    "could not read the L1 cancellation registry after nonce {} landed: {}",
+                    transfer.nonce,
+                    e
+                );
+                return None;
+            }
+        };
+        let mut nonces: Vec<u64> = std::iter::once(transfer.nonce)
+            .chain(transfer.coalesced.iter().copied())
+            .filter(|nonce| cancelled.contains(*nonce))
+            .collect();
+        nonces.dedup();
+        if nonces.is_empty() {
+            return None;
+        }
+        alerts::raise(&format!(
+            "Source {} nonce(s) {:?} landed on L2 in {} although the L1 cancellation registry lists them at slot {}; reconcile the refund",
+            self.source_id, nonces, transfer.signature, slot
+        ));
+        self.metrics.cancellation_races_total.inc();
+        Some(slot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A registry of `layout` whose list or bitmap starts at byte 8
+    fn registry(layout: RegistryLayout) -> CancellationRegistry {
+        CancellationRegistry {
+            address: SourceAccount::from(Pubkey::new_unique()),
+            layout,
+            offset: 8,
+        }
+    }
+
+    /// A list registry account holding `nonces`
+    fn list(nonces: &[u64]) -> Vec<u8> {
+        let mut data = vec![0xaa; 8];
+        data.extend_from_slice(&(nonces.len() as u32).to_le_bytes());
+        for nonce in nonces {
+            data.extend_from_slice(&nonce.to_le_bytes());
+        }
+        data
+    }
+
+    /// A bitmap registry account starting at `first`
+    fn bitmap(first: u64, bits: &[u8]) -> Vec<u8> {
+        let mut data = vec![0xaa; 8];
+        data.extend_from_slice(&first.to_le_bytes());
+        data.extend_from_slice(&(bits.len() as u32).to_le_bytes());
+        data.extend_from_slice(bits);
+        data
+    }
+
+    #[test]
+    fn list_registries_cancel_the_listed_nonces() {
+        let cancelled = registry(RegistryLayout::List)
+            .parse(&list(&[3, 70, u64::MAX]))
+            .unwrap();
+        for nonce in [3, 70, u64::MAX] {
+            assert!(cancelled.contains(nonce));
+        }
+        for nonce in [0, 4, 69] {
+            assert!(!cancelled.contains(nonce));
+        }
+    }
+
+    #[test]
+    fn bitmap_registries_count_bits_from_the_first_nonce() {
+        // 第 0 字节最高位为 first + 7，第 1 字节最低位为 first + 8
+        let cancelled = registry(RegistryLayout::Bitmap)
+            .parse(&bitmap(100, &[0b1000_0001, 0b0000_0001]))
+            .unwrap();
+        for nonce in [100, 107, 108] {
+            assert!(cancelled.contains(nonce));
+        }
+        // first 之前与位图之后的 nonce 都未取消
+        for nonce in [0, 99, 101, 106, 109, 115, 116, 1_000] {
+            assert!(!cancelled.contains(nonce));
+        }
+    }
+
+    #[test]
+    fn empty_registries_cancel_nothing() {
+        let cancelled = registry(RegistryLayout::List).parse(&list(&[])).unwrap();
+        assert!(!cancelled.contains(0));
+        let cancelled = registry(RegistryLayout::Bitmap)
+            .parse(&bitmap(0, &[]))
+            .unwrap();
+        assert!(!cancelled.contains(0));
+        assert!(!CancelledNonces::default().contains(0));
+    }
+
+    #[test]
+    fn truncated_registries_fail_the_read() {
+        let list_registry = registry(RegistryLayout::List);
+        let mut data = list(&[3, 70]);
+        data.pop();
+        assert!(list_registry.parse(&data).is_err());
+        assert!(list_registry.parse(&data[..10]).is_err());
+        assert!(list_registry.parse(&data[..4]).is_err());
+        // 计数溢出长度时不 panic
+        let mut data = vec![0xaa; 8];
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert!(list_registry.parse(&data).is_err());
+
+        let bitmap_registry = registry(RegistryLayout::Bitmap);
+        let mut data = bitmap(100, &[0xff, 0xff]);
+        data.pop();
+        assert!(bitmap_registry.parse(&data).is_err());
+        assert!(bitmap_registry.parse(&data[..18]).is_err());
+        assert!(bitmap_registry.parse(&data[..12]).is_err());
+        assert!(bitmap_registry.parse(&[]).is_err());
+    }
+}
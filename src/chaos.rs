@@ -0,0 +1,327 @@
+//! Fault injection for resilience testing.
+//! Built with `--features chaos`, every HTTP endpoint of the RPC pools is
+//! wrapped in a layer that, while `[chaos]` is enabled, injects faults at
+//! the configured probabilities (0 to 1):
+//! - `delay_probability`: holds the request for `delay_ms` first
+//! - `send_failure_probability`: fails a `sendTransaction` before it is sent
+//! - `drop_probability`: sends the request but drops its response, as a
+//!   timeout would; a dropped `sendTransaction` may still land
+//! - `stale_probability`: answers an account read with the response to the
+//!   previous identical request
+//!
+//! Faults surface as the transport errors of an outage, so failover,
+//! retries, checkpoints and the circuit breakers handle them as they would
+//! a real one. `seed` makes the faults of a run reproducible. `GET /chaos`
+//! shows the settings and the faults injected so far, and
+//! `POST /chaos?drop_probability=0.1` changes them at runtime. A build
+//! without the feature refuses `enabled = true`; the layer is meant for
+//! staging and is not compiled into production builds.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "chaos")]
+use {
+    async_trait::async_trait,
+    serde_json::Value,
+    solana_client::{
+        client_error::{ClientErrorKind, Result as ClientResult},
+        rpc_request::RpcRequest,
+        rpc_sender::{RpcSender, RpcTransportStats},
+    },
+    std::{
+        collections::HashMap,
+        io,
+        sync::{Mutex, OnceLock},
+        time::Duration,
+    },
+};
+
+/// Account reads remembered for stale answers; all are dropped once full
+#[cfg(feature = "chaos")]
+const MAX_REMEMBERED: usize = 1024;
+
+#[cfg(feature = "chaos")]
+static CHAOS: OnceLock<Chaos> = OnceLock::new();
+
+/// The `[chaos]` table
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChaosConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Seed of the fault rolls; random when unset
+    #[serde(default)]
+    pub seed: Option<u64>,
+    #[serde(default)]
+    pub drop_probability: f64,
+    #[serde(default)]
+    pub delay_probability: f64,
+    #[serde(default = "default_delay_ms")]
+    pub delay_ms: u64,
+    #[serde(default)]
+    pub stale_probability: f64,
+    #[serde(default)]
+    pub send_failure_probability: f64,
+}
+
+fn default_delay_ms() -> u64 {
+    1000
+}
+
+impl ChaosConfig {
+    fn validate(&self) -> Result<(), String> {
+        for (field, probability) in [
+            ("drop_probability", self.drop_probability),
+            ("delay_probability", self.delay_probability),
+            ("stale_probability", self.stale_probability),
+            ("send_failure_probability", self.send_failure_probability),
+        ] {
+            if !(0.0..=1.0).contains(&probability) {
+                return Err(format!(
+                    "chaos.{} must be between 0 and 1, not {}",
+                    field, probability
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies `name = value` changes, as given to `POST /chaos`
+    #[cfg(feature = "chaos")]
+    pub fn apply(&mut self, changes: &[(String, String)]) -> Result<(), String> {
+        for (name, value) in changes {
+            let invalid = |e: &dyn std::fmt::Display| format!("Invalid {} {}: {}", name, value, e);
+            let probability = |value: &str| value.parse::<f64>().map_err(|e| invalid(&e));
+            match name.as_str() {
+                "enabled" => self.enabled = value.parse().map_err(|e| invalid(&e))?,
+                "seed" => self.seed = Some(value.parse().map_err(|e| invalid(&e))?),
+                "delay_ms" => self.delay_ms = value.parse().map_err(|e| invalid(&e))?,
+                "drop_probability" => self.drop_probability = probability(value)?,
+                "delay_probability" => self.delay_probability = probability(value)?,
+                "stale_probability" => self.stale_probability = probability(value)?,
+                "send_failure_probability" => self.send_failure_probability = probability(value)?,
+                _ => return Err(format!("Unknown chaos setting {}", name)),
+            }
+        }
+        self.validate()
+    }
+}
+
+/// Faults injected since the start
+#[cfg(feature = "chaos")]
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct FaultCounts {
+    pub delayed: u64,
+    pub dropped: u64,
+    pub stale: u64,
+    pub failed_sends: u64,
+}
+
+/// Settings and injected faults, as `GET /chaos` shows them
+#[cfg(all(feature = "chaos", feature = "admin"))]
+#[derive(Debug, Clone, Serialize)]
+pub struct ChaosStatus {
+    #[serde(flatten)]
+    pub settings: ChaosConfig,
+    pub injected: FaultCounts,
+}
+
+/// Sets up fault injection from `config`; fails on an invalid table, or an
+/// enabled one in a build without the `chaos` feature
+pub fn init(config: &ChaosConfig) -> Result<()> {
+    config.validate().map_err(anyhow::Error::msg)?;
+    #[cfg(not(feature = "chaos"))]
+    if config.enabled {
+        return Err(anyhow::anyhow!(
+            "chaos.enabled requires a build with --features chaos"
+        ));
+    }
+    #[cfg(feature = "chaos")]
+    {
+        if config.enabled {
+            tracing::warn!("fault injection is enabled; do not run this build in production");
+        }
+        let _ = CHAOS.set(Chaos::new(config.clone()));
+    }
+    Ok(())
+}
+
+/// Current settings and faults, none before `init`
+#[cfg(all(feature = "chaos", feature = "admin"))]
+pub fn status() -> Option<ChaosStatus> {
+    CHAOS.get().map(Chaos::status)
+}
+
+/// Applies `changes` to the running settings; a new `seed` restarts the
+/// rolls
+#[cfg(all(feature = "chaos", feature = "admin"))]
+pub fn update(changes: &[(String, String)]) -> Result<ChaosStatus, String> {
+    let chaos = CHAOS
+        .get()
+        .ok_or_else(|| "Fault injection is not set up".to_string())?;
+    let mut settings = chaos.settings.lock().unwrap().clone();
+    settings.apply(changes)?;
+    if let Some(seed) = settings
+        .seed
+        .filter(|_| changes.iter().any(|(name, _)| name == "seed"))
+    {
+        *chaos.rng.lock().unwrap() = seed;
+    }
+    *chaos.settings.lock().unwrap() = settings;
+    Ok(chaos.status())
+}
+
+#[cfg(feature = "chaos")]
+struct Chaos {
+    settings: Mutex<ChaosConfig>,
+    /// SplitMix64 state
+    rng: Mutex<u64>,
+    /// Last response of each account read
+    responses: Mutex<HashMap<String, Value>>,
+    injected: Mutex<FaultCounts>,
+}
+
+#[cfg(feature = "chaos")]
+impl Chaos {
+    fn new(settings: ChaosConfig) -> Self {
+        let seed = settings.seed.unwrap_or_else(|| {
+            let mut bytes = [0u8; 8];
+            let _ = getrandom::getrandom(&mut bytes);
+            u64::from_le_bytes(bytes)
+        });
+        Self {
+            settings: Mutex::new(settings),
+            rng: Mutex::new(seed),
+            responses: Mutex::new(HashMap::new()),
+            injected: Mutex::new(FaultCounts::default()),
+        }
+    }
+
+    #[cfg(feature = "admin")]
+    fn status(&self) -> ChaosStatus {
+        ChaosStatus {
+            settings: self.settings.lock().unwrap().clone(),
+            injected: *self.injected.lock().unwrap(),
+        }
+    }
+
+    fn roll(&self, probability: f64) -> bool {
+        if probability <= 0.0 {
+            return false;
+        }
+        let mut state = self.rng.lock().unwrap();
+        *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = *state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        ((z >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+
+    fn count(&self, fault: impl FnOnce(&mut FaultCounts) -> &mut u64) {
+        *fault(&mut self.injected.lock().unwrap()) += 1;
+    }
+
+    /// Remembers `value` as the answer to `key`
+    fn remember(&self, key: String, value: &Value) {
+        let mut responses = self.responses.lock().unwrap();
+        if responses.len() >= MAX_REMEMBERED && !responses.contains_key(&key) {
+            responses.clear();
+        }
+        responses.insert(key, value.clone());
+    }
+}
+
+/// Whether `request` reads account data that may be answered stale
+#[cfg(feature = "chaos")]
+fn is_account_read(request: RpcRequest) -> bool {
+    matches!(
+        request,
+        RpcRequest::GetAccountInfo
+            | RpcRequest::GetMultipleAccounts
+            | RpcRequest::GetProgramAccounts
+            | RpcRequest::GetBalance
+    )
+}
+
+#[cfg(feature = "chaos")]
+fn injected_error(kind: io::ErrorKind, fault: &str) -> solana_client::client_error::ClientError {
+    ClientErrorKind::Io(io::Error::new(kind, format!("chaos: {}", fault))).into()
+}
+
+/// Injects faults into the requests of one endpoint
+#[cfg(feature = "chaos")]
+pub struct ChaosSender<S> {
+    /// Config field of the endpoint, e.g. `l1_urls[1]`
+    endpoint: String,
+    inner: S,
+}
+
+#[cfg(feature = "chaos")]
+impl<S> ChaosSender<S> {
+    pub fn new(endpoint: impl Into<String>, inner: S) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            inner,
+        }
+    }
+}
+
+// `ClientError` is dictated by the `RpcSender` interface
+#[cfg(feature = "chaos")]
+#[allow(clippy::result_large_err)]
+#[async_trait]
+impl<S: RpcSender + Send + Sync> RpcSender for ChaosSender<S> {
+    async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        let Some(chaos) = CHAOS.get() else {
+            return self.inner.send(request, params).await;
+        };
+        let settings = chaos.settings.lock().unwrap().clone();
+        if !settings.enabled {
+            return self.inner.send(request, params).await;
+        }
+        if chaos.roll(settings.delay_probability) {
+            chaos.count(|counts| &mut counts.delayed);
+            tokio::time::sleep(Duration::from_millis(settings.delay_ms)).await;
+        }
+        if request == RpcRequest::SendTransaction && chaos.roll(settings.send_failure_probability) {
+            chaos.count(|counts| &mut counts.failed_sends);
+            tracing::info!("Chaos: failed {} to {}", request, self.endpoint);
+            return Err(injected_error(
+                io::ErrorKind::ConnectionRefused,
+                "send failed",
+            ));
+        }
+        let key = is_account_read(request).then(|| format!("{} {}", request, params));
+        let response = self.inner.send(request, params).await;
+        if chaos.roll(settings.drop_probability) {
+            chaos.count(|counts| &mut counts.dropped);
+            tracing::info!(
+                "Chaos: dropped the response to {} from {}",
+                request,
+                self.endpoint
+            );
+            return Err(injected_error(io::ErrorKind::TimedOut, "response dropped"));
+        }
+        let (Some(key), Ok(value)) = (key, &response) else {
+            return response;
+        };
+        if chaos.roll(settings.stale_probability) {
+            let previous = chaos.responses.lock().unwrap().get(&key).cloned();
+            if let Some(previous) = previous {
+                chaos.count(|counts| &mut counts.stale);
+                tracing::info!("Chaos: answered {} from {} stale", request, self.endpoint);
+                return Ok(previous);
+            }
+        }
+        chaos.remember(key, value);
+        response
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        self.inner.get_transport_stats()
+    }
+
+    fn url(&self) -> String {
+        self.inner.url()
+    }
+}
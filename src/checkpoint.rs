@@ -0,0 +1,106 @@
+//! Nonce checkpoint.
+//! After every confirmed relay the next L1 nonce is saved under
+//! `nonce_checkpoint`, with the signature that reached it, and a restarted
+//! relayer resumes from it. The L2 nonce account stays the source of truth
+//! above the checkpoint; a poll that reads it below the checkpoint (a node
+//! behind, or an account reset) enqueues nothing and is alerted once, so
+//! relayed nonces are not sent again. A deliberate reset of the L2 nonce
+//! account needs `nonce_checkpoint.json` removed from the state directory.
+
+use crate::{alerts, inflight::InFlightTransfer, state::StateStore, Relayer};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex,
+};
+
+/// State key holding the checkpoint
+pub const NONCE_CHECKPOINT_KEY: &str = "nonce_checkpoint";
+
+/// Next L1 nonce after the highest confirmed relay
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NonceCheckpoint {
+    pub next_nonce: u64,
+    /// Transaction that confirmed `next_nonce - 1`
+    pub signature: String,
+    pub saved_at: u64,
+}
+
+/// The saved checkpoint and whether a poll is held below it
+pub struct Checkpoint {
+    saved: Mutex<Option<NonceCheckpoint>>,
+    held: AtomicBool,
+}
+
+impl Checkpoint {
+    pub fn load(store: &StateStore) -> Result<Self> {
+        Ok(Self {
+            saved: Mutex::new(store.get(NONCE_CHECKPOINT_KEY)?),
+            held: AtomicBool::new(false),
+        })
+    }
+
+    /// The next nonce of the saved checkpoint
+    pub fn next_nonce(&self) -> Option<u64> {
+        self.saved
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|saved| saved.next_nonce)
+    }
+}
+
+impl Relayer {
+    /// Moves the checkpoint past the confirmed `transfer`; a failed write
+    /// only warns, since the receipt is already saved
+    pub(crate) fn save_checkpoint(&self, transfer: &InFlightTransfer) {
+        let next_nonce = transfer.nonce + 1;
+        let mut saved = self.checkpoint.saved.lock().unwrap();
+        // 并发确认可能乱序，只向前推进
+        if saved
+            .as_ref()
+            .is_some_and(|saved| saved.next_nonce >= next_nonce)
+        {
+            return;
+        }
+        let checkpoint = NonceCheckpoint {
+            next_nonce,
+            signature: transfer.signature.clone(),
+            saved_at: self.clock.unix_timestamp(),
+        };
+        if let Err(e) = self.state.put(NONCE_CHECKPOINT_KEY, &checkpoint) {
+            tracing::warn!(
+                "failed to save the nonce checkpoint at {}: {}",
+                next_nonce,
+                e
+            );
+        }
+        *saved = Some(checkpoint);
+    }
+
+    /// Whether the L2 nonce `l2_nonce` trails the checkpoint, so nothing may
+    /// be enqueued; alerted when the hold starts
+    pub(crate) fn below_checkpoint(&self, l2_nonce: u64) -> bool {
+        let Some(next_nonce) = self.checkpoint.next_nonce() else {
+            return false;
+        };
+        let below = l2_nonce < next_nonce;
+        if below != self.checkpoint.held.swap(below, Ordering::Relaxed) {
+            if below {
+                alerts::raise(&format!(
+                    "{}: L2 nonce account reads nonce {}, behind the checkpoint {}; holding new transfers so relayed nonces are not sent again",
+                    self.name(),
+                    l2_nonce,
+                    next_nonce
+                ));
+            } else {
+                tracing::info!(
+                    "L2 nonce account caught up with the checkpoint {}",
+                    next_nonce
+                );
+            }
+        }
+        below
+    }
+}
@@ -0,0 +1,646 @@
+//! Command-line interface of the relayer binary.
+
+#[cfg(feature = "testing")]
+use crate::simulate::{AmountDistribution, ArrivalPattern};
+use crate::{
+    config::{ConfigFormat, ProcessRole},
+    decode::Cluster,
+    dlq::ResolutionKind,
+    fees,
+    journal::{self, JournalFilter},
+    replay_batch::{self, ReplayFilter},
+    report::{self, Granularity},
+};
+use clap::{Args, Parser, Subcommand};
+use std::{path::PathBuf, time::Duration};
+
+#[derive(Debug, Parser)]
+#[clap(name = "sol-bridge-relayer", about = "Solana L1 to L2 bridge relayer")]
+pub struct Cli {
+    /// Path to the configuration file (TOML, YAML or JSON); `-` reads stdin
+    #[clap(long, default_value = "config.toml")]
+    pub config: PathBuf,
+
+    /// Configuration format, overriding the file extension
+    #[clap(long, value_enum)]
+    pub config_format: Option<ConfigFormat>,
+
+    /// Configuration profile (`[profiles.<name>]`) merged over `[default]`
+    #[clap(long, env = "RELAYER_PROFILE")]
+    pub profile: Option<String>,
+
+    /// Bridge (`[[bridges]]` entry) the command applies to; `run` relays
+    /// every bridge when omitted
+    #[clap(long, env = "RELAYER_BRIDGE")]
+    pub bridge: Option<String>,
+
+    /// Print the build (version, commit, build time, rustc, features) and exit
+    #[clap(short = 'V', long = "version")]
+    pub show_version: bool,
+
+    /// With `--version`, also load the config and print its fingerprint
+    #[clap(long, requires = "show-version")]
+    pub verbose: bool,
+
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Monitor L1 and relay transfers to L2 (default)
+    Run {
+        /// Run a single iteration per source and print its outcome as JSON
+        #[clap(long)]
+        once: bool,
+        /// Simulate relay transactions on L2 instead of sending them, keeping
+        /// state changes in memory (`dry_run` in the config)
+        #[clap(long)]
+        dry_run: bool,
+        /// Run only the observer or the submitter side of the
+        /// `[intent_queue]` (`process_role` in the config)
+        #[clap(long, value_enum)]
+        role: Option<ProcessRole>,
+    },
+    /// Check L1 transfer PDAs against what L2 has relayed
+    Reconcile(ReconcileArgs),
+    /// Show the pending queue with each failed nonce's attempts and next retry
+    Status {
+        /// Source to show; every source when omitted
+        #[clap(long)]
+        source: Option<String>,
+        /// Also read the L1 nonce and the L2 nonce account now
+        #[clap(long)]
+        live: bool,
+    },
+    /// Relay a dead-lettered or backing-off nonce again, as the admin API's
+    /// `POST /replay/{nonce}` does
+    Replay {
+        /// L1 nonce to relay again
+        #[clap(long)]
+        nonce: u64,
+        /// Operator name recorded with the resolution; defaults to `$USER`
+        #[clap(long)]
+        by: Option<String>,
+        /// Source of the nonce; required when several sources are configured
+        #[clap(long)]
+        source: Option<String>,
+    },
+    /// Read the transfer-info PDA of a nonce and print its decoded fields
+    DecodePda {
+        /// L1 nonce of the transfer
+        #[clap(long)]
+        nonce: u64,
+        /// Source of the nonce; required when several sources are configured
+        #[clap(long)]
+        source: Option<String>,
+    },
+    /// Aggregate relayed volume and activity from the receipts
+    Report(ReportArgs),
+    /// Print the reconciliation snapshots as JSON lines, oldest first
+    Snapshots {
+        /// Only snapshots taken at or after this unix time
+        #[clap(long, default_value = "0")]
+        from: u64,
+        /// Source to show; required when several sources are configured
+        #[clap(long)]
+        source: Option<String>,
+    },
+    /// Move old receipts into compressed monthly archives next to the state
+    Compact {
+        /// Archive receipts relayed longer ago than this, e.g. `90d`
+        #[clap(long, default_value = "90d", value_parser = report::parse_span)]
+        older_than: Duration,
+        /// Source to compact; every source when omitted
+        #[clap(long)]
+        source: Option<String>,
+    },
+    /// Export or import the whole state of a source as one bundle
+    State {
+        #[clap(subcommand)]
+        command: StateCommand,
+    },
+    /// Add the confirmed transaction's details to receipts saved without them
+    EnrichReceipts {
+        /// Source whose receipts are enriched; required when several sources are configured
+        #[clap(long)]
+        source: Option<String>,
+    },
+    /// Close the L2 receipt PDAs outside the retention window, refunding
+    /// their rent to `receipt_pdas.collector`
+    CloseReceipts {
+        /// Only count the receipt PDAs that would be closed
+        #[clap(long)]
+        dry_run: bool,
+        /// Source whose receipt PDAs are closed; required when several sources are configured
+        #[clap(long)]
+        source: Option<String>,
+    },
+    /// Write receipts for relays found in L2 transaction history
+    ImportHistory {
+        /// Address whose transactions are read, e.g. an earlier relayer key;
+        /// the L2 program when omitted
+        #[clap(long)]
+        address: Option<String>,
+        /// Discard saved progress and read the whole history again
+        #[clap(long)]
+        restart: bool,
+        /// Maximum L2 RPC requests per second
+        #[clap(long, default_value = "5")]
+        rps: u32,
+        /// Source whose receipts are written; required when several sources are configured
+        #[clap(long)]
+        source: Option<String>,
+    },
+    /// Relay dead-lettered or missing nonces again at a limited rate, with
+    /// resumable progress; running relayers hold their submissions meanwhile
+    #[clap(visible_alias = "backfill")]
+    ReplayBatch(ReplayBatchArgs),
+    /// Plan, without sending, the L2 transaction of every pending nonce and
+    /// compare it with another build's plan; exits 1 on a divergence
+    ShadowCompare {
+        /// Plan written by another build (file or `http(s)://` URL)
+        #[clap(long)]
+        against: Option<String>,
+        /// Plan file written by this build (JSON lines)
+        #[clap(long, default_value = "shadow-plan.jsonl")]
+        output: PathBuf,
+        /// Source to plan; required when several sources are configured
+        #[clap(long)]
+        source: Option<String>,
+    },
+    /// Inspect the effective configuration
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Review and resolve dead-lettered nonces
+    Dlq {
+        #[clap(subcommand)]
+        command: DlqCommand,
+    },
+    /// Ship receipts to the `[receipt_sink]`
+    Sink {
+        #[clap(subcommand)]
+        command: SinkCommand,
+    },
+    /// Inspect the decision journal written to `journal_path`
+    Journal {
+        #[clap(subcommand)]
+        command: JournalCommand,
+    },
+    /// Hex-dump an account and try to decode it with every known layout
+    DecodeAccount {
+        /// Address of the account
+        pubkey: String,
+        /// Chain to read the account from
+        #[clap(long, value_enum, default_value = "l1")]
+        cluster: Cluster,
+    },
+    /// Check a relay proof, given as a receipt file or a bare proof
+    VerifyProof {
+        /// JSON file holding the proof, e.g. `state_dir/receipts/<nonce>.json`
+        path: PathBuf,
+        /// Relayer key the proof must be signed by
+        #[clap(long)]
+        pubkey: String,
+    },
+    /// Promote the secondary signer after checking its L2 balance; running
+    /// relayers switch to it before their next batch
+    RotateKey,
+    /// Diagnose the configuration without sending or writing anything;
+    /// exits 69 if a check fails
+    Doctor {
+        /// Print the report as one JSON object
+        #[clap(long)]
+        json: bool,
+    },
+    /// Measure the CPU cost of PDA derivation, account decoding and
+    /// transaction signing on this host, without RPC
+    Bench {
+        /// Nonces whose PDA is derived
+        #[clap(long, default_value = "1000000")]
+        nonces: u64,
+        /// Iterations of the decoding and signing benchmarks
+        #[clap(long, default_value = "100000")]
+        iterations: u64,
+        /// Print JSON instead of text
+        #[clap(long)]
+        json: bool,
+    },
+    /// List the transfers parked above `manual_approval_threshold`, with their
+    /// approvals and the message approvers sign
+    Approvals {
+        /// Only this source
+        #[clap(long)]
+        source: Option<String>,
+        /// Print JSON instead of text
+        #[clap(long)]
+        json: bool,
+    },
+    /// Sign the approval of a transfer parked above `manual_approval_threshold`
+    Approve {
+        /// L1 nonce of the parked transfer
+        nonce: u64,
+        /// Keypair file of one of the `approver_pubkeys`
+        #[clap(long)]
+        keypair: String,
+        /// Source of the transfer; required when several sources are configured
+        #[clap(long)]
+        source: Option<String>,
+    },
+    /// Cancel a transfer held in its recipient's quiet period; running relayers
+    /// record it as cancelled instead of relaying it
+    Cancel {
+        /// L1 nonce of the held transfer
+        nonce: u64,
+        /// Why the transfer is cancelled, kept in its receipt and alert
+        #[clap(long)]
+        reason: String,
+        /// Source of the transfer; required when several sources are configured
+        #[clap(long)]
+        source: Option<String>,
+    },
+    /// Change a transfer rate limit; running relayers apply it before their next batch
+    Throttle {
+        /// Transfers per minute; 0 lifts the limit
+        #[clap(long, required_unless_present = "reset")]
+        per_minute: Option<u64>,
+        /// Go back to the configured limit
+        #[clap(long, conflicts_with = "per-minute")]
+        reset: bool,
+        /// Source whose own limit changes; the limit shared by all sources when omitted
+        #[clap(long)]
+        source: Option<String>,
+    },
+    /// Change the log filter; running relayers apply it within a few seconds
+    LogLevel {
+        /// Directives as in `RUST_LOG`, e.g. `info,sol_bridge_relayer::pipeline=debug`
+        #[clap(required_unless_present = "reset")]
+        filter: Option<String>,
+        /// Go back to the configured levels
+        #[clap(long, conflicts_with = "filter")]
+        reset: bool,
+    },
+    /// Make standby relayers active
+    Promote {
+        /// Drop the promotion; the configured role applies from the next start
+        #[clap(long)]
+        reset: bool,
+    },
+    /// Encrypt a keypair file with a passphrase, read from the
+    /// `keypair_passphrase_env` variable or prompted for
+    EncryptKeypair {
+        /// Keypair file to encrypt
+        input: String,
+        /// Where to write the encrypted keypair; must not exist
+        #[clap(long)]
+        output: String,
+    },
+    /// Resume submissions held by the anomaly circuit breaker
+    ResumeBreaker {
+        /// Source to resume; every source when omitted
+        #[clap(long)]
+        source: Option<String>,
+    },
+    /// Confirm that relaying up to an abnormally large nonce is intended
+    ConfirmJump {
+        /// L1 nonce the relayer may catch up to
+        #[clap(long)]
+        to: u64,
+        /// Source whose jump is confirmed; required when several sources are configured
+        #[clap(long)]
+        source: Option<String>,
+    },
+    /// Relay synthetic L1 traffic through the fakes and report throughput,
+    /// latency, retries and fee spend
+    #[cfg(feature = "testing")]
+    SimulateLoad(SimulateLoadArgs),
+    /// Play random schedules of L1 advances, late PDAs, send failures and
+    /// crashes through the fakes and check the nonce-progression invariants
+    #[cfg(feature = "testing")]
+    CheckInvariants {
+        /// Schedules to generate
+        #[clap(long, default_value = "100")]
+        seeds: u64,
+        /// Seed of the first schedule
+        #[clap(long, default_value = "1")]
+        seed: u64,
+        /// Steps per schedule
+        #[clap(long, default_value = "40")]
+        steps: usize,
+        /// Replay a schedule written by an earlier failure instead
+        #[clap(long)]
+        schedule: Option<PathBuf>,
+        /// Where the minimal failing schedule is written
+        #[clap(long, default_value = "invariant-failure.json")]
+        output: PathBuf,
+        /// Print JSON instead of text
+        #[clap(long)]
+        json: bool,
+    },
+    /// Start solana-test-validator with the bridge programs and fixture
+    /// accounts, relay transfers of the first source through it and check
+    /// the recipients' L2 balances
+    #[cfg(feature = "testing")]
+    E2e(E2eArgs),
+}
+
+/// Arguments of `reconcile`
+#[derive(Debug, Args)]
+pub struct ReconcileArgs {
+    /// Walk every nonce from the genesis nonce, with resumable progress
+    #[clap(long)]
+    pub full: bool,
+    /// Discard saved full-scan progress and start over
+    #[clap(long, requires = "full")]
+    pub restart: bool,
+    /// Number of most recent nonces checked by an incremental reconcile
+    #[clap(long, default_value = "1000")]
+    pub window: u64,
+    /// Maximum L1 RPC requests per second
+    #[clap(long, default_value = "5")]
+    pub rps: u32,
+    /// Detailed discrepancies file (JSON lines)
+    #[clap(long, default_value = "reconcile-discrepancies.jsonl")]
+    pub output: PathBuf,
+    /// Source to reconcile; required when several sources are configured
+    #[clap(long)]
+    pub source: Option<String>,
+}
+
+/// Arguments of `report`
+#[derive(Debug, Args)]
+pub struct ReportArgs {
+    /// Length of each reported period
+    #[clap(long, value_enum, default_value = "day")]
+    pub granularity: Granularity,
+    /// Only report the most recent span, e.g. `30d`, `12h` or `90m`
+    #[clap(long, value_parser = report::parse_span, conflicts_with_all = &["from", "to"])]
+    pub last: Option<Duration>,
+    /// First day to report, `YYYY-MM-DD` in UTC
+    #[clap(long, value_parser = fees::parse_day)]
+    pub from: Option<String>,
+    /// Last day to report, `YYYY-MM-DD` in UTC
+    #[clap(long, value_parser = fees::parse_day)]
+    pub to: Option<String>,
+    /// Print the daily fee ledgers instead, per bridge and source; every
+    /// source unless `--source` is given
+    #[clap(long, conflicts_with_all = &["granularity", "last"])]
+    pub fees: bool,
+    /// Print JSON instead of a table
+    #[clap(long)]
+    pub json: bool,
+    /// Write the report to this file instead of stdout
+    #[clap(long)]
+    pub output: Option<PathBuf>,
+    /// Source to report on; required when several sources are configured
+    #[clap(long)]
+    pub source: Option<String>,
+}
+
+/// Arguments of `replay-batch`
+#[derive(Debug, Args)]
+pub struct ReplayBatchArgs {
+    /// First nonce of the range to replay
+    #[clap(long, required_unless_present = "from-dlq")]
+    pub from: Option<u64>,
+    /// End of the range (exclusive); the L1 nonce when omitted
+    #[clap(long, requires = "from")]
+    pub to: Option<u64>,
+    /// Replay the open dead letters, within the range if one is given
+    #[clap(long)]
+    pub from_dlq: bool,
+    /// Only replay messages matching `type=<native|token|nft|type id>`; repeatable
+    #[clap(long = "filter", value_parser = replay_batch::parse_filter)]
+    pub filters: Vec<ReplayFilter>,
+    /// Nonces submitted per second
+    #[clap(long, default_value = "1")]
+    pub rate: f64,
+    /// Summary file (JSON)
+    #[clap(long, default_value = "replay-batch-summary.json")]
+    pub output: PathBuf,
+    /// Discard saved progress and select the nonces again
+    #[clap(long)]
+    pub restart: bool,
+    /// Operator recorded on the lease and on replayed dead letters; defaults to `$USER`
+    #[clap(long)]
+    pub by: Option<String>,
+    /// Source to replay; required when several sources are configured
+    #[clap(long)]
+    pub source: Option<String>,
+}
+
+#[cfg(feature = "testing")]
+/// Arguments of `simulate-load`
+#[derive(Debug, Args)]
+pub struct SimulateLoadArgs {
+    /// Transfers to generate
+    #[clap(long, default_value = "1000")]
+    pub transfers: u64,
+    /// Mean arrivals per simulated second
+    #[clap(long, default_value = "10")]
+    pub rate: f64,
+    #[clap(long, value_enum, default_value = "poisson")]
+    pub arrivals: ArrivalPattern,
+    /// Smallest transfer amount in lamports
+    #[clap(long, default_value = "1000000")]
+    pub amount_min: u64,
+    /// Largest transfer amount in lamports
+    #[clap(long, default_value = "10000000000")]
+    pub amount_max: u64,
+    #[clap(long, value_enum, default_value = "log-uniform")]
+    pub amounts: AmountDistribution,
+    /// Fraction of token messages
+    #[clap(long, default_value = "0")]
+    pub token_ratio: f64,
+    /// Fraction of NFT messages
+    #[clap(long, default_value = "0")]
+    pub nft_ratio: f64,
+    /// Fraction of messages of an unknown type
+    #[clap(long, default_value = "0")]
+    pub unknown_ratio: f64,
+    /// Fraction of L2 submissions that fail
+    #[clap(long, default_value = "0")]
+    pub send_failure_rate: f64,
+    /// Fraction of L2 submissions that are accepted but never land
+    #[clap(long, default_value = "0")]
+    pub drop_rate: f64,
+    /// L2 prioritization fee in micro-lamports per compute unit
+    #[clap(long, default_value = "0")]
+    pub prioritization_fee: u64,
+    /// Simulated time the backlog may take to drain, e.g. `10m`
+    #[clap(long, value_parser = report::parse_span, default_value = "10m")]
+    pub drain_timeout: Duration,
+    /// Seed of the generated traffic and failures
+    #[clap(long, default_value = "1")]
+    pub seed: u64,
+    /// Print JSON instead of text
+    #[clap(long)]
+    pub json: bool,
+}
+
+#[cfg(feature = "testing")]
+/// Arguments of `e2e`
+#[derive(Debug, Args)]
+pub struct E2eArgs {
+    /// Build (.so) of the L2 bridge program
+    #[clap(long)]
+    pub l2_program: PathBuf,
+    /// Build (.so) of the L1 bridge program; the L1 accounts are
+    /// preloaded without it
+    #[clap(long)]
+    pub l1_program: Option<PathBuf>,
+    /// Transfers waiting on L1
+    #[clap(long, default_value = "3")]
+    pub transfers: u64,
+    /// Lamports of every transfer
+    #[clap(long, default_value = "1000000000")]
+    pub amount: u64,
+    /// Run L1 and L2 on validators of their own
+    #[clap(long)]
+    pub two_validators: bool,
+    #[clap(long, default_value = "solana-test-validator")]
+    pub validator: PathBuf,
+    /// RPC port of the first validator
+    #[clap(long, default_value = "18899")]
+    pub rpc_port: u16,
+    /// Ledgers, account files, wallet and state; a temporary directory
+    /// by default
+    #[clap(long)]
+    pub work_dir: Option<PathBuf>,
+    /// How long the transfers may take to land, e.g. `2m`
+    #[clap(long, value_parser = report::parse_span, default_value = "2m")]
+    pub timeout: Duration,
+    /// Print JSON instead of text
+    #[clap(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Print the merged configuration (profile applied, defaults filled in)
+    /// as JSON, with secrets redacted
+    Show,
+    /// Check the configuration without touching the network: every source's
+    /// addresses and discriminators and the signer keys
+    Validate,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum DlqCommand {
+    /// Show every dead letter with its error, attempts, age, notes and resolution
+    List {
+        /// Print the entries as JSON
+        #[clap(long)]
+        json: bool,
+        /// Source whose dead letters are listed; all sources by default
+        #[clap(long)]
+        source: Option<String>,
+    },
+    /// Attach an operator note to a dead letter
+    Annotate {
+        /// L1 nonce of the dead letter
+        nonce: u64,
+        /// Text of the note, e.g. "refunded off-chain"
+        #[clap(long)]
+        note: String,
+        /// Operator name recorded with the note; defaults to `$USER`
+        #[clap(long)]
+        by: Option<String>,
+        /// Source of the nonce; required when several sources are configured
+        #[clap(long)]
+        source: Option<String>,
+    },
+    /// Close a dead letter; `replayed` relays the nonce again
+    Resolve {
+        /// L1 nonce of the dead letter
+        nonce: u64,
+        /// How the entry is closed
+        #[clap(long = "as", value_enum)]
+        resolution: ResolutionKind,
+        /// Why, recorded with the resolution
+        #[clap(long)]
+        note: Option<String>,
+        /// Operator name recorded with the resolution; defaults to `$USER`
+        #[clap(long)]
+        by: Option<String>,
+        /// Source of the nonce; required when several sources are configured
+        #[clap(long)]
+        source: Option<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum StateCommand {
+    /// Write the state to a bundle; running relayers hold their submissions
+    /// until it is written
+    Export {
+        /// Bundle file to write, e.g. `state.jsonl.zst`; compressed when it ends in `.zst`
+        #[clap(long)]
+        out: PathBuf,
+        /// Operator recorded in the bundle and on the lease; defaults to `$USER`
+        #[clap(long)]
+        by: Option<String>,
+        /// Source to export; required when several sources are configured
+        #[clap(long)]
+        source: Option<String>,
+    },
+    /// Replace the state with a bundle; stop the relayers using it first
+    Import {
+        /// Bundle file written by `state export`
+        bundle: PathBuf,
+        /// Import even if the state has receipts above the bundle's
+        #[clap(long)]
+        force: bool,
+        /// Source to import into; required when several sources are configured
+        #[clap(long)]
+        source: Option<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SinkCommand {
+    /// Ship the stored receipts the sink has not received, above its
+    /// shipped-up-to watermark
+    Backfill {
+        /// Ship every stored receipt from this nonce on instead
+        #[clap(long)]
+        from: Option<u64>,
+        /// Source whose receipts are shipped; all sources by default
+        #[clap(long)]
+        source: Option<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum JournalCommand {
+    /// Print the most recent journal records
+    Tail {
+        /// Number of records
+        #[clap(long, default_value = "20")]
+        lines: usize,
+        /// Keep printing records as they are appended
+        #[clap(long)]
+        follow: bool,
+        /// Only records matching `nonce=<nonce>`, `event=<event>` or
+        /// `type=native|token|nft`; repeat to require several
+        #[clap(long = "filter", value_parser = journal::parse_filter)]
+        filters: Vec<JournalFilter>,
+        /// Print the records as JSON lines
+        #[clap(long)]
+        json: bool,
+        /// Source whose journal is read; required when several sources are configured
+        #[clap(long)]
+        source: Option<String>,
+    },
+    /// Reconstruct the pending queue as it was at a point in time
+    Replay {
+        /// Unix time to reconstruct; records after it are ignored
+        #[clap(long)]
+        from: u64,
+        /// Source whose journal is read; required when several sources are configured
+        #[clap(long)]
+        source: Option<String>,
+    },
+}
@@ -0,0 +1,75 @@
+//! Time source abstraction.
+//! Everything that reads the wall clock or sleeps goes through `Clock`, so
+//! time-based behavior (retry delays, pacing, timeouts) can be driven
+//! deterministically.
+
+use async_trait::async_trait;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time;
+
+#[async_trait]
+pub trait Clock: Send + Sync {
+    /// Current wall-clock time
+    fn now(&self) -> SystemTime;
+
+    /// Waits for `duration`
+    async fn sleep(&self, duration: Duration);
+
+    /// Time elapsed since `earlier`, zero if `earlier` is in the future
+    fn elapsed(&self, earlier: SystemTime) -> Duration {
+        self.now().duration_since(earlier).unwrap_or_default()
+    }
+
+    /// Seconds since the Unix epoch
+    fn unix_timestamp(&self) -> u64 {
+        self.now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+/// The real clock
+pub struct SystemClock;
+
+#[async_trait]
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        time::sleep(duration).await;
+    }
+}
+
+/// Clock that follows tokio's timer from a fixed start time. Under
+/// `tokio::time::pause()` sleeps complete instantly and time only moves when
+/// the runtime auto-advances or `tokio::time::advance` is called.
+#[cfg(feature = "testing")]
+pub struct TokioClock {
+    start: SystemTime,
+    started_at: time::Instant,
+}
+
+#[cfg(feature = "testing")]
+impl TokioClock {
+    pub fn starting_at(start: SystemTime) -> Self {
+        Self {
+            start,
+            started_at: time::Instant::now(),
+        }
+    }
+}
+
+#[cfg(feature = "testing")]
+#[async_trait]
+impl Clock for TokioClock {
+    fn now(&self) -> SystemTime {
+        self.start + self.started_at.elapsed()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        time::sleep(duration).await;
+    }
+}
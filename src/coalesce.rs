@@ -0,0 +1,126 @@
+//! Coalescing of transfer bursts.
+//! With `coalesce_window_ms` set, the submitter lets pending nonces accumulate
+//! for that long before taking a batch, and runs of consecutive nonces paying
+//! the same recipient with the same message type are relayed as one L2
+//! transfer of the summed amount. The merged transfer carries the highest
+//! nonce of the run, so the L2 program must accept a nonce past the one it
+//! expects; its memo and receipt list every constituent nonce.
+//!
+//! Only consecutive nonces are merged, so the L2 nonce account never moves
+//! past a nonce whose transfer is not part of a landed transaction.
+
+use crate::{pipeline::FetchedTransfer, Relayer};
+use anyhow::Result;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+#[derive(Debug, Clone, Copy)]
+pub struct CoalesceSettings {
+    /// How long pending nonces accumulate before a batch is taken
+    pub window: Duration,
+    /// Largest summed amount of a merged transfer
+    pub max_amount: Option<u64>,
+}
+
+impl CoalesceSettings {
+    /// Summed amount if `next` can join the run `run` as one transfer
+    fn merged_amount(&self, run: &FetchedTransfer, next: &FetchedTransfer) -> Option<u64> {
+        let mergeable = next.nonce == run.nonce + 1
+            && next.to_address == run.to_address
+            && next.message_type == run.message_type
+            // 只合并同一铸币的代币转账，NFT 不合并
+            && next.token == run.token
+            && run.token.is_none_or(|token| !token.nft)
+            // 默克尔证明只对应单条消息，无法合并
+            && run.proof.is_none()
+            && next.proof.is_none()
+            // 有截止时间的转账各自过期，不合并
+            && run.expires_at.is_none()
+            && next.expires_at.is_none()
+            // 多条目 PDA 的条目按 (nonce, 序号) 各自记回执，不合并
+            && run.entry.is_none()
+            && next.entry.is_none();
+        if !mergeable {
+            return None;
+        }
+        run.amount
+            .checked_add(next.amount)
+            .filter(|amount| self.max_amount.is_none_or(|max| *amount <= max))
+    }
+}
+
+/// Appends `next` to the run `run`, which then relays `amount`
+fn absorb(mut run: FetchedTransfer, next: FetchedTransfer, amount: u64) -> FetchedTransfer {
+    if run.coalesced.is_empty() {
+        run.coalesced.push(run.nonce);
+    }
+    run.coalesced.push(next.nonce);
+    run.nonce = next.nonce;
+    run.amount = amount;
+    run.l1_slot = run.l1_slot.max(next.l1_slot);
+    // 合并后的转账不对应单条 L1 消息
+    run.digest = None;
+    if run.sender != next.sender {
+        run.sender = None;
+    }
+    run
+}
+
+impl Relayer {
+    /// Merges runs of fetched transfers and forwards them to the build stage;
+    /// passes every transfer through unchanged when coalescing is disabled
+    pub(crate) async fn coalesce_stage(
+        &self,
+        mut input: mpsc::Receiver<FetchedTransfer>,
+        output: mpsc::Sender<FetchedTransfer>,
+    ) -> Result<()> {
+        let Some(mut settings) = self.coalescing else {
+            while let Some(fetched) = input.recv().await {
+                if output.send(fetched).await.is_err() {
+                    return Ok(());
+                }
+            }
+            return Ok(());
+        };
+
+        // 合并金额不超过 L2 程序的单笔上限
+        if let Some(max) = self
+            .l2_limits
+            .as_ref()
+            .and_then(|watch| watch.max_per_transfer())
+        {
+            settings.max_amount = Some(settings.max_amount.map_or(max, |limit| limit.min(max)));
+        }
+        // 也不超过 [policy] 的单笔上限
+        let max_amount = self
+            .policy
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|policy| policy.max_amount);
+        if let Some(max) = max_amount {
+            settings.max_amount = Some(settings.max_amount.map_or(max, |limit| limit.min(max)));
+        }
+        let mut run: Option<FetchedTransfer> = None;
+        while let Some(fetched) = input.recv().await {
+            let Some(current) = run.take() else {
+                run = Some(fetched);
+                continue;
+            };
+            match settings.merged_amount(&current, &fetched) {
+                Some(amount) => run = Some(absorb(current, fetched, amount)),
+                None => {
+                    run = Some(fetched);
+                    if output.send(current).await.is_err() {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+
+        if let Some(current) = run {
+            let _ = output.send(current).await;
+        }
+        Ok(())
+    }
+}
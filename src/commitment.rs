@@ -0,0 +1,71 @@
+//! Commitment levels of the RPC clients.
+//! `l1_commitment` and `l2_commitment` (default `confirmed`) set the
+//! commitment of the source and destination clients: every read made
+//! through a client, and the confirmation the submitter waits for without
+//! `[[confirmation_rules]]`, use it. With `l1_relay_finalized = true` the
+//! monitor keeps polling at `l1_commitment`, but only queues the nonces the
+//! watched account also shows at finalized commitment, so a message dropped
+//! by an L1 reorg is never relayed.
+
+use crate::Relayer;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_sdk::commitment_config::CommitmentConfig;
+use std::fmt;
+
+/// Commitment of one chain's client
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Commitment {
+    Processed,
+    #[default]
+    Confirmed,
+    Finalized,
+}
+
+impl Commitment {
+    pub fn config(self) -> CommitmentConfig {
+        match self {
+            Self::Processed => CommitmentConfig::processed(),
+            Self::Confirmed => CommitmentConfig::confirmed(),
+            Self::Finalized => CommitmentConfig::finalized(),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Processed => "processed",
+            Self::Confirmed => "confirmed",
+            Self::Finalized => "finalized",
+        }
+    }
+}
+
+impl fmt::Display for Commitment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Relayer {
+    /// Caps `queue_to` at the watched account's nonce at finalized
+    /// commitment; nothing past `relayed` is queued while the account is
+    /// missing there
+    pub(crate) async fn finalized_l1_nonce(&self, queue_to: u64, relayed: u64) -> Result<u64> {
+        let read = self
+            .read_watched_at(&self.l1_client, CommitmentConfig::finalized())
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to read the finalized watched account: {}", e))?;
+        let finalized = read.nonce.unwrap_or(relayed).max(relayed);
+        if finalized < queue_to {
+            tracing::debug!(
+                "Holding nonces {}..{} until L1 finalizes them (finalized nonce {} at slot {})",
+                finalized,
+                queue_to,
+                finalized,
+                read.slot
+            );
+        }
+        Ok(queue_to.min(finalized))
+    }
+}
@@ -7,48 +7,2437 @@
  * @LastEditTime: 2024-11-21 10:39:19
  */
 //! Configuration management for the relayer.
-//! Handles loading and parsing of configuration from TOML files.
+//! Handles loading and parsing of configuration from TOML, YAML or JSON files.
+//! A file either holds the settings at the top level, or a `[default]`
+//! section with shared values and `[profiles.<name>]` sections overriding them.
 
+use crate::{
+    alerts::AlertWebhook,
+    batching::BatchStrategy,
+    cancellation::RegistryLayout,
+    chaos::ChaosConfig,
+    commitment::Commitment,
+    confirmation_strategy::{ConfirmationRule, ConfirmationStrategy},
+    direction::Direction,
+    dust::DustPolicy,
+    limits::LimitsConfig,
+    merkle::MessageSource,
+    message_decoder::TransferInfoLayout,
+    models::{
+        account::AccountDiscriminators,
+        message::{MessageType, UnknownTypePolicy},
+        processed::ProcessedLayout,
+    },
+    pda::SeedScheme,
+    policy::TransferPolicy,
+    priority::QueuePriority,
+    priority_fee::PriorityFeeStrategy,
+    program_accounts::ProgramAccountsConfig,
+    pubkeys::{DestinationAccount, DestinationProgramId, SourceAccount, SourceProgramId},
+    queue::OverflowPolicy,
+    replay::OutsideWindowPolicy,
+    secrets::{Redacted, SecretUrl, Secrets},
+    signing::SignerBackend,
+    standby::Role,
+    subscription::WatchMode,
+    token::{MintMapping, TokenAccountMismatch, TokenAccountRentPayer, UnmappedMintPolicy},
+    transaction::InstructionVersion,
+};
 use anyhow::{Error, Result};
+use config::{ConfigError, FileFormat, Map, Value, ValueKind};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
 use std::env;
+use std::fmt;
+use std::io::Read;
 use std::path::Path;
+use yaml_rust::{Yaml, YamlEmitter, YamlLoader};
+
+/// Id of the source built from the top-level fields when no `[[sources]]` are configured
+pub const DEFAULT_SOURCE_ID: &str = "default";
 
 /// Configuration structure for the relayer
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct RelayerConfig {
-    pub l1_url: String,
-    pub l2_url: String,
-    pub watched_account: String,
-    pub wallet_path: String,
-    pub l1_program_id: String,
-    pub l2_program_id: String,
-    pub nonce_account: String,
+    pub l1_url: SecretUrl,
+    pub l2_url: SecretUrl,
+    /// Further L1 endpoints, tried in order when `l1_url` fails
+    #[serde(default)]
+    pub l1_urls: Vec<SecretUrl>,
+    /// Further L2 endpoints, tried in order when `l2_url` fails
+    #[serde(default)]
+    pub l2_urls: Vec<SecretUrl>,
+    /// Spread reads over the healthy endpoints instead of using the first
+    #[serde(default)]
+    pub rpc_round_robin_reads: bool,
+    /// How long a failed endpoint is skipped before it is health-checked
+    #[serde(default = "default_rpc_failover_cooldown_secs")]
+    pub rpc_failover_cooldown_secs: u64,
+    /// `l1_to_l2` relays deposits; `l2_to_l1` watches L2 and submits on L1
+    #[serde(default)]
+    pub direction: Direction,
+    /// Commitment of the watched chain's client: processed, confirmed or finalized
+    #[serde(default)]
+    #[serde(alias = "source_commitment")]
+    pub l1_commitment: Commitment,
+    /// Commitment of the submitting chain's client
+    #[serde(default)]
+    #[serde(alias = "destination_commitment")]
+    pub l2_commitment: Commitment,
+    /// Independent L1 RPC providers that must confirm high-value reads
+    #[serde(default)]
+    #[serde(alias = "source_verify_urls")]
+    pub l1_verify_urls: Vec<SecretUrl>,
+    /// Native transfers above this many lamports are read again from every
+    /// `l1_verify_urls` provider before they are relayed
+    #[serde(default)]
+    pub dual_read_threshold: u64,
+    /// Seconds between reads of the watched account at confirmed and
+    /// finalized commitment from every L1 endpoint (0: no probe)
+    #[serde(default)]
+    #[serde(alias = "source_lag_probe_interval_secs")]
+    pub l1_lag_probe_interval_secs: u64,
+    /// Slots an L1 endpoint's confirmed view may trail the best-known slot
+    /// before it is flagged for quarantine
+    #[serde(default = "default_l1_lag_quarantine_slots")]
+    #[serde(alias = "source_lag_quarantine_slots")]
+    pub l1_lag_quarantine_slots: u64,
+    /// How the monitor learns of watched-account changes: `poll`, or
+    /// `subscribe` over WebSocket with polling while disconnected
+    #[serde(default)]
+    #[serde(alias = "source_watch_mode")]
+    pub l1_watch_mode: WatchMode,
+    /// WebSocket endpoint for `l1_watch_mode = "subscribe"`; derived from
+    /// `l1_url` when unset
+    #[serde(default)]
+    #[serde(alias = "source_ws_url")]
+    pub l1_ws_url: Option<SecretUrl>,
+    /// Single-source setup; ignored when `[[sources]]` are configured
+    #[serde(default)]
+    pub watched_account: Option<SourceAccount>,
+    /// Keypair file of the primary signer; unused with `signer_backend = "remote"`
+    #[serde(default)]
+    pub wallet_path: Redacted<String>,
+    /// Second signer key, promoted by `rotate-key`
+    #[serde(default)]
+    pub secondary_wallet_path: Option<Redacted<String>>,
+    /// Environment variable holding the passphrase of encrypted keypair files
+    #[serde(default = "default_keypair_passphrase_env")]
+    pub keypair_passphrase_env: String,
+    /// Where the primary signer key lives: "file" (`wallet_path`) or "remote"
+    #[serde(default)]
+    pub signer_backend: SignerBackend,
+    /// Signing service of `signer_backend = "remote"`
+    #[serde(default)]
+    pub remote_signer_url: Option<SecretUrl>,
+    /// Public key the signing service signs with
+    #[serde(default)]
+    pub remote_signer_pubkey: Option<String>,
+    /// Bearer token sent to the signing service
+    #[serde(default)]
+    pub remote_signer_token: Option<Redacted<String>>,
+    /// How long one signing request may take
+    #[serde(default = "default_remote_signer_timeout_ms")]
+    pub remote_signer_timeout_ms: u64,
+    /// Single-source setup; ignored when `[[sources]]` are configured
+    #[serde(default)]
+    #[serde(alias = "source_program_id")]
+    pub l1_program_id: Option<SourceProgramId>,
+    #[serde(alias = "destination_program_id")]
+    pub l2_program_id: DestinationProgramId,
+    /// Layout of the L2 `relay_message` data: 1 (amount, nonce), 2 (adds the
+    /// message type and source nonce), 3 (adds the bridge domain), 4 (adds
+    /// the deadline) or 5 (adds the L1 sender); checked against the L2
+    /// program at startup
+    #[serde(default)]
+    #[serde(alias = "destination_instruction_version")]
+    pub l2_instruction_version: InstructionVersion,
+    /// Id of the L2 deployment this relayer serves (e.g. staging or
+    /// production); must match the L2 program's config account
+    #[serde(default)]
+    pub bridge_domain: Option<u64>,
+    /// Pass each transfer's L1 sender to L2, in the memo and in version 5
+    /// data; `false` keeps senders off L2 for privacy-sensitive deployments
+    #[serde(default = "default_relay_sender")]
+    pub relay_sender: bool,
+    /// Where the L2 program marks processed nonces, read right before each
+    /// send (`[l2_processed_nonces]`; unchecked when unset)
+    #[serde(default)]
+    #[serde(alias = "destination_processed_nonces")]
+    pub l2_processed_nonces: Option<ProcessedNoncesConfig>,
+    /// L2 bridge-config account whose paused flag holds submissions (unchecked when unset)
+    #[serde(default)]
+    #[serde(alias = "destination_bridge_config_account")]
+    pub l2_bridge_config_account: Option<DestinationAccount>,
+    /// Offset of the `u8` paused flag in the bridge-config account data
+    #[serde(default = "default_l2_pause_flag_offset")]
+    #[serde(alias = "destination_pause_flag_offset")]
+    pub l2_pause_flag_offset: usize,
+    /// Longest time a read of the paused flag is trusted
+    #[serde(default = "default_l2_pause_check_interval_secs")]
+    #[serde(alias = "destination_pause_check_interval_secs")]
+    pub l2_pause_check_interval_secs: u64,
+    /// Batches of at least this many nonces re-read the paused flag first
+    #[serde(default = "default_l2_pause_recheck_batch_size")]
+    #[serde(alias = "destination_pause_recheck_batch_size")]
+    pub l2_pause_recheck_batch_size: u64,
+    /// Slots the L2 node may trail its highest-known slot, or
+    /// `l2_health_reference_url`, before submissions are held; the L2
+    /// health is not checked when unset
+    #[serde(default)]
+    #[serde(alias = "destination_health_max_lag_slots")]
+    pub l2_health_max_lag_slots: Option<u64>,
+    /// Second L2 endpoint whose slot the node is compared with
+    #[serde(default)]
+    #[serde(alias = "destination_health_reference_url")]
+    pub l2_health_reference_url: Option<SecretUrl>,
+    /// Seconds the node's slot may stay unchanged before it counts as
+    /// stalled (0: no stall check)
+    #[serde(default = "default_l2_health_stall_secs")]
+    #[serde(alias = "destination_health_stall_secs")]
+    pub l2_health_stall_secs: u64,
+    /// First wait before an unhealthy L2 cluster is probed again, doubled
+    /// after every failed probe
+    #[serde(default = "default_l2_health_backoff_secs")]
+    #[serde(alias = "destination_health_backoff_secs")]
+    pub l2_health_backoff_secs: u64,
+    /// Longest wait between probes of an unhealthy L2 cluster
+    #[serde(default = "default_l2_health_max_backoff_secs")]
+    #[serde(alias = "destination_health_max_backoff_secs")]
+    pub l2_health_max_backoff_secs: u64,
+    /// Offset of the limits block in the bridge-config account; limits are
+    /// not checked when unset
+    #[serde(default)]
+    #[serde(alias = "destination_limits_offset")]
+    pub l2_limits_offset: Option<usize>,
+    /// Longest time a read of the L2 limits is trusted
+    #[serde(default = "default_l2_limits_ttl_secs")]
+    #[serde(alias = "destination_limits_ttl_secs")]
+    pub l2_limits_ttl_secs: u64,
+    /// Derive the bridge-config account from the L2 program id (PDA
+    /// `["config"]`) instead of trusting `l2_bridge_config_account`
+    #[serde(default)]
+    #[serde(alias = "destination_bridge_config_pda")]
+    pub l2_bridge_config_pda: bool,
+    /// Expected owner of the L2 nonce accounts (the L2 program when unset)
+    #[serde(default)]
+    #[serde(alias = "destination_nonce_account_owner")]
+    pub l2_nonce_account_owner: Option<DestinationProgramId>,
+    /// Bytes an L2 nonce account must hold at least
+    #[serde(default = "default_l2_nonce_account_min_size")]
+    #[serde(alias = "destination_nonce_account_min_size")]
+    pub l2_nonce_account_min_size: usize,
+    /// Expected owner of the bridge-config account (the L2 program when unset)
+    #[serde(default)]
+    #[serde(alias = "destination_bridge_config_owner")]
+    pub l2_bridge_config_owner: Option<DestinationProgramId>,
+    /// Bytes the bridge-config account must hold at least; by default enough
+    /// for the paused flag and, with `bridge_domain`, the domain
+    #[serde(default)]
+    #[serde(alias = "destination_bridge_config_min_size")]
+    pub l2_bridge_config_min_size: Option<usize>,
+    /// Seconds between re-validations of the L2 accounts before a batch (0: startup only)
+    #[serde(default = "default_l2_account_check_interval_secs")]
+    #[serde(alias = "destination_account_check_interval_secs")]
+    pub l2_account_check_interval_secs: u64,
+    /// Programs owning off-curve (PDA) recipients and their deposit
+    /// instruction (`[[pda_recipient_programs]]` tables); native transfers to
+    /// a PDA of any other program are dead-lettered
+    #[serde(default)]
+    pub pda_recipient_programs: Vec<PdaRecipientProgram>,
+    /// L2 mints paid out for the L1 mints of token and NFT messages
+    /// (`[[mint_mappings]]` tables)
+    #[serde(default)]
+    pub mint_mappings: Vec<MintMapping>,
+    /// File of further `[[mint_mappings]]` tables (TOML, YAML or JSON by its
+    /// extension), read at load and again on every reload
+    #[serde(default)]
+    pub mint_mappings_file: Option<String>,
+    /// Handling of token and NFT messages whose L1 mint is not mapped: "hold"
+    /// (alert and retry until a reload maps it) or "dead_letter"
+    #[serde(default)]
+    pub unmapped_mint: UnmappedMintPolicy,
+    /// Who pays the rent of a token transfer recipient's missing associated
+    /// token account: "relayer" or "recipient"
+    #[serde(default)]
+    pub token_account_rent_payer: TokenAccountRentPayer,
+    /// Handling of a recipient naming a token account of another mint, or a
+    /// frozen one: "dead_letter" or "owner" (pay its owner's associated token
+    /// account)
+    #[serde(default)]
+    pub token_account_mismatch: TokenAccountMismatch,
+    /// Accounts of the `relay_message` instruction in order
+    /// (`[[l2_instruction_accounts]]` tables); empty uses the built-in layout
+    #[serde(default)]
+    #[serde(alias = "destination_instruction_accounts")]
+    pub l2_instruction_accounts: Vec<InstructionAccountConfig>,
+    /// Single-source setup; ignored when `[[sources]]` are configured
+    #[serde(default)]
+    pub nonce_account: Option<DestinationAccount>,
+    /// Single-source setup; ignored when `[[sources]]` are configured
+    #[serde(default)]
+    pub cancellation_registry: Option<CancellationRegistryConfig>,
+    /// Single-source setup; ignored when `[[sources]]` are configured
+    #[serde(default)]
+    pub discriminators: AccountDiscriminators,
+    /// Single-source setup; ignored when `[[sources]]` are configured
+    #[serde(default)]
+    pub durable_nonce_account: Option<DestinationAccount>,
+    /// Single-source setup; ignored when `[[sources]]` are configured
+    #[serde(default)]
+    pub seeds: SeedScheme,
+    /// Single-source setup; ignored when `[[sources]]` are configured
+    #[serde(default)]
+    pub transfer_info_layout: Option<TransferInfoLayout>,
+    /// L1 source programs relayed side by side (`[[sources]]` tables)
+    #[serde(default)]
+    pub sources: Vec<SourceConfig>,
+    /// "active" (default) relays; "standby" mirrors state and checks
+    /// transactions without submitting until `promote` runs
+    #[serde(default)]
+    pub role: Role,
+    /// Pause between the checks of a standby
+    #[serde(default = "default_standby_interval_secs")]
+    pub standby_interval_secs: u64,
+    /// Simulate the transaction a standby builds for the next pending nonce
+    #[serde(default)]
+    pub standby_simulate: bool,
+    /// Send a 1-lamport self-transfer through the submission stack before
+    /// relaying, and stop if it does not confirm
+    #[serde(default = "default_warmup_transaction")]
+    pub warmup_transaction: bool,
+    /// Measure the CPU cost of a nonce before relaying and warn if the host
+    /// cannot keep up with `send_concurrency` × `submit_batch_size`
+    #[serde(default)]
+    pub startup_self_test: bool,
+    /// Maximum L2 requests per second while a standby mirrors receipts
+    #[serde(default = "default_standby_mirror_rps")]
+    pub standby_mirror_rps: u32,
+    /// Nonces a standby's mirror may be behind L2 while `/readyz` reports it
+    /// ready
+    #[serde(default = "default_standby_ready_max_lag")]
+    pub standby_ready_max_lag: u64,
+    /// Devnet convenience mode: keep the relayer wallet funded via L2 airdrops
+    #[serde(default)]
+    pub dev_mode: bool,
+    /// Balance (lamports) below which an airdrop is requested in dev mode
+    #[serde(default = "default_dev_airdrop_threshold")]
+    pub dev_airdrop_threshold: u64,
+    /// Lamports requested per airdrop in dev mode
+    #[serde(default = "default_dev_airdrop_amount")]
+    pub dev_airdrop_amount: u64,
+    /// Simulate every pending transfer, write a diff report and exit without sending
+    #[serde(default)]
+    pub audit_mode: bool,
+    /// Run the pipeline but simulate relay transactions instead of sending
+    /// them, keeping state writes in memory
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Part of the relay this process runs: all of it, or the observer or
+    /// submitter side of an `[intent_queue]`
+    #[serde(default)]
+    pub process_role: ProcessRole,
+    /// Output path of the audit report
+    #[serde(default = "default_audit_report_path")]
+    pub audit_report_path: String,
+    /// Output path of the crash report written on a fatal exit
+    #[serde(default = "default_crash_report_path")]
+    pub crash_report_path: String,
+    /// JSON-lines journal of the relayer's decisions (disabled when unset)
+    #[serde(default)]
+    pub journal_path: Option<String>,
+    /// Size at which the journal is rotated
+    #[serde(default = "default_journal_max_bytes")]
+    pub journal_max_bytes: u64,
+    /// Rotated journal files kept
+    #[serde(default = "default_journal_retain_files")]
+    pub journal_retain_files: usize,
+    /// Directory holding the relayer's persisted state
+    #[serde(default = "default_state_dir")]
+    pub state_dir: String,
+    /// First nonce walked by `reconcile --full`
+    #[serde(default)]
+    pub reconcile_genesis_nonce: u64,
+    /// Shortest pause between monitor polls, used while the L1 nonce moves
+    #[serde(default = "default_poll_interval_min_ms")]
+    pub poll_interval_min_ms: u64,
+    /// Longest pause between monitor polls while the L1 nonce is idle
+    #[serde(default = "default_poll_interval_max_ms")]
+    pub poll_interval_max_ms: u64,
+    /// How long the L1 nonce must be unchanged before the poll interval grows
+    #[serde(default = "default_poll_idle_after_secs")]
+    pub poll_idle_after_secs: u64,
+    /// Pending nonces at which the monitor stops polling until the backlog
+    /// drains (0 never stops)
+    #[serde(default = "default_poll_suspend_backlog")]
+    pub poll_suspend_backlog: u64,
+    /// Poll interval while the watched account is reported missing
+    #[serde(default = "default_watched_account_missing_poll_secs")]
+    pub watched_account_missing_poll_secs: u64,
+    /// How long the watched account may be missing before an alert is raised
+    #[serde(default = "default_watched_account_missing_alert_secs")]
+    pub watched_account_missing_alert_secs: u64,
+    /// Webhooks every alert is posted to (`[[alert_webhooks]]` tables)
+    #[serde(default)]
+    pub alert_webhooks: Vec<AlertWebhook>,
+    /// Fault injection into the RPC clients (`[chaos]`), in builds with the
+    /// `chaos` feature
+    #[serde(default)]
+    pub chaos: ChaosConfig,
+    /// Submission batches failing in a row that raise an alert (0 never does)
+    #[serde(default)]
+    pub alert_consecutive_failures: u32,
+    /// Failed monitor reads in a row that raise an alert (0 never does)
+    #[serde(default = "default_alert_consecutive_read_failures")]
+    pub alert_consecutive_read_failures: u32,
+    /// Nonces the L2 nonce account may be behind L1 before an alert is raised
+    /// (never when unset)
+    #[serde(default)]
+    pub alert_nonce_lag: Option<u64>,
+    /// Signer balance in lamports below which an alert is raised (never when unset)
+    #[serde(default)]
+    pub alert_min_balance: Option<u64>,
+    /// Signer balance in lamports below which no batch is started (unchecked when unset)
+    #[serde(default)]
+    pub min_signer_balance: Option<u64>,
+    /// Seconds between reads of the signer balance in `run` (0 reads it before batches only)
+    #[serde(default = "default_balance_check_interval_secs")]
+    pub balance_check_interval_secs: u64,
+    /// Program and arguments run to top up the signer wallet
+    #[serde(default)]
+    pub top_up_command: Vec<String>,
+    /// Signer balance below which `top_up_command` runs (`min_signer_balance` when unset)
+    #[serde(default)]
+    pub top_up_threshold: Option<u64>,
+    /// Shortest pause between two runs of `top_up_command`
+    #[serde(default = "default_top_up_cooldown_secs")]
+    pub top_up_cooldown_secs: u64,
+    /// Largest nonce jump relayed without operator confirmation
+    #[serde(default = "default_max_expected_jump")]
+    pub max_expected_jump: u64,
+    /// Sliding interval of the anomaly circuit breaker
+    #[serde(default = "default_anomaly_interval_secs")]
+    pub anomaly_interval_secs: u64,
+    /// Largest L1 nonce advance per interval before the breaker trips (0 disables)
+    #[serde(default)]
+    pub anomaly_max_nonces: u64,
+    /// Largest amount admitted for submission per interval before the breaker
+    /// trips (0 disables)
+    #[serde(default)]
+    pub anomaly_max_amount: u64,
+    /// L1 slots a newly observed nonce must age before it is relayed (0 disables the hold)
+    #[serde(default)]
+    pub min_confirmation_slots: u64,
+    /// Queue only nonces the watched account shows at finalized commitment
+    #[serde(default)]
+    #[serde(alias = "source_relay_finalized")]
+    pub l1_relay_finalized: bool,
+    /// Capacity of the channels between the fetch, build and send stages
+    #[serde(default = "default_pipeline_channel_capacity")]
+    pub pipeline_channel_capacity: usize,
+    /// Transfer-info PDAs read per bulk fetch (at most 99; the watched account is read with them)
+    #[serde(default = "default_fetch_batch_size")]
+    pub fetch_batch_size: usize,
+    /// Transactions awaiting L2 confirmation at once; values above 1 require an
+    /// L2 program that accepts out-of-order nonces
+    #[serde(default = "default_send_concurrency")]
+    pub send_concurrency: usize,
+    /// L2 blocks a durable-nonce transaction is awaited before it is built
+    /// again from the same nonce
+    #[serde(default = "default_durable_nonce_confirmation_blocks")]
+    pub durable_nonce_confirmation_blocks: u64,
+    /// Build relay transactions on a recent blockhash while the durable nonce
+    /// account is closed, instead of failing every batch
+    #[serde(default = "default_durable_nonce_fallback")]
+    pub durable_nonce_fallback: bool,
+    /// Recent signatures of the L2 nonce account searched for an earlier
+    /// relay of a nonce before it is sent (0 does not search)
+    #[serde(default)]
+    pub earlier_submission_signatures: usize,
+    /// Native transfers packed into one L2 transaction, as many as fit in a
+    /// packet and the compute limit (1 sends one transaction per transfer)
+    #[serde(default = "default_pack_max_transfers")]
+    pub pack_max_transfers: usize,
+    /// Simulate each relay transaction before it is sent and split it when
+    /// it needs more compute units than it requests
+    #[serde(default)]
+    pub simulate_compute_units: bool,
+    /// Transactions awaiting L2 confirmation at once across all sources
+    /// (unlimited when unset)
+    #[serde(default)]
+    pub max_concurrent_submissions: Option<usize>,
+    /// `sendTransaction` requests made to L2 per second across all sources,
+    /// rebroadcasts included (unlimited when unset)
+    #[serde(default)]
+    pub max_l2_sends_per_second: Option<u32>,
+    /// Sends the L2 send rate limit lets through at once after an idle period
+    #[serde(default = "default_l2_send_burst")]
+    #[serde(alias = "destination_send_burst")]
+    pub l2_send_burst: u32,
+    /// Seconds in-flight transactions may take to resolve on SIGINT or SIGTERM
+    #[serde(default = "default_shutdown_timeout_secs")]
+    pub shutdown_timeout_secs: u64,
+    /// Nonces held in the in-memory pending queue
+    #[serde(default = "default_queue_capacity")]
+    pub queue_capacity: usize,
+    /// Monitor behavior when the pending queue is full: "block" or "spill_to_disk"
+    #[serde(default = "default_queue_overflow")]
+    pub queue_overflow: OverflowPolicy,
+    /// Nonces taken from the pending queue per submission batch
+    #[serde(default = "default_submit_batch_size")]
+    pub submit_batch_size: usize,
+    /// How many nonces each batch takes: "static" (`submit_batch_size`) or
+    /// "fee_aware" (from the L2 fee market and the backlog)
+    #[serde(default)]
+    pub batch_strategy: BatchStrategy,
+    /// Longest a fee-aware batch waits to fill while fees are high
+    #[serde(default = "default_batch_latency_target_ms")]
+    pub batch_latency_target_ms: u64,
+    /// Median prioritization fee (micro-lamports per compute unit) from which
+    /// fees count as high
+    #[serde(default = "default_batch_high_fee_micro_lamports")]
+    pub batch_high_fee_micro_lamports: u64,
+    /// Backlog up to which a fee-aware submitter relays nonce by nonce while fees are low
+    #[serde(default = "default_batch_small_backlog")]
+    pub batch_small_backlog: u64,
+    /// Seconds a fee price read is reused
+    #[serde(default = "default_batch_fee_refresh_secs")]
+    pub batch_fee_refresh_secs: u64,
+    /// Compute-unit price of relay transactions: "none", "static" or "dynamic"
+    #[serde(default)]
+    pub priority_fee_strategy: PriorityFeeStrategy,
+    /// Static compute-unit price in micro-lamports, and the dynamic floor
+    #[serde(default)]
+    pub compute_unit_price_micro_lamports: u64,
+    /// Highest dynamic compute-unit price in micro-lamports
+    #[serde(default = "default_compute_unit_price_max_micro_lamports")]
+    pub compute_unit_price_max_micro_lamports: u64,
+    /// Percentile of the recent prioritization fees a dynamic price pays
+    #[serde(default = "default_priority_fee_percentile")]
+    pub priority_fee_percentile: u8,
+    /// Seconds a dynamic price is reused
+    #[serde(default = "default_priority_fee_refresh_secs")]
+    pub priority_fee_refresh_secs: u64,
+    /// Compute units requested per relay transaction (the runtime default when unset)
+    #[serde(default)]
+    pub compute_unit_limit: Option<u32>,
+    /// Lets pending nonces accumulate this long and relays consecutive transfers
+    /// to the same recipient as one (0 disables coalescing); requires an L2
+    /// program that accepts a nonce past the expected one
+    #[serde(default)]
+    pub coalesce_window_ms: u64,
+    /// Largest summed amount of a coalesced transfer
+    #[serde(default)]
+    pub coalesce_max_amount: Option<u64>,
+    /// Order in which pending transfers are relayed: "nonce", "amount_desc" or
+    /// "oldest_first"; the latter two require an L2 program that accepts
+    /// out-of-order nonces
+    #[serde(default)]
+    pub priority: QueuePriority,
+    /// Pending nonces ranked per batch outside of "nonce" priority
+    #[serde(default = "default_priority_window")]
+    pub priority_window: usize,
+    /// Seconds after which a waiting transfer is relayed ahead of any priority
+    #[serde(default = "default_priority_max_wait_secs")]
+    pub priority_max_wait_secs: u64,
+    /// Pause before a confirmed transaction is read back to enrich its receipt
+    #[serde(default = "default_receipt_enrich_delay_ms")]
+    pub receipt_enrich_delay_ms: u64,
+    /// Reads of a confirmed transaction before its receipt is left for
+    /// `enrich-receipts` (0 disables enrichment)
+    #[serde(default = "default_receipt_enrich_attempts")]
+    pub receipt_enrich_attempts: u32,
+    /// Receipts waiting for enrichment at most; the oldest are left for
+    /// `enrich-receipts` when more confirm
+    #[serde(default = "default_receipt_enrich_queue_capacity")]
+    pub receipt_enrich_queue_capacity: usize,
+    /// Minimum pause between submission batches
+    #[serde(default)]
+    pub submit_interval_ms: u64,
+    /// Transfers relayed per minute across all sources (unlimited when unset)
+    #[serde(default)]
+    pub max_transfers_per_minute: Option<u64>,
+    /// Transfers relayed per minute by each source, unless the source sets its own
+    #[serde(default)]
+    pub max_transfers_per_minute_per_source: Option<u64>,
+    /// Transfers a limit lets through at once after an idle period
+    #[serde(default = "default_transfer_rate_burst")]
+    pub transfer_rate_burst: u64,
+    /// Pause before retrying a failed submission batch
+    #[serde(default = "default_retry_delay_ms")]
+    pub retry_delay_ms: u64,
+    /// Pause between re-sends of a transaction awaiting confirmation (0 never re-sends)
+    #[serde(default = "default_rebroadcast_interval_ms")]
+    pub rebroadcast_interval_ms: u64,
+    /// How long a transaction is re-sent before the relayer only waits for it
+    /// to land or expire
+    #[serde(default = "default_rebroadcast_max_secs")]
+    pub rebroadcast_max_secs: u64,
+    /// Wait before the first retry of a failed nonce, doubled per further failure
+    #[serde(default = "default_retry_backoff_initial_secs")]
+    pub retry_backoff_initial_secs: u64,
+    /// Longest wait between attempts of a failed nonce
+    #[serde(default = "default_retry_backoff_max_secs")]
+    pub retry_backoff_max_secs: u64,
+    /// Failed attempts after which a nonce is dead-lettered (0 retries forever)
+    #[serde(default = "default_max_nonce_attempts")]
+    pub max_nonce_attempts: u32,
+    /// Tolerated difference between the clocks of L1, L2 and the relayer when
+    /// checking transfer deadlines
+    #[serde(default = "default_expiry_skew_secs")]
+    pub expiry_skew_secs: u64,
+    /// Every failure of a transfer this close to its deadline is alerted
+    #[serde(default = "default_expiry_alert_secs")]
+    pub expiry_alert_secs: u64,
+    /// A message read again this long after its first read is dead-lettered
+    /// instead of relayed; 0 relays messages of any age
+    #[serde(default)]
+    pub message_ttl_secs: u64,
+    /// A transfer-info PDA missing this long is alerted
+    #[serde(default = "default_missing_pda_alert_secs")]
+    pub missing_pda_alert_secs: u64,
+    /// Reads of a PDA missing this long count as failed attempts; 0 waits
+    /// indefinitely
+    #[serde(default = "default_missing_pda_deadline_secs")]
+    pub missing_pda_deadline_secs: u64,
+    /// Receipts kept below the lowest pending nonce; older ones are pruned
+    #[serde(default)]
+    pub replay_window_nonces: Option<u64>,
+    /// Days receipts are kept; with both windows set a receipt is pruned once
+    /// it is outside both
+    #[serde(default)]
+    pub replay_window_days: Option<u64>,
+    /// Pause between prunes of receipts outside the replay window
+    #[serde(default = "default_replay_prune_interval_secs")]
+    pub replay_prune_interval_secs: u64,
+    /// Whether a nonce whose receipt may have been pruned counts as relayed:
+    /// "assume_processed" or "assume_unprocessed"
+    #[serde(default)]
+    pub outside_replay_window: OutsideWindowPolicy,
+    /// Consecutive failed batches that open the submission circuit breaker
+    #[serde(default = "default_breaker_failure_threshold")]
+    pub breaker_failure_threshold: u32,
+    /// How long the circuit breaker stays open before submissions resume
+    #[serde(default = "default_breaker_cooldown_secs")]
+    pub breaker_cooldown_secs: u64,
+    /// Write Prometheus metrics to this file every poll (node_exporter textfile collector)
+    #[serde(default)]
+    pub metrics_textfile: Option<String>,
+    /// Address to serve `GET /metrics` on, e.g. "0.0.0.0:9100" (no server when unset)
+    #[serde(default)]
+    pub metrics_listen: Option<String>,
+    /// Address to serve the admin API on, e.g. "127.0.0.1:9200" (no server when unset)
+    #[serde(default)]
+    pub admin_listen: Option<String>,
+    /// Bearer token required by the admin API's POST endpoints, which are
+    /// refused without one
+    #[serde(default)]
+    pub admin_token: Option<Redacted<String>>,
+    /// Where L1 messages are read from: "pda" (one PDA per nonce), "merkle" or
+    /// "program_accounts"
+    #[serde(default = "default_message_source")]
+    pub message_source: MessageSource,
+    /// Indexer serving merkle-batched messages and proofs (`GET {url}/messages/{nonce}`)
+    #[serde(default)]
+    pub merkle_indexer_url: Option<SecretUrl>,
+    /// Account-index service the watched account is read from before the L1
+    /// RPC (`[l1_account_cache]` section; none when unset)
+    #[serde(default)]
+    #[serde(alias = "source_account_cache")]
+    pub l1_account_cache: Option<AccountCacheConfig>,
+    /// Message account layout for `message_source = "program_accounts"`
+    #[serde(default)]
+    pub program_accounts: Option<ProgramAccountsConfig>,
+    /// Seconds between `getProgramAccounts` scans for transfer PDAs the
+    /// watched-account counter missed, in PDA mode (0 disables)
+    #[serde(default)]
+    pub pda_discovery_interval_secs: u64,
+    /// PDAs after the L2 nonce a discovery scan maps to nonces
+    #[serde(default = "default_pda_discovery_window")]
+    pub pda_discovery_window: u64,
+    /// Confirmation strategy of each transfer (`[[confirmation_rules]]`
+    /// tables, first match wins); every transaction is confirmed at the L2
+    /// client's commitment when empty
+    #[serde(default)]
+    pub confirmation_rules: Vec<ConfirmationRule>,
+    /// "fast" (default) or "safe", for transfers no confirmation rule matches
+    #[serde(default)]
+    pub confirmation_default_strategy: ConfirmationStrategy,
+    /// Handling of messages whose type id is not in the source's `message_types`:
+    /// "halt", "skip" (receipt without relaying; needs an L2 program that accepts
+    /// nonce gaps) or "dead_letter"
+    #[serde(default)]
+    pub unknown_message_type: UnknownTypePolicy,
+    /// Lamports below which a native transfer is dust (0: no check); token
+    /// transfers use their mint mapping's `dust_threshold`
+    #[serde(default)]
+    pub dust_threshold: u64,
+    /// Handling of dust: "skip" (receipt without relaying; needs an L2
+    /// program that accepts nonce gaps), "accumulate" (paid out once the
+    /// recipient's tally reaches the threshold) or "dead_letter"
+    #[serde(default)]
+    pub dust: DustPolicy,
+    /// Public keys whose ed25519 attestation of a transfer is accepted
+    #[serde(default)]
+    pub attestor_pubkeys: Vec<String>,
+    /// Reject transfers without an attestation signature; while false, unsigned
+    /// legacy messages are still relayed (signed ones are always verified)
+    #[serde(default)]
+    pub require_attestation: bool,
+    /// Native transfers above this many lamports are only relayed once
+    /// `required_approvals` approvers have signed them (disabled when unset)
+    #[serde(default)]
+    pub manual_approval_threshold: Option<u64>,
+    /// Public keys allowed to approve parked transfers
+    #[serde(default)]
+    pub approver_pubkeys: Vec<String>,
+    /// Distinct approvals a parked transfer needs
+    #[serde(default = "default_required_approvals")]
+    pub required_approvals: usize,
+    /// How long a parked transfer may wait before an alert is raised
+    #[serde(default = "default_approval_timeout_secs")]
+    pub approval_timeout_secs: u64,
+    /// Webhook (e.g. a Slack incoming webhook) notified of approval requests
+    #[serde(default)]
+    pub approval_webhook_url: Option<Redacted<String>>,
+    /// How long the first transfers to a recipient never seen before are held,
+    /// cancellable with `cancel` (disabled when unset)
+    #[serde(default)]
+    pub new_recipient_quiet_period_secs: Option<u64>,
+    /// Backlog drain time the autoscaling signal aims for (no signal when unset)
+    #[serde(default)]
+    pub scale_target_drain_secs: Option<u64>,
+    /// Most replicas the scaling signal recommends
+    #[serde(default = "default_scale_max_replicas")]
+    pub scale_max_replicas: u32,
+    /// Seconds between scaling signal updates
+    #[serde(default = "default_scale_interval_secs")]
+    pub scale_interval_secs: u64,
+    /// Autoscaler webhook that receives the signal whenever the recommendation changes
+    #[serde(default)]
+    pub scale_webhook_url: Option<Redacted<String>>,
+    /// State writes (and journal records) kept in memory while storage is
+    /// unavailable; 0 fails on the first storage error
+    #[serde(default = "default_storage_buffer_capacity")]
+    pub storage_buffer_capacity: usize,
+    /// Seconds between reconciliation snapshots (none when unset)
+    #[serde(default)]
+    pub snapshot_interval_secs: Option<u64>,
+    /// Days reconciliation snapshots are kept; 0 keeps them all
+    #[serde(default = "default_snapshot_retention_days")]
+    pub snapshot_retention_days: u64,
+    /// Distributed tracing export (`[tracing]` section)
+    #[serde(default)]
+    pub tracing: TracingConfig,
+    /// Log destination, format and levels (`[logging]` section)
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    /// Remote copy of every receipt (`[receipt_sink]` section; none when unset)
+    #[serde(default)]
+    pub receipt_sink: Option<ReceiptSinkConfig>,
+    /// Event per relayed transfer (`[event_stream]` section; none when unset)
+    #[serde(default)]
+    pub event_stream: Option<EventStreamConfig>,
+    /// Queue between the observer and submitter processes (`[intent_queue]`
+    /// section; none when unset)
+    #[serde(default)]
+    pub intent_queue: Option<IntentQueueConfig>,
+    /// Address lookup tables of the L2 transactions, sent as v0 when set
+    /// (`[address_lookup_tables]` section; legacy transactions when unset)
+    #[serde(default)]
+    pub address_lookup_tables: Option<AddressLookupTablesConfig>,
+    /// Closing of L2 receipt PDAs outside the retention window
+    /// (`[receipt_pdas]` section; never closed when unset)
+    #[serde(default)]
+    pub receipt_pdas: Option<ReceiptPdasConfig>,
+    /// Amount and destination guardrails (`[policy]` section; none when unset)
+    #[serde(default)]
+    pub policy: Option<TransferPolicy>,
+    /// Amount bounds and compute budgets, overridden per message type
+    /// (`[limits]` with `[limits.native]`, `[limits.token]` and `[limits.nft]`)
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    /// Profile the configuration was loaded with, if any
+    #[serde(skip)]
+    pub profile: Option<String>,
+    /// Bridges relayed side by side by this process (`[[bridges]]` tables,
+    /// each with a `name`), every one the shared values overlaid with its own
+    #[serde(skip_deserializing, skip_serializing_if = "Vec::is_empty")]
+    pub bridges: Vec<RelayerConfig>,
+    /// Name of the `[[bridges]]` entry this configuration belongs to
+    #[serde(skip)]
+    pub bridge: Option<String>,
+    /// Values resolved from `${VAR}` and `file:` references
+    #[serde(skip)]
+    secrets: Secrets,
+}
+
+/// Prints the configuration with secret fields masked and resolved secrets redacted
+impl fmt::Debug for RelayerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let json = serde_json::to_string(self).map_err(|_| fmt::Error)?;
+        write!(f, "RelayerConfig {}", self.redact(&json))
+    }
+}
+
+/// Syntax of a configuration file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ConfigFormat {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// Format implied by the file extension (.toml, .yaml/.yml, .json)
+    pub fn from_path(path: &Path) -> Result<Self> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => Ok(Self::Toml),
+            Some("yaml" | "yml") => Ok(Self::Yaml),
+            Some("json") => Ok(Self::Json),
+            _ => Err(Error::msg(format!(
+                "Cannot tell the format of {} from its extension; use .toml, .yaml, .yml or .json, or pass --config-format",
+                path.display()
+            ))),
+        }
+    }
+
+    fn file_format(self) -> FileFormat {
+        match self {
+            Self::Toml => FileFormat::Toml,
+            Self::Yaml => FileFormat::Yaml,
+            Self::Json => FileFormat::Json,
+        }
+    }
+}
+
+/// One L1 source program with its own nonce sequence
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SourceConfig {
+    /// Namespaces the source's state, metrics and alerts
+    pub id: String,
+    #[serde(alias = "source_program_id")]
+    pub l1_program_id: SourceProgramId,
+    pub watched_account: SourceAccount,
+    /// L2 account tracking the nonces relayed for this source
+    pub nonce_account: DestinationAccount,
+    /// Transfer-info PDA derivation (`[sources.seeds]`)
+    #[serde(default)]
+    pub seeds: SeedScheme,
+    /// Raw message-type ids mapped to message types, e.g. `0 = "native"`; when
+    /// set, messages of other mapped types are dead-lettered and unmapped ids
+    /// are handled by `unknown_message_type`
+    #[serde(default)]
+    pub message_types: BTreeMap<String, MessageType>,
+    /// Transfers relayed per minute by this source, overriding
+    /// `max_transfers_per_minute_per_source`
+    #[serde(default)]
+    pub max_transfers_per_minute: Option<u64>,
+    /// L1 account listing the nonces users cancelled
+    /// (`[sources.cancellation_registry]`; none when unset)
+    #[serde(default)]
+    pub cancellation_registry: Option<CancellationRegistryConfig>,
+    /// Discriminators checked before the source's accounts are decoded
+    /// (`[sources.discriminators]`; nothing is checked when unset)
+    #[serde(default)]
+    pub discriminators: AccountDiscriminators,
+    /// System nonce account on L2, authorized to the signer, whose nonce
+    /// replaces the recent blockhash of relay transactions
+    #[serde(default)]
+    pub durable_nonce_account: Option<DestinationAccount>,
+    /// Byte offsets of the transfer-info fields, for an L1 program whose
+    /// PDAs are not in the Borsh layout (`[sources.transfer_info_layout]`)
+    #[serde(default)]
+    pub transfer_info_layout: Option<TransferInfoLayout>,
+}
+
+/// L2 accounts in which the program marks the nonces it processed
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProcessedNoncesConfig {
+    /// "pda" (one marker account per nonce) or "bitmap" (one account)
+    pub layout: ProcessedLayout,
+    /// First seed of the marker PDAs
+    #[serde(default = "default_processed_seed_prefix")]
+    pub seed_prefix: String,
+    /// The bitmap account, for the `bitmap` layout
+    #[serde(default)]
+    pub account: Option<DestinationAccount>,
+    /// Bytes before the bitmap, e.g. an 8-byte Anchor discriminator
+    #[serde(default = "default_registry_offset")]
+    pub offset: usize,
+}
+
+/// L1 account in which users cancel pending transfers by nonce
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CancellationRegistryConfig {
+    pub account: SourceAccount,
+    /// "list" (u32 LE count, then u64 LE nonces) or "bitmap" (u64 LE first
+    /// nonce, u32 LE byte count, then one bit per nonce)
+    #[serde(default)]
+    pub layout: RegistryLayout,
+    /// Bytes before the list or bitmap, e.g. an 8-byte Anchor discriminator
+    #[serde(default = "default_registry_offset")]
+    pub offset: usize,
+}
+
+/// Program owning off-curve recipients, e.g. escrow vaults
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PdaRecipientProgram {
+    pub program_id: String,
+    /// Discriminator of the program's deposit instruction: 8 bytes in hex
+    pub deposit_discriminator: String,
+}
+
+/// Role of an account of the `relay_message` instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountRole {
+    NonceAccount,
+    Signer,
+    Recipient,
+    Fixed,
+    Pda,
+}
+
+/// One account of the `relay_message` instruction
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct InstructionAccountConfig {
+    pub role: AccountRole,
+    /// Address of a `fixed` account
+    #[serde(default)]
+    pub pubkey: Option<String>,
+    /// Seeds of a `pda` account: literal strings, `{nonce}`, `{recipient}` or `{signer}`
+    #[serde(default)]
+    pub seeds: Vec<String>,
+    /// Program deriving a `pda` account (default: the L2 program)
+    #[serde(default)]
+    pub program_id: Option<String>,
+    #[serde(default)]
+    pub writable: bool,
+    #[serde(default)]
+    pub signer: bool,
+}
+
+/// OpenTelemetry trace export settings
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TracingConfig {
+    /// OTLP/HTTP traces endpoint, e.g. `http://localhost:4318/v1/traces`; export is disabled when unset
+    #[serde(default)]
+    pub otlp_endpoint: Option<SecretUrl>,
+    /// `service.name` reported with every span
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+    /// Fraction of nonce traces exported, between 0.0 and 1.0
+    #[serde(default = "default_sample_ratio")]
+    pub sample_ratio: f64,
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        Self {
+            otlp_endpoint: None,
+            service_name: default_service_name(),
+            sample_ratio: default_sample_ratio(),
+        }
+    }
+}
+
+/// Where log lines are written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogDestination {
+    #[default]
+    Stderr,
+    /// The file at `logging.path`
+    File,
+    /// Stderr and the file
+    Both,
+}
+
+/// How log lines are formatted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+/// Log output settings
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LoggingConfig {
+    /// "stderr", "file" or "both"
+    #[serde(default)]
+    pub destination: LogDestination,
+    /// Log file, reopened on SIGUSR1; required unless the destination is stderr
+    #[serde(default)]
+    pub path: Option<String>,
+    /// "text" or "json"
+    #[serde(default)]
+    pub format: LogFormat,
+    /// Level of every module not in `modules`: off, error, warn, info, debug or trace
+    #[serde(default = "default_log_level")]
+    pub level: String,
+    /// Levels of single modules, e.g. `"sol_bridge_relayer::pipeline" = "debug"`
+    #[serde(default)]
+    pub modules: BTreeMap<String, String>,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            destination: LogDestination::default(),
+            path: None,
+            format: LogFormat::default(),
+            level: default_log_level(),
+            modules: BTreeMap::new(),
+        }
+    }
+}
+
+/// Where the receipt sink ships receipts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SinkKind {
+    Http,
+    S3,
+}
+
+/// Where relay events are published
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventSinkKind {
+    Webhook,
+    Nats,
+    Kafka,
+}
+
+/// Relay event publishing settings
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EventStreamConfig {
+    /// "webhook" (a `POST` per batch), "nats" (a `PUB` per event) or "kafka"
+    /// (a Kafka REST Proxy)
+    pub kind: EventSinkKind,
+    /// Webhook URL, `nats://[user:password@]host[:port]` or the REST Proxy's
+    /// base URL
+    pub url: SecretUrl,
+    /// NATS subject or Kafka topic
+    #[serde(default = "default_event_subject")]
+    pub subject: String,
+    /// Bearer token of the webhook and the REST Proxy, or NATS `auth_token`
+    #[serde(default)]
+    pub token: Option<Redacted<String>>,
+    /// Events published per request
+    #[serde(default = "default_sink_batch_size")]
+    pub batch_size: usize,
+    /// Pause between publications of the outbox
+    #[serde(default = "default_event_flush_interval_ms")]
+    pub flush_interval_ms: u64,
+    /// Longest pause before a failed publication is retried
+    #[serde(default = "default_event_max_backoff_secs")]
+    pub max_backoff_secs: u64,
+}
+
+/// Part of the relay a process runs (`process_role`, `run --role`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum ProcessRole {
+    /// Watches L1 and relays to L2
+    #[default]
+    Relayer,
+    /// Watches L1 and writes signed intents to the intent queue; holds no
+    /// L2 key
+    Observer,
+    /// Relays the intents of the intent queue; never reads L1
+    Submitter,
+}
+
+/// Where intents are queued between the roles
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IntentQueueKind {
+    File,
+    Redis,
+}
+
+/// Intent queue between the observer and submitter processes
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IntentQueueConfig {
+    /// "file" (a directory per source) or "redis" (a list per source)
+    pub kind: IntentQueueKind,
+    /// Directory of `kind = "file"`, shared by both processes
+    #[serde(default)]
+    pub path: Option<String>,
+    /// `redis://[:password@]host[:port][/db]` of `kind = "redis"`
+    #[serde(default)]
+    pub url: Option<SecretUrl>,
+    /// Prefix of the Redis lists, followed by `:<source id>`
+    #[serde(default = "default_intent_key")]
+    pub key: String,
+    /// Keypair the observer signs intents with
+    #[serde(default)]
+    pub observer_keypair_path: Option<String>,
+    /// Public key (base58) the submitter accepts intents from
+    #[serde(default)]
+    pub observer_pubkey: Option<String>,
+    /// Pause between the observer's L1 reads, and between the submitter's
+    /// reads of the queue
+    #[serde(default = "default_intent_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+}
+
+/// Address lookup tables of v0 L2 transactions
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AddressLookupTablesConfig {
+    /// Existing tables (base58) that transactions are compiled against
+    #[serde(default)]
+    pub tables: Vec<String>,
+    /// Whether the relayer creates a table of its own and extends it with
+    /// the accounts its transactions keep using
+    #[serde(default)]
+    pub auto_extend: bool,
+    /// Sent transactions an account must appear in before it is added
+    #[serde(default = "default_lookup_table_min_uses")]
+    pub min_uses: u32,
+    /// Pause between reloads (and extensions) of the tables
+    #[serde(default = "default_lookup_table_refresh_secs")]
+    pub refresh_secs: u64,
+}
+
+/// Closing of the L2 program's per-nonce receipt PDAs
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReceiptPdasConfig {
+    /// Account (base58) the rent of closed receipt PDAs is refunded to
+    pub collector: String,
+    /// Seed of the receipt PDA, followed by the nonce (u64 LE)
+    #[serde(default = "default_receipt_pda_seed")]
+    pub seed: String,
+    /// Days a receipt PDA is kept after its relay, on top of the replay window
+    #[serde(default = "default_receipt_pda_retention_days")]
+    pub retention_days: u64,
+    /// Close instructions per transaction
+    #[serde(default = "default_receipt_pda_batch_size")]
+    pub batch_size: usize,
+    /// Pause between closing runs of `run`; 0 leaves closing to `close-receipts`
+    #[serde(default)]
+    pub close_interval_secs: u64,
+}
+
+/// Account-index service in front of the L1 RPC
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AccountCacheConfig {
+    /// Base URL; an account is read from `GET {url}/accounts/{pubkey}`
+    pub url: SecretUrl,
+    /// Bearer token sent to the service
+    #[serde(default)]
+    pub token: Option<Redacted<String>>,
+    /// Slots a cached account may trail the L1 RPC before the RPC is read instead
+    #[serde(default = "default_account_cache_max_staleness_slots")]
+    pub max_staleness_slots: u64,
+    /// How often the RPC's slot is read to judge the cache's freshness
+    #[serde(default = "default_account_cache_slot_check_secs")]
+    pub slot_check_secs: u64,
+    /// Timeout of a cache request
+    #[serde(default = "default_account_cache_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+/// Receipt shipping settings
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReceiptSinkConfig {
+    /// "http" (a `POST` per batch) or "s3" (hourly JSON-lines objects)
+    pub kind: SinkKind,
+    /// HTTP endpoint, or the S3 endpoint, e.g. `https://s3.eu-west-1.amazonaws.com`
+    pub url: SecretUrl,
+    /// Bearer token sent to the HTTP endpoint
+    #[serde(default)]
+    pub token: Option<Redacted<String>>,
+    #[serde(default)]
+    pub bucket: Option<String>,
+    /// Key prefix of the S3 objects
+    #[serde(default = "default_sink_prefix")]
+    pub prefix: String,
+    #[serde(default = "default_sink_region")]
+    pub region: String,
+    #[serde(default)]
+    pub access_key_id: Option<Redacted<String>>,
+    #[serde(default)]
+    pub secret_access_key: Option<Redacted<String>>,
+    /// Receipts shipped per request
+    #[serde(default = "default_sink_batch_size")]
+    pub batch_size: usize,
+    /// Pause between shipments of the queue
+    #[serde(default = "default_sink_flush_interval_secs")]
+    pub flush_interval_secs: u64,
+    /// Receipts waiting at most; the oldest are shipped by a catch-up when more are saved
+    #[serde(default = "default_sink_queue_capacity")]
+    pub queue_capacity: usize,
+    /// Longest pause before a failed shipment is retried
+    #[serde(default = "default_sink_max_backoff_secs")]
+    pub max_backoff_secs: u64,
+}
+
+fn default_lookup_table_min_uses() -> u32 {
+    3
+}
+
+fn default_lookup_table_refresh_secs() -> u64 {
+    60
+}
+
+fn default_receipt_pda_seed() -> String {
+    "receipt".to_string()
+}
+
+fn default_receipt_pda_retention_days() -> u64 {
+    30
+}
+
+fn default_receipt_pda_batch_size() -> usize {
+    10
+}
+
+fn default_intent_key() -> String {
+    "relayer:intents".to_string()
+}
+
+fn default_intent_poll_interval_ms() -> u64 {
+    1000
+}
+
+fn default_event_subject() -> String {
+    "bridge.relayed".to_string()
+}
+
+fn default_event_flush_interval_ms() -> u64 {
+    1000
+}
+
+fn default_event_max_backoff_secs() -> u64 {
+    60
+}
+
+fn default_dev_airdrop_threshold() -> u64 {
+    1_000_000_000
+}
+
+fn default_dev_airdrop_amount() -> u64 {
+    2_000_000_000
+}
+
+fn default_journal_max_bytes() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_journal_retain_files() -> usize {
+    5
+}
+
+/// `path` with a leading `~` replaced by the home directory
+fn endpoints<'a>(
+    field: &str,
+    url: &'a SecretUrl,
+    fallbacks_field: &str,
+    fallbacks: &'a [SecretUrl],
+) -> Vec<(String, &'a SecretUrl)> {
+    let fallbacks = fallbacks
+        .iter()
+        .enumerate()
+        .map(|(index, url)| (format!("{}[{}]", fallbacks_field, index), url));
+    std::iter::once((field.to_string(), url))
+        .chain(fallbacks)
+        .collect()
+}
+
+/// The `[[mint_mappings]]` tables of `mint_mappings_file`
+fn load_mint_mappings(path: &Path) -> Result<Vec<MintMapping>> {
+    #[derive(Deserialize)]
+    struct MintMappingsFile {
+        #[serde(default)]
+        mint_mappings: Vec<MintMapping>,
+    }
+
+    let format = ConfigFormat::from_path(path)?;
+    let text = std::fs::read_to_string(path).map_err(|e| {
+        Error::msg(format!(
+            "Failed to read mint_mappings_file {}: {}",
+            path.display(),
+            e
+        ))
+    })?;
+    let text = match format {
+        ConfigFormat::Yaml => quote_yaml_keys(&text)?,
+        ConfigFormat::Toml | ConfigFormat::Json => text,
+    };
+    let file: MintMappingsFile = config::Config::builder()
+        .add_source(config::File::from_str(&text, format.file_format()))
+        .build()?
+        .try_deserialize()
+        .map_err(|e| Error::msg(format!("mint_mappings_file {}: {}", path.display(), e)))?;
+    Ok(file.mint_mappings)
+}
+
+fn expand_home(path: &Redacted<String>) -> Result<Redacted<String>> {
+    if !path.expose().starts_with('~') {
+        return Ok(path.clone());
+    }
+    let home =
+        env::var("HOME").map_err(|_| Error::msg("Failed to get HOME environment variable"))?;
+    Ok(Redacted::new(path.expose().replacen('~', &home, 1)))
+}
+
+fn default_l2_pause_flag_offset() -> usize {
+    16
+}
+
+fn default_l2_pause_check_interval_secs() -> u64 {
+    10
+}
+
+fn default_l2_pause_recheck_batch_size() -> u64 {
+    10
+}
+
+fn default_l2_health_stall_secs() -> u64 {
+    30
+}
+
+fn default_l2_health_backoff_secs() -> u64 {
+    2
+}
+
+fn default_l2_health_max_backoff_secs() -> u64 {
+    60
+}
+
+fn default_relay_sender() -> bool {
+    true
+}
+
+fn default_warmup_transaction() -> bool {
+    true
+}
+
+fn default_l1_lag_quarantine_slots() -> u64 {
+    50
+}
+
+fn default_l2_limits_ttl_secs() -> u64 {
+    5
+}
+
+fn default_scale_max_replicas() -> u32 {
+    4
+}
+
+fn default_scale_interval_secs() -> u64 {
+    15
+}
+
+fn default_storage_buffer_capacity() -> usize {
+    1000
+}
+
+fn default_snapshot_retention_days() -> u64 {
+    90
+}
+
+fn default_l2_nonce_account_min_size() -> usize {
+    24
+}
+
+fn default_l2_account_check_interval_secs() -> u64 {
+    300
+}
+
+fn default_required_approvals() -> usize {
+    1
+}
+
+fn default_shutdown_timeout_secs() -> u64 {
+    30
+}
+
+fn default_approval_timeout_secs() -> u64 {
+    3600
+}
+
+fn default_audit_report_path() -> String {
+    "audit-report.json".to_string()
+}
+
+fn default_crash_report_path() -> String {
+    "crash-report.json".to_string()
+}
+
+fn default_poll_interval_min_ms() -> u64 {
+    1_000
+}
+
+fn default_poll_interval_max_ms() -> u64 {
+    10_000
+}
+
+fn default_poll_idle_after_secs() -> u64 {
+    60
+}
+
+fn default_poll_suspend_backlog() -> u64 {
+    1_000
+}
+
+fn default_alert_consecutive_read_failures() -> u32 {
+    10
+}
+
+fn default_watched_account_missing_poll_secs() -> u64 {
+    10
+}
+
+fn default_watched_account_missing_alert_secs() -> u64 {
+    300
+}
+
+fn default_max_expected_jump() -> u64 {
+    10_000
+}
+
+fn default_anomaly_interval_secs() -> u64 {
+    3_600
+}
+
+fn default_pipeline_channel_capacity() -> usize {
+    64
+}
+
+fn default_fetch_batch_size() -> usize {
+    99
+}
+
+fn default_send_concurrency() -> usize {
+    1
+}
+
+fn default_durable_nonce_confirmation_blocks() -> u64 {
+    1500
+}
+
+fn default_durable_nonce_fallback() -> bool {
+    true
+}
+
+fn default_pack_max_transfers() -> usize {
+    1
+}
+
+fn default_remote_signer_timeout_ms() -> u64 {
+    5000
+}
+
+fn default_keypair_passphrase_env() -> String {
+    "RELAYER_KEYPAIR_PASSPHRASE".to_string()
+}
+
+fn default_queue_capacity() -> usize {
+    10_000
+}
+
+fn default_queue_overflow() -> OverflowPolicy {
+    OverflowPolicy::Block
+}
+
+fn default_priority_window() -> usize {
+    1_000
+}
+
+fn default_priority_max_wait_secs() -> u64 {
+    300
+}
+
+fn default_submit_batch_size() -> usize {
+    100
+}
+
+fn default_batch_latency_target_ms() -> u64 {
+    2_000
+}
+
+fn default_batch_high_fee_micro_lamports() -> u64 {
+    10_000
+}
+
+fn default_batch_small_backlog() -> u64 {
+    4
+}
+
+fn default_batch_fee_refresh_secs() -> u64 {
+    10
+}
+
+fn default_compute_unit_price_max_micro_lamports() -> u64 {
+    50_000
+}
+
+fn default_priority_fee_percentile() -> u8 {
+    50
+}
+
+fn default_priority_fee_refresh_secs() -> u64 {
+    10
+}
+
+fn default_retry_delay_ms() -> u64 {
+    1_000
+}
+
+fn default_expiry_skew_secs() -> u64 {
+    30
+}
+
+fn default_expiry_alert_secs() -> u64 {
+    600
+}
+
+fn default_missing_pda_alert_secs() -> u64 {
+    60
+}
+
+fn default_missing_pda_deadline_secs() -> u64 {
+    600
+}
+
+fn default_rebroadcast_interval_ms() -> u64 {
+    1_000
+}
+
+fn default_rebroadcast_max_secs() -> u64 {
+    60
+}
+
+fn default_retry_backoff_initial_secs() -> u64 {
+    1
+}
+
+fn default_retry_backoff_max_secs() -> u64 {
+    300
+}
+
+fn default_max_nonce_attempts() -> u32 {
+    10
+}
+
+fn default_replay_prune_interval_secs() -> u64 {
+    3600
+}
+
+fn default_breaker_failure_threshold() -> u32 {
+    5
+}
+
+fn default_breaker_cooldown_secs() -> u64 {
+    60
+}
+
+fn default_rpc_failover_cooldown_secs() -> u64 {
+    30
+}
+
+fn default_transfer_rate_burst() -> u64 {
+    1
+}
+
+fn default_l2_send_burst() -> u32 {
+    1
+}
+
+fn default_balance_check_interval_secs() -> u64 {
+    60
+}
+
+fn default_top_up_cooldown_secs() -> u64 {
+    600
+}
+
+fn default_receipt_enrich_delay_ms() -> u64 {
+    2000
+}
+
+fn default_receipt_enrich_attempts() -> u32 {
+    5
+}
+
+fn default_receipt_enrich_queue_capacity() -> usize {
+    10_000
+}
+
+fn default_standby_interval_secs() -> u64 {
+    10
+}
+
+fn default_standby_ready_max_lag() -> u64 {
+    10
+}
+
+fn default_standby_mirror_rps() -> u32 {
+    5
+}
+
+fn default_pda_discovery_window() -> u64 {
+    1000
+}
+
+fn default_message_source() -> MessageSource {
+    MessageSource::Pda
+}
+
+fn default_sink_prefix() -> String {
+    "receipts/".to_string()
+}
+
+fn default_sink_region() -> String {
+    "us-east-1".to_string()
+}
+
+fn default_sink_batch_size() -> usize {
+    100
+}
+
+fn default_sink_flush_interval_secs() -> u64 {
+    10
+}
+
+fn default_sink_queue_capacity() -> usize {
+    10_000
+}
+
+fn default_sink_max_backoff_secs() -> u64 {
+    300
+}
+
+fn default_account_cache_max_staleness_slots() -> u64 {
+    10
+}
+
+fn default_account_cache_slot_check_secs() -> u64 {
+    10
+}
+
+fn default_account_cache_timeout_ms() -> u64 {
+    500
+}
+
+fn default_registry_offset() -> usize {
+    8
+}
+
+fn default_processed_seed_prefix() -> String {
+    "processed".to_string()
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+fn default_service_name() -> String {
+    "sol-bridge-relayer".to_string()
+}
+
+fn default_sample_ratio() -> f64 {
+    1.0
+}
+
+fn default_state_dir() -> String {
+    "relayer-state".to_string()
 }
 
 impl RelayerConfig {
-    /// Loads configuration from a TOML file
-    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+    /// Loads configuration from a file (`-` reads stdin), merging `[default]`
+    /// with the `[profiles.<profile>]` section when the file defines profiles.
+    /// The format follows the extension unless `format` is given.
+    pub fn load<P: AsRef<Path>>(
+        path: P,
+        format: Option<ConfigFormat>,
+        profile: Option<&str>,
+    ) -> Result<Self> {
         let config_path = path.as_ref();
-        if !config_path.exists() {
-            return Err(Error::msg(format!(
-                "Configuration file not found: {}",
-                config_path.display()
-            )));
-        }
+        let (text, format) = if config_path == Path::new("-") {
+            let format = format.ok_or_else(|| {
+                Error::msg("Reading the config from stdin requires --config-format")
+            })?;
+            let mut text = String::new();
+            std::io::stdin()
+                .read_to_string(&mut text)
+                .map_err(|e| Error::msg(format!("Failed to read config from stdin: {}", e)))?;
+            (text, format)
+        } else {
+            if !config_path.exists() {
+                return Err(Error::msg(format!(
+                    "Configuration file not found: {}",
+                    config_path.display()
+                )));
+            }
+            let format = match format {
+                Some(format) => format,
+                None => ConfigFormat::from_path(config_path)?,
+            };
+            let text = std::fs::read_to_string(config_path).map_err(|e| {
+                Error::msg(format!(
+                    "Failed to read configuration file {}: {}",
+                    config_path.display(),
+                    e
+                ))
+            })?;
+            (text, format)
+        };
+        let text = match format {
+            ConfigFormat::Yaml => quote_yaml_keys(&text)?,
+            ConfigFormat::Toml | ConfigFormat::Json => text,
+        };
 
         let settings = config::Config::builder()
-            .add_source(config::File::with_name(config_path.to_str().unwrap()))
+            .add_source(config::File::from_str(&text, format.file_format()))
             .build()?;
 
-        let mut config: RelayerConfig = settings.try_deserialize()?;
+        let defaults = settings.get_table("default").ok();
+        let profiles = settings.get_table("profiles").ok();
+        let (mut base, mut overrides) = if defaults.is_none() && profiles.is_none() {
+            if let Some(profile) = profile {
+                return Err(Error::msg(format!(
+                    "Unknown profile {}: {} defines no profiles",
+                    profile,
+                    config_path.display()
+                )));
+            }
+            (settings.try_deserialize()?, Map::new())
+        } else {
+            (
+                defaults.unwrap_or_default(),
+                Self::select_profile(profiles, profile)?,
+            )
+        };
+        let bridges = take_bridges(&mut base, &mut overrides)?;
+        if bridges.is_empty() {
+            return Self::merge(base, overrides, None, profile)?.finish(profile);
+        }
+
+        let bridges = bridges
+            .into_iter()
+            .map(|(name, table)| {
+                let mut bridge = Self::merge(
+                    base.clone(),
+                    overrides.clone(),
+                    Some((&name, table)),
+                    profile,
+                )?
+                .finish(profile)?;
+                bridge.bridge = Some(name);
+                Ok(bridge)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        // 进程级设置（日志、追踪、指标文件）取自第一个 bridge
+        let mut config = bridges[0].clone();
+        config.bridge = None;
+        for bridge in &bridges {
+            config.secrets.extend(&bridge.secrets);
+        }
+        config.bridges = bridges;
+        Ok(config)
+    }
+
+    /// Applies the profile name and checks and expands the loaded values
+    fn finish(mut self, profile: Option<&str>) -> Result<Self> {
+        self.profile = profile.map(str::to_string);
+        self.validate_urls()?;
+        self.validate_settings()?;
+        if let Some(path) = &self.mint_mappings_file {
+            let mappings = load_mint_mappings(Path::new(path))?;
+            self.mint_mappings.extend(mappings);
+        }
+        self.wallet_path = expand_home(&self.wallet_path)?;
+        if let Some(path) = &self.secondary_wallet_path {
+            self.secondary_wallet_path = Some(expand_home(path)?);
+        }
+        Ok(self)
+    }
 
-        if config.wallet_path.starts_with('~') {
-            let home = env::var("HOME")
-                .map_err(|_| Error::msg("Failed to get HOME environment variable"))?;
-            config.wallet_path = config.wallet_path.replace('~', &home);
+    /// The configuration of the `[[bridges]]` entry `name`
+    pub fn bridge(&self, name: &str) -> Result<RelayerConfig> {
+        if let Some(bridge) = self
+            .bridges
+            .iter()
+            .find(|bridge| bridge.bridge.as_deref() == Some(name))
+        {
+            return Ok(bridge.clone());
         }
+        Err(Error::msg(format!(
+            "Unknown bridge {} (configured: {})",
+            name,
+            if self.bridges.is_empty() {
+                "none".to_string()
+            } else {
+                self.bridge_names().join(", ")
+            }
+        )))
+    }
+
+    /// Names of the `[[bridges]]` entries, in order
+    pub fn bridge_names(&self) -> Vec<&str> {
+        self.bridges
+            .iter()
+            .filter_map(|bridge| bridge.bridge.as_deref())
+            .collect()
+    }
+
+    /// The `[profiles.<profile>]` table, or nothing without a profile
+    fn select_profile(
+        profiles: Option<Map<String, Value>>,
+        profile: Option<&str>,
+    ) -> Result<Map<String, Value>> {
+        let Some(name) = profile else {
+            return Ok(Map::new());
+        };
+        let mut profiles = profiles.unwrap_or_default();
+        let Some(table) = profiles.remove(name) else {
+            let mut available: Vec<_> = profiles.keys().map(String::as_str).collect();
+            available.sort_unstable();
+            return Err(Error::msg(format!(
+                "Unknown profile {} (available: {})",
+                name,
+                if available.is_empty() {
+                    "none".to_string()
+                } else {
+                    available.join(", ")
+                }
+            )));
+        };
+        table
+            .into_table()
+            .map_err(|e| Error::msg(format!("Invalid [profiles.{}]: {}", name, e)))
+    }
 
+    /// Overlays the selected profile's values on the base values, and a
+    /// bridge's values on both, resolving environment and secret references
+    /// on the way
+    fn merge(
+        base: Map<String, Value>,
+        overrides: Map<String, Value>,
+        bridge: Option<(&str, Map<String, Value>)>,
+        profile: Option<&str>,
+    ) -> Result<Self> {
+        let mut base_values = Vec::new();
+        flatten_table("", base, &mut base_values);
+        let mut profile_values = Vec::new();
+        flatten_table("", overrides, &mut profile_values);
+        let mut bridge_values = Vec::new();
+        if let Some((_, table)) = bridge.clone() {
+            flatten_table("", table, &mut bridge_values);
+        }
+
+        let mut secrets = Secrets::default();
+        let mut builder = config::Config::builder();
+        for (key, value) in base_values
+            .iter_mut()
+            .chain(profile_values.iter_mut())
+            .chain(bridge_values.iter_mut())
+        {
+            secrets.resolve(key, value)?;
+            builder = builder.set_override(key.as_str(), value.clone())?;
+        }
+
+        let mut config: Self = builder.build()?.try_deserialize().map_err(|e| {
+            let key = match &e {
+                ConfigError::Type { key: Some(key), .. } => Some(key),
+                _ => None,
+            };
+            if let Some((name, _)) = bridge {
+                if key.is_none_or(|key| bridge_values.iter().any(|(k, _)| k == key)) {
+                    return Error::msg(format!("Invalid configuration of bridge {}: {}", name, e));
+                }
+            }
+            let Some(name) = profile else {
+                return Error::msg(format!("Invalid configuration: {}", e));
+            };
+            // 指出出错的值来自哪个 section
+            let from_profile = key.is_none_or(|key| profile_values.iter().any(|(k, _)| k == key));
+            let origin = if from_profile {
+                format!("[profiles.{}]", name)
+            } else {
+                "[default]".to_string()
+            };
+            Error::msg(format!("Invalid configuration in {}: {}", origin, e))
+        })?;
+        config.secrets = secrets;
         Ok(config)
     }
+
+    /// `text` with resolved environment and secret values redacted, for output
+    pub fn redact(&self, text: &str) -> String {
+        self.secrets.redact(text)
+    }
+
+    /// Whether transfer-info PDAs carry attestations: `attestor_pubkeys` or
+    /// `require_attestation` is set
+    pub fn reads_attestations(&self) -> bool {
+        !self.attestor_pubkeys.is_empty() || self.require_attestation
+    }
+
+    /// Endpoint of the watched chain: `l1_url`, or `l2_url` for withdrawals
+    pub fn source_url(&self) -> &SecretUrl {
+        match self.direction {
+            Direction::L1ToL2 => &self.l1_url,
+            Direction::L2ToL1 => &self.l2_url,
+        }
+    }
+
+    /// Endpoint transactions are sent to: `l2_url`, or `l1_url` for withdrawals
+    pub fn destination_url(&self) -> &SecretUrl {
+        match self.direction {
+            Direction::L1ToL2 => &self.l2_url,
+            Direction::L2ToL1 => &self.l1_url,
+        }
+    }
+
+    /// `l1_url` and `l1_urls`, named by their config field
+    pub fn l1_endpoints(&self) -> Vec<(String, &SecretUrl)> {
+        endpoints("l1_url", &self.l1_url, "l1_urls", &self.l1_urls)
+    }
+
+    /// `l2_url` and `l2_urls`, named by their config field
+    pub fn l2_endpoints(&self) -> Vec<(String, &SecretUrl)> {
+        endpoints("l2_url", &self.l2_url, "l2_urls", &self.l2_urls)
+    }
+
+    /// Endpoints of the watched chain, `source_url` first
+    pub fn source_endpoints(&self) -> Vec<(String, &SecretUrl)> {
+        match self.direction {
+            Direction::L1ToL2 => self.l1_endpoints(),
+            Direction::L2ToL1 => self.l2_endpoints(),
+        }
+    }
+
+    /// Endpoints transactions are sent to, `destination_url` first
+    pub fn destination_endpoints(&self) -> Vec<(String, &SecretUrl)> {
+        match self.direction {
+            Direction::L1ToL2 => self.l2_endpoints(),
+            Direction::L2ToL1 => self.l1_endpoints(),
+        }
+    }
+
+    /// Checks settings that only work together
+    fn validate_settings(&self) -> Result<()> {
+        // 可选子系统只在启用对应 feature 的构建中可用
+        for (set, setting, feature, built) in [
+            (
+                self.admin_listen.is_some(),
+                "admin_listen",
+                "admin",
+                cfg!(feature = "admin"),
+            ),
+            (
+                self.tracing.otlp_endpoint.is_some(),
+                "tracing.otlp_endpoint",
+                "telemetry",
+                cfg!(feature = "telemetry"),
+            ),
+            (
+                !self.alert_webhooks.is_empty(),
+                "alert_webhooks",
+                "webhooks",
+                cfg!(feature = "webhooks"),
+            ),
+            (
+                self.approval_webhook_url.is_some(),
+                "approval_webhook_url",
+                "webhooks",
+                cfg!(feature = "webhooks"),
+            ),
+            (
+                self.scale_webhook_url.is_some(),
+                "scale_webhook_url",
+                "webhooks",
+                cfg!(feature = "webhooks"),
+            ),
+        ] {
+            if set && !built {
+                return Err(anyhow::anyhow!(
+                    "{} requires a build with --features {}",
+                    setting,
+                    feature
+                ));
+            }
+        }
+        // v1 数据只有 nonce，L2 无法得知合并了哪些 nonce
+        if self.coalesce_window_ms > 0 && self.l2_instruction_version == InstructionVersion::V1 {
+            return Err(Error::msg(
+                "coalesce_window_ms requires l2_instruction_version 2 or later, which encodes the first coalesced nonce",
+            ));
+        }
+        if self.max_concurrent_submissions == Some(0) {
+            return Err(Error::msg(
+                "max_concurrent_submissions must be at least 1; leave it unset for no limit",
+            ));
+        }
+        self.limits.validate()?;
+        Ok(())
+    }
+
+    /// Checks every endpoint URL; errors name the field, not the URL
+    fn validate_urls(&self) -> Result<()> {
+        let mut urls = self.l1_endpoints();
+        urls.extend(self.l2_endpoints());
+        urls.extend(
+            self.l1_verify_urls
+                .iter()
+                .enumerate()
+                .map(|(index, url)| (format!("l1_verify_urls[{}]", index), url)),
+        );
+        urls.extend(
+            self.l1_ws_url
+                .iter()
+                .map(|url| ("l1_ws_url".to_string(), url)),
+        );
+        urls.extend(
+            self.l2_health_reference_url
+                .iter()
+                .map(|url| ("l2_health_reference_url".to_string(), url)),
+        );
+        urls.extend(
+            self.merkle_indexer_url
+                .iter()
+                .map(|url| ("merkle_indexer_url".to_string(), url)),
+        );
+        urls.extend(
+            self.remote_signer_url
+                .iter()
+                .map(|url| ("remote_signer_url".to_string(), url)),
+        );
+        urls.extend(
+            self.l1_account_cache
+                .iter()
+                .map(|cache| ("l1_account_cache.url".to_string(), &cache.url)),
+        );
+        urls.extend(
+            self.tracing
+                .otlp_endpoint
+                .iter()
+                .map(|url| ("tracing.otlp_endpoint".to_string(), url)),
+        );
+        urls.extend(
+            self.receipt_sink
+                .iter()
+                .map(|sink| ("receipt_sink.url".to_string(), &sink.url)),
+        );
+        for (field, url) in urls {
+            url.validate()
+                .map_err(|e| Error::msg(format!("Invalid URL in {}: {}", field, e)))?;
+        }
+        Ok(())
+    }
+
+    /// Pretty-printed JSON of the configuration as `Debug` shows it, for `config show`
+    pub fn to_redacted_json(&self) -> Result<String> {
+        Ok(self.redact(&serde_json::to_string_pretty(self)?))
+    }
+
+    /// SHA-256 of the redacted configuration, equal on hosts running the same config
+    pub fn fingerprint(&self) -> Result<String> {
+        Ok(solana_sdk::hash::hash(self.to_redacted_json()?.as_bytes()).to_string())
+    }
+
+    /// Configured sources, or the single source described by the top-level fields
+    pub fn sources(&self) -> Result<Vec<SourceConfig>> {
+        if self.sources.is_empty() {
+            let (Some(l1_program_id), Some(watched_account), Some(nonce_account)) =
+                (self.l1_program_id, self.watched_account, self.nonce_account)
+            else {
+                return Err(Error::msg(
+                    "Configure l1_program_id, watched_account and nonce_account, or [[sources]]",
+                ));
+            };
+            return Ok(vec![SourceConfig {
+                id: DEFAULT_SOURCE_ID.to_string(),
+                l1_program_id,
+                watched_account,
+                nonce_account,
+                seeds: self.seeds.clone(),
+                message_types: BTreeMap::new(),
+                max_transfers_per_minute: None,
+                cancellation_registry: self.cancellation_registry.clone(),
+                discriminators: self.discriminators.clone(),
+                durable_nonce_account: self.durable_nonce_account,
+                transfer_info_layout: self.transfer_info_layout.clone(),
+            }]);
+        }
+
+        if self.message_source == MessageSource::Merkle && self.sources.len() > 1 {
+            return Err(Error::msg("Merkle mode supports a single source"));
+        }
+        let mut ids = HashSet::new();
+        for source in &self.sources {
+            let valid_id = !source.id.is_empty()
+                && source
+                    .id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !valid_id {
+                return Err(Error::msg(format!(
+                    "Invalid source id {:?}: use letters, digits, '-' and '_'",
+                    source.id
+                )));
+            }
+            if !ids.insert(source.id.as_str()) {
+                return Err(Error::msg(format!("Duplicate source id {}", source.id)));
+            }
+        }
+        Ok(self.sources.clone())
+    }
+
+    /// Whether state and metrics are namespaced by source id; the single
+    /// top-level source keeps the un-namespaced layout of earlier versions
+    pub fn namespaced_sources(&self) -> bool {
+        !self.sources.is_empty()
+    }
+}
+
+/// Removes the `[[bridges]]` tables from the loaded values, the profile's
+/// replacing the shared ones, and returns them by name
+fn take_bridges(
+    base: &mut Map<String, Value>,
+    overrides: &mut Map<String, Value>,
+) -> Result<Vec<(String, Map<String, Value>)>> {
+    let shared = base.remove("bridges");
+    let Some(bridges) = overrides.remove("bridges").or(shared) else {
+        return Ok(Vec::new());
+    };
+    let bridges = bridges
+        .into_array()
+        .map_err(|e| Error::msg(format!("Invalid [[bridges]]: {}", e)))?;
+    let mut names = HashSet::new();
+    bridges
+        .into_iter()
+        .map(|bridge| {
+            let mut table = bridge
+                .into_table()
+                .map_err(|e| Error::msg(format!("Invalid [[bridges]] entry: {}", e)))?;
+            let name = table
+                .remove("name")
+                .ok_or_else(|| Error::msg("Every [[bridges]] entry needs a name"))?
+                .into_string()
+                .map_err(|e| Error::msg(format!("Invalid bridge name: {}", e)))?;
+            let valid = !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !valid {
+                return Err(Error::msg(format!(
+                    "Invalid bridge name {:?}: use letters, digits, '-' and '_'",
+                    name
+                )));
+            }
+            if !names.insert(name.clone()) {
+                return Err(Error::msg(format!("Duplicate bridge name {}", name)));
+            }
+            Ok((name, table))
+        })
+        .collect()
+}
+
+/// Flattens nested tables into dotted keys so that profiles override single
+/// values instead of whole sections; arrays are kept as one value
+fn flatten_table(prefix: &str, table: Map<String, Value>, out: &mut Vec<(String, Value)>) {
+    for (key, value) in table {
+        let key = if prefix.is_empty() {
+            key
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        match value.kind {
+            ValueKind::Table(table) => flatten_table(&key, table, out),
+            _ => out.push((key, value)),
+        }
+    }
+}
+
+/// The `config` crate's YAML parser drops non-string mapping keys, such as the
+/// ids in `message_types: {0: native}`; turn them into strings first so YAML
+/// files mean the same as their TOML and JSON equivalents
+fn quote_yaml_keys(text: &str) -> Result<String> {
+    let documents = YamlLoader::load_from_str(text)
+        .map_err(|e| Error::msg(format!("Invalid YAML configuration: {}", e)))?;
+    let Some(document) = documents.into_iter().next() else {
+        return Ok(String::new());
+    };
+
+    let mut out = String::new();
+    YamlEmitter::new(&mut out)
+        .dump(&stringify_yaml_keys(document))
+        .map_err(|e| Error::msg(format!("Invalid YAML configuration: {:?}", e)))?;
+    Ok(out)
+}
+
+fn stringify_yaml_keys(yaml: Yaml) -> Yaml {
+    match yaml {
+        Yaml::Hash(hash) => Yaml::Hash(
+            hash.into_iter()
+                .map(|(key, value)| {
+                    let key = match key {
+                        Yaml::Integer(key) => Yaml::String(key.to_string()),
+                        Yaml::Boolean(key) => Yaml::String(key.to_string()),
+                        Yaml::Real(key) => Yaml::String(key),
+                        key => key,
+                    };
+                    (key, stringify_yaml_keys(value))
+                })
+                .collect(),
+        ),
+        Yaml::Array(items) => Yaml::Array(items.into_iter().map(stringify_yaml_keys).collect()),
+        yaml => yaml,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::pubkey::Pubkey;
+    use std::io::Write;
+
+    /// Loads a TOML config with the required fields and `extra`
+    fn load(extra: &str) -> Result<RelayerConfig> {
+        let mut file = tempfile::Builder::new().suffix(".toml").tempfile()?;
+        writeln!(
+            file,
+            "l1_url = 'http://l1'\n\
+             l2_url = 'http://l2'\n\
+             wallet_path = 'wallet.json'\n\
+             watched_account = '{}'\n\
+             l1_program_id = '{}'\n\
+             l2_program_id = '{}'\n\
+             nonce_account = '{}'\n\
+             {}",
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            extra
+        )?;
+        RelayerConfig::load(file.path(), None, None)
+    }
+
+    #[test]
+    fn addresses_load_as_tagged_keys_or_fail_naming_the_value() {
+        let config = load("").unwrap();
+        let source = config.sources().unwrap().remove(0);
+        assert_eq!(Some(source.watched_account), config.watched_account);
+        assert_eq!(Some(source.l1_program_id), config.l1_program_id);
+        assert_eq!(Some(source.nonce_account), config.nonce_account);
+
+        let error = load(&format!(
+            "[[sources]]\nid = 'a'\nl1_program_id = '{}'\nwatched_account = '{}'\n\
+             nonce_account = 'not a pubkey'",
+            Pubkey::new_unique(),
+            Pubkey::new_unique()
+        ))
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("invalid pubkey not a pubkey"), "{}", error);
+    }
+
+    #[test]
+    fn coalescing_needs_an_instruction_version_with_the_source_nonce() {
+        let error = load("coalesce_window_ms = 100").unwrap_err();
+        assert!(
+            error.to_string().contains("coalesce_window_ms"),
+            "{}",
+            error
+        );
+        let config = load("coalesce_window_ms = 100\nl2_instruction_version = 2").unwrap();
+        assert_eq!(config.coalesce_window_ms, 100);
+    }
+
+    #[test]
+    fn optional_subsystems_need_their_feature() {
+        for (extra, enabled) in [
+            ("admin_listen = '127.0.0.1:0'", cfg!(feature = "admin")),
+            (
+                "scale_webhook_url = 'http://scaler'",
+                cfg!(feature = "webhooks"),
+            ),
+            (
+                "[tracing]\notlp_endpoint = 'http://collector:4318/v1/traces'",
+                cfg!(feature = "telemetry"),
+            ),
+        ] {
+            let loaded = load(extra);
+            assert_eq!(loaded.is_ok(), enabled, "{}: {:?}", extra, loaded.err());
+        }
+    }
+
+    #[test]
+    fn role_keys_name_the_source_and_destination_settings() {
+        let config = load(
+            "direction = 'l2_to_l1'\n\
+             source_commitment = 'finalized'\n\
+             destination_commitment = 'processed'\n\
+             destination_instruction_version = 2",
+        )
+        .unwrap();
+        assert_eq!(config.l1_commitment, Commitment::Finalized);
+        assert_eq!(config.l2_commitment, Commitment::Processed);
+        assert_eq!(config.l2_instruction_version, InstructionVersion::V2);
+        // 同一设置不能同时用两个名字
+        let error = load(&format!("source_program_id = '{}'", Pubkey::new_unique())).unwrap_err();
+        assert!(error.to_string().contains("l1_program_id"), "{}", error);
+    }
+
+    #[test]
+    fn mint_mappings_file_adds_its_tables() {
+        let mut mappings = tempfile::Builder::new().suffix(".json").tempfile().unwrap();
+        let l1_mint = Pubkey::new_unique();
+        write!(
+            mappings,
+            r#"{{"mint_mappings": [{{"l1_mint": "{}", "l2_mint": "{}", "decimals": 6, "enabled": false}}]}}"#,
+            l1_mint,
+            Pubkey::new_unique()
+        )
+        .unwrap();
+        let config = load(&format!(
+            "mint_mappings_file = '{}'",
+            mappings.path().display()
+        ))
+        .unwrap();
+        assert_eq!(config.mint_mappings.len(), 1);
+        assert_eq!(*config.mint_mappings[0].l1_mint.pubkey(), l1_mint);
+        assert!(!config.mint_mappings[0].enabled);
+    }
+
+    #[test]
+    fn limits_load_with_per_type_overrides() {
+        let config =
+            load("[limits]\nmax_amount = 1000\n[limits.nft]\ncompute_unit_limit = 400000").unwrap();
+        assert_eq!(config.limits.defaults.max_amount, Some(1000));
+        assert_eq!(
+            config.limits.nft.and_then(|nft| nft.compute_unit_limit),
+            Some(400_000)
+        );
+        let error = load("[limits]\nmax_amount = 10\n[limits.token]\nmin_amount = 20").unwrap_err();
+        assert!(error.to_string().contains("limits.token"), "{}", error);
+    }
+
+    /// Loads `text` from a temporary file ending in `suffix`
+    fn load_as(suffix: &str, text: &str, profile: Option<&str>) -> Result<RelayerConfig> {
+        let mut file = tempfile::Builder::new().suffix(suffix).tempfile()?;
+        write!(file, "{}", text)?;
+        RelayerConfig::load(file.path(), None, profile)
+    }
+
+    #[test]
+    fn yaml_and_json_configs_mean_the_same_as_toml() {
+        let (program, watched, nonce) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let toml = format!(
+            "l1_url = 'http://l1'\nl2_url = 'http://l2'\nwallet_path = 'w'\n\
+             l2_program_id = '{program}'\npoll_interval_min_ms = 250\n\
+             [[sources]]\nid = 'a'\nl1_program_id = '{program}'\n\
+             watched_account = '{watched}'\nnonce_account = '{nonce}'\n\
+             message_types = {{ \"0\" = 'native', \"1\" = 'token' }}\n"
+        );
+        // YAML 的整数键与 TOML 的字符串键同义
+        let yaml = format!(
+            "l1_url: http://l1\nl2_url: http://l2\nwallet_path: w\n\
+             l2_program_id: {program}\npoll_interval_min_ms: 250\n\
+             sources: [{{id: a, l1_program_id: {program}, watched_account: {watched}, \
+             nonce_account: {nonce}, message_types: {{0: native, 1: token}}}}]\n"
+        );
+        let json = format!(
+            r#"{{"l1_url": "http://l1", "l2_url": "http://l2", "wallet_path": "w",
+                "l2_program_id": "{program}", "poll_interval_min_ms": 250,
+                "sources": [{{"id": "a", "l1_program_id": "{program}",
+                    "watched_account": "{watched}", "nonce_account": "{nonce}",
+                    "message_types": {{"0": "native", "1": "token"}}}}]}}"#
+        );
+        for (suffix, text) in [(".toml", toml), (".yml", yaml), (".json", json)] {
+            let config = load_as(suffix, &text, None).unwrap();
+            assert_eq!(config.poll_interval_min_ms, 250, "{}", suffix);
+            let source = config.sources().unwrap().remove(0);
+            assert_eq!(*source.watched_account.pubkey(), watched, "{}", suffix);
+            assert_eq!(
+                source.message_types,
+                BTreeMap::from([
+                    ("0".to_string(), MessageType::Native),
+                    ("1".to_string(), MessageType::Token),
+                ]),
+                "{}",
+                suffix
+            );
+        }
+    }
+
+    #[test]
+    fn yaml_profiles_override_the_defaults() {
+        let yaml = format!(
+            "default: {{l1_url: http://l1, l2_url: http://l2, wallet_path: w, \
+             watched_account: {}, l1_program_id: {}, l2_program_id: {}, \
+             nonce_account: {}, poll_interval_min_ms: 250}}\n\
+             profiles: {{fast: {{poll_interval_min_ms: 50}}}}\n",
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique()
+        );
+        let config = load_as(".yaml", &yaml, Some("fast")).unwrap();
+        assert_eq!(config.poll_interval_min_ms, 50);
+        assert_eq!(config.profile.as_deref(), Some("fast"));
+        assert_eq!(
+            load_as(".yaml", &yaml, None).unwrap().poll_interval_min_ms,
+            250
+        );
+    }
+
+    #[test]
+    fn the_format_follows_the_extension() {
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("relayer.yml")).unwrap(),
+            ConfigFormat::Yaml
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("relayer.json")).unwrap(),
+            ConfigFormat::Json
+        );
+        let error = ConfigFormat::from_path(Path::new("relayer.conf")).unwrap_err();
+        assert!(error.to_string().contains("--config-format"), "{}", error);
+        assert!(load_as(".ini", "l1_url = 'x'", None).is_err());
+    }
+
+    #[test]
+    fn debug_output_masks_secret_fields_and_resolved_values() {
+        env::set_var("CONFIG_TEST_RPC_TOKEN", "tok-42");
+        let config = load(
+            "l1_urls = ['https://rpc.example.com/v2/APIKEY']\n\
+             metrics_textfile = '/var/lib/${CONFIG_TEST_RPC_TOKEN}.prom'",
+        );
+        let shown = format!("{:?}", config.unwrap());
+        assert!(!shown.contains("APIKEY"), "{}", shown);
+        assert!(!shown.contains("tok-42"), "{}", shown);
+        assert!(!shown.contains("wallet.json"), "{}", shown);
+        assert!(
+            shown.contains("https://rpc.example.com/<redacted>"),
+            "{}",
+            shown
+        );
+    }
+
+    #[test]
+    fn zero_concurrent_submissions_is_refused() {
+        let error = load("max_concurrent_submissions = 0").unwrap_err();
+        assert!(
+            error.to_string().contains("max_concurrent_submissions"),
+            "{}",
+            error
+        );
+        assert!(load("max_concurrent_submissions = 1").is_ok());
+    }
 }
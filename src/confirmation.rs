@@ -0,0 +1,127 @@
+//! Reorg protection by slot age.
+//! A newly observed L1 nonce is held until the L1 slot has advanced by
+//! `min_confirmation_slots` past the slot it was first seen at, and the L1
+//! nonce is read again before the held nonces are queued.
+
+use crate::{alerts, Relayer};
+use anyhow::Result;
+use std::{collections::VecDeque, sync::Mutex};
+
+#[derive(Default)]
+struct Observations {
+    /// (L1 nonce, slot it was first seen at), ascending
+    held: VecDeque<(u64, u64)>,
+    /// Highest nonce released to the queue
+    released: u64,
+}
+
+pub struct ConfirmationHold {
+    min_slots: u64,
+    observations: Mutex<Observations>,
+}
+
+impl ConfirmationHold {
+    pub fn new(min_slots: u64) -> Self {
+        Self {
+            min_slots,
+            observations: Mutex::new(Observations::default()),
+        }
+    }
+
+    /// Records that L1 reported `nonce` at `slot`
+    fn observe(&self, nonce: u64, slot: u64) {
+        let mut observations = self.observations.lock().unwrap();
+        let newest = observations
+            .held
+            .back()
+            .map_or(observations.released, |(nonce, _)| *nonce);
+        if nonce > newest {
+            observations.held.push_back((nonce, slot));
+        }
+    }
+
+    /// Highest held nonce that is at least `min_slots` old at `slot`
+    fn aged(&self, slot: u64) -> Option<u64> {
+        self.observations
+            .lock()
+            .unwrap()
+            .held
+            .iter()
+            .take_while(|(_, seen)| seen.saturating_add(self.min_slots) <= slot)
+            .last()
+            .map(|(nonce, _)| *nonce)
+    }
+
+    /// Releases the observations up to `nonce` to the queue
+    fn release(&self, nonce: u64) {
+        let mut observations = self.observations.lock().unwrap();
+        observations.held.retain(|(held, _)| *held > nonce);
+        observations.released = observations.released.max(nonce);
+    }
+
+    /// Forgets held observations above `nonce` after L1 reported a lower nonce
+    fn roll_back(&self, nonce: u64) -> bool {
+        let mut observations = self.observations.lock().unwrap();
+        let before = observations.held.len();
+        observations.held.retain(|(held, _)| *held <= nonce);
+        observations.held.len() != before
+    }
+
+    /// (highest released nonce, highest held nonce)
+    fn bounds(&self) -> (u64, u64) {
+        let observations = self.observations.lock().unwrap();
+        let newest = observations
+            .held
+            .back()
+            .map_or(observations.released, |(nonce, _)| *nonce);
+        (observations.released, newest)
+    }
+}
+
+impl Relayer {
+    /// Returns the L1 nonce up to which transfers may be queued: the newest
+    /// observation that is `min_confirmation_slots` old and that L1 still reports
+    pub(crate) async fn confirmed_l1_nonce(
+        &self,
+        hold: &ConfirmationHold,
+        l1_nonce: u64,
+        l2_nonce: u64,
+    ) -> Result<u64> {
+        let slot = self.l1_client.get_slot().await?;
+        if hold.roll_back(l1_nonce) {
+            alerts::raise(&format!(
+                "L1 nonce went back to {} before it was confirmed; dropped the newer observations",
+                l1_nonce
+            ));
+        }
+        hold.observe(l1_nonce, slot);
+
+        if let Some(aged) = hold.aged(slot) {
+            // 入队前重新读取 L1 nonce，确认期间没有发生回滚
+            let current = self.current_l1_nonce(l2_nonce).await?;
+            if current >= aged {
+                hold.release(aged);
+            } else if hold.roll_back(current) {
+                alerts::raise(&format!(
+                    "L1 nonce went back to {} while nonces up to {} were held for confirmation",
+                    current, aged
+                ));
+            }
+        }
+
+        let (released, newest) = hold.bounds();
+        let confirmed = released.max(l2_nonce);
+        let held = newest.saturating_sub(confirmed);
+        self.metrics.held_for_confirmation.set(held);
+        if held > 0 {
+            tracing::debug!(
+                "Holding nonces {}..{} for {} L1 slots of confirmation (current slot {})",
+                confirmed,
+                newest,
+                hold.min_slots,
+                slot
+            );
+        }
+        Ok(confirmed)
+    }
+}
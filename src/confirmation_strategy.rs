@@ -0,0 +1,344 @@
+//! Per-transfer confirmation strategies.
+//! With `[[confirmation_rules]]`, every transfer is confirmed under the
+//! strategy of the first rule it matches by amount and message type, or
+//! `confirmation_default_strategy`. `fast` counts a transaction as relayed
+//! once it is `confirmed`. `safe` waits for `finalized` and reads the
+//! recipient's balance at that commitment before writing the receipt; a
+//! balance below the amount is alerted. Without rules every transaction is
+//! confirmed at the L2 client's commitment, as before.
+//!
+//! A safe transaction leaves the submitter as soon as it is `confirmed`: the
+//! L2 nonce has advanced, so later transfers can be sent behind it. It stays
+//! in flight, marked landed, and a background loop waits for it to finalize,
+//! so slow finality never holds fast transfers and a restart resumes the
+//! wait. Landed transactions no longer reserve wallet balance. A landed
+//! transaction that disappears before finalizing is alerted for
+//! reconciliation. The strategy is recorded in flight and on the receipt.
+
+use crate::{
+    alerts,
+    config::RelayerConfig,
+    inflight::InFlightTransfer,
+    models::message::MessageType,
+    token::{account_balance, token_balance},
+    Relayer,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature};
+use std::{fmt, str::FromStr, time::Duration};
+
+/// Pause between checks of the landed transactions
+const FINALITY_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Signatures per `getSignatureStatuses` call
+const MAX_STATUS_BATCH: usize = 256;
+
+/// How long a transfer's transaction is confirmed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfirmationStrategy {
+    /// Relayed once `confirmed`
+    #[default]
+    Fast,
+    /// Relayed once `finalized` and the recipient's balance is read again
+    Safe,
+}
+
+impl ConfirmationStrategy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Fast => "fast",
+            Self::Safe => "safe",
+        }
+    }
+}
+
+impl fmt::Display for ConfirmationStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// One `[[confirmation_rules]]` table; every condition set must hold
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfirmationRule {
+    pub strategy: ConfirmationStrategy,
+    /// Transfers of at least this many lamports
+    #[serde(default)]
+    pub min_amount: Option<u64>,
+    /// Transfers of at most this many lamports
+    #[serde(default)]
+    pub max_amount: Option<u64>,
+    /// Messages of this type under the source's `message_types`
+    #[serde(default)]
+    pub message_type: Option<MessageType>,
+    /// Messages with this raw type id
+    #[serde(default)]
+    pub message_type_id: Option<u8>,
+}
+
+impl ConfirmationRule {
+    fn matches(&self, amount: u64, kind: Option<MessageType>, id: Option<u8>) -> bool {
+        self.min_amount.is_none_or(|min| amount >= min)
+            && self.max_amount.is_none_or(|max| amount <= max)
+            && self.message_type.is_none_or(|wanted| kind == Some(wanted))
+            && self.message_type_id.is_none_or(|wanted| id == Some(wanted))
+    }
+}
+
+/// The rules of a relayer and the strategy of transfers no rule matches
+pub struct ConfirmationRules {
+    rules: Vec<ConfirmationRule>,
+    default: ConfirmationStrategy,
+}
+
+impl ConfirmationRules {
+    /// The rules of `config`, none without `[[confirmation_rules]]`
+    pub fn from_config(config: &RelayerConfig) -> Option<Self> {
+        if config.confirmation_rules.is_empty() {
+            return None;
+        }
+        Some(Self {
+            rules: config.confirmation_rules.clone(),
+            default: config.confirmation_default_strategy,
+        })
+    }
+
+    /// Strategy of a transfer of `amount` carrying a message of `kind`
+    /// (raw type id `id`)
+    pub fn select(
+        &self,
+        amount: u64,
+        kind: Option<MessageType>,
+        id: Option<u8>,
+    ) -> ConfirmationStrategy {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(amount, kind, id))
+            .map_or(self.default, |rule| rule.strategy)
+    }
+}
+
+impl Relayer {
+    /// Strategy of a transfer of `amount` with raw message type `id`; none
+    /// without rules
+    pub(crate) fn confirmation_strategy(
+        &self,
+        amount: u64,
+        id: Option<u8>,
+    ) -> Option<ConfirmationStrategy> {
+        let rules = self.confirmation_rules.as_ref()?;
+        // 未配置映射时所有消息都按原生转账处理
+        let kind = id.map(|id| {
+            if self.message_types.is_empty() {
+                MessageType::Native
+            } else {
+                self.message_types
+                    .get(&id)
+                    .copied()
+                    .unwrap_or(MessageType::Unknown(id))
+            }
+        });
+        Some(rules.select(amount, kind, id))
+    }
+
+    /// Commitment a transaction under `strategy` must reach to count as
+    /// landed; safe transactions are finalized afterwards
+    pub(crate) fn landing_commitment(
+        &self,
+        strategy: Option<ConfirmationStrategy>,
+    ) -> CommitmentConfig {
+        match strategy {
+            Some(_) => CommitmentConfig::confirmed(),
+            None => self.l2_client.commitment(),
+        }
+    }
+
+    /// Commitment at which a transaction under `strategy` is fully confirmed
+    pub(crate) fn final_commitment(
+        &self,
+        strategy: Option<ConfirmationStrategy>,
+    ) -> CommitmentConfig {
+        match strategy {
+            Some(ConfirmationStrategy::Fast) => CommitmentConfig::confirmed(),
+            Some(ConfirmationStrategy::Safe) => CommitmentConfig::finalized(),
+            None => self.l2_client.commitment(),
+        }
+    }
+
+    /// Reads the recipient's finalized balance (of the L2 mint, for a token
+    /// transfer) for the receipt of a safe transfer and alerts if it does not
+    /// hold the amount
+    pub(crate) async fn check_recipient_balance(&self, transfer: &InFlightTransfer) -> Result<u64> {
+        let recipient = Pubkey::from_str(&transfer.to).map_err(|e| {
+            anyhow::anyhow!(
+                "Invalid recipient {} of nonce {}: {}",
+                transfer.to,
+                transfer.nonce,
+                e
+            )
+        })?;
+        let balance = match (&transfer.mint, &transfer.token_account) {
+            // 收款方指定的代币账户直接读取
+            (Some(_), Some(account)) => {
+                let account = Pubkey::from_str(account).map_err(|e| {
+                    anyhow::anyhow!(
+                        "Invalid token account {} of nonce {}: {}",
+                        account,
+                        transfer.nonce,
+                        e
+                    )
+                })?;
+                account_balance(
+                    &self.l2_client,
+                    &account,
+                    &recipient,
+                    CommitmentConfig::finalized(),
+                )
+                .await?
+                .unwrap_or_default()
+            }
+            (Some(mint), None) => {
+                let mint = Pubkey::from_str(mint).map_err(|e| {
+                    anyhow::anyhow!("Invalid mint {} of nonce {}: {}", mint, transfer.nonce, e)
+                })?;
+                token_balance(
+                    &self.l2_client,
+                    &recipient,
+                    &mint,
+                    CommitmentConfig::finalized(),
+                )
+                .await?
+                .unwrap_or_default()
+            }
+            (None, _) => {
+                self.l2_client
+                    .get_balance_with_commitment(&recipient, CommitmentConfig::finalized())
+                    .await?
+                    .value
+            }
+        };
+        if balance < transfer.amount {
+            alerts::raise(&format!(
+                "{}: recipient {} of finalized nonce {} holds {} {}, less than the {} relayed; check the transfer",
+                self.name(),
+                transfer.to,
+                transfer.nonce,
+                balance,
+                transfer.mint.as_deref().unwrap_or("lamports"),
+                transfer.amount
+            ));
+            self.metrics.recipient_balance_mismatches_total.inc();
+        }
+        Ok(balance)
+    }
+
+    /// Writes the receipt of the landed `transfer`, finalized in `slot`
+    async fn finish_finalized(&self, transfer: &InFlightTransfer, slot: u64) -> Result<()> {
+        let mut receipt = self.confirmed_receipt(transfer, slot).await?;
+        receipt.rebroadcasts = transfer.rebroadcasts;
+        self.record_confirmed(transfer, receipt)?;
+        self.in_flight.remove(&self.state, transfer.nonce)?;
+        tracing::info!(
+            "Nonce {} finalized in slot {} ({})",
+            transfer.nonce,
+            slot,
+            transfer.signature
+        );
+        Ok(())
+    }
+
+    /// Checks every landed transaction once, writing the receipts of the
+    /// finalized ones; returns how many are still waiting
+    pub(crate) async fn finalize_landed(&self) -> Result<usize> {
+        let landed: Vec<InFlightTransfer> = self
+            .in_flight
+            .all()
+            .into_iter()
+            .filter(|transfer| transfer.landed_slot.is_some())
+            .collect();
+        let mut waiting = 0;
+        for chunk in landed.chunks(MAX_STATUS_BATCH) {
+            let signatures = chunk
+                .iter()
+                .map(|transfer| {
+                    Signature::from_str(&transfer.signature).map_err(|e| {
+                        anyhow::anyhow!("Invalid in-flight signature {}: {}", transfer.signature, e)
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let statuses = self
+                .l2_client
+                .get_signature_statuses(&signatures)
+                .await?
+                .value;
+            let block_height = self.l2_client.get_block_height().await?;
+            for (transfer, status) in chunk.iter().zip(statuses) {
+                let status = match status {
+                    // 过期后状态缓存里可能已没有这笔交易，查历史记录
+                    None if block_height > transfer.last_valid_block_height => self
+                        .l2_client
+                        .get_signature_statuses_with_history(&[Signature::from_str(
+                            &transfer.signature,
+                        )?])
+                        .await?
+                        .value
+                        .remove(0),
+                    status => status,
+                };
+                let failure = match status {
+                    Some(status) if status.err.is_none() => {
+                        if status.satisfies_commitment(self.final_commitment(transfer.strategy)) {
+                            self.finish_finalized(transfer, status.slot).await?;
+                        } else {
+                            waiting += 1;
+                        }
+                        continue;
+                    }
+                    Some(status) => status.err.map_or(String::new(), |err| err.to_string()),
+                    None if block_height > transfer.last_valid_block_height => {
+                        "dropped".to_string()
+                    }
+                    None => {
+                        waiting += 1;
+                        continue;
+                    }
+                };
+                // 确认后又消失或失败，交易所在的分叉被放弃
+                alerts::raise(&format!(
+                    "{}: nonce {} landed in slot {} ({}) but did not finalize: {}; reconcile the L2 nonce account",
+                    self.name(),
+                    transfer.nonce,
+                    transfer.landed_slot.unwrap_or_default(),
+                    transfer.signature,
+                    failure
+                ));
+                self.in_flight.remove(&self.state, transfer.nonce)?;
+            }
+        }
+        self.metrics
+            .finality_pending
+            .set(ConfirmationStrategy::Safe.as_str(), waiting as u64);
+        Ok(waiting)
+    }
+
+    /// Waits for landed safe transactions to finalize; never returns
+    pub(crate) async fn finalize_transfers(&self) -> Result<()> {
+        loop {
+            // 先注册等待，避免错过检查期间标记为落地的交易
+            let landed = self.in_flight.landed().notified();
+            let waiting = match self.finalize_landed().await {
+                Ok(waiting) => waiting,
+                Err(e) => {
+                    tracing::warn!("failed to check landed transactions: {}", e);
+                    1
+                }
+            };
+            if waiting == 0 {
+                landed.await;
+            } else {
+                self.clock.sleep(FINALITY_POLL_INTERVAL).await;
+            }
+        }
+    }
+}
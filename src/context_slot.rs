@@ -0,0 +1,230 @@
+//! Context-slot high-water marks.
+//! Behind a load balancer or a failover, successive reads of one cluster can
+//! be answered by different nodes, and a later read by a node behind the one
+//! that answered an earlier read: a PDA the watched account announced then
+//! looks missing, and the L2 nonce account looks older than it is. The
+//! highest context slot seen on each cluster is kept, and the reads of the
+//! watched account, the PDA snapshot and the L2 nonce account ask for at
+//! least that slot (`minContextSlot`). A node that has not reached it
+//! answers `-32016`; that answer, or a PDA missing from a read answered below
+//! the mark by a node that ignores `minContextSlot`, holds the batch as
+//! `NodeBehind`, which is retried without counting as a failed attempt.
+
+use crate::Relayer;
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind},
+    nonblocking::rpc_client::RpcClient,
+    rpc_config::RpcAccountInfoConfig,
+    rpc_custom_error::JSON_RPC_SERVER_ERROR_MIN_CONTEXT_SLOT_NOT_REACHED,
+    rpc_request::RpcError,
+    rpc_response::{Response, RpcResult},
+};
+use solana_sdk::{account::Account, commitment_config::CommitmentConfig, pubkey::Pubkey};
+use std::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Highest context slot answered by a cluster's nodes
+#[derive(Debug, Default)]
+pub struct SlotHighWater(AtomicU64);
+
+impl SlotHighWater {
+    pub fn observe(&self, slot: u64) {
+        self.0.fetch_max(slot, Ordering::Relaxed);
+    }
+
+    /// The mark, none before the first read
+    pub fn get(&self) -> Option<u64> {
+        Some(self.0.load(Ordering::Relaxed)).filter(|&slot| slot > 0)
+    }
+}
+
+/// High-water marks of both clusters
+#[derive(Debug, Default)]
+pub struct ContextSlots {
+    pub l1: SlotHighWater,
+    pub l2: SlotHighWater,
+}
+
+/// A read was answered by a node behind a slot already seen on its cluster
+#[derive(Debug)]
+pub struct NodeBehind {
+    pub cluster: &'static str,
+    /// Slot the node answered at; none when it refused `minContextSlot`
+    pub slot: Option<u64>,
+    pub high_water: u64,
+}
+
+impl fmt::Display for NodeBehind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.slot {
+            Some(slot) => write!(
+                f,
+                "{} node answered at slot {}, behind slot {} already seen; reading again",
+                self.cluster, slot, self.high_water
+            ),
+            None => write!(
+                f,
+                "{} node has not reached slot {} already seen; reading again",
+                self.cluster, self.high_water
+            ),
+        }
+    }
+}
+
+impl std::error::Error for NodeBehind {}
+
+impl NodeBehind {
+    /// Fails if a `cluster` read answered at `slot` trails the mark
+    /// `high_water` taken before it; a read at the mark is current
+    pub fn check(cluster: &'static str, slot: u64, high_water: Option<u64>) -> Result<(), Self> {
+        match high_water {
+            Some(high_water) if slot < high_water => Err(Self {
+                cluster,
+                slot: Some(slot),
+                high_water,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Whether `error` is a node's refusal of `minContextSlot`
+pub fn min_context_slot_not_reached(error: &ClientError) -> bool {
+    matches!(
+        error.kind(),
+        ClientErrorKind::RpcError(RpcError::RpcResponseError { code, .. })
+            if *code == JSON_RPC_SERVER_ERROR_MIN_CONTEXT_SLOT_NOT_REACHED
+    )
+}
+
+/// Reads `pubkey` like `getAccountInfo` would, through `getMultipleAccounts`,
+/// whose errors keep the node's error code
+pub async fn read_account(
+    client: &RpcClient,
+    pubkey: &Pubkey,
+    min_context_slot: Option<u64>,
+) -> RpcResult<Option<Account>> {
+    let response = client
+        .get_multiple_accounts_with_config(
+            &[*pubkey],
+            account_config(client.commitment(), min_context_slot),
+        )
+        .await?;
+    Ok(Response {
+        context: response.context,
+        value: response.value.into_iter().next().flatten(),
+    })
+}
+
+/// Account read at `commitment` from a node at least at `min_context_slot`
+pub fn account_config(
+    commitment: CommitmentConfig,
+    min_context_slot: Option<u64>,
+) -> RpcAccountInfoConfig {
+    RpcAccountInfoConfig {
+        encoding: Some(UiAccountEncoding::Base64Zstd),
+        data_slice: None,
+        commitment: Some(commitment),
+        min_context_slot,
+    }
+}
+
+impl Relayer {
+    /// `error` of an L1 read, as `NodeBehind` when the node refused the mark
+    pub(crate) fn l1_read_error(&self, error: impl Into<anyhow::Error>) -> anyhow::Error {
+        self.read_error("L1", &self.context_slots.l1, error)
+    }
+
+    /// `error` of an L2 read, as `NodeBehind` when the node refused the mark
+    pub(crate) fn l2_read_error(&self, error: impl Into<anyhow::Error>) -> anyhow::Error {
+        self.read_error("L2", &self.context_slots.l2, error)
+    }
+
+    fn read_error(
+        &self,
+        cluster: &'static str,
+        mark: &SlotHighWater,
+        error: impl Into<anyhow::Error>,
+    ) -> anyhow::Error {
+        let error = error.into();
+        let client_error = error.downcast_ref::<ClientError>();
+        if client_error.is_some() {
+            self.metrics.rpc_errors_total.inc(&cluster.to_lowercase());
+        }
+        let refused = client_error.is_some_and(min_context_slot_not_reached);
+        match mark.get() {
+            Some(high_water) if refused => {
+                self.metrics
+                    .node_behind_reads_total
+                    .inc(&cluster.to_lowercase());
+                NodeBehind {
+                    cluster,
+                    slot: None,
+                    high_water,
+                }
+                .into()
+            }
+            _ => error,
+        }
+    }
+
+    /// Fails with `NodeBehind` if an L1 read answered at `slot` trails the
+    /// mark `high_water` taken before it
+    pub(crate) fn check_l1_behind(&self, slot: u64, high_water: Option<u64>) -> anyhow::Result<()> {
+        NodeBehind::check("L1", slot, high_water).map_err(|behind| {
+            self.metrics.node_behind_reads_total.inc("l1");
+            behind.into()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_client::rpc_request::RpcResponseErrorData;
+
+    #[test]
+    fn reads_behind_the_mark_are_held_and_reads_at_it_pass() {
+        let behind = NodeBehind::check("L1", 99, Some(100)).unwrap_err();
+        assert_eq!((behind.slot, behind.high_water), (Some(99), 100));
+        assert!(NodeBehind::check("L1", 100, Some(100)).is_ok());
+        assert!(NodeBehind::check("L1", 101, Some(100)).is_ok());
+        // 首次读取前没有水位
+        assert!(NodeBehind::check("L1", 0, None).is_ok());
+    }
+
+    #[test]
+    fn the_mark_only_moves_forward() {
+        let mark = SlotHighWater::default();
+        assert_eq!(mark.get(), None);
+        mark.observe(100);
+        assert_eq!(mark.get(), Some(100));
+        mark.observe(90);
+        assert_eq!(mark.get(), Some(100));
+        mark.observe(100);
+        assert_eq!(mark.get(), Some(100));
+        mark.observe(120);
+        assert_eq!(mark.get(), Some(120));
+    }
+
+    #[test]
+    fn refusals_of_min_context_slot_are_recognised() {
+        let refusal = |code| {
+            ClientError::from(ClientErrorKind::RpcError(RpcError::RpcResponseError {
+                code,
+                message: String::new(),
+                data: RpcResponseErrorData::Empty,
+            }))
+        };
+        assert!(min_context_slot_not_reached(&refusal(
+            JSON_RPC_SERVER_ERROR_MIN_CONTEXT_SLOT_NOT_REACHED
+        )));
+        assert!(!min_context_slot_not_reached(&refusal(-32002)));
+        assert!(!min_context_slot_not_reached(&ClientError::from(
+            ClientErrorKind::Custom("timeout".to_string())
+        )));
+    }
+}
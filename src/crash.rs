@@ -0,0 +1,200 @@
+//! Fatal exits.
+//! A fatal error ends the process with an exit code naming its category, so a
+//! service manager can tell a bad config, which a restart will not fix, from a
+//! failed startup check, corrupt state or a crash. Once the config has loaded,
+//! every fatal exit (panics included, through a panic hook) also writes a
+//! crash report to `crash_report_path`: the error, the build, recent alerts,
+//! a fingerprint of the config and, per source, the last relayed nonce and a
+//! snapshot of the persisted pending queue.
+
+use crate::{
+    alerts,
+    build_info::BuildInfo,
+    clock::{Clock, SystemClock},
+    config::{RelayerConfig, SourceConfig},
+    dlq::DeadLetterQueue,
+    queue::{NonceRetry, PendingQueue},
+    receipts::Receipt,
+    source_state,
+    state::CorruptState,
+};
+use anyhow::Result;
+use serde::Serialize;
+use std::{fmt, process::ExitCode};
+
+/// Pending nonces listed in a crash report per source
+const PENDING_SNAPSHOT: usize = 100;
+
+/// Category of a fatal error; attach it with `anyhow::Context::context`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitCategory {
+    /// The config could not be loaded or is invalid (exit code 78)
+    Config,
+    /// A startup check against the clusters or the signer failed (69)
+    Preflight,
+    /// A state value no longer parses (65)
+    Storage,
+    /// The relayer panicked (70)
+    Panic,
+    /// Any other error (1)
+    Fatal,
+}
+
+impl ExitCategory {
+    /// Exit code of the category, following sysexits.h
+    pub fn code(self) -> u8 {
+        match self {
+            ExitCategory::Config => 78,
+            ExitCategory::Preflight => 69,
+            ExitCategory::Storage => 65,
+            ExitCategory::Panic => 70,
+            ExitCategory::Fatal => 1,
+        }
+    }
+
+    /// Category of `error`: corrupt state wins over the attached category
+    pub fn of(error: &anyhow::Error) -> Self {
+        if error.chain().any(|cause| cause.is::<CorruptState>()) {
+            return ExitCategory::Storage;
+        }
+        error
+            .downcast_ref::<ExitCategory>()
+            .copied()
+            .unwrap_or(ExitCategory::Fatal)
+    }
+}
+
+impl fmt::Display for ExitCategory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ExitCategory::Config => "configuration error",
+            ExitCategory::Preflight => "startup check failed",
+            ExitCategory::Storage => "corrupt state",
+            ExitCategory::Panic => "panic",
+            ExitCategory::Fatal => "fatal error",
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CrashReport {
+    pub at: u64,
+    pub category: ExitCategory,
+    pub exit_code: u8,
+    /// Build of the crashed binary
+    pub build: BuildInfo,
+    /// The error followed by its causes
+    pub errors: Vec<String>,
+    /// Most recent operator alerts, oldest first
+    pub recent_alerts: Vec<String>,
+    /// SHA-256 of the redacted config, to tell deployments apart
+    pub config_fingerprint: String,
+    pub sources: Vec<SourceSnapshot>,
+}
+
+/// Persisted state of one source at the time of the crash
+#[derive(Debug, Default, Serialize)]
+pub struct SourceSnapshot {
+    pub id: String,
+    /// Highest nonce with a stored receipt
+    pub last_relayed_nonce: Option<u64>,
+    pub queued: u64,
+    pub spilled: u64,
+    /// First pending nonces, oldest first
+    pub pending: Vec<u64>,
+    /// Pending nonces that failed, with their last error
+    pub retries: Vec<(u64, NonceRetry)>,
+    pub dead_letters: usize,
+    /// Why the state could not be read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snapshot_error: Option<String>,
+}
+
+impl CrashReport {
+    pub fn new(config: &RelayerConfig, category: ExitCategory, errors: Vec<String>) -> Self {
+        let config_fingerprint = config.fingerprint().unwrap_or_default();
+        // 读取状态本身可能失败，报告照常写出
+        let sources = match config.sources() {
+            Ok(sources) => sources
+                .iter()
+                .map(|source| {
+                    snapshot(config, source).unwrap_or_else(|e| SourceSnapshot {
+                        id: source.id.clone(),
+                        snapshot_error: Some(format!("{:#}", e)),
+                        ..SourceSnapshot::default()
+                    })
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        Self {
+            at: SystemClock.unix_timestamp(),
+            category,
+            exit_code: category.code(),
+            build: BuildInfo::current(),
+            errors,
+            recent_alerts: alerts::recent(),
+            config_fingerprint,
+            sources,
+        }
+    }
+
+    /// Writes the report to `crash_report_path`
+    pub fn write(&self, config: &RelayerConfig) -> Result<()> {
+        std::fs::write(&config.crash_report_path, serde_json::to_vec_pretty(self)?).map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to write crash report {}: {}",
+                config.crash_report_path,
+                e
+            )
+        })
+    }
+}
+
+fn snapshot(config: &RelayerConfig, source: &SourceConfig) -> Result<SourceSnapshot> {
+    let state = source_state(config, source)?;
+    let queue = PendingQueue::load(&state, config.queue_capacity, config.queue_overflow)?;
+    let (queued, spilled) = queue.depth();
+    Ok(SourceSnapshot {
+        id: source.id.clone(),
+        last_relayed_nonce: Receipt::stored_nonces(&state)?.last().copied(),
+        queued,
+        spilled,
+        pending: queue.front(PENDING_SNAPSHOT),
+        retries: queue.retries(),
+        dead_letters: DeadLetterQueue::load(&state)?.len(),
+        snapshot_error: None,
+    })
+}
+
+/// Prints `error`, writes the crash report when the config is known and
+/// returns the exit code of the error's category
+pub fn exit(config: Option<&RelayerConfig>, error: &anyhow::Error) -> ExitCode {
+    let category = ExitCategory::of(error);
+    eprintln!("Error: {:?}", error);
+    if let Some(config) = config {
+        let errors = error.chain().map(|cause| cause.to_string()).collect();
+        write_report(config, category, errors);
+    }
+    ExitCode::from(category.code())
+}
+
+/// Makes a panic anywhere in the relayer write a crash report and exit with
+/// the panic exit code instead of unwinding into a half-stopped process
+pub fn install_panic_hook(config: &RelayerConfig) {
+    let config = config.clone();
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        write_report(&config, ExitCategory::Panic, vec![info.to_string()]);
+        std::process::exit(ExitCategory::Panic.code().into());
+    }));
+}
+
+fn write_report(config: &RelayerConfig, category: ExitCategory, errors: Vec<String>) {
+    match CrashReport::new(config, category, errors).write(config) {
+        Ok(()) => eprintln!("Crash report written to {}", config.crash_report_path),
+        Err(e) => eprintln!("{}", e),
+    }
+}
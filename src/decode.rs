@@ -0,0 +1,275 @@
+//! Account-data decoding for debugging layout drift.
+//! `decode-account` hex-dumps a raw account and tries every layout the relayer
+//! knows, reporting which decoded and where the others ran out of data. Parse
+//! errors elsewhere quote the first bytes of the data through `hex_prefix`.
+
+use crate::{merkle::LeafChunk, models::account::TransferInfoAccount};
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use std::fmt::Write;
+
+/// Bytes of account data quoted in parse errors
+const ERROR_PREFIX_LEN: usize = 64;
+
+/// Chain an account is read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Cluster {
+    L1,
+    L2,
+}
+
+/// Lowercase hex of `data`
+pub fn hex(data: &[u8]) -> String {
+    data.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{:02x}", byte);
+        hex
+    })
+}
+
+/// First bytes of `data` in hex, for parse errors
+pub fn hex_prefix(data: &[u8]) -> String {
+    let shown = data.len().min(ERROR_PREFIX_LEN);
+    if shown == data.len() {
+        format!("data: {}", hex(data))
+    } else {
+        format!(
+            "first {} of {} bytes: {}",
+            shown,
+            data.len(),
+            hex(&data[..shown])
+        )
+    }
+}
+
+/// 16 bytes per line: offset, hex bytes and printable ASCII
+pub fn hex_dump(data: &[u8]) -> String {
+    let mut dump = String::new();
+    for (line, chunk) in data.chunks(16).enumerate() {
+        let bytes: Vec<String> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let ascii: String = chunk
+            .iter()
+            .map(|&byte| {
+                if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        let _ = writeln!(
+            dump,
+            "{:08x}  {:<47}  |{}|",
+            line * 16,
+            bytes.join(" "),
+            ascii
+        );
+    }
+    dump
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    U8,
+    U64,
+    Pubkey,
+    Signature,
+    Bytes(usize),
+}
+
+impl Kind {
+    fn len(self) -> usize {
+        match self {
+            Kind::U8 => 1,
+            Kind::U64 => 8,
+            Kind::Pubkey => 32,
+            Kind::Signature => 64,
+            Kind::Bytes(len) => len,
+        }
+    }
+
+    fn format(self, bytes: &[u8]) -> String {
+        match self {
+            Kind::U8 => bytes[0].to_string(),
+            Kind::U64 => u64::from_le_bytes(bytes.try_into().unwrap()).to_string(),
+            Kind::Pubkey => Pubkey::try_from(bytes).unwrap().to_string(),
+            Kind::Signature => Signature::new(bytes).to_string(),
+            Kind::Bytes(_) => hex(bytes),
+        }
+    }
+}
+
+/// Fixed-size layout, fields in offset order
+struct Layout {
+    name: &'static str,
+    fields: &'static [(&'static str, Kind)],
+}
+
+const LAYOUTS: &[Layout] = &[
+    Layout {
+        name: "NonceStatus (L1 watched account)",
+        fields: &[("nonce", Kind::U64)],
+    },
+    Layout {
+        name: "NonceStatus (L1 watched account, two-counter)",
+        fields: &[
+            ("discriminator", Kind::Bytes(8)),
+            ("in_nonce", Kind::U64),
+            ("out_nonce", Kind::U64),
+            ("paused", Kind::U8),
+        ],
+    },
+    Layout {
+        name: "Info (transfer-info PDA, legacy)",
+        fields: &[
+            ("discriminator", Kind::Bytes(8)),
+            ("from", Kind::Pubkey),
+            ("to", Kind::Pubkey),
+            ("amount", Kind::U64),
+            ("message_type", Kind::U8),
+            ("reserved", Kind::Bytes(6)),
+        ],
+    },
+    Layout {
+        name: "Info (transfer-info PDA, V1 attested)",
+        fields: &[
+            ("discriminator", Kind::Bytes(8)),
+            ("from", Kind::Pubkey),
+            ("to", Kind::Pubkey),
+            ("amount", Kind::U64),
+            ("message_type", Kind::U8),
+            ("reserved", Kind::Bytes(6)),
+            ("attestation", Kind::Signature),
+        ],
+    },
+    Layout {
+        name: "L2 nonce account",
+        fields: &[
+            ("discriminator", Kind::Bytes(8)),
+            ("l1_nonce", Kind::U64),
+            ("l2_nonce", Kind::U64),
+        ],
+    },
+    Layout {
+        name: "L2 bridge config (domain)",
+        fields: &[("discriminator", Kind::Bytes(8)), ("domain", Kind::U64)],
+    },
+];
+
+/// Result of decoding an account with one layout
+struct Interpretation {
+    pub layout: &'static str,
+    /// Decoded fields, or why decoding failed
+    pub result: Result<Vec<(String, String)>, String>,
+}
+
+impl Layout {
+    fn decode(&self, data: &[u8]) -> Result<Vec<(String, String)>, String> {
+        let mut fields = Vec::with_capacity(self.fields.len() + 1);
+        let mut offset = 0;
+        for (name, kind) in self.fields {
+            let end = offset + kind.len();
+            let bytes = data.get(offset..end).ok_or_else(|| {
+                format!(
+                    "field {} at offset {}..{} is past the end of the {} bytes",
+                    name,
+                    offset,
+                    end,
+                    data.len()
+                )
+            })?;
+            fields.push((format!("{} @{}", name, offset), kind.format(bytes)));
+            offset = end;
+        }
+        if data.len() > offset {
+            fields.push((
+                format!("trailing @{}", offset),
+                format!("{} byte(s) not in this layout", data.len() - offset),
+            ));
+        }
+        Ok(fields)
+    }
+}
+
+/// Fields of a decoded transfer-info PDA
+fn transfer_info_fields(info: &TransferInfoAccount) -> Vec<(String, String)> {
+    let mut fields = vec![
+        ("version".to_string(), format!("{:?}", info.version)),
+        ("from".to_string(), info.fields.sender.to_string()),
+        ("to".to_string(), info.fields.recipient.to_string()),
+        ("amount".to_string(), info.fields.amount.to_string()),
+        (
+            "message_type".to_string(),
+            info.fields.message_type.to_string(),
+        ),
+        ("reserved".to_string(), hex(&info.fields.reserved)),
+    ];
+    if let Some(expires_at) = info.expires_at {
+        fields.push(("expires_at".to_string(), expires_at.to_string()));
+    }
+    if let Some(mint) = info.mint {
+        fields.push(("mint".to_string(), mint.to_string()));
+    }
+    fields
+}
+
+/// Decodes `data` with every known layout
+fn interpret(data: &[u8]) -> Vec<Interpretation> {
+    let mut interpretations: Vec<Interpretation> = LAYOUTS
+        .iter()
+        .map(|layout| Interpretation {
+            layout: layout.name,
+            result: layout.decode(data),
+        })
+        .collect();
+    interpretations.push(Interpretation {
+        layout: "Info (transfer-info PDA, Borsh, version by size)",
+        result: TransferInfoAccount::parse(data)
+            .map(|info| transfer_info_fields(&info))
+            .map_err(|e| e.to_string()),
+    });
+    interpretations.push(Interpretation {
+        layout: "Info (transfer-info PDA, Borsh, attested, version by size)",
+        result: TransferInfoAccount::split_attested(data)
+            .and_then(|(body, signature)| {
+                let mut fields = transfer_info_fields(&TransferInfoAccount::parse(body)?);
+                if let Some(signature) = signature {
+                    fields.push((format!("attestation @{}", body.len()), hex(signature)));
+                }
+                Ok(fields)
+            })
+            .map_err(|e| e.to_string()),
+    });
+    interpretations.push(Interpretation {
+        layout: "LeafChunkAccount (merkle mode)",
+        result: LeafChunk::from_bytes(data)
+            .map(|chunk| {
+                vec![
+                    ("leafs @12".to_string(), chunk.leafs.len().to_string()),
+                    (
+                        format!("root @{}", 12 + chunk.leafs.len() * 32),
+                        hex(&chunk.root),
+                    ),
+                ]
+            })
+            .map_err(|e| e.to_string()),
+    });
+    interpretations
+}
+
+/// Hex dump of `data` followed by every layout's decoded fields or failure
+pub fn render(data: &[u8]) -> String {
+    let mut rendered = hex_dump(data);
+    for interpretation in interpret(data) {
+        match interpretation.result {
+            Ok(fields) => {
+                let _ = writeln!(rendered, "\n{}: decoded", interpretation.layout);
+                for (name, value) in fields {
+                    let _ = writeln!(rendered, "- {}: {}", name, value);
+                }
+            }
+            Err(e) => {
+                let _ = writeln!(rendered, "\n{}: failed, {}", interpretation.layout, e);
+            }
+        }
+    }
+    rendered
+}
@@ -0,0 +1,70 @@
+//! Devnet convenience mode.
+//! Keeps the relayer wallet funded on local/dev clusters by requesting airdrops.
+
+use crate::clock::Clock;
+use anyhow::Result;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use std::time::Duration;
+
+/// Genesis hash of mainnet-beta; dev mode refuses to run against it
+const MAINNET_GENESIS_HASH: &str = "5eykt4UsFv8P8NJdTREpY1vzqKqZKvdpKuc147dw2N9d";
+const AIRDROP_CONFIRM_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct AirdropFunder {
+    threshold: u64,
+    amount: u64,
+}
+
+impl AirdropFunder {
+    /// Creates a funder after verifying the cluster is not mainnet
+    pub async fn new(client: &RpcClient, threshold: u64, amount: u64) -> Result<Self> {
+        let genesis_hash = client.get_genesis_hash().await?;
+        if genesis_hash.to_string() == MAINNET_GENESIS_HASH {
+            return Err(anyhow::anyhow!(
+                "dev_mode cannot be enabled against mainnet (genesis hash {})",
+                genesis_hash
+            ));
+        }
+
+        Ok(Self { threshold, amount })
+    }
+
+    /// Requests an airdrop if the balance of `pubkey` is below the threshold
+    /// and waits until it is confirmed
+    pub async fn ensure_funded(
+        &self,
+        client: &RpcClient,
+        clock: &dyn Clock,
+        pubkey: &Pubkey,
+    ) -> Result<()> {
+        let balance = client.get_balance(pubkey).await?;
+        if balance >= self.threshold {
+            return Ok(());
+        }
+
+        tracing::info!(
+            "Dev mode: balance of {} is {} lamports (threshold {}), requesting airdrop of {} lamports",
+            pubkey, balance, self.threshold, self.amount
+        );
+        let signature = client
+            .request_airdrop(pubkey, self.amount)
+            .await
+            .map_err(|e| anyhow::anyhow!("Airdrop request failed: {}", e))?;
+
+        let started = clock.now();
+        while !client.confirm_transaction(&signature).await? {
+            if clock.elapsed(started) > AIRDROP_CONFIRM_TIMEOUT {
+                return Err(anyhow::anyhow!(
+                    "Airdrop {} not confirmed within {:?}",
+                    signature,
+                    AIRDROP_CONFIRM_TIMEOUT
+                ));
+            }
+            clock.sleep(Duration::from_millis(500)).await;
+        }
+
+        tracing::info!("Airdrop confirmed: {}", signature);
+        Ok(())
+    }
+}
@@ -0,0 +1,59 @@
+//! Relaying direction of a bridge.
+//! A bridge relays deposits from L1 to L2 by default. With
+//! `direction = "l2_to_l1"` it relays withdrawals instead: the same pipeline
+//! watches the L2 withdrawal program and sends release transactions on L1,
+//! reading through `l2_url` and submitting through `l1_url`. Everything else
+//! keeps its role rather than its chain: the source settings
+//! (`l1_program_id`, `watched_account`, `[[sources]]`, the `l1_*` settings)
+//! describe the watched L2 program, and the destination settings
+//! (`l2_program_id`, `nonce_account`, the `l2_*` settings) the L1 release
+//! program, which takes the same `relay_message` instruction. Those settings
+//! are also read under their role, `source_*` for `l1_*` and
+//! `destination_*` for `l2_*`, e.g. `source_program_id`. Each direction
+//! is its own bridge, so it has its own nonce sequence, nonce account and
+//! state.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Which chain a bridge watches and which it submits to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// Deposits: watches L1, submits on L2
+    #[default]
+    L1ToL2,
+    /// Withdrawals: watches L2, submits on L1
+    L2ToL1,
+}
+
+impl Direction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::L1ToL2 => "l1_to_l2",
+            Self::L2ToL1 => "l2_to_l1",
+        }
+    }
+
+    /// The chain watched, `L1` or `L2`
+    pub fn source_chain(self) -> &'static str {
+        match self {
+            Self::L1ToL2 => "L1",
+            Self::L2ToL1 => "L2",
+        }
+    }
+
+    /// The chain transactions are sent to, whose fees the signer pays
+    pub fn destination_chain(self) -> &'static str {
+        match self {
+            Self::L1ToL2 => "L2",
+            Self::L2ToL1 => "L1",
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
@@ -0,0 +1,415 @@
+//! Dead-letter queue.
+//! Nonces that must not be relayed without operator review are recorded here;
+//! the submitter holds at a dead-lettered nonce instead of retrying it.
+//!
+//! Operators review entries with `dlq list`, attach notes with `dlq annotate`
+//! and close them with `dlq resolve`. A resolved entry stays in the state as
+//! the audit trail. `skipped` and `refunded` close the nonce without relaying
+//! it: the command writes a receipt without a transaction and the pipeline
+//! never fetches the nonce. `replayed` sends the nonce through the submitter
+//! again with its attempts reset, and the L2 signature it lands with is
+//! recorded on the entry. Running relayers reload the entries before each
+//! batch.
+
+use crate::{
+    alerts, attempts::AttemptRecord, journal::JournalEvent, receipts::Receipt, state::StateStore,
+    Relayer,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, sync::Mutex, time::Duration};
+
+/// State key holding the dead letters
+pub const DEAD_LETTERS_KEY: &str = "dead_letters";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub nonce: u64,
+    pub reason: String,
+    pub recorded_at: u64,
+    /// Failed attempts before the nonce was dead-lettered
+    #[serde(default)]
+    pub attempts: u32,
+    /// Most recent failed attempts, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failures: Vec<AttemptRecord>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<OperatorNote>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolution: Option<Resolution>,
+    /// Earlier entries of the nonce, resolved as replayed before it was
+    /// dead-lettered again
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub previous: Vec<DeadLetter>,
+    /// Rejected entries of a multi-entry PDA, by index, with the reason;
+    /// replaying the nonce sends only the entries without a receipt
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub entries: BTreeMap<u8, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OperatorNote {
+    /// Operator who wrote the note
+    pub by: String,
+    pub at: u64,
+    pub note: String,
+}
+
+/// How an operator closed a dead letter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum ResolutionKind {
+    /// Never relayed
+    Skipped,
+    /// Relayed again through the submitter
+    Replayed,
+    /// Compensated outside the bridge, never relayed
+    Refunded,
+}
+
+impl fmt::Display for ResolutionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            ResolutionKind::Skipped => "skipped",
+            ResolutionKind::Replayed => "replayed",
+            ResolutionKind::Refunded => "refunded",
+        };
+        f.write_str(kind)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Resolution {
+    #[serde(rename = "as")]
+    pub kind: ResolutionKind,
+    /// Operator who resolved the entry
+    pub by: String,
+    pub at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// L2 transaction the replayed nonce landed in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub l2_signature: Option<String>,
+    /// Whether a relayer has picked the resolution up
+    #[serde(default)]
+    pub applied: bool,
+}
+
+impl DeadLetter {
+    /// Whether the entry still awaits operator review
+    pub fn is_open(&self) -> bool {
+        self.resolution.is_none()
+    }
+
+    /// Lines printed by `dlq list`, `now` giving the age
+    pub fn to_text(&self, now: u64) -> String {
+        let mut text = format!(
+            "- Nonce {} [{}]: {} attempt(s), dead-lettered {:?} ago (at {}): {}",
+            self.nonce,
+            self.resolution
+                .as_ref()
+                .map_or("open".to_string(), |resolution| resolution.kind.to_string()),
+            self.attempts,
+            Duration::from_secs(now.saturating_sub(self.recorded_at)),
+            self.recorded_at,
+            self.reason
+        );
+        for (index, reason) in &self.entries {
+            text.push_str(&format!("\n    entry {}: {}", index, reason));
+        }
+        for failure in &self.failures {
+            text.push_str(&format!("\n    {}", failure));
+        }
+        for note in &self.notes {
+            text.push_str(&format!(
+                "\n    note by {} at {}: {}",
+                note.by, note.at, note.note
+            ));
+        }
+        if let Some(resolution) = &self.resolution {
+            text.push_str(&format!(
+                "\n    resolved as {} by {} at {}",
+                resolution.kind, resolution.by, resolution.at
+            ));
+            if let Some(note) = &resolution.note {
+                text.push_str(&format!(": {}", note));
+            }
+            match (&resolution.l2_signature, resolution.kind) {
+                (Some(signature), _) => text.push_str(&format!("\n    relayed in {}", signature)),
+                (None, ResolutionKind::Replayed) => text.push_str("\n    not relayed yet"),
+                (None, _) => {}
+            }
+        }
+        if !self.previous.is_empty() {
+            text.push_str(&format!(
+                "\n    dead-lettered {} time(s) before",
+                self.previous.len()
+            ));
+        }
+        text
+    }
+}
+
+/// A dead letter as `dlq list --json` prints it
+#[derive(Serialize)]
+pub struct ListedDeadLetter<'a> {
+    pub source: &'a str,
+    pub age_secs: u64,
+    #[serde(flatten)]
+    pub letter: &'a DeadLetter,
+}
+
+pub struct DeadLetterQueue {
+    entries: Mutex<BTreeMap<u64, DeadLetter>>,
+}
+
+impl DeadLetterQueue {
+    pub fn load(store: &StateStore) -> Result<Self> {
+        let entries: BTreeMap<u64, DeadLetter> = store.get(DEAD_LETTERS_KEY)?.unwrap_or_default();
+        let open = entries.values().filter(|letter| letter.is_open()).count();
+        if open > 0 {
+            tracing::info!("{} dead-lettered nonce(s) awaiting operator review", open);
+        }
+        Ok(Self {
+            entries: Mutex::new(entries),
+        })
+    }
+
+    /// Records `letter`; returns false if the nonce is already dead-lettered
+    /// or closed without relaying. An entry resolved as replayed is kept in
+    /// the new one's `previous`
+    pub fn push(&self, store: &StateStore, mut letter: DeadLetter) -> Result<bool> {
+        let mut entries = self.entries.lock().unwrap();
+        // 运维命令可能刚改过存储，以存储为准
+        *entries = store.get(DEAD_LETTERS_KEY)?.unwrap_or_default();
+        if let Some(existing) = entries.get(&letter.nonce) {
+            match &existing.resolution {
+                Some(resolution) if resolution.kind == ResolutionKind::Replayed => {
+                    let mut earlier = existing.clone();
+                    letter.previous = std::mem::take(&mut earlier.previous);
+                    letter.previous.push(earlier);
+                }
+                _ => return Ok(false),
+            }
+        }
+        entries.insert(letter.nonce, letter);
+        store.put(DEAD_LETTERS_KEY, &*entries)?;
+        Ok(true)
+    }
+
+    /// Number of entries awaiting review
+    pub fn len(&self) -> usize {
+        self.entries
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|letter| letter.is_open())
+            .count()
+    }
+
+    /// Whether `nonce` awaits review
+    pub fn contains(&self, nonce: u64) -> bool {
+        self.entries
+            .lock()
+            .unwrap()
+            .get(&nonce)
+            .is_some_and(DeadLetter::is_open)
+    }
+
+    /// Whether an operator closed `nonce` without relaying it
+    pub fn closed_unrelayed(&self, nonce: u64) -> bool {
+        self.entries
+            .lock()
+            .unwrap()
+            .get(&nonce)
+            .and_then(|letter| letter.resolution.as_ref())
+            .is_some_and(|resolution| resolution.kind != ResolutionKind::Replayed)
+    }
+
+    /// Whether an operator resolved `nonce` as replayed
+    pub fn replayed(&self, nonce: u64) -> bool {
+        self.entries
+            .lock()
+            .unwrap()
+            .get(&nonce)
+            .and_then(|letter| letter.resolution.as_ref())
+            .is_some_and(|resolution| resolution.kind == ResolutionKind::Replayed)
+    }
+
+    /// Lowest nonce in `from..to` awaiting review, if any
+    pub fn first_in(&self, from: u64, to: u64) -> Option<u64> {
+        self.entries
+            .lock()
+            .unwrap()
+            .range(from..to)
+            .find(|(_, letter)| letter.is_open())
+            .map(|(nonce, _)| *nonce)
+    }
+
+    /// Every entry, open and resolved, by nonce
+    pub fn entries(&self) -> Vec<DeadLetter> {
+        self.entries.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Attaches an operator note to the entry of `nonce`
+    pub fn annotate(&self, store: &StateStore, nonce: u64, note: OperatorNote) -> Result<()> {
+        self.update(store, nonce, |letter| {
+            letter.notes.push(note);
+            Ok(())
+        })
+    }
+
+    /// Closes the open entry of `nonce`. Skipped and refunded nonces get a
+    /// receipt without a transaction, so they count as done everywhere
+    pub fn resolve(&self, store: &StateStore, nonce: u64, resolution: Resolution) -> Result<()> {
+        let kind = resolution.kind;
+        let at = resolution.at;
+        if let Some(receipt) = Receipt::load(store, nonce)? {
+            if !receipt.signature.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "Nonce {} was already relayed in {}",
+                    nonce,
+                    receipt.signature
+                ));
+            }
+        }
+        self.update(store, nonce, |letter| {
+            if let Some(existing) = &letter.resolution {
+                return Err(anyhow::anyhow!(
+                    "Dead letter {} was already resolved as {} by {}",
+                    nonce,
+                    existing.kind,
+                    existing.by
+                ));
+            }
+            letter.resolution = Some(resolution);
+            Ok(())
+        })?;
+        if kind != ResolutionKind::Replayed {
+            Receipt::resolved(nonce, kind, at).save(store)?;
+        }
+        Ok(())
+    }
+
+    /// Re-reads the entries written by operator commands; returns the
+    /// resolutions no relayer has picked up yet and marks them applied
+    pub fn reload(&self, store: &StateStore) -> Result<Vec<(u64, ResolutionKind)>> {
+        let mut entries = self.entries.lock().unwrap();
+        *entries = store.get(DEAD_LETTERS_KEY)?.unwrap_or_default();
+        let mut applied = Vec::new();
+        for letter in entries.values_mut() {
+            if let Some(resolution) = letter.resolution.as_mut().filter(|r| !r.applied) {
+                resolution.applied = true;
+                applied.push((letter.nonce, resolution.kind));
+            }
+        }
+        if !applied.is_empty() {
+            store.put(DEAD_LETTERS_KEY, &*entries)?;
+        }
+        Ok(applied)
+    }
+
+    /// Records the L2 transaction a replayed nonce landed in
+    pub fn link_replay(&self, store: &StateStore, nonce: u64, signature: &str) -> Result<()> {
+        let replayed = self
+            .entries
+            .lock()
+            .unwrap()
+            .get(&nonce)
+            .and_then(|letter| letter.resolution.as_ref())
+            .is_some_and(|resolution| {
+                resolution.kind == ResolutionKind::Replayed && resolution.l2_signature.is_none()
+            });
+        if !replayed {
+            return Ok(());
+        }
+        self.update(store, nonce, |letter| {
+            if let Some(resolution) = &mut letter.resolution {
+                resolution.l2_signature = Some(signature.to_string());
+            }
+            Ok(())
+        })
+    }
+
+    /// Records entry `index` of the multi-entry PDA of `nonce` as rejected
+    /// for `reason` in its dead letter
+    pub fn reject_entry(
+        &self,
+        store: &StateStore,
+        nonce: u64,
+        index: u8,
+        reason: &str,
+    ) -> Result<()> {
+        self.update(store, nonce, |letter| {
+            letter.entries.insert(index, reason.to_string());
+            Ok(())
+        })
+    }
+
+    /// Re-reads the entries, applies `change` to the one of `nonce` and
+    /// persists them
+    fn update(
+        &self,
+        store: &StateStore,
+        nonce: u64,
+        change: impl FnOnce(&mut DeadLetter) -> Result<()>,
+    ) -> Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        *entries = store.get(DEAD_LETTERS_KEY)?.unwrap_or_default();
+        let letter = entries
+            .get_mut(&nonce)
+            .ok_or_else(|| anyhow::anyhow!("Nonce {} is not in the dead-letter queue", nonce))?;
+        change(letter)?;
+        store.put(DEAD_LETTERS_KEY, &*entries)
+    }
+}
+
+impl Relayer {
+    /// Moves `nonce` to the dead-letter queue, alerting the first time
+    pub(crate) fn dead_letter(&self, nonce: u64, reason: &str) -> Result<()> {
+        let letter = DeadLetter {
+            nonce,
+            reason: reason.to_string(),
+            recorded_at: self.clock.unix_timestamp(),
+            attempts: self.queue.attempts(nonce),
+            failures: self.queue.failures(nonce),
+            notes: Vec::new(),
+            resolution: None,
+            previous: Vec::new(),
+            entries: BTreeMap::new(),
+        };
+        if self.dead_letters.push(&self.state, letter)? {
+            self.journal(
+                None,
+                JournalEvent::DeadLettered {
+                    nonce,
+                    reason: reason.to_string(),
+                },
+            );
+            alerts::raise(&format!(
+                "Nonce {} moved to the dead-letter queue: {}",
+                nonce, reason
+            ));
+        }
+        Ok(())
+    }
+
+    /// Picks up dead letters resolved by operators since the last batch; a
+    /// replayed nonce starts over with no failed attempts
+    pub(crate) fn refresh_dead_letters(&self) -> Result<()> {
+        for (nonce, kind) in self.dead_letters.reload(&self.state)? {
+            tracing::info!("Dead letter {} resolved as {}", nonce, kind);
+            if kind == ResolutionKind::Replayed {
+                self.queue.clear_retry(&self.state, nonce)?;
+            }
+            self.journal(
+                None,
+                JournalEvent::DeadLetterResolved {
+                    nonce,
+                    resolution: kind,
+                },
+            );
+        }
+        Ok(())
+    }
+}
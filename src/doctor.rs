@@ -0,0 +1,730 @@
+//! Configuration diagnosis.
+//! `doctor` runs the startup checks of every source (without the dev-mode
+//! airdrop) and a set of extra checks that explain common misconfigurations:
+//! RPC latency of both clusters, how far each endpoint trails finality and
+//! the primary L1 endpoint, the staleness of every L1 endpoint's confirmed
+//! and finalized view of the watched account, the host clock against block
+//! times, the message encoding against its frozen test vectors, whether the
+//! PDA seed scheme finds the transfer-info account of the current nonce, and
+//! the wallet balance against the last day's spend. Nothing is sent and no
+//! state is written, so it can run against production next to a relayer.
+
+use crate::{
+    config::{RelayerConfig, SourceConfig},
+    merkle::MessageSource,
+    models::message::{check_info_vectors, NonceStatus, WatchedLayout, INFO_ENCODING_VERSION},
+    receipts::Receipt,
+    rpc_pool::{destination_client, source_client},
+    signer::Signers,
+    signing::{read_signers, SignerBackend},
+    source_state, Relayer,
+};
+use serde::Serialize;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{commitment_config::CommitmentConfig, native_token::lamports_to_sol};
+use std::{
+    fmt::Write,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// RPC round trips timed per cluster
+const LATENCY_SAMPLES: usize = 5;
+/// Median round trip above which latency is a warning
+const SLOW_RPC: Duration = Duration::from_millis(500);
+/// Slots an endpoint may trail finality, or the primary L1 endpoint, before
+/// it is a warning
+const FINALITY_LAG_SLOTS: u64 = 100;
+const ENDPOINT_LAG_SLOTS: u64 = 25;
+/// Difference between the host clock and the latest block time that is a warning
+const CLOCK_SKEW_SECS: i64 = 30;
+/// Days of spend the wallet should cover
+const BALANCE_WARN_DAYS: f64 = 7.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+    /// What to change when the check does not pass
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct DoctorReport {
+    pub checks: Vec<Check>,
+}
+
+impl DoctorReport {
+    fn add(&mut self, name: &str, status: CheckStatus, detail: String, hint: Option<String>) {
+        self.checks.push(Check {
+            name: name.to_string(),
+            status,
+            detail,
+            hint,
+        });
+    }
+
+    fn pass(&mut self, name: &str, detail: String) {
+        self.add(name, CheckStatus::Pass, detail, None);
+    }
+
+    fn warn(&mut self, name: &str, detail: String, hint: &str) {
+        self.add(name, CheckStatus::Warn, detail, Some(hint.to_string()));
+    }
+
+    fn fail(&mut self, name: &str, detail: String, hint: &str) {
+        self.add(name, CheckStatus::Fail, detail, Some(hint.to_string()));
+    }
+
+    pub fn count(&self, status: CheckStatus) -> usize {
+        self.checks
+            .iter()
+            .filter(|check| check.status == status)
+            .count()
+    }
+
+    /// One line per check, colored with ANSI escapes when `color` is set
+    pub fn to_text(&self, color: bool) -> String {
+        let mut out = String::new();
+        for check in &self.checks {
+            let (label, escape) = match check.status {
+                CheckStatus::Pass => ("PASS", "\x1b[32m"),
+                CheckStatus::Warn => ("WARN", "\x1b[33m"),
+                CheckStatus::Fail => ("FAIL", "\x1b[31m"),
+            };
+            let label = if color {
+                format!("{}{}\x1b[0m", escape, label)
+            } else {
+                label.to_string()
+            };
+            let _ = writeln!(out, "[{}] {}: {}", label, check.name, check.detail);
+            if let Some(hint) = &check.hint {
+                let _ = writeln!(out, "       hint: {}", hint);
+            }
+        }
+        let _ = writeln!(
+            out,
+            "\n{} passed, {} warning(s), {} failed",
+            self.count(CheckStatus::Pass),
+            self.count(CheckStatus::Warn),
+            self.count(CheckStatus::Fail)
+        );
+        out
+    }
+}
+
+/// Runs every check against the clusters and state described by `config`
+pub async fn diagnose(config: &RelayerConfig) -> DoctorReport {
+    let mut report = DoctorReport::default();
+    let l1 = RpcClient::new_with_commitment(
+        config.source_url().expose().to_string(),
+        config.l1_commitment.config(),
+    );
+    let l2 = RpcClient::new_with_commitment(
+        config.destination_url().expose().to_string(),
+        config.l2_commitment.config(),
+    );
+
+    let l1_slot = check_endpoint(
+        &mut report,
+        "L1",
+        &config.redact(&config.source_url().to_string()),
+        &l1,
+    )
+    .await;
+    let l2_slot = check_endpoint(
+        &mut report,
+        "L2",
+        &config.redact(&config.destination_url().to_string()),
+        &l2,
+    )
+    .await;
+    if let Some(primary) = l1_slot {
+        for (index, url) in config.l1_verify_urls.iter().enumerate() {
+            let name = format!("L1 verifier {} lag", index);
+            let verifier = RpcClient::new_with_commitment(
+                url.expose().to_string(),
+                config.l1_commitment.config(),
+            );
+            match verifier.get_slot().await {
+                Ok(slot) if primary.saturating_sub(slot) > ENDPOINT_LAG_SLOTS => report.warn(
+                    &name,
+                    format!("{} slots behind l1_url", primary - slot),
+                    "A lagging verifier holds high-value transfers as lag mismatches; use a provider that keeps up",
+                ),
+                Ok(slot) => report.pass(
+                    &name,
+                    format!("within {} slot(s) of l1_url", primary.abs_diff(slot)),
+                ),
+                Err(e) => report.fail(
+                    &name,
+                    format!("{}: {}", config.redact(&url.to_string()), e),
+                    "Check l1_verify_urls; dual-read holds high-value transfers while a verifier is unreachable",
+                ),
+            }
+        }
+    }
+    for (cluster, endpoints) in [
+        ("L1", config.source_endpoints()),
+        ("L2", config.destination_endpoints()),
+    ] {
+        // 备用端点只检查能否应答，主端点已在上面检查
+        for (field, url) in endpoints.into_iter().skip(1) {
+            let name = format!("{} RPC {}", cluster, field);
+            let client = RpcClient::new_with_commitment(
+                url.expose().to_string(),
+                CommitmentConfig::confirmed(),
+            );
+            match client.get_slot().await {
+                Ok(slot) => report.pass(
+                    &name,
+                    format!("{}: at slot {}", config.redact(&url.to_string()), slot),
+                ),
+                Err(e) => report.warn(
+                    &name,
+                    format!("{}: {}", config.redact(&url.to_string()), e),
+                    &format!(
+                        "Failover skips this endpoint while it is down; fix or remove {}",
+                        field
+                    ),
+                ),
+            }
+        }
+    }
+    check_clock(&mut report, &l1, l1_slot, &l2, l2_slot).await;
+    match check_info_vectors() {
+        Ok(checked) => report.pass(
+            "Message encoding",
+            format!(
+                "version {} matches {} frozen vector(s)",
+                INFO_ENCODING_VERSION, checked
+            ),
+        ),
+        Err(e) => report.fail(
+            "Message encoding",
+            e.to_string(),
+            "Message digests in receipts, proofs and memos would change; restore the encoding or bump its version",
+        ),
+    }
+
+    // 远程签名服务只检查配置，不请求签名
+    let primary = match config.signer_backend {
+        SignerBackend::File => "wallet",
+        SignerBackend::Remote => "remote signer",
+    };
+    let signers = match read_signers(config) {
+        Ok((keypair, secondary)) => {
+            report.pass(
+                "Signer keys",
+                match &secondary {
+                    Some(secondary) => format!(
+                        "{} {}, secondary {}",
+                        primary,
+                        keypair.pubkey(),
+                        secondary.pubkey()
+                    ),
+                    None => format!("{} {}", primary, keypair.pubkey()),
+                },
+            );
+            Some((keypair, secondary))
+        }
+        Err(e) => {
+            report.fail(
+                "Signer keys",
+                e.to_string(),
+                "Point wallet_path (and secondary_wallet_path) at readable Solana keypair files, or set remote_signer_url and remote_signer_pubkey",
+            );
+            None
+        }
+    };
+
+    let sources = match config.sources() {
+        Ok(sources) => sources,
+        Err(e) => {
+            report.fail("Sources", e.to_string(), "Check the [[sources]] entries");
+            return report;
+        }
+    };
+    let Some((keypair, secondary)) = signers else {
+        return report;
+    };
+    let wallet = keypair.pubkey();
+    let mut keys = Some((keypair, secondary));
+    for source in &sources {
+        // 每个 source 都需要自己的 Signers，密钥文件重新读取
+        let (keypair, secondary) = match keys.take() {
+            Some(keys) => keys,
+            None => match read_signers(config) {
+                Ok(keys) => keys,
+                Err(_) => break,
+            },
+        };
+        check_source(
+            &mut report,
+            config,
+            source,
+            Signers::with_signers(keypair, secondary),
+        )
+        .await;
+    }
+    check_balance(&mut report, config, &sources, &l2, &wallet).await;
+    report
+}
+
+/// Times `getSlot` and compares the confirmed and finalized slots; returns
+/// the confirmed slot if the endpoint answered
+async fn check_endpoint(
+    report: &mut DoctorReport,
+    cluster: &str,
+    url: &str,
+    client: &RpcClient,
+) -> Option<u64> {
+    let name = format!("{} RPC", cluster);
+    let mut round_trips = Vec::with_capacity(LATENCY_SAMPLES);
+    let mut slot = 0;
+    for _ in 0..LATENCY_SAMPLES {
+        let started = Instant::now();
+        match client.get_slot().await {
+            Ok(read) => slot = read,
+            Err(e) => {
+                report.fail(
+                    &name,
+                    format!("{}: {}", url, e),
+                    &format!(
+                        "Check {}_url, the network path and any API key the provider requires",
+                        cluster.to_lowercase()
+                    ),
+                );
+                return None;
+            }
+        }
+        round_trips.push(started.elapsed());
+    }
+    round_trips.sort_unstable();
+    let median = round_trips[round_trips.len() / 2];
+    let detail = format!(
+        "{}: median {:?}, max {:?} over {} reads",
+        url,
+        median,
+        round_trips[round_trips.len() - 1],
+        LATENCY_SAMPLES
+    );
+    if median > SLOW_RPC {
+        report.warn(
+            &name,
+            detail,
+            "Slow RPC delays detection and confirmation; use a provider closer to the relayer",
+        );
+    } else {
+        report.pass(&name, detail);
+    }
+
+    let name = format!("{} finality lag", cluster);
+    match client
+        .get_slot_with_commitment(CommitmentConfig::finalized())
+        .await
+    {
+        Ok(finalized) if slot.saturating_sub(finalized) > FINALITY_LAG_SLOTS => report.warn(
+            &name,
+            format!(
+                "confirmed slot {} is {} slots ahead of finalized slot {}",
+                slot,
+                slot - finalized,
+                finalized
+            ),
+            "The node is slow to finalize; reorg protection and confirmation holds take longer on it",
+        ),
+        Ok(finalized) => report.pass(
+            &name,
+            format!(
+                "confirmed slot {}, finalized {} ({} behind)",
+                slot,
+                finalized,
+                slot.saturating_sub(finalized)
+            ),
+        ),
+        Err(e) => report.warn(
+            &name,
+            format!("failed to read the finalized slot: {}", e),
+            "The provider may restrict commitment levels; the relayer reads at confirmed",
+        ),
+    }
+    Some(slot)
+}
+
+/// Compares the host clock with the block times of both clusters
+async fn check_clock(
+    report: &mut DoctorReport,
+    l1: &RpcClient,
+    l1_slot: Option<u64>,
+    l2: &RpcClient,
+    l2_slot: Option<u64>,
+) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64);
+    let mut skews = Vec::new();
+    for (cluster, client, slot) in [("L1", l1, l1_slot), ("L2", l2, l2_slot)] {
+        let Some(slot) = slot else {
+            continue;
+        };
+        match client.get_block_time(slot).await {
+            Ok(block_time) => skews.push((cluster, now - block_time)),
+            Err(e) => report.warn(
+                "Clock skew",
+                format!(
+                    "failed to read the {} block time of slot {}: {}",
+                    cluster, slot, e
+                ),
+                "The provider may not serve getBlockTime; check the host clock with NTP",
+            ),
+        }
+    }
+    if skews.is_empty() {
+        return;
+    }
+    let detail = skews
+        .iter()
+        .map(|(cluster, skew)| format!("host is {:+}s from the {} block time", skew, cluster))
+        .collect::<Vec<_>>()
+        .join(", ");
+    if skews.iter().any(|(_, skew)| skew.abs() > CLOCK_SKEW_SECS) {
+        report.warn(
+            "Clock skew",
+            detail,
+            "Sync the host clock (NTP); retry backoff, rate limits and receipt times use it",
+        );
+    } else {
+        report.pass("Clock skew", detail);
+    }
+}
+
+/// Runs the startup checks of `source`, then reads its watched account, the
+/// transfer-info PDA of its current nonce and its L2 nonce account
+async fn check_source(
+    report: &mut DoctorReport,
+    config: &RelayerConfig,
+    source: &SourceConfig,
+    signers: Signers,
+) {
+    let prefix = if config.namespaced_sources() {
+        format!("Source {}: ", source.id)
+    } else {
+        String::new()
+    };
+    let name = |check: &str| format!("{}{}", prefix, check);
+
+    // 诊断不得修改链上状态，跳过 dev_mode 空投
+    let mut config = config.clone();
+    config.dev_mode = false;
+    let state = match source_state(&config, source) {
+        Ok(state) => state,
+        Err(e) => {
+            report.fail(
+                &name("State directory"),
+                e.to_string(),
+                "Make state_dir readable by the relayer user",
+            );
+            return;
+        }
+    };
+    let relayer = match Relayer::with_clients(
+        &config,
+        source,
+        signers,
+        source_client(&config),
+        destination_client(&config),
+        state,
+        Arc::new(crate::clock::SystemClock),
+    )
+    .await
+    {
+        Ok(relayer) => {
+            report.pass(
+                &name("Startup checks"),
+                "the relayer would start".to_string(),
+            );
+            relayer
+        }
+        Err(e) => {
+            report.fail(
+                &name("Startup checks"),
+                format!("{:#}", e),
+                "The relayer refuses to start with this error; fix what it names",
+            );
+            return;
+        }
+    };
+
+    let l1_nonce = check_watched_account(report, &name("Watched account"), &relayer).await;
+    for lag in relayer.probe_l1_lag().await {
+        let check = name(&format!("L1 endpoint {} staleness", lag.endpoint));
+        if lag.error.is_some() {
+            report.warn(
+                &check,
+                lag.to_string(),
+                "The endpoint did not answer at both commitments; its lag is unknown",
+            );
+        } else if lag.quarantined {
+            report.warn(
+                &check,
+                lag.to_string(),
+                "The provider serves stale data; replace it or raise l1_lag_quarantine_slots",
+            );
+        } else {
+            report.pass(&check, lag.to_string());
+        }
+    }
+    let l2_nonce = match relayer.read_l2_relayed_nonce().await {
+        Ok(nonce) => {
+            report.pass(
+                &name("L2 nonce account"),
+                format!(
+                    "{} expects L1 nonce {}",
+                    relayer.transaction_builder.nonce_account, nonce
+                ),
+            );
+            Some(nonce)
+        }
+        Err(e) => {
+            report.fail(
+                &name("L2 nonce account"),
+                e.to_string(),
+                "Check nonce_account and that l2_url points at the cluster the L2 program is deployed on",
+            );
+            None
+        }
+    };
+    if let Some(durable) = &relayer.durable_nonce {
+        match relayer.relay_blockhash().await {
+            Ok((nonce, _)) => report.pass(
+                &name("Durable nonce account"),
+                format!("{} holds nonce {}", durable.account, nonce),
+            ),
+            Err(e) => report.fail(
+                &name("Durable nonce account"),
+                format!("{:#}", e),
+                "Create the nonce account on L2 with the signer as its authority (solana create-nonce-account)",
+            ),
+        }
+    }
+    if config.message_source == MessageSource::Pda {
+        if let Some(l1_nonce) = l1_nonce {
+            check_seed_scheme(
+                report,
+                &name("PDA seed scheme"),
+                &relayer,
+                l1_nonce,
+                l2_nonce,
+            )
+            .await;
+        }
+    }
+}
+
+/// Reads the watched account; returns its nonce if it decodes
+async fn check_watched_account(
+    report: &mut DoctorReport,
+    name: &str,
+    relayer: &Relayer,
+) -> Option<u64> {
+    let account = match relayer
+        .l1_client
+        .get_account_with_commitment(
+            relayer.watched_account.pubkey(),
+            relayer.l1_client.commitment(),
+        )
+        .await
+    {
+        Ok(response) => response.value,
+        Err(e) => {
+            report.fail(name, e.to_string(), "Check l1_url");
+            return None;
+        }
+    };
+    let Some(account) = account else {
+        report.fail(
+            name,
+            format!("{} not found", relayer.watched_account),
+            "Check watched_account and that l1_url points at the cluster the L1 program is deployed on",
+        );
+        return None;
+    };
+    let Some(layout) = WatchedLayout::detect(account.data.len()) else {
+        report.fail(
+            name,
+            format!(
+                "{} holds only {} bytes",
+                relayer.watched_account,
+                account.data.len()
+            ),
+            "watched_account must be the L1 program's nonce account",
+        );
+        return None;
+    };
+    match NonceStatus::parse(&account.data, layout) {
+        Ok(status) => {
+            let paused = if status.paused {
+                ", bridging paused"
+            } else {
+                ""
+            };
+            report.pass(
+                name,
+                format!(
+                    "{} at nonce {} ({} layout, {} bytes, owner {}{})",
+                    relayer.watched_account,
+                    status.nonce,
+                    layout,
+                    account.data.len(),
+                    account.owner,
+                    paused
+                ),
+            );
+            Some(status.nonce)
+        }
+        Err(e) => {
+            report.fail(
+                name,
+                e.to_string(),
+                "watched_account must be the L1 program's nonce account",
+            );
+            None
+        }
+    }
+}
+
+/// Looks up the transfer-info PDA of the next nonce to relay, or of the last
+/// transfer when L2 has caught up
+async fn check_seed_scheme(
+    report: &mut DoctorReport,
+    name: &str,
+    relayer: &Relayer,
+    l1_nonce: u64,
+    l2_nonce: Option<u64>,
+) {
+    let pending = l2_nonce.filter(|&l2_nonce| l2_nonce < l1_nonce);
+    let nonce = match pending {
+        Some(nonce) => nonce,
+        None if l1_nonce == 0 => {
+            report.pass(
+                name,
+                "no transfer on L1 yet, nothing to look up".to_string(),
+            );
+            return;
+        }
+        None => l1_nonce - 1,
+    };
+    let (pda, _) = relayer.pda_manager.find_address(nonce);
+    match relayer
+        .pda_manager
+        .get_transfer_info(&relayer.l1_client, &pda)
+        .await
+    {
+        Ok((amount, to, _)) => report.pass(
+            name,
+            format!(
+                "nonce {} found at {}: {} SOL to {}",
+                nonce,
+                pda,
+                lamports_to_sol(amount),
+                to
+            ),
+        ),
+        // 待处理的 nonce 必须能找到；已处理的可能已被 L1 程序关闭
+        Err(e) if pending.is_some() => report.fail(
+            name,
+            format!("pending nonce {}: {}", nonce, e),
+            "The seeds do not derive the L1 program's PDAs; check seeds (prefix, include_watched_account, nonce_encoding) and l1_program_id",
+        ),
+        Err(e) => report.warn(
+            name,
+            format!("last nonce {}: {}", nonce, e),
+            "Unless the L1 program closes relayed PDAs, check seeds and l1_program_id",
+        ),
+    }
+}
+
+/// Compares the active wallet's balance with what the receipts of the last
+/// day spent
+async fn check_balance(
+    report: &mut DoctorReport,
+    config: &RelayerConfig,
+    sources: &[SourceConfig],
+    l2: &RpcClient,
+    wallet: &solana_sdk::pubkey::Pubkey,
+) {
+    let name = "Wallet balance";
+    let balance = match l2.get_balance(wallet).await {
+        Ok(balance) => balance,
+        Err(e) => {
+            report.fail(name, e.to_string(), "Check l2_url");
+            return;
+        }
+    };
+    if let Some(minimum) = config
+        .min_signer_balance
+        .filter(|&minimum| balance < minimum)
+    {
+        report.fail(
+            name,
+            format!(
+                "{} SOL, below min_signer_balance {} SOL; no batch is started",
+                lamports_to_sol(balance),
+                lamports_to_sol(minimum)
+            ),
+            "Fund the wallet or lower min_signer_balance",
+        );
+        return;
+    }
+    let since = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+        .saturating_sub(86_400);
+    let (mut fees, mut spent, mut transfers) = (0u64, 0u64, 0u64);
+    for source in sources {
+        let Ok(receipts) = source_state(config, source).and_then(|state| Receipt::all(&state))
+        else {
+            continue;
+        };
+        for receipt in receipts
+            .iter()
+            .filter(|receipt| receipt.relayed_at >= since && !receipt.signature.is_empty())
+        {
+            transfers += 1;
+            fees += receipt.actual_fee.unwrap_or(receipt.l2_fee);
+            spent += receipt.actual_cost.unwrap_or(receipt.estimated_cost);
+        }
+    }
+    let detail = format!(
+        "{} SOL; the last 24h relayed {} transfer(s) for {} SOL, {} SOL of it fees",
+        lamports_to_sol(balance),
+        transfers,
+        lamports_to_sol(spent),
+        lamports_to_sol(fees)
+    );
+    if spent == 0 {
+        report.pass(name, detail);
+        return;
+    }
+    let days = balance as f64 / spent as f64;
+    let detail = format!("{}: covers {:.1} day(s) at that rate", detail, days);
+    let hint = "Fund the wallet; relayed amounts and fees are paid from it";
+    if days < 1.0 {
+        report.fail(name, detail, hint);
+    } else if days < BALANCE_WARN_DAYS {
+        report.warn(name, detail, hint);
+    } else {
+        report.pass(name, detail);
+    }
+}
@@ -0,0 +1,72 @@
+//! Bridge domain separation.
+//! Staging and production L2 run the same program, so a relay transaction
+//! built for one would also be accepted by the other. With `bridge_domain`
+//! set, instruction version 3 carries the domain id and the L2 program
+//! rejects transactions for another domain. At startup the domain is checked
+//! against the one the program's config account (PDA `["config"]`, domain as
+//! u64 LE after the 8-byte discriminator) advertises.
+
+use anyhow::Result;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+
+/// Seed of the L2 program's config account
+const CONFIG_SEED: &[u8] = b"config";
+
+/// Offset of the domain id in the config account data
+pub(crate) const DOMAIN_OFFSET: usize = 8;
+
+/// Address of the config account of `program_id`
+pub fn config_address(program_id: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[CONFIG_SEED], program_id).0
+}
+
+/// Config account data advertising `domain`
+#[cfg(feature = "testing")]
+pub fn config_data(domain: u64) -> Vec<u8> {
+    let mut data = vec![0u8; DOMAIN_OFFSET];
+    data.extend_from_slice(&domain.to_le_bytes());
+    data
+}
+
+/// Fails unless the config account of `program_id` advertises `domain`
+pub async fn verify(client: &RpcClient, program_id: &Pubkey, domain: u64) -> Result<()> {
+    let address = config_address(program_id);
+    let account = client
+        .get_account_with_commitment(&address, client.commitment())
+        .await?
+        .value
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Config account {} of L2 program {} not found; cannot verify bridge domain {}",
+                address,
+                program_id,
+                domain
+            )
+        })?;
+    let advertised = account
+        .data
+        .get(DOMAIN_OFFSET..DOMAIN_OFFSET + 8)
+        .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Config account {} is {} bytes, too short to hold a bridge domain",
+                address,
+                account.data.len()
+            )
+        })?;
+    if advertised != domain {
+        return Err(anyhow::anyhow!(
+            "L2 program {} serves bridge domain {}, but bridge_domain is {}; refusing to relay to the wrong cluster",
+            program_id,
+            advertised,
+            domain
+        ));
+    }
+    tracing::info!(
+        "Bridge domain {} matches L2 config account {}",
+        domain,
+        address
+    );
+    Ok(())
+}
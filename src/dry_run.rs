@@ -0,0 +1,111 @@
+//! Dry-run mode.
+//! With `run --dry-run` (or `dry_run = true`) the whole pipeline runs as
+//! usual, from the L1 reads to the built and signed relay transaction, but
+//! the send stage simulates each transaction on L2 instead of sending it and
+//! logs the simulated result and the compute units it consumed. Nothing is
+//! sent, so no fees or transfers are paid.
+//!
+//! State writes are kept in memory on top of `state_dir`, so a dry run
+//! against a live deployment never moves its queue or writes receipts; the
+//! warm-up transaction, the journal and the receipt sink are off.
+
+use crate::{state::StorageBackend, PreparedTransfer, Relayer};
+use anyhow::Result;
+use solana_client::rpc_config::RpcSimulateTransactionConfig;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+/// Storage backend answering reads from `inner` and keeping every write and
+/// removal in memory
+pub struct DryRunStorage {
+    inner: Arc<dyn StorageBackend>,
+    /// Written data, or none for a removal
+    writes: Mutex<BTreeMap<String, Option<Vec<u8>>>>,
+}
+
+impl DryRunStorage {
+    pub fn new(inner: Arc<dyn StorageBackend>) -> Self {
+        Self {
+            inner,
+            writes: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl StorageBackend for DryRunStorage {
+    fn read(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        if let Some(data) = self.writes.lock().unwrap().get(key) {
+            return Ok(data.clone());
+        }
+        self.inner.read(key)
+    }
+
+    fn write(&self, key: &str, data: &[u8]) -> Result<()> {
+        self.writes
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), Some(data.to_vec()));
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.writes.lock().unwrap().insert(key.to_string(), None);
+        Ok(())
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        let mut keys = self.inner.list(prefix)?;
+        for (key, data) in self.writes.lock().unwrap().iter() {
+            if !key.starts_with(prefix) {
+                continue;
+            }
+            keys.retain(|listed| listed != key);
+            if data.is_some() {
+                keys.push(key.clone());
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    }
+}
+
+impl Relayer {
+    /// Simulates the transaction of `prepared` in place of sending it. A
+    /// rejected simulation is logged, not returned, so the dry run goes on
+    /// with the next nonce
+    pub(crate) async fn simulate_transfer(&self, prepared: &PreparedTransfer) -> Result<()> {
+        let config = RpcSimulateTransactionConfig {
+            commitment: Some(self.l2_client.commitment()),
+            ..RpcSimulateTransactionConfig::default()
+        };
+        let simulation = self
+            .l2_client
+            .simulate_transaction_with_config(&prepared.transaction, config)
+            .await?
+            .value;
+        let units = simulation
+            .units_consumed
+            .map_or_else(|| "unknown".to_string(), |units| units.to_string());
+        match simulation.err {
+            None => tracing::info!(
+                parent: &prepared.span,
+                units_consumed = simulation.units_consumed,
+                "Dry run: nonce {} simulated successfully ({} compute units), not sent",
+                prepared.nonce,
+                units
+            ),
+            Some(err) => tracing::warn!(
+                parent: &prepared.span,
+                units_consumed = simulation.units_consumed,
+                error = %err,
+                logs = ?simulation.logs.unwrap_or_default(),
+                "Dry run: simulation of nonce {} failed ({} compute units)",
+                prepared.nonce,
+                units
+            ),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+//! Dual-read verification of L1 data.
+//! With `l1_verify_urls` set, a transfer above `dual_read_threshold` is only
+//! relayed if every verification provider returns the same transfer-info
+//! bytes as the primary L1 RPC and reports a watched-account nonce past it.
+//! A provider that is behind (older slot, missing account or lower nonce) is a
+//! lag mismatch; disagreeing bytes at the same or a newer slot are a hard
+//! mismatch, which usually means a faulty or malicious provider. Either way
+//! the batch fails and the transfer is retried with backoff.
+//! `dual_read_threshold` is in lamports and applies to native transfers; a
+//! token or NFT transfer is verified above its mint mapping's
+//! `dual_read_threshold`, in the mint's base units.
+
+use crate::{alerts, models::message::MessageType, Relayer};
+use anyhow::Result;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+
+/// L1 data read from the primary RPC for one transfer
+pub struct PrimaryRead<'a> {
+    pub nonce: u64,
+    pub pda: Pubkey,
+    pub data: &'a [u8],
+}
+
+/// Outcome of comparing one transfer with one verification provider
+enum Comparison {
+    Agreement,
+    Lag(String),
+    Hard(String),
+}
+
+/// Second L1 provider a high-value read is confirmed against
+pub struct L1Verifier {
+    /// Redacted URL, for logs and alerts
+    pub url: String,
+    pub client: RpcClient,
+}
+
+impl Relayer {
+    /// Amount above which the transfer in PDA `data` is verified, in
+    /// lamports for a native transfer and in base units for a token or NFT
+    /// transfer; none for a message that is not relayed as decoded (an
+    /// unknown type or an unmapped mint)
+    pub(crate) fn dual_read_threshold(&self, data: &[u8]) -> Option<u64> {
+        if self.message_types.is_empty() {
+            return Some(self.dual_read_threshold);
+        }
+        let id = self.pda_manager.message_type_id(data)?;
+        match self.message_types.get(&id)? {
+            MessageType::Native => Some(self.dual_read_threshold),
+            MessageType::Token | MessageType::Nft => {
+                let l1_mint = self.pda_manager.mint(data)?;
+                let map = self.mint_map.lock().unwrap().clone();
+                map.enabled(&l1_mint).map(|token| token.dual_read_threshold)
+            }
+            MessageType::Unknown(_) => None,
+        }
+    }
+
+    /// Whether the transfer in `data` is above its dual-read threshold; none
+    /// if it does not decode or has no threshold
+    pub(crate) fn exceeds_dual_read_threshold(&self, data: &[u8]) -> Option<bool> {
+        let (amount, _) = self.pda_manager.parse_transfer_info(data).ok()?;
+        Some(amount > self.dual_read_threshold(data)?)
+    }
+
+    /// Confirms `reads`, taken from the primary L1 RPC at `slot`, with every
+    /// verification provider; fails on the first mismatch
+    pub(crate) async fn verify_l1_reads(&self, reads: &[PrimaryRead<'_>], slot: u64) -> Result<()> {
+        if reads.is_empty() {
+            return Ok(());
+        }
+
+        for verifier in &self.l1_verifiers {
+            let mut addresses: Vec<Pubkey> = reads.iter().map(|read| read.pda).collect();
+            addresses.push(*self.watched_account.pubkey());
+            let response = verifier
+                .client
+                .get_multiple_accounts_with_commitment(&addresses, verifier.client.commitment())
+                .await
+                .map_err(|e| {
+                    anyhow::anyhow!("Verification read from {} failed: {}", verifier.url, e)
+                })?;
+            let verifier_slot = response.context.slot;
+            let mut accounts = response.value;
+            let watched_nonce = accounts
+                .pop()
+                .flatten()
+                .and_then(|account| self.watched.decode(&account.data))
+                .map(|status| status.nonce);
+
+            for (read, account) in reads.iter().zip(accounts) {
+                let comparison = match account {
+                    Some(account) if account.data == read.data => match watched_nonce {
+                        Some(watched) if self.discovered_end(watched) > read.nonce => {
+                            Comparison::Agreement
+                        }
+                        _ => Comparison::Lag(format!(
+                            "watched-account nonce {:?} does not cover nonce {}",
+                            watched_nonce, read.nonce
+                        )),
+                    },
+                    None => Comparison::Lag("transfer-info PDA not found".to_string()),
+                    Some(_) if verifier_slot < slot => Comparison::Lag(format!(
+                        "data differs at slot {}, behind the primary's {}",
+                        verifier_slot, slot
+                    )),
+                    Some(_) => Comparison::Hard(format!(
+                        "data differs at slot {} (primary at {})",
+                        verifier_slot, slot
+                    )),
+                };
+
+                match comparison {
+                    Comparison::Agreement => self.metrics.dual_read_agreements_total.inc(),
+                    Comparison::Lag(reason) => {
+                        // 落后的节点通常很快追上，只重试不告警
+                        self.metrics.dual_read_lag_mismatches_total.inc();
+                        return Err(anyhow::anyhow!(
+                            "Nonce {} held: verification provider {} is behind ({})",
+                            read.nonce,
+                            verifier.url,
+                            reason
+                        ));
+                    }
+                    Comparison::Hard(reason) => {
+                        self.metrics.dual_read_hard_mismatches_total.inc();
+                        let message = format!(
+                            "Nonce {} held: L1 providers disagree on transfer-info PDA {}; {} {}",
+                            read.nonce, read.pda, verifier.url, reason
+                        );
+                        alerts::raise(&message);
+                        return Err(anyhow::anyhow!(message));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
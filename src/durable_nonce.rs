@@ -0,0 +1,255 @@
+//! Durable-nonce relay transactions.
+//! A relay transaction built on a recent blockhash can only land within about
+//! 150 blocks; past that, a slow L2 cluster makes every rebroadcast fail and
+//! the transfer is built again. With a source's `durable_nonce_account` set
+//! (a system nonce account on L2 whose authority is the signer), relay
+//! transactions instead start with `AdvanceNonceAccount` and carry the
+//! account's stored nonce as their blockhash. They stay valid until the nonce
+//! advances, which only a landed transaction does, so:
+//!
+//! - a transaction is awaited for `durable_nonce_confirmation_blocks`
+//!   instead of the blockhash lifetime
+//! - a transaction built again after that wait reuses the same nonce, and of
+//!   all transactions built on one nonce at most one can land
+//!
+//! The same holds for transactions sent side by side, so durable nonces
+//! require `send_concurrency = 1`. Warm-up transactions keep using a recent
+//! blockhash and leave the nonce alone.
+//!
+//! A closed nonce account is alerted once. With `durable_nonce_fallback`
+//! (default) transactions are then built on a recent blockhash without
+//! `AdvanceNonceAccount`, which is safe since none built on the old nonce can
+//! land any more; otherwise every batch fails until the account is back.
+//!
+//! An account authorized to another key than the signer fails the start.
+
+use crate::{alerts, pubkeys::DestinationAccount, Relayer};
+use anyhow::Result;
+use solana_client::nonblocking::nonce_utils;
+use solana_sdk::{hash::Hash, nonce, pubkey::Pubkey};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// The nonce account of a source and how long its transactions are awaited
+pub struct DurableNonce {
+    pub account: DestinationAccount,
+    /// L2 blocks a transaction is awaited before it is built again
+    confirmation_blocks: u64,
+    /// Fall back to a recent blockhash while the account is closed
+    fallback: bool,
+    /// Set while the account is closed, shared with the transaction builder
+    pub closed: Arc<AtomicBool>,
+}
+
+impl DurableNonce {
+    /// Fails when transactions would be sent side by side, since only one of
+    /// those built on the same nonce can land
+    pub fn new(
+        account: DestinationAccount,
+        confirmation_blocks: u64,
+        fallback: bool,
+        send_concurrency: usize,
+    ) -> Result<Self> {
+        if send_concurrency > 1 {
+            return Err(anyhow::anyhow!(
+                "durable_nonce_account requires send_concurrency = 1, got {}",
+                send_concurrency
+            ));
+        }
+        Ok(Self {
+            account,
+            confirmation_blocks: confirmation_blocks.max(1),
+            fallback,
+            closed: Arc::default(),
+        })
+    }
+}
+
+impl Relayer {
+    /// Blockhash of the next relay transaction and the L2 block height it is
+    /// awaited until: the latest blockhash, or the stored nonce of the
+    /// durable nonce account (the latest blockhash while it is closed, with
+    /// `durable_nonce_fallback`)
+    pub(crate) async fn relay_blockhash(&self) -> Result<(Hash, u64)> {
+        let commitment = self.l2_client.commitment();
+        let Some(durable) = &self.durable_nonce else {
+            return Ok(self
+                .l2_client
+                .get_latest_blockhash_with_commitment(commitment)
+                .await?);
+        };
+        let address = durable.account.pubkey();
+        let account = self
+            .l2_client
+            .get_account_with_commitment(address, commitment)
+            .await
+            .map_err(|e| anyhow::anyhow!("Durable nonce account {}: {}", address, e))?
+            .value;
+        let closed = account.is_none();
+        if durable.closed.swap(closed, Ordering::Relaxed) != closed {
+            let mode = if durable.fallback {
+                "relay transactions use a recent blockhash"
+            } else {
+                "relaying fails until it is recreated (durable_nonce_fallback = false)"
+            };
+            if closed {
+                alerts::raise(&format!(
+                    "{}: durable nonce account {} is closed; {}",
+                    self.name(),
+                    address,
+                    mode
+                ));
+            } else {
+                alerts::resolve(&format!(
+                    "{}: durable nonce account {} is back; relay transactions use its nonce",
+                    self.name(),
+                    address
+                ));
+            }
+        }
+        let Some(account) = account else {
+            if !durable.fallback {
+                return Err(anyhow::anyhow!(
+                    "Durable nonce account {} is closed and durable_nonce_fallback is off",
+                    address
+                ));
+            }
+            return Ok(self
+                .l2_client
+                .get_latest_blockhash_with_commitment(commitment)
+                .await?);
+        };
+        let data = nonce_utils::data_from_account(&account)
+            .map_err(|e| anyhow::anyhow!("Durable nonce account {}: {}", address, e))?;
+        self.check_nonce_authority(address, &data)?;
+        let block_height = self.l2_client.get_block_height().await?;
+        Ok((
+            data.blockhash(),
+            block_height.saturating_add(durable.confirmation_blocks),
+        ))
+    }
+
+    /// Fails at startup when the durable nonce account is not authorized to
+    /// the signer, rather than at the first relay transaction. A closed
+    /// account is left to `relay_blockhash`, which alerts it
+    pub(crate) async fn verify_durable_nonce(&self) -> Result<()> {
+        let Some(durable) = &self.durable_nonce else {
+            return Ok(());
+        };
+        let address = durable.account.pubkey();
+        let account = self
+            .l2_client
+            .get_account_with_commitment(address, self.l2_client.commitment())
+            .await
+            .map_err(|e| anyhow::anyhow!("Durable nonce account {}: {}", address, e))?
+            .value;
+        let Some(account) = account else {
+            return Ok(());
+        };
+        let data = nonce_utils::data_from_account(&account)
+            .map_err(|e| anyhow::anyhow!("Durable nonce account {}: {}", address, e))?;
+        self.check_nonce_authority(address, &data)
+    }
+
+    /// Fails unless the nonce stored at `address` is authorized to the signer
+    fn check_nonce_authority(&self, address: &Pubkey, data: &nonce::state::Data) -> Result<()> {
+        let signer = self.signers.active().pubkey();
+        if data.authority != signer {
+            return Err(anyhow::anyhow!(
+                "Durable nonce account {} is authorized to {}, not to the signer {}",
+                address,
+                data.authority,
+                signer
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use crate::testing::{self, Harness};
+    use solana_sdk::{hash::Hash, nonce::state::DurableNonce, pubkey::Pubkey};
+    use std::sync::atomic::Ordering;
+
+    /// A harness whose durable nonce account was never created on the fake L2
+    fn closed_nonce_harness(fallback: bool) -> Harness {
+        let mut config = testing::config();
+        config.durable_nonce_account = Some(Pubkey::new_unique().into());
+        config.durable_nonce_fallback = fallback;
+        Harness::new(config).unwrap()
+    }
+
+    #[tokio::test]
+    async fn a_closed_nonce_account_falls_back_to_a_recent_blockhash() {
+        let relayer = closed_nonce_harness(true).relayer().await.unwrap();
+        let (blockhash, _) = relayer.relay_blockhash().await.unwrap();
+        let (latest, _) = relayer
+            .l2_client
+            .get_latest_blockhash_with_commitment(relayer.l2_client.commitment())
+            .await
+            .unwrap();
+        assert_eq!(blockhash, latest);
+        assert!(relayer
+            .transaction_builder
+            .durable_nonce_closed
+            .load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn without_the_fallback_a_closed_nonce_account_fails_the_build() {
+        let relayer = closed_nonce_harness(false).relayer().await.unwrap();
+        assert!(relayer.relay_blockhash().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn a_nonce_account_of_another_authority_fails_at_startup() {
+        let harness = closed_nonce_harness(true);
+        let address = *harness.config.durable_nonce_account.unwrap().pubkey();
+        let other = Pubkey::new_unique();
+        harness
+            .l2
+            .set_durable_nonce(address, other, &Hash::new_unique());
+        let error = harness.relayer().await.err().unwrap().to_string();
+        assert!(
+            error.contains(&format!("authorized to {}", other)),
+            "{}",
+            error
+        );
+
+        harness
+            .l2
+            .set_durable_nonce(address, harness.signer(), &Hash::new_unique());
+        assert!(harness.relayer().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn a_nonce_advanced_by_another_process_is_read_for_the_next_build() {
+        let harness = closed_nonce_harness(false);
+        let address = *harness.config.durable_nonce_account.unwrap().pubkey();
+        let first = Hash::new_unique();
+        harness
+            .l2
+            .set_durable_nonce(address, harness.signer(), &first);
+        let relayer = harness.relayer().await.unwrap();
+        let (blockhash, _) = relayer.relay_blockhash().await.unwrap();
+        assert_eq!(blockhash, *DurableNonce::from_blockhash(&first).as_hash());
+
+        // 另一进程推进了 nonce，之前构建的交易再也无法上链
+        let advanced = Hash::new_unique();
+        harness
+            .l2
+            .set_durable_nonce(address, harness.signer(), &advanced);
+        let (blockhash, _) = relayer.relay_blockhash().await.unwrap();
+        assert_eq!(
+            blockhash,
+            *DurableNonce::from_blockhash(&advanced).as_hash()
+        );
+        assert!(!relayer
+            .transaction_builder
+            .durable_nonce_closed
+            .load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,261 @@
+//! Dust transfers.
+//! Amounts are paid 1:1, in lamports or the L2 mint's base units, so the
+//! smallest amount worth a relay transaction is configured rather than
+//! derived from decimals: `dust_threshold` in lamports for native transfers,
+//! and a `[[mint_mappings]]` entry's `dust_threshold` in its base units (0,
+//! the default, turns the check off). A transfer below it is handled by
+//! `dust`:
+//!
+//! - `dead_letter` (default): parked for an operator
+//! - `skip`: recorded with a receipt without a transaction (`dust = "skip"`)
+//!   and not relayed; needs an L2 program that accepts nonce gaps
+//! - `accumulate`: credited to its recipient's tally in the state store
+//!   (`dust/<mint>/<recipient>`, `native` for lamports) and recorded like a
+//!   skipped one (`dust = "accumulate"`). The first later transfer to the
+//!   recipient, dust or not, that brings the tally to the threshold pays it
+//!   out with its own amount; its receipt lists the contributing nonces in
+//!   `dust_nonces`. The tally marks them as claimed by that nonce, so no
+//!   other transfer pays them too, and drops them once its transaction is
+//!   confirmed; a claiming transfer built again, or replayed from the
+//!   dead-letter queue, pays them again
+//!
+//! Coalesced transfers, whose amount is already a sum, are not checked; dust
+//! entries of a multi-entry PDA are dead-lettered whatever the policy.
+//! Skipped and accumulated dust is journaled as `skipped`, counted in
+//! `relayer_dust_transfers_total` and reported as `dust` by `report`.
+
+use crate::{
+    inflight::InFlightTransfer, journal::JournalEvent, limits, pipeline::FetchedTransfer,
+    receipts::Receipt, Relayer,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Handling of transfers below the dust threshold (`dust`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DustPolicy {
+    /// Record a receipt without relaying and move on
+    Skip,
+    /// Credit the amount to the recipient's tally, paid out once it reaches
+    /// the threshold
+    Accumulate,
+    /// Park the nonce in the dead-letter queue
+    #[default]
+    DeadLetter,
+}
+
+/// The dust settings of a source
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct DustSettings {
+    pub policy: DustPolicy,
+    /// Lamports below which a native transfer is dust; 0 for none
+    pub threshold: u64,
+}
+
+/// Dust of one nonce in a recipient's tally
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct DustEntry {
+    amount: u64,
+    /// Nonce of the transfer paying the dust out, once one has claimed it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    claimed_by: Option<u64>,
+}
+
+/// State key of the tally of `recipient` in `mint` (`native` for lamports)
+fn tally_key(mint: Option<String>, recipient: &str) -> String {
+    format!("dust/{}/{}", mint.as_deref().unwrap_or("native"), recipient)
+}
+
+impl Relayer {
+    /// Applies the dust policy to `fetched`; returns whether it was recorded
+    /// without being relayed, now or before. An accumulated tally that
+    /// `fetched` brings to the threshold is added to its amount
+    pub(crate) fn check_dust(&self, fetched: &mut FetchedTransfer) -> Result<bool> {
+        let threshold = match fetched.token {
+            None => self.dust.threshold,
+            Some(token) => token.dust_threshold,
+        };
+        if threshold == 0 || !fetched.coalesced.is_empty() {
+            return Ok(false);
+        }
+        // 同批次后面的 nonce 失败时，已记录的尘埃会被再次读取
+        if Receipt::load(&self.state, fetched.nonce)?.is_some_and(|receipt| receipt.dust.is_some())
+        {
+            return Ok(true);
+        }
+        let key = tally_key(
+            fetched.token.map(|token| token.l2_mint.to_string()),
+            &fetched.to_address.to_string(),
+        );
+        // 跳过和累积会为整个 nonce 写回执，多条目 PDA 的条目只能进入死信
+        let policy = match fetched.entry {
+            Some(_) => DustPolicy::DeadLetter,
+            None => self.dust.policy,
+        };
+        match policy {
+            DustPolicy::Accumulate => {
+                let mut tally: BTreeMap<u64, DustEntry> = self.state.get(&key)?.unwrap_or_default();
+                tally.remove(&fetched.nonce);
+                // 已被其他转账认领的尘埃由那笔转账支付
+                let owed: Vec<u64> = tally
+                    .iter()
+                    .filter(|(_, entry)| entry.claimed_by.is_none_or(|by| by == fetched.nonce))
+                    .map(|(nonce, _)| *nonce)
+                    .collect();
+                let total = owed.iter().fold(fetched.amount, |total, nonce| {
+                    total.saturating_add(tally[nonce].amount)
+                });
+                if total >= threshold {
+                    if !owed.is_empty() {
+                        for nonce in &owed {
+                            tally.get_mut(nonce).unwrap().claimed_by = Some(fetched.nonce);
+                        }
+                        self.state.put(&key, &tally)?;
+                    }
+                    fetched.amount = total;
+                    fetched.dust = owed;
+                    return Ok(false);
+                }
+                tally.insert(
+                    fetched.nonce,
+                    DustEntry {
+                        amount: fetched.amount,
+                        claimed_by: None,
+                    },
+                );
+                self.state.put(&key, &tally)?;
+                self.skip_dust(fetched, DustPolicy::Accumulate, total)?;
+                Ok(true)
+            }
+            _ if fetched.amount >= threshold => Ok(false),
+            DustPolicy::Skip => {
+                self.skip_dust(fetched, DustPolicy::Skip, fetched.amount)?;
+                Ok(true)
+            }
+            DustPolicy::DeadLetter => {
+                let reason = format!(
+                    "dust transfer of {} below the threshold of {}",
+                    fetched.amount, threshold
+                );
+                self.dead_letter_transfer(fetched, &reason)?;
+                Err(anyhow::anyhow!(
+                    "Nonce {} rejected as dust: {}",
+                    fetched.nonce,
+                    reason
+                ))
+            }
+        }
+    }
+
+    /// Records `fetched` as dust handled by `policy`; `tally` is what its
+    /// recipient is owed in dust, its amount included
+    fn skip_dust(&self, fetched: &FetchedTransfer, policy: DustPolicy, tally: u64) -> Result<()> {
+        tracing::info!(
+            "Skipping nonce {}: dust transfer of {} to {} (dust = {:?}, tally {})",
+            fetched.nonce,
+            fetched.amount,
+            fetched.to_address,
+            policy,
+            tally
+        );
+        Receipt::dust(fetched, policy, self.clock.unix_timestamp()).save(&self.state)?;
+        self.schedule_shipping(fetched.nonce);
+        self.attempts.finish(fetched.nonce);
+        self.journal_message(
+            Some(fetched.l1_slot),
+            Some(limits::message_type_of(fetched.token)),
+            JournalEvent::Skipped {
+                nonce: fetched.nonce,
+                reason: match policy {
+                    DustPolicy::Accumulate => format!("dust accumulated, {} owed", tally),
+                    _ => "dust".to_string(),
+                },
+            },
+        );
+        self.metrics.dust_transfers_total.inc();
+        Ok(())
+    }
+
+    /// Drops the dust nonces the confirmed `transfer` paid out from its
+    /// recipient's tally
+    pub(crate) fn settle_dust(&self, transfer: &InFlightTransfer) -> Result<()> {
+        if transfer.dust.is_empty() {
+            return Ok(());
+        }
+        let key = tally_key(transfer.mint.clone(), &transfer.to);
+        let mut tally: BTreeMap<u64, DustEntry> = self.state.get(&key)?.unwrap_or_default();
+        tally.retain(|nonce, _| !transfer.dust.contains(nonce));
+        if tally.is_empty() {
+            self.state.remove(&key)
+        } else {
+            self.state.put(&key, &tally)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::testing::{self, Harness};
+    use solana_sdk::pubkey::Pubkey;
+
+    fn dust_harness(policy: DustPolicy, amounts: &[u64], to: &Pubkey) -> Harness {
+        let mut config = testing::config();
+        config.dust = policy;
+        config.dust_threshold = 1_000;
+        let harness = Harness::new(config).unwrap();
+        harness.l2.fund(to, 1_000_000_000);
+        for (nonce, amount) in amounts.iter().enumerate() {
+            harness
+                .l1
+                .set_transfer_info(harness.transfer_pda(nonce as u64), *amount, to);
+        }
+        harness.l1.set_nonce(amounts.len() as u64);
+        harness
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn accumulated_dust_is_paid_out_once_the_tally_reaches_the_threshold() {
+        let to = Pubkey::new_unique();
+        let harness = dust_harness(DustPolicy::Accumulate, &[400, 400, 400, 2_000], &to);
+        let relayer = harness.relayer().await.unwrap();
+
+        relayer.process_nonce_range(0, 4, 0).await.unwrap();
+        for nonce in [0, 1] {
+            let receipt = Receipt::load(&relayer.state, nonce).unwrap().unwrap();
+            assert_eq!(receipt.dust, Some(DustPolicy::Accumulate));
+            assert!(receipt.signature.is_empty());
+        }
+        let paid = Receipt::load(&relayer.state, 2).unwrap().unwrap();
+        assert_eq!((paid.amount, paid.dust_nonces), (1_200, vec![0, 1]));
+        assert!(!paid.signature.is_empty());
+        let plain = Receipt::load(&relayer.state, 3).unwrap().unwrap();
+        assert_eq!((plain.amount, plain.dust_nonces), (2_000, vec![]));
+        assert_eq!(harness.l2.balance(&to), 1_000_003_200);
+        let tally: Option<BTreeMap<u64, DustEntry>> = relayer
+            .state
+            .get(&tally_key(None, &to.to_string()))
+            .unwrap();
+        assert!(tally.is_none());
+        assert_eq!(relayer.metrics.dust_transfers_total.get(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn skipped_dust_gets_a_receipt_and_dead_lettered_dust_waits_for_review() {
+        let to = Pubkey::new_unique();
+        let harness = dust_harness(DustPolicy::Skip, &[400, 2_000], &to);
+        let relayer = harness.relayer().await.unwrap();
+        relayer.process_nonce_range(0, 2, 0).await.unwrap();
+        let skipped = Receipt::load(&relayer.state, 0).unwrap().unwrap();
+        assert_eq!(skipped.dust, Some(DustPolicy::Skip));
+        assert_eq!(harness.l2.balance(&to), 1_000_002_000);
+
+        let harness = dust_harness(DustPolicy::DeadLetter, &[400], &to);
+        let relayer = harness.relayer().await.unwrap();
+        assert!(relayer.process_nonce_range(0, 1, 0).await.is_err());
+        assert!(relayer.dead_letters.contains(0));
+        assert!(Receipt::load(&relayer.state, 0).unwrap().is_none());
+    }
+}
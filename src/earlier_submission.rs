@@ -0,0 +1,127 @@
+//! Earlier submissions found in L2 history.
+//! The in-flight record of a transaction is written before it is sent, so a
+//! restart awaits it instead of sending the nonce again. When that record is
+//! lost anyway (e.g. a wiped or restored `state_dir`), a restart would send
+//! the nonce a second time, and an L2 program without its own dedup pays it
+//! twice. With `earlier_submission_signatures`
+//! set, that many recent signatures of the source's L2 nonce account, which
+//! every relay writes, are read right before each send, after the L2
+//! processed-nonce check, and their memos searched for the nonce:
+//!
+//! - a successful match is imported as the nonce's receipt, as
+//!   `import-history` would, and the nonce is not sent
+//! - a match whose receipt cannot be recovered (e.g. the nonce was merged
+//!   into another transfer's relay) is alerted and fails the attempt, for an
+//!   operator to reconcile
+//!
+//! Only confirmed transactions that kept their memo are found: a
+//! transaction dropped from its memo to fit the size limit, or not yet
+//! confirmed at the L2 client's commitment, is not. Entries of a
+//! multi-entry PDA, whose receipts are kept per entry, are not searched.
+
+use crate::{
+    alerts,
+    history::{Imported, RelayMemo},
+    journal::JournalEvent,
+    limits,
+    receipts::Receipt,
+    PreparedTransfer, Relayer,
+};
+use anyhow::Result;
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+
+/// `getSignaturesForAddress` returns at most 1000 signatures per request
+const MAX_SIGNATURES: usize = 1000;
+
+/// Relay memos in the `memo` field of a signature status, which lists each
+/// memo of the transaction as `[<length>] <memo>`, separated by `; `
+fn relay_memos(memo: &str) -> Vec<RelayMemo> {
+    memo.split("; ")
+        .filter_map(|memo| {
+            let (_, memo) = memo.split_once("] ")?;
+            serde_json::from_str(memo).ok()
+        })
+        .collect()
+}
+
+impl Relayer {
+    /// Skips `prepared` when a successful relay of one of its nonces is among
+    /// the recent signatures of the L2 nonce account
+    pub(crate) async fn check_earlier_submission(
+        &self,
+        prepared: &PreparedTransfer,
+    ) -> Result<bool> {
+        // 条目按 (nonce, 序号) 记回执，同一 nonce 的早先交易不代表这些条目已落地
+        if self.earlier_submission_signatures == 0 || !prepared.entries.is_empty() {
+            return Ok(false);
+        }
+        let nonces: Vec<u64> = std::iter::once(prepared.nonce)
+            .chain(prepared.coalesced.iter().copied())
+            .collect();
+        let config = GetConfirmedSignaturesForAddress2Config {
+            before: None,
+            until: None,
+            limit: Some(self.earlier_submission_signatures.min(MAX_SIGNATURES)),
+            commitment: Some(self.l2_client.commitment()),
+        };
+        let statuses = self
+            .l2_client
+            .get_signatures_for_address_with_config(
+                self.transaction_builder.nonce_account.pubkey(),
+                config,
+            )
+            .await?;
+        let domain = self.transaction_builder.domain;
+        let Some(earlier) = statuses.iter().find(|status| {
+            status.err.is_none()
+                && status.memo.as_deref().is_some_and(|memo| {
+                    relay_memos(memo).iter().any(|memo| {
+                        let (memo_domain, relayed) = memo.relayed();
+                        memo_domain == domain && relayed.iter().any(|n| nonces.contains(n))
+                    })
+                })
+        }) else {
+            return Ok(false);
+        };
+
+        for imported in self.import_transaction(&earlier.signature).await? {
+            if let Imported::Receipt(receipt) = imported {
+                receipt.save(&self.state)?;
+            }
+        }
+        // 已有或刚导入的回执才能证明该 nonce 已经落地
+        let recovered = Receipt::load(&self.state, prepared.nonce)?
+            .filter(|receipt| receipt.coalesced == prepared.coalesced);
+        if recovered.is_none() {
+            alerts::raise(&format!(
+                "{}: transaction {} on L2 already relayed nonces of the transfer of nonce {} (merged {:?}) but no matching receipt could be recovered; reconcile the queue",
+                self.name(),
+                earlier.signature,
+                prepared.nonce,
+                prepared.coalesced
+            ));
+            return Err(anyhow::anyhow!(
+                "Nonce {} was already submitted in {}, which does not match its transfer",
+                prepared.nonce,
+                earlier.signature
+            ));
+        }
+        tracing::info!(
+            "Skipping nonce {}: already relayed by {} on L2",
+            prepared.nonce,
+            earlier.signature
+        );
+        self.schedule_shipping(prepared.nonce);
+        self.attempts.finish(prepared.nonce);
+        self.journal_message(
+            Some(prepared.l1_slot),
+            Some(limits::message_type_of(prepared.token)),
+            JournalEvent::Skipped {
+                nonce: prepared.nonce,
+                reason: format!("already relayed by {}", earlier.signature),
+            },
+        );
+        self.metrics.earlier_submissions_total.inc();
+        Ok(true)
+    }
+}
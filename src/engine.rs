@@ -0,0 +1,133 @@
+//! Embedding API.
+//! `RelayerEngine` runs the relayers of a config inside another service,
+//! without the command line: it builds one relayer per source (of every
+//! `[[bridges]]` entry), and runs them until `shutdown` has drained them, or
+//! one iteration at a time with `run_once`. The host keeps what the binary
+//! does around them: signal handling, the tracing subscriber, the metrics
+//! and admin listeners and config reloads. Alert webhooks are started as in
+//! the binary. `RelayerEvents` reports landed transfers and failed batches
+//! as they happen.
+
+use crate::{
+    alerts, chaos, config::RelayerConfig, inflight::InFlightTransfer, init_relayers,
+    receipts::Receipt, rpc_pool::SharedPools, state::StateStore, step::RelayOutcome, Relayer,
+};
+use anyhow::Result;
+use std::sync::Arc;
+
+/// Callbacks of a `RelayerEngine`, run on the relayer's task; keep them short
+pub trait RelayerEvents: Send + Sync {
+    /// The L2 transaction of a transfer of `source` landed and its receipt
+    /// was written
+    fn on_relayed(&self, _source: &str, _receipt: &Receipt) {}
+
+    /// A submission batch of `source` failed; its nonce is retried
+    fn on_error(&self, _source: &str, _error: &anyhow::Error) {}
+}
+
+/// The relayers of one config, driven by the embedding service
+pub struct RelayerEngine {
+    relayers: Vec<Relayer>,
+}
+
+impl RelayerEngine {
+    /// Builds a relayer for every source of `config`, failing if any of them
+    /// fails to start
+    pub async fn new(config: &RelayerConfig) -> Result<Self> {
+        alerts::init(&config.alert_webhooks)?;
+        chaos::init(&config.chaos)?;
+        let pools = SharedPools::default();
+        let mut relayers = Vec::new();
+        if config.bridges.is_empty() {
+            relayers = init_relayers(config, &config.sources()?, &pools).await?;
+        }
+        for bridge in &config.bridges {
+            relayers.extend(init_relayers(bridge, &bridge.sources()?, &pools).await?);
+        }
+        Ok(Self { relayers })
+    }
+
+    #[cfg(feature = "testing")]
+    pub(crate) fn from_relayers(relayers: Vec<Relayer>) -> Self {
+        Self { relayers }
+    }
+
+    /// Reports the events of every relayer to `events`
+    pub fn with_events(mut self, events: Arc<dyn RelayerEvents>) -> Self {
+        for relayer in &mut self.relayers {
+            relayer.events = Some(events.clone());
+        }
+        self
+    }
+
+    /// Ids of the sources, prefixed with their bridge in `[[bridges]]` mode
+    pub fn sources(&self) -> Vec<String> {
+        self.relayers.iter().map(Relayer::qualified_id).collect()
+    }
+
+    /// State store of `source`, as named by `sources`
+    pub fn state(&self, source: &str) -> Option<&StateStore> {
+        self.relayers
+            .iter()
+            .find(|relayer| relayer.qualified_id() == source)
+            .map(|relayer| &relayer.state)
+    }
+
+    /// Monitors and relays every source until all have stopped. A source that
+    /// fails is alerted and leaves the others running; the first error is
+    /// returned
+    pub async fn run(&self) -> Result<()> {
+        let results = futures::future::join_all(self.relayers.iter().map(|relayer| async move {
+            let result = relayer.monitor_and_relay().await;
+            if let Err(e) = &result {
+                alerts::raise(&format!("{} stopped: {}", relayer.name(), e));
+            }
+            result
+        }))
+        .await;
+        results.into_iter().collect()
+    }
+
+    /// Runs one iteration of every source, as `run --once` does
+    pub async fn run_once(&self) -> Result<Vec<RelayOutcome>> {
+        let mut outcomes = Vec::with_capacity(self.relayers.len());
+        for relayer in &self.relayers {
+            outcomes.push(relayer.run_once().await?);
+        }
+        Ok(outcomes)
+    }
+
+    /// Asks every relayer to drain its in-flight transactions and stop;
+    /// `run` returns once they have
+    pub fn shutdown(&self) {
+        for relayer in &self.relayers {
+            relayer.shutdown.begin();
+        }
+    }
+}
+
+impl Relayer {
+    /// Source id, prefixed with the bridge name when there is one
+    fn qualified_id(&self) -> String {
+        match &self.bridge {
+            Some(bridge) => format!("{}/{}", bridge, self.source_id),
+            None => self.source_id.clone(),
+        }
+    }
+
+    /// Reports the landed `transfer` to the embedding service and queues its
+    /// event for the event stream
+    pub(crate) fn notify_relayed(&self, transfer: &InFlightTransfer, receipt: &Receipt) {
+        self.queue_relay_event(transfer, receipt);
+        if let Some(events) = &self.events {
+            events.on_relayed(&self.qualified_id(), receipt);
+        }
+    }
+
+    /// Reports a failed submission batch to the embedding service
+    pub(crate) fn notify_error(&self, error: &anyhow::Error) {
+        if let Some(events) = &self.events {
+            events.on_error(&self.qualified_id(), error);
+        }
+    }
+}
@@ -0,0 +1,263 @@
+//! Receipt enrichment.
+//! A receipt is saved as soon as its transaction confirms, with what was known
+//! before sending. A background loop then reads the transaction with
+//! `getTransaction` and adds what it actually did: the fee paid, compute
+//! units consumed, L2 slot and block time, the relayer wallet's balance change
+//! and the recipient's balances before and after. A failed read is retried up
+//! to `receipt_enrich_attempts` times and then left for `enrich-receipts`;
+//! relaying never waits on or fails because of enrichment. At most
+//! `receipt_enrich_queue_capacity` receipts wait at once; when the cluster
+//! falls behind, the oldest are left for `enrich-receipts` instead.
+
+use crate::{
+    fees,
+    receipts::{packed_share, Receipt},
+    Relayer,
+};
+use anyhow::Result;
+use solana_client::rpc_config::RpcTransactionConfig;
+use solana_sdk::signature::Signature;
+use solana_transaction_status::{
+    option_serializer::OptionSerializer, EncodedConfirmedTransactionWithStatusMeta,
+    UiTransactionEncoding,
+};
+use std::{collections::VecDeque, str::FromStr, sync::Mutex, time::Duration};
+use tokio::sync::Notify;
+
+/// Confirmed receipts waiting to be enriched
+pub struct EnrichmentQueue {
+    /// Nonce and failed attempts of each waiting receipt
+    pending: Mutex<VecDeque<(u64, u32)>>,
+    notify: Notify,
+    /// Pause before each read, giving the RPC node time to index the transaction
+    delay: Duration,
+    /// Reads of a receipt before it is left to `enrich-receipts` (0 disables)
+    attempts: u32,
+    /// Receipts waiting at most
+    capacity: usize,
+}
+
+impl EnrichmentQueue {
+    pub fn new(delay: Duration, attempts: u32, capacity: usize) -> Self {
+        Self {
+            pending: Mutex::new(VecDeque::new()),
+            notify: Notify::new(),
+            delay,
+            attempts,
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Schedules the receipt of `nonce` for enrichment; returns the nonce
+    /// whose receipt was dropped from the full queue, if any
+    fn push(&self, nonce: u64) -> Option<u64> {
+        if self.attempts == 0 {
+            return None;
+        }
+        let mut pending = self.pending.lock().unwrap();
+        let evicted = (pending.len() >= self.capacity)
+            .then(|| pending.pop_front())
+            .flatten()
+            .map(|(nonce, _)| nonce);
+        pending.push_back((nonce, 0));
+        drop(pending);
+        self.notify.notify_one();
+        evicted
+    }
+
+    /// Receipts waiting to be enriched
+    pub fn len(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    fn pop(&self) -> Option<(u64, u32)> {
+        self.pending.lock().unwrap().pop_front()
+    }
+
+    /// Schedules another read after a failed one; false once out of
+    /// attempts or when the queue is full
+    fn retry(&self, nonce: u64, failed: u32) -> bool {
+        if failed >= self.attempts || self.len() >= self.capacity {
+            return false;
+        }
+        self.pending.lock().unwrap().push_back((nonce, failed));
+        true
+    }
+}
+
+/// Adds what the confirmed transaction did to `receipt`
+pub(crate) fn add_transaction_details(
+    receipt: &mut Receipt,
+    confirmed: &EncodedConfirmedTransactionWithStatusMeta,
+) -> Result<()> {
+    let meta = confirmed.transaction.meta.as_ref().ok_or_else(|| {
+        anyhow::anyhow!("Transaction {} has no status metadata", receipt.signature)
+    })?;
+
+    if receipt.packed.is_empty() {
+        // 第一个账户是付款的 relayer 钱包
+        receipt.actual_fee = Some(meta.fee);
+        receipt.actual_cost = meta
+            .pre_balances
+            .first()
+            .zip(meta.post_balances.first())
+            .map(|(pre, post)| pre.saturating_sub(*post));
+    } else {
+        // 打包交易的手续费由各 nonce 分摊，钱包支出按各自金额计
+        let fee = packed_share(meta.fee, receipt.nonce, &receipt.packed);
+        receipt.actual_fee = Some(fee);
+        receipt.actual_cost = Some(receipt.amount.saturating_add(fee));
+    }
+    receipt.compute_units = match meta.compute_units_consumed {
+        OptionSerializer::Some(units) => Some(units),
+        OptionSerializer::None | OptionSerializer::Skip => None,
+    };
+    let recipient = confirmed.transaction.transaction.decode().and_then(|tx| {
+        tx.message
+            .static_account_keys()
+            .iter()
+            .position(|key| key.to_string() == receipt.to)
+    });
+    if let Some(index) = recipient {
+        receipt.recipient_pre_balance = meta.pre_balances.get(index).copied();
+        receipt.recipient_post_balance = meta.post_balances.get(index).copied();
+    }
+    receipt.l2_slot = Some(confirmed.slot);
+    receipt.block_time = confirmed.block_time;
+    Ok(())
+}
+
+impl Relayer {
+    /// Schedules the enrichment of the receipt of `nonce`
+    pub(crate) fn schedule_enrichment(&self, nonce: u64) {
+        if let Some(evicted) = self.enrichment.push(nonce) {
+            self.metrics.enrichment_evictions_total.inc();
+            tracing::warn!(
+                "enrichment queue full, leaving the receipt of nonce {} for enrich-receipts",
+                evicted
+            );
+        }
+    }
+
+    /// Enriches confirmed receipts as they are saved; never returns
+    pub(crate) async fn enrich_receipts(&self) -> Result<()> {
+        let queue = &self.enrichment;
+        loop {
+            let Some((nonce, failed)) = queue.pop() else {
+                queue.notify.notified().await;
+                continue;
+            };
+            self.clock.sleep(queue.delay).await;
+            self.enrich_pending(nonce, failed).await;
+        }
+    }
+
+    /// Enriches the receipts scheduled so far once each, without waiting
+    pub(crate) async fn enrich_scheduled(&self) {
+        let scheduled = self.enrichment.pending.lock().unwrap().len();
+        for _ in 0..scheduled {
+            let Some((nonce, failed)) = self.enrichment.pop() else {
+                break;
+            };
+            self.enrich_pending(nonce, failed).await;
+        }
+    }
+
+    async fn enrich_pending(&self, nonce: u64, failed: u32) {
+        let error = match self.enrich_receipt(nonce).await {
+            Ok(_) => return,
+            Err(e) => e,
+        };
+        if !self.enrichment.retry(nonce, failed + 1) {
+            tracing::warn!(
+                "giving up enriching the receipt of nonce {} after {} attempt(s), run enrich-receipts later: {}",
+                nonce,
+                failed + 1,
+                error
+            );
+        }
+    }
+
+    /// Adds the details of the confirmed transaction to the receipt of
+    /// `nonce`; returns false if there is no receipt left to enrich
+    pub(crate) async fn enrich_receipt(&self, nonce: u64) -> Result<bool> {
+        let Some(mut receipt) = Receipt::load(&self.state, nonce)? else {
+            // 回执可能已被 replay window 清理
+            return Ok(false);
+        };
+        if !receipt.needs_enrichment() {
+            return Ok(false);
+        }
+        let signature = Signature::from_str(&receipt.signature).map_err(|e| {
+            anyhow::anyhow!("Invalid receipt signature {}: {}", receipt.signature, e)
+        })?;
+        let config = RpcTransactionConfig {
+            encoding: Some(UiTransactionEncoding::Base64),
+            commitment: Some(self.l2_client.commitment()),
+            max_supported_transaction_version: Some(0),
+        };
+        let confirmed = self
+            .l2_client
+            .get_transaction_with_config(&signature, config)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to read transaction {}: {}", signature, e))?;
+        add_transaction_details(&mut receipt, &confirmed)?;
+        receipt.save(&self.state)?;
+        fees::record_receipt(&self.state, &receipt)?;
+        self.schedule_shipping(nonce);
+        Ok(true)
+    }
+
+    /// Enriches every stored receipt still missing its transaction details;
+    /// returns how many were enriched and how many failed
+    pub(crate) async fn backfill_receipts(&self) -> Result<(usize, usize)> {
+        let (mut enriched, mut failed) = (0, 0);
+        for receipt in Receipt::all(&self.state)? {
+            if !receipt.needs_enrichment() {
+                continue;
+            }
+            match self.enrich_receipt(receipt.nonce).await {
+                Ok(true) => enriched += 1,
+                Ok(false) => {}
+                Err(e) => {
+                    failed += 1;
+                    tracing::warn!("Nonce {}: {}", receipt.nonce, e);
+                }
+            }
+        }
+        Ok((enriched, failed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_full_queue_evicts_its_oldest_receipt() {
+        let queue = EnrichmentQueue::new(Duration::ZERO, 3, 2);
+        assert_eq!(queue.push(1), None);
+        assert_eq!(queue.push(2), None);
+        assert_eq!(queue.push(3), Some(1));
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.pop(), Some((2, 0)));
+    }
+
+    #[test]
+    fn retries_stop_at_the_attempts_or_the_capacity() {
+        let queue = EnrichmentQueue::new(Duration::ZERO, 2, 2);
+        assert!(queue.retry(1, 1));
+        assert!(!queue.retry(1, 2));
+        // 重试不挤掉新确认的回执
+        assert!(queue.retry(2, 1));
+        assert!(!queue.retry(3, 1));
+        assert_eq!(queue.len(), 2);
+    }
+
+    #[test]
+    fn zero_attempts_disables_enrichment() {
+        let queue = EnrichmentQueue::new(Duration::ZERO, 0, 2);
+        assert_eq!(queue.push(1), None);
+        assert_eq!(queue.len(), 0);
+    }
+}
@@ -0,0 +1,312 @@
+//! Multi-entry transfer-info PDAs.
+//! With `[discriminators] transfer_info_entries` set, a transfer-info PDA
+//! starting with that discriminator holds up to 8 transfers: a u32 LE count,
+//! then each entry's fields in the V3 layout without a discriminator. PDAs
+//! without it decode as before, so both are read while the L1 program
+//! migrates.
+//!
+//! Every entry is read and admitted as a transfer of its own; the entries of
+//! a nonce still pending are relayed together in one transaction, one
+//! `relay_message` call each carrying the entry index and count
+//! (`l2_instruction_version = 6`). An entry rejected before it is sent is
+//! dead-lettered under its index while the others are relayed; one held by a
+//! check, e.g. awaiting approval, holds the nonce as before. Landed entries get a
+//! receipt keyed on (nonce, index) in `entry_receipts/<nonce>`, so a retried
+//! or replayed nonce only sends the entries without one; the nonce's own
+//! receipt is written once every entry has a receipt, and lists them.
+//!
+//! Entries relay native transfers only; token and NFT entries, and dust
+//! entries whatever the `dust` policy, are dead-lettered.
+
+use crate::{
+    inflight::InFlightTransfer, journal::JournalEvent, models::account::TransferInfoAccount,
+    pipeline::FetchedTransfer, receipts::Receipt, state::StateStore, Relayer,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Position of a transfer among the entries of its PDA
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransferEntry {
+    pub index: u8,
+    /// Entries the PDA holds
+    pub count: u8,
+    /// Entries of the nonce read without a receipt, this one included; the
+    /// build stage gathers that many into one transaction
+    pub pending: u8,
+}
+
+/// An entry relayed in a transaction, as recorded in flight and in receipts
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntryReceipt {
+    pub index: u8,
+    /// Entries the PDA holds
+    pub count: u8,
+    pub amount: u64,
+    /// Recipient (base58)
+    pub to: String,
+    /// Confirmed L2 transaction; empty in flight and for a skipped entry
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub signature: String,
+    #[serde(default)]
+    pub relayed_at: u64,
+    /// Digest of the entry's message (`Info::digest`, base58)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+    /// Why the entry was recorded without being relayed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<String>,
+}
+
+impl EntryReceipt {
+    /// In-flight record of `fetched`; none unless it is an entry
+    pub fn of(fetched: &FetchedTransfer) -> Option<Self> {
+        let entry = fetched.entry?;
+        Some(Self {
+            index: entry.index,
+            count: entry.count,
+            amount: fetched.amount,
+            to: fetched.to_address.to_string(),
+            signature: String::new(),
+            relayed_at: 0,
+            digest: fetched.digest.map(|digest| digest.to_string()),
+            skipped: None,
+        })
+    }
+
+    /// Receipts of the entries of `nonce`, by index
+    pub fn load(store: &StateStore, nonce: u64) -> Result<BTreeMap<u8, Self>> {
+        Ok(store.get(&key(nonce))?.unwrap_or_default())
+    }
+}
+
+/// State key of the entry receipts of `nonce`
+fn key(nonce: u64) -> String {
+    format!("entry_receipts/{}", nonce)
+}
+
+impl Relayer {
+    /// The V3 data of each entry when `data` is a multi-entry PDA; none for
+    /// any other PDA
+    pub(crate) fn transfer_entries(&self, data: &[u8]) -> Option<Result<Vec<Vec<u8>>>> {
+        self.entries_discriminator
+            .filter(|discriminator| data.starts_with(discriminator))
+            .map(|_| TransferInfoAccount::split_entries(data))
+    }
+
+    /// Dead-letters the nonce of `fetched`, naming its entry when it is one
+    pub(crate) fn dead_letter_transfer(
+        &self,
+        fetched: &FetchedTransfer,
+        reason: &str,
+    ) -> Result<()> {
+        let Some(entry) = fetched.entry else {
+            return self.dead_letter(fetched.nonce, reason);
+        };
+        self.dead_letter(
+            fetched.nonce,
+            &format!("entry {} of {}: {}", entry.index, entry.count, reason),
+        )?;
+        self.dead_letters
+            .reject_entry(&self.state, fetched.nonce, entry.index, reason)
+    }
+
+    /// Records the entry `fetched` as skipped for `reason` without relaying
+    /// it; `receipt` is saved as the nonce's when that leaves no entry
+    /// without a receipt
+    pub(crate) fn skip_entry(
+        &self,
+        fetched: &FetchedTransfer,
+        reason: &str,
+        mut receipt: Receipt,
+    ) -> Result<()> {
+        let Some(mut skipped) = EntryReceipt::of(fetched) else {
+            return Ok(());
+        };
+        tracing::info!(
+            "Skipping entry {} of nonce {}: {}",
+            skipped.index,
+            fetched.nonce,
+            reason
+        );
+        skipped.relayed_at = self.clock.unix_timestamp();
+        skipped.skipped = Some(reason.to_string());
+        self.journal(
+            Some(fetched.l1_slot),
+            JournalEvent::Skipped {
+                nonce: fetched.nonce,
+                reason: format!("entry {}: {}", skipped.index, reason),
+            },
+        );
+        self.metrics.skipped_messages_total.inc();
+        if let Some(entries) = self.record_entries(fetched.nonce, vec![skipped])? {
+            complete(&mut receipt, entries);
+            receipt.save(&self.state)?;
+            self.schedule_shipping(fetched.nonce);
+        }
+        Ok(())
+    }
+
+    /// Records the receipts of the entries the confirmed `transfer` relayed.
+    /// Returns whether `receipt` is the nonce's: always for a single
+    /// transfer, and for entries once every entry of the nonce has a receipt,
+    /// which `receipt` then lists
+    pub(crate) fn settle_entries(
+        &self,
+        transfer: &InFlightTransfer,
+        receipt: &mut Receipt,
+    ) -> Result<bool> {
+        if transfer.entries.is_empty() {
+            return Ok(true);
+        }
+        let landed = transfer
+            .entries
+            .iter()
+            .map(|entry| EntryReceipt {
+                signature: transfer.signature.clone(),
+                relayed_at: receipt.relayed_at,
+                ..entry.clone()
+            })
+            .collect();
+        let Some(entries) = self.record_entries(transfer.nonce, landed)? else {
+            tracing::info!(
+                "Entries {:?} of nonce {} landed in {}; other entries have no receipt yet",
+                transfer
+                    .entries
+                    .iter()
+                    .map(|entry| entry.index)
+                    .collect::<Vec<_>>(),
+                transfer.nonce,
+                transfer.signature
+            );
+            return Ok(false);
+        };
+        complete(receipt, entries);
+        Ok(true)
+    }
+
+    /// Adds `receipts` to the entry receipts of `nonce`; returns all of them
+    /// once every entry has one
+    fn record_entries(
+        &self,
+        nonce: u64,
+        receipts: Vec<EntryReceipt>,
+    ) -> Result<Option<Vec<EntryReceipt>>> {
+        let mut stored = EntryReceipt::load(&self.state, nonce)?;
+        let count = receipts
+            .first()
+            .map_or(0, |receipt| usize::from(receipt.count));
+        for receipt in receipts {
+            stored.insert(receipt.index, receipt);
+        }
+        self.state.put(&key(nonce), &stored)?;
+        Ok((stored.len() >= count).then(|| stored.into_values().collect()))
+    }
+}
+
+/// Makes `receipt` the receipt of a nonce whose entries have the receipts
+/// `entries`: their total amount, the first entry's recipient and the
+/// entries themselves
+fn complete(receipt: &mut Receipt, entries: Vec<EntryReceipt>) {
+    receipt.amount = entries
+        .iter()
+        .fold(0u64, |total, entry| total.saturating_add(entry.amount));
+    if let Some(first) = entries.first() {
+        receipt.to = first.to.clone();
+    }
+    receipt.entries = entries;
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::{
+        dlq::{Resolution, ResolutionKind},
+        testing::{self, Harness},
+        transaction::InstructionVersion,
+    };
+    use solana_sdk::pubkey::Pubkey;
+
+    const ENTRIES: [u8; 8] = [0xe1; 8];
+
+    fn entries_harness() -> Harness {
+        let mut config = testing::config();
+        config.l2_instruction_version = InstructionVersion::V6;
+        config.discriminators.transfer_info_entries = Some("e1e1e1e1e1e1e1e1".to_string());
+        config.dust_threshold = 1_000;
+        let harness = Harness::new(config).unwrap();
+        harness.l2.use_instruction_version(InstructionVersion::V6);
+        harness
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rejected_entries_are_dead_lettered_and_replayed_alone() {
+        let mut harness = entries_harness();
+        let to: Vec<Pubkey> = (0..4).map(|_| Pubkey::new_unique()).collect();
+        for recipient in &to {
+            harness.l2.fund(recipient, 1_000_000_000);
+        }
+        // 单条目 PDA 照常读取
+        harness
+            .l1
+            .set_transfer_info(harness.transfer_pda(0), 5_000, &to[3]);
+        harness.l1.set_pda_data(
+            harness.transfer_pda(1),
+            testing::transfer_entries_data(
+                ENTRIES,
+                &[(2_000, to[0]), (400, to[1]), (3_000, to[2])],
+            ),
+        );
+        harness.l1.set_nonce(2);
+        let relayer = harness.relayer().await.unwrap();
+
+        assert!(relayer.process_nonce_range(0, 2, 0).await.is_err());
+        assert_eq!(
+            Receipt::load(&relayer.state, 0).unwrap().unwrap().amount,
+            5_000
+        );
+        let balances: Vec<u64> = to
+            .iter()
+            .map(|recipient| harness.l2.balance(recipient))
+            .collect();
+        assert_eq!(
+            balances,
+            [1_000_002_000, 1_000_000_000, 1_000_003_000, 1_000_005_000]
+        );
+        let letter = relayer.dead_letters.entries().remove(0);
+        assert_eq!(letter.nonce, 1);
+        assert_eq!(letter.entries.keys().copied().collect::<Vec<_>>(), [1]);
+        let landed = EntryReceipt::load(&relayer.state, 1).unwrap();
+        assert_eq!(landed.keys().copied().collect::<Vec<_>>(), [0, 2]);
+        assert!(Receipt::load(&relayer.state, 1).unwrap().is_none());
+        assert_eq!(harness.l2.relayed_nonce(), 1);
+
+        // 运维调整阈值后重放，只提交没有回执的条目
+        relayer
+            .dead_letters
+            .resolve(
+                &relayer.state,
+                1,
+                Resolution {
+                    kind: ResolutionKind::Replayed,
+                    by: "ops".to_string(),
+                    at: 0,
+                    note: None,
+                    l2_signature: None,
+                    applied: false,
+                },
+            )
+            .unwrap();
+        harness.config.dust_threshold = 0;
+        let relayer = harness.relayer().await.unwrap();
+        relayer.refresh_dead_letters().unwrap();
+        relayer.process_nonce_range(1, 2, 0).await.unwrap();
+        assert_eq!(harness.l2.balance(&to[0]), 1_000_002_000);
+        assert_eq!(harness.l2.balance(&to[1]), 1_000_000_400);
+        let receipt = Receipt::load(&relayer.state, 1).unwrap().unwrap();
+        assert_eq!((receipt.amount, receipt.entries.len()), (5_400, 3));
+        assert_eq!(receipt.to, to[0].to_string());
+        assert_eq!(harness.l2.relayed_nonce(), 2);
+    }
+}
@@ -0,0 +1,365 @@
+//! Relay event stream.
+//! With an `[event_stream]` section, every transfer that lands on L2 is
+//! published as a `RelayEvent` once its receipt is written:
+//!
+//! - `kind = "webhook"`: a `POST` of `{"source", "events"}` per batch
+//! - `kind = "nats"`: a `PUB` to `subject` per event over the NATS text
+//!   protocol (plain TCP, no TLS), confirmed by a `PING` round trip
+//! - `kind = "kafka"`: a `POST` of the batch to `topics/{subject}` of a
+//!   Kafka REST Proxy (v2 JSON records keyed by the event id); the crate has
+//!   no native Kafka client
+//!
+//! Events are written to an outbox in `state_dir` (`event_outbox/`) next to
+//! the receipt, and a background loop publishes it every
+//! `flush_interval_ms` in nonce order, retrying a failed batch with a backoff
+//! of up to `max_backoff_secs`, so relaying never waits on the stream and a
+//! restart publishes what was left. Delivery is at least once: consumers
+//! should key events by `id`.
+
+use crate::{
+    config::{EventSinkKind, EventStreamConfig},
+    inflight::InFlightTransfer,
+    models::message::MessageType,
+    receipts::Receipt,
+    Relayer,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+
+/// State key prefix of the events waiting to be published
+pub const EVENT_OUTBOX_PREFIX: &str = "event_outbox/";
+/// Port of a `nats://` URL without one
+const NATS_DEFAULT_PORT: u16 = 4222;
+/// Longest wait for one NATS publication, connection included
+const NATS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A transfer that landed on L2
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelayEvent {
+    /// `<source>:<nonce>`, the same for every delivery of the event
+    pub id: String,
+    pub source: String,
+    pub nonce: u64,
+    /// L1 sender (base58), when the message carries one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    /// Recipient (base58)
+    pub to: String,
+    pub amount: u64,
+    /// Absent for transactions sent by older versions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_type: Option<MessageType>,
+    /// L2 mint paid out (base58); absent for native transfers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mint: Option<String>,
+    pub l1_slot: u64,
+    pub l2_signature: String,
+    /// Nonces merged into the transfer, empty unless coalesced
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub coalesced: Vec<u64>,
+    pub relayed_at: u64,
+}
+
+impl RelayEvent {
+    pub fn new(source: &str, transfer: &InFlightTransfer, receipt: &Receipt) -> Self {
+        Self {
+            id: format!("{}:{}", source, receipt.nonce),
+            source: source.to_string(),
+            nonce: receipt.nonce,
+            from: transfer.sender.clone(),
+            to: receipt.to.clone(),
+            amount: receipt.amount,
+            message_type: transfer.message_type,
+            mint: transfer.mint.clone(),
+            l1_slot: receipt.l1_slot,
+            l2_signature: receipt.signature.clone(),
+            coalesced: receipt.coalesced.clone(),
+            relayed_at: receipt.relayed_at,
+        }
+    }
+}
+
+fn outbox_key(nonce: u64) -> String {
+    format!("{}{:020}", EVENT_OUTBOX_PREFIX, nonce)
+}
+
+enum Target {
+    Webhook {
+        url: String,
+    },
+    Nats {
+        host: String,
+        port: u16,
+        user: Option<String>,
+        password: Option<String>,
+    },
+    Kafka {
+        url: String,
+    },
+}
+
+pub struct EventStream {
+    target: Target,
+    source: String,
+    subject: String,
+    token: Option<String>,
+    http: reqwest::Client,
+    batch_size: usize,
+    interval: Duration,
+    max_backoff: Duration,
+}
+
+impl EventStream {
+    pub fn new(config: &EventStreamConfig, source: &str) -> Result<Self> {
+        let invalid = || anyhow::anyhow!("event_stream.url is not a valid {:?} URL", config.kind);
+        let url = reqwest::Url::parse(config.url.expose()).map_err(|_| invalid())?;
+        let target = match config.kind {
+            EventSinkKind::Webhook | EventSinkKind::Kafka
+                if !matches!(url.scheme(), "http" | "https") =>
+            {
+                return Err(anyhow::anyhow!(
+                    "event_stream.url must be http or https for kind = {:?}",
+                    config.kind
+                ))
+            }
+            EventSinkKind::Webhook => Target::Webhook {
+                url: config.url.expose().to_string(),
+            },
+            EventSinkKind::Kafka => Target::Kafka {
+                url: config.url.expose().trim_end_matches('/').to_string(),
+            },
+            EventSinkKind::Nats => {
+                if url.scheme() != "nats" {
+                    return Err(anyhow::anyhow!(
+                        "event_stream.url must be nats://host[:port] for kind = \"nats\""
+                    ));
+                }
+                Target::Nats {
+                    host: url.host_str().ok_or_else(invalid)?.to_string(),
+                    port: url.port().unwrap_or(NATS_DEFAULT_PORT),
+                    user: (!url.username().is_empty()).then(|| url.username().to_string()),
+                    password: url.password().map(str::to_string),
+                }
+            }
+        };
+        if config.subject.is_empty() {
+            return Err(anyhow::anyhow!("event_stream.subject must not be empty"));
+        }
+        Ok(Self {
+            target,
+            source: source.to_string(),
+            subject: config.subject.clone(),
+            token: config.token.as_ref().map(|token| token.expose().clone()),
+            http: reqwest::Client::new(),
+            batch_size: config.batch_size.max(1),
+            interval: Duration::from_millis(config.flush_interval_ms.max(1)),
+            max_backoff: Duration::from_secs(config.max_backoff_secs.max(1)),
+        })
+    }
+
+    /// Publishes `events`; an error leaves them for a retry
+    pub async fn publish(&self, events: &[RelayEvent]) -> Result<()> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        match &self.target {
+            Target::Webhook { url } => {
+                let body = serde_json::json!({ "source": self.source, "events": events });
+                self.post(url, "application/json", &body).await?;
+                Ok(())
+            }
+            Target::Kafka { url } => {
+                let records: Vec<_> = events
+                    .iter()
+                    .map(|event| serde_json::json!({ "key": event.id, "value": event }))
+                    .collect();
+                let body = serde_json::json!({ "records": records });
+                let response = self
+                    .post(
+                        &format!("{}/topics/{}", url, self.subject),
+                        "application/vnd.kafka.json.v2+json",
+                        &body,
+                    )
+                    .await?;
+                // REST Proxy 对每条记录单独返回错误
+                let failed = response["offsets"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .find(|offset| !offset["error_code"].is_null());
+                match failed {
+                    Some(offset) => Err(anyhow::anyhow!(
+                        "Kafka REST Proxy rejected an event: {}",
+                        offset["error"]
+                    )),
+                    None => Ok(()),
+                }
+            }
+            Target::Nats { .. } => tokio::time::timeout(NATS_TIMEOUT, self.publish_nats(events))
+                .await
+                .map_err(|_| {
+                    anyhow::anyhow!("NATS publication timed out after {:?}", NATS_TIMEOUT)
+                })?,
+        }
+    }
+
+    /// `POST` of `body` to `url`, returning the JSON response (null if none)
+    async fn post(
+        &self,
+        url: &str,
+        content_type: &str,
+        body: &serde_json::Value,
+    ) -> Result<serde_json::Value> {
+        let mut request = self
+            .http
+            .post(url)
+            .header("content-type", content_type)
+            .body(serde_json::to_vec(body)?);
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        let response = request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| anyhow::anyhow!("Event stream request failed: {}", e.without_url()))?;
+        let bytes = response.bytes().await?;
+        Ok(serde_json::from_slice(&bytes).unwrap_or_default())
+    }
+
+    /// Connects, publishes every event and waits for the `PONG` that follows
+    /// them, so an error reply (e.g. a denied subject) fails the batch
+    async fn publish_nats(&self, events: &[RelayEvent]) -> Result<()> {
+        let Target::Nats {
+            host,
+            port,
+            user,
+            password,
+        } = &self.target
+        else {
+            unreachable!("NATS publication to another target");
+        };
+        let stream = TcpStream::connect((host.as_str(), *port))
+            .await
+            .map_err(|e| anyhow::anyhow!("NATS connection failed: {}", e))?;
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        let info = lines.next_line().await?.unwrap_or_default();
+        if !info.starts_with("INFO") {
+            return Err(anyhow::anyhow!("NATS server sent no INFO: {}", info));
+        }
+        let connect = serde_json::json!({
+            "verbose": false,
+            "pedantic": false,
+            "name": "sol-bridge-relayer",
+            "user": user,
+            "pass": password,
+            "auth_token": self.token,
+        });
+        let mut commands = format!("CONNECT {}\r\n", connect).into_bytes();
+        for event in events {
+            let payload = serde_json::to_vec(event)?;
+            commands.extend(format!("PUB {} {}\r\n", self.subject, payload.len()).bytes());
+            commands.extend(payload);
+            commands.extend(b"\r\n");
+        }
+        commands.extend(b"PING\r\n");
+        writer.write_all(&commands).await?;
+        loop {
+            let line = lines
+                .next_line()
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("NATS server closed the connection"))?;
+            match line.as_str() {
+                "PONG" => return Ok(()),
+                "PING" => writer.write_all(b"PONG\r\n").await?,
+                line if line.starts_with("-ERR") => {
+                    return Err(anyhow::anyhow!("NATS server rejected the events: {}", line))
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Relayer {
+    /// Writes the event of the landed `transfer` to the outbox, if an event
+    /// stream is configured
+    pub(crate) fn queue_relay_event(&self, transfer: &InFlightTransfer, receipt: &Receipt) {
+        if self.event_stream.is_none() {
+            return;
+        }
+        let event = RelayEvent::new(&self.source_id, transfer, receipt);
+        if let Err(e) = self.state.put(&outbox_key(receipt.nonce), &event) {
+            tracing::warn!(
+                "failed to queue the relay event of nonce {}: {}",
+                receipt.nonce,
+                e
+            );
+        }
+    }
+
+    /// Publishes the outbox to the event stream; never returns
+    pub(crate) async fn publish_events(&self) -> Result<()> {
+        let Some(stream) = &self.event_stream else {
+            return futures::future::pending().await;
+        };
+        let mut failures = 0u32;
+        loop {
+            self.clock.sleep(stream.interval).await;
+            // standby 与 active 共用 outbox，由 active 实例发布
+            if self.is_standby().unwrap_or(true) {
+                continue;
+            }
+            match self.publish_outbox(stream).await {
+                Ok(()) => failures = 0,
+                Err(e) => {
+                    failures += 1;
+                    self.metrics.event_stream_failures_total.inc();
+                    let backoff = stream
+                        .interval
+                        .saturating_mul(2u32.saturating_pow(failures.min(16)))
+                        .min(stream.max_backoff);
+                    tracing::warn!(
+                        "event stream failed ({} in a row), retrying in {:?}: {}",
+                        failures,
+                        backoff,
+                        e
+                    );
+                    self.clock.sleep(backoff).await;
+                }
+            }
+        }
+    }
+
+    /// Publishes the outbox in batches, removing each once it is published
+    pub(crate) async fn publish_outbox(&self, stream: &EventStream) -> Result<()> {
+        loop {
+            let keys = self.state.list(EVENT_OUTBOX_PREFIX)?;
+            self.metrics.event_stream_queued.set(keys.len() as u64);
+            if keys.is_empty() {
+                return Ok(());
+            }
+            let mut batch = Vec::new();
+            let mut events = Vec::new();
+            for key in keys.into_iter().take(stream.batch_size) {
+                if let Some(event) = self.state.get::<RelayEvent>(&key)? {
+                    events.push(event);
+                }
+                batch.push(key);
+            }
+            stream.publish(&events).await?;
+            for key in &batch {
+                self.state.remove(key)?;
+            }
+            self.metrics
+                .event_stream_published_total
+                .inc_by(events.len() as u64);
+        }
+    }
+}
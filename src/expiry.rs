@@ -0,0 +1,104 @@
+//! Transfer deadlines.
+//! A transfer-info PDA in the extended layout carries `expires_at`, the Unix
+//! time after which the transfer must not be executed on L2 because L1
+//! refunds it. Right before a transfer is sent, its deadline is checked
+//! against the L2 cluster's clock (the clock sysvar) and the local clock,
+//! tolerating `expiry_skew_secs` of difference between them: the transfer is
+//! skipped once the L2 clock plus the tolerance, or the local clock minus it,
+//! has reached the deadline. A skipped transfer gets a receipt without a
+//! transaction (`expired_at`). With `l2_instruction_version = 4` the deadline
+//! is also passed to the L2 program, which enforces it itself.
+//!
+//! A failure of a transfer within `expiry_alert_secs` of its deadline is
+//! alerted every time instead of only once the nonce is dead-lettered.
+
+use crate::{alerts, journal::JournalEvent, limits, receipts::Receipt, PreparedTransfer, Relayer};
+use anyhow::Result;
+use solana_sdk::sysvar::clock::{self, Clock};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ExpirySettings {
+    /// Tolerated difference between the L1, L2 and local clocks
+    pub skew: Duration,
+    /// How close to its deadline every failure of a transfer is alerted
+    pub alert_window: Duration,
+    /// Age after its first read past which a message is dead-lettered
+    pub message_ttl: Option<Duration>,
+}
+
+impl Relayer {
+    /// Unix time of the L2 cluster, from its clock sysvar
+    async fn l2_unix_time(&self) -> Result<u64> {
+        let account = self
+            .l2_client
+            .get_account_with_commitment(&clock::id(), self.l2_client.commitment())
+            .await?
+            .value
+            .ok_or_else(|| anyhow::anyhow!("L2 clock sysvar not found"))?;
+        let clock: Clock = bincode::deserialize(&account.data)
+            .map_err(|e| anyhow::anyhow!("Invalid L2 clock sysvar: {}", e))?;
+        Ok(clock.unix_timestamp.max(0) as u64)
+    }
+
+    /// The deadline of `prepared` if it has passed, by the L2 clock or the
+    /// local one, so the transfer must not be sent
+    pub(crate) async fn passed_deadline(&self, prepared: &PreparedTransfer) -> Result<Option<u64>> {
+        let Some(expires_at) = prepared.expires_at else {
+            return Ok(None);
+        };
+        let skew = self.expiry.skew.as_secs();
+        let l2_now = self.l2_unix_time().await?;
+        let local_now = self.clock.unix_timestamp();
+        // 交易落地前 L1 可能已按自己的时钟退款，提前 skew 秒停止提交
+        let passed = l2_now.saturating_add(skew) >= expires_at
+            || local_now.saturating_sub(skew) >= expires_at;
+        Ok(passed.then_some(expires_at))
+    }
+
+    /// Records a receipt for `prepared`, not relayed because its deadline
+    /// `expires_at` has passed
+    pub(crate) fn expire_transfer(
+        &self,
+        prepared: &PreparedTransfer,
+        expires_at: u64,
+    ) -> Result<()> {
+        tracing::info!(
+            "Skipping nonce {}: its deadline {} has passed",
+            prepared.nonce,
+            expires_at
+        );
+        Receipt::expired(prepared, expires_at, self.clock.unix_timestamp()).save(&self.state)?;
+        self.schedule_shipping(prepared.nonce);
+        self.attempts.finish(prepared.nonce);
+        self.journal_message(
+            Some(prepared.l1_slot),
+            Some(limits::message_type_of(prepared.token)),
+            JournalEvent::Skipped {
+                nonce: prepared.nonce,
+                reason: format!("expired at {}", expires_at),
+            },
+        );
+        self.metrics.expired_transfers_total.inc();
+        Ok(())
+    }
+
+    /// Alerts a failure of `nonce` if its deadline `expires_at` is within
+    /// the alert window
+    pub(crate) fn alert_near_deadline(
+        &self,
+        nonce: u64,
+        expires_at: u64,
+        attempts: u32,
+        error: &anyhow::Error,
+    ) {
+        let left = expires_at.saturating_sub(self.clock.unix_timestamp());
+        if left > self.expiry.alert_window.as_secs() {
+            return;
+        }
+        alerts::raise(&format!(
+            "Nonce {} has failed {} time(s) and expires in {}s (at {}): {}",
+            nonce, attempts, left, expires_at, error
+        ));
+    }
+}
@@ -0,0 +1,267 @@
+//! Fee and throughput accounting.
+//! When a receipt is enriched, the fee charged for its transaction (from the
+//! confirmed transaction's metadata; a packed transaction's share), the
+//! compute units it consumed and the transfers it relayed are added to the
+//! ledger of the UTC day it was relayed on, one state key per day
+//! (`fees/2024-03-01`). Warm-up transactions are added when they confirm.
+//! Each receipt is counted once, when its enrichment is saved, so receipts
+//! left for `enrich-receipts` are counted by the run that enriches them.
+//! `report --fees` prints the ledgers of a date range per bridge and source,
+//! and `GET /status` shows today's.
+
+use crate::{receipts::Receipt, report::civil_from_days, state::StateStore, warmup::WarmupCost};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+/// Prefix of the daily ledger keys
+pub const FEES_PREFIX: &str = "fees/";
+
+/// What the relayer spent and relayed on one day
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeDay {
+    /// `YYYY-MM-DD`, in UTC; empty in totals
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub day: String,
+    /// Relay transactions; a packed one counts once
+    pub transactions: u64,
+    pub transfers: u64,
+    /// Lamports or token base units relayed
+    pub volume: u64,
+    /// Fees of the relay transactions
+    pub fees: u64,
+    pub compute_units: u64,
+    pub warmup_transactions: u64,
+    pub warmup_fees: u64,
+}
+
+impl FeeDay {
+    fn key(day: &str) -> String {
+        format!("{}{}", FEES_PREFIX, day)
+    }
+
+    /// The ledger of `day`, empty if nothing was recorded
+    pub fn load(store: &StateStore, day: &str) -> Result<Self> {
+        Ok(store.get(&Self::key(day))?.unwrap_or_else(|| Self {
+            day: day.to_string(),
+            ..Self::default()
+        }))
+    }
+
+    /// Ledgers of the days from `from` to `to` (inclusive, either open),
+    /// oldest first
+    pub fn range(store: &StateStore, from: Option<&str>, to: Option<&str>) -> Result<Vec<Self>> {
+        let mut days = Vec::new();
+        for key in store.list(FEES_PREFIX)? {
+            let day = key.strip_prefix(FEES_PREFIX).unwrap_or_default();
+            if from.is_some_and(|from| day < from) || to.is_some_and(|to| day > to) {
+                continue;
+            }
+            if let Some(ledger) = store.get::<Self>(&key)? {
+                days.push(ledger);
+            }
+        }
+        days.sort_by(|a, b| a.day.cmp(&b.day));
+        Ok(days)
+    }
+
+    /// Relay and warm-up fees
+    pub fn total_fees(&self) -> u64 {
+        self.fees.saturating_add(self.warmup_fees)
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.transactions += other.transactions;
+        self.transfers += other.transfers;
+        self.volume = self.volume.saturating_add(other.volume);
+        self.fees = self.fees.saturating_add(other.fees);
+        self.compute_units = self.compute_units.saturating_add(other.compute_units);
+        self.warmup_transactions += other.warmup_transactions;
+        self.warmup_fees = self.warmup_fees.saturating_add(other.warmup_fees);
+    }
+}
+
+/// `YYYY-MM-DD` of a Unix time, in UTC
+pub fn day_of(unix: u64) -> String {
+    let (year, month, day) = civil_from_days((unix / 86_400) as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Unix time at which the `YYYY-MM-DD` day `day` starts, in UTC
+pub fn day_start(day: &str) -> Result<u64> {
+    let days = parse_day(day)
+        .and_then(|day| days_of(&day).ok_or_else(|| format!("invalid date {}", day)))
+        .map_err(anyhow::Error::msg)?;
+    Ok(days.max(0) as u64 * 86_400)
+}
+
+/// Parses a `YYYY-MM-DD` date
+pub fn parse_day(day: &str) -> Result<String, String> {
+    // 往返换算能排除 2 月 30 日这类不存在的日期
+    match days_of(day) {
+        Some(days) if days >= 0 && day_of(days as u64 * 86_400) == day => Ok(day.to_string()),
+        _ => Err(format!("invalid date {}, expected YYYY-MM-DD", day)),
+    }
+}
+
+/// Days from 1970-01-01 to the `YYYY-MM-DD` date `day`, the inverse of
+/// `civil_from_days`
+fn days_of(day: &str) -> Option<i64> {
+    let mut parts = day.split('-');
+    let mut next = |len: usize| {
+        parts
+            .next()
+            .filter(|part| part.len() == len && part.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|part| part.parse::<i64>().ok())
+    };
+    let (year, month, date) = (next(4)?, next(2)?, next(2)?);
+    if parts.next().is_some() || !(1..=12).contains(&month) {
+        return None;
+    }
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let doy = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + date - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    Some(era * 146_097 + doe - 719_468)
+}
+
+/// Adds the enriched `receipt` to the ledger of the day it was relayed on
+pub(crate) fn record_receipt(store: &StateStore, receipt: &Receipt) -> Result<()> {
+    let mut ledger = FeeDay::load(store, &day_of(receipt.relayed_at))?;
+    if receipt
+        .packed
+        .first()
+        .is_none_or(|first| *first == receipt.nonce)
+    {
+        ledger.transactions += 1;
+    }
+    ledger.transfers += receipt.coalesced.len().max(1) as u64;
+    ledger.volume = ledger.volume.saturating_add(receipt.amount);
+    ledger.fees = ledger
+        .fees
+        .saturating_add(receipt.actual_fee.unwrap_or(receipt.l2_fee));
+    ledger.compute_units = ledger
+        .compute_units
+        .saturating_add(receipt.compute_units.unwrap_or_default());
+    store.put(&FeeDay::key(&ledger.day), &ledger)
+}
+
+/// Adds a confirmed warm-up transaction to the ledger of its day
+pub(crate) fn record_warmup(store: &StateStore, cost: &WarmupCost) -> Result<()> {
+    let mut ledger = FeeDay::load(store, &day_of(cost.confirmed_at))?;
+    ledger.warmup_transactions += 1;
+    ledger.warmup_fees = ledger.warmup_fees.saturating_add(cost.fee);
+    store.put(&FeeDay::key(&ledger.day), &ledger)
+}
+
+/// Ledgers of one source
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceFees {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bridge: Option<String>,
+    pub source: String,
+    pub days: Vec<FeeDay>,
+    pub totals: FeeDay,
+}
+
+/// Ledgers of a date range across sources
+#[derive(Debug, Clone, Serialize)]
+pub struct FeeReport {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub sources: Vec<SourceFees>,
+    pub totals: FeeDay,
+}
+
+impl FeeReport {
+    pub fn new(from: Option<String>, to: Option<String>) -> Self {
+        Self {
+            from,
+            to,
+            sources: Vec::new(),
+            totals: FeeDay::default(),
+        }
+    }
+
+    /// Adds the ledgers `store` holds for the range
+    pub fn add_source(
+        &mut self,
+        bridge: Option<&str>,
+        source: &str,
+        store: &StateStore,
+    ) -> Result<()> {
+        let days = FeeDay::range(store, self.from.as_deref(), self.to.as_deref())?;
+        let mut totals = FeeDay::default();
+        for day in &days {
+            totals.add(day);
+        }
+        self.totals.add(&totals);
+        self.sources.push(SourceFees {
+            bridge: bridge.map(str::to_string),
+            source: source.to_string(),
+            days,
+            totals,
+        });
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn to_table(&self) -> String {
+        let mut table = String::new();
+        let _ = writeln!(
+            table,
+            "Fees from {} to {}",
+            self.from.as_deref().unwrap_or("the start"),
+            self.to.as_deref().unwrap_or("today")
+        );
+        let header = |table: &mut String| {
+            let _ = writeln!(
+                table,
+                "{:<12} {:>12} {:>9} {:>20} {:>14} {:>14} {:>12} {:>14}",
+                "day",
+                "transactions",
+                "transfers",
+                "volume",
+                "fees",
+                "compute units",
+                "warm-ups",
+                "total fees"
+            );
+        };
+        let row = |table: &mut String, label: &str, day: &FeeDay| {
+            let _ = writeln!(
+                table,
+                "{:<12} {:>12} {:>9} {:>20} {:>14} {:>14} {:>12} {:>14}",
+                label,
+                day.transactions,
+                day.transfers,
+                day.volume,
+                day.fees,
+                day.compute_units,
+                day.warmup_transactions,
+                day.total_fees()
+            );
+        };
+        for source in &self.sources {
+            let _ = match &source.bridge {
+                Some(bridge) => writeln!(table, "\nBridge {}, source {}", bridge, source.source),
+                None => writeln!(table, "\nSource {}", source.source),
+            };
+            header(&mut table);
+            for day in &source.days {
+                row(&mut table, &day.day, day);
+            }
+            row(&mut table, "total", &source.totals);
+        }
+        if self.sources.len() > 1 {
+            table.push('\n');
+            header(&mut table);
+            row(&mut table, "all", &self.totals);
+        }
+        table
+    }
+}
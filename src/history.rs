@@ -0,0 +1,325 @@
+//! Receipt import from L2 transaction history.
+//! Relays made before receipts were kept (e.g. by an earlier relayer) leave
+//! the receipts store empty. `import-history` pages the signatures of an
+//! address (the L2 program by default, or a relayer key) from newest to
+//! oldest, reads each transaction and writes a receipt for every
+//! `relay_message` that has none yet (several in a packed transaction), with
+//! the L1 slot, domain, merged nonces and L2 mint from its memo. Progress is saved after every page, so an
+//! interrupted import resumes where it stopped, and a later import stops at
+//! the newest transaction of the last completed one.
+
+use crate::{enrich::add_transaction_details, receipts::Receipt, transaction, Relayer};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_client::{
+    rpc_client::GetConfirmedSignaturesForAddress2Config, rpc_config::RpcTransactionConfig,
+};
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use solana_transaction_status::{EncodedConfirmedTransactionWithStatusMeta, UiTransactionEncoding};
+use std::{str::FromStr, time::Duration};
+
+/// State key holding the progress of the last import
+pub const HISTORY_IMPORT_PROGRESS_KEY: &str = "history_import_progress";
+
+/// `getSignaturesForAddress` returns at most 1000 signatures per request
+const SIGNATURES_PER_PAGE: usize = 1000;
+
+/// Progress of an import, persisted after every page of signatures
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ImportProgress {
+    /// Address whose history is imported
+    pub address: String,
+    /// Oldest signature read so far; the next page starts before it
+    pub before: Option<String>,
+    /// Newest signature of the previous completed import; paging stops there
+    pub until: Option<String>,
+    /// Newest signature read by this import
+    pub newest: Option<String>,
+    pub checked: u64,
+    pub imported: u64,
+    pub skipped: u64,
+    pub unparseable: u64,
+    pub completed: bool,
+}
+
+pub struct ImportOptions {
+    /// Address whose signatures are paged
+    pub address: Pubkey,
+    /// Maximum number of L2 RPC requests per second
+    pub requests_per_second: u32,
+}
+
+/// What an L2 transaction contributed to the receipts store
+pub(crate) enum Imported {
+    Receipt(Box<Receipt>),
+    /// Not a successful relay, or one that already has a receipt
+    Skipped,
+    /// Relay instruction or transaction that could not be read, and why
+    Unparseable(String),
+}
+
+/// Memo fields recovered from a relay transaction
+#[derive(Deserialize)]
+pub(crate) struct RelayMemo {
+    pub(crate) nonce: u64,
+    #[serde(default)]
+    l1_slot: u64,
+    #[serde(default)]
+    domain: Option<u64>,
+    #[serde(default)]
+    nonces: Vec<u64>,
+    #[serde(default)]
+    digest: Option<String>,
+    #[serde(default)]
+    from: Option<String>,
+    #[serde(default)]
+    mint: Option<String>,
+    #[serde(default)]
+    packed: Vec<u64>,
+}
+
+impl RelayMemo {
+    /// Bridge domain and every nonce the memo's transaction relayed
+    pub(crate) fn relayed(&self) -> (Option<u64>, Vec<u64>) {
+        let nonces = std::iter::once(self.nonce)
+            .chain(self.nonces.iter().copied())
+            .chain(self.packed.iter().copied())
+            .collect();
+        (self.domain, nonces)
+    }
+}
+
+impl Relayer {
+    /// Imports receipts from the history of `options.address`, resuming
+    /// saved progress of the same address
+    pub(crate) async fn import_history(&self, options: &ImportOptions) -> Result<ImportProgress> {
+        let address = options.address.to_string();
+        let saved = self
+            .state
+            .get::<ImportProgress>(HISTORY_IMPORT_PROGRESS_KEY)?
+            .filter(|progress| progress.address == address);
+        let mut progress = match saved {
+            Some(progress) if !progress.completed => {
+                tracing::info!(
+                    "Resuming import of {} ({} transactions checked so far)",
+                    address,
+                    progress.checked
+                );
+                progress
+            }
+            saved => ImportProgress {
+                address: address.clone(),
+                until: saved.and_then(|progress| progress.newest),
+                ..ImportProgress::default()
+            },
+        };
+
+        let min_request_interval =
+            Duration::from_secs_f64(1.0 / options.requests_per_second.max(1) as f64);
+        loop {
+            let config = GetConfirmedSignaturesForAddress2Config {
+                before: parse_signature(progress.before.as_deref())?,
+                until: parse_signature(progress.until.as_deref())?,
+                limit: Some(SIGNATURES_PER_PAGE),
+                commitment: Some(self.l2_client.commitment()),
+            };
+            let started = self.clock.now();
+            let page = self
+                .l2_client
+                .get_signatures_for_address_with_config(&options.address, config)
+                .await?;
+            self.pace(started, min_request_interval).await;
+
+            for status in &page {
+                progress
+                    .newest
+                    .get_or_insert_with(|| status.signature.clone());
+                progress.checked += 1;
+                let imported = if status.err.is_some() {
+                    vec![Imported::Skipped]
+                } else {
+                    let started = self.clock.now();
+                    let imported = self.import_transaction(&status.signature).await?;
+                    self.pace(started, min_request_interval).await;
+                    imported
+                };
+                for imported in imported {
+                    match imported {
+                        Imported::Receipt(receipt) => {
+                            receipt.save(&self.state)?;
+                            progress.imported += 1;
+                        }
+                        Imported::Skipped => progress.skipped += 1,
+                        Imported::Unparseable(reason) => {
+                            tracing::warn!("Transaction {}: {}", status.signature, reason);
+                            progress.unparseable += 1;
+                        }
+                    }
+                }
+            }
+
+            if let Some(oldest) = page.last() {
+                progress.before = Some(oldest.signature.clone());
+            }
+            if page.len() < SIGNATURES_PER_PAGE {
+                break;
+            }
+            self.state.put(HISTORY_IMPORT_PROGRESS_KEY, &progress)?;
+            tracing::info!(
+                "Import progress: {} transactions checked, {} imported",
+                progress.checked,
+                progress.imported
+            );
+        }
+
+        progress.completed = true;
+        // 没有新交易时保留上次的最新签名
+        if progress.newest.is_none() {
+            progress.newest = progress.until.clone();
+        }
+        self.state.put(HISTORY_IMPORT_PROGRESS_KEY, &progress)?;
+        Ok(progress)
+    }
+
+    async fn pace(&self, started: std::time::SystemTime, min_request_interval: Duration) {
+        let elapsed = self.clock.elapsed(started);
+        if elapsed < min_request_interval {
+            self.clock.sleep(min_request_interval - elapsed).await;
+        }
+    }
+
+    /// Reads transaction `signature` and recovers the relays it made.
+    /// RPC errors are returned, transactions that cannot be parsed are not.
+    pub(crate) async fn import_transaction(&self, signature: &str) -> Result<Vec<Imported>> {
+        let parsed = Signature::from_str(signature)
+            .map_err(|e| anyhow::anyhow!("Invalid signature {}: {}", signature, e))?;
+        let config = RpcTransactionConfig {
+            encoding: Some(UiTransactionEncoding::Base64),
+            commitment: Some(self.l2_client.commitment()),
+            max_supported_transaction_version: Some(0),
+        };
+        let confirmed = self
+            .l2_client
+            .get_transaction_with_config(&parsed, config)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to read transaction {}: {}", signature, e))?;
+        Ok(self.relay_receipts(signature, &confirmed))
+    }
+
+    /// Receipts of every `relay_message` in the transaction; a packed
+    /// transaction holds several
+    fn relay_receipts(
+        &self,
+        signature: &str,
+        confirmed: &EncodedConfirmedTransactionWithStatusMeta,
+    ) -> Vec<Imported> {
+        let Some(tx) = confirmed.transaction.transaction.decode() else {
+            return vec![Imported::Unparseable(
+                "transaction could not be decoded".to_string(),
+            )];
+        };
+        let keys = tx.message.static_account_keys();
+        let program_id = *self.transaction_builder.program_id.pubkey();
+        let key = |index: &u8| keys.get(*index as usize);
+        let relays: Vec<_> = tx
+            .message
+            .instructions()
+            .iter()
+            .filter(|instruction| {
+                key(&instruction.program_id_index) == Some(&program_id)
+                    && transaction::is_relay_message(&instruction.data)
+            })
+            .collect();
+        if relays.is_empty() {
+            return vec![Imported::Skipped];
+        }
+
+        let version = self.transaction_builder.instruction_version;
+        let decoded: Vec<_> = relays
+            .iter()
+            .map(|relay| version.decode(&relay.data))
+            .collect();
+        let packed: Vec<u64> = match decoded.as_slice() {
+            [_] => Vec::new(),
+            _ => decoded.iter().flatten().map(|(_, nonce)| *nonce).collect(),
+        };
+        let memo = tx.message.instructions().iter().find_map(|instruction| {
+            (key(&instruction.program_id_index) == Some(&spl_memo::id()))
+                .then(|| serde_json::from_slice::<RelayMemo>(&instruction.data).ok())
+                .flatten()
+        });
+        let relayed_at = confirmed
+            .block_time
+            .map(|time| time.max(0) as u64)
+            .unwrap_or_else(|| self.clock.unix_timestamp());
+        let signer = keys.first().map(Pubkey::to_string).unwrap_or_default();
+        let recipient = self.transaction_builder.accounts.recipient_index();
+
+        relays
+            .iter()
+            .zip(decoded)
+            .map(|(relay, decoded)| {
+                let Some((amount, nonce)) = decoded else {
+                    return Imported::Unparseable(format!(
+                        "relay_message data does not match instruction version {}",
+                        u8::from(version)
+                    ));
+                };
+                let Some(to) = relay.accounts.get(recipient).and_then(key) else {
+                    return Imported::Unparseable(
+                        "relay_message has no recipient account".to_string(),
+                    );
+                };
+                match Receipt::load(&self.state, nonce) {
+                    Ok(None) => {}
+                    Ok(Some(_)) => return Imported::Skipped,
+                    Err(e) => return Imported::Unparseable(format!("stored receipt: {}", e)),
+                }
+
+                let mut receipt = Receipt::imported(
+                    nonce,
+                    amount,
+                    to.to_string(),
+                    signature.to_string(),
+                    signer.clone(),
+                    relayed_at,
+                );
+                receipt.packed = packed.clone();
+                // memo 的 nonce 不一致时不采用其内容；打包交易的 memo 只带 L1 slot 和 domain
+                match &memo {
+                    Some(memo) if memo.nonce == nonce => {
+                        receipt.l1_slot = memo.l1_slot;
+                        receipt.domain = memo.domain;
+                        receipt.coalesced = memo.nonces.clone();
+                        receipt.digest = memo.digest.clone();
+                        receipt.sender = memo.from.clone();
+                        receipt.mint = memo.mint.clone();
+                    }
+                    Some(memo) if memo.packed.contains(&nonce) => {
+                        receipt.l1_slot = memo.l1_slot;
+                        receipt.domain = memo.domain;
+                    }
+                    _ => {}
+                }
+                if let Some(sender) = version.decode_sender(&relay.data) {
+                    receipt.sender = Some(sender.to_string());
+                }
+                if let Err(e) = add_transaction_details(&mut receipt, confirmed) {
+                    return Imported::Unparseable(e.to_string());
+                }
+                receipt.l2_fee = receipt.actual_fee.unwrap_or_default();
+                receipt.estimated_cost = receipt.actual_cost.unwrap_or_default();
+                Imported::Receipt(Box::new(receipt))
+            })
+            .collect()
+    }
+}
+
+fn parse_signature(signature: Option<&str>) -> Result<Option<Signature>> {
+    signature
+        .map(|signature| {
+            Signature::from_str(signature)
+                .map_err(|e| anyhow::anyhow!("Invalid saved signature {}: {}", signature, e))
+        })
+        .transpose()
+}
@@ -0,0 +1,783 @@
+//! In-flight transaction tracking.
+//! Every L2 transaction is recorded in the state store before it is submitted,
+//! so that after a crash the relayer can find out whether it landed instead of
+//! blindly sending the nonce again.
+//!
+//! The records double as the ledger of lamports reserved in the relayer
+//! wallet: a transaction is only recorded, and so sent, when the signer's
+//! balance covers its cost on top of the estimated costs of the signer's
+//! other in-flight transactions whose blockhash has not expired. Otherwise
+//! the send waits for one of them to be resolved, so concurrent sends never
+//! overdraw the wallet; it fails with the shortfall only when nothing else is
+//! in flight. The reservations are read from the persisted records, so those
+//! of a previous run count until they are resolved or expire.
+
+use crate::{
+    confirm_span, confirmation_strategy::ConfirmationStrategy, entries::EntryReceipt, limits,
+    models::message::MessageType, receipts::Receipt, rent::TransferCost, state::StateStore,
+    PreparedTransfer, Relayer,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_client::{rpc_client::SerializableTransaction, rpc_config::RpcSendTransactionConfig};
+use solana_sdk::{
+    commitment_config::CommitmentConfig, signature::Signature, transaction::TransactionError,
+};
+use std::{
+    collections::BTreeMap,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+use tokio::sync::Notify;
+use tracing::{info_span, Instrument, Span};
+
+/// State key holding the in-flight transactions
+pub const IN_FLIGHT_KEY: &str = "in_flight";
+
+const STATUS_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Re-sending of submitted transactions that nodes may have dropped
+#[derive(Debug, Clone, Copy)]
+pub struct RebroadcastSettings {
+    /// Pause between re-sends; zero never re-sends
+    pub interval: Duration,
+    /// How long a transaction is re-sent
+    pub max_duration: Duration,
+}
+
+/// A submitted transaction that has not been resolved yet
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InFlightTransfer {
+    pub nonce: u64,
+    pub signature: String,
+    pub blockhash: String,
+    pub last_valid_block_height: u64,
+    pub submitted_at: u64,
+    /// Transfer details for the receipt (absent in records of older versions)
+    #[serde(default)]
+    pub amount: u64,
+    #[serde(default)]
+    pub to: String,
+    #[serde(default)]
+    pub l1_slot: u64,
+    /// Nonces merged into the transaction, empty unless coalesced
+    #[serde(default)]
+    pub coalesced: Vec<u64>,
+    /// Nonces relayed by the same transaction, empty unless packed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub packed: Vec<u64>,
+    /// Estimated network fee of the transaction
+    #[serde(default)]
+    pub fee: u64,
+    /// Estimated lamports the transaction takes out of the relayer wallet
+    #[serde(default)]
+    pub estimated_cost: u64,
+    /// Rent of the recipient token account the transaction creates
+    #[serde(default)]
+    pub token_account_rent: u64,
+    /// Signer that paid for the transaction
+    #[serde(default)]
+    pub signer: String,
+    /// Digest of the L1 message (`Info::digest`, base58), none if coalesced
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+    /// L1 sender passed to L2 (base58)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sender: Option<String>,
+    /// L2 mint paid out (base58), for token and NFT transfers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mint: Option<String>,
+    /// Token account paid (base58), when the recipient named one instead of
+    /// a wallet
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_account: Option<String>,
+    /// Nonces of accumulated dust paid out with the transfer
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dust: Vec<u64>,
+    /// Kind of the relayed message (absent in records of older versions)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_type: Option<MessageType>,
+    /// Confirmation strategy, none without `[[confirmation_rules]]`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<ConfirmationStrategy>,
+    /// Message type whose `[limits]` override applied, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limits: Option<MessageType>,
+    /// Slot a `safe` transaction was confirmed in, while it waits to finalize
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub landed_slot: Option<u64>,
+    /// Times the transaction was re-sent before it landed
+    #[serde(default)]
+    pub rebroadcasts: u32,
+    /// Entries of a multi-entry PDA the transaction relays; `amount` is their
+    /// total and `to` the first one's recipient
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub entries: Vec<EntryReceipt>,
+}
+
+impl InFlightTransfer {
+    pub(crate) fn new(prepared: &PreparedTransfer, submitted_at: u64) -> Self {
+        Self {
+            nonce: prepared.nonce,
+            signature: prepared.transaction.signatures[0].to_string(),
+            blockhash: prepared.transaction.message.recent_blockhash().to_string(),
+            last_valid_block_height: prepared.last_valid_block_height,
+            submitted_at,
+            amount: prepared.amount,
+            to: prepared.to_address.to_string(),
+            l1_slot: prepared.l1_slot,
+            coalesced: prepared.coalesced.clone(),
+            packed: prepared.packed.clone(),
+            fee: prepared.cost.fee,
+            estimated_cost: prepared.cost.spent(),
+            token_account_rent: prepared.cost.rent,
+            signer: prepared.transaction.message.static_account_keys()[0].to_string(),
+            digest: prepared.digest.map(|digest| digest.to_string()),
+            sender: prepared.sender.map(|sender| sender.to_string()),
+            mint: prepared.token.map(|token| token.l2_mint.to_string()),
+            token_account: prepared
+                .token
+                .filter(|token| token.named.is_some())
+                .map(|token| token.recipient_account(&prepared.to_address).to_string()),
+            dust: prepared.dust.clone(),
+            message_type: Some(limits::message_type_of(prepared.token)),
+            strategy: prepared.strategy,
+            limits: prepared.limits,
+            landed_slot: None,
+            rebroadcasts: 0,
+            entries: prepared.entries.clone(),
+        }
+    }
+}
+
+/// Final state of a submitted transaction
+#[derive(Debug)]
+pub enum TransactionOutcome {
+    /// Landed in `slot` and reached the client commitment
+    Confirmed { slot: u64 },
+    /// Landed but the program returned an error
+    Failed(TransactionError),
+    /// Never landed and its blockhash has expired, so it never will
+    Expired,
+}
+
+/// Result of reserving the cost of a transaction
+#[derive(Debug, PartialEq, Eq)]
+pub enum Reservation {
+    /// Recorded; it may be sent
+    Reserved,
+    /// A transaction was resolved since the balance was read; read it again
+    Stale,
+    /// The balance only covers the cost once some of `transactions`
+    /// in-flight transactions, reserving `reserved` lamports, are resolved
+    Held { transactions: usize, reserved: u64 },
+}
+
+/// Persisted set of in-flight transactions keyed by nonce
+pub struct InFlightTracker {
+    entries: Mutex<BTreeMap<u64, InFlightTransfer>>,
+    /// Transactions resolved so far
+    removals: AtomicU64,
+    removed: Notify,
+    /// Notified when a `safe` transaction lands and waits to finalize
+    landed: Notify,
+}
+
+impl InFlightTracker {
+    pub fn load(store: &StateStore) -> Result<Self> {
+        let entries = store.get(IN_FLIGHT_KEY)?.unwrap_or_default();
+        Ok(Self {
+            entries: Mutex::new(entries),
+            removals: AtomicU64::new(0),
+            removed: Notify::new(),
+            landed: Notify::new(),
+        })
+    }
+
+    /// Replaces the tracked transactions with `entries`, e.g. the ones
+    /// another process persisted
+    pub fn reload(&self, entries: BTreeMap<u64, InFlightTransfer>) {
+        *self.entries.lock().unwrap() = entries;
+    }
+
+    /// Records `transfer`, replacing any previous transaction for the same
+    /// nonce, if `balance`, read after `removals` transactions
+    /// were resolved, covers `cost` on top of the other in-flight
+    /// transactions of its signer that can still land at `block_height`
+    pub fn reserve(
+        &self,
+        store: &StateStore,
+        transfer: InFlightTransfer,
+        cost: &TransferCost,
+        balance: u64,
+        block_height: u64,
+        removals: u64,
+    ) -> Result<Reservation> {
+        let mut entries = self.entries.lock().unwrap();
+        // 读余额后有交易落地并移除时，余额已过时
+        if self.removals.load(Ordering::Relaxed) != removals {
+            return Ok(Reservation::Stale);
+        }
+        let (transactions, reserved) =
+            Self::reserved_locked(&entries, &transfer.signer, block_height, transfer.nonce);
+        let available = balance.saturating_sub(reserved);
+        if available < cost.total() {
+            if transactions == 0 {
+                cost.ensure_covered(available)?;
+            }
+            return Ok(Reservation::Held {
+                transactions,
+                reserved,
+            });
+        }
+        let nonce = transfer.nonce;
+        let previous = entries.insert(nonce, transfer);
+        // 记录未写入存储时不发送，也不留下内存中的记录
+        if let Err(e) = store.put_through(IN_FLIGHT_KEY, &*entries) {
+            match previous {
+                Some(previous) => entries.insert(nonce, previous),
+                None => entries.remove(&nonce),
+            };
+            return Err(e);
+        }
+        Ok(Reservation::Reserved)
+    }
+
+    /// Marks the transaction of `nonce` as confirmed in `slot`, waiting to
+    /// finalize; it no longer reserves balance
+    pub fn mark_landed(
+        &self,
+        store: &StateStore,
+        nonce: u64,
+        slot: u64,
+        rebroadcasts: u32,
+    ) -> Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.get_mut(&nonce) {
+            entry.landed_slot = Some(slot);
+            entry.rebroadcasts = rebroadcasts;
+            store.put(IN_FLIGHT_KEY, &*entries)?;
+            self.removals.fetch_add(1, Ordering::Relaxed);
+            self.removed.notify_waiters();
+            self.landed.notify_one();
+        }
+        Ok(())
+    }
+
+    /// Notified when a transaction is marked landed
+    pub fn landed(&self) -> &Notify {
+        &self.landed
+    }
+
+    /// Notified when a transaction is resolved or marked landed
+    pub fn removed(&self) -> &Notify {
+        &self.removed
+    }
+
+    /// (transactions, lamports) reserved by the in-flight transactions of
+    /// `signer` that can still land at `block_height`
+    pub fn reserved(&self, signer: &str, block_height: u64) -> (usize, u64) {
+        Self::reserved_locked(
+            &self.entries.lock().unwrap(),
+            signer,
+            block_height,
+            u64::MAX,
+        )
+    }
+
+    fn reserved_locked(
+        entries: &BTreeMap<u64, InFlightTransfer>,
+        signer: &str,
+        block_height: u64,
+        except: u64,
+    ) -> (usize, u64) {
+        entries
+            .values()
+            // 旧版本的记录没有签名者，保守地计入
+            .filter(|entry| {
+                entry.nonce != except && (entry.signer == signer || entry.signer.is_empty())
+            })
+            // 已落地的交易已从余额中扣除
+            .filter(|entry| {
+                entry.last_valid_block_height >= block_height && entry.landed_slot.is_none()
+            })
+            .fold((0, 0), |(count, lamports), entry| {
+                (count + 1, lamports.saturating_add(entry.estimated_cost))
+            })
+    }
+
+    /// Transactions resolved so far
+    pub fn removals(&self) -> u64 {
+        self.removals.load(Ordering::Relaxed)
+    }
+
+    pub fn remove(&self, store: &StateStore, nonce: u64) -> Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.remove(&nonce).is_some() {
+            self.removals.fetch_add(1, Ordering::Relaxed);
+            self.removed.notify_waiters();
+            store.put(IN_FLIGHT_KEY, &*entries)?;
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, nonce: u64) -> Option<InFlightTransfer> {
+        self.entries.lock().unwrap().get(&nonce).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn all(&self) -> Vec<InFlightTransfer> {
+        self.entries.lock().unwrap().values().cloned().collect()
+    }
+}
+
+impl Relayer {
+    /// Records `transfer` once the balance of its signer covers the cost of
+    /// `prepared` on top of the signer's other in-flight transactions,
+    /// waiting for them to be resolved while it does not
+    pub(crate) async fn reserve_in_flight(
+        &self,
+        prepared: &PreparedTransfer,
+        transfer: InFlightTransfer,
+    ) -> Result<()> {
+        let signer = prepared.transaction.message.static_account_keys()[0];
+        loop {
+            // 先注册等待，避免错过检查期间的移除
+            let removed = self.in_flight.removed.notified();
+            let removals = self.in_flight.removals();
+            let balance = self.l2_client.get_balance(&signer).await?;
+            self.alert_balance(&signer, balance);
+            let block_height = self.l2_client.get_block_height().await?;
+            let reservation = self.in_flight.reserve(
+                &self.state,
+                transfer.clone(),
+                &prepared.cost,
+                balance,
+                block_height,
+                removals,
+            )?;
+            let (transactions, reserved) = match reservation {
+                Reservation::Reserved => break,
+                Reservation::Stale => continue,
+                Reservation::Held {
+                    transactions,
+                    reserved,
+                } => (transactions, reserved),
+            };
+            self.metrics.balance_reserved.set(reserved);
+            tracing::info!(
+                "Holding nonce {}: balance {} lamports, {} reserved by {} in-flight transaction(s), {} needed",
+                prepared.nonce,
+                balance,
+                reserved,
+                transactions,
+                prepared.cost.total()
+            );
+            tokio::select! {
+                () = removed => {}
+                // 钱包也可能从外部充值
+                () = self.clock.sleep(self.submitter.retry_delay) => {}
+            }
+        }
+        let (_, reserved) = self.in_flight.reserved(&signer.to_string(), 0);
+        self.metrics.balance_reserved.set(reserved);
+        Ok(())
+    }
+
+    /// Polls `signature` until it reaches `commitment`, fails, or its
+    /// blockhash expires
+    pub(crate) async fn await_transaction(
+        &self,
+        signature: &Signature,
+        last_valid_block_height: u64,
+        commitment: CommitmentConfig,
+    ) -> Result<TransactionOutcome> {
+        let outcome = self
+            .poll_transaction(signature, last_valid_block_height, commitment)
+            .await?;
+        Span::current().record("outcome", format!("{:?}", outcome));
+        Ok(outcome)
+    }
+
+    async fn poll_transaction(
+        &self,
+        signature: &Signature,
+        last_valid_block_height: u64,
+        commitment: CommitmentConfig,
+    ) -> Result<TransactionOutcome> {
+        let mut polls = 0u64;
+        loop {
+            polls += 1;
+            Span::current().record("polls", polls);
+            let status = self
+                .l2_client
+                .get_signature_statuses(&[*signature])
+                .await?
+                .value
+                .remove(0);
+
+            if let Some(status) = status {
+                Span::current().record("slot", status.slot);
+                if let Some(err) = status.err {
+                    return Ok(TransactionOutcome::Failed(err));
+                }
+                if status.satisfies_commitment(commitment) {
+                    return Ok(TransactionOutcome::Confirmed { slot: status.slot });
+                }
+            } else if self.l2_client.get_block_height().await? > last_valid_block_height {
+                // 过期后再查一次，避免错过刚落地的交易
+                let status = self
+                    .l2_client
+                    .get_signature_statuses_with_history(&[*signature])
+                    .await?
+                    .value
+                    .remove(0);
+                let Some(status) = status else {
+                    return Ok(TransactionOutcome::Expired);
+                };
+                Span::current().record("slot", status.slot);
+                return Ok(match status.err {
+                    Some(err) => TransactionOutcome::Failed(err),
+                    None => TransactionOutcome::Confirmed { slot: status.slot },
+                });
+            }
+
+            self.clock.sleep(STATUS_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Re-sends `transaction` every `rebroadcast.interval` for up to
+    /// `rebroadcast.max_duration`, counting the re-sends in `count`; never
+    /// completes, so it is meant to be raced against the confirmation
+    pub(crate) async fn rebroadcast(
+        &self,
+        transaction: &(impl SerializableTransaction + Sync),
+        count: &AtomicU32,
+    ) {
+        let settings = self.rebroadcast;
+        if !settings.interval.is_zero() {
+            // 重复发送同一笔已签名交易不会被执行两次
+            let config = RpcSendTransactionConfig {
+                skip_preflight: true,
+                ..RpcSendTransactionConfig::default()
+            };
+            let resends = settings.max_duration.as_millis() / settings.interval.as_millis();
+            for _ in 0..resends {
+                self.clock.sleep(settings.interval).await;
+                self.await_send_rate().await;
+                match self
+                    .l2_client
+                    .send_transaction_with_config(transaction, config)
+                    .await
+                {
+                    Ok(_) => {
+                        count.fetch_add(1, Ordering::Relaxed);
+                        self.metrics.rebroadcasts_total.inc();
+                    }
+                    Err(e) => tracing::warn!(
+                        "rebroadcast of {} failed: {}",
+                        transaction.get_signature(),
+                        e
+                    ),
+                }
+            }
+        }
+        futures::future::pending().await
+    }
+
+    /// Receipt of the transaction `transfer` confirmed in `slot`, with the
+    /// approvals it was relayed with and its relay proof; the transaction's
+    /// details are added by enrichment. A replayed dead letter of the nonce
+    /// is linked to the transaction, and the failed attempts of its nonces
+    /// are copied into the receipt. A nonce the L1 cancellation registry lists
+    /// by now is recorded as a race; a `safe` receipt gets the recipient's
+    /// finalized balance
+    pub(crate) async fn confirmed_receipt(
+        &self,
+        transfer: &InFlightTransfer,
+        slot: u64,
+    ) -> Result<Receipt> {
+        let mut receipt = Receipt::confirmed(transfer, self.clock.unix_timestamp());
+        receipt.domain = self.transaction_builder.domain;
+        receipt.approvals = self.take_approvals(transfer.nonce)?;
+        receipt.proof = Some(self.relay_proof(transfer, slot)?);
+        receipt.cancelled_in_flight = self.cancellation_race(transfer).await;
+        if transfer.strategy == Some(ConfirmationStrategy::Safe) {
+            receipt.finalized_recipient_balance =
+                Some(self.check_recipient_balance(transfer).await?);
+        }
+        if let Some(strategy) = transfer.strategy {
+            self.metrics.confirmed_total.inc(strategy.as_str());
+        }
+        if transfer.token_account_rent > 0 {
+            self.record_token_account_rent(transfer.token_account_rent);
+        }
+        self.settle_dust(transfer)?;
+        let mut attempts = 0;
+        for nonce in std::iter::once(transfer.nonce).chain(transfer.coalesced.iter().copied()) {
+            self.dead_letters
+                .link_replay(&self.state, nonce, &transfer.signature)?;
+            self.attempts.finish(nonce);
+            self.observe_relay_latency(nonce);
+            attempts = attempts.max(self.queue.attempts(nonce));
+            receipt.failures.extend(self.queue.failures(nonce));
+        }
+        receipt.failures.sort_by_key(|failure| failure.started_at);
+        self.metrics
+            .attempts_per_transfer
+            .observe(u64::from(attempts) + 1);
+        Ok(receipt)
+    }
+
+    /// Saves `receipt` of the confirmed `transfer` and hands it on. A
+    /// transaction relaying entries of a multi-entry PDA records their
+    /// receipts, and the nonce's only once every entry has one
+    pub(crate) fn record_confirmed(
+        &self,
+        transfer: &InFlightTransfer,
+        mut receipt: Receipt,
+    ) -> Result<()> {
+        if !self.settle_entries(transfer, &mut receipt)? {
+            self.journal_confirmed(transfer);
+            return Ok(());
+        }
+        receipt.save(&self.state)?;
+        self.schedule_enrichment(transfer.nonce);
+        self.schedule_shipping(transfer.nonce);
+        self.journal_confirmed(transfer);
+        self.notify_relayed(transfer, &receipt);
+        self.save_checkpoint(transfer);
+        Ok(())
+    }
+
+    /// Resolves the in-flight transaction recorded for `nonce`, if any.
+    /// Returns true if it landed, meaning the nonce must not be sent again.
+    pub(crate) async fn resolve_in_flight(&self, nonce: u64) -> Result<bool> {
+        let Some(transfer) = self.in_flight.get(nonce) else {
+            return Ok(false);
+        };
+
+        let signature = Signature::from_str(&transfer.signature).map_err(|e| {
+            anyhow::anyhow!("Invalid in-flight signature {}: {}", transfer.signature, e)
+        })?;
+        let span = info_span!(parent: None, "recover_in_flight", nonce);
+        let outcome = self
+            .await_transaction(
+                &signature,
+                transfer.last_valid_block_height,
+                self.final_commitment(transfer.strategy),
+            )
+            .instrument(confirm_span(&span, &signature))
+            .await?;
+        tracing::info!(
+            "In-flight transaction {} for nonce {}: {:?}",
+            signature,
+            nonce,
+            outcome
+        );
+        let confirmed = matches!(outcome, TransactionOutcome::Confirmed { .. });
+        if let TransactionOutcome::Confirmed { slot } = outcome {
+            let mut receipt = self.confirmed_receipt(&transfer, slot).await?;
+            receipt.rebroadcasts = transfer.rebroadcasts;
+            self.record_confirmed(&transfer, receipt)?;
+        }
+        self.in_flight.remove(&self.state, nonce)?;
+
+        Ok(confirmed)
+    }
+
+    /// Resolves every transaction persisted before the last shutdown.
+    /// Nonces whose transaction expired or failed stay in the pending queue
+    /// and are sent again by the submitter. Landed `safe` transactions are
+    /// left to finalize in the background.
+    pub(crate) async fn recover_in_flight(&self) -> Result<()> {
+        let pending: Vec<InFlightTransfer> = self
+            .in_flight
+            .all()
+            .into_iter()
+            .filter(|transfer| transfer.landed_slot.is_none())
+            .collect();
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        tracing::info!(
+            "Recovering {} in-flight transaction(s) from the previous run...",
+            pending.len()
+        );
+        for transfer in pending {
+            if self.resolve_in_flight(transfer.nonce).await? {
+                self.metrics.relayed_total.inc();
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        state::StorageBackend,
+        storage_buffer::{storage_unavailable, BufferedStorage},
+    };
+    use std::sync::Arc;
+
+    /// Storage failing every write, like a full disk
+    struct FullDisk;
+
+    impl StorageBackend for FullDisk {
+        fn read(&self, _key: &str) -> Result<Option<Vec<u8>>> {
+            Ok(None)
+        }
+
+        fn write(&self, _key: &str, _data: &[u8]) -> Result<()> {
+            Err(anyhow::anyhow!("No space left on device"))
+        }
+
+        fn delete(&self, _key: &str) -> Result<()> {
+            Err(anyhow::anyhow!("No space left on device"))
+        }
+
+        fn list(&self, _prefix: &str) -> Result<Vec<String>> {
+            Ok(Vec::new())
+        }
+    }
+
+    fn transfer(nonce: u64) -> InFlightTransfer {
+        serde_json::from_value(serde_json::json!({
+            "nonce": nonce,
+            "signature": "signature",
+            "blockhash": "blockhash",
+            "last_valid_block_height": 100,
+            "submitted_at": 0,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn a_record_the_storage_refuses_is_not_reserved() {
+        let store = StateStore::new(BufferedStorage::new(Arc::new(FullDisk), 16));
+        let tracker = InFlightTracker::load(&store).unwrap();
+
+        let error = tracker
+            .reserve(
+                &store,
+                transfer(7),
+                &TransferCost::default(),
+                1_000_000,
+                0,
+                0,
+            )
+            .unwrap_err();
+        assert!(storage_unavailable(&error).is_some());
+        assert!(tracker.all().is_empty());
+        // 其他键仍缓冲在内存中
+        store.put("pending_queue", &[7u64]).unwrap();
+        assert_eq!(
+            store.get::<Vec<u64>>("pending_queue").unwrap(),
+            Some(vec![7])
+        );
+    }
+
+    fn signed(
+        nonce: u64,
+        signer: &str,
+        cost: u64,
+        last_valid_block_height: u64,
+    ) -> InFlightTransfer {
+        InFlightTransfer {
+            signer: signer.to_string(),
+            estimated_cost: cost,
+            last_valid_block_height,
+            ..transfer(nonce)
+        }
+    }
+
+    fn cost(amount: u64) -> TransferCost {
+        TransferCost {
+            amount,
+            ..TransferCost::default()
+        }
+    }
+
+    #[test]
+    fn in_flight_costs_of_the_signer_are_reserved_until_resolved() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = StateStore::open(dir.path()).unwrap();
+        let tracker = InFlightTracker::load(&store).unwrap();
+        let reserve = |transfer, amount, removals| {
+            tracker
+                .reserve(&store, transfer, &cost(amount), 1_000, 50, removals)
+                .unwrap()
+        };
+
+        assert_eq!(
+            reserve(signed(1, "a", 600, 100), 600, 0),
+            Reservation::Reserved
+        );
+        // 其他签名者和已过期的交易不占用余额
+        assert_eq!(
+            reserve(signed(2, "b", 900, 100), 900, 0),
+            Reservation::Reserved
+        );
+        assert_eq!(
+            reserve(signed(3, "a", 300, 40), 300, 0),
+            Reservation::Reserved
+        );
+        assert_eq!(
+            reserve(signed(4, "a", 500, 100), 500, 0),
+            Reservation::Held {
+                transactions: 1,
+                reserved: 600
+            }
+        );
+        assert_eq!(tracker.reserved("a", 50), (1, 600));
+        // 同一 nonce 重新预留时不计自身
+        assert_eq!(
+            reserve(signed(1, "a", 700, 100), 700, 0),
+            Reservation::Reserved
+        );
+
+        tracker.remove(&store, 1).unwrap();
+        assert_eq!(
+            reserve(signed(4, "a", 500, 100), 500, 0),
+            Reservation::Stale
+        );
+        assert_eq!(
+            reserve(signed(4, "a", 500, 100), 500, 1),
+            Reservation::Reserved
+        );
+        assert_eq!(
+            InFlightTracker::load(&store).unwrap().reserved("a", 50),
+            (1, 500)
+        );
+    }
+
+    #[test]
+    fn a_cost_no_resolution_can_cover_fails_with_the_shortfall() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = StateStore::open(dir.path()).unwrap();
+        let tracker = InFlightTracker::load(&store).unwrap();
+        let error = tracker
+            .reserve(
+                &store,
+                signed(1, "a", 2_000, 100),
+                &cost(2_000),
+                1_000,
+                0,
+                0,
+            )
+            .unwrap_err();
+        assert!(error.to_string().contains("short by 1000"), "{}", error);
+        assert!(tracker.all().is_empty());
+    }
+}
@@ -0,0 +1,545 @@
+//! Accounts of the L2 `relay_message` instruction.
+//! The account list is data-driven so that an account added by the L2
+//! program (a fee vault, an event authority) is a config change. Each
+//! `[[l2_instruction_accounts]]` table is one account, in order, with a role:
+//!
+//! - `nonce_account`: the source's L2 nonce account
+//! - `signer`: the active signer key, always a signer
+//! - `recipient`: the transfer's recipient (exactly one)
+//! - `fixed`: the account at `pubkey`
+//! - `pda`: the PDA of `program_id` (the L2 program by default) for `seeds`,
+//!   each a literal string or `{nonce}` (u64 LE), `{recipient}` or `{signer}`
+//!
+//! and `writable` / `signer` flags (only the signer role may sign). Without
+//! the section the built-in layout is used: nonce account (writable), signer
+//! (writable), recipient (writable), system program. A configured list is
+//! checked at startup by simulating a relay of the next nonce: the simulation
+//! must not fail on a missing, unsigned, read-only or mismatched account.
+
+use crate::{
+    config::{AccountRole, InstructionAccountConfig},
+    pipeline::FetchedTransfer,
+    pubkeys::{DestinationAccount, DestinationProgramId},
+    Relayer,
+};
+use anyhow::Result;
+use solana_client::rpc_config::RpcSimulateTransactionConfig;
+use solana_sdk::{
+    hash::Hash,
+    instruction::{AccountMeta, InstructionError},
+    pubkey::{Pubkey, MAX_SEEDS, MAX_SEED_LEN},
+    transaction::TransactionError,
+};
+use std::str::FromStr;
+use tracing::info_span;
+
+/// Anchor error codes of failed account constraints and account checks
+const ANCHOR_ACCOUNT_ERRORS: std::ops::Range<u32> = 2000..4000;
+
+/// A seed of a derived account
+#[derive(Debug, Clone)]
+enum Seed {
+    Literal(Vec<u8>),
+    Nonce,
+    Recipient,
+    Signer,
+}
+
+#[derive(Debug, Clone)]
+enum AccountSource {
+    NonceAccount,
+    Signer,
+    Recipient,
+    Fixed(Pubkey),
+    Pda {
+        program_id: Pubkey,
+        seeds: Vec<Seed>,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct AccountSpec {
+    source: AccountSource,
+    writable: bool,
+    signer: bool,
+}
+
+/// Resolved account list of the `relay_message` instruction
+#[derive(Debug, Clone)]
+pub struct InstructionAccounts {
+    accounts: Vec<AccountSpec>,
+    /// Whether the list comes from `l2_instruction_accounts`
+    pub configured: bool,
+}
+
+impl InstructionAccounts {
+    /// Layout of `l2_instruction_accounts`, or the built-in one when empty
+    pub fn from_config(
+        accounts: &[InstructionAccountConfig],
+        l2_program_id: &DestinationProgramId,
+    ) -> Result<Self> {
+        if accounts.is_empty() {
+            return Ok(Self::default());
+        }
+        let specs = accounts
+            .iter()
+            .enumerate()
+            .map(|(index, account)| {
+                parse_account(account, l2_program_id).map_err(|e| {
+                    anyhow::anyhow!("Invalid l2_instruction_accounts[{}]: {}", index, e)
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let recipients = specs
+            .iter()
+            .filter(|spec| matches!(spec.source, AccountSource::Recipient))
+            .count();
+        if recipients != 1 {
+            return Err(anyhow::anyhow!(
+                "l2_instruction_accounts must list exactly one recipient account, found {}",
+                recipients
+            ));
+        }
+        Ok(Self {
+            accounts: specs,
+            configured: true,
+        })
+    }
+
+    /// Position of the recipient in the account list
+    pub fn recipient_index(&self) -> usize {
+        self.accounts
+            .iter()
+            .position(|spec| matches!(spec.source, AccountSource::Recipient))
+            .expect("a recipient account is required")
+    }
+
+    /// Account metas of the instruction relaying `transfer`
+    pub fn resolve(
+        &self,
+        nonce_account: &DestinationAccount,
+        payer: &Pubkey,
+        transfer: &FetchedTransfer,
+    ) -> Vec<AccountMeta> {
+        self.accounts
+            .iter()
+            .map(|spec| {
+                let pubkey = match &spec.source {
+                    AccountSource::NonceAccount => *nonce_account.pubkey(),
+                    AccountSource::Signer => *payer,
+                    AccountSource::Recipient => transfer.to_address,
+                    AccountSource::Fixed(pubkey) => *pubkey,
+                    AccountSource::Pda { program_id, seeds } => {
+                        let nonce = transfer.nonce.to_le_bytes();
+                        let seeds: Vec<&[u8]> = seeds
+                            .iter()
+                            .map(|seed| match seed {
+                                Seed::Literal(bytes) => bytes.as_slice(),
+                                Seed::Nonce => &nonce,
+                                Seed::Recipient => transfer.to_address.as_ref(),
+                                Seed::Signer => payer.as_ref(),
+                            })
+                            .collect();
+                        Pubkey::find_program_address(&seeds, program_id).0
+                    }
+                };
+                AccountMeta {
+                    pubkey,
+                    is_signer: spec.signer,
+                    is_writable: spec.writable,
+                }
+            })
+            .collect()
+    }
+}
+
+impl Default for InstructionAccounts {
+    fn default() -> Self {
+        let account = |source, writable, signer| AccountSpec {
+            source,
+            writable,
+            signer,
+        };
+        Self {
+            accounts: vec![
+                account(AccountSource::NonceAccount, true, false),
+                account(AccountSource::Signer, true, true),
+                account(AccountSource::Recipient, true, false),
+                account(
+                    AccountSource::Fixed(solana_sdk::system_program::id()),
+                    false,
+                    false,
+                ),
+            ],
+            configured: false,
+        }
+    }
+}
+
+fn parse_account(
+    account: &InstructionAccountConfig,
+    l2_program_id: &DestinationProgramId,
+) -> Result<AccountSpec> {
+    let pubkey = |value: &str| {
+        Pubkey::from_str(value).map_err(|e| anyhow::anyhow!("invalid pubkey {}: {}", value, e))
+    };
+    let source = match account.role {
+        AccountRole::NonceAccount => AccountSource::NonceAccount,
+        AccountRole::Signer => AccountSource::Signer,
+        AccountRole::Recipient => AccountSource::Recipient,
+        AccountRole::Fixed => AccountSource::Fixed(pubkey(
+            account
+                .pubkey
+                .as_deref()
+                .ok_or_else(|| anyhow::anyhow!("a fixed account needs pubkey"))?,
+        )?),
+        AccountRole::Pda => {
+            // bump seed 占用一个位置
+            if account.seeds.is_empty() || account.seeds.len() >= MAX_SEEDS {
+                return Err(anyhow::anyhow!(
+                    "a pda account needs 1 to {} seeds, got {}",
+                    MAX_SEEDS - 1,
+                    account.seeds.len()
+                ));
+            }
+            let seeds = account
+                .seeds
+                .iter()
+                .map(|seed| match seed.as_str() {
+                    "{nonce}" => Ok(Seed::Nonce),
+                    "{recipient}" => Ok(Seed::Recipient),
+                    "{signer}" => Ok(Seed::Signer),
+                    literal if literal.len() > MAX_SEED_LEN => Err(anyhow::anyhow!(
+                        "seed {} is longer than {} bytes",
+                        literal,
+                        MAX_SEED_LEN
+                    )),
+                    literal => Ok(Seed::Literal(literal.as_bytes().to_vec())),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            AccountSource::Pda {
+                program_id: match &account.program_id {
+                    Some(program_id) => pubkey(program_id)?,
+                    None => *l2_program_id.pubkey(),
+                },
+                seeds,
+            }
+        }
+    };
+    if !matches!(source, AccountSource::Fixed(_)) && account.pubkey.is_some() {
+        return Err(anyhow::anyhow!("only a fixed account takes pubkey"));
+    }
+    let is_signer = matches!(source, AccountSource::Signer);
+    if account.signer && !is_signer {
+        // 除签名密钥外没有可签名的私钥
+        return Err(anyhow::anyhow!(
+            "only the signer account can sign; the relayer holds no other key"
+        ));
+    }
+    Ok(AccountSpec {
+        source,
+        writable: account.writable,
+        signer: is_signer,
+    })
+}
+
+/// Whether a simulation error means the program rejected an account
+fn account_error(error: &TransactionError) -> bool {
+    match error {
+        TransactionError::AccountNotFound
+        | TransactionError::ProgramAccountNotFound
+        | TransactionError::InvalidAccountIndex
+        | TransactionError::AccountLoadedTwice => true,
+        TransactionError::InstructionError(_, error) => match error {
+            InstructionError::NotEnoughAccountKeys
+            | InstructionError::MissingRequiredSignature
+            | InstructionError::MissingAccount
+            | InstructionError::InvalidAccountData
+            | InstructionError::InvalidAccountOwner
+            | InstructionError::IncorrectProgramId
+            | InstructionError::InvalidSeeds
+            | InstructionError::UninitializedAccount
+            | InstructionError::ReadonlyDataModified
+            | InstructionError::ReadonlyLamportChange
+            | InstructionError::ExternalAccountDataModified
+            | InstructionError::ExternalAccountLamportSpend
+            | InstructionError::PrivilegeEscalation
+            | InstructionError::Immutable => true,
+            InstructionError::Custom(code) => ANCHOR_ACCOUNT_ERRORS.contains(code),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+impl Relayer {
+    /// Simulates a relay of the next nonce to the signer with the configured
+    /// account list; fails if the program rejects one of the accounts. Other
+    /// failures (amount, nonce or program logic) pass, since no real
+    /// transfer is simulated.
+    pub(crate) async fn verify_instruction_accounts(&self) -> Result<()> {
+        let nonce = self.read_l2_relayed_nonce().await?;
+        let payer = self.signers.active();
+        let transfer = FetchedTransfer {
+            nonce,
+            amount: 1,
+            to_address: payer.pubkey(),
+            l1_slot: 0,
+            message_type: None,
+            coalesced: Vec::new(),
+            proof: None,
+            expires_at: None,
+            digest: None,
+            sender: None,
+            token: None,
+            dust: Vec::new(),
+            create_token_account: false,
+            entry: None,
+            span: info_span!("verify_instruction_accounts", nonce),
+        };
+        let transaction = self.transaction_builder.build_transfer_transaction(
+            &transfer,
+            None,
+            None,
+            payer,
+            Hash::default(),
+        )?;
+        let config = RpcSimulateTransactionConfig {
+            sig_verify: false,
+            replace_recent_blockhash: true,
+            commitment: Some(self.l2_client.commitment()),
+            ..RpcSimulateTransactionConfig::default()
+        };
+        let simulation = self
+            .l2_client
+            .simulate_transaction_with_config(&transaction, config)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to simulate l2_instruction_accounts: {}", e))?
+            .value;
+        match simulation.err {
+            Some(error) if account_error(&error) => Err(anyhow::anyhow!(
+                "L2 program {} rejected the accounts of l2_instruction_accounts: {} (logs: {})",
+                self.transaction_builder.program_id,
+                error,
+                simulation.logs.unwrap_or_default().join("; ")
+            )),
+            Some(error) => {
+                tracing::info!(
+                    "l2_instruction_accounts accepted; the test relay failed on: {}",
+                    error
+                );
+                Ok(())
+            }
+            None => {
+                tracing::info!("l2_instruction_accounts accepted by a simulated relay");
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::system_program;
+
+    fn account(role: AccountRole) -> InstructionAccountConfig {
+        InstructionAccountConfig {
+            role,
+            pubkey: None,
+            seeds: Vec::new(),
+            program_id: None,
+            writable: false,
+            signer: false,
+        }
+    }
+
+    fn pda(seeds: &[&str]) -> InstructionAccountConfig {
+        InstructionAccountConfig {
+            seeds: seeds.iter().map(|seed| seed.to_string()).collect(),
+            ..account(AccountRole::Pda)
+        }
+    }
+
+    fn error_of(accounts: &[InstructionAccountConfig]) -> String {
+        InstructionAccounts::from_config(accounts, &Pubkey::new_unique().into())
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn without_a_list_the_built_in_layout_is_used() {
+        let program_id = Pubkey::new_unique().into();
+        let accounts = InstructionAccounts::from_config(&[], &program_id).unwrap();
+        assert!(!accounts.configured);
+        assert_eq!(accounts.recipient_index(), 2);
+        let nonce_account = DestinationAccount::from(Pubkey::new_unique());
+        let payer = Pubkey::new_unique();
+        let transfer = FetchedTransfer::native(7, 1_000, Pubkey::new_unique());
+        assert_eq!(
+            accounts.resolve(&nonce_account, &payer, &transfer),
+            vec![
+                AccountMeta::new(*nonce_account.pubkey(), false),
+                AccountMeta::new(payer, true),
+                AccountMeta::new(transfer.to_address, false),
+                AccountMeta::new_readonly(system_program::id(), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn a_configured_list_resolves_in_order_with_derived_pdas() {
+        let l2_program = Pubkey::new_unique();
+        let fee_program = Pubkey::new_unique();
+        let vault = Pubkey::new_unique();
+        let config = [
+            InstructionAccountConfig {
+                pubkey: Some(vault.to_string()),
+                writable: true,
+                ..account(AccountRole::Fixed)
+            },
+            InstructionAccountConfig {
+                signer: true,
+                writable: true,
+                ..account(AccountRole::Signer)
+            },
+            pda(&["receipt", "{nonce}", "{recipient}"]),
+            InstructionAccountConfig {
+                program_id: Some(fee_program.to_string()),
+                ..pda(&["fee", "{signer}"])
+            },
+            InstructionAccountConfig {
+                writable: true,
+                ..account(AccountRole::Recipient)
+            },
+            account(AccountRole::NonceAccount),
+        ];
+        let accounts = InstructionAccounts::from_config(&config, &l2_program.into()).unwrap();
+        assert!(accounts.configured);
+        assert_eq!(accounts.recipient_index(), 4);
+
+        let nonce_account = DestinationAccount::from(Pubkey::new_unique());
+        let payer = Pubkey::new_unique();
+        let transfer = FetchedTransfer::native(7, 1_000, Pubkey::new_unique());
+        let receipt = Pubkey::find_program_address(
+            &[
+                b"receipt",
+                &7u64.to_le_bytes(),
+                transfer.to_address.as_ref(),
+            ],
+            &l2_program,
+        )
+        .0;
+        let fee = Pubkey::find_program_address(&[b"fee", payer.as_ref()], &fee_program).0;
+        assert_eq!(
+            accounts.resolve(&nonce_account, &payer, &transfer),
+            vec![
+                AccountMeta::new(vault, false),
+                AccountMeta::new(payer, true),
+                AccountMeta::new_readonly(receipt, false),
+                AccountMeta::new_readonly(fee, false),
+                AccountMeta::new(transfer.to_address, false),
+                AccountMeta::new_readonly(*nonce_account.pubkey(), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn the_signer_role_always_signs_and_no_other_may() {
+        let config = [
+            account(AccountRole::Signer),
+            account(AccountRole::Recipient),
+        ];
+        let accounts =
+            InstructionAccounts::from_config(&config, &Pubkey::new_unique().into()).unwrap();
+        let payer = Pubkey::new_unique();
+        let transfer = FetchedTransfer::native(1, 1, Pubkey::new_unique());
+        let metas = accounts.resolve(&Pubkey::new_unique().into(), &payer, &transfer);
+        assert!(metas[0].is_signer);
+        assert!(!metas[0].is_writable);
+
+        let error = error_of(&[
+            account(AccountRole::Recipient),
+            InstructionAccountConfig {
+                signer: true,
+                ..account(AccountRole::NonceAccount)
+            },
+        ]);
+        assert!(error.contains("l2_instruction_accounts[1]"), "{}", error);
+        assert!(
+            error.contains("only the signer account can sign"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn a_list_needs_exactly_one_recipient() {
+        let error = error_of(&[account(AccountRole::Signer)]);
+        assert!(
+            error.contains("exactly one recipient account, found 0"),
+            "{}",
+            error
+        );
+        let error = error_of(&[
+            account(AccountRole::Recipient),
+            account(AccountRole::Recipient),
+        ]);
+        assert!(error.contains("found 2"), "{}", error);
+    }
+
+    #[test]
+    fn malformed_accounts_are_refused_with_their_index() {
+        let recipient = account(AccountRole::Recipient);
+        let cases = [
+            (account(AccountRole::Fixed), "a fixed account needs pubkey"),
+            (
+                InstructionAccountConfig {
+                    pubkey: Some("not a pubkey".to_string()),
+                    ..account(AccountRole::Fixed)
+                },
+                "invalid pubkey not a pubkey",
+            ),
+            (
+                InstructionAccountConfig {
+                    pubkey: Some(Pubkey::new_unique().to_string()),
+                    ..account(AccountRole::Signer)
+                },
+                "only a fixed account takes pubkey",
+            ),
+            (pda(&[]), "needs 1 to 15 seeds, got 0"),
+            (pda(&["seed"; MAX_SEEDS]), "got 16"),
+            (
+                pda(&[&"x".repeat(MAX_SEED_LEN + 1)]),
+                "is longer than 32 bytes",
+            ),
+        ];
+        for (bad, expected) in cases {
+            let error = error_of(&[recipient.clone(), bad]);
+            assert!(error.contains("l2_instruction_accounts[1]"), "{}", error);
+            assert!(error.contains(expected), "{}", error);
+        }
+    }
+
+    #[test]
+    fn only_account_failures_of_the_simulation_are_fatal() {
+        let instruction = |error| TransactionError::InstructionError(0, error);
+        for fatal in [
+            TransactionError::AccountNotFound,
+            instruction(InstructionError::MissingRequiredSignature),
+            instruction(InstructionError::ReadonlyLamportChange),
+            // Anchor 的 ConstraintSeeds
+            instruction(InstructionError::Custom(2006)),
+        ] {
+            assert!(account_error(&fatal), "{:?}", fatal);
+        }
+        for passing in [
+            TransactionError::InsufficientFundsForFee,
+            instruction(InstructionError::InsufficientFunds),
+            // 程序自定义错误
+            instruction(InstructionError::Custom(6000)),
+        ] {
+            assert!(!account_error(&passing), "{:?}", passing);
+        }
+    }
+}
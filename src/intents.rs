@@ -0,0 +1,637 @@
+//! Intent queue between the observer and submitter roles.
+//! With `process_role = "observer"` (`run --role observer`) a process reads
+//! L1 and nothing else: every transfer-info PDA from the source's cursor on,
+//! then the watched account and the cancellation registry whenever they
+//! change, each as an `Intent` signed with `observer_keypair_path`. With
+//! `process_role = "submitter"` the relayer runs as usual, except that its L1
+//! client is a `SubmitterFeed`: it answers the account reads of the pipeline
+//! from the intents signed by `observer_pubkey`, so the process holding the
+//! L2 key never talks to an L1 RPC node. The raw account data travels in the
+//! intent, so the submitter decodes and checks it (attestations, proofs,
+//! policies) exactly as a relayer would.
+//!
+//! The queue is a directory per source (`kind = "file"`, one JSON file per
+//! intent, written atomically) or a Redis list per source (`kind = "redis"`,
+//! `RPUSH`, then `LRANGE` and `LTRIM` by the submitter). Intents carry a
+//! sequence number; the submitter applies each once, in order, and only
+//! removes an intent from the queue once its account is saved in its own
+//! state (`l1_view/`), so a restart of either side loses nothing. An intent
+//! with a bad signature, of another source or out of order is alerted and
+//! dropped.
+
+use crate::{
+    alerts,
+    config::{IntentQueueConfig, IntentQueueKind, RelayerConfig, SourceConfig},
+    dry_run::DryRunStorage,
+    merkle::MessageSource,
+    state::StateStore,
+    subscription::WatchMode,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use solana_account_decoder::{UiAccount, UiAccountEncoding};
+use solana_client::{
+    client_error::Result as ClientResult,
+    nonblocking::rpc_client::RpcClient,
+    rpc_client::RpcClientConfig,
+    rpc_request::{RpcError, RpcRequest},
+    rpc_sender::{RpcSender, RpcTransportStats},
+};
+use solana_sdk::{
+    account::Account,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::Mutex,
+};
+
+/// State key prefix of the L1 accounts known to a submitter
+pub const L1_VIEW_PREFIX: &str = "l1_view/";
+/// State key of the submitter's position in the intent stream
+pub const L1_VIEW_KEY: &str = "l1_view";
+/// Intents read from the queue at a time
+const READ_BATCH: usize = 100;
+/// Longest wait for one Redis command, connection included
+const REDIS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An L1 account as read by the observer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountSnapshot {
+    pub pubkey: String,
+    pub owner: String,
+    pub lamports: u64,
+    /// Account data, base64
+    pub data: String,
+}
+
+impl AccountSnapshot {
+    pub fn new(pubkey: &Pubkey, account: &Account) -> Self {
+        Self {
+            pubkey: pubkey.to_string(),
+            owner: account.owner.to_string(),
+            lamports: account.lamports,
+            data: base64::encode(&account.data),
+        }
+    }
+
+    fn account(&self) -> Result<(Pubkey, Account)> {
+        Ok((
+            Pubkey::from_str(&self.pubkey)?,
+            Account {
+                lamports: self.lamports,
+                data: base64::decode(&self.data)?,
+                owner: Pubkey::from_str(&self.owner)?,
+                executable: false,
+                rent_epoch: 0,
+            },
+        ))
+    }
+}
+
+/// One L1 account read, to be applied by the submitter
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Intent {
+    pub source: String,
+    /// Position in the source's stream, from 0
+    pub seq: u64,
+    /// Nonce of a transfer-info PDA; none for the watched account and the
+    /// cancellation registry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<u64>,
+    /// L1 slot the account was read at
+    pub slot: u64,
+    pub account: AccountSnapshot,
+    pub observed_at: u64,
+}
+
+/// An intent as queued: its JSON and the observer's signature of it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedIntent {
+    pub payload: String,
+    pub observer: String,
+    pub signature: String,
+}
+
+impl SignedIntent {
+    pub fn sign(intent: &Intent, observer: &Keypair) -> Result<Self> {
+        let payload = serde_json::to_string(intent)?;
+        Ok(Self {
+            signature: observer.sign_message(payload.as_bytes()).to_string(),
+            observer: observer.pubkey().to_string(),
+            payload,
+        })
+    }
+
+    /// The intent, if signed by `observer`
+    pub fn verify(&self, observer: &Pubkey) -> Result<Intent> {
+        let signed = Signature::from_str(&self.signature)
+            .ok()
+            .is_some_and(|signature| signature.verify(observer.as_ref(), self.payload.as_bytes()));
+        if !signed {
+            return Err(anyhow::anyhow!("not signed by observer {}", observer));
+        }
+        Ok(serde_json::from_str(&self.payload)?)
+    }
+}
+
+enum Queue {
+    File {
+        dir: PathBuf,
+    },
+    Redis {
+        host: String,
+        port: u16,
+        password: Option<String>,
+        db: Option<u32>,
+        key: String,
+    },
+}
+
+/// The queue of one source
+pub struct IntentQueue {
+    queue: Queue,
+}
+
+impl IntentQueue {
+    pub fn new(config: &IntentQueueConfig, source_id: &str) -> Result<Self> {
+        let queue = match config.kind {
+            IntentQueueKind::File => {
+                let path = config.path.as_ref().ok_or_else(|| {
+                    anyhow::anyhow!("intent_queue.path is required for kind = \"file\"")
+                })?;
+                let dir = PathBuf::from(path).join(source_id);
+                std::fs::create_dir_all(&dir).map_err(|e| {
+                    anyhow::anyhow!("Failed to create intent queue {}: {}", dir.display(), e)
+                })?;
+                Queue::File { dir }
+            }
+            IntentQueueKind::Redis => {
+                let url = config.url.as_ref().ok_or_else(|| {
+                    anyhow::anyhow!("intent_queue.url is required for kind = \"redis\"")
+                })?;
+                let url = reqwest::Url::parse(url.expose())
+                    .ok()
+                    .filter(|url| url.scheme() == "redis")
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "intent_queue.url must be redis://[:password@]host[:port][/db]"
+                        )
+                    })?;
+                let db = match url.path().trim_start_matches('/') {
+                    "" => None,
+                    db => Some(db.parse().map_err(|_| {
+                        anyhow::anyhow!("intent_queue.url has an invalid database {}", db)
+                    })?),
+                };
+                Queue::Redis {
+                    host: url
+                        .host_str()
+                        .ok_or_else(|| anyhow::anyhow!("intent_queue.url has no host"))?
+                        .to_string(),
+                    port: url.port().unwrap_or(6379),
+                    password: url.password().map(str::to_string),
+                    db,
+                    key: format!("{}:{}", config.key, source_id),
+                }
+            }
+        };
+        Ok(Self { queue })
+    }
+
+    /// Appends `intents`, in order
+    pub async fn push(&self, intents: &[(u64, SignedIntent)]) -> Result<()> {
+        if intents.is_empty() {
+            return Ok(());
+        }
+        match &self.queue {
+            Queue::File { dir } => {
+                for (seq, intent) in intents {
+                    // 先写临时文件再改名，避免提交方读到半个文件
+                    let path = dir.join(format!("{:020}.json", seq));
+                    let partial = path.with_extension("json.tmp");
+                    std::fs::write(&partial, serde_json::to_vec(intent)?)?;
+                    std::fs::rename(&partial, &path)?;
+                }
+                Ok(())
+            }
+            Queue::Redis { key, .. } => {
+                let mut args = vec![b"RPUSH".to_vec(), key.as_bytes().to_vec()];
+                for (_, intent) in intents {
+                    args.push(serde_json::to_vec(intent)?);
+                }
+                self.redis(&args).await?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Up to `count` intents from the head of the queue, without removing
+    /// them; an entry that cannot be parsed is returned as the error
+    pub async fn peek(&self, count: usize) -> Result<Vec<Result<SignedIntent>>> {
+        let parse = |bytes: &[u8]| {
+            serde_json::from_slice(bytes).map_err(|e| anyhow::anyhow!("invalid intent: {}", e))
+        };
+        match &self.queue {
+            Queue::File { dir } => {
+                let mut files: Vec<_> = std::fs::read_dir(dir)?
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|path| {
+                        path.extension()
+                            .is_some_and(|extension| extension == "json")
+                    })
+                    .collect();
+                files.sort();
+                files
+                    .into_iter()
+                    .take(count)
+                    .map(|path| Ok(parse(&std::fs::read(path)?)))
+                    .collect()
+            }
+            Queue::Redis { key, .. } => {
+                let reply = self
+                    .redis(&[
+                        b"LRANGE".to_vec(),
+                        key.as_bytes().to_vec(),
+                        b"0".to_vec(),
+                        (count - 1).to_string().into_bytes(),
+                    ])
+                    .await?;
+                match reply {
+                    Resp::Array(entries) => Ok(entries
+                        .into_iter()
+                        .map(|entry| match entry {
+                            Resp::Bulk(Some(bytes)) => parse(&bytes),
+                            other => Err(anyhow::anyhow!("unexpected entry {:?}", other)),
+                        })
+                        .collect()),
+                    other => Err(anyhow::anyhow!("Unexpected LRANGE reply {:?}", other)),
+                }
+            }
+        }
+    }
+
+    /// Removes the first `count` intents
+    pub async fn remove(&self, count: usize) -> Result<()> {
+        if count == 0 {
+            return Ok(());
+        }
+        match &self.queue {
+            Queue::File { dir } => {
+                let mut files: Vec<_> = std::fs::read_dir(dir)?
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|path| {
+                        path.extension()
+                            .is_some_and(|extension| extension == "json")
+                    })
+                    .collect();
+                files.sort();
+                for path in files.into_iter().take(count) {
+                    std::fs::remove_file(path)?;
+                }
+                Ok(())
+            }
+            Queue::Redis { key, .. } => {
+                self.redis(&[
+                    b"LTRIM".to_vec(),
+                    key.as_bytes().to_vec(),
+                    count.to_string().into_bytes(),
+                    b"-1".to_vec(),
+                ])
+                .await?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Runs one Redis command on a new connection
+    async fn redis(&self, args: &[Vec<u8>]) -> Result<Resp> {
+        let Queue::Redis {
+            host,
+            port,
+            password,
+            db,
+            ..
+        } = &self.queue
+        else {
+            unreachable!("Redis command on another queue");
+        };
+        let command = async {
+            let stream = TcpStream::connect((host.as_str(), *port))
+                .await
+                .map_err(|e| anyhow::anyhow!("Redis connection failed: {}", e))?;
+            let (reader, mut writer) = stream.into_split();
+            let mut reader = BufReader::new(reader);
+            let mut commands = Vec::new();
+            if let Some(password) = password {
+                commands.extend(encode_command(&[
+                    b"AUTH".to_vec(),
+                    password.clone().into_bytes(),
+                ]));
+            }
+            if let Some(db) = db {
+                commands.extend(encode_command(&[
+                    b"SELECT".to_vec(),
+                    db.to_string().into_bytes(),
+                ]));
+            }
+            commands.extend(encode_command(args));
+            writer.write_all(&commands).await?;
+            // AUTH 与 SELECT 的回复先读掉
+            for _ in 0..password.is_some() as usize + db.is_some() as usize {
+                read_reply(&mut reader).await?;
+            }
+            read_reply(&mut reader).await
+        };
+        tokio::time::timeout(REDIS_TIMEOUT, command)
+            .await
+            .map_err(|_| anyhow::anyhow!("Redis command timed out after {:?}", REDIS_TIMEOUT))?
+    }
+}
+
+/// A Redis reply
+#[derive(Debug)]
+enum Resp {
+    /// A status or integer reply
+    Status,
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<Resp>),
+}
+
+fn encode_command(args: &[Vec<u8>]) -> Vec<u8> {
+    let mut command = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        command.extend(format!("${}\r\n", arg.len()).bytes());
+        command.extend(arg);
+        command.extend(b"\r\n");
+    }
+    command
+}
+
+/// Reads one reply; an error reply fails
+async fn read_reply<R: AsyncBufReadExt + AsyncReadExt + Unpin + Send>(
+    reader: &mut R,
+) -> Result<Resp> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).await? == 0 {
+        return Err(anyhow::anyhow!("Redis server closed the connection"));
+    }
+    let line = line.trim_end_matches("\r\n");
+    let (kind, rest) = line.split_at(line.len().min(1));
+    match kind {
+        "+" => Ok(Resp::Status),
+        "-" => Err(anyhow::anyhow!("Redis error: {}", rest)),
+        ":" => Ok(Resp::Status),
+        "$" => {
+            let len: i64 = rest.parse()?;
+            if len < 0 {
+                return Ok(Resp::Bulk(None));
+            }
+            let mut bulk = vec![0; len as usize + 2];
+            reader.read_exact(&mut bulk).await?;
+            bulk.truncate(len as usize);
+            Ok(Resp::Bulk(Some(bulk)))
+        }
+        "*" => {
+            let len: i64 = rest.parse()?;
+            let mut entries = Vec::with_capacity(len.max(0) as usize);
+            for _ in 0..len.max(0) {
+                entries.push(Box::pin(read_reply(reader)).await?);
+            }
+            Ok(Resp::Array(entries))
+        }
+        _ => Err(anyhow::anyhow!("Unexpected Redis reply: {}", line)),
+    }
+}
+
+/// Position of a submitter in the intent stream
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct ViewPosition {
+    /// Sequence number of the next intent
+    next_seq: u64,
+    /// Highest L1 slot of an applied intent
+    slot: u64,
+}
+
+struct View {
+    position: ViewPosition,
+    accounts: HashMap<Pubkey, Account>,
+    read_at: Option<Instant>,
+}
+
+/// L1 client of a submitter, answering from the intent queue
+pub struct SubmitterFeed {
+    source_id: String,
+    queue: IntentQueue,
+    observer: Pubkey,
+    state: StateStore,
+    interval: Duration,
+    /// Whether applied intents are removed from the queue
+    consume: bool,
+    view: Mutex<View>,
+}
+
+/// The L1 client of `source` in the submitter role
+pub fn submitter_client(
+    config: &RelayerConfig,
+    source: &SourceConfig,
+    state: StateStore,
+) -> Result<RpcClient> {
+    let queue_config = config.intent_queue.as_ref().ok_or_else(|| {
+        anyhow::anyhow!("process_role = \"submitter\" requires an [intent_queue]")
+    })?;
+    if config.message_source != MessageSource::Pda {
+        return Err(anyhow::anyhow!(
+            "The submitter role relays transfer-info PDAs; use message_source = \"pda\""
+        ));
+    }
+    if config.l1_watch_mode == WatchMode::Subscribe || config.l1_account_cache.is_some() {
+        return Err(anyhow::anyhow!(
+            "The submitter role reads L1 only through the intent queue; use l1_watch_mode = \"poll\" without [l1_account_cache]"
+        ));
+    }
+    let observer = queue_config
+        .observer_pubkey
+        .as_deref()
+        .ok_or_else(|| anyhow::anyhow!("The submitter role requires intent_queue.observer_pubkey"))
+        .and_then(|pubkey| {
+            Pubkey::from_str(pubkey)
+                .map_err(|e| anyhow::anyhow!("Invalid intent_queue.observer_pubkey: {}", e))
+        })?;
+    // 试运行不改动 state_dir，也不从队列中取走意图
+    let state = if config.dry_run {
+        state.with_backend(Arc::new(DryRunStorage::new(state.backend())))
+    } else {
+        state
+    };
+    let mut accounts = HashMap::new();
+    for key in state.list(L1_VIEW_PREFIX)? {
+        if let Some(snapshot) = state.get::<AccountSnapshot>(&key)? {
+            let (pubkey, account) = snapshot.account()?;
+            accounts.insert(pubkey, account);
+        }
+    }
+    let feed = SubmitterFeed {
+        source_id: source.id.clone(),
+        queue: IntentQueue::new(queue_config, &source.id)?,
+        observer,
+        view: Mutex::new(View {
+            position: state.get(L1_VIEW_KEY)?.unwrap_or_default(),
+            accounts,
+            read_at: None,
+        }),
+        state,
+        interval: Duration::from_millis(queue_config.poll_interval_ms),
+        consume: !config.dry_run,
+    };
+    Ok(RpcClient::new_sender(
+        feed,
+        RpcClientConfig::with_commitment(config.l1_commitment.config()),
+    ))
+}
+
+impl SubmitterFeed {
+    /// Applies the queued intents, at most once per `interval`
+    async fn read_queue(&self, view: &mut View) -> Result<()> {
+        if view
+            .read_at
+            .is_some_and(|read_at| read_at.elapsed() < self.interval)
+        {
+            return Ok(());
+        }
+        view.read_at = Some(Instant::now());
+        loop {
+            let entries = self.queue.peek(READ_BATCH).await?;
+            let read = entries.len();
+            for entry in entries {
+                if let Err(e) = entry.and_then(|signed| self.apply(view, &signed)) {
+                    alerts::raise(&format!(
+                        "Source {}: dropped an intent of the queue: {}",
+                        self.source_id, e
+                    ));
+                }
+            }
+            if !self.consume {
+                return Ok(());
+            }
+            self.queue.remove(read).await?;
+            if read < READ_BATCH {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Saves the account of `signed`; a repeated intent is skipped
+    fn apply(&self, view: &mut View, signed: &SignedIntent) -> Result<()> {
+        let intent = signed.verify(&self.observer)?;
+        if intent.source != self.source_id {
+            return Err(anyhow::anyhow!(
+                "intent {} is of source {}",
+                intent.seq,
+                intent.source
+            ));
+        }
+        // 观察方重发的意图序号不大于已应用的，跳过
+        if intent.seq < view.position.next_seq {
+            return Ok(());
+        }
+        if intent.seq > view.position.next_seq {
+            return Err(anyhow::anyhow!(
+                "intent {} skips {} (intents {}..{} are missing)",
+                intent.seq,
+                view.position.next_seq,
+                view.position.next_seq,
+                intent.seq
+            ));
+        }
+        let (pubkey, account) = intent.account.account()?;
+        self.state
+            .put(&format!("{}{}", L1_VIEW_PREFIX, pubkey), &intent.account)?;
+        view.accounts.insert(pubkey, account);
+        view.position = ViewPosition {
+            next_seq: intent.seq + 1,
+            slot: view.position.slot.max(intent.slot),
+        };
+        self.state.put(L1_VIEW_KEY, &view.position)?;
+        Ok(())
+    }
+}
+
+fn encode(pubkey: &Pubkey, account: Option<&Account>) -> Value {
+    match account {
+        Some(account) => json!(UiAccount::encode(
+            pubkey,
+            account,
+            UiAccountEncoding::Base64,
+            None,
+            None
+        )),
+        None => Value::Null,
+    }
+}
+
+fn pubkey_param(param: &Value) -> Option<Pubkey> {
+    param
+        .as_str()
+        .and_then(|pubkey| Pubkey::from_str(pubkey).ok())
+}
+
+#[async_trait]
+impl RpcSender for SubmitterFeed {
+    async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        let mut view = self.view.lock().await;
+        if let Err(e) = self.read_queue(&mut view).await {
+            return Err(RpcError::ForUser(format!("Intent queue read failed: {}", e)).into());
+        }
+        let slot = view.position.slot;
+        let context = |value| json!({ "context": { "slot": slot }, "value": value });
+        match request {
+            RpcRequest::GetVersion => {
+                Ok(json!({ "solana-core": "intent-queue", "feature-set": null }))
+            }
+            RpcRequest::GetSlot => Ok(json!(slot)),
+            RpcRequest::GetAccountInfo => {
+                let pubkey = pubkey_param(&params[0])
+                    .ok_or_else(|| RpcError::ParseError("pubkey".to_string()))?;
+                Ok(context(encode(&pubkey, view.accounts.get(&pubkey))))
+            }
+            RpcRequest::GetMultipleAccounts => {
+                let accounts = params[0]
+                    .as_array()
+                    .ok_or_else(|| RpcError::ParseError("pubkey list".to_string()))?
+                    .iter()
+                    .map(|param| {
+                        let pubkey = pubkey_param(param)?;
+                        Some(encode(&pubkey, view.accounts.get(&pubkey)))
+                    })
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| RpcError::ParseError("pubkey".to_string()))?;
+                Ok(context(json!(accounts)))
+            }
+            _ => Err(RpcError::ForUser(format!(
+                "The submitter role reads L1 only through the intent queue; {} is not available",
+                request
+            ))
+            .into()),
+        }
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        RpcTransportStats::default()
+    }
+
+    fn url(&self) -> String {
+        format!("intent-queue:{}", self.source_id)
+    }
+}
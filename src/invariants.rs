@@ -0,0 +1,601 @@
+//! Nonce-progression invariant checks, enabled with the `testing` feature.
+//! `check-invariants` generates random schedules of L1 nonce advances, late
+//! transfer-info PDAs, failed and dropped L2 sends, waits and crashes (a run
+//! cut off mid-way, followed by a fresh relayer over the same state), and
+//! plays each through `run_once` against `FakeL1`, `FakeL2` and
+//! `FakeStorage` on a paused clock. After every step it checks that
+//!
+//! - no nonce is covered by two landed relay transactions,
+//! - every receipt with a signature belongs to a landed transaction,
+//! - neither the persisted queue position nor the L2 nonce passes a nonce
+//!   that is not relayed, skipped or dead-lettered,
+//!
+//! and once the schedule ends and the backlog drains (every PDA written, no
+//! injected failure left), that every nonce below L1's was relayed exactly
+//! once with a receipt, skipped or dead-lettered; nonces behind a dead letter
+//! may stay pending. A failing schedule is shrunk by dropping steps and
+//! halving their sizes while it still fails, and the minimal one is written
+//! as JSON to replay with `--schedule`. `cargo test --features testing`
+//! checks the same invariants over proptest-generated schedules.
+
+use crate::{
+    clock::{Clock, TokioClock},
+    config::{RelayerConfig, SourceConfig},
+    merkle::MessageSource,
+    pda::PdaManager,
+    receipts::Receipt,
+    signer::Signers,
+    simulate::Rng,
+    testing::{FakeL1, FakeL2, FakeStorage},
+    Relayer,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fmt,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+/// Recipients the transfers cycle through
+const RECIPIENTS: usize = 8;
+/// Runs allowed for the backlog to drain after the schedule
+const DRAIN_RUNS: usize = 120;
+/// Simulated time between drain runs, longer than most retry backoffs
+const DRAIN_PAUSE: Duration = Duration::from_secs(30);
+
+/// One step of a schedule
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum Step {
+    /// L1 shows `count` more nonces; their PDAs appear `delay` steps later
+    Advance { count: u64, delay: usize },
+    /// The next `count` L2 sends fail
+    FailSends { count: usize },
+    /// The next `count` L2 sends are accepted but never land
+    DropSends { count: usize },
+    /// One `run_once`
+    Run,
+    /// A `run_once` cut off after `after_ms` of simulated time, then a
+    /// restart over the same state
+    Crash { after_ms: u64 },
+    /// Simulated time passes
+    Wait { secs: u64 },
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Step::Advance { count, delay: 0 } => write!(f, "advance {}", count),
+            Step::Advance { count, delay } => {
+                write!(f, "advance {} (PDAs {} steps late)", count, delay)
+            }
+            Step::FailSends { count } => write!(f, "fail {} send(s)", count),
+            Step::DropSends { count } => write!(f, "drop {} send(s)", count),
+            Step::Run => write!(f, "run"),
+            Step::Crash { after_ms } => write!(f, "crash after {}ms", after_ms),
+            Step::Wait { secs } => write!(f, "wait {}s", secs),
+        }
+    }
+}
+
+/// A broken invariant and the step after which it was seen
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Violation {
+    /// Index of the step; none while draining after the schedule
+    pub step: Option<usize>,
+    pub invariant: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.step {
+            Some(step) => write!(f, "after step {}: {}", step, self.invariant),
+            None => write!(f, "while draining: {}", self.invariant),
+        }
+    }
+}
+
+/// Outcome of one seed
+#[derive(Debug, Serialize)]
+pub struct SeedReport {
+    pub seed: u64,
+    pub steps: usize,
+    pub nonces: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub violation: Option<Violation>,
+    /// Minimal failing schedule, when the seed failed
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub shrunk: Vec<Step>,
+}
+
+/// Random schedule of `steps` steps
+pub fn generate(seed: u64, steps: usize) -> Vec<Step> {
+    let mut rng = Rng(seed);
+    let mut pick = |below: u64| rng.next_u64() % below;
+    (0..steps)
+        .map(|_| match pick(100) {
+            0..=29 => Step::Advance {
+                count: 1 + pick(5),
+                delay: if pick(4) == 0 {
+                    1 + pick(3) as usize
+                } else {
+                    0
+                },
+            },
+            30..=39 => Step::FailSends {
+                count: 1 + pick(3) as usize,
+            },
+            40..=47 => Step::DropSends {
+                count: 1 + pick(2) as usize,
+            },
+            48..=84 => Step::Run,
+            85..=92 => Step::Crash {
+                after_ms: pick(3_000),
+            },
+            _ => Step::Wait {
+                secs: 1 + pick(120),
+            },
+        })
+        .collect()
+}
+
+/// Plays `schedule` on a paused runtime of its own; the first violation, if any
+pub fn check(config: &RelayerConfig, schedule: &[Step]) -> Result<Option<Violation>> {
+    // 暂停的时钟只能用于单线程 runtime，在独立线程中运行
+    std::thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .start_paused(true)
+                    .build()?
+                    .block_on(play(config, schedule))
+            })
+            .join()
+            .map_err(|_| anyhow::anyhow!("Invariant check panicked"))?
+    })
+}
+
+/// Smallest schedule derived from `schedule` that still breaks an invariant
+pub fn shrink(config: &RelayerConfig, schedule: &[Step]) -> Result<(Vec<Step>, Violation)> {
+    let mut current = schedule.to_vec();
+    let mut violation = check(config, &current)?
+        .ok_or_else(|| anyhow::anyhow!("The schedule does not break an invariant"))?;
+    loop {
+        let mut smaller = false;
+        let mut index = 0;
+        while index < current.len() {
+            let candidates = [
+                Some([&current[..index], &current[index + 1..]].concat()),
+                halved(&current[index]).map(|step| {
+                    let mut candidate = current.clone();
+                    candidate[index] = step;
+                    candidate
+                }),
+            ];
+            let mut shrunk = false;
+            for candidate in candidates.into_iter().flatten() {
+                if let Some(found) = check(config, &candidate)? {
+                    current = candidate;
+                    violation = found;
+                    shrunk = true;
+                    break;
+                }
+            }
+            if shrunk {
+                smaller = true;
+            } else {
+                index += 1;
+            }
+        }
+        if !smaller {
+            return Ok((current, violation));
+        }
+    }
+}
+
+/// `step` with its size halved, if it can get smaller
+fn halved(step: &Step) -> Option<Step> {
+    match *step {
+        Step::Advance { count, delay } if delay > 0 => Some(Step::Advance {
+            count,
+            delay: delay / 2,
+        }),
+        Step::Advance { count, delay } if count > 1 => Some(Step::Advance {
+            count: count / 2,
+            delay,
+        }),
+        Step::FailSends { count } if count > 1 => Some(Step::FailSends { count: count / 2 }),
+        Step::DropSends { count } if count > 1 => Some(Step::DropSends { count: count / 2 }),
+        Step::Crash { after_ms } if after_ms > 0 => Some(Step::Crash {
+            after_ms: after_ms / 2,
+        }),
+        Step::Wait { secs } if secs > 1 => Some(Step::Wait { secs: secs / 2 }),
+        _ => None,
+    }
+}
+
+/// Fakes and position shared by the relayers of one schedule
+struct World {
+    config: RelayerConfig,
+    source: SourceConfig,
+    l1: FakeL1,
+    l2: FakeL2,
+    storage: FakeStorage,
+    pdas: PdaManager,
+    recipients: Vec<Pubkey>,
+    signer: Vec<u8>,
+    clock: Arc<TokioClock>,
+    l1_nonce: u64,
+    /// Nonces whose PDA is written once the step index reaches the value
+    late: BTreeMap<u64, usize>,
+}
+
+impl World {
+    async fn relayer(&self) -> Result<Relayer> {
+        Relayer::with_clients(
+            &self.config,
+            &self.source,
+            Signers::new(Keypair::from_bytes(&self.signer)?, None),
+            self.l1.client(),
+            self.l2.client(),
+            self.storage.store(),
+            self.clock.clone(),
+        )
+        .await
+    }
+
+    fn write_pda(&self, nonce: u64) {
+        let recipient = &self.recipients[nonce as usize % self.recipients.len()];
+        self.l1
+            .set_transfer_info(self.pdas.find_address(nonce).0, 1_000_000, recipient);
+    }
+
+    /// Writes the late PDAs due at step `index`
+    fn write_due(&mut self, index: usize) {
+        let due: Vec<u64> = self
+            .late
+            .iter()
+            .filter(|(_, due)| **due <= index)
+            .map(|(nonce, _)| *nonce)
+            .collect();
+        for nonce in due {
+            self.late.remove(&nonce);
+            self.write_pda(nonce);
+        }
+    }
+
+    /// First broken invariant in the current state; with `drained`, also
+    /// requires every nonce below L1's to be resolved
+    fn violation(&self, relayer: &Relayer, drained: bool) -> Result<Option<String>> {
+        let mut covered: BTreeMap<u64, usize> = BTreeMap::new();
+        for nonces in self.l2.relayed_nonces() {
+            for nonce in nonces {
+                *covered.entry(nonce).or_default() += 1;
+            }
+        }
+        if let Some((nonce, times)) = covered.iter().find(|(_, times)| **times > 1) {
+            return Ok(Some(format!(
+                "nonce {} relayed by {} landed transactions",
+                nonce, times
+            )));
+        }
+
+        let landed: HashSet<String> = self
+            .l2
+            .sent()
+            .iter()
+            .map(|transaction| transaction.signatures[0].to_string())
+            .collect();
+        let mut resolved: BTreeSet<u64> = covered.keys().copied().collect();
+        let mut receipted = BTreeSet::new();
+        for receipt in Receipt::all(&relayer.state)? {
+            if !receipt.signature.is_empty() && !landed.contains(&receipt.signature) {
+                return Ok(Some(format!(
+                    "receipt of nonce {} names transaction {}, which never landed",
+                    receipt.nonce, receipt.signature
+                )));
+            }
+            let nonces = if receipt.coalesced.is_empty() {
+                vec![receipt.nonce]
+            } else {
+                receipt.coalesced.clone()
+            };
+            resolved.extend(&nonces);
+            receipted.extend(nonces);
+        }
+        let dead_letters: BTreeSet<u64> = relayer
+            .dead_letters
+            .entries()
+            .iter()
+            .map(|letter| letter.nonce)
+            .collect();
+        resolved.extend(&dead_letters);
+
+        let prefix = (0..).find(|nonce| !resolved.contains(nonce)).unwrap_or(0);
+        let completed = relayer.queue.completed();
+        if completed > prefix {
+            return Ok(Some(format!(
+                "persisted queue position {} passed nonce {}, which is not resolved",
+                completed, prefix
+            )));
+        }
+        let l2_nonce = self.l2.relayed_nonce();
+        if l2_nonce > prefix {
+            return Ok(Some(format!(
+                "L2 nonce {} passed nonce {}, which is not resolved",
+                l2_nonce, prefix
+            )));
+        }
+        if !drained {
+            return Ok(None);
+        }
+        // 死信之后的 nonce 会一直挡在队列中
+        let blocked_from = dead_letters.first().copied().unwrap_or(u64::MAX);
+        if let Some(nonce) = (0..self.l1_nonce.min(blocked_from)).find(|n| !resolved.contains(n)) {
+            return Ok(Some(format!(
+                "nonce {} below L1 nonce {} was neither relayed, skipped nor dead-lettered",
+                nonce, self.l1_nonce
+            )));
+        }
+        if let Some(nonce) = covered.keys().find(|nonce| !receipted.contains(nonce)) {
+            return Ok(Some(format!(
+                "nonce {} landed on L2 but has no receipt",
+                nonce
+            )));
+        }
+        Ok(None)
+    }
+
+    fn done(&self, relayer: &Relayer) -> bool {
+        let (queued, spilled) = relayer.queue.depth();
+        queued + spilled == 0
+            || (0..self.l1_nonce).all(|nonce| {
+                relayer.dead_letters.contains(nonce)
+                    || Receipt::load(&relayer.state, nonce)
+                        .ok()
+                        .flatten()
+                        .is_some()
+            })
+    }
+}
+
+async fn play(config: &RelayerConfig, schedule: &[Step]) -> Result<Option<Violation>> {
+    if config.message_source != MessageSource::Pda {
+        return Err(anyhow::anyhow!(
+            "check-invariants serves transfer-info PDAs only; set message_source = \"pda\""
+        ));
+    }
+    // 预设的发送失败按次数计，预热交易会打乱顺序
+    let mut config = config.clone();
+    config.warmup_transaction = false;
+    let source = config.sources()?.remove(0);
+    let watched = *source.watched_account.pubkey();
+    let nonce_account = *source.nonce_account.pubkey();
+    let l2 = FakeL2::new(nonce_account);
+    l2.set_owner(nonce_account, *config.l2_program_id.pubkey());
+    let keypair = Keypair::new();
+    l2.fund(&keypair.pubkey(), u64::MAX / 2);
+    let recipients: Vec<Pubkey> = (0..RECIPIENTS).map(|_| Keypair::new().pubkey()).collect();
+    for recipient in &recipients {
+        l2.fund(recipient, 1_000_000_000);
+    }
+    let mut world = World {
+        pdas: PdaManager::new(
+            source.l1_program_id,
+            source.watched_account,
+            source.seeds.clone(),
+        ),
+        config,
+        source,
+        l1: FakeL1::new(watched),
+        l2,
+        storage: FakeStorage::default(),
+        recipients,
+        signer: keypair.to_bytes().to_vec(),
+        clock: Arc::new(TokioClock::starting_at(SystemTime::UNIX_EPOCH)),
+        l1_nonce: 0,
+        late: BTreeMap::new(),
+    };
+    let mut relayer = world.relayer().await?;
+    let violation =
+        |step: Option<usize>, invariant: String| Ok(Some(Violation { step, invariant }));
+
+    for (index, step) in schedule.iter().enumerate() {
+        world.write_due(index);
+        match step {
+            Step::Advance { count, delay } => {
+                for nonce in world.l1_nonce..world.l1_nonce + count {
+                    if *delay == 0 {
+                        world.write_pda(nonce);
+                    } else {
+                        world.late.insert(nonce, index + delay);
+                    }
+                }
+                world.l1_nonce += count;
+                world.l1.set_nonce(world.l1_nonce);
+            }
+            Step::FailSends { count } => {
+                let next = world.l2.send_attempts() + 1;
+                for attempt in next..next + count {
+                    world.l2.fail_send(attempt);
+                }
+            }
+            Step::DropSends { count } => {
+                let next = world.l2.send_attempts() + 1;
+                for attempt in next..next + count {
+                    world.l2.drop_send(attempt);
+                }
+            }
+            Step::Run => {
+                if let Err(e) = relayer.run_once().await {
+                    return violation(Some(index), format!("run_once failed: {}", e));
+                }
+            }
+            Step::Crash { after_ms } => {
+                tokio::select! {
+                    result = relayer.run_once() => {
+                        if let Err(e) = result {
+                            return violation(Some(index), format!("run_once failed: {}", e));
+                        }
+                    }
+                    () = world.clock.sleep(Duration::from_millis(*after_ms)) => {}
+                }
+                drop(relayer);
+                relayer = match world.relayer().await {
+                    Ok(relayer) => relayer,
+                    Err(e) => return violation(Some(index), format!("restart failed: {}", e)),
+                };
+            }
+            Step::Wait { secs } => world.clock.sleep(Duration::from_secs(*secs)).await,
+        }
+        if let Some(invariant) = world.violation(&relayer, false)? {
+            return violation(Some(index), invariant);
+        }
+    }
+
+    // 写入所有迟到的 PDA，等待积压排空
+    world.write_due(usize::MAX);
+    for _ in 0..DRAIN_RUNS {
+        if let Err(e) = relayer.run_once().await {
+            return violation(None, format!("run_once failed: {}", e));
+        }
+        if let Some(invariant) = world.violation(&relayer, false)? {
+            return violation(None, invariant);
+        }
+        if world.done(&relayer) {
+            break;
+        }
+        world.clock.sleep(DRAIN_PAUSE).await;
+    }
+    Ok(world.violation(&relayer, true)?.map(|invariant| Violation {
+        step: None,
+        invariant,
+    }))
+}
+
+/// Checks `seeds` seeds from `first_seed`, shrinking the failing ones
+pub fn run(
+    config: &RelayerConfig,
+    first_seed: u64,
+    seeds: u64,
+    steps: usize,
+) -> Result<Vec<SeedReport>> {
+    let mut reports = Vec::new();
+    for seed in first_seed..first_seed + seeds {
+        let schedule = generate(seed, steps);
+        let nonces = schedule
+            .iter()
+            .map(|step| match step {
+                Step::Advance { count, .. } => *count,
+                _ => 0,
+            })
+            .sum();
+        let mut report = SeedReport {
+            seed,
+            steps,
+            nonces,
+            violation: None,
+            shrunk: Vec::new(),
+        };
+        if check(config, &schedule)?.is_some() {
+            let (shrunk, violation) = shrink(config, &schedule)?;
+            report.violation = Some(violation);
+            report.shrunk = shrunk;
+        }
+        reports.push(report);
+    }
+    Ok(reports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn step() -> impl Strategy<Value = Step> {
+        prop_oneof![
+            3 => (1..6u64, prop_oneof![3 => Just(0usize), 1 => 1..4usize])
+                .prop_map(|(count, delay)| Step::Advance { count, delay }),
+            1 => (1..4usize).prop_map(|count| Step::FailSends { count }),
+            1 => (1..3usize).prop_map(|count| Step::DropSends { count }),
+            4 => Just(Step::Run),
+            1 => (0..3_000u64).prop_map(|after_ms| Step::Crash { after_ms }),
+            1 => (1..121u64).prop_map(|secs| Step::Wait { secs }),
+        ]
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(24))]
+
+        #[test]
+        fn every_fault_schedule_keeps_the_nonce_invariants(
+            schedule in proptest::collection::vec(step(), 1..30)
+        ) {
+            let violation = check(&crate::testing::config(), &schedule).unwrap();
+            prop_assert!(violation.is_none(), "{}", violation.unwrap());
+        }
+    }
+
+    #[test]
+    fn a_seed_always_generates_the_same_schedule() {
+        assert_eq!(generate(7, 40), generate(7, 40));
+        assert_ne!(generate(7, 40), generate(8, 40));
+        let reports = run(&crate::testing::config(), 1, 2, 30).unwrap();
+        assert_eq!(reports.len(), 2);
+        assert!(reports
+            .iter()
+            .all(|report| report.violation.is_none() && report.shrunk.is_empty()));
+    }
+
+    #[test]
+    fn steps_halve_down_to_their_smallest_size() {
+        let mut step = Step::Advance { count: 4, delay: 3 };
+        let mut sizes = Vec::new();
+        while let Some(smaller) = halved(&step) {
+            sizes.push(smaller.clone());
+            step = smaller;
+        }
+        assert_eq!(
+            sizes,
+            vec![
+                Step::Advance { count: 4, delay: 1 },
+                Step::Advance { count: 4, delay: 0 },
+                Step::Advance { count: 2, delay: 0 },
+                Step::Advance { count: 1, delay: 0 },
+            ]
+        );
+        assert_eq!(halved(&Step::Run), None);
+        assert_eq!(halved(&Step::Wait { secs: 1 }), None);
+        assert_eq!(
+            halved(&Step::Crash { after_ms: 1 }),
+            Some(Step::Crash { after_ms: 0 })
+        );
+    }
+
+    #[test]
+    fn a_schedule_replays_from_its_json() {
+        let schedule = generate(3, 12);
+        let replayed: Vec<Step> =
+            serde_json::from_slice(&serde_json::to_vec(&schedule).unwrap()).unwrap();
+        assert_eq!(replayed, schedule);
+        let error = shrink(&crate::testing::config(), &schedule)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("does not break an invariant"), "{}", error);
+    }
+
+    #[test]
+    fn only_pda_sources_are_checked() {
+        let mut config = crate::testing::config();
+        config.message_source = MessageSource::ProgramAccounts;
+        let error = check(&config, &[Step::Run]).unwrap_err().to_string();
+        assert!(error.contains("message_source = \"pda\""), "{}", error);
+    }
+}
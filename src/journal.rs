@@ -0,0 +1,720 @@
+//! Event journal.
+//! With `journal_path` set, every significant decision of the relayer (nonce
+//! observed, nonces enqueued, message skipped, retry scheduled, nonce
+//! dead-lettered, dead letter resolved, transaction confirmed) is appended to a JSON-lines file,
+//! separate from the receipts. Each record is written and flushed as one line,
+//! so a crash leaves at worst a torn last line, which readers skip. The file
+//! is rotated to `<path>.1`, `<path>.2`, ... once it would exceed
+//! `journal_max_bytes`, keeping `journal_retain_files` rotated files.
+//!
+//! While the file cannot be written, up to `storage_buffer_capacity` records
+//! are kept in memory and written, in order, before the next new record.
+//!
+//! Records about one transfer carry its message type (`native`, `token` or
+//! `nft`) when it was read from L1.
+//!
+//! `journal tail` prints the most recent records as readable lines (`--json`
+//! prints the records), optionally only those of a nonce, an event type or a
+//! message type;
+//! with `--follow` it keeps printing records as they are appended, across
+//! rotations. `journal replay` folds the records up to a point in time back
+//! into the pending-queue state of that moment, for post-incident analysis.
+
+use crate::{
+    dlq::ResolutionKind, inflight::InFlightTransfer, models::message::MessageType, Relayer,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fmt::Write as _,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum JournalEvent {
+    /// The L1 watched nonce or the L2 relayed nonce changed
+    NonceObserved {
+        l1_nonce: u64,
+        l2_nonce: u64,
+    },
+    /// Nonces `from..to` entered the pending queue
+    Enqueued {
+        from: u64,
+        to: u64,
+    },
+    /// A message was recorded without being relayed
+    Skipped {
+        nonce: u64,
+        reason: String,
+    },
+    /// A nonce failed and will be tried again
+    RetryScheduled {
+        nonce: u64,
+        attempts: u32,
+        next_retry_at: u64,
+        error: String,
+    },
+    DeadLettered {
+        nonce: u64,
+        reason: String,
+    },
+    /// A relayer picked up an operator's resolution of a dead letter
+    DeadLetterResolved {
+        nonce: u64,
+        resolution: ResolutionKind,
+    },
+    /// The L2 transaction of a nonce (and the nonces merged into it) landed
+    Confirmed {
+        nonce: u64,
+        signature: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        coalesced: Vec<u64>,
+    },
+}
+
+/// Interval at which `journal tail --follow` reads new records
+pub const FOLLOW_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Names of the events, as in the `event` field of the records
+const EVENT_NAMES: [&str; 7] = [
+    "nonce_observed",
+    "enqueued",
+    "skipped",
+    "retry_scheduled",
+    "dead_lettered",
+    "dead_letter_resolved",
+    "confirmed",
+];
+
+impl JournalEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            JournalEvent::NonceObserved { .. } => "nonce_observed",
+            JournalEvent::Enqueued { .. } => "enqueued",
+            JournalEvent::Skipped { .. } => "skipped",
+            JournalEvent::RetryScheduled { .. } => "retry_scheduled",
+            JournalEvent::DeadLettered { .. } => "dead_lettered",
+            JournalEvent::DeadLetterResolved { .. } => "dead_letter_resolved",
+            JournalEvent::Confirmed { .. } => "confirmed",
+        }
+    }
+
+    /// Whether the event is about `nonce`
+    fn concerns(&self, nonce: u64) -> bool {
+        match self {
+            JournalEvent::NonceObserved { .. } => false,
+            JournalEvent::Enqueued { from, to } => (*from..*to).contains(&nonce),
+            JournalEvent::Skipped { nonce: event, .. }
+            | JournalEvent::RetryScheduled { nonce: event, .. }
+            | JournalEvent::DeadLettered { nonce: event, .. }
+            | JournalEvent::DeadLetterResolved { nonce: event, .. } => *event == nonce,
+            JournalEvent::Confirmed {
+                nonce: event,
+                coalesced,
+                ..
+            } => *event == nonce || coalesced.contains(&nonce),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalRecord {
+    /// Unix time of the decision
+    pub at: u64,
+    /// L1 slot the decision is based on, when known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slot: Option<u64>,
+    /// Type of the message the record is about, when read from L1; absent in
+    /// records of older versions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_type: Option<MessageType>,
+    #[serde(flatten)]
+    pub event: JournalEvent,
+}
+
+impl JournalRecord {
+    /// One readable line, the event colored with ANSI escapes when `color`
+    /// is set
+    pub fn to_text(&self, color: bool) -> String {
+        let details = match &self.event {
+            JournalEvent::NonceObserved { l1_nonce, l2_nonce } => {
+                format!("L1 nonce {}, L2 nonce {}", l1_nonce, l2_nonce)
+            }
+            JournalEvent::Enqueued { from, to } => format!("nonces {}..{}", from, to),
+            JournalEvent::Skipped { nonce, reason } => format!("nonce {}: {}", nonce, reason),
+            JournalEvent::RetryScheduled {
+                nonce,
+                attempts,
+                next_retry_at,
+                error,
+            } => format!(
+                "nonce {} failed {} time(s), next attempt at {}: {}",
+                nonce, attempts, next_retry_at, error
+            ),
+            JournalEvent::DeadLettered { nonce, reason } => format!("nonce {}: {}", nonce, reason),
+            JournalEvent::DeadLetterResolved { nonce, resolution } => {
+                format!("nonce {} resolved as {}", nonce, resolution)
+            }
+            JournalEvent::Confirmed {
+                nonce,
+                signature,
+                coalesced,
+            } if coalesced.is_empty() => format!("nonce {} in {}", nonce, signature),
+            JournalEvent::Confirmed {
+                nonce,
+                signature,
+                coalesced,
+            } => format!(
+                "nonce {} in {} (coalesced {:?})",
+                nonce, signature, coalesced
+            ),
+        };
+        let name = self.event.name();
+        let escape = match &self.event {
+            JournalEvent::Confirmed { .. } => Some("\x1b[32m"),
+            JournalEvent::RetryScheduled { .. } | JournalEvent::Skipped { .. } => Some("\x1b[33m"),
+            JournalEvent::DeadLettered { .. } => Some("\x1b[31m"),
+            _ => None,
+        };
+        let name = match escape {
+            Some(escape) if color => format!("{}{}\x1b[0m", escape, name),
+            _ => name.to_string(),
+        };
+        let slot = self
+            .slot
+            .map_or(String::new(), |slot| format!(" (slot {})", slot));
+        let message_type = self
+            .message_type
+            .map_or(String::new(), |message_type| format!(" [{}]", message_type));
+        format!("{}{} {}{}: {}", self.at, slot, name, message_type, details)
+    }
+}
+
+/// Selects the records of `journal tail`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JournalFilter {
+    /// Records about a nonce, including ranges and coalesced transactions
+    /// containing it
+    Nonce(u64),
+    /// Records of one event type
+    Event(&'static str),
+    /// Records about transfers of one message type
+    MessageType(MessageType),
+}
+
+impl JournalFilter {
+    pub fn matches(&self, record: &JournalRecord) -> bool {
+        match self {
+            JournalFilter::Nonce(nonce) => record.event.concerns(*nonce),
+            JournalFilter::Event(name) => record.event.name() == *name,
+            JournalFilter::MessageType(message_type) => record.message_type == Some(*message_type),
+        }
+    }
+}
+
+/// Parses `nonce=<nonce>`, `event=<event>` or `type=native|token|nft`
+pub fn parse_filter(filter: &str) -> Result<JournalFilter, String> {
+    match filter.split_once('=') {
+        Some(("nonce", nonce)) => nonce
+            .parse()
+            .map(JournalFilter::Nonce)
+            .map_err(|_| format!("invalid nonce in {:?}", filter)),
+        Some(("event", name)) => EVENT_NAMES
+            .into_iter()
+            .find(|event| *event == name)
+            .map(JournalFilter::Event)
+            .ok_or_else(|| {
+                format!(
+                    "unknown event {:?}, expected one of {}",
+                    name,
+                    EVENT_NAMES.join(", ")
+                )
+            }),
+        Some(("type", "native")) => Ok(JournalFilter::MessageType(MessageType::Native)),
+        Some(("type", "token")) => Ok(JournalFilter::MessageType(MessageType::Token)),
+        Some(("type", "nft")) => Ok(JournalFilter::MessageType(MessageType::Nft)),
+        Some(("type", name)) => Err(format!(
+            "unknown message type {:?}, expected native, token or nft",
+            name
+        )),
+        _ => Err(format!(
+            "invalid filter {:?}, expected nonce=<nonce>, event=<event> or type=<message type>",
+            filter
+        )),
+    }
+}
+
+struct JournalFile {
+    writer: BufWriter<File>,
+    size: u64,
+}
+
+pub struct Journal {
+    path: PathBuf,
+    max_bytes: u64,
+    retain: usize,
+    file: Mutex<Option<JournalFile>>,
+    /// Lines not written yet because the file could not be written
+    backlog: Mutex<VecDeque<Vec<u8>>>,
+    backlog_capacity: usize,
+    /// A write failed part-way, so the file may end in a torn line
+    torn: AtomicBool,
+    /// Nonces of the last `NonceObserved` record, to record changes only
+    observed: Mutex<Option<(u64, u64)>>,
+}
+
+impl Journal {
+    pub fn new(path: PathBuf, max_bytes: u64, retain: usize, backlog_capacity: usize) -> Self {
+        Self {
+            path,
+            max_bytes: max_bytes.max(1),
+            retain,
+            file: Mutex::new(None),
+            backlog: Mutex::new(VecDeque::new()),
+            backlog_capacity,
+            torn: AtomicBool::new(false),
+            observed: Mutex::new(None),
+        }
+    }
+
+    /// Appends `record` as one line after the lines kept from failed writes;
+    /// when the write fails the record is kept too, unless the backlog is full
+    pub fn append(&self, record: &JournalRecord) -> Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+
+        let mut backlog = self.backlog.lock().unwrap();
+        if !backlog.is_empty() && backlog.len() >= self.backlog_capacity {
+            return Err(anyhow::anyhow!(
+                "journal backlog full ({} records), record dropped",
+                backlog.len()
+            ));
+        }
+        backlog.push_back(line);
+        let written = self.write_backlog(&mut backlog);
+        if written.is_err() && self.backlog_capacity == 0 {
+            backlog.clear();
+        }
+        written
+    }
+
+    /// Writes the lines kept from failed writes, in order
+    pub fn flush_backlog(&self) -> Result<()> {
+        self.write_backlog(&mut self.backlog.lock().unwrap())
+    }
+
+    fn write_backlog(&self, backlog: &mut VecDeque<Vec<u8>>) -> Result<()> {
+        while let Some(line) = backlog.front() {
+            self.write_line(line)?;
+            backlog.pop_front();
+        }
+        Ok(())
+    }
+
+    /// Records in memory waiting for the file to be writable again
+    pub fn backlog(&self) -> (usize, usize) {
+        (self.backlog.lock().unwrap().len(), self.backlog_capacity)
+    }
+
+    /// Writes one line, rotating the file first if it would grow past
+    /// `max_bytes`
+    fn write_line(&self, line: &[u8]) -> Result<()> {
+        let mut file = self.file.lock().unwrap();
+        if file
+            .as_ref()
+            .is_some_and(|file| file.size > 0 && file.size + line.len() as u64 > self.max_bytes)
+        {
+            *file = None;
+            self.rotate()?;
+        }
+        if file.is_none() {
+            *file = Some(self.open()?);
+        }
+        let open = file.as_mut().unwrap();
+        // 上次写入中断时先结束残缺的行
+        let torn = self.torn.load(Ordering::Relaxed);
+        let written = (if torn {
+            open.writer.write_all(b"\n")
+        } else {
+            Ok(())
+        })
+        .and_then(|()| open.writer.write_all(line))
+        .and_then(|()| open.writer.flush());
+        if let Err(e) = written {
+            // 丢弃未写出的缓冲，下次重新打开文件
+            let _ = file.take().map(|open| open.writer.into_parts());
+            self.torn.store(true, Ordering::Relaxed);
+            return Err(e.into());
+        }
+        self.torn.store(false, Ordering::Relaxed);
+        open.size += line.len() as u64 + torn as u64;
+        Ok(())
+    }
+
+    fn open(&self) -> Result<JournalFile> {
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| {
+                anyhow::anyhow!("Failed to open journal {}: {}", self.path.display(), e)
+            })?;
+        let size = file.metadata()?.len();
+        Ok(JournalFile {
+            writer: BufWriter::new(file),
+            size,
+        })
+    }
+
+    /// Shifts `<path>.N` to `<path>.N+1`, dropping the oldest, and moves the
+    /// current file to `<path>.1`
+    fn rotate(&self) -> Result<()> {
+        if self.retain == 0 {
+            fs::remove_file(&self.path)?;
+            return Ok(());
+        }
+        let _ = fs::remove_file(rotated_path(&self.path, self.retain));
+        for index in (1..self.retain).rev() {
+            let from = rotated_path(&self.path, index);
+            if from.exists() {
+                fs::rename(&from, rotated_path(&self.path, index + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        Ok(())
+    }
+
+    /// Records of `path` and its rotated files, oldest first; torn lines are skipped
+    pub fn read(path: &Path) -> Result<Vec<JournalRecord>> {
+        let mut records = Self::read_rotated(path)?;
+        records.extend(Self::read_files(&[path.to_path_buf()])?);
+        Ok(records)
+    }
+
+    /// Records of the rotated files of `path`, oldest first
+    pub fn read_rotated(path: &Path) -> Result<Vec<JournalRecord>> {
+        let mut files: Vec<PathBuf> = (1..)
+            .map(|index| rotated_path(path, index))
+            .take_while(|rotated| rotated.exists())
+            .collect();
+        files.reverse();
+        Self::read_files(&files)
+    }
+
+    fn read_files(files: &[PathBuf]) -> Result<Vec<JournalRecord>> {
+        let mut records = Vec::new();
+        for file in files.iter().filter(|file| file.exists()) {
+            let reader = BufReader::new(File::open(file)?);
+            for line in reader.lines() {
+                if let Ok(record) = serde_json::from_str(&line?) {
+                    records.push(record);
+                }
+            }
+        }
+        Ok(records)
+    }
+}
+
+/// Reads the records appended to a journal file as they are written
+pub struct JournalFollower {
+    path: PathBuf,
+    reader: Option<BufReader<File>>,
+    /// Bytes of the open file read so far
+    position: u64,
+    /// Start of a line whose end is not written yet
+    partial: String,
+}
+
+impl JournalFollower {
+    /// Follows `path` from the start of the current file
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            reader: None,
+            position: 0,
+            partial: String::new(),
+        }
+    }
+
+    /// Records appended since the last call; after a rotation, the rest of
+    /// the rotated file and then the new file
+    pub fn poll(&mut self) -> Result<Vec<JournalRecord>> {
+        let mut records = Vec::new();
+        loop {
+            if self.reader.is_none() {
+                match File::open(&self.path) {
+                    Ok(file) => self.reader = Some(BufReader::new(file)),
+                    // 轮转时文件可能暂时不存在
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(records),
+                    Err(e) => {
+                        return Err(anyhow::anyhow!(
+                            "Failed to open journal {}: {}",
+                            self.path.display(),
+                            e
+                        ))
+                    }
+                }
+            }
+            let reader = self.reader.as_mut().unwrap();
+            loop {
+                let read = reader.read_line(&mut self.partial)?;
+                if read == 0 {
+                    break;
+                }
+                self.position += read as u64;
+                if self.partial.ends_with('\n') {
+                    if let Ok(record) = serde_json::from_str(&self.partial) {
+                        records.push(record);
+                    }
+                    self.partial.clear();
+                }
+            }
+            // 新文件比已读的旧文件短，说明已轮转
+            let rotated = match fs::metadata(&self.path) {
+                Ok(metadata) => metadata.len() < self.position,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => true,
+                Err(e) => return Err(e.into()),
+            };
+            if !rotated {
+                return Ok(records);
+            }
+            self.reader = None;
+            self.position = 0;
+            self.partial.clear();
+        }
+    }
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", index));
+    PathBuf::from(rotated)
+}
+
+/// Retry state of a nonce at the replayed moment
+#[derive(Debug, Clone)]
+pub struct ReplayedRetry {
+    pub attempts: u32,
+    pub next_retry_at: u64,
+    pub error: String,
+}
+
+/// Pending-queue state reconstructed from the journal
+#[derive(Debug, Default)]
+pub struct ReplayedState {
+    pub at: u64,
+    /// Last observed (L1 nonce, L2 nonce)
+    pub nonces: Option<(u64, u64)>,
+    pub pending: BTreeSet<u64>,
+    pub retries: BTreeMap<u64, ReplayedRetry>,
+    pub dead_letters: BTreeMap<u64, String>,
+    /// Records folded in
+    pub records: usize,
+}
+
+impl ReplayedState {
+    /// Folds the records at or before `at` into the state of that moment
+    pub fn replay(records: &[JournalRecord], at: u64) -> Self {
+        let mut state = Self {
+            at,
+            ..Self::default()
+        };
+        for record in records.iter().filter(|record| record.at <= at) {
+            state.records += 1;
+            match &record.event {
+                JournalEvent::NonceObserved { l1_nonce, l2_nonce } => {
+                    state.nonces = Some((*l1_nonce, *l2_nonce));
+                }
+                JournalEvent::Enqueued { from, to } => state.pending.extend(*from..*to),
+                JournalEvent::Skipped { nonce, .. } => state.complete(*nonce),
+                JournalEvent::RetryScheduled {
+                    nonce,
+                    attempts,
+                    next_retry_at,
+                    error,
+                } => {
+                    state.retries.insert(
+                        *nonce,
+                        ReplayedRetry {
+                            attempts: *attempts,
+                            next_retry_at: *next_retry_at,
+                            error: error.clone(),
+                        },
+                    );
+                }
+                JournalEvent::DeadLettered { nonce, reason } => {
+                    state.dead_letters.insert(*nonce, reason.clone());
+                }
+                JournalEvent::DeadLetterResolved { nonce, resolution } => {
+                    state.dead_letters.remove(nonce);
+                    state.retries.remove(nonce);
+                    if *resolution != ResolutionKind::Replayed {
+                        state.complete(*nonce);
+                    }
+                }
+                JournalEvent::Confirmed {
+                    nonce, coalesced, ..
+                } => {
+                    state.complete(*nonce);
+                    for nonce in coalesced {
+                        state.complete(*nonce);
+                    }
+                }
+            }
+        }
+        state
+    }
+
+    fn complete(&mut self, nonce: u64) {
+        self.pending.remove(&nonce);
+        self.retries.remove(&nonce);
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        let _ = writeln!(
+            text,
+            "State at {} ({} journal record(s))",
+            self.at, self.records
+        );
+        if let Some((l1_nonce, l2_nonce)) = self.nonces {
+            let _ = writeln!(text, "- L1 nonce {}, L2 nonce {}", l1_nonce, l2_nonce);
+        }
+        let _ = writeln!(
+            text,
+            "- {} pending: {}",
+            self.pending.len(),
+            format_ranges(&self.pending)
+        );
+        for (nonce, retry) in &self.retries {
+            let _ = writeln!(
+                text,
+                "- nonce {}: {} attempt(s), next at {}, last error: {}",
+                nonce, retry.attempts, retry.next_retry_at, retry.error
+            );
+        }
+        for (nonce, reason) in &self.dead_letters {
+            let _ = writeln!(text, "- nonce {} dead-lettered: {}", nonce, reason);
+        }
+        text
+    }
+}
+
+/// `3..6, 9` for {3, 4, 5, 9}
+fn format_ranges(nonces: &BTreeSet<u64>) -> String {
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    for &nonce in nonces {
+        match ranges.last_mut() {
+            Some((_, end)) if *end == nonce => *end = nonce + 1,
+            _ => ranges.push((nonce, nonce + 1)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|(start, end)| {
+            if end - start == 1 {
+                start.to_string()
+            } else {
+                format!("{}..{}", start, end)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl Relayer {
+    /// Appends `event` to the journal, if one is configured. A failed write
+    /// only logs a warning: the journal is a debugging aid, not relay state.
+    pub(crate) fn journal(&self, slot: Option<u64>, event: JournalEvent) {
+        self.journal_message(slot, None, event);
+    }
+
+    /// Appends `event` about a transfer of `message_type` to the journal
+    pub(crate) fn journal_message(
+        &self,
+        slot: Option<u64>,
+        message_type: Option<MessageType>,
+        event: JournalEvent,
+    ) {
+        let Some(journal) = &self.journal else {
+            return;
+        };
+        if let JournalEvent::NonceObserved { l1_nonce, l2_nonce } = event {
+            let mut observed = journal.observed.lock().unwrap();
+            if *observed == Some((l1_nonce, l2_nonce)) {
+                return;
+            }
+            *observed = Some((l1_nonce, l2_nonce));
+        }
+        let record = JournalRecord {
+            at: self.clock.unix_timestamp(),
+            slot,
+            message_type,
+            event,
+        };
+        if let Err(e) = journal.append(&record) {
+            tracing::warn!("failed to write journal {}: {}", journal.path.display(), e);
+        }
+    }
+
+    pub(crate) fn journal_confirmed(&self, transfer: &InFlightTransfer) {
+        self.journal_message(
+            Some(transfer.l1_slot),
+            transfer.message_type,
+            JournalEvent::Confirmed {
+                nonce: transfer.nonce,
+                signature: transfer.signature.clone(),
+                coalesced: transfer.coalesced.clone(),
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_parse_and_match_the_message_type() {
+        let record: JournalRecord = serde_json::from_str(
+            r#"{"at":7,"slot":3,"message_type":"token","event":"confirmed","nonce":4,"signature":"sig"}"#,
+        )
+        .unwrap();
+        let legacy: JournalRecord =
+            serde_json::from_str(r#"{"at":7,"event":"confirmed","nonce":4,"signature":"sig"}"#)
+                .unwrap();
+
+        let token = parse_filter("type=token").unwrap();
+        assert_eq!(token, JournalFilter::MessageType(MessageType::Token));
+        assert!(token.matches(&record));
+        assert!(!token.matches(&legacy));
+        assert!(!parse_filter("type=native").unwrap().matches(&record));
+        assert_eq!(
+            parse_filter("type=nft"),
+            Ok(JournalFilter::MessageType(MessageType::Nft))
+        );
+        assert!(parse_filter("type=wrapped").is_err());
+        assert_eq!(parse_filter("nonce=4"), Ok(JournalFilter::Nonce(4)));
+        assert_eq!(
+            parse_filter("event=confirmed"),
+            Ok(JournalFilter::Event("confirmed"))
+        );
+        assert!(parse_filter("kind=token").is_err());
+        assert_eq!(
+            record.to_text(false),
+            "7 (slot 3) confirmed [token]: nonce 4 in sig"
+        );
+    }
+}
@@ -0,0 +1,72 @@
+//! Sanity guard for abnormally large nonce jumps.
+//! A misparsed nonce account can make the relayer try to "catch up" an absurd
+//! number of nonces; such jumps are held until an operator confirms them.
+
+use crate::{alerts, state::StateStore};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{future::Future, sync::Mutex};
+
+/// State key written by `confirm-jump`
+pub const JUMP_CONFIRMATION_KEY: &str = "jump_confirmation";
+
+/// Operator confirmation that relaying up to `confirmed_to` is intended
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JumpConfirmation {
+    pub confirmed_to: u64,
+}
+
+pub struct JumpGuard {
+    max_expected_jump: u64,
+    alerted_for: Mutex<Option<u64>>,
+}
+
+impl JumpGuard {
+    pub fn new(max_expected_jump: u64) -> Self {
+        Self {
+            max_expected_jump,
+            alerted_for: Mutex::new(None),
+        }
+    }
+
+    /// Returns whether relaying nonces `from..to` may proceed.
+    /// Jumps above the configured maximum are only allowed once an operator
+    /// has confirmed a target close enough to `to`. `raw_account` is only
+    /// awaited when a jump is reported.
+    pub async fn allows(
+        &self,
+        store: &StateStore,
+        from: u64,
+        to: u64,
+        raw_account: impl Future<Output = Result<Vec<u8>>>,
+    ) -> Result<bool> {
+        let confirmed_to = store
+            .get::<JumpConfirmation>(JUMP_CONFIRMATION_KEY)?
+            .map(|confirmation| confirmation.confirmed_to)
+            .unwrap_or(0);
+        let jump = to.saturating_sub(from.max(confirmed_to));
+        if jump <= self.max_expected_jump {
+            *self.alerted_for.lock().unwrap() = None;
+            return Ok(true);
+        }
+
+        if *self.alerted_for.lock().unwrap() != Some(to) {
+            let raw = raw_account
+                .await
+                .map(|data| to_hex(&data))
+                .unwrap_or_else(|e| format!("<unavailable: {}>", e));
+            tracing::warn!("Watched account raw data: {}", raw);
+            alerts::raise(&format!(
+                "Nonce jumped from {} to {} ({} nonces, max expected {}); relaying is held until confirmed with `confirm-jump --to {}`",
+                from, to, jump, self.max_expected_jump, to
+            ));
+            *self.alerted_for.lock().unwrap() = Some(to);
+        }
+
+        Ok(false)
+    }
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
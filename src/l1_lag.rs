@@ -0,0 +1,251 @@
+//! Staleness of the L1 endpoints.
+//! With `l1_lag_probe_interval_secs` set, the watched account is read every
+//! interval from `l1_url` and every `l1_verify_urls` provider, at both
+//! confirmed and finalized commitment, recording the slot and nonce of each
+//! read. An endpoint's lag is how far its confirmed slot trails the highest
+//! slot any endpoint reported; one lagging more than `l1_lag_quarantine_slots`
+//! is flagged for quarantine and alerted. Endpoints are named by their config
+//! field (`l1_url`, `l1_verify_urls[0]`, ...) so that URLs, which may carry
+//! API keys, stay out of metrics.
+//!
+//! The tree has no L1 failover layer yet: the flag is exported as
+//! `relayer_l1_endpoint_quarantined` and shown by `status` and `doctor`, but
+//! reads keep using `l1_url`.
+
+use crate::{alerts, Relayer};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::commitment_config::CommitmentConfig;
+use std::{collections::BTreeSet, fmt, sync::Mutex, time::Duration};
+
+/// State key holding the last probe, for `status`
+pub const L1_LAG_KEY: &str = "l1_lag";
+
+/// A read of the watched account at one commitment
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct EndpointRead {
+    pub slot: u64,
+    /// None when the account is missing or does not decode
+    pub nonce: Option<u64>,
+}
+
+/// Probe result of one L1 endpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EndpointLag {
+    /// Config field of the endpoint
+    pub endpoint: String,
+    pub confirmed: Option<EndpointRead>,
+    pub finalized: Option<EndpointRead>,
+    /// Slots the confirmed read trails the best-known slot
+    pub lag_slots: Option<u64>,
+    /// Nonces the confirmed read trails the highest nonce read
+    pub nonce_lag: Option<u64>,
+    pub quarantined: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl fmt::Display for EndpointLag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let describe = |read: &Option<EndpointRead>| match read {
+            Some(EndpointRead {
+                slot,
+                nonce: Some(nonce),
+            }) => format!("slot {} nonce {}", slot, nonce),
+            Some(EndpointRead { slot, nonce: None }) => format!("slot {} (no nonce)", slot),
+            None => "unread".to_string(),
+        };
+        write!(
+            f,
+            "{}: confirmed {}, finalized {}",
+            self.endpoint,
+            describe(&self.confirmed),
+            describe(&self.finalized)
+        )?;
+        if let Some(lag) = self.lag_slots {
+            write!(f, ", {} slot(s) behind", lag)?;
+        }
+        if let Some(lag) = self.nonce_lag.filter(|&lag| lag > 0) {
+            write!(f, ", {} nonce(s) behind", lag)?;
+        }
+        if self.quarantined {
+            write!(f, " (quarantined)")?;
+        }
+        if let Some(error) = &self.error {
+            write!(f, ": {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Last probe of every L1 endpoint
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct L1LagStatus {
+    pub endpoints: Vec<EndpointLag>,
+    pub checked_at: u64,
+}
+
+pub struct L1LagProbe {
+    interval: Duration,
+    threshold: u64,
+    /// Endpoints flagged by the last probes
+    quarantined: Mutex<BTreeSet<String>>,
+}
+
+impl L1LagProbe {
+    pub fn new(interval: Duration, threshold: u64) -> Self {
+        Self {
+            interval,
+            threshold,
+            quarantined: Mutex::new(BTreeSet::new()),
+        }
+    }
+}
+
+impl Relayer {
+    /// Every L1 endpoint with its config field
+    fn l1_endpoints(&self) -> Vec<(String, &RpcClient)> {
+        std::iter::once(("l1_url".to_string(), &self.l1_client))
+            .chain(
+                self.l1_verifiers
+                    .iter()
+                    .enumerate()
+                    .map(|(index, verifier)| {
+                        (format!("l1_verify_urls[{}]", index), &verifier.client)
+                    }),
+            )
+            .collect()
+    }
+
+    pub(crate) async fn read_watched_at(
+        &self,
+        client: &RpcClient,
+        commitment: CommitmentConfig,
+    ) -> Result<EndpointRead> {
+        let response = client
+            .get_account_with_commitment(self.watched_account.pubkey(), commitment)
+            .await?;
+        Ok(EndpointRead {
+            slot: response.context.slot,
+            nonce: response
+                .value
+                .and_then(|account| self.watched.decode(&account.data))
+                .map(|status| status.nonce),
+        })
+    }
+
+    /// Reads the watched account from every L1 endpoint at confirmed and
+    /// finalized commitment and computes each endpoint's lag. An endpoint
+    /// that fails to answer keeps the quarantine flag of the last probe.
+    pub(crate) async fn probe_l1_lag(&self) -> Vec<EndpointLag> {
+        let mut results = Vec::new();
+        for (endpoint, client) in self.l1_endpoints() {
+            let confirmed = self
+                .read_watched_at(client, CommitmentConfig::confirmed())
+                .await;
+            let finalized = self
+                .read_watched_at(client, CommitmentConfig::finalized())
+                .await;
+            let error = match (&confirmed, &finalized) {
+                (Err(e), _) => Some(format!("confirmed read failed: {}", e)),
+                (_, Err(e)) => Some(format!("finalized read failed: {}", e)),
+                _ => None,
+            };
+            results.push(EndpointLag {
+                endpoint,
+                confirmed: confirmed.ok(),
+                finalized: finalized.ok(),
+                lag_slots: None,
+                nonce_lag: None,
+                quarantined: false,
+                error,
+            });
+        }
+
+        let best_slot = results
+            .iter()
+            .filter_map(|result| result.confirmed.map(|read| read.slot))
+            .max();
+        let best_nonce = results
+            .iter()
+            .filter_map(|result| result.confirmed.and_then(|read| read.nonce))
+            .max();
+        let mut quarantined = self.l1_lag.quarantined.lock().unwrap();
+        for result in &mut results {
+            let Some(confirmed) = result.confirmed else {
+                result.quarantined = quarantined.contains(&result.endpoint);
+                continue;
+            };
+            let lag = best_slot.unwrap_or_default().saturating_sub(confirmed.slot);
+            result.lag_slots = Some(lag);
+            result.nonce_lag = best_nonce
+                .zip(confirmed.nonce)
+                .map(|(best, nonce)| best.saturating_sub(nonce));
+            result.quarantined = lag > self.l1_lag.threshold;
+            if result.quarantined {
+                quarantined.insert(result.endpoint.clone());
+            } else {
+                quarantined.remove(&result.endpoint);
+            }
+        }
+        results
+    }
+
+    /// Probes the L1 endpoints every `l1_lag_probe_interval_secs`, exporting
+    /// their lag and alerting when one is flagged for quarantine
+    pub(crate) async fn watch_l1_lag(&self) -> Result<()> {
+        if self.l1_lag.interval.is_zero() {
+            return futures::future::pending().await;
+        }
+        loop {
+            let flagged: BTreeSet<String> = self.l1_lag.quarantined.lock().unwrap().clone();
+            let endpoints = self.probe_l1_lag().await;
+            for lag in &endpoints {
+                let finality = lag
+                    .confirmed
+                    .zip(lag.finalized)
+                    .map(|(confirmed, finalized)| confirmed.slot.saturating_sub(finalized.slot));
+                if let Some(slots) = lag.lag_slots {
+                    self.metrics.l1_endpoint_lag_slots.set(&lag.endpoint, slots);
+                }
+                if let Some(slots) = finality {
+                    self.metrics
+                        .l1_endpoint_finality_lag_slots
+                        .set(&lag.endpoint, slots);
+                }
+                if let Some(nonces) = lag.nonce_lag {
+                    self.metrics
+                        .l1_endpoint_nonce_lag
+                        .set(&lag.endpoint, nonces);
+                }
+                self.metrics
+                    .l1_endpoint_quarantined
+                    .set(&lag.endpoint, lag.quarantined as u64);
+                match (flagged.contains(&lag.endpoint), lag.quarantined) {
+                    (false, true) => alerts::raise(&format!(
+                        "L1 endpoint {} flagged for quarantine: confirmed view {} slots behind the best-known slot (threshold {})",
+                        lag.endpoint,
+                        lag.lag_slots.unwrap_or_default(),
+                        self.l1_lag.threshold
+                    )),
+                    (true, false) => {
+                        tracing::info!("L1 endpoint {} caught up; quarantine flag cleared", lag.endpoint)
+                    }
+                    _ => {}
+                }
+                if let Some(error) = &lag.error {
+                    tracing::warn!("L1 lag probe of {}: {}", lag.endpoint, error);
+                }
+            }
+            let status = L1LagStatus {
+                endpoints,
+                checked_at: self.clock.unix_timestamp(),
+            };
+            if let Err(e) = self.state.put(L1_LAG_KEY, &status) {
+                tracing::warn!("failed to save the L1 lag probe: {}", e);
+            }
+            self.clock.sleep(self.l1_lag.interval).await;
+        }
+    }
+}
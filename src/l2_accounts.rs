@@ -0,0 +1,197 @@
+//! L2 account guard.
+//! Every relay transaction writes the source's L2 nonce account, and the
+//! pause and domain checks read the bridge-config account, so a typo in
+//! either address would otherwise fail every transaction. Both accounts are
+//! validated at startup, which fails on a mismatch, and again before a batch
+//! once the last successful check is older than
+//! `l2_account_check_interval_secs`. While a check fails, nothing is
+//! submitted and the accounts are checked again before every retry.
+//!
+//! Each account must exist, be owned by `l2_nonce_account_owner` or
+//! `l2_bridge_config_owner` (the L2 program by default) and hold at least
+//! `l2_nonce_account_min_size` or `l2_bridge_config_min_size` bytes. With
+//! `l2_bridge_config_pda = true` the bridge-config account is derived from
+//! the L2 program id (PDA `["config"]`); a differing
+//! `l2_bridge_config_account` only draws a warning.
+
+use crate::{alerts, config::RelayerConfig, domain, pubkeys::DestinationProgramId, Relayer};
+use anyhow::Result;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use std::{fmt, sync::Mutex, time::Duration};
+
+/// An L2 account the relayer depends on, and what it must look like
+#[derive(Debug, Clone)]
+pub struct ExpectedAccount {
+    /// `nonce account` or `bridge-config account`
+    pub role: &'static str,
+    pub address: Pubkey,
+    pub owner: Pubkey,
+    pub min_size: usize,
+}
+
+/// An L2 account is missing or not what the L2 program expects
+#[derive(Debug)]
+pub struct AccountCheckFailed {
+    pub role: &'static str,
+    pub address: Pubkey,
+    /// What was expected and what was found
+    pub expectation: String,
+}
+
+impl fmt::Display for AccountCheckFailed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "L2 {} {} {}", self.role, self.address, self.expectation)
+    }
+}
+
+impl std::error::Error for AccountCheckFailed {}
+
+/// Address of the bridge-config account, derived from `program_id` with
+/// `l2_bridge_config_pda`; warns when the configured address differs
+pub fn bridge_config_address(config: &RelayerConfig, program_id: &Pubkey) -> Option<Pubkey> {
+    let configured = config
+        .l2_bridge_config_account
+        .map(|address| *address.pubkey());
+    if !config.l2_bridge_config_pda {
+        return configured;
+    }
+    let derived = domain::config_address(program_id);
+    if let Some(configured) = configured.filter(|configured| *configured != derived) {
+        tracing::warn!(
+            "l2_bridge_config_account {} is not the config PDA {} of L2 program {}; using the PDA",
+            configured,
+            derived,
+            program_id
+        );
+    }
+    Some(derived)
+}
+
+/// Expected owner from `configured`, the L2 program when unset
+fn owner(configured: Option<DestinationProgramId>, program_id: &Pubkey) -> Pubkey {
+    configured.map_or(*program_id, |owner| *owner.pubkey())
+}
+
+pub struct L2AccountGuard {
+    accounts: Vec<ExpectedAccount>,
+    interval: Duration,
+    /// Time of the last check and why it failed, if it did
+    last: Mutex<Option<(u64, Option<String>)>>,
+}
+
+impl L2AccountGuard {
+    /// Guard of `nonce_account` and, when set, `bridge_config`, with the
+    /// expectations from `config`
+    pub fn from_config(
+        config: &RelayerConfig,
+        program_id: &Pubkey,
+        nonce_account: Pubkey,
+        bridge_config: Option<Pubkey>,
+    ) -> Self {
+        let mut accounts = vec![ExpectedAccount {
+            role: "nonce account",
+            address: nonce_account,
+            owner: owner(config.l2_nonce_account_owner, program_id),
+            min_size: config.l2_nonce_account_min_size,
+        }];
+        if let Some(address) = bridge_config {
+            // 默认大小覆盖暂停标志和桥接域
+            let needed = if config.bridge_domain.is_some() {
+                (config.l2_pause_flag_offset + 1).max(domain::DOMAIN_OFFSET + 8)
+            } else {
+                config.l2_pause_flag_offset + 1
+            };
+            accounts.push(ExpectedAccount {
+                role: "bridge-config account",
+                address,
+                owner: owner(config.l2_bridge_config_owner, program_id),
+                min_size: config.l2_bridge_config_min_size.unwrap_or(needed),
+            });
+        }
+        Self {
+            accounts,
+            interval: Duration::from_secs(config.l2_account_check_interval_secs),
+            last: Mutex::new(None),
+        }
+    }
+
+    /// Fails with `AccountCheckFailed` naming the first account that is
+    /// missing or does not meet its expectations
+    pub async fn validate(&self, client: &RpcClient) -> Result<()> {
+        for expected in &self.accounts {
+            let failed = |expectation: String| AccountCheckFailed {
+                role: expected.role,
+                address: expected.address,
+                expectation,
+            };
+            let account = client
+                .get_account_with_commitment(&expected.address, client.commitment())
+                .await?
+                .value
+                .ok_or_else(|| failed("does not exist".to_string()))?;
+            if account.owner != expected.owner {
+                return Err(failed(format!(
+                    "is owned by {}, expected {}",
+                    account.owner, expected.owner
+                ))
+                .into());
+            }
+            if account.data.len() < expected.min_size {
+                return Err(failed(format!(
+                    "holds {} bytes, expected at least {}",
+                    account.data.len(),
+                    expected.min_size
+                ))
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Validates the accounts at startup and records the check at `now`
+    pub async fn check_startup(&self, client: &RpcClient, now: u64) -> Result<()> {
+        self.validate(client).await?;
+        *self.last.lock().unwrap() = Some((now, None));
+        Ok(())
+    }
+}
+
+impl Relayer {
+    /// Wait before trying again while an L2 account check fails. Re-validates
+    /// the accounts once the last successful check is older than the
+    /// interval, and before every retry after a failed one.
+    pub(crate) async fn l2_account_hold(&self) -> Result<Option<Duration>> {
+        let guard = &self.l2_accounts;
+        let now = self.clock.unix_timestamp();
+        let last = guard.last.lock().unwrap().clone();
+        if let Some((checked_at, None)) = last {
+            if guard.interval.is_zero() || now.saturating_sub(checked_at) < guard.interval.as_secs()
+            {
+                return Ok(None);
+            }
+        }
+
+        let failure = match guard.validate(&self.l2_client).await {
+            Ok(()) => None,
+            Err(e) if e.is::<AccountCheckFailed>() => Some(e.to_string()),
+            Err(e) => return Err(e),
+        };
+        match (last.and_then(|(_, failure)| failure), &failure) {
+            (None, Some(failure)) => alerts::raise(&format!(
+                "{}; submissions are held until it is fixed",
+                failure
+            )),
+            (Some(_), None) => tracing::info!("L2 accounts valid again; submissions continue"),
+            _ => {}
+        }
+        *guard.last.lock().unwrap() = Some((now, failure.clone()));
+        self.metrics
+            .l2_account_check_failed
+            .set(failure.is_some() as u64);
+        Ok(failure.map(|failure| {
+            tracing::warn!("Holding submissions: {}", failure);
+            self.submitter.retry_delay
+        }))
+    }
+}
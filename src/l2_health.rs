@@ -0,0 +1,215 @@
+//! L2 cluster health guard.
+//! With `l2_health_max_lag_slots` set, every submission batch first probes the
+//! L2 node: `getHealth`, how far its processed slot trails the highest slot it
+//! has received shreds for (`getMaxShredInsertSlot`) and, with
+//! `l2_health_reference_url`, the slot of that endpoint, and whether its slot
+//! has stood still for `l2_health_stall_secs`. An unhealthy, lagging or
+//! stalled node holds submissions: the hold is alerted once and the probe is
+//! retried after `l2_health_backoff_secs`, doubling up to
+//! `l2_health_max_backoff_secs`, while L1 is still monitored and new nonces
+//! queue up. A hold is not a failed batch, so it never opens the circuit
+//! breaker; it is exported as `relayer_l2_health_held` and shown by `status`.
+
+use crate::{alerts, config::RelayerConfig, Relayer};
+use serde::{Deserialize, Serialize};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::commitment_config::CommitmentConfig;
+use std::{sync::Mutex, time::Duration};
+
+/// State key holding the last probe, for `status`
+pub const L2_HEALTH_KEY: &str = "l2_health";
+
+/// Last probe of the L2 cluster
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct L2HealthStatus {
+    /// Why submissions are held; none while the cluster is healthy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub held: Option<String>,
+    /// Unix time the current hold started
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub held_since: Option<u64>,
+    /// Processed slot of the L2 node
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slot: Option<u64>,
+    /// Slots the node trails its highest-known slot or the reference endpoint
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lag_slots: Option<u64>,
+    pub checked_at: u64,
+}
+
+#[derive(Debug, Default)]
+struct HealthState {
+    last: L2HealthStatus,
+    /// Wait before the next probe while held
+    backoff: Duration,
+    /// A slot of the node and the Unix time it was first seen
+    last_slot: Option<(u64, u64)>,
+}
+
+pub struct L2HealthGuard {
+    max_lag_slots: u64,
+    reference: Option<RpcClient>,
+    stall: Duration,
+    backoff: Duration,
+    max_backoff: Duration,
+    state: Mutex<HealthState>,
+}
+
+impl L2HealthGuard {
+    /// The guard described by `config`, none without `l2_health_max_lag_slots`
+    pub fn from_config(config: &RelayerConfig) -> Option<Self> {
+        let max_lag_slots = config.l2_health_max_lag_slots?;
+        Some(Self {
+            max_lag_slots,
+            reference: config.l2_health_reference_url.as_ref().map(|url| {
+                RpcClient::new_with_commitment(
+                    url.expose().to_string(),
+                    CommitmentConfig::processed(),
+                )
+            }),
+            stall: Duration::from_secs(config.l2_health_stall_secs),
+            backoff: Duration::from_secs(config.l2_health_backoff_secs.max(1)),
+            max_backoff: Duration::from_secs(
+                config
+                    .l2_health_max_backoff_secs
+                    .max(config.l2_health_backoff_secs.max(1)),
+            ),
+            state: Mutex::new(HealthState::default()),
+        })
+    }
+}
+
+impl Relayer {
+    /// Probes the L2 node; returns its slot, its lag and why it holds
+    /// submissions, if it does
+    async fn probe_l2_health(
+        &self,
+        guard: &L2HealthGuard,
+    ) -> (Option<u64>, Option<u64>, Option<String>) {
+        let now = self.clock.unix_timestamp();
+        if let Err(e) = self.l2_client.get_health().await {
+            return (None, None, Some(format!("node reports unhealthy: {}", e)));
+        }
+        let slot = match self
+            .l2_client
+            .get_slot_with_commitment(CommitmentConfig::processed())
+            .await
+        {
+            Ok(slot) => slot,
+            Err(e) => return (None, None, Some(format!("getSlot failed: {}", e))),
+        };
+
+        // 节点自身已知的最高 slot 读取失败时只和参考节点比较
+        let known = self.l2_client.get_max_shred_insert_slot().await.ok();
+        let reference = match &guard.reference {
+            Some(client) => match client.get_slot().await {
+                Ok(slot) => Some(slot),
+                Err(e) => {
+                    tracing::warn!("could not read the slot of l2_health_reference_url: {}", e);
+                    None
+                }
+            },
+            None => None,
+        };
+        let lag = known
+            .into_iter()
+            .chain(reference)
+            .max()
+            .map(|best| best.saturating_sub(slot));
+
+        let mut state = guard.state.lock().unwrap();
+        let since = match state.last_slot {
+            Some((last, since)) if last == slot => since,
+            _ => now,
+        };
+        state.last_slot = Some((slot, since));
+        drop(state);
+
+        let reason = if let Some(lag) = lag.filter(|&lag| lag > guard.max_lag_slots) {
+            Some(format!(
+                "node is {} slot(s) behind (threshold {})",
+                lag, guard.max_lag_slots
+            ))
+        } else if !guard.stall.is_zero() && now.saturating_sub(since) >= guard.stall.as_secs() {
+            Some(format!(
+                "slot {} has not advanced for {}s",
+                slot,
+                now - since
+            ))
+        } else {
+            None
+        };
+        (Some(slot), lag, reason)
+    }
+
+    /// Wait before trying again while the L2 cluster is unhealthy or behind;
+    /// always none when unconfigured
+    pub(crate) async fn l2_health_hold(&self) -> Option<Duration> {
+        let guard = self.l2_health.as_ref()?;
+        let now = self.clock.unix_timestamp();
+        {
+            let state = guard.state.lock().unwrap();
+            let retry_at = state.last.checked_at + state.backoff.as_secs();
+            if state.last.held.is_some() && now < retry_at {
+                return Some(Duration::from_secs(retry_at - now));
+            }
+        }
+
+        let (slot, lag, reason) = self.probe_l2_health(guard).await;
+        if let Some(lag) = lag {
+            self.metrics.l2_health_lag_slots.set(lag);
+        }
+        let mut state = guard.state.lock().unwrap();
+        let held_since = match (&state.last.held, &reason) {
+            (None, Some(reason)) => {
+                alerts::raise(&format!(
+                    "{}: L2 cluster unhealthy, holding submissions: {}",
+                    self.name(),
+                    reason
+                ));
+                self.metrics.l2_health_holds_total.inc();
+                state.backoff = guard.backoff;
+                Some(now)
+            }
+            (Some(_), Some(_)) => {
+                state.backoff = (state.backoff * 2).min(guard.max_backoff);
+                state.last.held_since
+            }
+            (Some(_), None) => {
+                tracing::info!(
+                    "L2 cluster healthy again after {}s; submissions resume",
+                    now.saturating_sub(state.last.held_since.unwrap_or(now))
+                );
+                None
+            }
+            (None, None) => None,
+        };
+        let changed = state.last.checked_at == 0 || state.last.held.is_some() != reason.is_some();
+        state.last = L2HealthStatus {
+            held: reason,
+            held_since,
+            slot,
+            lag_slots: lag,
+            checked_at: now,
+        };
+        let status = state.last.clone();
+        let wait = status.held.is_some().then_some(state.backoff);
+        drop(state);
+
+        self.metrics.l2_health_held.set(wait.is_some() as u64);
+        // 健康时每批都会探测，只在首次探测、状态变化或扣留期间保存
+        if changed || wait.is_some() {
+            if let Err(e) = self.state.put(L2_HEALTH_KEY, &status) {
+                tracing::warn!("failed to save the L2 health probe: {}", e);
+            }
+        }
+        if let (Some(wait), Some(reason)) = (wait, &status.held) {
+            tracing::warn!(
+                "L2 cluster unhealthy ({}), probing again in {:?}",
+                reason,
+                wait
+            );
+        }
+        wait
+    }
+}
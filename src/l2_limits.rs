@@ -0,0 +1,312 @@
+//! Limits enforced by the L2 program.
+//! The L2 program rejects, after charging the fee, a transfer above its
+//! per-transfer maximum or one that would take the amount minted in its
+//! current period past its mint cap. With `l2_limits_offset` set, the
+//! relayer reads these limits from the bridge-config account, at that offset:
+//!
+//! `version u8 | max_per_transfer u64 | period_cap u64 | period_minted u64 | period_ends_at i64`
+//!
+//! (little endian; a zero maximum or cap means none). Later versions may
+//! append fields, which are ignored; an account without the block, or with
+//! version 0, advertises no limits. A read is trusted for
+//! `l2_limits_ttl_secs`, counting the transfers passed since on top of the
+//! minted amount it reported.
+//!
+//! Every transfer is checked before its transaction is built. One above the
+//! maximum or the whole cap is dead-lettered; coalescing never merges past
+//! them. One that would exceed the cap is held, without counting as a failed
+//! attempt, until the period ends (`period_ends_at`) and the program resets
+//! the minted amount.
+//! The hold is saved for `status` and exported as `relayer_l2_cap_held`.
+
+use crate::{decode, pipeline::FetchedTransfer, Relayer};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+/// State key holding the transfer held for the L2 mint cap, for `status`
+pub const L2_CAP_HOLD_KEY: &str = "l2_cap_hold";
+
+/// Size of the version 1 limits block
+const LIMITS_V1_SIZE: usize = 33;
+
+/// Limits advertised by the bridge-config account
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct L2Limits {
+    pub max_per_transfer: Option<u64>,
+    pub period_cap: Option<u64>,
+    /// Amount minted in the current period
+    pub period_minted: u64,
+    /// Unix time at which the current period ends
+    pub period_ends_at: u64,
+}
+
+impl L2Limits {
+    /// Decodes the limits block at `offset` of the bridge-config account
+    /// `data`; none when the account advertises no limits
+    pub fn parse(data: &[u8], offset: usize) -> Result<Option<Self>> {
+        let Some(block) = data.get(offset..) else {
+            return Ok(None);
+        };
+        let version = block.first().copied().unwrap_or_default();
+        if version == 0 {
+            return Ok(None);
+        }
+        if block.len() < LIMITS_V1_SIZE {
+            return Err(anyhow::anyhow!(
+                "L2 limits block version {} at offset {} is {} bytes, expected at least {} ({})",
+                version,
+                offset,
+                block.len(),
+                LIMITS_V1_SIZE,
+                decode::hex_prefix(data)
+            ));
+        }
+        let field = |at: usize| u64::from_le_bytes(block[at..at + 8].try_into().unwrap());
+        let limit = |value: u64| (value > 0).then_some(value);
+        Ok(Some(Self {
+            max_per_transfer: limit(field(1)),
+            period_cap: limit(field(9)),
+            period_minted: field(17),
+            period_ends_at: (field(25) as i64).max(0) as u64,
+        }))
+    }
+}
+
+/// A transfer held because it would exceed the L2 mint cap
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CapHeld {
+    pub nonce: u64,
+    pub amount: u64,
+    /// Amount the cap still allowed in the period
+    pub remaining: u64,
+    pub period_ends_at: u64,
+    pub since: u64,
+}
+
+impl fmt::Display for CapHeld {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Nonce {} ({} lamports) held for the L2 mint cap: {} lamports left in the period ending at {}",
+            self.nonce, self.amount, self.remaining, self.period_ends_at
+        )
+    }
+}
+
+impl std::error::Error for CapHeld {}
+
+struct CachedLimits {
+    limits: Option<L2Limits>,
+    read_at: u64,
+    /// Lamports of the transfers passed since the read
+    passed: u64,
+}
+
+pub struct L2LimitWatch {
+    /// Bridge-config account holding the limits
+    address: Pubkey,
+    offset: usize,
+    ttl: Duration,
+    cache: Mutex<Option<CachedLimits>>,
+    /// Whether a hold may be saved, e.g. by the previous run
+    hold_saved: AtomicBool,
+}
+
+impl L2LimitWatch {
+    pub fn new(address: Pubkey, offset: usize, ttl: Duration) -> Self {
+        Self {
+            address,
+            offset,
+            ttl,
+            cache: Mutex::new(None),
+            hold_saved: AtomicBool::new(true),
+        }
+    }
+
+    /// Largest amount of one transfer allowed by the last read, if limited
+    pub fn max_per_transfer(&self) -> Option<u64> {
+        let limits = self.cache.lock().unwrap().as_ref()?.limits?;
+        [limits.max_per_transfer, limits.period_cap]
+            .into_iter()
+            .flatten()
+            .min()
+    }
+}
+
+impl Relayer {
+    /// Checks `fetched` against the limits of the L2 program. Dead-letters a
+    /// transfer above the per-transfer maximum; holds one the mint cap does
+    /// not allow until the period ends.
+    pub(crate) async fn check_l2_limits(&self, fetched: &FetchedTransfer) -> Result<()> {
+        let Some(watch) = &self.l2_limits else {
+            return Ok(());
+        };
+        let now = self.clock.unix_timestamp();
+        let fresh = watch
+            .cache
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|cached| now.saturating_sub(cached.read_at) < watch.ttl.as_secs());
+        if !fresh {
+            let data = self
+                .l2_client
+                .get_account_with_commitment(&watch.address, self.l2_client.commitment())
+                .await?
+                .value
+                .ok_or_else(|| {
+                    anyhow::anyhow!("L2 bridge-config account {} not found", watch.address)
+                })?
+                .data;
+            let limits = L2Limits::parse(&data, watch.offset)?;
+            *watch.cache.lock().unwrap() = Some(CachedLimits {
+                limits,
+                read_at: now,
+                passed: 0,
+            });
+        }
+
+        let mut cache = watch.cache.lock().unwrap();
+        let cached = cache.as_mut().expect("limits read above");
+        let Some(limits) = cached.limits else {
+            return Ok(());
+        };
+        // 超过单笔上限或整个周期额度的转账永远无法落地
+        let ceiling = [
+            limits
+                .max_per_transfer
+                .map(|max| (max, "per-transfer maximum")),
+            limits.period_cap.map(|cap| (cap, "mint cap")),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        if let Some((max, limit)) = ceiling {
+            if fetched.amount > max {
+                drop(cache);
+                let reason = format!("amount {} exceeds the L2 {} {}", fetched.amount, limit, max);
+                // 合并后的金额超限不是单个 nonce 的问题，下次不再合并
+                if fetched.coalesced.is_empty() {
+                    self.dead_letter_transfer(fetched, &reason)?;
+                }
+                return Err(anyhow::anyhow!(
+                    "Nonce {} rejected before submission: {}",
+                    fetched.nonce,
+                    reason
+                ));
+            }
+        }
+        if let Some(cap) = limits.period_cap {
+            // 周期结束后程序在下一次铸造时清零
+            let minted = if now >= limits.period_ends_at {
+                0
+            } else {
+                limits.period_minted
+            };
+            let remaining = cap.saturating_sub(minted.saturating_add(cached.passed));
+            if fetched.amount > remaining {
+                let held = CapHeld {
+                    nonce: fetched.nonce,
+                    amount: fetched.amount,
+                    remaining,
+                    period_ends_at: limits.period_ends_at,
+                    since: now,
+                };
+                drop(cache);
+                let since = self
+                    .state
+                    .get::<CapHeld>(L2_CAP_HOLD_KEY)?
+                    .filter(|earlier| earlier.nonce == held.nonce)
+                    .map_or(now, |earlier| earlier.since);
+                let held = CapHeld { since, ..held };
+                self.state.put(L2_CAP_HOLD_KEY, &held)?;
+                watch.hold_saved.store(true, Ordering::Relaxed);
+                self.metrics.l2_cap_held.set(1);
+                return Err(held.into());
+            }
+        }
+        cached.passed = cached.passed.saturating_add(fetched.amount);
+        drop(cache);
+        if watch.hold_saved.swap(false, Ordering::Relaxed) {
+            self.state.remove(L2_CAP_HOLD_KEY)?;
+            self.metrics.l2_cap_held.set(0);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A version `version` limits block of the given fields
+    fn block(version: u8, max: u64, cap: u64, minted: u64, ends_at: i64) -> Vec<u8> {
+        let mut block = vec![version];
+        block.extend_from_slice(&max.to_le_bytes());
+        block.extend_from_slice(&cap.to_le_bytes());
+        block.extend_from_slice(&minted.to_le_bytes());
+        block.extend_from_slice(&ends_at.to_le_bytes());
+        block
+    }
+
+    /// `block` at offset 16 of a bridge-config account
+    fn account(block: &[u8]) -> Vec<u8> {
+        [&[0xaa; 16][..], block].concat()
+    }
+
+    #[test]
+    fn version_one_blocks_decode_with_zero_limits_as_none() {
+        let data = account(&block(1, 5_000, 0, 1_200, 1_700_000_000));
+        assert_eq!(
+            L2Limits::parse(&data, 16).unwrap(),
+            Some(L2Limits {
+                max_per_transfer: Some(5_000),
+                period_cap: None,
+                period_minted: 1_200,
+                period_ends_at: 1_700_000_000,
+            })
+        );
+        let data = account(&block(1, 0, 9_000, 0, -5));
+        let limits = L2Limits::parse(&data, 16).unwrap().unwrap();
+        assert_eq!(limits.max_per_transfer, None);
+        assert_eq!(limits.period_cap, Some(9_000));
+        assert_eq!(limits.period_ends_at, 0);
+    }
+
+    #[test]
+    fn accounts_without_a_block_advertise_no_limits() {
+        let data = account(&block(0, 5_000, 9_000, 0, 0));
+        assert_eq!(L2Limits::parse(&data, 16).unwrap(), None);
+        assert_eq!(L2Limits::parse(&data, data.len()).unwrap(), None);
+        assert_eq!(L2Limits::parse(&data, data.len() + 8).unwrap(), None);
+    }
+
+    #[test]
+    fn short_blocks_fail_and_longer_versions_are_read_as_version_one() {
+        let mut short = account(&block(1, 5_000, 9_000, 0, 0));
+        short.pop();
+        assert!(L2Limits::parse(&short, 16).is_err());
+
+        // 版本 2 在末尾追加字段，只读取版本 1 的部分
+        let mut v2 = account(&block(2, 5_000, 9_000, 300, 1_700_000_000));
+        v2.extend_from_slice(&[0xff; 24]);
+        assert_eq!(
+            L2Limits::parse(&v2, 16).unwrap(),
+            Some(L2Limits {
+                max_per_transfer: Some(5_000),
+                period_cap: Some(9_000),
+                period_minted: 300,
+                period_ends_at: 1_700_000_000,
+            })
+        );
+    }
+}
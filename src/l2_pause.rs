@@ -0,0 +1,130 @@
+//! On-chain pause of the L2 program.
+//! Ops can pause the L2 bridge, after which every relay transaction fails with
+//! `BridgePaused`. With `l2_bridge_config_account` set, the relayer reads the
+//! paused flag of that account (a `u8` at `l2_pause_flag_offset`) at most
+//! every `l2_pause_check_interval_secs`, and again before batches of
+//! `l2_pause_recheck_batch_size` or more nonces and after a failed batch.
+//! While it is set nothing is submitted; submissions resume on their own once
+//! it clears.
+
+use crate::{alerts, decode, Relayer};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use std::{sync::Mutex, time::Duration};
+
+/// State key holding the last read of the paused flag, for `status`
+pub const L2_PAUSE_KEY: &str = "l2_pause";
+
+/// Last read of the L2 paused flag
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct L2PauseStatus {
+    pub paused: bool,
+    pub checked_at: u64,
+}
+
+pub struct L2PauseWatch {
+    /// Bridge-config account holding the flag
+    pub address: Pubkey,
+    flag_offset: usize,
+    interval: Duration,
+    /// Batches of at least this many nonces re-read the flag first
+    recheck_batch_size: u64,
+    last: Mutex<Option<L2PauseStatus>>,
+}
+
+impl L2PauseWatch {
+    pub fn new(
+        address: Pubkey,
+        flag_offset: usize,
+        interval: Duration,
+        recheck_batch_size: u64,
+    ) -> Self {
+        Self {
+            address,
+            flag_offset,
+            interval,
+            recheck_batch_size,
+            last: Mutex::new(None),
+        }
+    }
+
+    /// Pause before a held submission checks the flag again
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Makes the next check read the account, e.g. after a failed batch
+    pub fn expire(&self) {
+        if let Some(last) = self.last.lock().unwrap().as_mut() {
+            last.checked_at = 0;
+        }
+    }
+
+    fn parse(&self, data: &[u8]) -> Result<bool> {
+        match data.get(self.flag_offset) {
+            Some(0) => Ok(false),
+            Some(1) => Ok(true),
+            Some(flag) => Err(anyhow::anyhow!(
+                "Invalid paused flag {} at offset {} of L2 bridge-config account {} ({})",
+                flag,
+                self.flag_offset,
+                self.address,
+                decode::hex_prefix(data)
+            )),
+            None => Err(anyhow::anyhow!(
+                "L2 bridge-config account {} is {} bytes, too short for the paused flag at offset {}",
+                self.address,
+                data.len(),
+                self.flag_offset
+            )),
+        }
+    }
+}
+
+impl Relayer {
+    /// Whether the L2 program is paused, for a batch of `batch_len` nonces.
+    /// Reads the bridge-config account when the last read is older than the
+    /// check interval or the batch is large; always false when unconfigured.
+    pub(crate) async fn l2_paused(&self, batch_len: u64) -> Result<bool> {
+        let Some(watch) = &self.l2_pause else {
+            return Ok(false);
+        };
+        let now = self.clock.unix_timestamp();
+        let last = *watch.last.lock().unwrap();
+        if let Some(last) = last {
+            let fresh = now.saturating_sub(last.checked_at) < watch.interval.as_secs();
+            if fresh && batch_len < watch.recheck_batch_size {
+                return Ok(last.paused);
+            }
+        }
+
+        let data = self
+            .l2_client
+            .get_account_with_commitment(&watch.address, self.l2_client.commitment())
+            .await?
+            .value
+            .ok_or_else(|| anyhow::anyhow!("L2 bridge-config account {} not found", watch.address))?
+            .data;
+        let paused = watch.parse(&data)?;
+        match last {
+            Some(last) if last.paused == paused => {}
+            _ if paused => alerts::raise(&format!(
+                "L2 program reports bridging paused (bridge-config account {}); submissions are held until it resumes",
+                watch.address
+            )),
+            // 首次读取且未暂停时无需提示
+            Some(_) => tracing::info!("L2 program resumed bridging; submissions continue"),
+            None => {}
+        }
+
+        let status = L2PauseStatus {
+            paused,
+            checked_at: now,
+        };
+        *watch.last.lock().unwrap() = Some(status);
+        self.state.put(L2_PAUSE_KEY, &status)?;
+        self.metrics.l2_paused.set(paused as u64);
+        Ok(paused)
+    }
+}
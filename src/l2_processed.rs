@@ -0,0 +1,180 @@
+//! Processed nonces recorded by the L2 program.
+//! The L2 nonce account only says how far the nonces were relayed in order,
+//! so an L2 program that accepts nonces out of order or with gaps may record
+//! each processed nonce itself. With `[l2_processed_nonces]` set, that
+//! record is read right before each transaction is sent, after the in-flight
+//! check: a nonce already marked is not sent again and gets a receipt
+//! without a transaction (`processed_on_l2`), so a relayer restarted with
+//! stale state never pays for a relay the program would reject. A coalesced
+//! transfer with marked nonces is alerted and fails the attempt instead, for
+//! an operator to reconcile. Marker PDAs are read at the L2 client's
+//! commitment in one `getMultipleAccounts` call per transfer.
+
+use crate::{
+    alerts,
+    config::ProcessedNoncesConfig,
+    journal::JournalEvent,
+    limits,
+    models::processed::{ProcessedBitmap, ProcessedLayout},
+    pubkeys::DestinationAccount,
+    receipts::Receipt,
+    PreparedTransfer, Relayer,
+};
+use anyhow::Result;
+use solana_sdk::pubkey::Pubkey;
+
+/// Where the processed nonces of one source are read from
+pub struct ProcessedNonces {
+    layout: ProcessedLayout,
+    seed_prefix: Vec<u8>,
+    program_id: Pubkey,
+    nonce_account: Pubkey,
+    /// The bitmap account, for the `bitmap` layout
+    account: Option<DestinationAccount>,
+    offset: usize,
+}
+
+impl ProcessedNonces {
+    pub fn from_config(
+        config: &ProcessedNoncesConfig,
+        program_id: &Pubkey,
+        nonce_account: &Pubkey,
+    ) -> Result<Self> {
+        let account = config.account;
+        if config.layout == ProcessedLayout::Bitmap && account.is_none() {
+            return Err(anyhow::anyhow!(
+                "l2_processed_nonces with the bitmap layout requires an account"
+            ));
+        }
+        Ok(Self {
+            layout: config.layout,
+            seed_prefix: config.seed_prefix.as_bytes().to_vec(),
+            program_id: *program_id,
+            nonce_account: *nonce_account,
+            account,
+            offset: config.offset,
+        })
+    }
+
+    /// Marker PDA of `nonce` in the `pda` layout
+    pub fn marker(&self, nonce: u64) -> Pubkey {
+        Pubkey::find_program_address(
+            &[
+                &self.seed_prefix,
+                self.nonce_account.as_ref(),
+                &nonce.to_le_bytes(),
+            ],
+            &self.program_id,
+        )
+        .0
+    }
+}
+
+impl Relayer {
+    /// Which of `nonces` the L2 program marks processed, with the slot read at
+    async fn read_processed(
+        &self,
+        processed: &ProcessedNonces,
+        nonces: &[u64],
+    ) -> Result<(u64, Vec<u64>)> {
+        match (&processed.layout, &processed.account) {
+            (ProcessedLayout::Bitmap, Some(address)) => {
+                let response = self
+                    .l2_client
+                    .get_account_with_commitment(address.pubkey(), self.l2_client.commitment())
+                    .await?;
+                // 位图账户还不存在时没有已处理的 nonce
+                let bitmap = match &response.value {
+                    Some(account) => ProcessedBitmap::parse(&account.data, processed.offset)
+                        .map_err(|e| anyhow::anyhow!("{}: {}", address, e))?,
+                    None => ProcessedBitmap::default(),
+                };
+                Ok((
+                    response.context.slot,
+                    nonces
+                        .iter()
+                        .copied()
+                        .filter(|nonce| bitmap.contains(*nonce))
+                        .collect(),
+                ))
+            }
+            _ => {
+                let markers: Vec<Pubkey> = nonces
+                    .iter()
+                    .map(|nonce| processed.marker(*nonce))
+                    .collect();
+                let response = self
+                    .l2_client
+                    .get_multiple_accounts_with_commitment(&markers, self.l2_client.commitment())
+                    .await?;
+                Ok((
+                    response.context.slot,
+                    nonces
+                        .iter()
+                        .zip(&response.value)
+                        .filter(|(_, account)| account.is_some())
+                        .map(|(nonce, _)| *nonce)
+                        .collect(),
+                ))
+            }
+        }
+    }
+
+    /// Slot at which the L2 program was read marking `nonce` processed, none
+    /// when it is not marked or `[l2_processed_nonces]` is unset
+    pub(crate) async fn processed_on_l2(&self, nonce: u64) -> Result<Option<u64>> {
+        let Some(processed) = &self.processed_nonces else {
+            return Ok(None);
+        };
+        let (slot, marked) = self.read_processed(processed, &[nonce]).await?;
+        Ok((!marked.is_empty()).then_some(slot))
+    }
+
+    /// Reads the processed nonces right before `prepared` is sent; returns
+    /// whether the transfer was skipped as already processed
+    pub(crate) async fn check_l2_processed(&self, prepared: &PreparedTransfer) -> Result<bool> {
+        let Some(processed) = &self.processed_nonces else {
+            return Ok(false);
+        };
+        let nonces: Vec<u64> = std::iter::once(prepared.nonce)
+            .chain(prepared.coalesced.iter().copied())
+            .collect();
+        let (slot, marked) = self.read_processed(processed, &nonces).await?;
+        if marked.is_empty() {
+            return Ok(false);
+        }
+        if !prepared.coalesced.is_empty() {
+            // 合并的转账无法只发送未处理的部分，交给运维核对
+            alerts::raise(&format!(
+                "{}: nonces {:?} of the transfer coalesced into nonce {} are already processed on L2 at slot {}; reconcile the queue",
+                self.name(),
+                marked,
+                prepared.nonce,
+                slot
+            ));
+            return Err(anyhow::anyhow!(
+                "Coalesced nonces {:?} are already processed on L2 at slot {}",
+                marked,
+                slot
+            ));
+        }
+        tracing::info!(
+            "Skipping nonce {}: the L2 program marked it processed at slot {}",
+            prepared.nonce,
+            slot
+        );
+        Receipt::processed_on_l2(prepared, slot, self.clock.unix_timestamp()).save(&self.state)?;
+        self.schedule_shipping(prepared.nonce);
+        self.attempts.finish(prepared.nonce);
+        self.journal_message(
+            Some(prepared.l1_slot),
+            Some(limits::message_type_of(prepared.token)),
+            JournalEvent::Skipped {
+                nonce: prepared.nonce,
+                reason: format!("already processed on L2 at slot {}", slot),
+            },
+        );
+        self.metrics.already_processed_total.inc();
+        Ok(true)
+    }
+}
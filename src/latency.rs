@@ -0,0 +1,58 @@
+//! Relay latency.
+//! The monitor records when it first enqueues each range of nonces, and the
+//! confirmation of a nonce observes the seconds since then in
+//! `relayer_relay_latency_seconds`. The times are kept in memory only, so
+//! nonces restored from the persisted queue after a restart are not
+//! measured.
+
+use crate::Relayer;
+use std::{collections::VecDeque, ops::Range, sync::Mutex};
+
+/// Unix times at which ranges of nonces were enqueued, oldest first
+#[derive(Default)]
+pub struct ObservedNonces {
+    ranges: Mutex<VecDeque<(Range<u64>, u64)>>,
+}
+
+impl ObservedNonces {
+    /// Records that `nonces` were enqueued at `at`
+    pub fn observe(&self, nonces: Range<u64>, at: u64) {
+        if !nonces.is_empty() {
+            self.ranges.lock().unwrap().push_back((nonces, at));
+        }
+    }
+
+    /// When `nonce` was enqueued, if this process saw it
+    pub fn observed_at(&self, nonce: u64) -> Option<u64> {
+        self.ranges
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(range, _)| range.contains(&nonce))
+            .map(|(_, at)| *at)
+    }
+
+    /// Forgets the ranges entirely below `nonce`, which L2 has relayed
+    pub fn prune_below(&self, nonce: u64) {
+        self.ranges
+            .lock()
+            .unwrap()
+            .retain(|(range, _)| range.end > nonce);
+    }
+}
+
+impl Relayer {
+    /// Seconds since `nonce` was enqueued, if this process saw it
+    pub(crate) fn relay_latency(&self, nonce: u64) -> Option<u64> {
+        self.observed
+            .observed_at(nonce)
+            .map(|at| self.clock.unix_timestamp().saturating_sub(at))
+    }
+
+    /// Observes the latency of `nonce`, confirmed now
+    pub(crate) fn observe_relay_latency(&self, nonce: u64) {
+        if let Some(latency) = self.relay_latency(nonce) {
+            self.metrics.relay_latency_seconds.observe(latency);
+        }
+    }
+}
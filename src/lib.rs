@@ -0,0 +1,3855 @@
+//! Solana L1 to L2 bridge relayer implementation.
+//! This module provides functionality to monitor L1 accounts and relay messages to L2.
+//! The `sol-bridge-relayer` binary runs `run_cli`; services embedding the
+//! relayer drive it through `RelayerEngine` instead.
+
+/// Prints a line of command output to stdout; unlike `println!` it does not
+/// panic once stdout is closed, e.g. piped into `head`
+macro_rules! output {
+    ($($arg:tt)*) => {{
+        use std::io::Write as _;
+        let _ = writeln!(std::io::stdout(), $($arg)*);
+    }};
+}
+
+/// `output!` without the added newline, for text that ends in its own
+macro_rules! output_text {
+    ($($arg:tt)*) => {{
+        use std::io::Write as _;
+        let _ = write!(std::io::stdout(), $($arg)*);
+    }};
+}
+
+mod account_cache;
+mod admin;
+#[cfg(feature = "admin")]
+mod admin_api;
+mod alerts;
+mod anomaly;
+mod approval;
+mod archive;
+mod attempts;
+mod attestation;
+mod audit;
+mod batching;
+mod bench;
+mod build_info;
+mod cancellation;
+mod chaos;
+mod checkpoint;
+mod cli;
+mod clock;
+mod coalesce;
+mod commitment;
+mod config;
+mod confirmation;
+mod confirmation_strategy;
+mod context_slot;
+mod crash;
+mod decode;
+mod devnet;
+mod direction;
+mod dlq;
+mod doctor;
+mod domain;
+mod dry_run;
+mod dual_read;
+mod durable_nonce;
+mod dust;
+mod earlier_submission;
+mod engine;
+mod enrich;
+mod entries;
+mod event_stream;
+mod expiry;
+mod fees;
+mod history;
+mod inflight;
+mod instruction_accounts;
+mod intents;
+#[cfg(feature = "testing")]
+mod invariants;
+mod journal;
+mod jump_guard;
+mod l1_lag;
+mod l2_accounts;
+mod l2_health;
+mod l2_limits;
+mod l2_pause;
+mod l2_processed;
+mod latency;
+mod limits;
+mod lookup_tables;
+mod merkle;
+mod message_decoder;
+mod message_ttl;
+mod metrics;
+mod models;
+mod observer;
+mod pda;
+mod pda_discovery;
+mod pda_gaps;
+mod pda_recipient;
+mod pipeline;
+mod policy;
+mod poll;
+mod priority;
+mod priority_fee;
+mod program_accounts;
+mod proof;
+mod pubkeys;
+mod queue;
+mod quiet_period;
+mod receipt_pdas;
+mod receipt_sink;
+mod receipts;
+mod reconcile;
+mod reload;
+mod rent;
+mod replay;
+mod replay_batch;
+mod report;
+mod rpc_pool;
+mod scaling;
+mod secrets;
+mod send_rate;
+mod shadow;
+mod shutdown;
+mod signer;
+mod signing;
+#[cfg(feature = "testing")]
+mod simulate;
+mod snapshots;
+mod standby;
+mod state;
+mod state_bundle;
+mod step;
+mod storage_buffer;
+mod submitter;
+mod subscription;
+mod telemetry;
+#[cfg(feature = "testing")]
+mod test_validator;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod throughput;
+mod token;
+mod transaction;
+mod wallet;
+mod warmup;
+mod watched;
+mod webhooks;
+
+use crate::{
+    account_cache::AccountCacheReader,
+    admin::{replay_nonce, AdminPause, ManualPause, ADMIN_PAUSE_KEY, DEAD_LETTER_RESOLVED},
+    alerts::StallAlerts,
+    anomaly::{AnomalyBreaker, BreakerTrip, ANOMALY_BREAKER_KEY},
+    approval::{ApprovalPolicy, ApprovalScope},
+    archive::Archive,
+    attempts::{AttemptTracker, FailureClass},
+    attestation::AttestationVerifier,
+    audit::{audit_transfer, AuditEntry, AuditReport},
+    batching::{BatchStrategy, FeeAwareBatching},
+    build_info::BuildInfo,
+    cancellation::CancellationRegistry,
+    checkpoint::{Checkpoint, NonceCheckpoint, NONCE_CHECKPOINT_KEY},
+    cli::{
+        Cli, Command, ConfigCommand, DlqCommand, JournalCommand, ReconcileArgs, ReplayBatchArgs,
+        ReportArgs, SinkCommand, StateCommand,
+    },
+    clock::{Clock, SystemClock},
+    coalesce::CoalesceSettings,
+    config::ProcessRole,
+    confirmation::ConfirmationHold,
+    confirmation_strategy::{ConfirmationRules, ConfirmationStrategy},
+    context_slot::{ContextSlots, NodeBehind},
+    crash::ExitCategory,
+    decode::Cluster,
+    devnet::AirdropFunder,
+    direction::Direction,
+    dlq::{DeadLetterQueue, ListedDeadLetter, OperatorNote, Resolution, ResolutionKind},
+    dry_run::DryRunStorage,
+    dual_read::L1Verifier,
+    durable_nonce::DurableNonce,
+    dust::DustSettings,
+    enrich::EnrichmentQueue,
+    entries::EntryReceipt,
+    event_stream::EventStream,
+    expiry::ExpirySettings,
+    fees::FeeReport,
+    history::{ImportOptions, ImportProgress, HISTORY_IMPORT_PROGRESS_KEY},
+    inflight::{InFlightTracker, InFlightTransfer, RebroadcastSettings, TransactionOutcome},
+    instruction_accounts::InstructionAccounts,
+    journal::{
+        Journal, JournalEvent, JournalFollower, JournalRecord, ReplayedState, FOLLOW_INTERVAL,
+    },
+    jump_guard::{JumpConfirmation, JumpGuard, JUMP_CONFIRMATION_KEY},
+    l1_lag::{L1LagProbe, L1LagStatus, L1_LAG_KEY},
+    l2_accounts::L2AccountGuard,
+    l2_health::{L2HealthGuard, L2HealthStatus, L2_HEALTH_KEY},
+    l2_limits::{CapHeld, L2LimitWatch, L2_CAP_HOLD_KEY},
+    l2_pause::{L2PauseStatus, L2PauseWatch, L2_PAUSE_KEY},
+    l2_processed::ProcessedNonces,
+    latency::ObservedNonces,
+    limits::LimitsConfig,
+    lookup_tables::LookupTables,
+    merkle::{IndexerClient, MessageSource},
+    metrics::{Metrics, MetricsRegistry},
+    models::{
+        account::check_discriminator,
+        message::{MessageType, UnknownTypePolicy},
+    },
+    observer::Observer,
+    pda::PdaManager,
+    pda_discovery::{DiscoveryScan, PdaDiscovery, PDA_DISCOVERY_KEY},
+    pda_gaps::PdaGaps,
+    pda_recipient::DepositRoutes,
+    pipeline::{FetchedTransfer, PipelineSettings},
+    policy::TransferPolicy,
+    poll::AdaptivePoll,
+    priority::{PrioritySelector, QueuePriority},
+    priority_fee::PriorityFees,
+    program_accounts::MessageAccounts,
+    proof::RelayProof,
+    pubkeys::SourceAccount,
+    queue::{PendingQueue, RetryBackoff},
+    receipt_pdas::ReceiptPdas,
+    receipt_sink::{ReceiptSink, SinkWatermark, RECEIPT_SINK_KEY},
+    reconcile::{Reconciler, ScanOptions, ScanProgress, FULL_SCAN_PROGRESS_KEY},
+    reload::ConfigFile,
+    rent::{RentCache, TransferCost},
+    replay::ReplayWindow,
+    replay_batch::{ReplayInProgress, ReplayOptions, ReplaySelection, REPLAY_BATCH_PROGRESS_KEY},
+    report::Report,
+    rpc_pool::{destination_client, source_client, SharedPools},
+    scaling::Scaler,
+    send_rate::SendRateLimit,
+    shutdown::Shutdown,
+    signer::Signers,
+    signing::{read_keypair_path, read_signers},
+    snapshots::{MetricsSnapshot, Snapshotter},
+    standby::{Role, StandbySettings, StandbyStatus, ROLE_KEY, STANDBY_STATUS_KEY},
+    storage_buffer::{BufferedStorage, StorageHealth, StorageUnavailable},
+    submitter::SubmitterSettings,
+    subscription::{AccountSubscription, WatchMode},
+    telemetry::{TelemetryGuard, LOG_LEVEL_KEY},
+    throughput::{LeakyBucket, LimitOverride, Throughput, GLOBAL_LIMIT_KEY, SOURCE_LIMIT_KEY},
+    token::{
+        MintMap, TokenAccountMismatch, TokenAccountRentPayer, TokenTransfer, UnmappedMintPolicy,
+    },
+    transaction::{InstructionVersion, TransactionBuilder},
+    wallet::BalanceGuard,
+    watched::{WatchedAccount, WatchedAccountMissing, WatchedOwnerChanged, WATCHED_LAYOUT_KEY},
+};
+
+pub use crate::{
+    config::{ConfigFormat, RelayerConfig, SourceConfig},
+    engine::{RelayerEngine, RelayerEvents},
+    receipts::Receipt,
+    secrets::SecretUrl,
+    state::{CorruptState, StateStore},
+    step::RelayOutcome,
+};
+
+#[cfg(feature = "testing")]
+use crate::cli::{E2eArgs, SimulateLoadArgs};
+#[cfg(feature = "testing")]
+pub use crate::test_validator::{
+    run as run_e2e, E2eOptions, E2eReport, Fixture, TestValidator, ValidatorOptions,
+};
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{
+    commitment_config::CommitmentConfig, hash::Hash, native_token::lamports_to_sol, pubkey::Pubkey,
+    signature::Signer, transaction::VersionedTransaction,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    io::IsTerminal,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::sync::Semaphore;
+use tracing::{field, info_span, Instrument, Span};
+
+/// A signed L2 transaction together with the L1 transfer it relays
+struct PreparedTransfer {
+    nonce: u64,
+    amount: u64,
+    to_address: Pubkey,
+    /// L1 slot at which the message was read
+    l1_slot: u64,
+    /// Nonces merged into this transfer, empty unless coalesced
+    coalesced: Vec<u64>,
+    /// Nonces whose transfers share `transaction`, this one included;
+    /// empty unless packed
+    packed: Vec<u64>,
+    /// Unix time after which the transfer must not be executed
+    expires_at: Option<u64>,
+    /// Digest of the message read from L1, none once coalesced
+    digest: Option<Hash>,
+    /// L1 address that sent the transfer, when passed to L2
+    sender: Option<Pubkey>,
+    /// L2 mint paid out by a token or NFT transfer
+    token: Option<TokenTransfer>,
+    /// Nonces of accumulated dust paid out with the transfer
+    dust: Vec<u64>,
+    /// Confirmation strategy, none without `[[confirmation_rules]]`
+    strategy: Option<ConfirmationStrategy>,
+    /// Message type whose `[limits]` override applied, if any
+    limits: Option<MessageType>,
+    transaction: VersionedTransaction,
+    /// Estimated cost of `transaction` to the relayer wallet
+    cost: TransferCost,
+    last_valid_block_height: u64,
+    /// Entries of a multi-entry PDA relayed by `transaction`, empty for a
+    /// single transfer
+    entries: Vec<EntryReceipt>,
+    /// Root span of this nonce's trace
+    span: Span,
+}
+
+struct Relayer {
+    /// Id of the L1 source this relayer serves
+    source_id: String,
+    /// `[[bridges]]` entry the source belongs to, if any
+    bridge: Option<String>,
+    l1_client: RpcClient,
+    l2_client: RpcClient,
+    /// RPC endpoints with secrets redacted, for logs and spans
+    l1_rpc_url: String,
+    l2_rpc_url: String,
+    watched_account: SourceAccount,
+    /// Providers confirming high-value L1 reads (`l1_verify_urls`)
+    l1_verifiers: Vec<L1Verifier>,
+    /// Amount above which L1 reads are confirmed by `l1_verifiers`
+    dual_read_threshold: u64,
+    /// Staleness probe of `l1_url` and `l1_verifiers`
+    l1_lag: L1LagProbe,
+    /// Account cache read before the L1 RPC, set when `[l1_account_cache]` is
+    account_cache: Option<AccountCacheReader>,
+    /// Manual approval of large transfers, set when `manual_approval_threshold` is
+    approval: Option<ApprovalPolicy>,
+    /// Hold of transfers to new recipients, set when
+    /// `new_recipient_quiet_period_secs` is
+    quiet_period: Option<Duration>,
+    /// Availability and layout of the watched account across reads
+    watched: WatchedAccount,
+    poll: AdaptivePoll,
+    /// WebSocket subscription waking the monitor (`l1_watch_mode`)
+    subscription: Option<AccountSubscription>,
+    /// Active and standby signer keys
+    signers: Signers,
+    last_nonce: Mutex<Option<u64>>,
+    /// Nonce of the L1 watched account at the last poll
+    last_l1_nonce: Mutex<Option<u64>>,
+    /// Next nonce after the highest confirmed relay, saved in `state_dir`
+    checkpoint: Checkpoint,
+    pda_manager: PdaManager,
+    /// Deposit instructions of the programs owning off-curve recipients
+    deposit_routes: DepositRoutes,
+    /// Discriminator checked before a transfer-info PDA is decoded
+    transfer_info_discriminator: Option<[u8; 8]>,
+    /// Discriminator of multi-entry transfer-info PDAs, none if not read
+    entries_discriminator: Option<[u8; 8]>,
+    /// Raw message-type ids of the source; empty accepts every message
+    message_types: HashMap<u8, MessageType>,
+    /// L2 mints of the L1 mints of token and NFT messages, replaced by
+    /// config reloads
+    mint_map: Mutex<Arc<MintMap>>,
+    /// Handling of token and NFT messages of unmapped mints
+    unmapped_mint: UnmappedMintPolicy,
+    /// Unmapped L1 mints held with an alert, resolved once mapped
+    held_mints: Mutex<HashSet<Pubkey>>,
+    /// Who pays for a recipient's missing token account
+    token_account_rent_payer: TokenAccountRentPayer,
+    /// Handling of a recipient token account that cannot be paid into
+    token_account_mismatch: TokenAccountMismatch,
+    /// Whether L1 senders are passed to L2 (`relay_sender`)
+    relay_sender: bool,
+    /// Highest context slots seen on L1 and L2
+    context_slots: ContextSlots,
+    /// Handling of message type ids missing from `message_types`
+    unknown_message_type: UnknownTypePolicy,
+    /// Handling of transfers below the dust threshold
+    dust: DustSettings,
+    transaction_builder: TransactionBuilder,
+    /// Nonce account of durable-nonce relay transactions, if configured
+    durable_nonce: Option<DurableNonce>,
+    /// Recent L2 nonce account signatures searched before each send
+    earlier_submission_signatures: usize,
+    airdrop_funder: Option<AirdropFunder>,
+    rent_cache: RentCache,
+    state: StateStore,
+    jump_guard: JumpGuard,
+    anomaly: AnomalyBreaker,
+    /// Scans for transfer PDAs the counter missed, set when
+    /// `pda_discovery_interval_secs` is
+    pda_discovery: Option<PdaDiscovery>,
+    /// Paused flag of the L2 program, set when `l2_bridge_config_account` is
+    l2_pause: Option<L2PauseWatch>,
+    /// Processed-nonce record of the L2 program (`[l2_processed_nonces]`)
+    processed_nonces: Option<ProcessedNonces>,
+    /// Health probe of the L2 cluster, set when `l2_health_max_lag_slots` is
+    l2_health: Option<L2HealthGuard>,
+    /// Limits of the L2 program, set when `l2_limits_offset` is
+    l2_limits: Option<L2LimitWatch>,
+    /// Thresholds of the stall alerts
+    stall_alerts: StallAlerts,
+    /// Signer balance floor and top-up hook
+    balance_guard: BalanceGuard,
+    /// `[policy]` guardrails, checked before a transfer is built
+    policy: Mutex<Option<Arc<TransferPolicy>>>,
+    /// Amount bounds and compute budgets per message type (`[limits]`)
+    limits: LimitsConfig,
+    l2_accounts: L2AccountGuard,
+    /// Slot-age hold of new L1 nonces, set when `min_confirmation_slots` > 0
+    confirmation_hold: Option<ConfirmationHold>,
+    /// Whether only nonces seen at finalized commitment are queued
+    l1_relay_finalized: bool,
+    /// Per-transfer confirmation strategies, set when `[[confirmation_rules]]` are
+    confirmation_rules: Option<ConfirmationRules>,
+    pipeline: PipelineSettings,
+    queue: PendingQueue,
+    /// Stage and endpoint of the current attempt of each pending nonce
+    attempts: AttemptTracker,
+    /// When each range of pending nonces was enqueued, for the latency metric
+    observed: ObservedNonces,
+    in_flight: InFlightTracker,
+    /// Unset with a custom transfer-info layout
+    attestation: Option<AttestationVerifier>,
+    dead_letters: DeadLetterQueue,
+    /// Message indexer, set in merkle mode
+    indexer: Option<IndexerClient>,
+    /// Discovered message accounts, set in program-accounts mode
+    message_accounts: Option<MessageAccounts>,
+    submitter: SubmitterSettings,
+    rebroadcast: RebroadcastSettings,
+    expiry: ExpirySettings,
+    pda_gaps: PdaGaps,
+    /// Merging of transfer bursts, set when `coalesce_window_ms` > 0
+    coalescing: Option<CoalesceSettings>,
+    /// Ranking of pending transfers, set unless `priority = "nonce"`
+    priority: Option<PrioritySelector>,
+    /// Batch sizing from the fee market, set with `batch_strategy = "fee_aware"`
+    batching: Option<FeeAwareBatching>,
+    /// Compute-unit prices, with `priority_fee_strategy`
+    priority_fees: Mutex<Option<Arc<PriorityFees>>>,
+    /// Autoscaling signal, with `scale_target_drain_secs`
+    scaler: Option<Scaler>,
+    /// Write buffer of the state storage, unless `storage_buffer_capacity` is 0
+    storage: Option<StorageHealth>,
+    /// Reconciliation snapshots, with `snapshot_interval_secs`
+    snapshots: Option<Snapshotter>,
+    /// Retention of receipts (`replay_window_nonces` / `replay_window_days`)
+    replay_window: ReplayWindow,
+    /// Token of the replay lease this process holds
+    replay_lease: Mutex<Option<String>>,
+    /// Decision journal, set when `journal_path` is
+    journal: Option<Journal>,
+    /// Confirmed receipts waiting for their transaction details
+    enrichment: EnrichmentQueue,
+    /// Remote copy of the receipts, set when `[receipt_sink]` is
+    receipt_sink: Option<ReceiptSink>,
+    /// Closing of L2 receipt PDAs, set when `[receipt_pdas]` is
+    receipt_pdas: Option<ReceiptPdas>,
+    /// Published relay events, set when `[event_stream]` is
+    event_stream: Option<EventStream>,
+    throughput: Throughput,
+    /// Permits of `max_concurrent_submissions`, shared by the sources
+    submission_slots: Option<Arc<Semaphore>>,
+    /// `max_l2_sends_per_second`, shared by the sources
+    send_rate: Arc<SendRateLimit>,
+    /// Set on SIGINT or SIGTERM; the relayer drains and stops
+    shutdown: Shutdown,
+    /// Callbacks of the service embedding the relayer
+    events: Option<Arc<dyn RelayerEvents>>,
+    /// Hold of submissions set through the admin API
+    manual_pause: ManualPause,
+    /// Whether the relayer submits; a standby becomes active on `promote`
+    role: Mutex<Role>,
+    standby: StandbySettings,
+    /// Whether a warm-up transaction is sent before relaying
+    warmup: bool,
+    /// Simulate relay transactions instead of sending them
+    dry_run: bool,
+    metrics: Arc<Metrics>,
+    clock: Arc<dyn Clock>,
+}
+
+/// Identifies the relayer without its clients, keys or secrets
+impl fmt::Debug for Relayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Relayer")
+            .field("source_id", &self.source_id)
+            .field("l1_rpc_url", &self.l1_rpc_url)
+            .field("l2_rpc_url", &self.l2_rpc_url)
+            .field("watched_account", &self.watched_account)
+            .field("signers", &self.signers)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Relayer {
+    /// The source, with its bridge, as alerts name it
+    fn name(&self) -> String {
+        match &self.bridge {
+            Some(bridge) => format!("Bridge {} source {}", bridge, self.source_id),
+            None => format!("Source {}", self.source_id),
+        }
+    }
+
+    /// Creates the relayer of `source`, its clients sending through `pools`
+    pub async fn new(
+        config: &RelayerConfig,
+        source: &SourceConfig,
+        pools: &SharedPools,
+    ) -> Result<Self> {
+        let l2_client = pools.destination_client(config);
+        let (primary, secondary) = read_signers(config)?;
+        let state = source_state(config, source)?;
+        // 提交方不连接 L1，只读观察方写入队列的意图
+        let l1_client = match config.process_role {
+            ProcessRole::Submitter => {
+                intents::submitter_client(config, source, state.with_backend(state.backend()))?
+            }
+            ProcessRole::Relayer | ProcessRole::Observer => pools.source_client(config),
+        };
+
+        Self::with_clients(
+            config,
+            source,
+            Signers::with_signers(primary, secondary),
+            l1_client,
+            l2_client,
+            state,
+            Arc::new(SystemClock),
+        )
+        .await
+    }
+
+    /// Creates a relayer on top of the given clients, state store and clock
+    /// instead of the ones described by `config` (e.g. fakes in tests)
+    pub async fn with_clients(
+        config: &RelayerConfig,
+        source: &SourceConfig,
+        signers: Signers,
+        l1_client: RpcClient,
+        l2_client: RpcClient,
+        state: StateStore,
+        clock: Arc<dyn Clock>,
+    ) -> Result<Self> {
+        // 试运行的写入只保存在内存中，不改动 state_dir
+        let state = if config.dry_run {
+            state.with_backend(Arc::new(DryRunStorage::new(state.backend())))
+        } else {
+            state
+        };
+        // 存储故障时写入暂存在内存中
+        let (state, storage) = if config.storage_buffer_capacity > 0 {
+            let buffer = Arc::new(BufferedStorage::new(
+                state.backend(),
+                config.storage_buffer_capacity,
+            ));
+            (
+                state.with_backend(buffer.clone()),
+                Some(StorageHealth::new(buffer)),
+            )
+        } else {
+            (state, None)
+        };
+        let (transfer_info_discriminator, watched_discriminator) = source.discriminators.parse()?;
+        let entries_discriminator = source.discriminators.entries()?;
+        if entries_discriminator.is_some() {
+            if config.l2_instruction_version != InstructionVersion::V6 {
+                return Err(anyhow::anyhow!(
+                    "discriminators.transfer_info_entries requires l2_instruction_version = 6, which encodes the entry index"
+                ));
+            }
+            if source.transfer_info_layout.is_some() || config.reads_attestations() {
+                return Err(anyhow::anyhow!(
+                    "discriminators.transfer_info_entries needs the Borsh layout without attestations"
+                ));
+            }
+        }
+        let message_types = source
+            .message_types
+            .iter()
+            .map(|(id, message_type)| {
+                let id = id.parse::<u8>().map_err(|e| {
+                    anyhow::anyhow!(
+                        "Invalid message type id {} of source {}: {}",
+                        id,
+                        source.id,
+                        e
+                    )
+                })?;
+                Ok((id, *message_type))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+        let mint_map = MintMap::new(&config.mint_mappings, config.l2_instruction_version)?;
+        mint_map.check_decimals(&l2_client).await?;
+        let l2_program_id = config.l2_program_id;
+        let mut transaction_builder = TransactionBuilder::new(
+            l2_program_id,
+            source.nonce_account,
+            config.l2_instruction_version,
+            config.bridge_domain,
+            InstructionAccounts::from_config(&config.l2_instruction_accounts, &l2_program_id)?,
+        )?;
+        let durable_nonce = source
+            .durable_nonce_account
+            .map(|account| {
+                DurableNonce::new(
+                    account,
+                    config.durable_nonce_confirmation_blocks,
+                    config.durable_nonce_fallback,
+                    config.send_concurrency,
+                )
+            })
+            .transpose()?;
+        if let Some(durable) = &durable_nonce {
+            transaction_builder.durable_nonce = Some(durable.account);
+            transaction_builder.durable_nonce_closed = durable.closed.clone();
+        }
+        transaction_builder.lookup_tables = config
+            .address_lookup_tables
+            .as_ref()
+            .map(|tables| {
+                LookupTables::from_config(
+                    tables,
+                    transaction_builder
+                        .durable_nonce
+                        .map(|account| *account.pubkey()),
+                )
+            })
+            .transpose()?
+            .map(Arc::new);
+        let bridge_config = l2_accounts::bridge_config_address(config, l2_program_id.pubkey());
+        let l2_accounts = L2AccountGuard::from_config(
+            config,
+            l2_program_id.pubkey(),
+            *transaction_builder.nonce_account.pubkey(),
+            bridge_config,
+        );
+        l2_accounts
+            .check_startup(&l2_client, clock.unix_timestamp())
+            .await?;
+        if let Some(domain) = config.bridge_domain {
+            domain::verify(&l2_client, l2_program_id.pubkey(), domain).await?;
+        }
+
+        let l2_pause = bridge_config.map(|address| {
+            L2PauseWatch::new(
+                address,
+                config.l2_pause_flag_offset,
+                Duration::from_secs(config.l2_pause_check_interval_secs),
+                config.l2_pause_recheck_batch_size,
+            )
+        });
+
+        let processed_nonces = config
+            .l2_processed_nonces
+            .as_ref()
+            .map(|processed| {
+                ProcessedNonces::from_config(
+                    processed,
+                    l2_program_id.pubkey(),
+                    transaction_builder.nonce_account.pubkey(),
+                )
+            })
+            .transpose()?;
+
+        let l2_limits = match (bridge_config, config.l2_limits_offset) {
+            (Some(address), Some(offset)) => Some(L2LimitWatch::new(
+                address,
+                offset,
+                Duration::from_secs(config.l2_limits_ttl_secs),
+            )),
+            (None, Some(_)) => {
+                return Err(anyhow::anyhow!(
+                    "l2_limits_offset requires l2_bridge_config_account or l2_bridge_config_pda"
+                ))
+            }
+            _ => None,
+        };
+
+        signers.refresh(&state)?;
+        let airdrop_funder = if config.dev_mode {
+            tracing::info!("Dev mode enabled: relayer wallet will be funded via L2 airdrops");
+            let funder = AirdropFunder::new(
+                &l2_client,
+                config.dev_airdrop_threshold,
+                config.dev_airdrop_amount,
+            )
+            .await?;
+            funder
+                .ensure_funded(&l2_client, clock.as_ref(), &signers.active().pubkey())
+                .await?;
+            Some(funder)
+        } else {
+            None
+        };
+
+        let indexer = match config.message_source {
+            MessageSource::Pda | MessageSource::ProgramAccounts => None,
+            MessageSource::Merkle => {
+                let url = config.merkle_indexer_url.as_ref().ok_or_else(|| {
+                    anyhow::anyhow!("message_source = \"merkle\" requires merkle_indexer_url")
+                })?;
+                tracing::info!(
+                    "Merkle mode: reading messages from indexer {}",
+                    config.redact(&url.to_string())
+                );
+                Some(IndexerClient::new(url.expose()))
+            }
+        };
+
+        let message_accounts = match config.message_source {
+            MessageSource::ProgramAccounts => {
+                let settings = config.program_accounts.clone().ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "message_source = \"program_accounts\" requires a [program_accounts] section"
+                    )
+                })?;
+                Some(MessageAccounts::load(&state, settings)?)
+            }
+            MessageSource::Pda | MessageSource::Merkle => None,
+        };
+        if config.l1_relay_finalized && message_accounts.is_some() {
+            return Err(anyhow::anyhow!(
+                "l1_relay_finalized reads the watched account, which message_source = \"program_accounts\" does not use; set l1_commitment = \"finalized\" instead"
+            ));
+        }
+        let metrics = Arc::new(Metrics::new(
+            config.bridge.as_deref(),
+            config.namespaced_sources().then_some(source.id.as_str()),
+        ));
+        // promote 写入的角色优先于配置
+        let role = state.get::<Role>(ROLE_KEY)?.unwrap_or(config.role);
+        metrics.standby.set((role == Role::Standby) as u64);
+        let checkpoint = Checkpoint::load(&state)?;
+        if let Some(next_nonce) = checkpoint.next_nonce() {
+            tracing::info!("Resuming from the nonce checkpoint at {}", next_nonce);
+        }
+        let pda_discovery = (config.message_source == MessageSource::Pda
+            && config.pda_discovery_interval_secs > 0)
+            .then(|| {
+                state.get(PDA_DISCOVERY_KEY).map(|last| {
+                    PdaDiscovery::new(
+                        config.pda_discovery_interval_secs,
+                        config.pda_discovery_window,
+                        last,
+                    )
+                })
+            })
+            .transpose()?;
+        let anomaly = AnomalyBreaker::new(
+            config.anomaly_interval_secs,
+            config.anomaly_max_nonces,
+            config.anomaly_max_amount,
+        );
+        if let Some(trip) = anomaly.tripped(&state)? {
+            tracing::warn!(
+                "Anomaly circuit breaker tripped at {}: {}; resume with resume-breaker",
+                trip.at,
+                trip.reason
+            );
+        }
+
+        let relayer = Self {
+            source_id: source.id.clone(),
+            bridge: config.bridge.clone(),
+            l1_client,
+            l2_client,
+            l1_rpc_url: config.redact(&config.source_url().to_string()),
+            l2_rpc_url: config.redact(&config.destination_url().to_string()),
+            watched_account: source.watched_account,
+            l1_verifiers: config
+                .l1_verify_urls
+                .iter()
+                .map(|url| L1Verifier {
+                    url: config.redact(&url.to_string()),
+                    client: RpcClient::new_with_commitment(
+                        url.expose().to_string(),
+                        config.l1_commitment.config(),
+                    ),
+                })
+                .collect(),
+            dual_read_threshold: config.dual_read_threshold,
+            l1_lag: L1LagProbe::new(
+                Duration::from_secs(config.l1_lag_probe_interval_secs),
+                config.l1_lag_quarantine_slots,
+            ),
+            account_cache: config
+                .l1_account_cache
+                .as_ref()
+                .map(AccountCacheReader::from_config)
+                .transpose()?,
+            approval: approval_policy(config)?,
+            quiet_period: config
+                .new_recipient_quiet_period_secs
+                .map(Duration::from_secs),
+            watched: WatchedAccount::new(
+                Duration::from_secs(config.watched_account_missing_poll_secs),
+                Duration::from_secs(config.watched_account_missing_alert_secs),
+                watched_discriminator,
+                state.get(WATCHED_LAYOUT_KEY)?,
+            ),
+            poll: AdaptivePoll::new(
+                Duration::from_millis(config.poll_interval_min_ms),
+                Duration::from_millis(config.poll_interval_max_ms),
+                Duration::from_secs(config.poll_idle_after_secs),
+                config.poll_suspend_backlog,
+            ),
+            subscription: (config.l1_watch_mode == WatchMode::Subscribe).then(|| {
+                AccountSubscription::new(
+                    config
+                        .l1_ws_url
+                        .clone()
+                        .unwrap_or_else(|| config.source_url().websocket()),
+                )
+            }),
+            signers,
+            last_nonce: Mutex::new(checkpoint.next_nonce()),
+            last_l1_nonce: Mutex::new(None),
+            checkpoint,
+            pda_manager: PdaManager::new(
+                source.l1_program_id,
+                source.watched_account,
+                source.seeds.clone(),
+            )
+            .with_decoder(message_decoder::from_layout(
+                source.transfer_info_layout.as_ref(),
+                config.reads_attestations(),
+            ))
+            .with_registry(
+                source
+                    .cancellation_registry
+                    .as_ref()
+                    .map(CancellationRegistry::from_config),
+            ),
+            deposit_routes: DepositRoutes::new(&config.pda_recipient_programs)?,
+            transfer_info_discriminator,
+            entries_discriminator,
+            message_types,
+            mint_map: Mutex::new(Arc::new(mint_map)),
+            unmapped_mint: config.unmapped_mint,
+            held_mints: Mutex::default(),
+            token_account_rent_payer: config.token_account_rent_payer,
+            token_account_mismatch: config.token_account_mismatch,
+            relay_sender: config.relay_sender,
+            context_slots: ContextSlots::default(),
+            unknown_message_type: config.unknown_message_type,
+            dust: DustSettings {
+                policy: config.dust,
+                threshold: config.dust_threshold,
+            },
+            transaction_builder,
+            durable_nonce,
+            earlier_submission_signatures: config.earlier_submission_signatures,
+            airdrop_funder,
+            rent_cache: RentCache::new(),
+            jump_guard: JumpGuard::new(config.max_expected_jump),
+            anomaly,
+            pda_discovery,
+            l2_pause,
+            processed_nonces,
+            l2_health: L2HealthGuard::from_config(config),
+            l2_limits,
+            policy: Mutex::new(config.policy.clone().map(Arc::new)),
+            limits: config.limits.clone(),
+            balance_guard: BalanceGuard::from_config(config),
+            stall_alerts: StallAlerts::new(
+                config.alert_consecutive_failures,
+                config.alert_consecutive_read_failures,
+                config.alert_nonce_lag,
+                config.alert_min_balance,
+            ),
+            l2_accounts,
+            l1_relay_finalized: config.l1_relay_finalized,
+            confirmation_hold: (config.min_confirmation_slots > 0)
+                .then(|| ConfirmationHold::new(config.min_confirmation_slots)),
+            confirmation_rules: ConfirmationRules::from_config(config),
+            pipeline: PipelineSettings {
+                channel_capacity: config.pipeline_channel_capacity,
+                fetch_batch_size: config.fetch_batch_size,
+                send_concurrency: config.send_concurrency,
+                pack_max_transfers: config.pack_max_transfers,
+                simulate_compute_units: config.simulate_compute_units,
+            },
+            queue: PendingQueue::load(&state, config.queue_capacity, config.queue_overflow)?,
+            attempts: AttemptTracker::default(),
+            observed: ObservedNonces::default(),
+            in_flight: InFlightTracker::load(&state)?,
+            // 未配置证明时不读签名；签名只跟在 Borsh 布局之后
+            attestation: match (&source.transfer_info_layout, config.reads_attestations()) {
+                (_, false) => None,
+                (None, true) => Some(AttestationVerifier::new(
+                    &config.attestor_pubkeys,
+                    config.require_attestation,
+                )?),
+                (Some(_), true) => {
+                    return Err(anyhow::anyhow!(
+                        "Source {} sets transfer_info_layout, but attestations are only read from the Borsh layout; unset attestor_pubkeys and require_attestation",
+                        source.id
+                    ))
+                }
+            },
+            dead_letters: DeadLetterQueue::load(&state)?,
+            indexer,
+            message_accounts,
+            submitter: SubmitterSettings {
+                batch_size: config.submit_batch_size,
+                interval: Duration::from_millis(config.submit_interval_ms),
+                retry_delay: Duration::from_millis(config.retry_delay_ms),
+                breaker_threshold: config.breaker_failure_threshold,
+                breaker_cooldown: Duration::from_secs(config.breaker_cooldown_secs),
+                backoff: RetryBackoff {
+                    initial: Duration::from_secs(config.retry_backoff_initial_secs),
+                    max: Duration::from_secs(config.retry_backoff_max_secs),
+                },
+                max_attempts: config.max_nonce_attempts,
+            },
+            rebroadcast: RebroadcastSettings {
+                interval: Duration::from_millis(config.rebroadcast_interval_ms),
+                max_duration: Duration::from_secs(config.rebroadcast_max_secs),
+            },
+            expiry: ExpirySettings {
+                skew: Duration::from_secs(config.expiry_skew_secs),
+                alert_window: Duration::from_secs(config.expiry_alert_secs),
+                message_ttl: (config.message_ttl_secs > 0)
+                    .then(|| Duration::from_secs(config.message_ttl_secs)),
+            },
+            pda_gaps: PdaGaps::new(
+                Duration::from_secs(config.missing_pda_alert_secs),
+                Duration::from_secs(config.missing_pda_deadline_secs),
+            ),
+            coalescing: (config.coalesce_window_ms > 0).then(|| CoalesceSettings {
+                window: Duration::from_millis(config.coalesce_window_ms),
+                max_amount: config.coalesce_max_amount,
+            }),
+            priority: match config.priority {
+                QueuePriority::Nonce => None,
+                priority => Some(PrioritySelector::load(
+                    &state,
+                    priority,
+                    config.priority_window,
+                    Duration::from_secs(config.priority_max_wait_secs),
+                )?),
+            },
+            priority_fees: Mutex::new(PriorityFees::from_config(config)?.map(Arc::new)),
+            batching: (config.batch_strategy == BatchStrategy::FeeAware).then(|| {
+                FeeAwareBatching::new(
+                    Duration::from_millis(config.batch_latency_target_ms),
+                    config.batch_high_fee_micro_lamports,
+                    config.batch_small_backlog,
+                    Duration::from_secs(config.batch_fee_refresh_secs),
+                    config.submit_batch_size,
+                )
+            }),
+            scaler: config.scale_target_drain_secs.map(|target| {
+                Scaler::new(
+                    target,
+                    config.scale_max_replicas,
+                    Duration::from_secs(config.scale_interval_secs),
+                    config
+                        .scale_webhook_url
+                        .as_ref()
+                        .map(|url| url.expose().clone()),
+                )
+            }),
+            storage,
+            snapshots: config
+                .snapshot_interval_secs
+                .map(|interval| {
+                    Snapshotter::load(
+                        &state,
+                        Duration::from_secs(interval),
+                        (config.snapshot_retention_days > 0)
+                            .then(|| Duration::from_secs(config.snapshot_retention_days * 86_400)),
+                    )
+                })
+                .transpose()?,
+            replay_lease: Mutex::new(None),
+            replay_window: ReplayWindow::load(
+                &state,
+                config.replay_window_nonces,
+                config
+                    .replay_window_days
+                    .map(|days| Duration::from_secs(days * 86_400)),
+                Duration::from_secs(config.replay_prune_interval_secs),
+                config.outside_replay_window,
+            )?,
+            throughput: Throughput::new(
+                LeakyBucket::new(config.max_transfers_per_minute, config.transfer_rate_burst),
+                LeakyBucket::new(
+                    source
+                        .max_transfers_per_minute
+                        .or(config.max_transfers_per_minute_per_source),
+                    config.transfer_rate_burst,
+                ),
+            ),
+            submission_slots: config
+                .max_concurrent_submissions
+                .map(|limit| Arc::new(Semaphore::new(limit))),
+            send_rate: Arc::new(SendRateLimit::new(
+                config.max_l2_sends_per_second,
+                config.l2_send_burst,
+            )),
+            shutdown: Shutdown::new(Duration::from_secs(config.shutdown_timeout_secs)),
+            events: None,
+            manual_pause: ManualPause::load(&state)?,
+            journal: journal_path(config, &source.id)
+                .filter(|_| !config.dry_run)
+                .map(|path| {
+                    Journal::new(
+                        path,
+                        config.journal_max_bytes,
+                        config.journal_retain_files,
+                        config.storage_buffer_capacity,
+                    )
+                }),
+            enrichment: EnrichmentQueue::new(
+                Duration::from_millis(config.receipt_enrich_delay_ms),
+                config.receipt_enrich_attempts,
+                config.receipt_enrich_queue_capacity,
+            ),
+            receipt_sink: config
+                .receipt_sink
+                .as_ref()
+                .filter(|_| !config.dry_run)
+                .map(|sink| ReceiptSink::new(sink, &source.id))
+                .transpose()?,
+            receipt_pdas: config
+                .receipt_pdas
+                .as_ref()
+                .map(ReceiptPdas::from_config)
+                .transpose()?,
+            event_stream: config
+                .event_stream
+                .as_ref()
+                .filter(|_| !config.dry_run)
+                .map(|stream| EventStream::new(stream, &source.id))
+                .transpose()?,
+            role: Mutex::new(role),
+            standby: StandbySettings {
+                interval: Duration::from_secs(config.standby_interval_secs),
+                simulate: config.standby_simulate,
+                mirror_rps: config.standby_mirror_rps,
+                #[cfg(feature = "admin")]
+                ready_max_lag: config.standby_ready_max_lag,
+            },
+            warmup: config.warmup_transaction && !config.dry_run,
+            dry_run: config.dry_run,
+            metrics,
+            clock,
+            state,
+        };
+        if let Some(tables) = &relayer.transaction_builder.lookup_tables {
+            relayer.load_lookup_tables(tables).await?;
+        }
+        if relayer.transaction_builder.accounts.configured {
+            relayer.verify_instruction_accounts().await?;
+        }
+        relayer.verify_durable_nonce().await?;
+        Ok(relayer)
+    }
+
+    /// Reads the L1 watched-account nonce (in program-accounts mode, the end of the
+    /// discovered message run) and the L1 nonce recorded by the L2 nonce account
+    async fn read_nonces(&self) -> Result<(u64, u64)> {
+        let l2_nonce_status = self.read_l2_relayed_nonce().await?;
+        let l1_watched_nonce = self.current_l1_nonce(l2_nonce_status).await?;
+        Ok((l1_watched_nonce, l2_nonce_status))
+    }
+
+    /// Reads the L1 nonce from the configured message source; `relayed` is
+    /// where a program-account scan starts
+    async fn current_l1_nonce(&self, relayed: u64) -> Result<u64> {
+        match &self.message_accounts {
+            Some(accounts) => self.scan_message_accounts(accounts, relayed).await,
+            None => {
+                let counter = self.read_l1_nonce().await?;
+                self.discovered_l1_nonce(counter, relayed).await
+            }
+        }
+    }
+
+    /// Reads the next L1 nonce the L2 nonce account expects, i.e. how far L2 has relayed
+    async fn read_l2_relayed_nonce(&self) -> Result<u64> {
+        // 获取 L2 nonce account 的状态，要求节点不落后于已见过的 slot
+        let address = self.transaction_builder.nonce_account.pubkey();
+        let response = self
+            .l2_client
+            .get_account_with_config(
+                address,
+                context_slot::account_config(
+                    self.l2_client.commitment(),
+                    self.context_slots.l2.get(),
+                ),
+            )
+            .await
+            .map_err(|e| self.l2_read_error(e))?;
+        self.context_slots.l2.observe(response.context.slot);
+        let nonce_account = response
+            .value
+            .ok_or_else(|| anyhow::anyhow!("L2 nonce account {} not found", address))?
+            .data;
+
+        let l2_nonce_status = if nonce_account.len() >= 24 {
+            let l1_nonce_bytes: [u8; 8] = nonce_account[8..16].try_into()?;
+            let l2_nonce_bytes: [u8; 8] = nonce_account[16..24].try_into()?;
+
+            let l1_nonce = u64::from_le_bytes(l1_nonce_bytes);
+            let l2_nonce = u64::from_le_bytes(l2_nonce_bytes);
+
+            tracing::debug!(l1_nonce, l2_nonce, "Nonce account state");
+
+            l1_nonce
+        } else {
+            return Err(anyhow::anyhow!(
+                "Invalid nonce account data length: expected at least 24 bytes, got {} ({})",
+                nonce_account.len(),
+                decode::hex_prefix(&nonce_account)
+            ));
+        };
+
+        Ok(l2_nonce_status)
+    }
+
+    /// Runs the monitor and the submitter side by side until either fails
+    /// or a shutdown has drained the submitter
+    async fn monitor_and_relay(&self) -> Result<()> {
+        self.recover_in_flight().await?;
+        let background = async {
+            tokio::try_join!(
+                self.monitor(),
+                self.prune_replay_state(),
+                self.enrich_receipts(),
+                self.finalize_transfers(),
+                self.publish_scaling(),
+                self.watch_l1_lag(),
+                self.watch_signer_balance(),
+                self.watch_l1_account(),
+                self.ship_receipts(),
+                self.publish_events(),
+                self.maintain_lookup_tables(),
+                self.close_receipt_pdas_periodically()
+            )?;
+            Ok::<_, anyhow::Error>(())
+        };
+        tokio::select! {
+            result = background => result,
+            result = self.relay_until_shutdown() => result,
+        }
+    }
+
+    /// Watches the L1 nonce and enqueues newly observed nonces; never waits on
+    /// L2 submissions unless the pending queue is full
+    async fn monitor(&self) -> Result<()> {
+        loop {
+            // 停止时不再入队新的 nonce
+            if self.shutdown.requested() {
+                return Ok(());
+            }
+            // 积压足够多时已知落后，暂停轮询直到积压回落
+            let (queued, spilled) = self.queue.depth();
+            let suspended = self.poll.suspended(queued + spilled);
+            self.metrics.poll_suspended.set(suspended as u64);
+            if suspended {
+                // 暂停轮询时仍读取 watched account，及时发现 L1 暂停
+                if self.message_accounts.is_none() {
+                    if let Err(e) = self.read_l1_nonce().await {
+                        tracing::warn!("failed to read the watched account: {}", e);
+                    }
+                }
+                self.clock.sleep(self.poll.suspended_wait()).await;
+                continue;
+            }
+
+            match self.monitor_once().await {
+                Ok(l1_nonce) => {
+                    self.alert_read_failures(0, None);
+                    let (queued, spilled) = self.queue.depth();
+                    let interval =
+                        self.poll
+                            .next(l1_nonce, queued + spilled, self.clock.unix_timestamp());
+                    self.metrics
+                        .poll_interval_ms
+                        .set(interval.as_millis() as u64);
+                    self.wait_for_l1(interval).await;
+                }
+                // watched account 暂时不存在时降低轮询频率，不退出
+                Err(e) => match e.downcast_ref::<WatchedAccountMissing>() {
+                    Some(missing) => self.watched_account_missing(missing).await,
+                    // 存储不可用时继续监控，等存储恢复后再入队
+                    None if e.is::<StorageUnavailable>() || e.is::<NodeBehind>() => {
+                        tracing::warn!("{}", e);
+                        self.clock.sleep(self.submitter.retry_delay).await;
+                    }
+                    // owner 变化或状态损坏需要人工处理，停止该 source
+                    None if e.is::<WatchedOwnerChanged>()
+                        || e.chain().any(|cause| cause.is::<CorruptState>()) =>
+                    {
+                        return Err(e)
+                    }
+                    // RPC 超时或 5xx 时退避重试，不停止该 source
+                    None => {
+                        let (interval, consecutive) = self.poll.failed();
+                        tracing::warn!(
+                            "monitor read failed ({} in a row), retrying in {:?}: {:#}",
+                            consecutive,
+                            interval,
+                            e
+                        );
+                        self.metrics
+                            .poll_interval_ms
+                            .set(interval.as_millis() as u64);
+                        self.alert_read_failures(consecutive, Some(&e));
+                        self.clock.sleep(interval).await;
+                    }
+                },
+            }
+        }
+    }
+
+    /// One monitor poll: reads both nonces, enqueues what may be relayed and
+    /// returns the L1 nonce
+    async fn monitor_once(&self) -> Result<u64> {
+        let (l1_watched_nonce, l2_nonce_status, queue_to) = self.poll_nonces().await?;
+        // 如果 L1 watched account 的 nonce 大于当前处理的 nonce，加入待处理队列
+        if queue_to > l2_nonce_status {
+            let enqueued = self
+                .queue
+                .push_range(&self.state, l2_nonce_status, queue_to)
+                .await?;
+            self.observed
+                .observe(enqueued.clone(), self.clock.unix_timestamp());
+            if !enqueued.is_empty() {
+                self.journal(
+                    None,
+                    JournalEvent::Enqueued {
+                        from: enqueued.start,
+                        to: enqueued.end,
+                    },
+                );
+            }
+        }
+
+        self.observed.prune_below(l2_nonce_status);
+        let lag = l1_watched_nonce.saturating_sub(l2_nonce_status);
+        self.metrics.nonce_lag.set(lag);
+        self.alert_nonce_lag(lag);
+        self.record_queue_depth();
+        if let Err(e) = self.record_snapshot(l1_watched_nonce, l2_nonce_status) {
+            tracing::warn!("failed to record a reconciliation snapshot: {}", e);
+        }
+        Ok(l1_watched_nonce)
+    }
+
+    /// Reads both nonces and returns them with the end of the range that may
+    /// be enqueued (the L2 nonce when nothing may)
+    pub(crate) async fn poll_nonces(&self) -> Result<(u64, u64, u64)> {
+        let (l1_watched_nonce, l2_nonce_status) = self.read_nonces().await?;
+        self.journal(
+            None,
+            JournalEvent::NonceObserved {
+                l1_nonce: l1_watched_nonce,
+                l2_nonce: l2_nonce_status,
+            },
+        );
+
+        let previous = self.last_l1_nonce.lock().unwrap().replace(l1_watched_nonce);
+        // 本次运行的第一次轮询只作为基准
+        if let Some(previous) = previous {
+            self.anomaly.observe_nonces(
+                &self.state,
+                self.clock.unix_timestamp(),
+                l1_watched_nonce.saturating_sub(previous),
+            )?;
+        }
+        // 更新 last_nonce 为 L2 nonce account 中的值
+        {
+            let mut last_nonce = self.last_nonce.lock().unwrap();
+            if *last_nonce != Some(l2_nonce_status) {
+                tracing::info!(
+                    "Updating last_nonce from {} to {}",
+                    last_nonce.unwrap_or(0),
+                    l2_nonce_status
+                );
+                *last_nonce = Some(l2_nonce_status);
+            }
+        }
+
+        // 异常的 nonce 跳变需要运维确认后才继续
+        // 扫描模式每次最多取 max_accounts_per_scan 个 nonce，不做跳变检查
+        let raw_account = async {
+            Ok(self
+                .l1_client
+                .get_account_data(self.watched_account.pubkey())
+                .await?)
+        };
+        let jump_allowed = self.message_accounts.is_some()
+            || self
+                .jump_guard
+                .allows(&self.state, l2_nonce_status, l1_watched_nonce, raw_account)
+                .await?;
+
+        // 新 nonce 需达到 min_confirmation_slots 的确认深度后才入队
+        let queue_to = match &self.confirmation_hold {
+            _ if !jump_allowed || self.below_checkpoint(l2_nonce_status) => l2_nonce_status,
+            Some(hold) => {
+                self.confirmed_l1_nonce(hold, l1_watched_nonce, l2_nonce_status)
+                    .await?
+            }
+            None => l1_watched_nonce,
+        };
+        // 只入队 finalized 承诺下已可见的 nonce，避免转发被回滚的消息
+        let queue_to = if self.l1_relay_finalized && queue_to > l2_nonce_status {
+            self.finalized_l1_nonce(queue_to, l2_nonce_status).await?
+        } else {
+            queue_to
+        };
+
+        Ok((
+            l1_watched_nonce,
+            l2_nonce_status,
+            queue_to.max(l2_nonce_status),
+        ))
+    }
+
+    /// Fetches the transfer info for `nonce` from L1 and builds the signed L2 transaction
+    async fn prepare_l2_transfer(&self, nonce: u64) -> Result<PreparedTransfer> {
+        let (pda, bump) = self.pda_manager.find_address(nonce);
+        tracing::debug!(nonce, %pda, bump, "Preparing L2 transfer");
+
+        let (amount, to_address, l1_slot) = self
+            .pda_manager
+            .get_transfer_info(&self.l1_client, &pda)
+            .await?;
+
+        self.build_transfer(FetchedTransfer {
+            nonce,
+            amount,
+            to_address,
+            l1_slot,
+            message_type: None,
+            coalesced: Vec::new(),
+            proof: None,
+            expires_at: None,
+            digest: None,
+            sender: None,
+            token: None,
+            dust: Vec::new(),
+            create_token_account: false,
+            entry: None,
+            span: info_span!("audit_nonce", nonce),
+        })
+        .await
+    }
+
+    /// Simulates every transfer between the L2 and L1 nonces without sending anything
+    async fn run_audit(&self) -> Result<AuditReport> {
+        if self.indexer.is_some() || self.message_accounts.is_some() {
+            return Err(anyhow::anyhow!(
+                "Audit mode only supports the pda message source"
+            ));
+        }
+        let (l1_watched_nonce, l2_nonce_status) = self.read_nonces().await?;
+        tracing::info!(
+            "Auditing nonces {}..{} (nothing will be sent)",
+            l2_nonce_status,
+            l1_watched_nonce
+        );
+
+        let mut report = AuditReport::default();
+        for nonce in l2_nonce_status..l1_watched_nonce {
+            let entry = match self.prepare_l2_transfer(nonce).await {
+                Ok(prepared) => audit_transfer(
+                    &self.l2_client,
+                    prepared.nonce,
+                    &prepared.transaction,
+                    &prepared.to_address,
+                    prepared.amount,
+                )
+                .await
+                .unwrap_or_else(|e| AuditEntry::failed(nonce, e.to_string())),
+                Err(e) => AuditEntry::failed(nonce, e.to_string()),
+            };
+            match &entry.error {
+                Some(error) => tracing::warn!("Audit nonce {}: FAIL: {}", nonce, error),
+                None if entry.passed => tracing::info!("Audit nonce {}: PASS", nonce),
+                None => tracing::warn!("Audit nonce {}: FAIL", nonce),
+            }
+            report.push(entry);
+        }
+
+        Ok(report)
+    }
+
+    /// Submits the transaction of `transfers` (one transfer, or those packed
+    /// into it) and waits for it to land. The signature is persisted before
+    /// submission so a restart can tell whether it landed.
+    async fn send_transaction_to_l2(&self, transfers: Vec<PreparedTransfer>) -> Result<()> {
+        let mut handled = 0;
+        for prepared in &transfers {
+            if self.handled_before_send(prepared).await? {
+                handled += 1;
+            }
+        }
+        if handled == transfers.len() {
+            return Ok(());
+        }
+        let prepared = &transfers[0];
+        if handled > 0 {
+            // 打包交易中已有 nonce 被处理，剩余的 nonce 重试时重新打包
+            return Err(anyhow::anyhow!(
+                "Packed transaction of nonces {:?} no longer matches its transfers ({} already handled); rebuilt on retry",
+                prepared.packed,
+                handled
+            ));
+        }
+        // 批量重放持有租约时不提交，由重放进程发送
+        if let Some(lease) = self.foreign_replay_lease()? {
+            return Err(ReplayInProgress {
+                holder: lease.holder,
+            }
+            .into());
+        }
+
+        // 所有 source 共用提交名额，直到交易确认才归还
+        let _slot = match &self.submission_slots {
+            Some(slots) => Some(slots.acquire().await?),
+            None => None,
+        };
+        self.check_shutdown(prepared.nonce)?;
+        if self.dry_run {
+            return self.simulate_transfer(prepared).await;
+        }
+        if prepared.packed.is_empty() {
+            tracing::info!("Sending transaction to L2 for nonce {}...", prepared.nonce);
+        } else {
+            tracing::info!(
+                "Sending packed transaction to L2 for nonces {:?}...",
+                prepared.packed
+            );
+        }
+        let signature = prepared.transaction.signatures[0];
+        let mut in_flight = Vec::with_capacity(transfers.len());
+        for transfer in &transfers {
+            transfer.span.record("signature", signature.to_string());
+            let record = InFlightTransfer::new(transfer, self.clock.unix_timestamp());
+            self.reserve_in_flight(transfer, record.clone()).await?;
+            in_flight.push(record);
+        }
+        let advance = |class| {
+            for transfer in &transfers {
+                self.attempts.advance(
+                    transfer.nonce,
+                    &transfer.coalesced,
+                    class,
+                    &self.l2_rpc_url,
+                    None,
+                );
+            }
+        };
+        advance(FailureClass::Send);
+        for transfer in &transfers {
+            self.attempts
+                .sent(transfer.nonce, &transfer.coalesced, signature);
+        }
+
+        if let Some(tables) = &self.transaction_builder.lookup_tables {
+            tables.record(&prepared.transaction.message);
+        }
+        self.await_send_rate().await;
+        let sent = self
+            .l2_client
+            .send_transaction(&prepared.transaction)
+            .instrument(info_span!(
+                parent: &prepared.span,
+                "submit_transaction",
+                %signature,
+                rpc = %self.l2_rpc_url,
+            ))
+            .await;
+        // 节点过载时会丢弃交易，确认前定期重发同一笔交易
+        let rebroadcasts = AtomicU32::new(0);
+        let outcome = match sent {
+            Ok(_) => {
+                for transfer in &transfers {
+                    tracing::info!(parent: &transfer.span, %signature, "transaction sent");
+                }
+                advance(FailureClass::Confirm);
+                tokio::select! {
+                    outcome = self
+                        .await_transaction(
+                            &signature,
+                            prepared.last_valid_block_height,
+                            self.landing_commitment(prepared.strategy),
+                        )
+                        .instrument(confirm_span(&prepared.span, &signature)) => outcome,
+                    () = self.rebroadcast(&prepared.transaction, &rebroadcasts) => {
+                        unreachable!("rebroadcasting never completes")
+                    }
+                }
+            }
+            Err(err) => {
+                for transfer in &transfers {
+                    tracing::warn!(parent: &transfer.span, error = %err, "send failed");
+                }
+                tracing::warn!("Transaction failed: {:?}", err);
+                if let Some(program_error) = err.get_transaction_error() {
+                    tracing::warn!("Program error: {:?}", program_error);
+                    advance(FailureClass::Rejected);
+                    for transfer in &transfers {
+                        self.in_flight.remove(&self.state, transfer.nonce)?;
+                    }
+                }
+                // 发送失败但交易可能已到达节点，保留记录待下次确认
+                return Err(anyhow::anyhow!("L2 transaction failed: {}", err));
+            }
+        };
+        let outcome = outcome?;
+        let rebroadcasts = rebroadcasts.into_inner();
+        if let TransactionOutcome::Confirmed { slot } = outcome {
+            for transfer in &transfers {
+                if let Some(latency) = self.relay_latency(transfer.nonce) {
+                    transfer.span.record("latency_secs", latency);
+                }
+                tracing::info!(parent: &transfer.span, slot, rebroadcasts, "transaction confirmed");
+            }
+            if prepared.strategy == Some(ConfirmationStrategy::Safe) {
+                // 已确认即放行后续提交，终局确认和回执在后台完成
+                for transfer in &transfers {
+                    self.in_flight
+                        .mark_landed(&self.state, transfer.nonce, slot, rebroadcasts)?;
+                    tracing::info!(
+                        "Nonce {} confirmed in slot {} ({}); waiting for finality in the background",
+                        transfer.nonce, slot, signature
+                    );
+                }
+                return Ok(());
+            }
+            for record in &in_flight {
+                let mut receipt = self.confirmed_receipt(record, slot).await?;
+                receipt.rebroadcasts = rebroadcasts;
+                self.record_confirmed(record, receipt)?;
+            }
+        }
+        for transfer in &transfers {
+            self.in_flight.remove(&self.state, transfer.nonce)?;
+        }
+
+        match outcome {
+            TransactionOutcome::Confirmed { .. } => {
+                tracing::info!(
+                    %signature,
+                    rebroadcasts,
+                    "Transaction successful: https://explorer.solana.com/tx/{}?cluster=custom",
+                    signature
+                );
+                Ok(())
+            }
+            TransactionOutcome::Failed(err) => {
+                for transfer in &transfers {
+                    tracing::warn!(parent: &transfer.span, error = ?err, "transaction failed");
+                }
+                advance(FailureClass::Rejected);
+                tracing::warn!("Transaction failed, program error: {:?}", err);
+                Err(anyhow::anyhow!(
+                    "L2 transaction {} failed: {}",
+                    signature,
+                    err
+                ))
+            }
+            TransactionOutcome::Expired => {
+                for transfer in &transfers {
+                    tracing::warn!(parent: &transfer.span, "transaction expired");
+                }
+                advance(FailureClass::Expired);
+                Err(anyhow::anyhow!(
+                    "L2 transaction {} expired before landing",
+                    signature
+                ))
+            }
+        }
+    }
+
+    /// Whether `prepared` needs no submission: its transaction from a
+    /// previous run landed, L2 already processed the nonce, or it expired or
+    /// was cancelled on L1 and has a receipt saying so
+    async fn handled_before_send(&self, prepared: &PreparedTransfer) -> Result<bool> {
+        // 上次运行遗留的交易先确认结果，已落地则不再重发
+        if self.resolve_in_flight(prepared.nonce).await? {
+            tracing::info!("Nonce {} was already relayed", prepared.nonce);
+            return Ok(true);
+        }
+        if self.check_l2_processed(prepared).await? {
+            return Ok(true);
+        }
+        if self.check_earlier_submission(prepared).await? {
+            return Ok(true);
+        }
+        if let Some(expires_at) = self.passed_deadline(prepared).await? {
+            self.expire_transfer(prepared, expires_at)?;
+            return Ok(true);
+        }
+        self.check_l1_cancellation(prepared).await
+    }
+}
+
+/// Span around waiting for `signature` to land
+fn confirm_span(parent: &Span, signature: &solana_sdk::signature::Signature) -> Span {
+    info_span!(
+        parent: parent,
+        "confirm_transaction",
+        %signature,
+        slot = field::Empty,
+        polls = field::Empty,
+        outcome = field::Empty,
+    )
+}
+
+/// Runs the command line of the relayer binary
+pub async fn run_cli() -> ExitCode {
+    let cli = Cli::parse();
+    let build = BuildInfo::current();
+    if cli.show_version && !cli.verbose {
+        output!("{}", build);
+        return ExitCode::SUCCESS;
+    }
+
+    let config = match load_config(&cli) {
+        Ok(config) => config,
+        Err(e) => return crash::exit(None, &e),
+    };
+    if cli.show_version {
+        return match config.fingerprint() {
+            Ok(fingerprint) => {
+                if let Some(profile) = &config.profile {
+                    output!("Profile: {}", profile);
+                }
+                output!("Config fingerprint: {}", fingerprint);
+                ExitCode::SUCCESS
+            }
+            Err(e) => crash::exit(None, &e),
+        };
+    }
+    crash::install_panic_hook(&config);
+    let result = run(cli, &config).await;
+    // 退出前把已触发的告警发出去
+    alerts::flush(Duration::from_secs(5)).await;
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => crash::exit(Some(&config), &e),
+    }
+}
+
+fn load_config(cli: &Cli) -> Result<RelayerConfig> {
+    ConfigFile::from_cli(cli)?
+        .load()
+        .context(ExitCategory::Config)
+}
+
+async fn run(cli: Cli, config: &RelayerConfig) -> Result<()> {
+    let config_file = ConfigFile::from_cli(&cli)?;
+    let telemetry =
+        telemetry::init(&config.tracing, &config.logging).context(ExitCategory::Config)?;
+    alerts::init(&config.alert_webhooks).context(ExitCategory::Config)?;
+    chaos::init(&config.chaos).context(ExitCategory::Config)?;
+    tracing::info!("Starting {}", BuildInfo::current());
+    tracing::info!("Config loaded from {}", config_file.path().display());
+    if let Some(profile) = &config.profile {
+        tracing::info!("Profile: {}", profile);
+    }
+    tracing::info!("L1 URL: {}", config.redact(&config.l1_url.to_string()));
+    tracing::info!("L2 URL: {}", config.redact(&config.l2_url.to_string()));
+    if config.direction == Direction::L2ToL1 {
+        tracing::info!(
+            "Direction: {} (watching L2, submitting on L1)",
+            config.direction
+        );
+    }
+    if config.dry_run {
+        tracing::info!("Dry run: relay transactions are simulated on L2, not sent; state changes stay in memory");
+    }
+
+    let command = cli.command.unwrap_or(Command::Run {
+        once: false,
+        dry_run: false,
+        role: None,
+    });
+    if let Command::Config { command } = &command {
+        return cmd_config(config, command);
+    }
+    if !config.bridges.is_empty() {
+        return cmd_bridges(config, command, &telemetry, &config_file).await;
+    }
+    if let Command::EncryptKeypair { input, output } = &command {
+        return cmd_encrypt_keypair(config, input, output);
+    }
+
+    let sources = config.sources().context(ExitCategory::Config)?;
+    match command {
+        Command::ResumeBreaker { source } => {
+            cmd_resume_breaker(config, &sources, source.as_deref())
+        }
+        Command::ConfirmJump { to, source } => {
+            cmd_confirm_jump(config, &sources, to, source.as_deref())
+        }
+        Command::Throttle {
+            per_minute,
+            reset,
+            source,
+        } => cmd_throttle(config, &sources, per_minute, reset, source.as_deref()),
+        Command::LogLevel { filter, reset } => {
+            cmd_log_level(config, &sources, filter.as_deref(), reset)
+        }
+        Command::Promote { reset } => cmd_promote(config, &sources, reset),
+        Command::Approvals { source, json } => {
+            cmd_approvals(config, &sources, source.as_deref(), json)
+        }
+        Command::Approve {
+            nonce,
+            keypair,
+            source,
+        } => cmd_approve(config, &sources, nonce, &keypair, source.as_deref()),
+        Command::Cancel {
+            nonce,
+            reason,
+            source,
+        } => cmd_cancel(config, &sources, nonce, &reason, source.as_deref()),
+        Command::Status {
+            source,
+            live: false,
+        } => cmd_status(config, &sources, source.as_deref()),
+        Command::Replay { nonce, by, source } => {
+            cmd_replay(config, &sources, nonce, by, source.as_deref())
+        }
+        Command::Dlq { command } => cmd_dlq(config, &sources, &command),
+        Command::Sink { command } => cmd_sink(config, &sources, &command).await,
+        Command::Compact { older_than, source } => {
+            cmd_compact(config, &sources, older_than, source.as_deref())
+        }
+        Command::State { command } => cmd_state(config, &sources, &command).await,
+        Command::Snapshots { from, source } => {
+            cmd_snapshots(config, &sources, from, source.as_deref())
+        }
+        Command::Journal { command } => cmd_journal(config, &sources, &command).await,
+        Command::DecodeAccount { pubkey, cluster } => {
+            cmd_decode_account(config, &pubkey, cluster).await
+        }
+        Command::DecodePda { nonce, source } => {
+            cmd_decode_pda(config, &sources, nonce, source.as_deref()).await
+        }
+        Command::VerifyProof { path, pubkey } => cmd_verify_proof(&path, &pubkey),
+        Command::Doctor { json } => cmd_doctor(config, json).await,
+        Command::Bench {
+            nonces,
+            iterations,
+            json,
+        } => cmd_bench(config, nonces, iterations, json),
+        #[cfg(feature = "testing")]
+        Command::SimulateLoad(args) => cmd_simulate_load(config, &args),
+        #[cfg(feature = "testing")]
+        Command::CheckInvariants {
+            seeds,
+            seed,
+            steps,
+            schedule,
+            output,
+            json,
+        } => cmd_check_invariants(
+            config,
+            seeds,
+            seed,
+            steps,
+            schedule.as_deref(),
+            &output,
+            json,
+        ),
+        #[cfg(feature = "testing")]
+        Command::E2e(args) => cmd_e2e(config, args).await,
+        Command::RotateKey => cmd_rotate_key(config, &sources).await,
+        Command::Report(args) => cmd_report(config, &sources, &args),
+        Command::Config { .. } | Command::EncryptKeypair { .. } => {
+            unreachable!("handled before the sources are read")
+        }
+        command @ (Command::Run { .. }
+        | Command::Reconcile(_)
+        | Command::EnrichReceipts { .. }
+        | Command::CloseReceipts { .. }
+        | Command::ImportHistory { .. }
+        | Command::Status { live: true, .. }
+        | Command::ReplayBatch(_)
+        | Command::ShadowCompare { .. }) => {
+            run_relayers(config, &sources, command, &telemetry, &config_file).await
+        }
+    }
+}
+
+/// Runs `command` on the relayers of `sources`: relaying, or a command that
+/// reads both clusters; an audit in `audit_mode`
+async fn run_relayers(
+    config: &RelayerConfig,
+    sources: &[SourceConfig],
+    command: Command,
+    telemetry: &TelemetryGuard,
+    config_file: &ConfigFile,
+) -> Result<()> {
+    if config.process_role == ProcessRole::Observer {
+        let Command::Run { once, .. } = command else {
+            return Err(anyhow::anyhow!(
+                "process_role = \"observer\" only supports the run command"
+            ));
+        };
+        let observers = sources
+            .iter()
+            .map(|source| Observer::new(config, source, source_state(config, source)?))
+            .collect::<Result<Vec<_>>>()
+            .context(ExitCategory::Config)?;
+        return observer::run_observers(&observers, once).await;
+    }
+
+    tracing::info!("Initializing relayer...");
+    let relayers = init_relayers(config, sources, &SharedPools::default()).await?;
+    tracing::info!(
+        "Relayer initialized successfully ({} source(s))",
+        relayers.len()
+    );
+
+    if config.audit_mode {
+        return cmd_audit(config, &relayers).await;
+    }
+
+    match command {
+        Command::Run { once: true, .. } => cmd_run_once(&relayers).await,
+        Command::Run { once: false, .. } => {
+            cmd_run(config, sources, &relayers, telemetry, config_file).await
+        }
+        Command::Reconcile(args) => cmd_reconcile(config, sources, &relayers, args).await,
+        Command::EnrichReceipts { source } => {
+            cmd_enrich_receipts(sources, &relayers, source.as_deref()).await
+        }
+        Command::CloseReceipts { dry_run, source } => {
+            cmd_close_receipts(sources, &relayers, dry_run, source.as_deref()).await
+        }
+        Command::ImportHistory {
+            address,
+            restart,
+            rps,
+            source,
+        } => {
+            cmd_import_history(
+                sources,
+                &relayers,
+                address.as_deref(),
+                restart,
+                rps,
+                source.as_deref(),
+            )
+            .await
+        }
+        Command::Status { source, live: true } => {
+            cmd_live_status(config, sources, &relayers, source.as_deref()).await
+        }
+        Command::ReplayBatch(args) => cmd_replay_batch(sources, &relayers, args).await,
+        Command::ShadowCompare {
+            against,
+            output,
+            source,
+        } => {
+            cmd_shadow_compare(
+                sources,
+                &relayers,
+                against.as_deref(),
+                &output,
+                source.as_deref(),
+            )
+            .await
+        }
+        _ => unreachable!("handled before relayer initialization"),
+    }
+}
+
+/// Relayer of the selected source
+fn select_relayer<'a>(
+    sources: &[SourceConfig],
+    relayers: &'a [Relayer],
+    source: Option<&str>,
+) -> Result<&'a Relayer> {
+    let source = select_source(sources, source)?;
+    Ok(relayers
+        .iter()
+        .find(|relayer| relayer.source_id == source.id)
+        .expect("one relayer per source"))
+}
+
+/// Prints the effective configuration or checks it, bridge by bridge
+fn cmd_config(config: &RelayerConfig, command: &ConfigCommand) -> Result<()> {
+    match command {
+        ConfigCommand::Show => {
+            output!("{}", config.to_redacted_json()?);
+        }
+        ConfigCommand::Validate => {
+            if config.bridges.is_empty() {
+                validate_config(config).context(ExitCategory::Config)?;
+            }
+            for bridge in &config.bridges {
+                output!("\nBridge {}:", bridge.bridge.as_deref().unwrap_or_default());
+                validate_config(bridge).context(ExitCategory::Config)?;
+            }
+            output!("Configuration is valid");
+        }
+    }
+    Ok(())
+}
+
+/// Runs `command` on every `[[bridges]]` entry; only relaying, the log level
+/// and the fee report span bridges
+async fn cmd_bridges(
+    config: &RelayerConfig,
+    command: Command,
+    telemetry: &TelemetryGuard,
+    config_file: &ConfigFile,
+) -> Result<()> {
+    match command {
+        Command::Run { once, .. } if !config.audit_mode => {
+            run_bridges(config, once, telemetry, config_file).await
+        }
+        Command::LogLevel { filter, reset } => {
+            for bridge in &config.bridges {
+                cmd_log_level(bridge, &bridge.sources()?, filter.as_deref(), reset)?;
+            }
+            Ok(())
+        }
+        Command::Report(ReportArgs {
+            fees: true,
+            from,
+            to,
+            json,
+            output,
+            source,
+            ..
+        }) => {
+            let bridges: Vec<&RelayerConfig> = config.bridges.iter().collect();
+            let report = fee_report(&bridges, source.as_deref(), &from, &to)?;
+            let rendered = if json {
+                report.to_json()?
+            } else {
+                report.to_table()
+            };
+            write_report(output.as_deref(), rendered)
+        }
+        _ => Err(anyhow::anyhow!(
+            "{} bridges are configured, pick one with --bridge ({})",
+            config.bridges.len(),
+            config.bridge_names().join(", ")
+        )),
+    }
+}
+
+/// Encrypts the keypair file `input` to `output`
+fn cmd_encrypt_keypair(config: &RelayerConfig, input: &str, output: &str) -> Result<()> {
+    let pubkey = signing::encrypt_keypair_file(input, output, &config.keypair_passphrase_env)?;
+    output!("Encrypted keypair {} to {}", pubkey, output);
+    Ok(())
+}
+
+/// Resumes the tripped anomaly circuit breakers of the selected sources
+fn cmd_resume_breaker(
+    config: &RelayerConfig,
+    sources: &[SourceConfig],
+    source: Option<&str>,
+) -> Result<()> {
+    for candidate in sources {
+        if source.is_some_and(|source| source != candidate.id) {
+            continue;
+        }
+        if anomaly::resume(&source_state(config, candidate)?)? {
+            output!(
+                "Resumed the anomaly circuit breaker of source {}",
+                candidate.id
+            );
+        } else {
+            output!(
+                "Anomaly circuit breaker of source {} is not tripped",
+                candidate.id
+            );
+        }
+    }
+    if let Some(source) = source {
+        select_source(sources, Some(source))?;
+    }
+    Ok(())
+}
+
+/// Confirms a nonce jump of the selected source up to `to`
+fn cmd_confirm_jump(
+    config: &RelayerConfig,
+    sources: &[SourceConfig],
+    to: u64,
+    source: Option<&str>,
+) -> Result<()> {
+    let source = select_source(sources, source)?;
+    source_state(config, source)?.put(
+        JUMP_CONFIRMATION_KEY,
+        &JumpConfirmation { confirmed_to: to },
+    )?;
+    output!("Confirmed nonce jump of source {} up to {}", source.id, to);
+    Ok(())
+}
+
+/// Overrides the throughput limit of a source, or the one shared by all
+/// sources, or restores the configured one
+fn cmd_throttle(
+    config: &RelayerConfig,
+    sources: &[SourceConfig],
+    per_minute: Option<u64>,
+    reset: bool,
+    source: Option<&str>,
+) -> Result<()> {
+    let limit = (!reset).then(|| LimitOverride {
+        per_minute: per_minute.unwrap_or_default(),
+    });
+    // 全局限制写入每个 source 的状态，各 relayer 都能读到
+    let (key, selected, scope) = match source {
+        Some(id) => {
+            let source = select_source(sources, Some(id))?;
+            (
+                SOURCE_LIMIT_KEY,
+                vec![source],
+                format!("source {}", source.id),
+            )
+        }
+        None => (
+            GLOBAL_LIMIT_KEY,
+            sources.iter().collect(),
+            "all sources".to_string(),
+        ),
+    };
+    for source in selected {
+        let state = source_state(config, source)?;
+        match &limit {
+            Some(limit) => state.put(key, limit)?,
+            None => state.remove(key)?,
+        }
+    }
+    match limit {
+        Some(LimitOverride { per_minute: 0 }) => {
+            output!("Lifted the throughput limit of {}", scope)
+        }
+        Some(LimitOverride { per_minute }) => {
+            output!("Limited {} to {} transfers per minute", scope, per_minute)
+        }
+        None => output!("Restored the configured throughput limit of {}", scope),
+    }
+    Ok(())
+}
+
+/// Promotes the standbys of `sources` to active, or drops the promotion
+fn cmd_promote(config: &RelayerConfig, sources: &[SourceConfig], reset: bool) -> Result<()> {
+    for source in sources {
+        let state = source_state(config, source)?;
+        if reset {
+            state.remove(ROLE_KEY)?;
+        } else {
+            state.put(ROLE_KEY, &Role::Active)?;
+        }
+    }
+    if reset {
+        output!(
+            "Removed the promotion of {} source(s); the configured role applies from the next start",
+            sources.len()
+        );
+    } else {
+        output!(
+            "Promoted {} source(s) to active; running standbys start submitting before their next check",
+            sources.len()
+        );
+    }
+    Ok(())
+}
+
+/// Lists the transfers of the selected sources awaiting approval
+fn cmd_approvals(
+    config: &RelayerConfig,
+    sources: &[SourceConfig],
+    source: Option<&str>,
+    json: bool,
+) -> Result<()> {
+    let policy = approval_policy(config)?
+        .ok_or_else(|| anyhow::anyhow!("manual_approval_threshold is not configured"))?;
+    let selected = match source {
+        Some(id) => vec![select_source(sources, Some(id))?],
+        None => sources.iter().collect(),
+    };
+    let mut listed = Vec::new();
+    for source in selected {
+        let pending = policy.pending(
+            &source_state(config, source)?,
+            &approval_scope(config, source),
+        )?;
+        if !json {
+            output!(
+                "\nSource {}: {} transfer(s) awaiting approval",
+                source.id,
+                pending.len()
+            );
+            for pending in &pending {
+                let request = &pending.request;
+                output!(
+                    "- nonce {}: {} lamports to {}, requested at {}, {} of {} approvals{}",
+                    request.nonce,
+                    request.amount,
+                    request.to,
+                    request.requested_at,
+                    pending.approved_by.len(),
+                    pending.required,
+                    if pending.approved_by.is_empty() {
+                        String::new()
+                    } else {
+                        format!(" ({})", pending.approved_by.join(", "))
+                    }
+                );
+                output!("    message: {}", pending.message);
+            }
+        }
+        listed.push(serde_json::json!({ "source": source.id, "pending": pending }));
+    }
+    if json {
+        output!("{}", serde_json::to_string_pretty(&listed)?);
+    }
+    Ok(())
+}
+
+/// Signs the approval of a parked transfer with the keypair at `keypair`
+fn cmd_approve(
+    config: &RelayerConfig,
+    sources: &[SourceConfig],
+    nonce: u64,
+    keypair: &str,
+    source: Option<&str>,
+) -> Result<()> {
+    let source = select_source(sources, source)?;
+    let policy = approval_policy(config)?
+        .ok_or_else(|| anyhow::anyhow!("manual_approval_threshold is not configured"))?;
+    let approver = read_keypair_path(keypair, &config.keypair_passphrase_env)
+        .map_err(|e| anyhow::anyhow!("Failed to read keypair file {}: {}", keypair, e))?;
+    let (request, approvals) = policy.approve(
+        &source_state(config, source)?,
+        &approval_scope(config, source),
+        nonce,
+        &approver,
+        SystemClock.unix_timestamp(),
+    )?;
+    output!(
+        "Approved nonce {} of source {} ({} lamports to {}) as {}: {} of {} approvals",
+        request.nonce,
+        source.id,
+        request.amount,
+        request.to,
+        approver.pubkey(),
+        approvals,
+        policy.required
+    );
+    Ok(())
+}
+
+/// Cancels a transfer held in its recipient's quiet period
+fn cmd_cancel(
+    config: &RelayerConfig,
+    sources: &[SourceConfig],
+    nonce: u64,
+    reason: &str,
+    source: Option<&str>,
+) -> Result<()> {
+    let source = select_source(sources, source)?;
+    if config.new_recipient_quiet_period_secs.is_none() {
+        return Err(anyhow::anyhow!(
+            "new_recipient_quiet_period_secs is not configured"
+        ));
+    }
+    let hold = quiet_period::cancel(
+        &source_state(config, source)?,
+        nonce,
+        reason,
+        SystemClock.unix_timestamp(),
+    )?;
+    output!(
+        "Cancelling nonce {} of source {} ({} lamports to {}); running relayers record it as cancelled before relaying",
+        hold.nonce, source.id, hold.amount, hold.to
+    );
+    Ok(())
+}
+
+/// Prints the pending queues of the selected sources from their state
+fn cmd_status(
+    config: &RelayerConfig,
+    sources: &[SourceConfig],
+    source: Option<&str>,
+) -> Result<()> {
+    let selected = match source {
+        Some(id) => vec![select_source(sources, Some(id))?],
+        None => sources.iter().collect(),
+    };
+    for source in selected {
+        print_status(config, source)?;
+    }
+    Ok(())
+}
+
+/// Relays a dead-lettered or backing-off nonce again from the next start
+fn cmd_replay(
+    config: &RelayerConfig,
+    sources: &[SourceConfig],
+    nonce: u64,
+    by: Option<String>,
+    source: Option<&str>,
+) -> Result<()> {
+    let source = select_source(sources, source)?;
+    let state = source_state(config, source)?;
+    let queue = PendingQueue::load(&state, config.queue_capacity, config.queue_overflow)?;
+    let dead_letters = DeadLetterQueue::load(&state)?;
+    let by = by
+        .or_else(|| std::env::var("USER").ok())
+        .unwrap_or_else(|| "unknown".to_string());
+    let replay = replay_nonce(
+        &state,
+        &queue,
+        &dead_letters,
+        nonce,
+        &by,
+        SystemClock.unix_timestamp(),
+        "replay command",
+    )?;
+    match replay {
+        Ok(DEAD_LETTER_RESOLVED) => output!(
+            "Dead letter {} resolved as replayed; the relayer sends it again before its next batch",
+            nonce
+        ),
+        // 运行中的 relayer 在内存中保留退避状态，会覆盖这里的修改
+        Ok(_) => output!(
+            "Backoff of nonce {} cleared for the next start; a running relayer keeps its own, use POST /replay/{} of the admin API for it",
+            nonce, nonce
+        ),
+        Err(reason) => return Err(anyhow::anyhow!(reason)),
+    }
+    Ok(())
+}
+
+/// Archives the receipts of the selected sources older than `older_than`
+fn cmd_compact(
+    config: &RelayerConfig,
+    sources: &[SourceConfig],
+    older_than: Duration,
+    source: Option<&str>,
+) -> Result<()> {
+    let selected = match source {
+        Some(id) => vec![select_source(sources, Some(id))?],
+        None => sources.iter().collect(),
+    };
+    for source in selected {
+        compact_source(config, source, older_than)?;
+    }
+    Ok(())
+}
+
+/// Prints the reconciliation snapshots of the selected source from `from`
+fn cmd_snapshots(
+    config: &RelayerConfig,
+    sources: &[SourceConfig],
+    from: u64,
+    source: Option<&str>,
+) -> Result<()> {
+    let source = select_source(sources, source)?;
+    for snapshot in MetricsSnapshot::list(&source_state(config, source)?, from)? {
+        output!("{}", serde_json::to_string(&snapshot)?);
+    }
+    Ok(())
+}
+
+/// Prints or follows the decision journal of the selected source, or
+/// reconstructs its pending queue
+async fn cmd_journal(
+    config: &RelayerConfig,
+    sources: &[SourceConfig],
+    command: &JournalCommand,
+) -> Result<()> {
+    let (JournalCommand::Tail { source, .. } | JournalCommand::Replay { source, .. }) = command;
+    let source = select_source(sources, source.as_deref())?;
+    let path = journal_path(config, &source.id)
+        .ok_or_else(|| anyhow::anyhow!("journal_path is not configured"))?;
+    match command {
+        JournalCommand::Tail {
+            lines,
+            follow,
+            filters,
+            json,
+            ..
+        } => {
+            let color =
+                !*json && std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+            let print = |record: &JournalRecord| -> Result<()> {
+                if filters.iter().all(|filter| filter.matches(record)) {
+                    if *json {
+                        output!("{}", serde_json::to_string(record)?);
+                    } else {
+                        output!("{}", record.to_text(color));
+                    }
+                }
+                Ok(())
+            };
+            // 先读当前文件再跟随其后续写入，中间不漏记录
+            let mut follower = JournalFollower::new(&path);
+            let mut records = Journal::read_rotated(&path)?;
+            records.extend(follower.poll()?);
+            records.retain(|record| filters.iter().all(|filter| filter.matches(record)));
+            for record in &records[records.len().saturating_sub(*lines)..] {
+                print(record)?;
+            }
+            if *follow {
+                loop {
+                    tokio::time::sleep(FOLLOW_INTERVAL).await;
+                    for record in follower.poll()? {
+                        print(&record)?;
+                    }
+                }
+            }
+        }
+        JournalCommand::Replay { from, .. } => {
+            let records = Journal::read(&path)?;
+            output_text!("{}", ReplayedState::replay(&records, *from).to_text());
+        }
+    }
+    Ok(())
+}
+
+/// Hex-dumps the account `pubkey` of `cluster` and decodes it
+async fn cmd_decode_account(config: &RelayerConfig, pubkey: &str, cluster: Cluster) -> Result<()> {
+    let client = match cluster {
+        Cluster::L1 => rpc_pool::pool(config, "L1", config.l1_endpoints()),
+        Cluster::L2 => rpc_pool::pool(config, "L2", config.l2_endpoints()),
+    }
+    .client(CommitmentConfig::confirmed());
+    let address = Pubkey::from_str(pubkey)
+        .map_err(|e| anyhow::anyhow!("Invalid account address {}: {}", pubkey, e))?;
+    let account = client
+        .get_account_with_commitment(&address, client.commitment())
+        .await?
+        .value
+        .ok_or_else(|| anyhow::anyhow!("Account {} not found on {:?}", address, cluster))?;
+    output!(
+        "Account {} on {:?}: owner {}, {} lamports, {} bytes\n",
+        address,
+        cluster,
+        account.owner,
+        account.lamports,
+        account.data.len()
+    );
+    output_text!("{}", decode::render(&account.data));
+    Ok(())
+}
+
+/// Reads and decodes the transfer-info PDA of `nonce`
+async fn cmd_decode_pda(
+    config: &RelayerConfig,
+    sources: &[SourceConfig],
+    nonce: u64,
+    source: Option<&str>,
+) -> Result<()> {
+    if config.message_source != MessageSource::Pda {
+        return Err(anyhow::anyhow!(
+            "decode-pda reads transfer-info PDAs, but message_source is {:?}",
+            config.message_source
+        ));
+    }
+    let source = select_source(sources, source)?;
+    decode_pda(config, source, nonce).await
+}
+
+/// Checks the relay proof in the file at `path` against the relayer `pubkey`
+fn cmd_verify_proof(path: &Path, pubkey: &str) -> Result<()> {
+    let relayer = Pubkey::from_str(pubkey)
+        .map_err(|e| anyhow::anyhow!("Invalid relayer pubkey {}: {}", pubkey, e))?;
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+    let mut value: serde_json::Value = serde_json::from_str(&text)
+        .map_err(|e| anyhow::anyhow!("Invalid JSON in {}: {}", path.display(), e))?;
+    // 回执文件中的证明位于 proof 字段
+    if let Some(proof) = value.get_mut("proof") {
+        value = proof.take();
+    }
+    let proof: RelayProof = serde_json::from_value(value)
+        .map_err(|e| anyhow::anyhow!("No relay proof in {}: {}", path.display(), e))?;
+    proof.verify(&relayer)?;
+    output!(
+        "Valid relay proof: nonce {} of source account {} relayed {} lamports to {} in {} (slot {}), signed by {}",
+        proof.nonce,
+        proof.source_account,
+        proof.amount,
+        proof.recipient,
+        proof.l2_signature,
+        proof.slot,
+        proof.signer
+    );
+    Ok(())
+}
+
+/// Diagnoses the configuration; exits with the preflight code if a check fails
+async fn cmd_doctor(config: &RelayerConfig, json: bool) -> Result<()> {
+    let report = doctor::diagnose(config).await;
+    if json {
+        output!("{}", serde_json::to_string(&report)?);
+    } else {
+        let color = std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+        output_text!("\n{}", report.to_text(color));
+    }
+    if report.count(doctor::CheckStatus::Fail) > 0 {
+        // 诊断失败不是崩溃，不写崩溃报告
+        std::process::exit(ExitCategory::Preflight.code().into());
+    }
+    Ok(())
+}
+
+/// Measures the CPU cost of the relay path on this host
+fn cmd_bench(config: &RelayerConfig, nonces: u64, iterations: u64, json: bool) -> Result<()> {
+    let results = bench::run(config, nonces, iterations)?;
+    if json {
+        output!("{}", serde_json::to_string_pretty(&results)?);
+    } else {
+        output_text!("{}", bench::to_text(&results));
+    }
+    Ok(())
+}
+
+/// Relays synthetic traffic through the fakes and prints the load report
+#[cfg(feature = "testing")]
+fn cmd_simulate_load(config: &RelayerConfig, args: &SimulateLoadArgs) -> Result<()> {
+    let profile = simulate::LoadProfile {
+        transfers: args.transfers,
+        rate: args.rate,
+        arrivals: args.arrivals,
+        amount_min: args.amount_min,
+        amount_max: args.amount_max,
+        amounts: args.amounts,
+        token_ratio: args.token_ratio,
+        nft_ratio: args.nft_ratio,
+        unknown_ratio: args.unknown_ratio,
+        send_failure_rate: args.send_failure_rate,
+        drop_rate: args.drop_rate,
+        prioritization_fee: args.prioritization_fee,
+        drain_timeout: args.drain_timeout,
+        seed: args.seed,
+    };
+    let report = simulate::run(config, &profile)?;
+    if args.json {
+        output!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        output_text!("{}", report.to_text());
+    }
+    Ok(())
+}
+
+/// Checks the nonce-progression invariants on random schedules, or on the
+/// one at `schedule`; exits with the fatal code if one is broken
+#[cfg(feature = "testing")]
+fn cmd_check_invariants(
+    config: &RelayerConfig,
+    seeds: u64,
+    seed: u64,
+    steps: usize,
+    schedule: Option<&Path>,
+    output: &Path,
+    json: bool,
+) -> Result<()> {
+    let failed = match schedule {
+        Some(path) => {
+            let schedule: Vec<invariants::Step> = serde_json::from_slice(
+                &std::fs::read(path)
+                    .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?,
+            )?;
+            let violation = invariants::check(config, &schedule)?;
+            if json {
+                output!("{}", serde_json::to_string_pretty(&violation)?);
+            } else if let Some(violation) = &violation {
+                output!("{}: invariant broken {}", path.display(), violation);
+            } else {
+                output!("{}: all invariants hold", path.display());
+            }
+            violation.is_some()
+        }
+        None => {
+            let reports = invariants::run(config, seed, seeds, steps)?;
+            let failure = reports.iter().find(|report| report.violation.is_some());
+            if let Some(report) = failure {
+                std::fs::write(output, serde_json::to_vec_pretty(&report.shrunk)?)?;
+            }
+            if json {
+                output!("{}", serde_json::to_string_pretty(&reports)?);
+            } else {
+                for report in &reports {
+                    match &report.violation {
+                        None => output!(
+                            "Seed {}: {} steps, {} nonces, all invariants hold",
+                            report.seed,
+                            report.steps,
+                            report.nonces
+                        ),
+                        Some(violation) => {
+                            output!(
+                                "Seed {}: invariant broken {}; shrunk to {} steps:",
+                                report.seed,
+                                violation,
+                                report.shrunk.len()
+                            );
+                            for (index, step) in report.shrunk.iter().enumerate() {
+                                output!("  {}. {}", index, step);
+                            }
+                        }
+                    }
+                }
+            }
+            if let Some(report) = failure {
+                output!(
+                    "Minimal schedule of seed {} written to {}",
+                    report.seed,
+                    output.display()
+                );
+            }
+            failure.is_some()
+        }
+    };
+    if failed {
+        // 不变量被破坏不是崩溃，不写崩溃报告
+        std::process::exit(ExitCategory::Fatal.code().into());
+    }
+    Ok(())
+}
+
+/// Relays transfers through solana-test-validator; exits with the fatal code
+/// if they do not land
+#[cfg(feature = "testing")]
+async fn cmd_e2e(config: &RelayerConfig, args: E2eArgs) -> Result<()> {
+    let defaults = ValidatorOptions::default();
+    let options = E2eOptions {
+        validator: ValidatorOptions {
+            binary: args.validator,
+            rpc_port: args.rpc_port,
+            work_dir: args.work_dir.unwrap_or(defaults.work_dir),
+        },
+        l2_program: args.l2_program,
+        l1_program: args.l1_program,
+        transfers: args.transfers,
+        amount: args.amount,
+        two_validators: args.two_validators,
+        timeout: args.timeout,
+    };
+    let report = test_validator::run(config, &options).await?;
+    if args.json {
+        output!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        output_text!("{}", report.to_text());
+    }
+    if !report.passed {
+        std::process::exit(ExitCategory::Fatal.code().into());
+    }
+    Ok(())
+}
+
+/// Promotes the secondary signer of every source
+async fn cmd_rotate_key(config: &RelayerConfig, sources: &[SourceConfig]) -> Result<()> {
+    let stores = sources
+        .iter()
+        .map(|source| source_state(config, source))
+        .collect::<Result<Vec<_>>>()?;
+    let (primary, secondary) = read_signers(config)?;
+    let signers = Signers::with_signers(primary, secondary);
+    signers.refresh(&stores[0])?;
+    let l2_client = destination_client(config);
+    let rotation = signers
+        .rotate(&stores, &l2_client, SystemClock.unix_timestamp())
+        .await?;
+    output!(
+        "Rotated signer from {} to {} for {} source(s); running relayers switch before their next batch",
+        rotation.from,
+        rotation.to,
+        stores.len()
+    );
+    Ok(())
+}
+
+/// Writes the volume report of the selected source, or the fee report
+fn cmd_report(config: &RelayerConfig, sources: &[SourceConfig], args: &ReportArgs) -> Result<()> {
+    let rendered = if args.fees {
+        let report = fee_report(&[config], args.source.as_deref(), &args.from, &args.to)?;
+        if args.json {
+            report.to_json()?
+        } else {
+            report.to_table()
+        }
+    } else {
+        let source = select_source(sources, args.source.as_deref())?;
+        let since = match &args.from {
+            Some(from) => Some(fees::day_start(from)?),
+            None => args
+                .last
+                .map(|last| SystemClock.unix_timestamp().saturating_sub(last.as_secs())),
+        };
+        let until = args
+            .to
+            .as_deref()
+            .map(|to| fees::day_start(to).map(|start| start + 86_400))
+            .transpose()?;
+        let report = Report::build(
+            &source_state(config, source)?,
+            &Archive::new(archive_dir(config, &source.id)),
+            &source.id,
+            args.granularity,
+            since,
+            until,
+        )?;
+        if args.json {
+            report.to_json()?
+        } else {
+            report.to_table()
+        }
+    };
+    write_report(args.output.as_deref(), rendered)
+}
+
+/// Audits every relayer and writes its report; fails if a nonce failed
+async fn cmd_audit(config: &RelayerConfig, relayers: &[Relayer]) -> Result<()> {
+    let mut failed = 0;
+    for relayer in relayers {
+        let report = relayer.run_audit().await?;
+        let path = scoped_path(config, &config.audit_report_path, &relayer.source_id);
+        report.write(&path)?;
+        output!(
+            "\nAudit of source {} finished: {} passed, {} failed (report: {})",
+            relayer.source_id,
+            report.passed,
+            report.failed,
+            path.display()
+        );
+        if !report.all_passed() {
+            failed += report.failed;
+        }
+    }
+    if failed > 0 {
+        return Err(anyhow::anyhow!("Audit failed for {} nonce(s)", failed));
+    }
+    Ok(())
+}
+
+/// Runs one iteration of every relayer and prints its outcome
+async fn cmd_run_once(relayers: &[Relayer]) -> Result<()> {
+    for relayer in relayers {
+        let outcome = relayer.run_once().await?;
+        output!("{}", serde_json::to_string(&outcome)?);
+    }
+    Ok(())
+}
+
+/// Monitors and relays until shutdown
+async fn cmd_run(
+    config: &RelayerConfig,
+    sources: &[SourceConfig],
+    relayers: &[Relayer],
+    telemetry: &TelemetryGuard,
+    config_file: &ConfigFile,
+) -> Result<()> {
+    if config.startup_self_test {
+        bench::self_test(config, &sources[0])?;
+    }
+    tracing::info!("Starting monitoring...");
+    monitor_all(relayers, config, telemetry, config_file).await
+}
+
+/// Checks the selected source's L1 transfer PDAs against L2
+async fn cmd_reconcile(
+    config: &RelayerConfig,
+    sources: &[SourceConfig],
+    relayers: &[Relayer],
+    args: ReconcileArgs,
+) -> Result<()> {
+    if config.message_source == MessageSource::ProgramAccounts {
+        return Err(anyhow::anyhow!(
+            "Reconcile does not support the program_accounts message source"
+        ));
+    }
+    let relayer = select_relayer(sources, relayers, args.source.as_deref())?;
+    let store = &relayer.state;
+    if args.restart {
+        store.remove(FULL_SCAN_PROGRESS_KEY)?;
+    }
+
+    let (l1_watched_nonce, l2_nonce_status) = relayer.read_nonces().await?;
+    let (from, progress_key) = if args.full {
+        (config.reconcile_genesis_nonce, Some(FULL_SCAN_PROGRESS_KEY))
+    } else {
+        (l1_watched_nonce.saturating_sub(args.window), None)
+    };
+    let options = ScanOptions {
+        requests_per_second: args.rps,
+        output: args.output.clone(),
+        progress_key,
+    };
+
+    output!(
+        "\nReconciling nonces {}..{} of source {} ({})",
+        from,
+        l1_watched_nonce,
+        relayer.source_id,
+        if args.full {
+            "full scan"
+        } else {
+            "incremental"
+        }
+    );
+    let progress = Reconciler::new(
+        &relayer.l1_client,
+        &relayer.pda_manager,
+        relayer.clock.as_ref(),
+        l2_nonce_status,
+    )
+    .scan(store, from, l1_watched_nonce, &options)
+    .await?;
+    print_reconcile_summary(&progress, &args.output);
+    Ok(())
+}
+
+/// Adds the confirmed transaction's details to the selected source's receipts
+async fn cmd_enrich_receipts(
+    sources: &[SourceConfig],
+    relayers: &[Relayer],
+    source: Option<&str>,
+) -> Result<()> {
+    let relayer = select_relayer(sources, relayers, source)?;
+    let (enriched, failed) = relayer.backfill_receipts().await?;
+    output!(
+        "\nEnriched {} receipt(s) of source {}, {} failed",
+        enriched,
+        relayer.source_id,
+        failed
+    );
+    Ok(())
+}
+
+/// Closes the selected source's receipt PDAs outside the retention window
+async fn cmd_close_receipts(
+    sources: &[SourceConfig],
+    relayers: &[Relayer],
+    dry_run: bool,
+    source: Option<&str>,
+) -> Result<()> {
+    let relayer = select_relayer(sources, relayers, source)?;
+    let dry_run = dry_run || relayer.dry_run;
+    let summary = relayer.close_receipt_pdas(dry_run).await?;
+    output!(
+        "\n{} {} receipt PDA(s) of source {}, refunding {} lamports; checked {} nonce(s), every nonce below {} now",
+        if dry_run { "Would close" } else { "Closed" },
+        summary.closed,
+        relayer.source_id,
+        summary.refunded,
+        summary.checked,
+        summary.checked_below
+    );
+    Ok(())
+}
+
+/// Writes receipts of the selected source for relays in the L2 history of
+/// `address`, the L2 program by default
+async fn cmd_import_history(
+    sources: &[SourceConfig],
+    relayers: &[Relayer],
+    address: Option<&str>,
+    restart: bool,
+    rps: u32,
+    source: Option<&str>,
+) -> Result<()> {
+    let relayer = select_relayer(sources, relayers, source)?;
+    let address = match address {
+        Some(address) => Pubkey::from_str(address)
+            .map_err(|e| anyhow::anyhow!("Invalid address {}: {}", address, e))?,
+        None => *relayer.transaction_builder.program_id.pubkey(),
+    };
+    if restart {
+        relayer.state.remove(HISTORY_IMPORT_PROGRESS_KEY)?;
+    }
+    output!(
+        "\nImporting relays of {} from L2 history into source {}",
+        address,
+        relayer.source_id
+    );
+    let options = ImportOptions {
+        address,
+        requests_per_second: rps,
+    };
+    let progress = relayer.import_history(&options).await?;
+    print_import_summary(&progress);
+    Ok(())
+}
+
+/// Prints the pending queues of the selected sources with their nonces
+/// read from both clusters now
+async fn cmd_live_status(
+    config: &RelayerConfig,
+    sources: &[SourceConfig],
+    relayers: &[Relayer],
+    source: Option<&str>,
+) -> Result<()> {
+    let selected = match source {
+        Some(id) => vec![select_source(sources, Some(id))?],
+        None => sources.iter().collect(),
+    };
+    for source in selected {
+        print_status(config, source)?;
+        let relayer = select_relayer(sources, relayers, Some(&source.id))?;
+        let (l1_nonce, l2_nonce) = relayer.read_nonces().await?;
+        output!(
+            "L1 nonce {}, L2 nonce account expects {} ({} behind)",
+            l1_nonce,
+            l2_nonce,
+            l1_nonce.saturating_sub(l2_nonce)
+        );
+    }
+    Ok(())
+}
+
+/// Relays the selected source's dead-lettered or missing nonces again at a
+/// limited rate
+async fn cmd_replay_batch(
+    sources: &[SourceConfig],
+    relayers: &[Relayer],
+    args: ReplayBatchArgs,
+) -> Result<()> {
+    if args.rate.is_nan() || args.rate <= 0.0 {
+        return Err(anyhow::anyhow!("--rate must be above 0"));
+    }
+    let relayer = select_relayer(sources, relayers, args.source.as_deref())?;
+    if args.restart {
+        relayer.state.remove(REPLAY_BATCH_PROGRESS_KEY)?;
+    }
+    let options = ReplayOptions {
+        selection: ReplaySelection {
+            from: args.from,
+            to: args.to,
+            from_dlq: args.from_dlq,
+            filters: args.filters,
+        },
+        rate: args.rate,
+        by: args
+            .by
+            .or_else(|| std::env::var("USER").ok())
+            .unwrap_or_else(|| "unknown".to_string()),
+    };
+    output!("\nReplaying nonces of source {}", relayer.source_id);
+    let progress = relayer.replay_batch(&options).await?;
+    progress.write_summary(&args.output)?;
+    output!(
+        "\nReplay summary: {} relayed, {} skipped, {} failed of {} nonce(s); details in {}",
+        progress.relayed,
+        progress.skipped,
+        progress.failed,
+        progress.targets.len(),
+        args.output.display()
+    );
+    Ok(())
+}
+
+/// Plans the selected source's pending transactions and compares them with
+/// the plan at `against`; fails on a divergence
+async fn cmd_shadow_compare(
+    sources: &[SourceConfig],
+    relayers: &[Relayer],
+    against: Option<&str>,
+    output: &Path,
+    source: Option<&str>,
+) -> Result<()> {
+    let relayer = select_relayer(sources, relayers, source)?;
+    let plans = relayer.plan_transactions().await?;
+    shadow::write_plan(output, &plans)?;
+    let failed = plans.iter().filter(|plan| plan.error.is_some()).count();
+    output!(
+        "Planned {} nonce(s) of source {} ({} could not be built), written to {}",
+        plans.len(),
+        relayer.source_id,
+        failed,
+        output.display()
+    );
+    if let Some(against) = against {
+        let theirs = shadow::load_plan(against).await?;
+        let report = shadow::compare(&plans, &theirs);
+        for divergence in &report.divergences {
+            output!(
+                "Divergence at nonce {} in {}: ours {:?}, theirs {:?}",
+                divergence.nonce,
+                divergence.field,
+                divergence.ours,
+                divergence.theirs
+            );
+        }
+        if !report.only_ours.is_empty() {
+            output!("Only planned here: {:?}", report.only_ours);
+        }
+        if !report.only_theirs.is_empty() {
+            output!("Only planned by {}: {:?}", against, report.only_theirs);
+        }
+        output!(
+            "\nCompared {} nonce(s) with {}: {} divergence(s)",
+            report.compared,
+            against,
+            report.divergences.len()
+        );
+        if !report.divergences.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Shadow comparison found {} divergence(s)",
+                report.divergences.len()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Sets the log filter of running relayers of `sources`, or restores the
+/// configured levels when `filter` is `None` or `reset` is set
+fn cmd_log_level(
+    config: &RelayerConfig,
+    sources: &[SourceConfig],
+    filter: Option<&str>,
+    reset: bool,
+) -> Result<()> {
+    let filter = match filter {
+        Some(filter) if !reset => {
+            telemetry::parse_filter(filter)?;
+            Some(filter)
+        }
+        _ => None,
+    };
+    for source in sources {
+        let state = source_state(config, source)?;
+        match filter {
+            Some(filter) => state.put(LOG_LEVEL_KEY, &filter)?,
+            None => state.remove(LOG_LEVEL_KEY)?,
+        }
+    }
+    match filter {
+        Some(filter) => output!("Running relayers log with filter {}", filter),
+        None => output!("Running relayers log with the configured levels again"),
+    }
+    Ok(())
+}
+
+/// Creates the relayers of `sources`, sharing `config`'s global throughput
+/// and submission limits
+async fn init_relayers(
+    config: &RelayerConfig,
+    sources: &[SourceConfig],
+    pools: &SharedPools,
+) -> Result<Vec<Relayer>> {
+    let mut relayers: Vec<Relayer> = Vec::with_capacity(sources.len());
+    let global_limit = Arc::new(LeakyBucket::new(
+        config.max_transfers_per_minute,
+        config.transfer_rate_burst,
+    ));
+    for source in sources {
+        let mut relayer = Relayer::new(config, source, pools)
+            .await
+            .context(ExitCategory::Preflight)?;
+        relayer.throughput.share_global(&global_limit);
+        // 其余 source 共用第一个 relayer 的提交许可和发送限速
+        if let Some(first) = relayers.first() {
+            relayer.submission_slots = first.submission_slots.clone();
+            relayer.send_rate = first.send_rate.clone();
+        }
+        relayers.push(relayer);
+    }
+    Ok(relayers)
+}
+
+/// Runs every relayer until all have stopped, writing their metrics to
+/// `config`'s textfile, following the runtime log filter and reloading
+/// `config_file` on SIGHUP meanwhile
+async fn monitor_all(
+    relayers: &[Relayer],
+    config: &RelayerConfig,
+    telemetry: &TelemetryGuard,
+    config_file: &ConfigFile,
+) -> Result<()> {
+    let registry = MetricsRegistry::new(relayers.iter().map(|r| r.metrics.clone()).collect());
+    let stopped: Vec<AtomicBool> = relayers.iter().map(|_| AtomicBool::new(false)).collect();
+    // 各 source 独立运行，一个 source 出错不影响其他 source
+    let relaying = futures::future::join_all(relayers.iter().zip(&stopped).map(
+        |(relayer, stopped)| async move {
+            let result = relayer.monitor_and_relay().await;
+            stopped.store(true, Ordering::SeqCst);
+            if let Err(e) = &result {
+                alerts::raise(&format!("{} stopped: {}", relayer.name(), e));
+            }
+            result
+        },
+    ));
+    let results = tokio::select! {
+        results = relaying => results,
+        result = write_metrics(
+            &registry,
+            relayers[0].clock.as_ref(),
+            config.metrics_textfile.as_deref(),
+        ) => {
+            return result;
+        }
+        result = serve_metrics(&registry, config.metrics_listen.as_deref()) => {
+            return result;
+        }
+        result = serve_admin(relayers, &stopped, config, telemetry) => {
+            return result;
+        }
+        () = telemetry.follow_log_level(&relayers[0].state, relayers[0].clock.as_ref()) => {
+            unreachable!("following the log filter never completes")
+        }
+        () = stop_on_signal(relayers) => {
+            unreachable!("stopping on a signal never completes")
+        }
+        () = reload::reload_on_hangup(relayers, config, config_file) => {
+            unreachable!("reloading on SIGHUP never completes")
+        }
+    };
+    // 以第一个出错的 source 的错误决定退出码
+    let mut errors = results.into_iter().filter_map(Result::err);
+    if let Some(first) = errors.next() {
+        let failed = 1 + errors.count();
+        return Err(first.context(format!("{} source(s) stopped with an error", failed)));
+    }
+    Ok(())
+}
+
+/// Asks every relayer to drain and stop on the first SIGINT or SIGTERM
+async fn stop_on_signal(relayers: &[Relayer]) {
+    match shutdown::signalled().await {
+        Ok(signal) => {
+            tracing::info!("Received {}, draining in-flight transactions...", signal);
+            for relayer in relayers {
+                relayer.shutdown.begin();
+            }
+        }
+        Err(e) => tracing::warn!("{}; signals stop the relayer without draining", e),
+    }
+    futures::future::pending().await
+}
+
+/// Relays every `[[bridges]]` entry side by side. Each bridge has its own
+/// relayers, state namespace and metric labels; a bridge that fails to start
+/// or stops with an error is alerted and leaves the others running
+async fn run_bridges(
+    config: &RelayerConfig,
+    once: bool,
+    telemetry: &TelemetryGuard,
+    config_file: &ConfigFile,
+) -> Result<()> {
+    if config.process_role == ProcessRole::Observer {
+        let mut observers = Vec::new();
+        for bridge in &config.bridges {
+            for source in bridge.sources().context(ExitCategory::Config)? {
+                observers.push(
+                    Observer::new(bridge, &source, source_state(bridge, &source)?)
+                        .context(ExitCategory::Config)?,
+                );
+            }
+        }
+        return observer::run_observers(&observers, once).await;
+    }
+    tracing::info!("Initializing {} bridge(s)...", config.bridges.len());
+    let mut relayers = Vec::new();
+    let mut failed = Vec::new();
+    // 端点相同的 bridge 共用 RPC 连接池
+    let pools = SharedPools::default();
+    for bridge in &config.bridges {
+        let name = bridge.bridge.as_deref().unwrap_or_default();
+        let started = async {
+            let sources = bridge.sources().context(ExitCategory::Config)?;
+            let bridge_relayers = init_relayers(bridge, &sources, &pools).await?;
+            if !once && bridge.startup_self_test {
+                bench::self_test(bridge, &sources[0])?;
+            }
+            Ok::<_, anyhow::Error>(bridge_relayers)
+        };
+        match started.await {
+            Ok(bridge_relayers) => {
+                tracing::info!(
+                    "Bridge {} initialized ({} source(s))",
+                    name,
+                    bridge_relayers.len()
+                );
+                relayers.extend(bridge_relayers);
+            }
+            Err(e) => {
+                alerts::raise(&format!("Bridge {} failed to start: {:#}", name, e));
+                failed.push(e.context(format!("Bridge {} failed to start", name)));
+            }
+        }
+    }
+    if relayers.is_empty() {
+        return Err(failed.remove(0));
+    }
+
+    if once {
+        for relayer in &relayers {
+            let outcome = relayer.run_once().await?;
+            output!("{}", serde_json::to_string(&outcome)?);
+        }
+    } else {
+        tracing::info!("Starting monitoring...");
+        monitor_all(&relayers, config, telemetry, config_file).await?;
+    }
+    match failed.into_iter().next() {
+        Some(first) => Err(first),
+        None => Ok(()),
+    }
+}
+
+/// State store of `source`, namespaced by its bridge's name and, when
+/// `[[sources]]` are configured, by its id
+fn source_state(config: &RelayerConfig, source: &SourceConfig) -> Result<StateStore> {
+    let mut store = StateStore::open(&config.state_dir)?;
+    if let Some(bridge) = &config.bridge {
+        store = store.scoped(bridge);
+    }
+    Ok(if config.namespaced_sources() {
+        store.scoped(&source.id)
+    } else {
+        store
+    })
+}
+
+/// The fee ledgers of every source of `configs` (only those named `source`,
+/// when given) from `from` to `to`
+fn fee_report(
+    configs: &[&RelayerConfig],
+    source: Option<&str>,
+    from: &Option<String>,
+    to: &Option<String>,
+) -> Result<FeeReport> {
+    let mut report = FeeReport::new(from.clone(), to.clone());
+    for config in configs {
+        for candidate in config.sources()? {
+            if source.is_some_and(|source| source != candidate.id) {
+                continue;
+            }
+            report.add_source(
+                config.bridge.as_deref(),
+                &candidate.id,
+                &source_state(config, &candidate)?,
+            )?;
+        }
+    }
+    if let (Some(source), true) = (source, report.sources.is_empty()) {
+        return Err(anyhow::anyhow!("Unknown source {}", source));
+    }
+    Ok(report)
+}
+
+/// Writes a rendered report to `output`, or prints it
+fn write_report(output: Option<&std::path::Path>, rendered: String) -> Result<()> {
+    match output {
+        Some(path) => {
+            std::fs::write(path, rendered)
+                .map_err(|e| anyhow::anyhow!("Failed to write report {}: {}", path.display(), e))?;
+            output!("Report written to {}", path.display());
+        }
+        None => output!("\n{}", rendered),
+    }
+    Ok(())
+}
+
+/// Archives the receipts of `source` that are outside the replay window and
+/// older than `older_than`
+fn compact_source(
+    config: &RelayerConfig,
+    source: &SourceConfig,
+    older_than: Duration,
+) -> Result<()> {
+    let state = source_state(config, source)?;
+    let completed =
+        PendingQueue::load(&state, config.queue_capacity, config.queue_overflow)?.completed();
+    let nonce_floor = config
+        .replay_window_nonces
+        .map_or(completed, |nonces| completed.saturating_sub(nonces));
+    let retention = config.replay_window_days.map_or(older_than, |days| {
+        older_than.max(Duration::from_secs(days * 86_400))
+    });
+    let cutoff = SystemClock
+        .unix_timestamp()
+        .saturating_sub(retention.as_secs());
+
+    output!("Compacting receipts of source {}...", source.id);
+    let report = archive::compact(
+        &state,
+        &Archive::new(archive_dir(config, &source.id)),
+        nonce_floor,
+        cutoff,
+    )?;
+    output!(
+        "Archived {} receipt(s) below nonce {} relayed before {} in {} month(s)",
+        report.archived,
+        report.nonce_floor,
+        report.cutoff,
+        report.months.len()
+    );
+    if report.awaiting_enrichment > 0 {
+        output!(
+            "Kept {} older receipt(s) that still wait for enrichment; run enrich-receipts first",
+            report.awaiting_enrichment
+        );
+    }
+    Ok(())
+}
+
+/// Ships the receipts of the selected sources the sink has not received
+async fn cmd_sink(
+    config: &RelayerConfig,
+    sources: &[SourceConfig],
+    command: &SinkCommand,
+) -> Result<()> {
+    let SinkCommand::Backfill { from, source } = command;
+    let sink_config = config
+        .receipt_sink
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("No [receipt_sink] is configured"))?;
+    let selected = match source {
+        Some(id) => vec![select_source(sources, Some(id))?],
+        None => sources.iter().collect(),
+    };
+    for source in selected {
+        let state = source_state(config, source)?;
+        let sink = ReceiptSink::new(sink_config, &source.id)?;
+        let shipped =
+            receipt_sink::backfill(&sink, &state, *from, SystemClock.unix_timestamp()).await?;
+        let watermark: SinkWatermark = state.get(RECEIPT_SINK_KEY)?.unwrap_or_default();
+        output!(
+            "Shipped {} receipt(s) of source {}; shipped up to nonce {}",
+            shipped,
+            source.id,
+            watermark
+                .shipped_up_to
+                .map_or("none".to_string(), |nonce| nonce.to_string())
+        );
+    }
+    Ok(())
+}
+
+/// Exports the state of a source to a bundle or replaces it with one
+async fn cmd_state(
+    config: &RelayerConfig,
+    sources: &[SourceConfig],
+    command: &StateCommand,
+) -> Result<()> {
+    match command {
+        StateCommand::Export { out, by, source } => {
+            let source = select_source(sources, source.as_deref())?;
+            let by = by
+                .clone()
+                .or_else(|| std::env::var("USER").ok())
+                .unwrap_or_else(|| "unknown".to_string());
+            output!(
+                "Exporting the state of source {}; running relayers hold their submissions meanwhile",
+                source.id
+            );
+            let manifest = state_bundle::export(
+                &source_state_dir(config, &source.id),
+                out,
+                &source.id,
+                config.bridge.as_deref(),
+                &by,
+                &SystemClock,
+            )
+            .await?;
+            output!(
+                "Wrote {} entries of source {} to {} (sha256 {})",
+                manifest.entries,
+                source.id,
+                out.display(),
+                manifest.sha256
+            );
+        }
+        StateCommand::Import {
+            bundle,
+            force,
+            source,
+        } => {
+            let source = select_source(sources, source.as_deref())?;
+            let report = state_bundle::import(
+                &source_state_dir(config, &source.id),
+                bundle,
+                &source.id,
+                *force,
+                SystemClock.unix_timestamp(),
+            )?;
+            output!(
+                "Imported {} entries exported by {} at {} into source {}",
+                report.manifest.entries,
+                report.manifest.exported_by,
+                report.manifest.created_at,
+                source.id
+            );
+            if let Some(backup) = report.backup {
+                output!("The previous state was moved to {}", backup.display());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Lists, annotates or resolves dead letters in the state directory; running
+/// relayers pick the changes up before their next batch
+fn cmd_dlq(config: &RelayerConfig, sources: &[SourceConfig], command: &DlqCommand) -> Result<()> {
+    let now = SystemClock.unix_timestamp();
+    let operator = |by: &Option<String>| {
+        by.clone()
+            .or_else(|| std::env::var("USER").ok())
+            .unwrap_or_else(|| "unknown".to_string())
+    };
+    match command {
+        DlqCommand::List { json, source } => {
+            let selected = match source {
+                Some(id) => vec![select_source(sources, Some(id))?],
+                None => sources.iter().collect(),
+            };
+            let mut listed = Vec::new();
+            for source in selected {
+                let letters = DeadLetterQueue::load(&source_state(config, source)?)?.entries();
+                if !*json {
+                    output!("\nSource {}: {} dead letter(s)", source.id, letters.len());
+                    for letter in &letters {
+                        output!("{}", letter.to_text(now));
+                    }
+                }
+                listed.push((source.id.clone(), letters));
+            }
+            if *json {
+                let entries: Vec<ListedDeadLetter> = listed
+                    .iter()
+                    .flat_map(|(source, letters)| {
+                        letters.iter().map(move |letter| ListedDeadLetter {
+                            source,
+                            age_secs: now.saturating_sub(letter.recorded_at),
+                            letter,
+                        })
+                    })
+                    .collect();
+                output!("{}", serde_json::to_string_pretty(&entries)?);
+            }
+        }
+        DlqCommand::Annotate {
+            nonce,
+            note,
+            by,
+            source,
+        } => {
+            let state = source_state(config, select_source(sources, source.as_deref())?)?;
+            let note = OperatorNote {
+                by: operator(by),
+                at: now,
+                note: note.clone(),
+            };
+            DeadLetterQueue::load(&state)?.annotate(&state, *nonce, note)?;
+            output!("Note added to dead letter {}", nonce);
+        }
+        DlqCommand::Resolve {
+            nonce,
+            resolution,
+            note,
+            by,
+            source,
+        } => {
+            let state = source_state(config, select_source(sources, source.as_deref())?)?;
+            let kind = *resolution;
+            let resolution = Resolution {
+                kind,
+                by: operator(by),
+                at: now,
+                note: note.clone(),
+                l2_signature: None,
+                applied: false,
+            };
+            DeadLetterQueue::load(&state)?.resolve(&state, *nonce, resolution)?;
+            match kind {
+                ResolutionKind::Replayed => output!(
+                    "Dead letter {} resolved as replayed; the relayer sends it again before its next batch",
+                    nonce
+                ),
+                kind => output!(
+                    "Dead letter {} resolved as {}; it will not be relayed",
+                    nonce, kind
+                ),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Prints the persisted pending queue of `source` without contacting any cluster
+fn print_status(config: &RelayerConfig, source: &SourceConfig) -> Result<()> {
+    let state = source_state(config, source)?;
+    let queue = PendingQueue::load(&state, config.queue_capacity, config.queue_overflow)?;
+    let dead_letters = DeadLetterQueue::load(&state)?;
+    let (queued, spilled) = queue.depth();
+    output!(
+        "\nSource {}: {} pending ({} spilled to disk), {} dead-lettered",
+        source.id,
+        queued + spilled,
+        spilled,
+        dead_letters.len()
+    );
+
+    let now = SystemClock.unix_timestamp();
+    let retries = queue.retries();
+    for (nonce, retry) in &retries {
+        let due = if retry.next_retry_at > now {
+            format!("in {}s", retry.next_retry_at - now)
+        } else {
+            "due".to_string()
+        };
+        output!(
+            "- Nonce {}: {} attempt(s), next retry at {} ({}), last error: {}",
+            nonce,
+            retry.attempts,
+            retry.next_retry_at,
+            due,
+            retry.last_error
+        );
+        for failure in &retry.failures {
+            output!("    {}", failure);
+        }
+    }
+    output!(
+        "{} other pending nonce(s) have not failed yet",
+        (queued + spilled).saturating_sub(retries.len() as u64)
+    );
+    if let Some(checkpoint) = state.get::<NonceCheckpoint>(NONCE_CHECKPOINT_KEY)? {
+        output!(
+            "Nonce checkpoint: next nonce {}, reached by {} (at {})",
+            checkpoint.next_nonce,
+            checkpoint.signature,
+            checkpoint.saved_at
+        );
+    }
+    if let Some(pause) = state.get::<AdminPause>(ADMIN_PAUSE_KEY)? {
+        output!(
+            "Submissions paused through the admin API by {} (at {})",
+            pause.by,
+            pause.at
+        );
+    }
+    if let Some(pause) = state.get::<L2PauseStatus>(L2_PAUSE_KEY)? {
+        output!(
+            "L2 program {} (checked at {})",
+            if pause.paused { "paused" } else { "not paused" },
+            pause.checked_at
+        );
+    }
+    if let Some(health) = state.get::<L2HealthStatus>(L2_HEALTH_KEY)? {
+        match &health.held {
+            Some(reason) => output!(
+                "L2 cluster unhealthy, submissions held since {}: {} (checked at {})",
+                health.held_since.unwrap_or(health.checked_at),
+                reason,
+                health.checked_at
+            ),
+            None => output!("L2 cluster healthy (checked at {})", health.checked_at),
+        }
+    }
+    if let Some(held) = state.get::<CapHeld>(L2_CAP_HOLD_KEY)? {
+        output!("Held for the L2 mint cap since {}: {}", held.since, held);
+    }
+    if let Some(trip) = state.get::<BreakerTrip>(ANOMALY_BREAKER_KEY)? {
+        output!(
+            "Held by the anomaly circuit breaker since {}: {}; resume with resume-breaker",
+            trip.at,
+            trip.reason
+        );
+    }
+    if let Some(scan) = state.get::<DiscoveryScan>(PDA_DISCOVERY_KEY)? {
+        output!(
+            "Last transfer-PDA discovery at {}: {} account(s), {} unrelayed, {} at or past the counter {}{}",
+            scan.scanned_at,
+            scan.accounts,
+            scan.unrelayed,
+            scan.ahead_of_counter.len(),
+            scan.counter,
+            if scan.queue_to > scan.counter {
+                format!(", queued up to {}", scan.queue_to)
+            } else {
+                String::new()
+            }
+        );
+    }
+    let holds = quiet_period::holds(&state)?;
+    if !holds.is_empty() {
+        output!(
+            "{} transfer(s) held in a new-recipient quiet period:",
+            holds.len()
+        );
+    }
+    for (hold, cancellation) in &holds {
+        output!(
+            "- Nonce {}: {} lamports to {}, held until {} ({}){}",
+            hold.nonce,
+            hold.amount,
+            hold.to,
+            hold.release_at,
+            if hold.release_at > now {
+                format!("in {}s", hold.release_at - now)
+            } else {
+                "due".to_string()
+            },
+            match cancellation {
+                Some(cancellation) => format!(", cancellation requested: {}", cancellation.reason),
+                None => String::new(),
+            }
+        );
+    }
+    if let Some(sink) = state.get::<SinkWatermark>(RECEIPT_SINK_KEY)? {
+        if let Some(nonce) = sink.shipped_up_to {
+            output!(
+                "Receipt sink: shipped up to nonce {} (at {})",
+                nonce,
+                sink.shipped_at
+            );
+        }
+    }
+    if let Some(lag) = state.get::<L1LagStatus>(L1_LAG_KEY)? {
+        output!("L1 endpoint lag (checked at {}):", lag.checked_at);
+        for endpoint in &lag.endpoints {
+            output!("  {}", endpoint);
+        }
+    }
+    if let Some(standby) = state.get::<StandbyStatus>(STANDBY_STATUS_KEY)? {
+        output!(
+            "Standby (checked at {}): L2 at nonce {}, receipts mirrored up to nonce {}, {} behind{}",
+            standby.checked_at,
+            standby.l2_nonce,
+            standby.mirrored_nonce,
+            standby.lag,
+            match (&standby.verified_nonce, &standby.verify_error) {
+                (Some(nonce), Some(error)) => format!(", nonce {} not relayable: {}", nonce, error),
+                (Some(nonce), None) => format!(", nonce {} relayable", nonce),
+                _ => String::new(),
+            }
+        );
+    }
+    if let Some(role) = state.get::<Role>(ROLE_KEY)? {
+        output!("Role set by promote: {:?}", role);
+    }
+    for (key, scope) in [
+        (GLOBAL_LIMIT_KEY, "all sources"),
+        (SOURCE_LIMIT_KEY, "this source"),
+    ] {
+        if let Some(limit) = state.get::<LimitOverride>(key)? {
+            output!(
+                "Throughput limit of {} set by throttle: {}",
+                scope,
+                match limit.per_minute {
+                    0 => "unlimited".to_string(),
+                    per_minute => format!("{} transfers per minute", per_minute),
+                }
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Approval policy described by `config`, if manual approval is enabled
+fn approval_policy(config: &RelayerConfig) -> Result<Option<ApprovalPolicy>> {
+    ApprovalPolicy::new(
+        config.manual_approval_threshold,
+        &config.approver_pubkeys,
+        config.required_approvals,
+        Duration::from_secs(config.approval_timeout_secs),
+        config
+            .approval_webhook_url
+            .as_ref()
+            .map(|url| url.expose().clone()),
+    )
+}
+
+/// What the approval messages of `source` are scoped to
+fn approval_scope<'a>(config: &RelayerConfig, source: &'a SourceConfig) -> ApprovalScope<'a> {
+    ApprovalScope {
+        source_id: &source.id,
+        program_id: config.l2_program_id,
+        watched_account: source.watched_account,
+    }
+}
+
+/// Offline checks of `config`: every source's addresses, message types and
+/// discriminators, the L2 program and the signer keys
+fn validate_config(config: &RelayerConfig) -> Result<()> {
+    for source in config.sources()? {
+        source.discriminators.parse()?;
+        source.discriminators.entries()?;
+        for id in source.message_types.keys() {
+            id.parse::<u8>().map_err(|e| {
+                anyhow::anyhow!(
+                    "Invalid message type id {} of source {}: {}",
+                    id,
+                    source.id,
+                    e
+                )
+            })?;
+        }
+        output!("- Source {}: addresses and layouts valid", source.id);
+    }
+    let (primary, secondary) = read_signers(config)?;
+    match secondary {
+        Some(secondary) => output!(
+            "- Signer {}, secondary {}",
+            primary.pubkey(),
+            secondary.pubkey()
+        ),
+        None => output!("- Signer {}", primary.pubkey()),
+    }
+    Ok(())
+}
+
+/// Reads the transfer-info PDA of `nonce` and prints its decoded fields
+async fn decode_pda(config: &RelayerConfig, source: &SourceConfig, nonce: u64) -> Result<()> {
+    let (discriminator, _) = source.discriminators.parse()?;
+    let pdas = PdaManager::new(
+        source.l1_program_id,
+        source.watched_account,
+        source.seeds.clone(),
+    )
+    .with_decoder(message_decoder::from_layout(
+        source.transfer_info_layout.as_ref(),
+        config.reads_attestations(),
+    ));
+    let (address, bump) = pdas.find_address(nonce);
+    let client = source_client(config);
+    let account = client
+        .get_account_with_commitment(&address, client.commitment())
+        .await?
+        .value
+        .ok_or_else(|| {
+            anyhow::anyhow!("Transfer-info PDA {} of nonce {} not found", address, nonce)
+        })?;
+    output!(
+        "Transfer-info PDA {} of nonce {} (bump {}): owner {}, {} bytes",
+        address,
+        nonce,
+        bump,
+        account.owner,
+        account.data.len()
+    );
+    if let Some(expected) = &discriminator {
+        check_discriminator(&account.data, expected)?;
+    }
+    let info = pdas.decode(&account.data)?;
+    let fields = &info.fields;
+    output!("- Layout: {:?}", info.version);
+    if fields.sender == Pubkey::default() {
+        output!("- Sender: unset");
+    } else {
+        output!("- Sender: {}", fields.sender);
+    }
+    output!("- Recipient: {}", fields.recipient);
+    output!(
+        "- Amount: {} ({} SOL if native)",
+        fields.amount,
+        lamports_to_sol(fields.amount)
+    );
+    // 未配置映射时所有消息都按原生转账处理
+    let kind = if source.message_types.is_empty() {
+        Some(MessageType::Native)
+    } else {
+        source
+            .message_types
+            .get(&fields.message_type.to_string())
+            .copied()
+    };
+    match kind {
+        Some(kind) => output!("- Message type: {} ({:?})", fields.message_type, kind),
+        None => output!(
+            "- Message type: {} (not in the source's mapping)",
+            fields.message_type
+        ),
+    }
+    if let Some(expires_at) = info.expires_at {
+        output!("- Expires at: {}", expires_at);
+    }
+    if let Some(mint) = info.mint {
+        output!("- Mint: {}", mint);
+    }
+    Ok(())
+}
+
+/// Source named by `--source`, or the only configured source
+fn select_source<'a>(sources: &'a [SourceConfig], id: Option<&str>) -> Result<&'a SourceConfig> {
+    let ids = || {
+        sources
+            .iter()
+            .map(|source| source.id.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    match id {
+        Some(id) => sources
+            .iter()
+            .find(|source| source.id == id)
+            .ok_or_else(|| anyhow::anyhow!("Unknown source {} (configured: {})", id, ids())),
+        None if sources.len() == 1 => Ok(&sources[0]),
+        None => Err(anyhow::anyhow!(
+            "Several sources are configured, pick one with --source ({})",
+            ids()
+        )),
+    }
+}
+
+/// `audit-report.json` → `audit-report.<source>.json`
+fn source_path(path: &str, source: &str) -> PathBuf {
+    let path = std::path::Path::new(path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let file_name = match path.extension() {
+        Some(extension) => format!("{}.{}.{}", stem, source, extension.to_string_lossy()),
+        None => format!("{}.{}", stem, source),
+    };
+    path.with_file_name(file_name)
+}
+
+/// `path` for `source`, named after its bridge and, when `[[sources]]` are
+/// configured, its id: `journal.jsonl` → `journal.<bridge>.<source>.jsonl`
+fn scoped_path(config: &RelayerConfig, path: &str, source: &str) -> PathBuf {
+    let mut scope: Vec<&str> = config.bridge.iter().map(String::as_str).collect();
+    if config.namespaced_sources() {
+        scope.push(source);
+    }
+    if scope.is_empty() {
+        PathBuf::from(path)
+    } else {
+        source_path(path, &scope.join("."))
+    }
+}
+
+/// Journal file of `source`, if `journal_path` is set
+fn journal_path(config: &RelayerConfig, source: &str) -> Option<PathBuf> {
+    let path = config.journal_path.as_deref()?;
+    Some(scoped_path(config, path, source))
+}
+
+/// Directory holding the state of `source`, its namespace of the state directory
+fn source_state_dir(config: &RelayerConfig, source: &str) -> PathBuf {
+    let mut dir = PathBuf::from(&config.state_dir);
+    if let Some(bridge) = &config.bridge {
+        dir.push(bridge);
+    }
+    if config.namespaced_sources() {
+        dir.push(source);
+    }
+    dir
+}
+
+/// Receipt archives of `source`, in its namespace of the state directory
+fn archive_dir(config: &RelayerConfig, source: &str) -> PathBuf {
+    source_state_dir(config, source).join("archive")
+}
+
+/// Writes all sources' metrics to `path` every second; pends forever without a path
+async fn write_metrics(
+    registry: &MetricsRegistry,
+    clock: &dyn Clock,
+    path: Option<&str>,
+) -> Result<()> {
+    let Some(path) = path else {
+        return futures::future::pending().await;
+    };
+    loop {
+        registry.write_textfile(path)?;
+        clock.sleep(Duration::from_secs(1)).await;
+    }
+}
+
+/// Serves all sources' metrics on `address`; pends forever without an address
+async fn serve_metrics(registry: &MetricsRegistry, address: Option<&str>) -> Result<()> {
+    let Some(address) = address else {
+        return futures::future::pending().await;
+    };
+    let listener = tokio::net::TcpListener::bind(address)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to listen for metrics on {}: {}", address, e))?;
+    tracing::info!(
+        "Serving metrics on http://{}/metrics",
+        listener.local_addr()?
+    );
+    registry.serve(listener).await
+}
+
+/// Serves the admin API on `admin_listen`; pends forever without an address
+#[cfg(feature = "admin")]
+async fn serve_admin(
+    relayers: &[Relayer],
+    stopped: &[AtomicBool],
+    config: &RelayerConfig,
+    telemetry: &TelemetryGuard,
+) -> Result<()> {
+    let Some(address) = config.admin_listen.as_deref() else {
+        return futures::future::pending().await;
+    };
+    let token = config
+        .admin_token
+        .as_ref()
+        .map(|token| token.expose().as_str());
+    let admin = admin_api::AdminApi::new(relayers, stopped, token).with_telemetry(telemetry);
+    let listener = tokio::net::TcpListener::bind(address).await.map_err(|e| {
+        anyhow::anyhow!("Failed to listen for admin requests on {}: {}", address, e)
+    })?;
+    tracing::info!("Serving the admin API on http://{}", listener.local_addr()?);
+    admin.serve(listener).await
+}
+
+/// Builds without the `admin` feature refuse `admin_listen` at load
+#[cfg(not(feature = "admin"))]
+async fn serve_admin(
+    _relayers: &[Relayer],
+    _stopped: &[AtomicBool],
+    _config: &RelayerConfig,
+    _telemetry: &TelemetryGuard,
+) -> Result<()> {
+    futures::future::pending().await
+}
+
+fn print_import_summary(progress: &ImportProgress) {
+    output!("\nImport summary:");
+    output!("- Transactions checked: {}", progress.checked);
+    output!("- Imported: {}", progress.imported);
+    output!(
+        "- Skipped (not a relay, failed or already recorded): {}",
+        progress.skipped
+    );
+    output!("- Unparseable: {}", progress.unparseable);
+}
+
+fn print_reconcile_summary(progress: &ScanProgress, output: &std::path::Path) {
+    output!("\nReconcile summary:");
+    output!(
+        "- Nonces checked: {} ({}..{})",
+        progress.checked,
+        progress.start_nonce,
+        progress.target_nonce
+    );
+    output!("- Relayed on L2: {}", progress.relayed);
+    output!("- Pending on L2: {}", progress.pending);
+    output!("- Discrepancies: {}", progress.discrepancies);
+    output!("- Details: {}", output.display());
+}
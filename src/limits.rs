@@ -0,0 +1,254 @@
+//! Per-message-type limits.
+//! The `[limits]` table bounds the amount of a relayed transfer and sets the
+//! compute budget of its transaction; `[limits.native]`, `[limits.token]`
+//! and `[limits.nft]` override any of its keys for one message type:
+//!
+//! - `min_amount` and `max_amount`: bounds of the amount, in lamports or the
+//!   mint's base units; a transfer outside them is dead-lettered
+//! - `compute_unit_limit`: compute units requested, over the top-level
+//!   `compute_unit_limit`
+//! - `compute_unit_price_micro_lamports`: compute-unit price, over the
+//!   price of `priority_fee_strategy`
+//!
+//! A minimum above the maximum is refused at load, in the table and in each
+//! override with the keys it inherits. The receipt of a relay names the type
+//! whose override applied (`limits`). Packed transactions only carry native
+//! transfers and take the native values. No relay fee is charged per type:
+//! `relay_message` pays the L1 amount in full and takes no fee.
+
+use crate::{
+    models::message::MessageType, pipeline::FetchedTransfer, priority_fee::ComputeBudget,
+    token::TokenTransfer, Relayer,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// Keys of `[limits]`, or of one type's override
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TypeLimits {
+    /// Smallest amount relayed
+    #[serde(default)]
+    pub min_amount: Option<u64>,
+    /// Largest amount relayed
+    #[serde(default)]
+    pub max_amount: Option<u64>,
+    /// Compute units requested per transaction
+    #[serde(default)]
+    pub compute_unit_limit: Option<u32>,
+    /// Compute-unit price in micro-lamports
+    #[serde(default)]
+    pub compute_unit_price_micro_lamports: Option<u64>,
+}
+
+impl TypeLimits {
+    /// Each key of `self`, or of `base` where unset
+    fn or(self, base: Self) -> Self {
+        Self {
+            min_amount: self.min_amount.or(base.min_amount),
+            max_amount: self.max_amount.or(base.max_amount),
+            compute_unit_limit: self.compute_unit_limit.or(base.compute_unit_limit),
+            compute_unit_price_micro_lamports: self
+                .compute_unit_price_micro_lamports
+                .or(base.compute_unit_price_micro_lamports),
+        }
+    }
+
+    /// Why `amount` may not be relayed, if it may not
+    fn violation(&self, amount: u64) -> Option<String> {
+        match (self.min_amount, self.max_amount) {
+            (Some(min), _) if amount < min => {
+                Some(format!("amount {} is below the minimum {}", amount, min))
+            }
+            (_, Some(max)) if amount > max => {
+                Some(format!("amount {} exceeds the maximum {}", amount, max))
+            }
+            _ => None,
+        }
+    }
+
+    /// `budget` with the compute limit and price of these limits
+    fn budget(&self, budget: Option<ComputeBudget>) -> Option<ComputeBudget> {
+        if self.compute_unit_limit.is_none() && self.compute_unit_price_micro_lamports.is_none() {
+            return budget;
+        }
+        let base = budget.unwrap_or(ComputeBudget {
+            unit_price: 0,
+            unit_limit: None,
+        });
+        Some(ComputeBudget {
+            unit_price: self
+                .compute_unit_price_micro_lamports
+                .unwrap_or(base.unit_price),
+            unit_limit: self.compute_unit_limit.or(base.unit_limit),
+        })
+    }
+}
+
+/// `[limits]` table
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LimitsConfig {
+    /// Limits of every message type
+    #[serde(flatten)]
+    pub defaults: TypeLimits,
+    /// Override for native transfers (`[limits.native]`)
+    #[serde(default)]
+    pub native: Option<TypeLimits>,
+    /// Override for token transfers (`[limits.token]`)
+    #[serde(default)]
+    pub token: Option<TypeLimits>,
+    /// Override for NFT transfers (`[limits.nft]`)
+    #[serde(default)]
+    pub nft: Option<TypeLimits>,
+}
+
+impl LimitsConfig {
+    /// Effective limits of a `kind` message, and `kind` when its override
+    /// applied
+    pub fn resolve(&self, kind: MessageType) -> (TypeLimits, Option<MessageType>) {
+        let override_ = match kind {
+            MessageType::Native => self.native,
+            MessageType::Token => self.token,
+            MessageType::Nft => self.nft,
+            MessageType::Unknown(_) => None,
+        };
+        match override_ {
+            Some(limits) => (limits.or(self.defaults), Some(kind)),
+            None => (self.defaults, None),
+        }
+    }
+
+    /// Fails on a minimum above the maximum, in the table or in an override
+    /// with the keys it inherits
+    pub fn validate(&self) -> Result<()> {
+        for (name, kind) in [
+            ("limits", None),
+            ("limits.native", Some(MessageType::Native)),
+            ("limits.token", Some(MessageType::Token)),
+            ("limits.nft", Some(MessageType::Nft)),
+        ] {
+            let limits = kind.map_or(self.defaults, |kind| self.resolve(kind).0);
+            if let (Some(min), Some(max)) = (limits.min_amount, limits.max_amount) {
+                if min > max {
+                    return Err(anyhow::anyhow!(
+                        "{} sets min_amount {} above max_amount {}",
+                        name,
+                        min,
+                        max
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Message type of a transfer paying out `token`, native without one
+pub fn message_type_of(token: Option<TokenTransfer>) -> MessageType {
+    match token {
+        None => MessageType::Native,
+        Some(token) if token.nft => MessageType::Nft,
+        Some(_) => MessageType::Token,
+    }
+}
+
+impl Relayer {
+    /// Checks the amount of `fetched` against the limits of its type;
+    /// dead-letters a transfer outside them
+    pub(crate) fn check_limits(&self, fetched: &FetchedTransfer) -> Result<()> {
+        let kind = message_type_of(fetched.token);
+        let (limits, _) = self.limits.resolve(kind);
+        let Some(reason) = limits.violation(fetched.amount) else {
+            return Ok(());
+        };
+        let reason = format!("{:?} transfer {}", kind, reason);
+        self.dead_letter_transfer(fetched, &reason)?;
+        Err(anyhow::anyhow!(
+            "Nonce {} rejected by limits: {}",
+            fetched.nonce,
+            reason
+        ))
+    }
+
+    /// `budget` with the compute limit and price of a `token` transfer's type
+    pub(crate) fn typed_budget(
+        &self,
+        token: Option<TokenTransfer>,
+        budget: Option<ComputeBudget>,
+    ) -> Option<ComputeBudget> {
+        self.limits.resolve(message_type_of(token)).0.budget(budget)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(min: Option<u64>, max: Option<u64>) -> TypeLimits {
+        TypeLimits {
+            min_amount: min,
+            max_amount: max,
+            ..TypeLimits::default()
+        }
+    }
+
+    #[test]
+    fn overrides_inherit_unset_keys_and_are_named() {
+        let config = LimitsConfig {
+            defaults: TypeLimits {
+                compute_unit_limit: Some(200_000),
+                ..limits(Some(10), Some(1_000))
+            },
+            nft: Some(TypeLimits {
+                compute_unit_limit: Some(400_000),
+                ..limits(Some(1), None)
+            }),
+            ..LimitsConfig::default()
+        };
+        let (nft, applied) = config.resolve(MessageType::Nft);
+        assert_eq!(applied, Some(MessageType::Nft));
+        assert_eq!(nft.min_amount, Some(1));
+        assert_eq!(nft.max_amount, Some(1_000));
+        assert_eq!(nft.compute_unit_limit, Some(400_000));
+        assert_eq!(config.resolve(MessageType::Token), (config.defaults, None));
+        assert!(nft.violation(1).is_none());
+        assert!(config.defaults.violation(9).is_some());
+        assert!(config.defaults.violation(1_001).is_some());
+    }
+
+    #[test]
+    fn a_minimum_above_the_maximum_is_refused_with_inherited_keys() {
+        let config = LimitsConfig {
+            defaults: limits(None, Some(100)),
+            token: Some(limits(Some(500), None)),
+            ..LimitsConfig::default()
+        };
+        let error = config.validate().unwrap_err().to_string();
+        assert!(error.contains("limits.token"), "{}", error);
+        assert!(LimitsConfig {
+            defaults: limits(Some(5), Some(1)),
+            ..LimitsConfig::default()
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn the_type_sets_the_compute_budget() {
+        let typed = TypeLimits {
+            compute_unit_price_micro_lamports: Some(50),
+            ..TypeLimits::default()
+        };
+        let budget = ComputeBudget {
+            unit_price: 10,
+            unit_limit: Some(300_000),
+        };
+        assert_eq!(
+            typed.budget(Some(budget)),
+            Some(ComputeBudget {
+                unit_price: 50,
+                unit_limit: Some(300_000),
+            })
+        );
+        assert_eq!(TypeLimits::default().budget(None), None);
+    }
+}
@@ -0,0 +1,347 @@
+//! Address lookup tables on L2.
+//! With an `[address_lookup_tables]` section, relay transactions are built as
+//! v0 transactions compiled against the configured `tables`: every
+//! non-signer account a table holds takes one byte instead of 32, so packed
+//! batches with many recipients fit in a packet. Tables are read at startup
+//! and again every `refresh_secs`; a deactivated table is left out, and the
+//! addresses of an extension become usable from the slot after it, as on
+//! chain.
+//!
+//! With `auto_extend = true` the relayer also keeps a table of its own: the
+//! accounts its sent transactions load directly are counted, and those seen
+//! in `min_uses` transactions are added to the table (created by the first
+//! extension, its address kept in the state as `lookup_table`), up to the
+//! 256 addresses a table holds. Only the signer that created the table may
+//! extend it. The durable nonce account must stay a static key of its
+//! transactions and is never added.
+
+use crate::{
+    config::AddressLookupTablesConfig, inflight::TransactionOutcome, state::StateStore, Relayer,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_address_lookup_table_program::{
+    instruction::{create_lookup_table, extend_lookup_table},
+    state::{AddressLookupTable, LOOKUP_TABLE_MAX_ADDRESSES},
+};
+use solana_sdk::{
+    address_lookup_table_account::AddressLookupTableAccount,
+    commitment_config::CommitmentConfig,
+    message::VersionedMessage,
+    pubkey::Pubkey,
+    transaction::{Transaction, VersionedTransaction},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::{Mutex, RwLock},
+    time::Duration,
+};
+
+/// State key of the table created by `auto_extend`
+pub const LOOKUP_TABLE_KEY: &str = "lookup_table";
+/// Addresses added by one extension, so that it fits in a packet
+const EXTEND_BATCH: usize = 20;
+/// Accounts counted at most before the rarely used ones are forgotten
+const USES_CAPACITY: usize = 10_000;
+
+/// The table created by `auto_extend`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct OwnTable {
+    address: String,
+    /// Signer allowed to extend it
+    authority: String,
+}
+
+#[derive(Default)]
+struct Loaded {
+    /// Tables with their active addresses
+    accounts: Vec<AddressLookupTableAccount>,
+    /// Every address of the tables, active or not
+    held: HashSet<Pubkey>,
+    /// Addresses in the relayer's own table
+    own_len: usize,
+}
+
+pub struct LookupTables {
+    configured: Vec<Pubkey>,
+    auto_extend: bool,
+    min_uses: u32,
+    refresh: Duration,
+    /// Accounts never added to the relayer's table
+    excluded: Vec<Pubkey>,
+    loaded: RwLock<Loaded>,
+    /// Sent transactions each directly loaded account appeared in
+    uses: Mutex<HashMap<Pubkey, u32>>,
+}
+
+impl LookupTables {
+    /// `durable_nonce` is the account every transaction advances first, if any
+    pub fn from_config(
+        config: &AddressLookupTablesConfig,
+        durable_nonce: Option<Pubkey>,
+    ) -> Result<Self> {
+        let configured = config
+            .tables
+            .iter()
+            .map(|table| {
+                Pubkey::from_str(table)
+                    .map_err(|e| anyhow::anyhow!("Invalid lookup table {}: {}", table, e))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            configured,
+            auto_extend: config.auto_extend,
+            min_uses: config.min_uses.max(1),
+            refresh: Duration::from_secs(config.refresh_secs.max(1)),
+            excluded: durable_nonce.into_iter().collect(),
+            loaded: RwLock::new(Loaded::default()),
+            uses: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The loaded tables, with their active addresses
+    pub fn accounts(&self) -> Vec<AddressLookupTableAccount> {
+        self.loaded.read().unwrap().accounts.clone()
+    }
+
+    /// Counts the accounts `message` loads directly that a table could hold:
+    /// neither signers nor invoked programs
+    pub fn record(&self, message: &VersionedMessage) {
+        if !self.auto_extend {
+            return;
+        }
+        let keys = message.static_account_keys();
+        let held = &self.loaded.read().unwrap().held;
+        let mut uses = self.uses.lock().unwrap();
+        if uses.len() >= USES_CAPACITY {
+            // 只保留已够次数的账户，避免收款人过多时无限增长
+            uses.retain(|_, count| *count >= self.min_uses);
+        }
+        for (index, key) in keys.iter().enumerate() {
+            let invoked = message
+                .instructions()
+                .iter()
+                .any(|instruction| usize::from(instruction.program_id_index) == index);
+            if message.is_signer(index)
+                || invoked
+                || self.excluded.contains(key)
+                || held.contains(key)
+            {
+                continue;
+            }
+            *uses.entry(*key).or_default() += 1;
+        }
+    }
+
+    /// Up to `limit` of the accounts used at least `min_uses` times, the most
+    /// used first
+    fn candidates(&self, limit: usize) -> Vec<Pubkey> {
+        let uses = self.uses.lock().unwrap();
+        let mut candidates: Vec<_> = uses
+            .iter()
+            .filter(|(_, count)| **count >= self.min_uses)
+            .map(|(key, count)| (*key, *count))
+            .collect();
+        candidates.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        candidates
+            .into_iter()
+            .take(limit)
+            .map(|(key, _)| key)
+            .collect()
+    }
+
+    /// Stops counting `keys`, added to the relayer's table
+    fn added(&self, keys: &[Pubkey]) {
+        let mut loaded = self.loaded.write().unwrap();
+        let mut uses = self.uses.lock().unwrap();
+        for key in keys {
+            uses.remove(key);
+            loaded.held.insert(*key);
+        }
+        loaded.own_len += keys.len();
+    }
+}
+
+fn own_table(state: &StateStore) -> Result<Option<(Pubkey, Pubkey)>> {
+    state
+        .get::<OwnTable>(LOOKUP_TABLE_KEY)?
+        .map(|table| {
+            Ok((
+                Pubkey::from_str(&table.address)?,
+                Pubkey::from_str(&table.authority)?,
+            ))
+        })
+        .transpose()
+}
+
+impl Relayer {
+    /// Reads the configured tables and the relayer's own
+    pub(crate) async fn load_lookup_tables(&self, tables: &LookupTables) -> Result<()> {
+        let mut addresses = tables.configured.clone();
+        let own = own_table(&self.state)?.map(|(address, _)| address);
+        addresses.extend(own);
+        let slot = self.l2_client.get_slot().await?;
+        let accounts = self.l2_client.get_multiple_accounts(&addresses).await?;
+        let mut loaded = Loaded::default();
+        for (address, account) in addresses.iter().zip(accounts) {
+            let account = account
+                .filter(|account| account.owner == solana_address_lookup_table_program::id())
+                .ok_or_else(|| anyhow::anyhow!("Lookup table {} not found on L2", address))?;
+            let table = AddressLookupTable::deserialize(&account.data)
+                .map_err(|e| anyhow::anyhow!("Invalid lookup table {}: {}", address, e))?;
+            if table.meta.deactivation_slot != u64::MAX {
+                tracing::warn!("lookup table {} is deactivated, not used", address);
+                continue;
+            }
+            if let Some(excluded) = tables
+                .excluded
+                .iter()
+                .find(|key| table.addresses.contains(key))
+            {
+                return Err(anyhow::anyhow!(
+                    "Lookup table {} holds the durable nonce account {}, which must not be looked up",
+                    address,
+                    excluded
+                ));
+            }
+            // 同一 slot 内扩展的地址要到下一个 slot 才能使用
+            let active = if table.meta.last_extended_slot >= slot {
+                usize::from(table.meta.last_extended_slot_start_index)
+            } else {
+                table.addresses.len()
+            };
+            loaded.held.extend(table.addresses.iter());
+            if own == Some(*address) {
+                loaded.own_len = table.addresses.len();
+            }
+            loaded.accounts.push(AddressLookupTableAccount {
+                key: *address,
+                addresses: table.addresses[..active].to_vec(),
+            });
+        }
+        self.metrics.lookup_table_addresses.set(
+            loaded
+                .accounts
+                .iter()
+                .map(|table| table.addresses.len() as u64)
+                .sum(),
+        );
+        let mut current = tables.loaded.write().unwrap();
+        let mut uses = tables.uses.lock().unwrap();
+        for key in &loaded.held {
+            uses.remove(key);
+        }
+        *current = loaded;
+        Ok(())
+    }
+
+    /// Reloads the tables every `refresh_secs`, extending the relayer's own
+    /// first with `auto_extend`; pends forever without lookup tables
+    pub(crate) async fn maintain_lookup_tables(&self) -> Result<()> {
+        let Some(tables) = &self.transaction_builder.lookup_tables else {
+            return futures::future::pending().await;
+        };
+        loop {
+            self.clock.sleep(tables.refresh).await;
+            // standby 不发送交易，也不扩展
+            if tables.auto_extend && !self.dry_run && !self.is_standby().unwrap_or(true) {
+                if let Err(e) = self.extend_lookup_table(tables).await {
+                    tracing::warn!("failed to extend the lookup table: {}", e);
+                }
+            }
+            if let Err(e) = self.load_lookup_tables(tables).await {
+                tracing::warn!("failed to reload the lookup tables: {}", e);
+            }
+        }
+    }
+
+    /// Adds the most used accounts to the relayer's table, creating it first
+    async fn extend_lookup_table(&self, tables: &LookupTables) -> Result<()> {
+        let own = own_table(&self.state)?;
+        let payer = self.signers.active();
+        if let Some((address, authority)) = own {
+            if authority != payer.pubkey() {
+                tracing::warn!(
+                    "lookup table {} can only be extended by {}, not extended",
+                    address,
+                    authority
+                );
+                return Ok(());
+            }
+        }
+        let held = tables.loaded.read().unwrap().own_len;
+        let candidates = tables.candidates(EXTEND_BATCH.min(LOOKUP_TABLE_MAX_ADDRESSES - held));
+        if candidates.is_empty() {
+            return Ok(());
+        }
+
+        let authority = payer.pubkey();
+        let mut instructions = Vec::new();
+        let address = match own {
+            Some((address, _)) => address,
+            None => {
+                let slot = self
+                    .l2_client
+                    .get_slot_with_commitment(CommitmentConfig::finalized())
+                    .await?;
+                let (create, address) = create_lookup_table(authority, authority, slot);
+                instructions.push(create);
+                address
+            }
+        };
+        instructions.push(extend_lookup_table(
+            address,
+            authority,
+            Some(authority),
+            candidates.clone(),
+        ));
+        let (recent_blockhash, last_valid_block_height) = self
+            .l2_client
+            .get_latest_blockhash_with_commitment(self.l2_client.commitment())
+            .await?;
+        let transaction: VersionedTransaction = Transaction::new_signed_with_payer(
+            &instructions,
+            Some(&authority),
+            &[payer],
+            recent_blockhash,
+        )
+        .into();
+        let signature = transaction.signatures[0];
+        match self
+            .send_and_confirm(&transaction, last_valid_block_height)
+            .await?
+        {
+            TransactionOutcome::Confirmed { .. } => {
+                if own.is_none() {
+                    self.state.put(
+                        LOOKUP_TABLE_KEY,
+                        &OwnTable {
+                            address: address.to_string(),
+                            authority: authority.to_string(),
+                        },
+                    )?;
+                    tracing::info!("Created lookup table {} ({})", address, signature);
+                }
+                tables.added(&candidates);
+                self.metrics.lookup_table_extensions_total.inc();
+                tracing::info!(
+                    "Added {} account(s) to lookup table {} ({})",
+                    candidates.len(),
+                    address,
+                    signature
+                );
+                Ok(())
+            }
+            TransactionOutcome::Failed(err) => Err(anyhow::anyhow!(
+                "Lookup table extension {} failed: {}",
+                signature,
+                err
+            )),
+            TransactionOutcome::Expired => Err(anyhow::anyhow!(
+                "Lookup table extension {} expired before landing",
+                signature
+            )),
+        }
+    }
+}
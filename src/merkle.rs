@@ -0,0 +1,309 @@
+//! Merkle-batched L1 messages.
+//! In merkle mode the L1 program commits the root of each message batch to a
+//! `LeafChunkAccount` instead of writing one PDA per message. Messages and
+//! their proofs come from an indexer; every proof is checked locally against
+//! the root read from L1 before the message is relayed, and the proof is
+//! forwarded in the L2 instruction so the L2 program can check it as well.
+//!
+//! Tree format (mirrors the on-chain implementation):
+//! - binary tree, SHA-256 (`solana_program::hash::hashv`)
+//! - leaf = hashv(amount LE, nonce LE, recipient)
+//! - parent = hashv(left, right), where the leaf index bits select the side
+//! - a node without a sibling is paired with itself
+
+use crate::decode;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_sdk::{
+    hash::{hashv, Hash},
+    pubkey::Pubkey,
+};
+use std::str::FromStr;
+
+/// Where the relayer reads L1 messages from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageSource {
+    /// One transfer-info PDA per nonce
+    Pda,
+    /// Merkle-committed batches served by an indexer
+    Merkle,
+    /// Message accounts with a pending status byte, found via `getProgramAccounts`
+    ProgramAccounts,
+}
+
+/// Inclusion proof of one leaf
+#[derive(Debug, Clone)]
+pub struct MerkleProof {
+    pub leaf_index: u32,
+    pub siblings: Vec<[u8; 32]>,
+}
+
+impl MerkleProof {
+    /// Appends the proof in the L2 instruction layout: leaf index (u32 LE),
+    /// sibling count (u32 LE), then the siblings
+    pub fn encode_into(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.leaf_index.to_le_bytes());
+        out.extend_from_slice(&(self.siblings.len() as u32).to_le_bytes());
+        for sibling in &self.siblings {
+            out.extend_from_slice(sibling);
+        }
+    }
+
+    /// Returns whether the proof links `leaf` to `root`
+    pub fn verify(&self, leaf: [u8; 32], root: &[u8; 32]) -> bool {
+        let mut node = leaf;
+        let mut index = self.leaf_index;
+        for sibling in &self.siblings {
+            node = if index & 1 == 0 {
+                hashv(&[&node, sibling]).to_bytes()
+            } else {
+                hashv(&[sibling, &node]).to_bytes()
+            };
+            index >>= 1;
+        }
+        node == *root
+    }
+}
+
+/// Leaf hash of a message
+pub fn leaf_hash(amount: u64, nonce: u64, to: &Pubkey) -> [u8; 32] {
+    hashv(&[&amount.to_le_bytes(), &nonce.to_le_bytes(), to.as_ref()]).to_bytes()
+}
+
+/// Decoded `LeafChunkAccount` (the trailing `is_fulled` flag is not needed here)
+pub struct LeafChunk {
+    pub leafs: Vec<[u8; 32]>,
+    pub root: [u8; 32],
+}
+
+impl LeafChunk {
+    const DISCRIMINATOR: [u8; 8] = [110, 34, 209, 252, 184, 215, 83, 145];
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        if data.len() < 12 || data[..8] != Self::DISCRIMINATOR {
+            return Err(anyhow::anyhow!(
+                "Not a LeafChunkAccount: no discriminator and leaf count at offset 0..12 ({})",
+                decode::hex_prefix(data)
+            ));
+        }
+        let count = u32::from_le_bytes(data[8..12].try_into()?) as usize;
+        let leafs_end = 12 + count * 32;
+        if data.len() < leafs_end + 33 {
+            return Err(anyhow::anyhow!(
+                "Truncated LeafChunkAccount: {} leafs need {} bytes, got {} ({})",
+                count,
+                leafs_end + 33,
+                data.len(),
+                decode::hex_prefix(data)
+            ));
+        }
+
+        let leafs = data[12..leafs_end]
+            .chunks_exact(32)
+            .map(|leaf| leaf.try_into().unwrap())
+            .collect();
+        let root = data[leafs_end..leafs_end + 32].try_into()?;
+        Ok(Self { leafs, root })
+    }
+
+    /// Returns whether `proof` proves `leaf` against this chunk's root and,
+    /// when the chunk stores its leaves, the leaf sits at the proven index
+    pub fn proves(&self, leaf: [u8; 32], proof: &MerkleProof) -> bool {
+        let stored =
+            self.leafs.is_empty() || self.leafs.get(proof.leaf_index as usize) == Some(&leaf);
+        stored && proof.verify(leaf, &self.root)
+    }
+}
+
+/// A message and its proof as served by the indexer
+#[derive(Debug, Clone, Deserialize)]
+pub struct IndexedMessage {
+    pub nonce: u64,
+    pub amount: u64,
+    /// Recipient (base58)
+    pub to: String,
+    /// `LeafChunkAccount` holding the batch root (base58)
+    pub root_account: String,
+    pub leaf_index: u32,
+    /// Sibling hashes from the leaf upwards (base58)
+    pub proof: Vec<String>,
+}
+
+impl IndexedMessage {
+    pub fn to_address(&self) -> Result<Pubkey> {
+        Pubkey::from_str(&self.to)
+            .map_err(|e| anyhow::anyhow!("Invalid recipient {} from indexer: {}", self.to, e))
+    }
+
+    pub fn root_account(&self) -> Result<Pubkey> {
+        Pubkey::from_str(&self.root_account).map_err(|e| {
+            anyhow::anyhow!(
+                "Invalid root account {} from indexer: {}",
+                self.root_account,
+                e
+            )
+        })
+    }
+
+    pub fn merkle_proof(&self) -> Result<MerkleProof> {
+        let siblings = self
+            .proof
+            .iter()
+            .map(|sibling| {
+                Hash::from_str(sibling)
+                    .map(|hash| hash.to_bytes())
+                    .map_err(|e| anyhow::anyhow!("Invalid proof node {}: {}", sibling, e))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(MerkleProof {
+            leaf_index: self.leaf_index,
+            siblings,
+        })
+    }
+}
+
+/// HTTP client of the message indexer
+pub struct IndexerClient {
+    url: String,
+    http: reqwest::Client,
+}
+
+impl IndexerClient {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// Fetches `GET {url}/messages/{nonce}`
+    pub async fn message(&self, nonce: u64) -> Result<IndexedMessage> {
+        let url = format!("{}/messages/{}", self.url, nonce);
+        let message: IndexedMessage = self
+            .http
+            .get(&url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| anyhow::anyhow!("Indexer request {} failed: {}", url, e))?
+            .json()
+            .await
+            .map_err(|e| anyhow::anyhow!("Invalid indexer response for nonce {}: {}", nonce, e))?;
+        if message.nonce != nonce {
+            return Err(anyhow::anyhow!(
+                "Indexer returned nonce {} for nonce {}",
+                message.nonce,
+                nonce
+            ));
+        }
+        Ok(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Root and proofs of a tree over `leafs`, built level by level
+    fn tree(leafs: &[[u8; 32]]) -> ([u8; 32], Vec<MerkleProof>) {
+        let mut proofs: Vec<MerkleProof> = (0..leafs.len() as u32)
+            .map(|leaf_index| MerkleProof {
+                leaf_index,
+                siblings: Vec::new(),
+            })
+            .collect();
+        let mut level = leafs.to_vec();
+        let mut positions: Vec<usize> = (0..leafs.len()).collect();
+        while level.len() > 1 {
+            for (proof, position) in proofs.iter_mut().zip(&mut positions) {
+                let sibling = level.get(*position ^ 1).unwrap_or(&level[*position]);
+                proof.siblings.push(*sibling);
+                *position /= 2;
+            }
+            level = level
+                .chunks(2)
+                .map(|pair| hashv(&[&pair[0], pair.get(1).unwrap_or(&pair[0])]).to_bytes())
+                .collect();
+        }
+        (level[0], proofs)
+    }
+
+    fn chunk_data(leafs: &[[u8; 32]], root: &[u8; 32]) -> Vec<u8> {
+        let mut data = LeafChunk::DISCRIMINATOR.to_vec();
+        data.extend_from_slice(&(leafs.len() as u32).to_le_bytes());
+        for leaf in leafs {
+            data.extend_from_slice(leaf);
+        }
+        data.extend_from_slice(root);
+        data.push(1);
+        data
+    }
+
+    #[test]
+    fn every_leaf_of_an_odd_tree_is_proven() {
+        let to = Pubkey::new_unique();
+        let leafs: Vec<_> = (0..3)
+            .map(|nonce| leaf_hash(100 + nonce, nonce, &to))
+            .collect();
+        let (root, proofs) = tree(&leafs);
+        for (leaf, proof) in leafs.iter().zip(&proofs) {
+            assert!(proof.verify(*leaf, &root));
+        }
+        // 证明与叶子位置不符时不通过
+        assert!(!proofs[0].verify(leafs[1], &root));
+        let mut moved = proofs[1].clone();
+        moved.leaf_index = 0;
+        assert!(!moved.verify(leafs[1], &root));
+    }
+
+    #[test]
+    fn a_chunk_checks_the_stored_leaf_at_the_proven_index() {
+        let to = Pubkey::new_unique();
+        let leafs: Vec<_> = (0..4).map(|nonce| leaf_hash(7, nonce, &to)).collect();
+        let (root, proofs) = tree(&leafs);
+        let chunk = LeafChunk::from_bytes(&chunk_data(&leafs, &root)).unwrap();
+        assert_eq!(chunk.leafs, leafs);
+        assert_eq!(chunk.root, root);
+        assert!(chunk.proves(leafs[2], &proofs[2]));
+        assert!(!chunk.proves(leaf_hash(8, 2, &to), &proofs[2]));
+
+        let data = chunk_data(&leafs, &root);
+        assert!(LeafChunk::from_bytes(&data[..data.len() - 1]).is_err());
+        assert!(LeafChunk::from_bytes(&[0; 64]).is_err());
+    }
+
+    #[test]
+    fn the_proof_is_encoded_after_its_index_and_length() {
+        let proof = MerkleProof {
+            leaf_index: 5,
+            siblings: vec![[1; 32], [2; 32]],
+        };
+        let mut out = Vec::new();
+        proof.encode_into(&mut out);
+        assert_eq!(&out[..8], &[5, 0, 0, 0, 2, 0, 0, 0]);
+        assert_eq!(&out[8..40], &[1; 32]);
+        assert_eq!(&out[40..], &[2; 32]);
+    }
+
+    #[test]
+    fn indexer_proof_nodes_are_base58_hashes() {
+        let message = IndexedMessage {
+            nonce: 1,
+            amount: 2,
+            to: Pubkey::new_unique().to_string(),
+            root_account: "not-a-key".to_string(),
+            leaf_index: 3,
+            proof: vec![Hash::new_from_array([9; 32]).to_string()],
+        };
+        let proof = message.merkle_proof().unwrap();
+        assert_eq!(proof.leaf_index, 3);
+        assert_eq!(proof.siblings, vec![[9; 32]]);
+        assert!(message.root_account().is_err());
+        let invalid = IndexedMessage {
+            proof: vec!["0OIl".to_string()],
+            ..message
+        };
+        assert!(invalid.merkle_proof().is_err());
+    }
+}
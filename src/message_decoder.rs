@@ -0,0 +1,123 @@
+//! Transfer-info decoders.
+//! Transfer-info PDAs are decoded by the `MessageDecoder` of their source's
+//! `PdaManager`. The default reads the Borsh layout of `models::account`,
+//! its version told by the data size. An L1 program that lays the PDA out
+//! differently is read by setting `[transfer_info_layout]` (or
+//! `[sources.transfer_info_layout]`) to the byte offset of each field from
+//! the start of the data; a field without an offset is left unset. Together
+//! with `[seeds]` (`[sources.seeds]`) for the derivation, this points the
+//! relayer at another bridge program without code changes. Other decoders
+//! plug in through `PdaManager::with_decoder`. Attestations are only read from
+//! the Borsh layout; at a source reading them the signature follows the
+//! versioned body and is left out of the decoded fields.
+
+use crate::models::account::{TransferInfoAccount, TransferInfoFields, TransferInfoVersion};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use std::sync::Arc;
+
+/// Decodes the data of a transfer-info PDA
+pub trait MessageDecoder: Send + Sync {
+    fn decode(&self, data: &[u8]) -> Result<TransferInfoAccount>;
+}
+
+/// The Borsh layout of `TransferInfoAccount`
+pub struct BorshDecoder;
+
+impl MessageDecoder for BorshDecoder {
+    fn decode(&self, data: &[u8]) -> Result<TransferInfoAccount> {
+        TransferInfoAccount::parse(data)
+    }
+}
+
+/// The Borsh layout at a source reading attestations: the versioned body,
+/// optionally followed by the signature (`TransferInfoAccount::split_attested`)
+pub struct AttestedBorshDecoder;
+
+impl MessageDecoder for AttestedBorshDecoder {
+    fn decode(&self, data: &[u8]) -> Result<TransferInfoAccount> {
+        TransferInfoAccount::parse(TransferInfoAccount::split_attested(data)?.0)
+    }
+}
+
+/// Byte offsets of the transfer-info fields (`[transfer_info_layout]`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferInfoLayout {
+    /// Recipient, 32 bytes
+    pub recipient: usize,
+    /// Amount, u64 LE
+    pub amount: usize,
+    /// L1 sender, 32 bytes
+    #[serde(default)]
+    pub sender: Option<usize>,
+    /// Raw message-type id, one byte (0 when unset)
+    #[serde(default)]
+    pub message_type: Option<usize>,
+    /// Deadline, i64 LE Unix time
+    #[serde(default)]
+    pub expires_at: Option<usize>,
+    /// L1 mint of token and NFT messages, 32 bytes
+    #[serde(default)]
+    pub mint: Option<usize>,
+}
+
+/// `N` bytes of `data` at `offset`
+fn field<const N: usize>(data: &[u8], offset: usize, name: &str) -> Result<[u8; N]> {
+    data.get(offset..offset + N)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Transfer-info PDA has {} bytes, too few for {} at offset {}",
+                data.len(),
+                name,
+                offset
+            )
+        })
+}
+
+impl MessageDecoder for TransferInfoLayout {
+    fn decode(&self, data: &[u8]) -> Result<TransferInfoAccount> {
+        let pubkey = |offset: usize, name| field::<32>(data, offset, name).map(Pubkey::from);
+        let optional = |offset: Option<usize>, name| offset.map(|offset| pubkey(offset, name));
+        let fields = TransferInfoFields {
+            sender: optional(self.sender, "sender")
+                .transpose()?
+                .unwrap_or_default(),
+            recipient: pubkey(self.recipient, "recipient")?,
+            amount: u64::from_le_bytes(field(data, self.amount, "amount")?),
+            message_type: match self.message_type {
+                Some(offset) => field::<1>(data, offset, "message_type")?[0],
+                None => 0,
+            },
+            reserved: [0; 6],
+        };
+        let expires_at = self
+            .expires_at
+            .map(|offset| field(data, offset, "expires_at").map(i64::from_le_bytes))
+            .transpose()?;
+        let mint = optional(self.mint, "mint").transpose()?;
+        // 按配置的字段推断版本，供 decode-pda 显示
+        let version = match (expires_at, mint) {
+            (_, Some(_)) => TransferInfoVersion::V3,
+            (Some(_), None) => TransferInfoVersion::V2,
+            (None, None) => TransferInfoVersion::V1,
+        };
+        Ok(TransferInfoAccount {
+            version,
+            fields,
+            expires_at,
+            mint,
+        })
+    }
+}
+
+/// Decoder of `layout`, the Borsh layout when unset (followed by the
+/// attestation when `attested`)
+pub fn from_layout(layout: Option<&TransferInfoLayout>, attested: bool) -> Arc<dyn MessageDecoder> {
+    match layout {
+        Some(layout) => Arc::new(layout.clone()),
+        None if attested => Arc::new(AttestedBorshDecoder),
+        None => Arc::new(BorshDecoder),
+    }
+}
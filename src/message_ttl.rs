@@ -0,0 +1,73 @@
+//! Message time-to-live.
+//! With `message_ttl_secs` set, the L1 slot and local time at which each
+//! message is first read are saved in the state (`messages_seen/<nonce>`),
+//! so they survive retries and restarts. A message read again more than the
+//! TTL after that first read, e.g. one held through an incident, is not
+//! relayed automatically: it is dead-lettered, and relays only once an
+//! operator resolves it as `replayed`. The first read of a message that
+//! emerges from a stopped relayer's backlog is the read after the restart,
+//! so that gap is left to `max_expected_jump`.
+
+use crate::{pipeline::FetchedTransfer, state::StateStore, Relayer};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// Prefix of the first-read records
+pub const MESSAGES_SEEN_PREFIX: &str = "messages_seen/";
+
+/// When a message was first read
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct MessageSeen {
+    pub l1_slot: u64,
+    pub seen_at: u64,
+}
+
+impl MessageSeen {
+    fn key(nonce: u64) -> String {
+        format!("{}{}", MESSAGES_SEEN_PREFIX, nonce)
+    }
+
+    pub fn load(store: &StateStore, nonce: u64) -> Result<Option<Self>> {
+        store.get(&Self::key(nonce))
+    }
+}
+
+impl Relayer {
+    /// Records the first read of `fetched` and dead-letters it once it is
+    /// older than the TTL, unless an operator replayed it
+    pub(crate) fn check_message_ttl(&self, fetched: &FetchedTransfer) -> Result<()> {
+        let Some(ttl) = self.expiry.message_ttl else {
+            return Ok(());
+        };
+        let now = self.clock.unix_timestamp();
+        let seen = match MessageSeen::load(&self.state, fetched.nonce)? {
+            Some(seen) => seen,
+            None => {
+                let seen = MessageSeen {
+                    l1_slot: fetched.l1_slot,
+                    seen_at: now,
+                };
+                self.state.put(&MessageSeen::key(fetched.nonce), &seen)?;
+                seen
+            }
+        };
+        let age = now.saturating_sub(seen.seen_at);
+        if age <= ttl.as_secs() || self.dead_letters.replayed(fetched.nonce) {
+            return Ok(());
+        }
+        let reason = format!(
+            "message first read at {} (L1 slot {}) is {}s old, past message_ttl_secs {}; replay it to relay",
+            seen.seen_at,
+            seen.l1_slot,
+            age,
+            ttl.as_secs()
+        );
+        self.metrics.stale_messages_total.inc();
+        self.dead_letter_transfer(fetched, &reason)?;
+        Err(anyhow::anyhow!(
+            "Nonce {} not relayed automatically: {}",
+            fetched.nonce,
+            reason
+        ))
+    }
+}
@@ -0,0 +1,1010 @@
+//! Relayer metrics.
+//! Values are kept in atomics and rendered in the Prometheus text format.
+//! Every source owns a `Metrics` set; the `MetricsRegistry` renders all sets
+//! together, labelling samples with their bridge and source id, after the
+//! unlabelled `relayer_build_info`, for the textfile and for `GET /metrics`
+//! on `metrics_listen`.
+
+use crate::build_info::BuildInfo;
+use anyhow::Result;
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// Longest a scraper may take to send its request and read the response
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Largest request head read from a scraper
+const MAX_REQUEST_BYTES: usize = 8192;
+
+/// A metric rendered as one family of samples
+trait Family {
+    fn header(&self, out: &mut String);
+    fn sample(&self, out: &mut String, labels: &str);
+}
+
+pub struct Gauge {
+    name: &'static str,
+    help: &'static str,
+    value: AtomicU64,
+}
+
+impl Gauge {
+    const fn new(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            value: AtomicU64::new(0),
+        }
+    }
+
+    pub fn set(&self, value: u64) {
+        self.value.store(value, Ordering::Relaxed);
+    }
+
+    /// Raises the gauge to `value` if it is higher
+    pub fn set_max(&self, value: u64) {
+        self.value.fetch_max(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+impl Family for Gauge {
+    fn header(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} gauge", self.name);
+    }
+
+    fn sample(&self, out: &mut String, labels: &str) {
+        let _ = writeln!(out, "{}{} {}", self.name, labels, self.get());
+    }
+}
+
+pub struct Counter {
+    name: &'static str,
+    help: &'static str,
+    value: AtomicU64,
+}
+
+impl Counter {
+    const fn new(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            value: AtomicU64::new(0),
+        }
+    }
+
+    pub fn inc(&self) {
+        self.inc_by(1);
+    }
+
+    pub fn inc_by(&self, value: u64) {
+        self.value.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+impl Family for Counter {
+    fn header(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} counter", self.name);
+    }
+
+    fn sample(&self, out: &mut String, labels: &str) {
+        let _ = writeln!(out, "{}{} {}", self.name, labels, self.get());
+    }
+}
+
+/// Observations counted in cumulative buckets of upper bounds
+pub struct Histogram {
+    name: &'static str,
+    help: &'static str,
+    bounds: &'static [u64],
+    /// One count per bound, then the count above the last bound
+    buckets: Vec<AtomicU64>,
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    fn new(name: &'static str, help: &'static str, bounds: &'static [u64]) -> Self {
+        Self {
+            name,
+            help,
+            bounds,
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, value: u64) {
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|&bound| value <= bound)
+            .unwrap_or(self.bounds.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+    }
+}
+
+impl Family for Histogram {
+    fn header(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} histogram", self.name);
+    }
+
+    fn sample(&self, out: &mut String, labels: &str) {
+        // 在已有标签后追加 le
+        let prefix = match labels.strip_suffix('}') {
+            Some(labels) => format!("{},", labels),
+            None => "{".to_string(),
+        };
+        let mut count = 0;
+        for (index, bucket) in self.buckets.iter().enumerate() {
+            count += bucket.load(Ordering::Relaxed);
+            let bound = self
+                .bounds
+                .get(index)
+                .map_or("+Inf".to_string(), ToString::to_string);
+            let _ = writeln!(
+                out,
+                "{}_bucket{}le=\"{}\"}} {}",
+                self.name, prefix, bound, count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_sum{} {}",
+            self.name,
+            labels,
+            self.sum.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(out, "{}_count{} {}", self.name, labels, count);
+    }
+}
+
+/// A gauge with one sample per value of `label`, e.g. per L1 endpoint
+pub struct LabelledGauge {
+    name: &'static str,
+    help: &'static str,
+    label: &'static str,
+    values: Mutex<BTreeMap<String, u64>>,
+}
+
+impl LabelledGauge {
+    fn new(name: &'static str, help: &'static str, label: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            label,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn set(&self, value_of: &str, value: u64) {
+        self.values
+            .lock()
+            .unwrap()
+            .insert(value_of.to_string(), value);
+    }
+}
+
+/// Samples of `values`, each with `label` appended to `labels`
+fn labelled_samples(
+    out: &mut String,
+    name: &str,
+    labels: &str,
+    label: &str,
+    values: &BTreeMap<String, u64>,
+) {
+    // 在已有标签后追加
+    let prefix = match labels.strip_suffix('}') {
+        Some(labels) => format!("{},", labels),
+        None => "{".to_string(),
+    };
+    for (value_of, value) in values {
+        let _ = writeln!(
+            out,
+            "{}{}{}=\"{}\"}} {}",
+            name, prefix, label, value_of, value
+        );
+    }
+}
+
+impl Family for LabelledGauge {
+    fn header(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} gauge", self.name);
+    }
+
+    fn sample(&self, out: &mut String, labels: &str) {
+        labelled_samples(
+            out,
+            self.name,
+            labels,
+            self.label,
+            &self.values.lock().unwrap(),
+        );
+    }
+}
+
+/// A counter with one sample per value of `label`, e.g. per confirmation
+/// strategy
+pub struct LabelledCounter {
+    name: &'static str,
+    help: &'static str,
+    label: &'static str,
+    values: Mutex<BTreeMap<String, u64>>,
+}
+
+impl LabelledCounter {
+    fn new(name: &'static str, help: &'static str, label: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            label,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn inc(&self, value_of: &str) {
+        *self
+            .values
+            .lock()
+            .unwrap()
+            .entry(value_of.to_string())
+            .or_default() += 1;
+    }
+}
+
+impl Family for LabelledCounter {
+    fn header(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} counter", self.name);
+    }
+
+    fn sample(&self, out: &mut String, labels: &str) {
+        labelled_samples(
+            out,
+            self.name,
+            labels,
+            self.label,
+            &self.values.lock().unwrap(),
+        );
+    }
+}
+
+pub struct Metrics {
+    /// Rendered label set, e.g. `{bridge="a",source="b"}`; empty for a single
+    /// unnamed source
+    labels: String,
+    pub queue_depth: Gauge,
+    pub queue_overflow: Gauge,
+    pub nonce_lag: Gauge,
+    pub l1_max_slot: Gauge,
+    pub l1_cache_hits_total: Counter,
+    pub l1_cache_stale_total: Counter,
+    pub l1_cache_failures_total: Counter,
+    pub l1_cache_staleness_slots: Gauge,
+    pub held_for_confirmation: Gauge,
+    pub signer_balance: Gauge,
+    pub signer_balance_low: Gauge,
+    pub top_ups_total: Counter,
+    pub top_up_failures_total: Counter,
+    pub balance_reserved: Gauge,
+    pub rebroadcasts_total: Counter,
+    pub confirmed_total: LabelledCounter,
+    pub finality_pending: LabelledGauge,
+    pub recipient_balance_mismatches_total: Counter,
+    pub node_behind_reads_total: LabelledCounter,
+    pub rpc_errors_total: LabelledCounter,
+    pub l1_subscription_connected: Gauge,
+    pub l1_subscription_notifications_total: Counter,
+    pub l1_subscription_failures_total: Counter,
+    pub poll_interval_ms: Gauge,
+    pub poll_suspended: Gauge,
+    pub l1_paused: Gauge,
+    pub l2_paused: Gauge,
+    pub l2_cap_held: Gauge,
+    pub l2_account_check_failed: Gauge,
+    pub l2_health_held: Gauge,
+    pub l2_health_lag_slots: Gauge,
+    pub l2_health_holds_total: Counter,
+    pub l1_endpoint_lag_slots: LabelledGauge,
+    pub l1_endpoint_finality_lag_slots: LabelledGauge,
+    pub l1_endpoint_nonce_lag: LabelledGauge,
+    pub l1_endpoint_quarantined: LabelledGauge,
+    pub storage_degraded: Gauge,
+    pub storage_buffered_writes: Gauge,
+    pub storage_degraded_seconds: Gauge,
+    pub attempts_per_transfer: Histogram,
+    pub relay_latency_seconds: Histogram,
+    pub standby_signer_balance: Gauge,
+    pub standby: Gauge,
+    pub standby_lag: Gauge,
+    pub retries_tracked: Gauge,
+    pub in_flight: Gauge,
+    pub dead_letters: Gauge,
+    pub enrichment_pending: Gauge,
+    pub priority_tracked: Gauge,
+    pub enrichment_evictions_total: Counter,
+    pub receipt_sink_queued: Gauge,
+    pub receipt_sink_lag_seconds: Gauge,
+    pub receipt_sink_shipped_total: Counter,
+    pub receipt_sink_failures_total: Counter,
+    pub receipt_sink_dropped_total: Counter,
+    pub event_stream_queued: Gauge,
+    pub transaction_splits_total: Counter,
+    pub lookup_table_addresses: Gauge,
+    pub lookup_table_extensions_total: Counter,
+    pub anomaly_breaker_tripped: Gauge,
+    pub discovered_pdas_ahead: Gauge,
+    pub event_stream_published_total: Counter,
+    pub event_stream_failures_total: Counter,
+    pub relayed_total: Counter,
+    pub failed_batches_total: Counter,
+    pub throttled_ms_total: Counter,
+    pub policy_violations_total: Counter,
+    pub send_rate_limited_ms_total: Counter,
+    pub skipped_messages_total: Counter,
+    pub dust_transfers_total: Counter,
+    pub receipt_pdas_closed_total: Counter,
+    pub receipt_pda_refunds_lamports_total: Counter,
+    pub expired_transfers_total: Counter,
+    pub stale_messages_total: Counter,
+    pub token_accounts_created_total: Counter,
+    pub token_account_rent_lamports_total: Counter,
+    pub cancelled_transfers_total: Counter,
+    pub l1_cancelled_transfers_total: Counter,
+    pub already_processed_total: Counter,
+    pub earlier_submissions_total: Counter,
+    pub l1_rewinds_total: Counter,
+    pub cancellation_races_total: Counter,
+    pub dual_read_agreements_total: Counter,
+    pub dual_read_lag_mismatches_total: Counter,
+    pub dual_read_hard_mismatches_total: Counter,
+    pub stale_snapshots_total: Counter,
+    pub missing_pda_reads_total: Counter,
+    pub batch_size: Gauge,
+    pub fee_price: Gauge,
+    pub compute_unit_price: Gauge,
+    pub batches_per_nonce_total: Counter,
+    pub batches_drain_total: Counter,
+    pub batches_fill_total: Counter,
+    pub batches_unpriced_total: Counter,
+    pub processing_rate: Gauge,
+    pub estimated_drain_seconds: Gauge,
+    pub recommended_replicas: Gauge,
+}
+
+impl Metrics {
+    /// Metrics of one source of `bridge`; `source` is `None` when there is
+    /// only the legacy source, `bridge` without `[[bridges]]`
+    pub fn new(bridge: Option<&str>, source: Option<&str>) -> Self {
+        let labels: Vec<String> = bridge
+            .map(|bridge| format!("bridge=\"{}\"", bridge))
+            .into_iter()
+            .chain(source.map(|source| format!("source=\"{}\"", source)))
+            .collect();
+        Self {
+            labels: if labels.is_empty() {
+                String::new()
+            } else {
+                format!("{{{}}}", labels.join(","))
+            },
+            queue_depth: Gauge::new(
+                "relayer_queue_depth",
+                "Nonces waiting in the in-memory pending queue",
+            ),
+            queue_overflow: Gauge::new(
+                "relayer_queue_overflow",
+                "Nonces spilled to disk because the pending queue was full",
+            ),
+            nonce_lag: Gauge::new(
+                "relayer_nonce_lag",
+                "Nonces observed on L1 that the L2 nonce account has not reached yet",
+            ),
+            l1_max_slot: Gauge::new(
+                "relayer_l1_max_slot",
+                "Highest L1 slot at which a message was read",
+            ),
+            l1_cache_hits_total: Counter::new(
+                "relayer_l1_cache_hits_total",
+                "Watched-account reads served by the account cache",
+            ),
+            l1_cache_stale_total: Counter::new(
+                "relayer_l1_cache_stale_total",
+                "Cached watched-account reads too stale to use, read from the L1 RPC instead",
+            ),
+            l1_cache_failures_total: Counter::new(
+                "relayer_l1_cache_failures_total",
+                "Account cache requests that failed or missed the watched account",
+            ),
+            l1_cache_staleness_slots: Gauge::new(
+                "relayer_l1_cache_staleness_slots",
+                "Slots the last cached watched-account read trailed the L1 RPC",
+            ),
+            held_for_confirmation: Gauge::new(
+                "relayer_held_for_confirmation",
+                "Observed nonces held until they are min_confirmation_slots old",
+            ),
+            poll_interval_ms: Gauge::new(
+                "relayer_poll_interval_ms",
+                "Current pause between monitor polls in milliseconds",
+            ),
+            poll_suspended: Gauge::new(
+                "relayer_poll_suspended",
+                "1 while polling is suspended because of the pending backlog",
+            ),
+            l1_paused: Gauge::new(
+                "relayer_l1_paused",
+                "1 while the L1 program reports bridging paused",
+            ),
+            l2_paused: Gauge::new(
+                "relayer_l2_paused",
+                "1 while the L2 bridge-config account reports bridging paused",
+            ),
+            l2_cap_held: Gauge::new(
+                "relayer_l2_cap_held",
+                "1 while a transfer is held for the mint cap of the L2 program",
+            ),
+            l2_account_check_failed: Gauge::new(
+                "relayer_l2_account_check_failed",
+                "1 while the L2 nonce or bridge-config account fails validation",
+            ),
+            l2_health_held: Gauge::new(
+                "relayer_l2_health_held",
+                "1 while submissions are held because the L2 cluster is unhealthy or behind",
+            ),
+            l2_health_lag_slots: Gauge::new(
+                "relayer_l2_health_lag_slots",
+                "Slots the L2 node trails its highest-known slot or the reference endpoint",
+            ),
+            l2_health_holds_total: Counter::new(
+                "relayer_l2_health_holds_total",
+                "Times submissions were held for L2 cluster health",
+            ),
+            l1_endpoint_lag_slots: LabelledGauge::new(
+                "relayer_l1_endpoint_lag_slots",
+                "Slots an L1 endpoint's confirmed view trails the best-known slot",
+                "endpoint",
+            ),
+            l1_endpoint_finality_lag_slots: LabelledGauge::new(
+                "relayer_l1_endpoint_finality_lag_slots",
+                "Slots an L1 endpoint's finalized view trails its confirmed view",
+                "endpoint",
+            ),
+            l1_endpoint_nonce_lag: LabelledGauge::new(
+                "relayer_l1_endpoint_nonce_lag",
+                "Nonces an L1 endpoint's confirmed watched account trails the highest seen",
+                "endpoint",
+            ),
+            l1_endpoint_quarantined: LabelledGauge::new(
+                "relayer_l1_endpoint_quarantined",
+                "1 while an L1 endpoint lags past l1_lag_quarantine_slots",
+                "endpoint",
+            ),
+            storage_degraded: Gauge::new(
+                "relayer_storage_degraded",
+                "1 while the state storage fails and writes are buffered in memory",
+            ),
+            storage_buffered_writes: Gauge::new(
+                "relayer_storage_buffered_writes",
+                "State writes and journal records waiting for the storage",
+            ),
+            storage_degraded_seconds: Gauge::new(
+                "relayer_storage_degraded_seconds",
+                "Seconds since the state storage started failing",
+            ),
+            attempts_per_transfer: Histogram::new(
+                "relayer_attempts_per_transfer",
+                "Attempts each relayed transfer took, counting the one that landed",
+                &[1, 2, 3, 5, 10, 20],
+            ),
+            relay_latency_seconds: Histogram::new(
+                "relayer_relay_latency_seconds",
+                "Seconds from observing a nonce on L1 to confirming it on L2",
+                &[1, 2, 5, 10, 30, 60, 300, 1800],
+            ),
+            rebroadcasts_total: Counter::new(
+                "relayer_rebroadcasts_total",
+                "Re-sends of transactions awaiting confirmation",
+            ),
+            confirmed_total: LabelledCounter::new(
+                "relayer_confirmed_total",
+                "Transactions confirmed under [[confirmation_rules]], by strategy",
+                "strategy",
+            ),
+            finality_pending: LabelledGauge::new(
+                "relayer_finality_pending",
+                "Landed transactions waiting to finalize, by strategy",
+                "strategy",
+            ),
+            recipient_balance_mismatches_total: Counter::new(
+                "relayer_recipient_balance_mismatches_total",
+                "Safe transfers whose recipient held less than the amount once finalized",
+            ),
+            node_behind_reads_total: LabelledCounter::new(
+                "relayer_node_behind_reads_total",
+                "Reads answered by a node behind the highest slot already seen, by cluster",
+                "cluster",
+            ),
+            rpc_errors_total: LabelledCounter::new(
+                "relayer_rpc_errors_total",
+                "RPC requests that failed, by cluster",
+                "cluster",
+            ),
+            l1_subscription_connected: Gauge::new(
+                "relayer_l1_subscription_connected",
+                "1 while the WebSocket subscription to the watched account is open",
+            ),
+            l1_subscription_notifications_total: Counter::new(
+                "relayer_l1_subscription_notifications_total",
+                "Watched-account notifications received over WebSocket",
+            ),
+            l1_subscription_failures_total: Counter::new(
+                "relayer_l1_subscription_failures_total",
+                "Times the WebSocket subscription to the watched account dropped or failed to open",
+            ),
+            signer_balance: Gauge::new(
+                "relayer_signer_balance",
+                "L2 balance of the active signer in lamports",
+            ),
+            signer_balance_low: Gauge::new(
+                "relayer_signer_balance_low",
+                "1 while the active signer's balance is below min_signer_balance",
+            ),
+            top_ups_total: Counter::new(
+                "relayer_top_ups_total",
+                "Runs of top_up_command that succeeded",
+            ),
+            top_up_failures_total: Counter::new(
+                "relayer_top_up_failures_total",
+                "Runs of top_up_command that failed or timed out",
+            ),
+            balance_reserved: Gauge::new(
+                "relayer_balance_reserved",
+                "Lamports of the active signer reserved by in-flight transactions",
+            ),
+            standby_signer_balance: Gauge::new(
+                "relayer_standby_signer_balance",
+                "L2 balance of the signer not in use in lamports",
+            ),
+            standby: Gauge::new(
+                "relayer_role_standby",
+                "1 while the relayer is a standby that does not submit",
+            ),
+            standby_lag: Gauge::new(
+                "relayer_standby_lag",
+                "Nonces relayed on L2 that the standby has not mirrored a receipt for",
+            ),
+            retries_tracked: Gauge::new(
+                "relayer_retries_tracked",
+                "Pending nonces with a recorded failed attempt",
+            ),
+            in_flight: Gauge::new(
+                "relayer_in_flight",
+                "Transactions sent and awaiting confirmation",
+            ),
+            dead_letters: Gauge::new("relayer_dead_letters", "Nonces in the dead-letter queue"),
+            enrichment_pending: Gauge::new(
+                "relayer_enrichment_pending",
+                "Confirmed receipts waiting to be enriched",
+            ),
+            priority_tracked: Gauge::new(
+                "relayer_priority_tracked",
+                "Nonces remembered by priority ordering (ranked or relayed ahead)",
+            ),
+            enrichment_evictions_total: Counter::new(
+                "relayer_enrichment_evictions_total",
+                "Receipts dropped from the full enrichment queue, left for enrich-receipts",
+            ),
+            receipt_sink_queued: Gauge::new(
+                "relayer_receipt_sink_queued",
+                "Saved receipts waiting to ship to the receipt sink",
+            ),
+            receipt_sink_lag_seconds: Gauge::new(
+                "relayer_receipt_sink_lag_seconds",
+                "Age of the oldest receipt waiting to ship to the receipt sink",
+            ),
+            receipt_sink_shipped_total: Counter::new(
+                "relayer_receipt_sink_shipped_total",
+                "Receipts shipped to the receipt sink",
+            ),
+            receipt_sink_failures_total: Counter::new(
+                "relayer_receipt_sink_failures_total",
+                "Failed shipments to the receipt sink",
+            ),
+            receipt_sink_dropped_total: Counter::new(
+                "relayer_receipt_sink_dropped_total",
+                "Receipts dropped from the full receipt sink queue, left for the catch-up",
+            ),
+            transaction_splits_total: Counter::new(
+                "relayer_transaction_splits_total",
+                "Relay transactions split to fit the size or compute limit",
+            ),
+            lookup_table_addresses: Gauge::new(
+                "relayer_lookup_table_addresses",
+                "Addresses in the loaded L2 address lookup tables",
+            ),
+            lookup_table_extensions_total: Counter::new(
+                "relayer_lookup_table_extensions_total",
+                "Transactions creating or extending the relayer's lookup table",
+            ),
+            anomaly_breaker_tripped: Gauge::new(
+                "relayer_anomaly_breaker_tripped",
+                "1 while the anomaly circuit breaker holds submissions",
+            ),
+            discovered_pdas_ahead: Gauge::new(
+                "relayer_discovered_pdas_ahead",
+                "Unrelayed transfer PDAs the last discovery scan found at or past the watched-account nonce",
+            ),
+            event_stream_queued: Gauge::new(
+                "relayer_event_stream_queued",
+                "Relay events waiting in the outbox to publish",
+            ),
+            event_stream_published_total: Counter::new(
+                "relayer_event_stream_published_total",
+                "Relay events published to the event stream",
+            ),
+            event_stream_failures_total: Counter::new(
+                "relayer_event_stream_failures_total",
+                "Failed publications to the event stream",
+            ),
+            relayed_total: Counter::new("relayer_relayed_total", "Transfers relayed to L2"),
+            failed_batches_total: Counter::new(
+                "relayer_failed_batches_total",
+                "Submission batches that failed",
+            ),
+            throttled_ms_total: Counter::new(
+                "relayer_throttled_ms_total",
+                "Milliseconds submissions were held by the throughput limit",
+            ),
+            policy_violations_total: Counter::new(
+                "relayer_policy_violations_total",
+                "Transfers dead-lettered for violating the transfer policy",
+            ),
+            send_rate_limited_ms_total: Counter::new(
+                "relayer_send_rate_limited_ms_total",
+                "Milliseconds L2 sends waited for the send rate limit",
+            ),
+            skipped_messages_total: Counter::new(
+                "relayer_skipped_messages_total",
+                "Messages of an unknown type skipped without relaying",
+            ),
+            dust_transfers_total: Counter::new(
+                "relayer_dust_transfers_total",
+                "Transfers below the dust threshold skipped or accumulated without relaying",
+            ),
+            receipt_pdas_closed_total: Counter::new(
+                "relayer_receipt_pdas_closed_total",
+                "L2 receipt PDAs closed outside the retention window",
+            ),
+            receipt_pda_refunds_lamports_total: Counter::new(
+                "relayer_receipt_pda_refunds_lamports_total",
+                "Rent refunded to the collection account by closed receipt PDAs",
+            ),
+            expired_transfers_total: Counter::new(
+                "relayer_expired_transfers_total",
+                "Transfers skipped without relaying because their deadline passed",
+            ),
+            stale_messages_total: Counter::new(
+                "relayer_stale_messages_total",
+                "Messages dead-lettered because they were older than message_ttl_secs",
+            ),
+            token_accounts_created_total: Counter::new(
+                "relayer_token_accounts_created_total",
+                "Recipient token accounts created by the relayer",
+            ),
+            token_account_rent_lamports_total: Counter::new(
+                "relayer_token_account_rent_lamports_total",
+                "Lamports of rent the relayer paid for recipient token accounts",
+            ),
+            cancelled_transfers_total: Counter::new(
+                "relayer_cancelled_transfers_total",
+                "Transfers cancelled by an operator during their recipient's quiet period",
+            ),
+            l1_cancelled_transfers_total: Counter::new(
+                "relayer_l1_cancelled_transfers_total",
+                "Transfers not relayed because the L1 cancellation registry listed them",
+            ),
+            already_processed_total: Counter::new(
+                "relayer_already_processed_total",
+                "Transfers not sent because the L2 program already marked their nonce processed",
+            ),
+            earlier_submissions_total: Counter::new(
+                "relayer_earlier_submissions_total",
+                "Transfers not sent because an earlier relay of their nonce was found in L2 history",
+            ),
+            l1_rewinds_total: Counter::new(
+                "relayer_l1_rewinds_total",
+                "Times the L1 watched nonce went back below the highest one seen",
+            ),
+            cancellation_races_total: Counter::new(
+                "relayer_cancellation_races_total",
+                "Transfers that landed on L2 although the L1 cancellation registry listed them after they were sent",
+            ),
+            dual_read_agreements_total: Counter::new(
+                "relayer_dual_read_agreements_total",
+                "High-value L1 reads a verification provider agreed with",
+            ),
+            dual_read_lag_mismatches_total: Counter::new(
+                "relayer_dual_read_lag_mismatches_total",
+                "High-value L1 reads held because a verification provider was behind",
+            ),
+            dual_read_hard_mismatches_total: Counter::new(
+                "relayer_dual_read_hard_mismatches_total",
+                "High-value L1 reads held because a verification provider disagreed",
+            ),
+            stale_snapshots_total: Counter::new(
+                "relayer_stale_snapshots_total",
+                "L1 snapshots whose watched-account nonce did not cover the PDAs read with them",
+            ),
+            missing_pda_reads_total: Counter::new(
+                "relayer_missing_pda_reads_total",
+                "Reads of a queued nonce whose transfer-info PDA did not exist yet",
+            ),
+            batch_size: Gauge::new(
+                "relayer_batch_size",
+                "Nonces the last fee-aware batch decision allowed",
+            ),
+            fee_price: Gauge::new(
+                "relayer_fee_price_micro_lamports",
+                "Median recent L2 prioritization fee in micro-lamports per compute unit",
+            ),
+            compute_unit_price: Gauge::new(
+                "relayer_compute_unit_price_micro_lamports",
+                "Compute-unit price of the last relay transaction in micro-lamports",
+            ),
+            batches_per_nonce_total: Counter::new(
+                "relayer_batches_per_nonce_total",
+                "Fee-aware batches of one nonce sent at once (fees low, backlog small)",
+            ),
+            batches_drain_total: Counter::new(
+                "relayer_batches_drain_total",
+                "Fee-aware batches sent at once while fees were low",
+            ),
+            batches_fill_total: Counter::new(
+                "relayer_batches_fill_total",
+                "Fee-aware batches that waited to fill while fees were high",
+            ),
+            batches_unpriced_total: Counter::new(
+                "relayer_batches_unpriced_total",
+                "Fee-aware batches of the configured size because the fee price was unavailable",
+            ),
+            processing_rate: Gauge::new(
+                "relayer_processing_rate_per_minute",
+                "Moving average of transfers relayed per minute",
+            ),
+            estimated_drain_seconds: Gauge::new(
+                "relayer_estimated_drain_seconds",
+                "Seconds to drain the pending backlog at the processing rate (0 without a rate)",
+            ),
+            recommended_replicas: Gauge::new(
+                "relayer_recommended_replicas",
+                "Replicas that would drain the backlog within scale_target_drain_secs",
+            ),
+        }
+    }
+
+    /// Every metric, in rendering order
+    fn families(&self) -> Vec<&dyn Family> {
+        vec![
+            &self.queue_depth,
+            &self.queue_overflow,
+            &self.nonce_lag,
+            &self.l1_max_slot,
+            &self.l1_cache_hits_total,
+            &self.l1_cache_stale_total,
+            &self.l1_cache_failures_total,
+            &self.l1_cache_staleness_slots,
+            &self.held_for_confirmation,
+            &self.poll_interval_ms,
+            &self.poll_suspended,
+            &self.l1_paused,
+            &self.l2_paused,
+            &self.l2_cap_held,
+            &self.l2_account_check_failed,
+            &self.l2_health_held,
+            &self.l2_health_lag_slots,
+            &self.l2_health_holds_total,
+            &self.l1_endpoint_lag_slots,
+            &self.l1_endpoint_finality_lag_slots,
+            &self.l1_endpoint_nonce_lag,
+            &self.l1_endpoint_quarantined,
+            &self.storage_degraded,
+            &self.storage_buffered_writes,
+            &self.storage_degraded_seconds,
+            &self.attempts_per_transfer,
+            &self.relay_latency_seconds,
+            &self.signer_balance,
+            &self.signer_balance_low,
+            &self.top_ups_total,
+            &self.top_up_failures_total,
+            &self.balance_reserved,
+            &self.rebroadcasts_total,
+            &self.confirmed_total,
+            &self.finality_pending,
+            &self.recipient_balance_mismatches_total,
+            &self.node_behind_reads_total,
+            &self.rpc_errors_total,
+            &self.l1_subscription_connected,
+            &self.l1_subscription_notifications_total,
+            &self.l1_subscription_failures_total,
+            &self.standby_signer_balance,
+            &self.standby,
+            &self.standby_lag,
+            &self.retries_tracked,
+            &self.in_flight,
+            &self.dead_letters,
+            &self.enrichment_pending,
+            &self.priority_tracked,
+            &self.enrichment_evictions_total,
+            &self.receipt_sink_queued,
+            &self.receipt_sink_lag_seconds,
+            &self.receipt_sink_shipped_total,
+            &self.receipt_sink_failures_total,
+            &self.receipt_sink_dropped_total,
+            &self.event_stream_queued,
+            &self.transaction_splits_total,
+            &self.lookup_table_addresses,
+            &self.lookup_table_extensions_total,
+            &self.anomaly_breaker_tripped,
+            &self.discovered_pdas_ahead,
+            &self.event_stream_published_total,
+            &self.event_stream_failures_total,
+            &self.relayed_total,
+            &self.failed_batches_total,
+            &self.throttled_ms_total,
+            &self.policy_violations_total,
+            &self.send_rate_limited_ms_total,
+            &self.skipped_messages_total,
+            &self.dust_transfers_total,
+            &self.receipt_pdas_closed_total,
+            &self.receipt_pda_refunds_lamports_total,
+            &self.expired_transfers_total,
+            &self.stale_messages_total,
+            &self.token_accounts_created_total,
+            &self.token_account_rent_lamports_total,
+            &self.cancelled_transfers_total,
+            &self.l1_cancelled_transfers_total,
+            &self.already_processed_total,
+            &self.earlier_submissions_total,
+            &self.l1_rewinds_total,
+            &self.cancellation_races_total,
+            &self.dual_read_agreements_total,
+            &self.dual_read_lag_mismatches_total,
+            &self.dual_read_hard_mismatches_total,
+            &self.stale_snapshots_total,
+            &self.missing_pda_reads_total,
+            &self.batch_size,
+            &self.fee_price,
+            &self.compute_unit_price,
+            &self.batches_per_nonce_total,
+            &self.batches_drain_total,
+            &self.batches_fill_total,
+            &self.batches_unpriced_total,
+            &self.processing_rate,
+            &self.estimated_drain_seconds,
+            &self.recommended_replicas,
+        ]
+    }
+}
+
+/// All sources' metrics, rendered as one exposition
+pub struct MetricsRegistry {
+    sets: Vec<Arc<Metrics>>,
+}
+
+impl MetricsRegistry {
+    pub fn new(sets: Vec<Arc<Metrics>>) -> Self {
+        Self { sets }
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = BuildInfo::current().to_prometheus();
+        let Some(first) = self.sets.first() else {
+            return out;
+        };
+
+        for (index, family) in first.families().iter().enumerate() {
+            family.header(&mut out);
+            for set in &self.sets {
+                set.families()[index].sample(&mut out, &set.labels);
+            }
+        }
+        out
+    }
+
+    /// Atomically writes the rendered metrics to `path` (node_exporter textfile collector)
+    pub fn write_textfile<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("prom.tmp");
+        fs::write(&tmp, self.render())?;
+        fs::rename(&tmp, path)
+            .map_err(|e| anyhow::anyhow!("Failed to write metrics to {}: {}", path.display(), e))
+    }
+
+    /// Answers `GET /metrics` on `listener` with the rendered metrics, one
+    /// connection at a time; never returns
+    pub async fn serve(&self, listener: TcpListener) -> Result<()> {
+        loop {
+            let mut stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    tracing::warn!("failed to accept a metrics connection: {}", e);
+                    continue;
+                }
+            };
+            // 抓取超时直接断开，避免卡住后续请求
+            match tokio::time::timeout(SCRAPE_TIMEOUT, self.answer(&mut stream)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => tracing::warn!("failed to answer a metrics scrape: {}", e),
+                Err(_) => tracing::warn!("metrics scrape timed out"),
+            }
+        }
+    }
+
+    /// Reads one HTTP request from `stream` and writes the response
+    async fn answer(&self, stream: &mut TcpStream) -> std::io::Result<()> {
+        let mut head = Vec::new();
+        let mut buffer = [0u8; 1024];
+        while head.len() < MAX_REQUEST_BYTES && !head.windows(4).any(|w| w == b"\r\n\r\n") {
+            let read = stream.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            head.extend_from_slice(&buffer[..read]);
+        }
+        let head = String::from_utf8_lossy(&head);
+        let mut request = head.lines().next().unwrap_or_default().split_whitespace();
+        let method = request.next().unwrap_or_default();
+        let path = request
+            .next()
+            .unwrap_or_default()
+            .split('?')
+            .next()
+            .unwrap_or_default();
+        let (status, body) = match (method, path) {
+            ("GET", "/metrics") => ("200 OK", self.render()),
+            (_, "/metrics") => ("405 Method Not Allowed", "Use GET\n".to_string()),
+            _ => (
+                "404 Not Found",
+                "Metrics are served on /metrics\n".to_string(),
+            ),
+        };
+        let content_type = if status == "200 OK" {
+            "text/plain; version=0.0.4; charset=utf-8"
+        } else {
+            "text/plain; charset=utf-8"
+        };
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            content_type,
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await
+    }
+}
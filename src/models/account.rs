@@ -0,0 +1,292 @@
+//! Borsh layouts of the L1 accounts the relayer decodes.
+//! Account data is the 8-byte discriminator followed by the Borsh encoding
+//! of the account's fields; the legacy watched-account layout has no
+//! discriminator. With `[discriminators]` (or `[sources.discriminators]`)
+//! set, the discriminators of the transfer-info PDAs and of a two-counter
+//! watched account are checked, either as raw hex or as Anchor's
+//! `anchor:<AccountName>`, so an account of another type is never decoded.
+//! A transfer-info PDA starting with the `transfer_info_entries`
+//! discriminator holds several transfers instead: a u32 LE count, then each
+//! entry's fields in the V3 layout.
+
+use crate::decode;
+use anyhow::Result;
+use borsh::BorshDeserialize;
+use serde::{Deserialize, Serialize};
+use solana_sdk::{hash::hash, pubkey::Pubkey};
+
+/// Bytes before the Borsh-encoded fields
+pub const DISCRIMINATOR_LEN: usize = 8;
+
+pub type Discriminator = [u8; DISCRIMINATOR_LEN];
+
+/// Discriminators checked before decoding a source's accounts
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AccountDiscriminators {
+    /// Of every transfer-info PDA
+    #[serde(default)]
+    pub transfer_info: Option<String>,
+    /// Of the watched account, in the two-counter layout
+    #[serde(default)]
+    pub watched: Option<String>,
+    /// Of transfer-info PDAs holding several entries; none are read unless
+    /// set
+    #[serde(default)]
+    pub transfer_info_entries: Option<String>,
+}
+
+impl AccountDiscriminators {
+    /// (transfer info, watched account) discriminators
+    pub fn parse(&self) -> Result<(Option<Discriminator>, Option<Discriminator>)> {
+        let parse = |value: &Option<String>, field: &str| {
+            value
+                .as_deref()
+                .map(parse_discriminator)
+                .transpose()
+                .map_err(|e| anyhow::anyhow!("Invalid discriminators.{}: {}", field, e))
+        };
+        Ok((
+            parse(&self.transfer_info, "transfer_info")?,
+            parse(&self.watched, "watched")?,
+        ))
+    }
+
+    /// Discriminator of multi-entry transfer-info PDAs, which must differ
+    /// from the single-entry one
+    pub fn entries(&self) -> Result<Option<Discriminator>> {
+        let Some(value) = self.transfer_info_entries.as_deref() else {
+            return Ok(None);
+        };
+        let discriminator = parse_discriminator(value)
+            .map_err(|e| anyhow::anyhow!("Invalid discriminators.transfer_info_entries: {}", e))?;
+        if self.parse()?.0 == Some(discriminator) {
+            return Err(anyhow::anyhow!(
+                "discriminators.transfer_info_entries must differ from discriminators.transfer_info"
+            ));
+        }
+        Ok(Some(discriminator))
+    }
+}
+
+/// Anchor's discriminator of the account type `name`: the first 8 bytes of
+/// the SHA-256 of `account:<name>`
+pub fn anchor_discriminator(name: &str) -> Discriminator {
+    let digest = hash(format!("account:{}", name).as_bytes()).to_bytes();
+    let mut discriminator = [0u8; DISCRIMINATOR_LEN];
+    discriminator.copy_from_slice(&digest[..DISCRIMINATOR_LEN]);
+    discriminator
+}
+
+/// `anchor:<AccountName>` or 16 hex digits
+pub fn parse_discriminator(value: &str) -> Result<Discriminator> {
+    if let Some(name) = value.strip_prefix("anchor:") {
+        if name.is_empty() {
+            return Err(anyhow::anyhow!("anchor: needs an account name"));
+        }
+        return Ok(anchor_discriminator(name));
+    }
+    if value.len() != DISCRIMINATOR_LEN * 2 {
+        return Err(anyhow::anyhow!(
+            "expected anchor:<AccountName> or 16 hex digits, got {:?}",
+            value
+        ));
+    }
+    let mut discriminator = [0u8; DISCRIMINATOR_LEN];
+    for (index, byte) in discriminator.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&value[index * 2..index * 2 + 2], 16)
+            .map_err(|_| anyhow::anyhow!("{:?} is not hex", value))?;
+    }
+    Ok(discriminator)
+}
+
+/// Fails unless `data` starts with `expected`
+pub fn check_discriminator(data: &[u8], expected: &Discriminator) -> Result<()> {
+    let found = data.get(..DISCRIMINATOR_LEN).unwrap_or(data);
+    if found != expected {
+        return Err(anyhow::anyhow!(
+            "Account discriminator {} does not match the expected {} ({})",
+            decode::hex(found),
+            decode::hex(expected),
+            decode::hex_prefix(data)
+        ));
+    }
+    Ok(())
+}
+
+/// Fields of the two-counter watched account, after the discriminator
+#[derive(Debug, Clone, Copy, BorshDeserialize)]
+pub struct TwoCounterFields {
+    pub in_nonce: u64,
+    pub out_nonce: u64,
+    /// Kept as a byte so an invalid flag is reported as such
+    pub paused: u8,
+}
+
+/// Length of the ed25519 attestation signature that follows the versioned body
+/// at a source reading attestations
+pub const ATTESTATION_LEN: usize = 64;
+
+/// Fields every transfer-info PDA starts with, after the discriminator
+#[derive(Debug, Clone, Copy, BorshDeserialize)]
+pub struct TransferInfoFields {
+    /// L1 address that sent the transfer; the default key when unset
+    pub sender: Pubkey,
+    pub recipient: Pubkey,
+    pub amount: u64,
+    pub message_type: u8,
+    pub reserved: [u8; 6],
+}
+
+/// Version of the transfer-info layout, told apart by the data size
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TransferInfoVersion {
+    /// The base fields only
+    V1,
+    /// `expires_at` (i64) after them
+    V2,
+    /// `expires_at` and the L1 `mint` of a token or NFT message
+    V3,
+}
+
+impl TransferInfoVersion {
+    pub const V1_SIZE: usize = 87;
+    pub const V2_SIZE: usize = 95;
+    pub const V3_SIZE: usize = 127;
+
+    /// Newest version that fits in `data_len` bytes; trailing bytes are
+    /// ignored. None below the base size
+    pub fn detect(data_len: usize) -> Option<Self> {
+        match data_len {
+            len if len >= Self::V3_SIZE => Some(Self::V3),
+            len if len >= Self::V2_SIZE => Some(Self::V2),
+            len if len >= Self::V1_SIZE => Some(Self::V1),
+            _ => None,
+        }
+    }
+
+    /// Size of the body in this version
+    pub const fn size(self) -> usize {
+        match self {
+            Self::V1 => Self::V1_SIZE,
+            Self::V2 => Self::V2_SIZE,
+            Self::V3 => Self::V3_SIZE,
+        }
+    }
+
+    /// Version of a PDA at a source reading attestations, and whether the
+    /// signature follows the body. The size must be a body size exactly, or
+    /// one plus the signature; None for any other size
+    pub fn detect_attested(data_len: usize) -> Option<(Self, bool)> {
+        [Self::V1, Self::V2, Self::V3]
+            .into_iter()
+            .find_map(|version| match data_len.checked_sub(version.size()) {
+                Some(0) => Some((version, false)),
+                Some(ATTESTATION_LEN) => Some((version, true)),
+                _ => None,
+            })
+    }
+}
+
+/// Bytes of each entry of a multi-entry PDA: the V3 fields without a
+/// discriminator
+pub const ENTRY_SIZE: usize = TransferInfoVersion::V3_SIZE - DISCRIMINATOR_LEN;
+
+/// Most entries a multi-entry PDA holds
+pub const MAX_ENTRIES: usize = 8;
+
+/// A decoded transfer-info PDA
+#[derive(Debug, Clone, Copy)]
+pub struct TransferInfoAccount {
+    pub version: TransferInfoVersion,
+    pub fields: TransferInfoFields,
+    /// Raw deadline, from V2
+    pub expires_at: Option<i64>,
+    /// Raw L1 mint, from V3
+    pub mint: Option<Pubkey>,
+}
+
+impl TransferInfoAccount {
+    /// Splits the data of a PDA at a source reading attestations into the
+    /// versioned body and the signature after it, if any
+    pub fn split_attested(data: &[u8]) -> Result<(&[u8], Option<&[u8]>)> {
+        let (version, signed) = TransferInfoVersion::detect_attested(data.len()).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Transfer-info PDA has {} bytes, no attested layout size ({}, {} or {} bytes, each optionally followed by a {}-byte signature) ({})",
+                data.len(),
+                TransferInfoVersion::V1_SIZE,
+                TransferInfoVersion::V2_SIZE,
+                TransferInfoVersion::V3_SIZE,
+                ATTESTATION_LEN,
+                decode::hex_prefix(data)
+            )
+        })?;
+        let (body, signature) = data.split_at(version.size());
+        Ok((body, signed.then_some(signature)))
+    }
+
+    /// Splits the data of a multi-entry PDA into the V3 data of each entry,
+    /// behind the PDA's discriminator, so every entry decodes like a
+    /// single-entry PDA. Trailing bytes are ignored
+    pub fn split_entries(data: &[u8]) -> Result<Vec<Vec<u8>>> {
+        let count = data
+            .get(DISCRIMINATOR_LEN..DISCRIMINATOR_LEN + 4)
+            .map(|count| u32::from_le_bytes(count.try_into().unwrap()) as usize)
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Multi-entry transfer-info PDA has {} bytes, too few for its entry count ({})",
+                    data.len(),
+                    decode::hex_prefix(data)
+                )
+            })?;
+        if count == 0 || count > MAX_ENTRIES {
+            return Err(anyhow::anyhow!(
+                "Multi-entry transfer-info PDA holds {} entries, expected 1 to {} ({})",
+                count,
+                MAX_ENTRIES,
+                decode::hex_prefix(data)
+            ));
+        }
+        let entries = &data[DISCRIMINATOR_LEN + 4..];
+        if entries.len() < count * ENTRY_SIZE {
+            return Err(anyhow::anyhow!(
+                "Multi-entry transfer-info PDA of {} entries has {} bytes, expected {} ({})",
+                count,
+                data.len(),
+                DISCRIMINATOR_LEN + 4 + count * ENTRY_SIZE,
+                decode::hex_prefix(data)
+            ));
+        }
+        Ok(entries
+            .chunks_exact(ENTRY_SIZE)
+            .take(count)
+            .map(|entry| [&data[..DISCRIMINATOR_LEN], entry].concat())
+            .collect())
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let version = TransferInfoVersion::detect(data.len()).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Insufficient PDA account data length: expected {} bytes, got {} bytes ({})",
+                TransferInfoVersion::V1_SIZE,
+                data.len(),
+                decode::hex_prefix(data)
+            )
+        })?;
+        let mut rest = &data[DISCRIMINATOR_LEN..];
+        let fields = TransferInfoFields::deserialize(&mut rest)?;
+        let expires_at = match version {
+            TransferInfoVersion::V1 => None,
+            _ => Some(BorshDeserialize::deserialize(&mut rest)?),
+        };
+        let mint = match version {
+            TransferInfoVersion::V3 => Some(BorshDeserialize::deserialize(&mut rest)?),
+            _ => None,
+        };
+        Ok(Self {
+            version,
+            fields,
+            expires_at,
+            mint,
+        })
+    }
+}
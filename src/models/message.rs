@@ -1,21 +1,386 @@
+use crate::{
+    decode,
+    models::account::{TwoCounterFields, DISCRIMINATOR_LEN},
+};
 use anyhow::Result;
+use borsh::BorshDeserialize;
+use serde::{Deserialize, Serialize};
+use solana_sdk::{
+    hash::{hash, Hash},
+    pubkey::Pubkey,
+};
+use std::str::FromStr;
 
+/// Kind of message carried by a transfer-info PDA
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageType {
+    /// Native SOL transfer, relayed by `relay_message`
+    Native,
+    Token,
+    Nft,
+    /// Raw id the source's mapping does not know, e.g. a variant added to
+    /// the L1 program after this relayer was configured
+    #[serde(skip)]
+    Unknown(u8),
+}
+
+impl std::fmt::Display for MessageType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MessageType::Native => f.write_str("native"),
+            MessageType::Token => f.write_str("token"),
+            MessageType::Nft => f.write_str("nft"),
+            MessageType::Unknown(id) => write!(f, "unknown type {}", id),
+        }
+    }
+}
+
+/// What to do with a message of an unknown type (`unknown_message_type`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnknownTypePolicy {
+    /// Fail the batch and retry until the relayer is upgraded
+    Halt,
+    /// Record a receipt without relaying and move on
+    Skip,
+    /// Park the nonce in the dead-letter queue for replay after an upgrade
+    #[default]
+    DeadLetter,
+}
+
+/// Size of the two-counter watched-account layout:
+/// `discriminator | in_nonce u64 | out_nonce u64 | paused u8`
+pub const TWO_COUNTER_STATUS_SIZE: usize = 25;
+
+/// Layout of the L1 watched account
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchedLayout {
+    /// The nonce as the first 8 bytes, whatever follows
+    Legacy,
+    /// `TWO_COUNTER_STATUS_SIZE` bytes with the paused flag
+    TwoCounter,
+}
+
+impl WatchedLayout {
+    /// Layout of an account of `data_len` bytes read for the first time
+    pub fn detect(data_len: usize) -> Option<Self> {
+        match data_len {
+            TWO_COUNTER_STATUS_SIZE => Some(WatchedLayout::TwoCounter),
+            len if len >= 8 => Some(WatchedLayout::Legacy),
+            _ => None,
+        }
+    }
+
+    /// Layout of the account after a realloc to `data_len` bytes. Only the
+    /// two-counter layout has a size of its own; any other size may be a
+    /// layout this relayer does not know, where the first 8 bytes are no
+    /// longer the nonce
+    pub fn after_resize(data_len: usize) -> Option<Self> {
+        (data_len == TWO_COUNTER_STATUS_SIZE).then_some(WatchedLayout::TwoCounter)
+    }
+}
+
+impl std::fmt::Display for WatchedLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            WatchedLayout::Legacy => "legacy",
+            WatchedLayout::TwoCounter => "two-counter",
+        })
+    }
+}
+
+#[derive(Debug)]
 pub struct NonceStatus {
+    /// Nonce of the next L1 → L2 transfer (`out_nonce` in the two-counter layout)
     pub nonce: u64,
+    /// Inbound counter, only in the two-counter layout
+    pub in_nonce: Option<u64>,
+    /// Whether the L1 program has paused bridging; never set in the legacy layout
+    pub paused: bool,
 }
 
 impl NonceStatus {
+    /// Parses the legacy layout (the nonce as the first 8 bytes) or, for
+    /// accounts of exactly `TWO_COUNTER_STATUS_SIZE` bytes, the two-counter one
     pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let layout = WatchedLayout::detect(data.len()).unwrap_or(WatchedLayout::Legacy);
+        Self::parse(data, layout)
+    }
+
+    /// Parses `data` in `layout`
+    pub fn parse(data: &[u8], layout: WatchedLayout) -> Result<Self> {
+        if layout == WatchedLayout::TwoCounter {
+            if data.len() != TWO_COUNTER_STATUS_SIZE {
+                return Err(anyhow::anyhow!(
+                    "Invalid data length: expected {} bytes for the two-counter layout, got {} ({})",
+                    TWO_COUNTER_STATUS_SIZE,
+                    data.len(),
+                    decode::hex_prefix(data)
+                ));
+            }
+            let fields = TwoCounterFields::try_from_slice(&data[DISCRIMINATOR_LEN..])?;
+            let paused = match fields.paused {
+                0 => false,
+                1 => true,
+                flag => {
+                    return Err(anyhow::anyhow!(
+                        "Invalid paused flag {} in two-counter nonce account ({})",
+                        flag,
+                        decode::hex_prefix(data)
+                    ))
+                }
+            };
+            return Ok(Self {
+                nonce: fields.out_nonce,
+                in_nonce: Some(fields.in_nonce),
+                paused,
+            });
+        }
+
         if data.len() < 8 {
             return Err(anyhow::anyhow!(
-                "Invalid data length: expected at least 8 bytes, got {}",
-                data.len()
+                "Invalid data length: expected at least 8 bytes, got {} ({})",
+                data.len(),
+                decode::hex_prefix(data)
             ));
         }
 
         let nonce_bytes: [u8; 8] = data[0..8].try_into()?;
         let nonce = u64::from_le_bytes(nonce_bytes);
 
-        Ok(Self { nonce })
+        Ok(Self {
+            nonce,
+            in_nonce: None,
+            paused: false,
+        })
+    }
+}
+
+/// Version of `Info::canonical_bytes` written by this relayer. Any change to
+/// the encoding must bump it and add vectors for the new version to
+/// `test-vectors/info.json`
+pub const INFO_ENCODING_VERSION: u8 = 1;
+
+/// Prefix of the canonical encoding, separating message digests from other
+/// hashed data
+const INFO_DOMAIN_TAG: &[u8] = b"sol-bridge-relayer/info";
+
+/// Frozen encodings checked by `check_info_vectors`
+const INFO_VECTORS: &str = include_str!("../../test-vectors/info.json");
+
+/// A transfer message, independent of the layout of the account it was read
+/// from.
+///
+/// Canonical encoding, version 1, all integers little-endian, no padding:
+///
+/// | Bytes | Field |
+/// |-------|-------|
+/// | 23 | `b"sol-bridge-relayer/info"` |
+/// | 1 | version (`1`) |
+/// | 32 | source account: the source's L1 watched account |
+/// | 8 | nonce (`u64`) |
+/// | 32 | recipient |
+/// | 8 | amount in lamports (`u64`) |
+/// | 1 or 2 | message type: `0`, or `1` and the raw type id |
+/// | 1 or 9 | deadline: `0`, or `1` and the Unix time (`u64`) |
+///
+/// The sender and the attestation are not part of it, since not every
+/// message source carries them. `digest` is the SHA-256 of these bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Info {
+    pub source_account: Pubkey,
+    pub nonce: u64,
+    pub recipient: Pubkey,
+    pub amount: u64,
+    /// Raw message-type id, when the message source carries one
+    pub message_type: Option<u8>,
+    /// Unix time after which the transfer must not be executed on L2
+    pub expires_at: Option<u64>,
+}
+
+impl Info {
+    pub fn canonical_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(115);
+        bytes.extend_from_slice(INFO_DOMAIN_TAG);
+        bytes.push(INFO_ENCODING_VERSION);
+        bytes.extend_from_slice(self.source_account.as_ref());
+        bytes.extend_from_slice(&self.nonce.to_le_bytes());
+        bytes.extend_from_slice(self.recipient.as_ref());
+        bytes.extend_from_slice(&self.amount.to_le_bytes());
+        match self.message_type {
+            Some(id) => bytes.extend_from_slice(&[1, id]),
+            None => bytes.push(0),
+        }
+        match self.expires_at {
+            Some(expires_at) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&expires_at.to_le_bytes());
+            }
+            None => bytes.push(0),
+        }
+        bytes
+    }
+
+    pub fn digest(&self) -> Hash {
+        hash(&self.canonical_bytes())
+    }
+}
+
+/// A frozen encoding of `Info`
+#[derive(Deserialize)]
+struct InfoVector {
+    version: u8,
+    source_account: String,
+    nonce: u64,
+    recipient: String,
+    amount: u64,
+    message_type: Option<u8>,
+    expires_at: Option<u64>,
+    /// Hex of the canonical bytes
+    canonical_bytes: String,
+    /// Base58 SHA-256 of the canonical bytes
+    digest: String,
+}
+
+/// Checks `Info`'s encoding against the frozen vectors of its version;
+/// returns how many were checked. Fails when there are none, so a version
+/// bump comes with vectors of its own
+pub fn check_info_vectors() -> Result<usize> {
+    let vectors: Vec<InfoVector> = serde_json::from_str(INFO_VECTORS)
+        .map_err(|e| anyhow::anyhow!("Invalid test-vectors/info.json: {}", e))?;
+    let mut checked = 0;
+    for (index, vector) in vectors.iter().enumerate() {
+        if vector.version != INFO_ENCODING_VERSION {
+            continue;
+        }
+        let pubkey = |value: &str| {
+            Pubkey::from_str(value)
+                .map_err(|e| anyhow::anyhow!("Invalid pubkey {} in vector {}: {}", value, index, e))
+        };
+        let info = Info {
+            source_account: pubkey(&vector.source_account)?,
+            nonce: vector.nonce,
+            recipient: pubkey(&vector.recipient)?,
+            amount: vector.amount,
+            message_type: vector.message_type,
+            expires_at: vector.expires_at,
+        };
+        let bytes = decode::hex(&info.canonical_bytes());
+        if bytes != vector.canonical_bytes {
+            return Err(anyhow::anyhow!(
+                "Info encoding version {} no longer matches vector {}: expected {}, got {}; bump INFO_ENCODING_VERSION instead of changing the encoding",
+                INFO_ENCODING_VERSION,
+                index,
+                vector.canonical_bytes,
+                bytes
+            ));
+        }
+        let digest = info.digest().to_string();
+        if digest != vector.digest {
+            return Err(anyhow::anyhow!(
+                "Info digest of vector {} is {}, expected {}",
+                index,
+                digest,
+                vector.digest
+            ));
+        }
+        checked += 1;
+    }
+    if checked == 0 {
+        return Err(anyhow::anyhow!(
+            "test-vectors/info.json has no vectors for Info encoding version {}",
+            INFO_ENCODING_VERSION
+        ));
+    }
+    Ok(checked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two-counter status bytes
+    fn two_counter(in_nonce: u64, out_nonce: u64, paused: u8) -> Vec<u8> {
+        let mut data = vec![0u8; DISCRIMINATOR_LEN];
+        data.extend_from_slice(&in_nonce.to_le_bytes());
+        data.extend_from_slice(&out_nonce.to_le_bytes());
+        data.push(paused);
+        data
+    }
+
+    #[test]
+    fn the_two_counter_layout_reads_out_nonce_and_the_paused_flag() {
+        let status = NonceStatus::from_bytes(&two_counter(4, 9, 1)).unwrap();
+        assert_eq!(status.nonce, 9);
+        assert_eq!(status.in_nonce, Some(4));
+        assert!(status.paused);
+        assert!(
+            !NonceStatus::from_bytes(&two_counter(4, 9, 0))
+                .unwrap()
+                .paused
+        );
+        let error = NonceStatus::from_bytes(&two_counter(4, 9, 2)).unwrap_err();
+        assert!(error.to_string().contains("paused flag 2"), "{}", error);
+    }
+
+    #[test]
+    fn other_sizes_read_the_legacy_nonce() {
+        let mut data = 7u64.to_le_bytes().to_vec();
+        data.extend_from_slice(&[1; 24]);
+        let status = NonceStatus::from_bytes(&data).unwrap();
+        assert_eq!(
+            (status.nonce, status.in_nonce, status.paused),
+            (7, None, false)
+        );
+        assert!(NonceStatus::from_bytes(&[0; 7]).is_err());
+        // 布局被固定后，长度不符即报错
+        assert!(NonceStatus::parse(&data, WatchedLayout::TwoCounter).is_err());
+    }
+
+    #[test]
+    fn only_the_two_counter_size_survives_a_resize() {
+        assert_eq!(
+            WatchedLayout::detect(TWO_COUNTER_STATUS_SIZE),
+            Some(WatchedLayout::TwoCounter)
+        );
+        assert_eq!(WatchedLayout::detect(8), Some(WatchedLayout::Legacy));
+        assert_eq!(WatchedLayout::detect(7), None);
+        assert_eq!(
+            WatchedLayout::after_resize(TWO_COUNTER_STATUS_SIZE),
+            Some(WatchedLayout::TwoCounter)
+        );
+        assert_eq!(WatchedLayout::after_resize(32), None);
+    }
+
+    #[test]
+    fn the_info_encoding_matches_its_frozen_vectors() {
+        assert_eq!(check_info_vectors().unwrap(), 4);
+    }
+
+    #[test]
+    fn absent_and_zero_fields_encode_apart() {
+        let info = Info {
+            source_account: Pubkey::new_from_array([1; 32]),
+            nonce: 7,
+            recipient: Pubkey::new_from_array([2; 32]),
+            amount: 1_000_000,
+            message_type: None,
+            expires_at: None,
+        };
+        let bytes = info.canonical_bytes();
+        assert_eq!(bytes.len(), 106);
+        assert!(bytes.starts_with(INFO_DOMAIN_TAG));
+        assert_eq!(bytes[INFO_DOMAIN_TAG.len()], INFO_ENCODING_VERSION);
+
+        let typed = Info {
+            message_type: Some(0),
+            expires_at: Some(0),
+            ..info
+        };
+        assert_eq!(typed.canonical_bytes().len(), 106 + 1 + 8);
+        assert_ne!(typed.digest(), info.digest());
+        assert_eq!(info.digest(), hash(&bytes));
     }
 }
@@ -1 +1,3 @@
-pub mod message; 
\ No newline at end of file
+pub mod account;
+pub mod message;
+pub mod processed;
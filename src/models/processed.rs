@@ -0,0 +1,64 @@
+use crate::decode;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// How the L2 program records the nonces it has processed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProcessedLayout {
+    /// One marker account per nonce, derived under the L2 program from the
+    /// seed prefix, the nonce account and the nonce (u64 LE); a nonce is
+    /// processed once its marker exists
+    Pda,
+    /// One account: after its offset, the first nonce (u64 LE), a u32 LE byte
+    /// count, then bytes whose bit i (least significant first) of byte j
+    /// marks nonce first + 8j + i as processed
+    Bitmap,
+}
+
+/// Nonces a processed-nonce bitmap account marks
+#[derive(Debug, Clone, Default)]
+pub struct ProcessedBitmap {
+    first: u64,
+    bits: Vec<u8>,
+}
+
+impl ProcessedBitmap {
+    /// Decodes the bitmap laid out after `offset` bytes of `data`
+    pub fn parse(data: &[u8], offset: usize) -> Result<Self> {
+        let truncated = || {
+            anyhow::anyhow!(
+                "Processed-nonce bitmap is too short: {} bytes ({})",
+                data.len(),
+                decode::hex_prefix(data)
+            )
+        };
+        let body = data.get(offset..).ok_or_else(truncated)?;
+        let first = u64::from_le_bytes(
+            body.get(0..8)
+                .ok_or_else(truncated)?
+                .try_into()
+                .expect("8 bytes"),
+        );
+        let len = u32::from_le_bytes(
+            body.get(8..12)
+                .ok_or_else(truncated)?
+                .try_into()
+                .expect("4 bytes"),
+        ) as usize;
+        let bits = body.get(12..12 + len).ok_or_else(truncated)?;
+        Ok(Self {
+            first,
+            bits: bits.to_vec(),
+        })
+    }
+
+    pub fn contains(&self, nonce: u64) -> bool {
+        let Some(index) = nonce.checked_sub(self.first) else {
+            return false;
+        };
+        self.bits
+            .get((index / 8) as usize)
+            .is_some_and(|byte| byte & (1 << (index % 8)) != 0)
+    }
+}
@@ -0,0 +1,232 @@
+//! Observer role.
+//! `run --role observer` (or `process_role = "observer"`) starts one
+//! `Observer` per source instead of relayers: it reads L1 with the source's
+//! endpoints and writes what it saw to the intent queue (see `intents`),
+//! holding neither the L2 key nor an L2 client. Each round it reads the
+//! watched account, ships the transfer-info PDAs from its cursor up to the
+//! watched nonce in order (stopping at the first one L1 does not return yet),
+//! then the watched account and the cancellation registry when their data
+//! changed. The cursor (`observer_cursor`) is saved after the intents are
+//! queued, so after a crash the next round ships them again and the
+//! submitter skips the repeats by sequence number.
+
+use crate::{
+    config::{RelayerConfig, SourceConfig},
+    intents::{AccountSnapshot, Intent, IntentQueue, SignedIntent},
+    models::message::NonceStatus,
+    pda::PdaManager,
+    rpc_pool, shutdown,
+    signing::read_keypair_path,
+    state::StateStore,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{account::Account, pubkey::Pubkey, signature::Keypair};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// State key of the observer's position
+pub const OBSERVER_CURSOR_KEY: &str = "observer_cursor";
+
+/// What an observer has shipped so far
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct ObserverCursor {
+    /// Nonce of the next transfer-info PDA to ship
+    next_nonce: u64,
+    next_seq: u64,
+    /// Last shipped snapshots of the watched account and the registry
+    #[serde(default)]
+    watched: Option<AccountSnapshot>,
+    #[serde(default)]
+    registry: Option<AccountSnapshot>,
+}
+
+/// Summary of one `observe_once` round
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObserveOutcome {
+    pub source: String,
+    pub l1_nonce: u64,
+    /// Nonce of the next transfer-info PDA to ship
+    pub next_nonce: u64,
+    /// Intents queued this round
+    pub published: u64,
+}
+
+pub struct Observer {
+    source_id: String,
+    client: RpcClient,
+    pda_manager: PdaManager,
+    watched_account: Pubkey,
+    registry: Option<Pubkey>,
+    queue: IntentQueue,
+    keypair: Keypair,
+    state: StateStore,
+    genesis_nonce: u64,
+    batch_size: usize,
+    interval: Duration,
+}
+
+impl Observer {
+    pub fn new(config: &RelayerConfig, source: &SourceConfig, state: StateStore) -> Result<Self> {
+        let queue_config = config.intent_queue.as_ref().ok_or_else(|| {
+            anyhow::anyhow!("process_role = \"observer\" requires an [intent_queue]")
+        })?;
+        let path = queue_config.observer_keypair_path.as_ref().ok_or_else(|| {
+            anyhow::anyhow!("The observer role requires intent_queue.observer_keypair_path")
+        })?;
+        let keypair = read_keypair_path(path, &config.keypair_passphrase_env)
+            .map_err(|e| anyhow::anyhow!("Failed to read observer keypair {}: {}", path, e))?;
+        let registry = source
+            .cancellation_registry
+            .as_ref()
+            .map(|registry| *registry.account.pubkey());
+        Ok(Self {
+            source_id: source.id.clone(),
+            client: rpc_pool::source_client(config),
+            pda_manager: PdaManager::new(
+                source.l1_program_id,
+                source.watched_account,
+                source.seeds.clone(),
+            ),
+            watched_account: *source.watched_account.pubkey(),
+            registry,
+            queue: IntentQueue::new(queue_config, &source.id)?,
+            keypair,
+            state,
+            genesis_nonce: config.reconcile_genesis_nonce,
+            batch_size: config.fetch_batch_size.max(1),
+            interval: Duration::from_millis(queue_config.poll_interval_ms.max(1)),
+        })
+    }
+
+    /// Ships what changed on L1 since the last round
+    pub async fn observe_once(&self) -> Result<ObserveOutcome> {
+        let mut cursor = self
+            .state
+            .get::<ObserverCursor>(OBSERVER_CURSOR_KEY)?
+            .unwrap_or(ObserverCursor {
+                next_nonce: self.genesis_nonce,
+                ..ObserverCursor::default()
+            });
+        let mut pending = Vec::new();
+
+        let mut keys = vec![self.watched_account];
+        keys.extend(self.registry);
+        let (slot, accounts) = self.read(&keys).await?;
+        let watched = accounts[0]
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Watched account {} not found", self.watched_account))?;
+        let l1_nonce = NonceStatus::from_bytes(&watched.data)?.nonce;
+
+        // 先发 PDA，提交方读到新的 nonce 时对应的 PDA 已就位
+        'pdas: while cursor.next_nonce < l1_nonce {
+            let nonces: Vec<u64> = (cursor.next_nonce
+                ..l1_nonce.min(cursor.next_nonce + self.batch_size as u64))
+                .collect();
+            let addresses: Vec<Pubkey> = nonces
+                .iter()
+                .map(|nonce| self.pda_manager.find_address(*nonce).0)
+                .collect();
+            let (pda_slot, pdas) = self.read(&addresses).await?;
+            for ((nonce, address), pda) in nonces.iter().zip(&addresses).zip(pdas) {
+                let Some(pda) = pda else {
+                    break 'pdas;
+                };
+                self.ship(
+                    &mut cursor,
+                    &mut pending,
+                    Some(*nonce),
+                    pda_slot,
+                    AccountSnapshot::new(address, &pda),
+                )?;
+                cursor.next_nonce = nonce + 1;
+            }
+        }
+
+        let snapshot = AccountSnapshot::new(&self.watched_account, watched);
+        if cursor.watched.as_ref() != Some(&snapshot) {
+            self.ship(&mut cursor, &mut pending, None, slot, snapshot.clone())?;
+            cursor.watched = Some(snapshot);
+        }
+        if let (Some(registry), Some(Some(account))) = (self.registry, accounts.get(1)) {
+            let snapshot = AccountSnapshot::new(&registry, account);
+            if cursor.registry.as_ref() != Some(&snapshot) {
+                self.ship(&mut cursor, &mut pending, None, slot, snapshot.clone())?;
+                cursor.registry = Some(snapshot);
+            }
+        }
+
+        self.queue.push(&pending).await?;
+        self.state.put(OBSERVER_CURSOR_KEY, &cursor)?;
+        Ok(ObserveOutcome {
+            source: self.source_id.clone(),
+            l1_nonce,
+            next_nonce: cursor.next_nonce,
+            published: pending.len() as u64,
+        })
+    }
+
+    /// Observes every `interval` until a shutdown signal
+    pub async fn run(&self) -> Result<()> {
+        loop {
+            if let Err(e) = self.observe_once().await {
+                tracing::warn!("observer of source {} failed: {}", self.source_id, e);
+            }
+            tokio::time::sleep(self.interval).await;
+        }
+    }
+
+    /// Accounts at `keys` and the slot they were read at
+    async fn read(&self, keys: &[Pubkey]) -> Result<(u64, Vec<Option<Account>>)> {
+        let response = self
+            .client
+            .get_multiple_accounts_with_commitment(keys, self.client.commitment())
+            .await?;
+        Ok((response.context.slot, response.value))
+    }
+
+    /// Signs the next intent and adds it to `pending`
+    fn ship(
+        &self,
+        cursor: &mut ObserverCursor,
+        pending: &mut Vec<(u64, SignedIntent)>,
+        nonce: Option<u64>,
+        slot: u64,
+        account: AccountSnapshot,
+    ) -> Result<()> {
+        let intent = Intent {
+            source: self.source_id.clone(),
+            seq: cursor.next_seq,
+            nonce,
+            slot,
+            account,
+            observed_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        };
+        pending.push((intent.seq, SignedIntent::sign(&intent, &self.keypair)?));
+        cursor.next_seq += 1;
+        Ok(())
+    }
+}
+
+/// Runs `observers` once (printing each outcome) or until a shutdown signal
+pub async fn run_observers(observers: &[Observer], once: bool) -> Result<()> {
+    if once {
+        for observer in observers {
+            let outcome = observer.observe_once().await?;
+            output!("{}", serde_json::to_string(&outcome)?);
+        }
+        return Ok(());
+    }
+    tracing::info!("Starting observers ({} source(s))...", observers.len());
+    let observing = futures::future::try_join_all(observers.iter().map(Observer::run));
+    tokio::select! {
+        result = observing => result.map(|_| ()),
+        signal = shutdown::signalled() => {
+            tracing::info!("Received {}, stopping observers", signal?);
+            Ok(())
+        }
+    }
+}
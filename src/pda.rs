@@ -1,52 +1,284 @@
+use crate::{
+    cancellation::CancellationRegistry,
+    context_slot,
+    message_decoder::{BorshDecoder, MessageDecoder},
+    models::account::TransferInfoAccount,
+    pubkeys::{SourceAccount, SourceProgramId},
+};
 use anyhow::Result;
-use solana_client::rpc_client::RpcClient;
-use solana_sdk::pubkey::Pubkey;
+use serde::{Deserialize, Serialize};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{account::Account, pubkey::Pubkey};
+use std::{fmt, sync::Arc};
+
+/// Byte order of the nonce seed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NonceEncoding {
+    #[default]
+    LittleEndian,
+    BigEndian,
+}
+
+/// How a source program derives its transfer-info PDAs:
+/// `[prefix, watched_account?, nonce]`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SeedScheme {
+    #[serde(default = "default_seed_prefix")]
+    pub prefix: String,
+    #[serde(default = "default_include_watched_account")]
+    pub include_watched_account: bool,
+    #[serde(default)]
+    pub nonce_encoding: NonceEncoding,
+}
+
+fn default_seed_prefix() -> String {
+    "nonce".to_string()
+}
+
+fn default_include_watched_account() -> bool {
+    true
+}
+
+impl Default for SeedScheme {
+    fn default() -> Self {
+        Self {
+            prefix: default_seed_prefix(),
+            include_watched_account: default_include_watched_account(),
+            nonce_encoding: NonceEncoding::default(),
+        }
+    }
+}
+
+/// The watched account, transfer-info PDAs and cancellation registry as of
+/// one slot
+#[derive(Debug)]
+pub struct PdaSnapshot {
+    /// Context slot of the read
+    pub slot: u64,
+    /// Watched-account data at `slot`
+    pub watched: Option<Vec<u8>>,
+    /// The PDAs, in the order they were requested
+    pub pdas: Vec<Option<Account>>,
+    /// Cancellation-registry data at `slot`; none without a registry or
+    /// while the account does not exist
+    pub registry: Option<Vec<u8>>,
+}
+
+/// The watched account of a snapshot does not cover a nonce read with it,
+/// e.g. because the RPC node served the PDAs and the account from different
+/// slots' views
+#[derive(Debug)]
+pub struct SnapshotBehind {
+    pub slot: u64,
+    /// Nonce of the watched account, None if it was missing or unreadable
+    pub watched_nonce: Option<u64>,
+    /// Highest nonce read in the snapshot
+    pub nonce: u64,
+}
+
+impl fmt::Display for SnapshotBehind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.watched_nonce {
+            Some(watched) => write!(
+                f,
+                "L1 snapshot at slot {} has watched-account nonce {}, which does not cover nonce {}",
+                self.slot, watched, self.nonce
+            ),
+            None => write!(
+                f,
+                "L1 snapshot at slot {} has no readable watched account to cover nonce {}",
+                self.slot, self.nonce
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotBehind {}
 
 pub struct PdaManager {
-    program_id: Pubkey,
-    watched_account: Pubkey,
+    program_id: SourceProgramId,
+    watched_account: SourceAccount,
+    seeds: SeedScheme,
+    registry: Option<CancellationRegistry>,
+    decoder: Arc<dyn MessageDecoder>,
 }
 
 impl PdaManager {
-    pub fn new(program_id: Pubkey, watched_account: Pubkey) -> Self {
+    pub fn new(
+        program_id: SourceProgramId,
+        watched_account: SourceAccount,
+        seeds: SeedScheme,
+    ) -> Self {
         Self {
             program_id,
             watched_account,
+            seeds,
+            registry: None,
+            decoder: Arc::new(BorshDecoder),
         }
     }
 
+    /// Decodes transfer-info PDAs with `decoder` instead of the Borsh layout
+    pub fn with_decoder(mut self, decoder: Arc<dyn MessageDecoder>) -> Self {
+        self.decoder = decoder;
+        self
+    }
+
+    /// Reads `registry` in every snapshot
+    pub fn with_registry(mut self, registry: Option<CancellationRegistry>) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    pub fn registry(&self) -> Option<&CancellationRegistry> {
+        self.registry.as_ref()
+    }
+
+    pub fn program_id(&self) -> &SourceProgramId {
+        &self.program_id
+    }
+
     pub fn find_address(&self, nonce: u64) -> (Pubkey, u8) {
-        let seeds = [
-            b"nonce",
-            self.watched_account.as_ref(),
-            &nonce.to_le_bytes(),
-        ];
+        let nonce = match self.seeds.nonce_encoding {
+            NonceEncoding::LittleEndian => nonce.to_le_bytes(),
+            NonceEncoding::BigEndian => nonce.to_be_bytes(),
+        };
+        let mut seeds: Vec<&[u8]> = vec![self.seeds.prefix.as_bytes()];
+        if self.seeds.include_watched_account {
+            seeds.push(self.watched_account.pubkey().as_ref());
+        }
+        seeds.push(&nonce);
 
-        Pubkey::find_program_address(&seeds, &self.program_id)
+        Pubkey::find_program_address(&seeds, self.program_id.pubkey())
     }
 
+    /// Reads (amount, recipient) from the PDA, plus the slot it was read at
     pub async fn get_transfer_info(
         &self,
         client: &RpcClient,
         pda: &Pubkey,
-    ) -> Result<(u64, Pubkey)> {
-        let account = client.get_account(pda)?;
-        const EXPECTED_SIZE: usize = 87;
-
-        if account.data.len() < EXPECTED_SIZE {
-            return Err(anyhow::anyhow!(
-                "Insufficient PDA account data length: expected {} bytes, got {} bytes",
-                EXPECTED_SIZE,
-                account.data.len()
-            ));
-        }
+    ) -> Result<(u64, Pubkey, u64)> {
+        let response = client
+            .get_account_with_commitment(pda, client.commitment())
+            .await?;
+        let account = response
+            .value
+            .ok_or_else(|| anyhow::anyhow!("Transfer-info PDA {} not found", pda))?;
+        let (amount, to) = self.parse_transfer_info(&account.data)?;
+        Ok((amount, to, response.context.slot))
+    }
+
+    /// Reads the watched account, `pdas` and the cancellation registry with
+    /// one `getMultipleAccounts` call, so all come from the same slot, from a
+    /// node at least at `min_context_slot`
+    pub async fn read_snapshot(
+        &self,
+        client: &RpcClient,
+        pdas: &[Pubkey],
+        min_context_slot: Option<u64>,
+    ) -> Result<PdaSnapshot> {
+        let mut addresses = pdas.to_vec();
+        addresses.push(*self.watched_account.pubkey());
+        addresses.extend(
+            self.registry
+                .iter()
+                .map(|registry| *registry.address.pubkey()),
+        );
+        let response = client
+            .get_multiple_accounts_with_config(
+                &addresses,
+                context_slot::account_config(client.commitment(), min_context_slot),
+            )
+            .await?;
+        let mut accounts = response.value;
+        let registry = match self.registry {
+            Some(_) => accounts.pop().flatten().map(|account| account.data),
+            None => None,
+        };
+        let watched = accounts.pop().flatten().map(|account| account.data);
+        Ok(PdaSnapshot {
+            slot: response.context.slot,
+            watched,
+            pdas: accounts,
+            registry,
+        })
+    }
+
+    /// Decodes raw transfer-info PDA data with the source's decoder
+    pub fn decode(&self, data: &[u8]) -> Result<TransferInfoAccount> {
+        self.decoder.decode(data)
+    }
 
-        let to_bytes: [u8; 32] = account.data[40..72].try_into()?;
-        let to = Pubkey::from(to_bytes);
+    /// Raw message-type id of a transfer-info PDA (in the Borsh layout, the
+    /// byte after the amount)
+    pub fn message_type_id(&self, data: &[u8]) -> Option<u8> {
+        self.decode(data).ok().map(|info| info.fields.message_type)
+    }
+
+    /// L1 address that sent the transfer (in the Borsh layout, the 32 bytes
+    /// after the discriminator); none when unset
+    pub fn sender(&self, data: &[u8]) -> Option<Pubkey> {
+        let sender = self.decode(data).ok()?.fields.sender;
+        (sender != Pubkey::default()).then_some(sender)
+    }
 
-        let amount_bytes: [u8; 8] = account.data[72..80].try_into()?;
-        let amount = u64::from_le_bytes(amount_bytes);
+    /// Deadline (Unix time) of a transfer-info PDA; in the Borsh layout from
+    /// V2, `expires_at` (i64 LE) after the 87 bytes. None in V1 or when the
+    /// field is 0
+    pub fn expires_at(&self, data: &[u8]) -> Option<u64> {
+        let expires_at = self.decode(data).ok()?.expires_at?;
+        (expires_at > 0).then_some(expires_at as u64)
+    }
 
-        Ok((amount, to))
+    /// L1 mint of a token or NFT message (in the Borsh layout, the 32 bytes
+    /// after `expires_at`, V3); none when the PDA is shorter or the field is
+    /// unset
+    pub fn mint(&self, data: &[u8]) -> Option<Pubkey> {
+        let mint = self.decode(data).ok()?.mint?;
+        (mint != Pubkey::default()).then_some(mint)
+    }
+
+    /// Decodes (amount, recipient) from raw transfer-info PDA data
+    pub fn parse_transfer_info(&self, data: &[u8]) -> Result<(u64, Pubkey)> {
+        let info = self.decode(data)?;
+        Ok((info.fields.amount, info.fields.recipient))
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use crate::testing::{self, Harness};
+    use solana_sdk::pubkey::Pubkey;
+
+    #[tokio::test]
+    async fn a_snapshot_reads_the_pdas_in_order_with_the_watched_account() {
+        let harness = Harness::new(testing::config()).unwrap();
+        let recipient = Pubkey::new_unique();
+        harness.add_transfers(0..2, 500, &recipient);
+        let relayer = harness.relayer().await.unwrap();
+        let pdas = [
+            harness.transfer_pda(1),
+            harness.transfer_pda(5),
+            harness.transfer_pda(0),
+        ];
+
+        let snapshot = relayer
+            .pda_manager
+            .read_snapshot(&relayer.l1_client, &pdas, None)
+            .await
+            .unwrap();
+        assert_eq!(snapshot.pdas.len(), 3);
+        assert!(snapshot.pdas[1].is_none());
+        for account in [&snapshot.pdas[0], &snapshot.pdas[2]] {
+            let data = &account.as_ref().unwrap().data;
+            assert_eq!(
+                relayer.pda_manager.parse_transfer_info(data).unwrap(),
+                (500, recipient)
+            );
+        }
+        let watched = relayer.watched.decode(&snapshot.watched.unwrap()).unwrap();
+        assert_eq!(watched.nonce, 2);
+        assert!(snapshot.registry.is_none());
     }
 }
@@ -0,0 +1,243 @@
+//! Transfer-PDA discovery.
+//! In PDA mode the relayer only reads the PDAs of nonces below the watched
+//! account's counter, so a transfer whose PDA was created before the counter
+//! moved, or after a counter layout change the relayer misreads, is missed.
+//! With `pda_discovery_interval_secs` set, the monitor also scans the L1
+//! program every interval with `getProgramAccounts`, filtered by the
+//! transfer-info sizes (and discriminator, when configured), or by the
+//! `transfer_info_entries` discriminator for multi-entry PDAs, fetching no
+//! data, and maps the accounts found to the nonces of the next
+//! `pda_discovery_window` PDAs after the L2 nonce. A PDA without a receipt
+//! in the state is unrelayed; those at or past the counter are alerted, and
+//! the consecutive run of them starting at the counter is queued as if the
+//! counter had reached its end; snapshot and verification reads accept the
+//! counter as covering them. A PDA past a nonce that has none cannot be
+//! relayed before it, since L2 relays in order.
+
+use crate::{alerts, models::account::TransferInfoVersion, receipts::Receipt, Relayer};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_account_decoder::{UiAccountEncoding, UiDataSliceConfig};
+use solana_client::{
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, RpcFilterType},
+};
+use solana_sdk::pubkey::Pubkey;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    sync::Mutex,
+};
+
+/// State key of the last discovery scan
+pub const PDA_DISCOVERY_KEY: &str = "pda_discovery";
+
+/// Unrelayed nonces at or past the counter kept in a scan
+const MAX_AHEAD_LISTED: usize = 100;
+
+/// Outcome of one discovery scan
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiscoveryScan {
+    pub scanned_at: u64,
+    /// Watched-account nonce when the scan ran
+    pub counter: u64,
+    /// Transfer-info accounts the scan returned
+    pub accounts: usize,
+    /// PDAs of the window without a receipt
+    pub unrelayed: usize,
+    /// Unrelayed nonces at or past the counter, lowest first
+    pub ahead_of_counter: Vec<u64>,
+    /// End of the consecutive run of them starting at the counter, or at the
+    /// L2 nonce once L2 is past it
+    pub queue_to: u64,
+}
+
+pub struct PdaDiscovery {
+    interval_secs: u64,
+    window: u64,
+    /// Derived PDA of each nonce of the window
+    derived: Mutex<BTreeMap<u64, Pubkey>>,
+    last: Mutex<Option<DiscoveryScan>>,
+}
+
+impl PdaDiscovery {
+    pub fn new(interval_secs: u64, window: u64, last: Option<DiscoveryScan>) -> Self {
+        Self {
+            interval_secs,
+            window: window.max(1),
+            derived: Mutex::new(BTreeMap::new()),
+            last: Mutex::new(last),
+        }
+    }
+
+    fn due(&self, now: u64) -> bool {
+        self.last
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_none_or(|last| now >= last.scanned_at + self.interval_secs)
+    }
+
+    /// End of the discovered run past `counter`, from the last scan
+    fn queue_to(&self, counter: u64) -> u64 {
+        self.last
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map_or(counter, |last| last.queue_to.max(counter))
+    }
+}
+
+impl Relayer {
+    /// `counter` extended by the PDAs discovered past it; scans when due.
+    /// A failed scan is reported and the last one kept
+    pub(crate) async fn discovered_l1_nonce(&self, counter: u64, relayed: u64) -> Result<u64> {
+        let Some(discovery) = &self.pda_discovery else {
+            return Ok(counter);
+        };
+        let now = self.clock.unix_timestamp();
+        if discovery.due(now) {
+            match self
+                .scan_transfer_pdas(discovery, counter, relayed, now)
+                .await
+            {
+                Ok(scan) => {
+                    self.metrics
+                        .discovered_pdas_ahead
+                        .set(scan.ahead_of_counter.len() as u64);
+                    let previous = discovery.last.lock().unwrap().replace(scan.clone());
+                    if !scan.ahead_of_counter.is_empty()
+                        && previous.as_ref().map(|last| &last.ahead_of_counter)
+                            != Some(&scan.ahead_of_counter)
+                    {
+                        self.alert_discovered(&scan);
+                    }
+                    self.state.put(PDA_DISCOVERY_KEY, &scan)?;
+                }
+                Err(e) => tracing::warn!("transfer-PDA discovery scan failed: {:#}", e),
+            }
+        }
+        Ok(discovery.queue_to(counter))
+    }
+
+    /// End of the nonces `counter`, extended by the discovered PDAs, covers;
+    /// a snapshot or verification read checks its nonces against it
+    pub(crate) fn discovered_end(&self, counter: u64) -> u64 {
+        self.pda_discovery
+            .as_ref()
+            .map_or(counter, |discovery| discovery.queue_to(counter))
+    }
+
+    async fn scan_transfer_pdas(
+        &self,
+        discovery: &PdaDiscovery,
+        counter: u64,
+        relayed: u64,
+        now: u64,
+    ) -> Result<DiscoveryScan> {
+        let mut found = HashSet::new();
+        let mut scans: Vec<Vec<RpcFilterType>> = [
+            TransferInfoVersion::V1_SIZE,
+            TransferInfoVersion::V2_SIZE,
+            TransferInfoVersion::V3_SIZE,
+        ]
+        .into_iter()
+        .map(|size| {
+            let mut filters = vec![RpcFilterType::DataSize(size as u64)];
+            if let Some(discriminator) = &self.transfer_info_discriminator {
+                filters.push(RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+                    0,
+                    discriminator,
+                )));
+            }
+            filters
+        })
+        .collect();
+        // 多条目 PDA 的大小随条目数变化，只按判别符筛选
+        if let Some(discriminator) = &self.entries_discriminator {
+            scans.push(vec![RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+                0,
+                discriminator,
+            ))]);
+        }
+        for filters in scans {
+            let config = RpcProgramAccountsConfig {
+                filters: Some(filters),
+                account_config: RpcAccountInfoConfig {
+                    encoding: Some(UiAccountEncoding::Base64),
+                    data_slice: Some(UiDataSliceConfig {
+                        offset: 0,
+                        length: 0,
+                    }),
+                    commitment: Some(self.l1_client.commitment()),
+                    min_context_slot: None,
+                },
+                with_context: None,
+            };
+            let accounts = self
+                .l1_client
+                .get_program_accounts_with_config(self.pda_manager.program_id().pubkey(), config)
+                .await?;
+            found.extend(accounts.into_iter().map(|(address, _)| address));
+        }
+
+        let nonces: BTreeSet<u64> = {
+            let mut derived = discovery.derived.lock().unwrap();
+            // 窗口随 L2 nonce 前移，只推导新进入窗口的 PDA
+            *derived = derived.split_off(&relayed);
+            for nonce in relayed..relayed.saturating_add(discovery.window) {
+                derived
+                    .entry(nonce)
+                    .or_insert_with(|| self.pda_manager.find_address(nonce).0);
+            }
+            derived
+                .iter()
+                .filter(|(_, address)| found.contains(address))
+                .map(|(nonce, _)| *nonce)
+                .collect()
+        };
+        let mut unrelayed = Vec::new();
+        for nonce in nonces {
+            if Receipt::load(&self.state, nonce)?.is_none() {
+                unrelayed.push(nonce);
+            }
+        }
+        // L2 可能已越过计数器，连续段从两者较大处开始
+        let mut queue_to = counter.max(relayed);
+        while unrelayed.binary_search(&queue_to).is_ok() {
+            queue_to += 1;
+        }
+        Ok(DiscoveryScan {
+            scanned_at: now,
+            counter,
+            accounts: found.len(),
+            unrelayed: unrelayed.len(),
+            ahead_of_counter: unrelayed
+                .into_iter()
+                .filter(|nonce| *nonce >= counter)
+                .take(MAX_AHEAD_LISTED)
+                .collect(),
+            queue_to,
+        })
+    }
+
+    fn alert_discovered(&self, scan: &DiscoveryScan) {
+        let first = scan.ahead_of_counter[0];
+        let mut message = format!(
+            "{}: transfer-PDA discovery found {} unrelayed PDA(s) at or past the watched-account nonce {} (first {})",
+            self.name(),
+            scan.ahead_of_counter.len(),
+            scan.counter,
+            first
+        );
+        if scan.queue_to > scan.counter {
+            message.push_str(&format!("; queued up to {}", scan.queue_to));
+        }
+        if scan.ahead_of_counter.last() >= Some(&scan.queue_to) {
+            message.push_str(&format!(
+                "; nonce {} has no PDA, so the ones past it wait",
+                scan.queue_to
+            ));
+        }
+        alerts::raise(&message);
+    }
+}
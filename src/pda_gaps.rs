@@ -0,0 +1,172 @@
+//! Missing transfer-info PDAs.
+//! A queued nonce whose PDA does not exist yet is usually an L1 node that has
+//! not indexed the transfer. Such a read is held like a stale snapshot: it
+//! does not count as a failed attempt and the nonce is read again after
+//! `retry_delay_ms`, so it is told apart from a PDA that exists but fails to
+//! decode, which is a failure as before. Once a PDA has been missing for
+//! `missing_pda_alert_secs` the gap is alerted, and resolved when the PDA
+//! shows up. Past `missing_pda_deadline_secs` (0 waits indefinitely) every
+//! further miss counts as a failed attempt, so a nonce that never gets a
+//! PDA is eventually dead-lettered. The times count from the first miss of
+//! the running relayer.
+
+use crate::{alerts, Relayer};
+use solana_sdk::pubkey::Pubkey;
+use std::{collections::BTreeMap, fmt, sync::Mutex, time::Duration};
+
+/// The transfer-info PDA of a queued nonce does not exist yet
+#[derive(Debug)]
+pub struct PdaMissing {
+    pub nonce: u64,
+    pub pda: Pubkey,
+    /// Seconds since the first miss
+    pub missing_secs: u64,
+}
+
+impl fmt::Display for PdaMissing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Transfer-info PDA {} for nonce {} not found (missing for {}s), reading it again",
+            self.pda, self.nonce, self.missing_secs
+        )
+    }
+}
+
+impl std::error::Error for PdaMissing {}
+
+struct Gap {
+    since: u64,
+    alerted: bool,
+}
+
+/// First misses of the PDAs not found so far
+pub struct PdaGaps {
+    alert_after: Duration,
+    /// None waits indefinitely
+    deadline: Option<Duration>,
+    gaps: Mutex<BTreeMap<u64, Gap>>,
+}
+
+impl PdaGaps {
+    pub fn new(alert_after: Duration, deadline: Duration) -> Self {
+        Self {
+            alert_after,
+            deadline: (!deadline.is_zero()).then_some(deadline),
+            gaps: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl Relayer {
+    /// Error for a read of `nonce` that found no PDA at `pda`: `PdaMissing`,
+    /// or a plain failure past the deadline
+    pub(crate) fn missing_pda(&self, nonce: u64, pda: Pubkey) -> anyhow::Error {
+        self.metrics.missing_pda_reads_total.inc();
+        let now = self.clock.unix_timestamp();
+        let gaps = &self.pda_gaps;
+        let mut missing = gaps.gaps.lock().unwrap();
+        let gap = missing.entry(nonce).or_insert(Gap {
+            since: now,
+            alerted: false,
+        });
+        let missing_secs = now.saturating_sub(gap.since);
+        if !gap.alerted && missing_secs >= gaps.alert_after.as_secs() {
+            gap.alerted = true;
+            alerts::raise(&format!(
+                "{}: transfer-info PDA {} for nonce {} has been missing for {}s; the L1 node may be lagging, or the nonce has no transfer",
+                self.name(),
+                pda,
+                nonce,
+                missing_secs
+            ));
+        }
+        match gaps.deadline {
+            Some(deadline) if missing_secs >= deadline.as_secs() => anyhow::anyhow!(
+                "Transfer-info PDA {} for nonce {} not found after {}s",
+                pda,
+                nonce,
+                missing_secs
+            ),
+            _ => PdaMissing {
+                nonce,
+                pda,
+                missing_secs,
+            }
+            .into(),
+        }
+    }
+
+    /// Ends the gap of `nonce`, whose PDA was read
+    pub(crate) fn pda_found(&self, nonce: u64, pda: Pubkey) {
+        let gap = self.pda_gaps.gaps.lock().unwrap().remove(&nonce);
+        if let Some(gap) = gap.filter(|gap| gap.alerted) {
+            alerts::resolve(&format!(
+                "{}: transfer-info PDA {} for nonce {} appeared after {}s",
+                self.name(),
+                pda,
+                nonce,
+                self.clock.unix_timestamp().saturating_sub(gap.since)
+            ));
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::testing::{self, Harness};
+    use std::time::Duration;
+
+    /// Alerts raised so far about `pda`
+    fn alerts_for(pda: &Pubkey) -> usize {
+        let pda = pda.to_string();
+        alerts::recent()
+            .iter()
+            .filter(|alert| alert.contains(&pda))
+            .count()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_stuck_nonce_is_alerted_once_and_fails_past_the_deadline() {
+        let mut config = testing::config();
+        config.missing_pda_alert_secs = 60;
+        config.missing_pda_deadline_secs = 600;
+        let relayer = Harness::new(config).unwrap().relayer().await.unwrap();
+        let pda = Pubkey::new_unique();
+
+        assert!(relayer.missing_pda(7, pda).is::<PdaMissing>());
+        tokio::time::advance(Duration::from_secs(59)).await;
+        assert!(relayer.missing_pda(7, pda).is::<PdaMissing>());
+        assert_eq!(alerts_for(&pda), 0);
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        let error = relayer.missing_pda(7, pda);
+        assert_eq!(error.downcast_ref::<PdaMissing>().unwrap().missing_secs, 60);
+        assert_eq!(alerts_for(&pda), 1);
+        tokio::time::advance(Duration::from_secs(60)).await;
+        relayer.missing_pda(7, pda);
+        assert_eq!(alerts_for(&pda), 1);
+
+        tokio::time::advance(Duration::from_secs(480)).await;
+        assert!(!relayer.missing_pda(7, pda).is::<PdaMissing>());
+        assert_eq!(relayer.metrics.missing_pda_reads_total.get(), 5);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_found_pda_starts_the_next_gap_afresh() {
+        let mut config = testing::config();
+        config.missing_pda_alert_secs = 60;
+        let relayer = Harness::new(config).unwrap().relayer().await.unwrap();
+        let pda = Pubkey::new_unique();
+
+        relayer.missing_pda(3, pda);
+        tokio::time::advance(Duration::from_secs(50)).await;
+        relayer.pda_found(3, pda);
+        relayer.missing_pda(3, pda);
+        tokio::time::advance(Duration::from_secs(50)).await;
+        let error = relayer.missing_pda(3, pda);
+        assert_eq!(error.downcast_ref::<PdaMissing>().unwrap().missing_secs, 50);
+        assert_eq!(alerts_for(&pda), 0);
+    }
+}
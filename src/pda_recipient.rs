@@ -0,0 +1,109 @@
+//! Off-curve (PDA) recipients.
+//! A recipient that is a program-derived address, such as an escrow vault, may
+//! need its owner program to record the deposit. For every recipient off the
+//! ed25519 curve the relayer reads the owner of the L2 account. For a program
+//! listed in `pda_recipient_programs`, the relay transaction also calls that
+//! program's deposit instruction in the same transaction. Off-curve accounts
+//! owned by the system program take a plain transfer. Any other owner, or a
+//! recipient that does not exist yet, sends the nonce to the dead-letter
+//! queue with an alert.
+
+use crate::{config::PdaRecipientProgram, Relayer};
+use anyhow::Result;
+use solana_sdk::{pubkey::Pubkey, system_program};
+use std::{collections::HashMap, str::FromStr};
+
+/// Deposit instruction a transfer to a program's PDA goes through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DepositRoute {
+    pub program_id: Pubkey,
+    pub discriminator: [u8; 8],
+}
+
+/// Deposit instructions of the configured recipient programs
+pub struct DepositRoutes {
+    routes: HashMap<Pubkey, DepositRoute>,
+}
+
+impl DepositRoutes {
+    pub fn new(programs: &[PdaRecipientProgram]) -> Result<Self> {
+        let mut routes = HashMap::new();
+        for program in programs {
+            let program_id = Pubkey::from_str(&program.program_id).map_err(|e| {
+                anyhow::anyhow!(
+                    "Invalid PDA recipient program {}: {}",
+                    program.program_id,
+                    e
+                )
+            })?;
+            let discriminator = parse_discriminator(&program.deposit_discriminator)
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Invalid deposit_discriminator of PDA recipient program {}: expected 16 hex digits",
+                        program_id
+                    )
+                })?;
+            routes.insert(
+                program_id,
+                DepositRoute {
+                    program_id,
+                    discriminator,
+                },
+            );
+        }
+        Ok(Self { routes })
+    }
+}
+
+fn parse_discriminator(hex: &str) -> Option<[u8; 8]> {
+    if hex.len() != 16 || !hex.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0u8; 8];
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+/// How a native transfer reaches its recipient
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Routing {
+    /// Plain transfer by `relay_message`
+    Plain,
+    /// `relay_message` followed by the owner program's deposit instruction
+    Deposit(DepositRoute),
+    /// Off-curve recipient the relayer cannot route, and why
+    Unroutable(String),
+}
+
+impl Relayer {
+    /// How a transfer to `recipient` is relayed; RPC errors are returned,
+    /// recipients that cannot be routed are not
+    pub(crate) async fn recipient_routing(&self, recipient: &Pubkey) -> Result<Routing> {
+        if recipient.is_on_curve() {
+            return Ok(Routing::Plain);
+        }
+        let account = self
+            .l2_client
+            .get_account_with_commitment(recipient, self.l2_client.commitment())
+            .await?
+            .value;
+        let Some(owner) = account.map(|account| account.owner) else {
+            return Ok(Routing::Unroutable(format!(
+                "off-curve recipient {} does not exist on L2, so its owner program is unknown",
+                recipient
+            )));
+        };
+        if owner == system_program::id() {
+            return Ok(Routing::Plain);
+        }
+        Ok(match self.deposit_routes.routes.get(&owner) {
+            Some(route) => Routing::Deposit(*route),
+            None => Routing::Unroutable(format!(
+                "off-curve recipient {} is owned by {}, which is not in pda_recipient_programs",
+                recipient, owner
+            )),
+        })
+    }
+}
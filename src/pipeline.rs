@@ -0,0 +1,1402 @@
+//! Staged relay pipeline.
+//! Nonces flow through fetch → build → send stages connected by bounded
+//! channels, so a slow stage applies backpressure to the stages before it.
+
+use crate::{
+    attempts::FailureClass,
+    context_slot,
+    dual_read::PrimaryRead,
+    entries::{EntryReceipt, TransferEntry},
+    journal::JournalEvent,
+    limits,
+    merkle::{leaf_hash, IndexerClient, LeafChunk, MerkleProof},
+    models::{
+        account,
+        message::{Info, MessageType, UnknownTypePolicy},
+    },
+    pda::{PdaSnapshot, SnapshotBehind},
+    pda_recipient::{DepositRoute, Routing},
+    priority_fee::ComputeBudget,
+    receipts::{packed_share, Receipt},
+    rent::TransferCost,
+    token::TokenTransfer,
+    PreparedTransfer, Relayer,
+};
+use anyhow::Result;
+use futures::{
+    future::try_join_all,
+    stream::{FuturesUnordered, StreamExt},
+};
+use solana_sdk::{hash::Hash, pubkey::Pubkey, transaction::VersionedTransaction};
+use std::{collections::HashMap, time::Duration};
+use tokio::sync::mpsc;
+use tracing::{field, info_span, Instrument, Span};
+
+/// `getMultipleAccounts` accepts at most 100 addresses per request, one of
+/// which is the watched account read with the PDAs; a cancellation registry
+/// takes one more
+const MAX_FETCH_BATCH_SIZE: usize = 99;
+/// Reads of a snapshot whose watched account does not cover its nonces
+/// before the batch is held
+const SNAPSHOT_READS: u32 = 3;
+/// Pause between those reads, about one L1 slot
+const SNAPSHOT_RETRY_DELAY: Duration = Duration::from_millis(400);
+
+/// Transfer info read from an L1 transfer-info PDA
+pub struct FetchedTransfer {
+    pub nonce: u64,
+    pub amount: u64,
+    pub to_address: Pubkey,
+    /// L1 slot at which the message was read
+    pub l1_slot: u64,
+    /// Raw message-type id, when the message source carries one
+    pub message_type: Option<u8>,
+    /// Nonces merged into this transfer, empty unless coalesced
+    pub coalesced: Vec<u64>,
+    /// Inclusion proof, in merkle mode
+    pub proof: Option<MerkleProof>,
+    /// Unix time after which the transfer must not be executed on L2
+    pub expires_at: Option<u64>,
+    /// Digest of the message read from L1 (`Info::digest`); none once
+    /// coalesced
+    pub digest: Option<Hash>,
+    /// L1 address that sent the transfer, passed to L2 unless
+    /// `relay_sender = false`; none when the message source does not carry
+    /// it or once coalesced from several senders
+    pub sender: Option<Pubkey>,
+    /// L2 mint paid out by a token or NFT message; none for native transfers
+    pub token: Option<TokenTransfer>,
+    /// Nonces of accumulated dust paid out with this transfer, its amount
+    /// included; set when the transfer is admitted
+    pub dust: Vec<u64>,
+    /// Whether the transaction creates the recipient's missing token
+    /// account; set when the transfer is built
+    pub create_token_account: bool,
+    /// Position among the entries of a multi-entry PDA; none for a single
+    /// transfer
+    pub entry: Option<TransferEntry>,
+    /// Root span of this nonce's trace
+    pub span: Span,
+}
+
+#[cfg(test)]
+impl FetchedTransfer {
+    /// Native transfer of `amount` lamports to `to_address`, read at L1 slot 1
+    pub(crate) fn native(nonce: u64, amount: u64, to_address: Pubkey) -> Self {
+        Self {
+            nonce,
+            amount,
+            to_address,
+            l1_slot: 1,
+            message_type: None,
+            coalesced: Vec::new(),
+            proof: None,
+            expires_at: None,
+            digest: None,
+            sender: None,
+            token: None,
+            dust: Vec::new(),
+            create_token_account: false,
+            entry: None,
+            span: Span::none(),
+        }
+    }
+}
+
+/// Outcome of the message-type check
+pub(crate) enum MessageCheck {
+    /// Relayed, paying out the L2 mint of a token or NFT message
+    Relay(Option<TokenTransfer>),
+    /// Unknown type id, skipped under `unknown_message_type = "skip"`
+    Skip(u8),
+    /// Unknown type id, fails the batch under `unknown_message_type = "halt"`
+    Halt(u8),
+    /// L1 mint whose mapping is disabled, skipped
+    SkipMint(Pubkey),
+    /// Unmapped L1 mint, fails the batch under `unmapped_mint = "hold"`
+    HoldMint(Pubkey),
+}
+
+/// Sizing of the relay pipeline
+#[derive(Debug, Clone, Copy)]
+pub struct PipelineSettings {
+    /// Capacity of each channel between stages
+    pub channel_capacity: usize,
+    /// Number of PDAs read per `getMultipleAccounts` call
+    pub fetch_batch_size: usize,
+    /// Maximum number of transactions awaiting confirmation at once
+    pub send_concurrency: usize,
+    /// Most transfers packed into one transaction
+    pub pack_max_transfers: usize,
+    /// Whether transactions are simulated for their compute units before
+    /// they are sent
+    pub simulate_compute_units: bool,
+}
+
+impl Relayer {
+    /// Relays every nonce in `from..to` through the fetch → build → send pipeline.
+    /// The first error stops the pipeline; transactions already submitted are
+    /// still awaited before returning. `attempt` counts previous failed tries
+    /// and is recorded on each nonce's trace.
+    pub(crate) async fn process_nonce_range(&self, from: u64, to: u64, attempt: u32) -> Result<()> {
+        let nonces: Vec<u64> = (from..to).collect();
+        self.process_nonces(&nonces, attempt).await
+    }
+
+    /// Relays `nonces` in the given order, like `process_nonce_range`
+    pub(crate) async fn process_nonces(&self, nonces: &[u64], attempt: u32) -> Result<()> {
+        self.attempts
+            .start(nonces, self.clock.now(), &self.l1_rpc_url);
+        let capacity = self.pipeline.channel_capacity.max(1);
+        let (fetched_tx, fetched_rx) = mpsc::channel(capacity);
+        let (coalesced_tx, coalesced_rx) = mpsc::channel(capacity);
+        let (built_tx, built_rx) = mpsc::channel(capacity);
+
+        let (fetched, coalesced, built, sent) = tokio::join!(
+            self.fetch_stage(nonces, attempt, fetched_tx),
+            self.coalesce_stage(fetched_rx, coalesced_tx),
+            self.build_stage(coalesced_rx, built_tx),
+            self.send_stage(built_rx),
+        );
+
+        fetched?;
+        coalesced?;
+        built?;
+        sent
+    }
+
+    /// Reads transfer-info PDAs in bulk and forwards them to the build stage
+    async fn fetch_stage(
+        &self,
+        nonces: &[u64],
+        attempt: u32,
+        output: mpsc::Sender<FetchedTransfer>,
+    ) -> Result<()> {
+        for chunk in nonces.chunks(self.fetch_chunk_size()) {
+            for fetched in self.fetch_transfers(chunk, attempt).await? {
+                self.check_message_ttl(&fetched)?;
+                if output.send(fetched).await.is_err() {
+                    // 下游已停止
+                    return Ok(());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Nonces read per bulk fetch
+    pub(crate) fn fetch_chunk_size(&self) -> usize {
+        let limit = MAX_FETCH_BATCH_SIZE - usize::from(self.registry_address().is_some());
+        self.pipeline.fetch_batch_size.clamp(1, limit)
+    }
+
+    /// Reads the transfers of `nonces` from the configured message source
+    pub(crate) async fn fetch_transfers(
+        &self,
+        nonces: &[u64],
+        attempt: u32,
+    ) -> Result<Vec<FetchedTransfer>> {
+        // 运维已按跳过或链下退款关闭的死信 nonce 不再读取
+        let nonces: Vec<u64> = nonces
+            .iter()
+            .copied()
+            .filter(|nonce| !self.dead_letters.closed_unrelayed(*nonce))
+            .collect();
+        if nonces.is_empty() {
+            return Ok(Vec::new());
+        }
+        match &self.indexer {
+            Some(indexer) => {
+                self.fetch_indexed_transfers(indexer, &nonces, attempt)
+                    .await
+            }
+            None => self.fetch_pda_transfers(&nonces, attempt).await,
+        }
+    }
+
+    /// Root span of a nonce's trace
+    fn nonce_span(&self, nonce: u64, attempt: u32) -> Span {
+        info_span!(
+            parent: None,
+            "relay_nonce",
+            source = %self.source_id,
+            nonce,
+            attempt,
+            l1_rpc = %self.l1_rpc_url,
+            l2_rpc = %self.l2_rpc_url,
+            pda = field::Empty,
+            amount = field::Empty,
+            destination = field::Empty,
+            signature = field::Empty,
+            latency_secs = field::Empty,
+        )
+    }
+
+    /// Checks the PDA's message type against the source's mapping, and the
+    /// mint of a token or NFT message against the mint mappings; every
+    /// message is relayed as native when no mapping is configured. Errors
+    /// mean the nonce must be dead-lettered.
+    fn check_message_type(&self, nonce: u64, amount: u64, data: &[u8]) -> Result<MessageCheck> {
+        if self.message_types.is_empty() {
+            return Ok(MessageCheck::Relay(None));
+        }
+        let id = self
+            .pda_manager
+            .message_type_id(data)
+            .ok_or_else(|| anyhow::anyhow!("Nonce {} has no message type", nonce))?;
+        let message_type = self
+            .message_types
+            .get(&id)
+            .copied()
+            .unwrap_or(MessageType::Unknown(id));
+        match message_type {
+            MessageType::Native => Ok(MessageCheck::Relay(None)),
+            kind @ (MessageType::Token | MessageType::Nft) => {
+                self.token_transfer(nonce, kind, amount, data)
+            }
+            MessageType::Unknown(id) => match self.unknown_message_type {
+                UnknownTypePolicy::Halt => Ok(MessageCheck::Halt(id)),
+                UnknownTypePolicy::Skip => Ok(MessageCheck::Skip(id)),
+                UnknownTypePolicy::DeadLetter => Err(anyhow::anyhow!(
+                    "Nonce {} has unknown message type {}",
+                    nonce,
+                    id
+                )),
+            },
+        }
+    }
+
+    /// Reads the transfer-info PDAs (or discovered message accounts) of
+    /// `nonces` with a single RPC call
+    async fn fetch_pda_transfers(
+        &self,
+        nonces: &[u64],
+        attempt: u32,
+    ) -> Result<Vec<FetchedTransfer>> {
+        let pdas: Vec<Pubkey> = nonces
+            .iter()
+            .map(|nonce| match &self.message_accounts {
+                Some(accounts) => accounts.address(*nonce),
+                None => Ok(self.pda_manager.find_address(*nonce).0),
+            })
+            .collect::<Result<_>>()?;
+
+        let spans: Vec<(Span, Span)> = nonces
+            .iter()
+            .zip(&pdas)
+            .map(|(nonce, pda)| {
+                let root = self.nonce_span(*nonce, attempt);
+                root.record("pda", pda.to_string());
+                let fetch = info_span!(parent: &root, "fetch_pda", %pda, slot = field::Empty);
+                (root, fetch)
+            })
+            .collect();
+
+        let high_water = self.context_slots.l1.get();
+        let (slot, accounts, cancelled) = match &self.message_accounts {
+            Some(_) => {
+                let mut addresses = pdas.clone();
+                addresses.extend(self.registry_address());
+                let response = self
+                    .l1_client
+                    .get_multiple_accounts_with_config(
+                        &addresses,
+                        context_slot::account_config(self.l1_client.commitment(), high_water),
+                    )
+                    .await
+                    .map_err(|e| self.l1_read_error(e))?;
+                let mut accounts = response.value;
+                let cancelled = self.take_cancellations(&mut accounts)?;
+                (response.context.slot, accounts, cancelled)
+            }
+            None => {
+                let snapshot = self
+                    .read_covering_snapshot(nonces, &pdas, high_water)
+                    .await?;
+                let cancelled = self.cancellations(snapshot.registry.as_deref())?;
+                (snapshot.slot, snapshot.pdas, cancelled)
+            }
+        };
+        self.metrics.l1_max_slot.set_max(slot);
+        self.context_slots.l1.observe(slot);
+
+        if !self.l1_verifiers.is_empty() {
+            // 大额转账需要第二个 L1 RPC 读到相同的数据才提交
+            let high_value: Vec<PrimaryRead> = nonces
+                .iter()
+                .zip(&pdas)
+                .zip(&accounts)
+                .filter_map(|((nonce, pda), account)| {
+                    let data = &account.as_ref()?.data;
+                    // 多条目 PDA 任一条目超出阈值即需核对
+                    let exceeds = match self.transfer_entries(data) {
+                        Some(entries) => entries
+                            .ok()?
+                            .iter()
+                            .any(|entry| self.exceeds_dual_read_threshold(entry).unwrap_or(false)),
+                        None => self.exceeds_dual_read_threshold(data)?,
+                    };
+                    exceeds.then_some(PrimaryRead {
+                        nonce: *nonce,
+                        pda: *pda,
+                        data,
+                    })
+                })
+                .collect();
+            self.verify_l1_reads(&high_value, slot).await?;
+        }
+
+        let transfers = nonces
+            .iter()
+            .copied()
+            .zip(pdas)
+            .zip(accounts)
+            .zip(spans)
+            .map(|(((nonce, pda), account), (span, fetch_span))| {
+                fetch_span.record("slot", slot);
+                if cancelled
+                    .as_ref()
+                    .is_some_and(|cancelled| cancelled.contains(nonce))
+                {
+                    // 用户取消时可能已关闭 PDA，回执里的金额和收款人留空
+                    let (amount, to) = account
+                        .as_ref()
+                        .filter(|account| self.transfer_entries(&account.data).is_none())
+                        .and_then(|account| {
+                            self.pda_manager.parse_transfer_info(&account.data).ok()
+                        })
+                        .map_or((0, String::new()), |(amount, to)| (amount, to.to_string()));
+                    self.skip_cancelled_on_l1(nonce, slot, amount, to, slot)?;
+                    return Ok(Vec::new());
+                }
+                let Some(account) = account else {
+                    // 应答节点落后于已见过的 slot 时 PDA 可能只是尚未同步
+                    self.check_l1_behind(slot, high_water)?;
+                    return Err(self.missing_pda(nonce, pda));
+                };
+                self.pda_found(nonce, pda);
+                let Some(entries) = self.transfer_entries(&account.data) else {
+                    if let Some(expected) = &self.transfer_info_discriminator {
+                        account::check_discriminator(&account.data, expected).map_err(|e| {
+                            anyhow::anyhow!("Transfer-info PDA {} for nonce {}: {}", pda, nonce, e)
+                        })?;
+                    }
+                    let fetched =
+                        self.read_transfer(nonce, pda, slot, &account.data, None, span)?;
+                    return Ok(fetched.into_iter().collect());
+                };
+                let entries = entries.map_err(|e| {
+                    anyhow::anyhow!("Transfer-info PDA {} for nonce {}: {}", pda, nonce, e)
+                })?;
+                self.read_entries(nonce, pda, slot, &entries, span)
+            })
+            .collect::<Result<Vec<Vec<FetchedTransfer>>>>()?;
+        Ok(transfers.into_iter().flatten().collect())
+    }
+
+    /// Decodes the transfer in `data`, the PDA of `nonce` or one of its
+    /// entries, and checks its message type; none if it is skipped. Errors
+    /// after dead-lettering a transfer rejected by the checks
+    fn read_transfer(
+        &self,
+        nonce: u64,
+        pda: Pubkey,
+        slot: u64,
+        data: &[u8],
+        entry: Option<TransferEntry>,
+        span: Span,
+    ) -> Result<Option<FetchedTransfer>> {
+        let (amount, to_address) = self.pda_manager.parse_transfer_info(data).map_err(|e| {
+            anyhow::anyhow!(
+                "Transfer-info PDA {} for nonce {} could not be decoded: {}",
+                pda,
+                nonce,
+                e
+            )
+        })?;
+        let mut fetched = FetchedTransfer {
+            nonce,
+            amount,
+            to_address,
+            l1_slot: slot,
+            message_type: self.pda_manager.message_type_id(data),
+            coalesced: Vec::new(),
+            proof: None,
+            expires_at: self.pda_manager.expires_at(data),
+            digest: None,
+            sender: self.pda_manager.sender(data).filter(|_| self.relay_sender),
+            token: None,
+            dust: Vec::new(),
+            create_token_account: false,
+            entry,
+            span,
+        };
+        fetched.digest = Some(self.message_digest(&fetched));
+        let checked = self
+            .check_message_type(nonce, amount, data)
+            .and_then(|check| {
+                if let Some(attestation) = &self.attestation {
+                    attestation.verify(nonce, data)?;
+                }
+                Ok(check)
+            });
+        match checked {
+            Ok(MessageCheck::Relay(token)) => {
+                fetched.token = token;
+                Ok(Some(fetched))
+            }
+            Ok(MessageCheck::Skip(id)) => {
+                self.skip_message(&fetched, id)?;
+                Ok(None)
+            }
+            Ok(MessageCheck::Halt(id)) => Err(anyhow::anyhow!(
+                "Nonce {} has unknown message type {}; halting until the relayer is upgraded",
+                nonce,
+                id
+            )),
+            Ok(MessageCheck::SkipMint(mint)) => {
+                self.skip_disabled_mint(&fetched, &mint)?;
+                Ok(None)
+            }
+            Ok(MessageCheck::HoldMint(mint)) => Err(anyhow::anyhow!(
+                "Nonce {} carries L1 mint {}, which has no mint mapping; holding until a reload maps it",
+                nonce,
+                mint
+            )),
+            Err(e) => {
+                // 类型或验签校验失败的 nonce 交给运维处理，不再提交
+                self.dead_letter_transfer(&fetched, &e.to_string())?;
+                Err(anyhow::anyhow!(
+                    "Nonce {} rejected before submission: {}",
+                    nonce,
+                    e
+                ))
+            }
+        }
+    }
+
+    /// Reads the entries of the multi-entry PDA of `nonce` that have no
+    /// receipt yet. An entry rejected by the checks is dead-lettered while
+    /// the others are still relayed; errors only when none is left to relay
+    /// and the nonce is not done
+    fn read_entries(
+        &self,
+        nonce: u64,
+        pda: Pubkey,
+        slot: u64,
+        entries: &[Vec<u8>],
+        span: Span,
+    ) -> Result<Vec<FetchedTransfer>> {
+        let relayed = EntryReceipt::load(&self.state, nonce)?;
+        let count = entries.len() as u8;
+        let mut transfers = Vec::new();
+        let mut rejected = None;
+        for (index, data) in (0..count).zip(entries) {
+            if relayed.contains_key(&index) {
+                continue;
+            }
+            let entry = TransferEntry {
+                index,
+                count,
+                pending: 0,
+            };
+            match self.read_transfer(nonce, pda, slot, data, Some(entry), span.clone()) {
+                Ok(fetched) => transfers.extend(fetched),
+                // 被拒绝的条目已进入死信，其余条目照常提交
+                Err(e) if self.dead_letters.contains(nonce) => {
+                    rejected.get_or_insert(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        if let (true, Some(e)) = (transfers.is_empty(), rejected) {
+            return Err(e);
+        }
+        let pending = transfers.len() as u8;
+        for fetched in &mut transfers {
+            if let Some(entry) = &mut fetched.entry {
+                entry.pending = pending;
+            }
+        }
+        Ok(transfers)
+    }
+
+    /// Reads `pdas` together with the watched account until the account's
+    /// nonce covers every nonce in `nonces`, and returns that snapshot. A
+    /// transfer-info PDA read at a different slot
+    /// than the counter may not be the transfer the counter announced; after
+    /// `SNAPSHOT_READS` stale snapshots this fails with `SnapshotBehind`.
+    /// Every read asks for a node at least at `high_water`
+    async fn read_covering_snapshot(
+        &self,
+        nonces: &[u64],
+        pdas: &[Pubkey],
+        high_water: Option<u64>,
+    ) -> Result<PdaSnapshot> {
+        let highest = nonces.iter().copied().max().unwrap_or_default();
+        let mut reads = 1;
+        loop {
+            let snapshot = self
+                .pda_manager
+                .read_snapshot(&self.l1_client, pdas, high_water)
+                .await
+                .map_err(|e| self.l1_read_error(e))?;
+            let watched_nonce = snapshot
+                .watched
+                .as_deref()
+                .and_then(|data| self.watched.decode(data))
+                .map(|status| status.nonce);
+            if watched_nonce.is_some_and(|watched| self.discovered_end(watched) > highest) {
+                return Ok(snapshot);
+            }
+            self.metrics.stale_snapshots_total.inc();
+            let behind = SnapshotBehind {
+                slot: snapshot.slot,
+                watched_nonce,
+                nonce: highest,
+            };
+            if reads >= SNAPSHOT_READS {
+                return Err(behind.into());
+            }
+            tracing::warn!("{}, reading it again", behind);
+            reads += 1;
+            self.clock.sleep(SNAPSHOT_RETRY_DELAY).await;
+        }
+    }
+
+    /// Fetches the messages of `nonces` and their proofs from the indexer and
+    /// verifies every proof against the batch root read from L1
+    async fn fetch_indexed_transfers(
+        &self,
+        indexer: &IndexerClient,
+        nonces: &[u64],
+        attempt: u32,
+    ) -> Result<Vec<FetchedTransfer>> {
+        let messages = try_join_all(nonces.iter().map(|&nonce| async move {
+            let span = self.nonce_span(nonce, attempt);
+            let fetch_span = info_span!(
+                parent: &span,
+                "fetch_message",
+                root_account = field::Empty,
+                slot = field::Empty,
+            );
+            let message = indexer
+                .message(nonce)
+                .instrument(fetch_span.clone())
+                .await?;
+            Ok::<_, anyhow::Error>((message, span, fetch_span))
+        }))
+        .await?;
+
+        // 每个批次的 root 只读取一次，且只信任 L1 上的 root
+        let mut root_accounts = messages
+            .iter()
+            .map(|(message, _, _)| message.root_account())
+            .collect::<Result<Vec<_>>>()?;
+        root_accounts.sort();
+        root_accounts.dedup();
+        let mut addresses = root_accounts.clone();
+        addresses.extend(self.registry_address());
+        let response = self
+            .l1_client
+            .get_multiple_accounts_with_commitment(&addresses, self.l1_client.commitment())
+            .await?;
+        let slot = response.context.slot;
+        self.metrics.l1_max_slot.set_max(slot);
+        let mut accounts = response.value;
+        let cancelled = self.take_cancellations(&mut accounts)?;
+        let roots = root_accounts
+            .into_iter()
+            .zip(accounts)
+            .map(|(root_account, account)| {
+                let account = account.ok_or_else(|| {
+                    anyhow::anyhow!("Batch root account {} not found", root_account)
+                })?;
+                Ok((root_account, LeafChunk::from_bytes(&account.data)?))
+            })
+            .collect::<Result<HashMap<Pubkey, LeafChunk>>>()?;
+
+        messages
+            .into_iter()
+            .map(|(message, span, fetch_span)| {
+                let nonce = message.nonce;
+                let root_account = message.root_account()?;
+                fetch_span.record("root_account", root_account.to_string());
+                fetch_span.record("slot", slot);
+                if cancelled
+                    .as_ref()
+                    .is_some_and(|cancelled| cancelled.contains(nonce))
+                {
+                    let to = message
+                        .to_address()
+                        .map(|to| to.to_string())
+                        .unwrap_or_default();
+                    self.skip_cancelled_on_l1(nonce, slot, message.amount, to, slot)?;
+                    return Ok(None);
+                }
+
+                let to_address = message.to_address()?;
+                let proof = message.merkle_proof()?;
+                let leaf = leaf_hash(message.amount, nonce, &to_address);
+                if !roots[&root_account].proves(leaf, &proof) {
+                    let reason = format!(
+                        "Merkle proof for nonce {} does not match the root in {}",
+                        nonce, root_account
+                    );
+                    self.dead_letter(nonce, &reason)?;
+                    return Err(anyhow::anyhow!(
+                        "Nonce {} rejected before submission: {}",
+                        nonce,
+                        reason
+                    ));
+                }
+
+                let mut fetched = FetchedTransfer {
+                    nonce,
+                    amount: message.amount,
+                    to_address,
+                    l1_slot: slot,
+                    message_type: None,
+                    coalesced: Vec::new(),
+                    proof: Some(proof),
+                    expires_at: None,
+                    digest: None,
+                    sender: None,
+                    token: None,
+                    dust: Vec::new(),
+                    create_token_account: false,
+                    entry: None,
+                    span,
+                };
+                fetched.digest = Some(self.message_digest(&fetched));
+                Ok(Some(fetched))
+            })
+            .filter_map(Result::transpose)
+            .collect()
+    }
+
+    /// Digest of `fetched` as a message of this source
+    fn message_digest(&self, fetched: &FetchedTransfer) -> Hash {
+        Info {
+            source_account: *self.watched_account.pubkey(),
+            nonce: fetched.nonce,
+            recipient: fetched.to_address,
+            amount: fetched.amount,
+            message_type: fetched.message_type,
+            expires_at: fetched.expires_at,
+        }
+        .digest()
+    }
+
+    /// Records a receipt for a message of unknown type `id` that is not relayed
+    fn skip_message(&self, fetched: &FetchedTransfer, id: u8) -> Result<()> {
+        let receipt = Receipt::skipped(fetched, id, self.clock.unix_timestamp());
+        if fetched.entry.is_some() {
+            return self.skip_entry(fetched, &format!("unknown message type {}", id), receipt);
+        }
+        tracing::info!(
+            "Skipping nonce {}: unknown message type {} (unknown_message_type = \"skip\")",
+            fetched.nonce,
+            id
+        );
+        receipt.save(&self.state)?;
+        self.schedule_shipping(fetched.nonce);
+        self.journal(
+            Some(fetched.l1_slot),
+            JournalEvent::Skipped {
+                nonce: fetched.nonce,
+                reason: format!("unknown message type {}", id),
+            },
+        );
+        self.metrics.skipped_messages_total.inc();
+        Ok(())
+    }
+
+    /// Records a receipt for a token or NFT message of `mint`, whose mapping
+    /// is disabled, without relaying it
+    fn skip_disabled_mint(&self, fetched: &FetchedTransfer, mint: &Pubkey) -> Result<()> {
+        let receipt = Receipt::skipped_mint(fetched, mint, self.clock.unix_timestamp());
+        if fetched.entry.is_some() {
+            let reason = format!("mint mapping of {} disabled", mint);
+            return self.skip_entry(fetched, &reason, receipt);
+        }
+        tracing::info!(
+            "Skipping nonce {}: the mint mapping of L1 mint {} is disabled",
+            fetched.nonce,
+            mint
+        );
+        receipt.save(&self.state)?;
+        self.schedule_shipping(fetched.nonce);
+        self.journal_message(
+            Some(fetched.l1_slot),
+            Some(limits::message_type_of(fetched.token)),
+            JournalEvent::Skipped {
+                nonce: fetched.nonce,
+                reason: format!("mint mapping of {} disabled", mint),
+            },
+        );
+        self.metrics.skipped_messages_total.inc();
+        Ok(())
+    }
+
+    /// Builds, signs and cost-checks transactions, forwarding them to the
+    /// send stage; with `pack_max_transfers` above 1, native transfers that
+    /// are already fetched are packed into shared transactions
+    async fn build_stage(
+        &self,
+        mut input: mpsc::Receiver<FetchedTransfer>,
+        output: mpsc::Sender<Vec<PreparedTransfer>>,
+    ) -> Result<()> {
+        let mut carried = None;
+        loop {
+            let fetched = match carried.take() {
+                Some(fetched) => fetched,
+                None => match input.recv().await {
+                    Some(fetched) => fetched,
+                    None => break,
+                },
+            };
+            if fetched.entry.is_some() {
+                let (prepared, failed) = self.build_entries(fetched, &mut input).await?;
+                if let Some(prepared) = prepared {
+                    if output.send(vec![prepared]).await.is_err() {
+                        return Ok(());
+                    }
+                }
+                match failed {
+                    Some(e) => return Err(e),
+                    None => continue,
+                }
+            }
+            let Some(first) = self.admit_transfer(fetched).await? else {
+                continue;
+            };
+            let mut group = vec![first];
+            let mut held = None;
+            // 积压时把已读取的原生转账打包进同一笔交易，不等待后续转账
+            let packing = self.pipeline.pack_max_transfers > 1 && self.packable(&group[0].0)?;
+            while packing && group.len() < self.pipeline.pack_max_transfers {
+                let Ok(next) = input.try_recv() else {
+                    break;
+                };
+                if !self.packable(&next)? || !self.same_strategy(&group[0].0, &next) {
+                    carried = Some(next);
+                    break;
+                }
+                match self.admit_transfer(next).await {
+                    Ok(Some(next)) => group.push(next),
+                    Ok(None) => {}
+                    Err(e) => {
+                        // 先提交已就绪的转账，再返回错误
+                        held = Some(e);
+                        break;
+                    }
+                }
+            }
+
+            let transactions = if group.len() == 1 {
+                let (fetched, span) = group.remove(0);
+                let prepared = async {
+                    let mut prepared = self.build_transfer(fetched).await?;
+                    prepared.cost = self.check_transfer_cost(&prepared).await?;
+                    Ok::<_, anyhow::Error>(prepared)
+                }
+                .instrument(span)
+                .await?;
+                vec![vec![prepared]]
+            } else {
+                self.build_packed_transfers(group).await?
+            };
+            for transaction in transactions {
+                if output.send(transaction).await.is_err() {
+                    return Ok(());
+                }
+            }
+            if let Some(e) = held {
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs the checks `fetched` must pass before it is built, in its
+    /// `build_transaction` span; none if it was cancelled in its quiet period
+    /// or recorded as dust
+    async fn admit_transfer(
+        &self,
+        mut fetched: FetchedTransfer,
+    ) -> Result<Option<(FetchedTransfer, Span)>> {
+        if self.cancel_held_transfer(&fetched)? || self.check_dust(&mut fetched)? {
+            return Ok(None);
+        }
+        fetched.span.record("amount", fetched.amount);
+        fetched
+            .span
+            .record("destination", fetched.to_address.to_string());
+        let span = info_span!(
+            parent: &fetched.span,
+            "build_transaction",
+            blockhash = field::Empty,
+            last_valid_block_height = field::Empty,
+        );
+        self.attempts.advance(
+            fetched.nonce,
+            &fetched.coalesced,
+            FailureClass::Build,
+            &self.l2_rpc_url,
+            None,
+        );
+        if let Some(expires_at) = fetched.expires_at {
+            self.attempts.deadline(fetched.nonce, expires_at);
+        }
+        async {
+            self.check_policy(&fetched)?;
+            self.check_limits(&fetched)?;
+            self.check_quiet_period(&fetched)?;
+            self.check_approval(&fetched).await?;
+            self.check_l2_limits(&fetched).await?;
+            self.anomaly.admit(
+                &self.state,
+                self.clock.unix_timestamp(),
+                fetched.nonce,
+                fetched.token.is_none().then_some(fetched.amount),
+            )
+        }
+        .instrument(span.clone())
+        .await?;
+        Ok(Some((fetched, span)))
+    }
+
+    /// Admits the pending entries of one nonce, `first` and the ones after it
+    /// in `input`, and builds the transaction relaying the admitted ones.
+    /// The error returned with it fails the nonce once that transaction is
+    /// sent: an entry was held or rejected, and the others still go out
+    async fn build_entries(
+        &self,
+        first: FetchedTransfer,
+        input: &mut mpsc::Receiver<FetchedTransfer>,
+    ) -> Result<(Option<PreparedTransfer>, Option<anyhow::Error>)> {
+        let nonce = first.nonce;
+        let (count, pending) = first
+            .entry
+            .map_or((1, 1), |entry| (entry.count, entry.pending));
+        let mut entries = vec![first];
+        while entries.len() < usize::from(pending) {
+            let Some(next) = input.recv().await else {
+                break;
+            };
+            entries.push(next);
+        }
+
+        let mut admitted = Vec::with_capacity(entries.len());
+        let mut failed = None;
+        for fetched in entries {
+            let result = match fetched.token {
+                Some(_) => {
+                    let reason = "token and NFT entries are not relayed";
+                    self.dead_letter_transfer(&fetched, reason)?;
+                    Err(anyhow::anyhow!(
+                        "Nonce {} rejected before submission: {}",
+                        nonce,
+                        reason
+                    ))
+                }
+                None => self.admit_transfer(fetched).await,
+            };
+            match result {
+                Ok(Some(entry)) => admitted.push(entry),
+                Ok(None) => {}
+                Err(e) => {
+                    failed.get_or_insert(e);
+                }
+            }
+        }
+        // 读取时被拒绝的条目没有回执，其余条目提交后 nonce 仍算失败
+        let settled = EntryReceipt::load(&self.state, nonce)?.len() + admitted.len();
+        if failed.is_none()
+            && settled < usize::from(count)
+            && Receipt::load(&self.state, nonce)?.is_none()
+        {
+            failed = Some(anyhow::anyhow!(
+                "Nonce {} has {} of {} entries rejected before submission; replay it from the dead-letter queue",
+                nonce,
+                usize::from(count) - settled,
+                count
+            ));
+        }
+        if admitted.is_empty() {
+            return Ok((None, failed));
+        }
+        let span = admitted[0].1.clone();
+        let prepared = self.build_entry_transfer(admitted).instrument(span).await?;
+        Ok((Some(prepared), failed))
+    }
+
+    /// Builds the transaction relaying the admitted `entries` of one nonce,
+    /// prepared as one transfer of their total amount
+    async fn build_entry_transfer(
+        &self,
+        entries: Vec<(FetchedTransfer, Span)>,
+    ) -> Result<PreparedTransfer> {
+        let mut deposits = Vec::with_capacity(entries.len());
+        for (fetched, span) in &entries {
+            self.print_transfer(fetched);
+            deposits.push(self.deposit_route(fetched).instrument(span.clone()).await?);
+        }
+        let (recent_blockhash, last_valid_block_height) = self.relay_blockhash().await?;
+        Span::current()
+            .record("blockhash", recent_blockhash.to_string())
+            .record("last_valid_block_height", last_valid_block_height);
+        let budget = self.typed_budget(None, self.compute_budget().await);
+        let transfers: Vec<_> = entries
+            .iter()
+            .zip(&deposits)
+            .map(|((fetched, _), deposit)| (fetched, deposit.as_ref()))
+            .collect();
+        let transaction = self.transaction_builder.build_entries_transaction(
+            &transfers,
+            budget.as_ref(),
+            self.signers.active(),
+            recent_blockhash,
+        )?;
+        let nonce = entries[0].0.nonce;
+        if let Some(exceeded) = self.estimate_transaction(&transaction).await?.exceeded() {
+            return Err(anyhow::anyhow!(
+                "Transaction for the entries of nonce {} needs {}",
+                nonce,
+                exceeded
+            ));
+        }
+        self.print_transaction(budget.as_ref());
+        self.attempts.advance(
+            nonce,
+            &[],
+            FailureClass::Build,
+            &self.l2_rpc_url,
+            Some(recent_blockhash),
+        );
+
+        // 每个条目的收款人各自估算租金，手续费只计一次
+        let mut cost = TransferCost::default();
+        for (fetched, _) in &entries {
+            let entry = TransferCost::estimate_native(
+                &self.l2_client,
+                &self.rent_cache,
+                &transaction.message,
+                fetched.amount,
+                &fetched.to_address,
+            )
+            .await?;
+            cost = TransferCost {
+                amount: cost.amount.saturating_add(entry.amount),
+                fee: entry.fee,
+                rent: cost.rent.saturating_add(entry.rent),
+                reserve: entry.reserve,
+            };
+        }
+        let balance = self
+            .l2_client
+            .get_balance(&self.signers.active().pubkey())
+            .await?;
+        cost.ensure_covered(balance)?;
+
+        let records: Vec<EntryReceipt> = entries
+            .iter()
+            .filter_map(|(fetched, _)| EntryReceipt::of(fetched))
+            .collect();
+        let mut entries = entries.into_iter().map(|(fetched, _)| fetched);
+        let mut first = entries.next().expect("at least one admitted entry");
+        first.amount = entries.fold(first.amount, |total, fetched| {
+            total.saturating_add(fetched.amount)
+        });
+        // 各条目的摘要记在条目回执里
+        first.digest = None;
+        let mut prepared =
+            self.prepared_transfer(first, transaction, last_valid_block_height, Vec::new());
+        prepared.entries = records;
+        prepared.cost = cost;
+        Ok(prepared)
+    }
+
+    /// Whether `fetched` may share a transaction: native transfers only, not
+    /// coalesced ones, whose memo lists their merged nonces, and not a nonce
+    /// with an in-flight transaction or a receipt, which was already handled
+    /// once and is sent on its own
+    fn packable(&self, fetched: &FetchedTransfer) -> Result<bool> {
+        Ok(fetched.token.is_none()
+            && fetched.coalesced.is_empty()
+            && self.in_flight.get(fetched.nonce).is_none()
+            && Receipt::load(&self.state, fetched.nonce)?.is_none())
+    }
+
+    /// Whether `a` and `b` are confirmed under the same strategy, so their
+    /// shared transaction is awaited the same way
+    fn same_strategy(&self, a: &FetchedTransfer, b: &FetchedTransfer) -> bool {
+        self.confirmation_strategy(a.amount, a.message_type)
+            == self.confirmation_strategy(b.amount, b.message_type)
+    }
+
+    /// Sends transactions with at most `send_concurrency` awaiting
+    /// confirmation; each holds one transfer or a packed group
+    async fn send_stage(&self, mut input: mpsc::Receiver<Vec<PreparedTransfer>>) -> Result<()> {
+        let limit = self.pipeline.send_concurrency.max(1);
+        let mut in_flight = FuturesUnordered::new();
+        let mut result = Ok(());
+
+        loop {
+            tokio::select! {
+                Some(transfers) = input.recv(), if result.is_ok() && in_flight.len() < limit => {
+                    in_flight.push(self.send_transaction_to_l2(transfers));
+                }
+                Some(sent) = in_flight.next() => {
+                    if let Err(e) = sent {
+                        if result.is_ok() {
+                            // 停止接收新交易，等待已发送的交易确认
+                            input.close();
+                            result = Err(e);
+                        }
+                    }
+                }
+                else => break,
+            }
+        }
+
+        result
+    }
+
+    /// Builds and signs the L2 transaction for a fetched transfer
+    pub(crate) async fn build_transfer(
+        &self,
+        mut fetched: FetchedTransfer,
+    ) -> Result<PreparedTransfer> {
+        self.print_transfer(&fetched);
+        let deposit = self.deposit_route(&fetched).await?;
+        self.check_token_account(&mut fetched).await?;
+
+        tracing::debug!("Building transaction...");
+        let (recent_blockhash, last_valid_block_height) = self.relay_blockhash().await?;
+        Span::current()
+            .record("blockhash", recent_blockhash.to_string())
+            .record("last_valid_block_height", last_valid_block_height);
+        self.attempts.advance(
+            fetched.nonce,
+            &fetched.coalesced,
+            FailureClass::Build,
+            &self.l2_rpc_url,
+            Some(recent_blockhash),
+        );
+        let budget = self.typed_budget(fetched.token, self.compute_budget().await);
+        let transaction = self
+            .build_within_limits(
+                &fetched,
+                deposit.as_ref(),
+                budget.as_ref(),
+                recent_blockhash,
+            )
+            .await?;
+        self.print_transaction(budget.as_ref());
+
+        Ok(self.prepared_transfer(fetched, transaction, last_valid_block_height, Vec::new()))
+    }
+
+    /// Builds packed transactions for `group`, in order, each holding as many
+    /// of its transfers as fit; every transfer keeps its own prepared
+    /// transfer, sharing the transaction with the others it is packed with
+    async fn build_packed_transfers(
+        &self,
+        group: Vec<(FetchedTransfer, Span)>,
+    ) -> Result<Vec<Vec<PreparedTransfer>>> {
+        let mut deposits = Vec::with_capacity(group.len());
+        for (fetched, span) in &group {
+            self.print_transfer(fetched);
+            deposits.push(self.deposit_route(fetched).instrument(span.clone()).await?);
+        }
+
+        let mut transactions = Vec::new();
+        let mut rest: Vec<_> = group.into_iter().zip(deposits).collect();
+        while !rest.is_empty() {
+            tracing::debug!("Building packed transaction...");
+            let (recent_blockhash, last_valid_block_height) = self.relay_blockhash().await?;
+            let budget = self.typed_budget(None, self.compute_budget().await);
+            let transfers: Vec<_> = rest
+                .iter()
+                .map(|((fetched, _), deposit)| (fetched, deposit.as_ref()))
+                .collect();
+            let mut take = transfers.len();
+            let (transaction, count) = loop {
+                let (transaction, count) =
+                    self.transaction_builder.build_batch_transfer_transaction(
+                        &transfers[..take],
+                        budget.as_ref(),
+                        self.signers.active(),
+                        recent_blockhash,
+                    )?;
+                let estimate = self.estimate_transaction(&transaction).await?;
+                match estimate.exceeded() {
+                    None => break (transaction, count),
+                    // 按模拟的单笔计算量缩小打包数量
+                    Some(exceeded) if count > 1 => {
+                        take = estimate.fitting_transfers(count);
+                        tracing::info!(
+                            "Packed transaction of {} transfers needs {}; packing {}",
+                            count,
+                            exceeded,
+                            take
+                        );
+                        self.metrics.transaction_splits_total.inc();
+                    }
+                    Some(exceeded) => {
+                        return Err(anyhow::anyhow!(
+                            "Transaction for nonce {} needs {}",
+                            transfers[0].0.nonce,
+                            exceeded
+                        ))
+                    }
+                }
+            };
+            self.print_transaction(budget.as_ref());
+
+            let members: Vec<_> = rest.drain(..count).collect();
+            let packed: Vec<u64> = if count > 1 {
+                members
+                    .iter()
+                    .map(|((fetched, _), _)| fetched.nonce)
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            if count > 1 {
+                tracing::info!("Packed nonces: {:?}", packed);
+            }
+            let mut prepared = Vec::with_capacity(count);
+            for ((fetched, span), _) in members {
+                span.record("blockhash", recent_blockhash.to_string())
+                    .record("last_valid_block_height", last_valid_block_height);
+                self.attempts.advance(
+                    fetched.nonce,
+                    &fetched.coalesced,
+                    FailureClass::Build,
+                    &self.l2_rpc_url,
+                    Some(recent_blockhash),
+                );
+                prepared.push(self.prepared_transfer(
+                    fetched,
+                    transaction.clone(),
+                    last_valid_block_height,
+                    packed.clone(),
+                ));
+            }
+            self.check_packed_cost(&mut prepared).await?;
+            transactions.push(prepared);
+        }
+        Ok(transactions)
+    }
+
+    fn print_transfer(&self, fetched: &FetchedTransfer) {
+        let amount = match fetched.token {
+            Some(token) if token.nft => format!("NFT {}", token.l2_mint),
+            Some(token) => format!("{} base units of mint {}", fetched.amount, token.l2_mint),
+            None => format!(
+                "{} lamports ({} SOL)",
+                fetched.amount,
+                fetched.amount as f64 / 1_000_000_000.0
+            ),
+        };
+        tracing::debug!(
+            nonce = fetched.nonce,
+            recipient = %fetched.to_address,
+            sender = fetched.sender.map(tracing::field::display),
+            coalesced = ?fetched.coalesced,
+            l1_slot = fetched.l1_slot,
+            "Transfer of {}",
+            amount
+        );
+    }
+
+    /// Deposit route of `fetched`'s recipient, none when paid directly;
+    /// dead-letters the nonce if the recipient cannot be routed
+    async fn deposit_route(&self, fetched: &FetchedTransfer) -> Result<Option<DepositRoute>> {
+        // 代币转入收款人的关联代币账户，PDA 收款人也无需 deposit 路由
+        let routing = match fetched.token {
+            Some(_) => Routing::Plain,
+            None => self.recipient_routing(&fetched.to_address).await?,
+        };
+        match routing {
+            Routing::Plain => Ok(None),
+            Routing::Deposit(route) => {
+                tracing::debug!(
+                    "Recipient is a PDA of {}, deposited through its deposit instruction",
+                    route.program_id
+                );
+                Ok(Some(route))
+            }
+            Routing::Unroutable(reason) => {
+                // 无法路由的 PDA 收款人交给运维处理
+                self.dead_letter_transfer(fetched, &reason)?;
+                Err(anyhow::anyhow!(
+                    "Nonce {} rejected before submission: {}",
+                    fetched.nonce,
+                    reason
+                ))
+            }
+        }
+    }
+
+    fn print_transaction(&self, budget: Option<&ComputeBudget>) {
+        tracing::debug!(
+            program_id = %self.transaction_builder.program_id,
+            nonce_account = %self.transaction_builder.nonce_account,
+            signer = %self.signers.active().pubkey(),
+            unit_price = budget.map(|budget| budget.unit_price),
+            "Transaction built"
+        );
+    }
+
+    fn prepared_transfer(
+        &self,
+        fetched: FetchedTransfer,
+        transaction: VersionedTransaction,
+        last_valid_block_height: u64,
+        packed: Vec<u64>,
+    ) -> PreparedTransfer {
+        PreparedTransfer {
+            nonce: fetched.nonce,
+            amount: fetched.amount,
+            to_address: fetched.to_address,
+            l1_slot: fetched.l1_slot,
+            coalesced: fetched.coalesced,
+            packed,
+            expires_at: fetched.expires_at,
+            digest: fetched.digest,
+            sender: fetched.sender,
+            token: fetched.token,
+            dust: fetched.dust,
+            strategy: self.confirmation_strategy(fetched.amount, fetched.message_type),
+            limits: self
+                .limits
+                .resolve(limits::message_type_of(fetched.token))
+                .1,
+            transaction,
+            cost: TransferCost::default(),
+            last_valid_block_height,
+            entries: Vec::new(),
+            span: fetched.span,
+        }
+    }
+
+    /// Cost-checks a packed transaction: its transfers together must be
+    /// covered, and each carries its share of the fee
+    async fn check_packed_cost(&self, prepared: &mut [PreparedTransfer]) -> Result<()> {
+        let mut total = TransferCost::default();
+        for transfer in prepared.iter_mut() {
+            let cost = TransferCost::estimate_native(
+                &self.l2_client,
+                &self.rent_cache,
+                &transfer.transaction.message,
+                transfer.amount,
+                &transfer.to_address,
+            )
+            .await?;
+            total = TransferCost {
+                amount: total.amount.saturating_add(cost.amount),
+                fee: cost.fee,
+                rent: total.rent.saturating_add(cost.rent),
+                reserve: cost.reserve,
+            };
+            transfer.cost = TransferCost {
+                fee: packed_share(cost.fee, transfer.nonce, &transfer.packed),
+                ..cost
+            };
+        }
+        let balance = self
+            .l2_client
+            .get_balance(&self.signers.active().pubkey())
+            .await?;
+        total.ensure_covered(balance)?;
+        tracing::debug!(
+            "Estimated cost: {} lamports (balance {})",
+            total.total(),
+            balance
+        );
+        Ok(())
+    }
+
+    /// Fails if the relayer wallet cannot cover the transfer amount, fees and
+    /// rent, or a token transfer's tokens; returns the estimate
+    pub(crate) async fn check_transfer_cost(
+        &self,
+        prepared: &PreparedTransfer,
+    ) -> Result<TransferCost> {
+        let cost = match &prepared.token {
+            Some(token) => {
+                TransferCost::estimate_token(
+                    &self.l2_client,
+                    &self.rent_cache,
+                    &prepared.transaction.message,
+                    token,
+                    prepared.amount,
+                    &prepared.to_address,
+                )
+                .await?
+            }
+            None => {
+                TransferCost::estimate_native(
+                    &self.l2_client,
+                    &self.rent_cache,
+                    &prepared.transaction.message,
+                    prepared.amount,
+                    &prepared.to_address,
+                )
+                .await?
+            }
+        };
+        let balance = self
+            .l2_client
+            .get_balance(&self.signers.active().pubkey())
+            .await?;
+        cost.ensure_covered(balance)?;
+        tracing::debug!(
+            "Estimated cost: {} lamports (balance {})",
+            cost.total(),
+            balance
+        );
+        Ok(cost)
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use crate::{
+        pda::SnapshotBehind,
+        testing::{self, Harness},
+    };
+    use solana_sdk::pubkey::Pubkey;
+
+    #[tokio::test(start_paused = true)]
+    async fn a_snapshot_behind_its_nonces_is_read_again() {
+        let harness = Harness::new(testing::config()).unwrap();
+        harness.add_transfers(0..3, 500, &Pubkey::new_unique());
+        let relayer = harness.relayer().await.unwrap();
+        let pdas = [harness.transfer_pda(2)];
+
+        // 计数器尚未覆盖 nonce 2 的快照不可用
+        harness.l1.script_nonces([2, 3]);
+        let snapshot = relayer
+            .read_covering_snapshot(&[2], &pdas, None)
+            .await
+            .unwrap();
+        assert!(snapshot.pdas[0].is_some());
+        assert_eq!(relayer.metrics.stale_snapshots_total.get(), 1);
+
+        harness.l1.script_nonces([1, 1, 1]);
+        let error = relayer
+            .read_covering_snapshot(&[2], &pdas, None)
+            .await
+            .unwrap_err();
+        let behind = error.downcast_ref::<SnapshotBehind>().unwrap();
+        assert_eq!((behind.watched_nonce, behind.nonce), (Some(1), 2));
+        assert_eq!(relayer.metrics.stale_snapshots_total.get(), 4);
+    }
+}
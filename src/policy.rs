@@ -0,0 +1,283 @@
+//! Relayer-side transfer policy.
+//! Independently of the limits the L2 program enforces, the `[policy]` table
+//! sets guardrails the relayer checks before it builds, and so signs, any
+//! transaction, so a corrupted or malicious L1 account cannot make it relay
+//! an arbitrary transfer:
+//!
+//! - `max_amount`: lamports of one native transfer
+//! - `daily_cap`: lamports of the native transfers a source relays per UTC day
+//! - `rolling_cap`: lamports of the native transfers a source relays in any
+//!   24 hours
+//! - `allowed_destinations`: when not empty, the only recipients relayed to
+//! - `denied_destinations`: recipients never relayed to
+//!
+//! Amounts are checked for native transfers only, since token amounts are in
+//! each mint's own units; the destination lists apply to every message. A
+//! transfer that violates the policy is dead-lettered, which raises an alert,
+//! and counted in `relayer_policy_violations_total`. Coalescing never merges
+//! past `max_amount`. Both totals are kept in the state store per nonce, so
+//! a retried transfer is not counted twice and a restart keeps them; the
+//! rolling one drops each amount 24 hours after it was admitted.
+
+use crate::{pipeline::FetchedTransfer, pubkeys::DestinationAccount, Relayer};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// State key holding the native amounts relayed on the current UTC day
+pub const POLICY_DAILY_KEY: &str = "policy/daily";
+
+/// State key holding the native amounts relayed in the last 24 hours
+pub const POLICY_ROLLING_KEY: &str = "policy/rolling";
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// `[policy]` table
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferPolicy {
+    /// Largest native transfer in lamports (unlimited when unset)
+    #[serde(default)]
+    pub max_amount: Option<u64>,
+    /// Lamports of native transfers relayed per UTC day (unlimited when unset)
+    #[serde(default)]
+    pub daily_cap: Option<u64>,
+    /// Lamports of native transfers relayed in any 24 hours (unlimited when
+    /// unset)
+    #[serde(default)]
+    pub rolling_cap: Option<u64>,
+    /// Recipients transfers may go to; empty allows every recipient
+    #[serde(default)]
+    pub allowed_destinations: Vec<DestinationAccount>,
+    /// Recipients transfers never go to
+    #[serde(default)]
+    pub denied_destinations: Vec<DestinationAccount>,
+}
+
+/// Native amounts admitted on one UTC day, by nonce
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct DailyTotal {
+    /// Days since the Unix epoch
+    day: u64,
+    amounts: BTreeMap<u64, u64>,
+}
+
+impl DailyTotal {
+    /// Lamports admitted, leaving out `nonce`
+    fn total_except(&self, nonce: u64) -> u64 {
+        self.amounts
+            .iter()
+            .filter(|(&admitted, _)| admitted != nonce)
+            .fold(0u64, |total, (_, &amount)| total.saturating_add(amount))
+    }
+}
+
+/// Native amounts admitted in the last 24 hours, by nonce
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct RollingTotal {
+    admitted: BTreeMap<u64, Admitted>,
+}
+
+/// One admitted native transfer
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Admitted {
+    /// Unix time of the admission
+    at: u64,
+    amount: u64,
+}
+
+impl RollingTotal {
+    /// Drops the amounts admitted 24 hours or more before `now`
+    fn prune(&mut self, now: u64) {
+        self.admitted
+            .retain(|_, admitted| admitted.at.saturating_add(SECONDS_PER_DAY) > now);
+    }
+
+    /// Lamports admitted, leaving out `nonce`
+    fn total_except(&self, nonce: u64) -> u64 {
+        self.admitted
+            .iter()
+            .filter(|(&admitted, _)| admitted != nonce)
+            .fold(0u64, |total, (_, admitted)| {
+                total.saturating_add(admitted.amount)
+            })
+    }
+}
+
+impl TransferPolicy {
+    /// Why `fetched` may not be relayed under the destination lists and
+    /// `max_amount`, if it may not
+    fn violation(&self, fetched: &FetchedTransfer) -> Option<String> {
+        let destination = DestinationAccount::from(fetched.to_address);
+        if self.denied_destinations.contains(&destination) {
+            return Some(format!("destination {} is denied by policy", destination));
+        }
+        if !self.allowed_destinations.is_empty()
+            && !self.allowed_destinations.contains(&destination)
+        {
+            return Some(format!(
+                "destination {} is not in the policy's allowed destinations",
+                destination
+            ));
+        }
+        match self.max_amount {
+            Some(max) if fetched.token.is_none() && fetched.amount > max => Some(format!(
+                "amount {} exceeds the policy maximum {}",
+                fetched.amount, max
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl Relayer {
+    /// Checks `fetched` against `[policy]` and counts it towards the day's
+    /// total; dead-letters a transfer that violates it
+    pub(crate) fn check_policy(&self, fetched: &FetchedTransfer) -> Result<()> {
+        let Some(policy) = self.policy.lock().unwrap().clone() else {
+            return Ok(());
+        };
+        let mut violation = policy.violation(fetched);
+        let native = fetched.token.is_none();
+        let daily_cap = policy.daily_cap.filter(|_| native);
+        let rolling_cap = policy.rolling_cap.filter(|_| native);
+        let now = self.clock.unix_timestamp();
+        let today = now / SECONDS_PER_DAY;
+        let mut daily = self
+            .state
+            .get::<DailyTotal>(POLICY_DAILY_KEY)?
+            .filter(|daily| daily.day == today)
+            .unwrap_or(DailyTotal {
+                day: today,
+                amounts: BTreeMap::new(),
+            });
+        if let (None, Some(cap)) = (&violation, daily_cap) {
+            let relayed = daily.total_except(fetched.nonce);
+            if relayed.saturating_add(fetched.amount) > cap {
+                violation = Some(format!(
+                    "amount {} would take the day's total of {} past the policy's daily cap {}",
+                    fetched.amount, relayed, cap
+                ));
+            }
+        }
+        let mut rolling = self
+            .state
+            .get::<RollingTotal>(POLICY_ROLLING_KEY)?
+            .unwrap_or_default();
+        rolling.prune(now);
+        if let (None, Some(cap)) = (&violation, rolling_cap) {
+            let relayed = rolling.total_except(fetched.nonce);
+            if relayed.saturating_add(fetched.amount) > cap {
+                violation = Some(format!(
+                    "amount {} would take the last 24 hours' total of {} past the policy's rolling cap {}",
+                    fetched.amount, relayed, cap
+                ));
+            }
+        }
+
+        if let Some(reason) = violation {
+            self.metrics.policy_violations_total.inc();
+            self.dead_letter_transfer(fetched, &reason)?;
+            return Err(anyhow::anyhow!(
+                "Nonce {} rejected by policy: {}",
+                fetched.nonce,
+                reason
+            ));
+        }
+        if daily_cap.is_some() {
+            daily.amounts.insert(fetched.nonce, fetched.amount);
+            self.state.put(POLICY_DAILY_KEY, &daily)?;
+        }
+        if rolling_cap.is_some() {
+            rolling.admitted.insert(
+                fetched.nonce,
+                Admitted {
+                    at: now,
+                    amount: fetched.amount,
+                },
+            );
+            self.state.put(POLICY_ROLLING_KEY, &rolling)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn admitted(at: u64, amount: u64) -> Admitted {
+        Admitted { at, amount }
+    }
+
+    #[test]
+    fn the_rolling_total_drops_amounts_after_24_hours() {
+        let mut rolling = RollingTotal {
+            admitted: BTreeMap::from([(1, admitted(0, 5)), (2, admitted(3_600, 7))]),
+        };
+        rolling.prune(SECONDS_PER_DAY - 1);
+        assert_eq!(rolling.total_except(3), 12);
+        assert_eq!(rolling.total_except(2), 5);
+        rolling.prune(SECONDS_PER_DAY);
+        assert_eq!(rolling.total_except(3), 7);
+        rolling.prune(SECONDS_PER_DAY + 3_600);
+        assert!(rolling.admitted.is_empty());
+    }
+
+    #[cfg(feature = "testing")]
+    mod clocked {
+        use super::super::*;
+        use crate::testing::{self, Harness};
+        use solana_sdk::pubkey::Pubkey;
+        use std::time::Duration;
+
+        const SOL: u64 = 1_000_000_000;
+        const HOUR: Duration = Duration::from_secs(3_600);
+
+        fn native(nonce: u64, amount: u64) -> FetchedTransfer {
+            FetchedTransfer::native(nonce, amount, Pubkey::new_unique())
+        }
+
+        async fn relayer(daily_cap: Option<u64>, rolling_cap: Option<u64>) -> Relayer {
+            let mut config = testing::config();
+            config.policy = Some(TransferPolicy {
+                max_amount: None,
+                daily_cap,
+                rolling_cap,
+                allowed_destinations: Vec::new(),
+                denied_destinations: Vec::new(),
+            });
+            Harness::new(config).unwrap().relayer().await.unwrap()
+        }
+
+        #[tokio::test(start_paused = true)]
+        async fn the_rolling_cap_frees_each_amount_24_hours_after_it_was_admitted() {
+            let relayer = relayer(None, Some(3 * SOL)).await;
+            relayer.check_policy(&native(0, 2 * SOL)).unwrap();
+            tokio::time::advance(12 * HOUR).await;
+            relayer.check_policy(&native(1, SOL)).unwrap();
+            // 重试的 nonce 只计一次
+            relayer.check_policy(&native(1, SOL)).unwrap();
+            let error = relayer.check_policy(&native(2, SOL)).unwrap_err();
+            assert!(error.to_string().contains("rolling cap"), "{}", error);
+            assert_eq!(relayer.metrics.policy_violations_total.get(), 1);
+
+            // UTC 日期变更不会释放滚动窗口
+            tokio::time::advance(11 * HOUR).await;
+            assert!(relayer.check_policy(&native(3, SOL)).is_err());
+            tokio::time::advance(HOUR).await;
+            relayer.check_policy(&native(4, 2 * SOL)).unwrap();
+            assert!(relayer.check_policy(&native(5, 1)).is_err());
+        }
+
+        #[tokio::test(start_paused = true)]
+        async fn the_daily_cap_resets_at_the_utc_day() {
+            let relayer = relayer(Some(2 * SOL), None).await;
+            relayer.check_policy(&native(0, 2 * SOL)).unwrap();
+            tokio::time::advance(23 * HOUR).await;
+            let error = relayer.check_policy(&native(1, SOL)).unwrap_err();
+            assert!(error.to_string().contains("daily cap"), "{}", error);
+            tokio::time::advance(HOUR).await;
+            relayer.check_policy(&native(2, 2 * SOL)).unwrap();
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//! Adaptive monitor poll interval.
+//! The monitor polls every `poll_interval_min_ms` while the L1 nonce moves.
+//! Once it has not changed for `poll_idle_after_secs`, each unchanged poll
+//! doubles the interval up to `poll_interval_max_ms`; the first change drops
+//! it back to the minimum. While nonces are pending the interval stays at the
+//! minimum whatever the nonce does, so the L2 nonce is followed closely
+//! while the backlog drains. While `poll_suspend_backlog` or more nonces are
+//! pending the relayer is known to be behind, so the monitor stops polling
+//! until the backlog drains below that.
+//! A failed read of the nonces (an RPC timeout, a 5xx) doubles the interval,
+//! from at least the minimum, up to `poll_interval_max_ms`, so an unhealthy
+//! endpoint is not hammered; the first successful read resets it.
+
+use std::{sync::Mutex, time::Duration};
+
+struct PollState {
+    current: Duration,
+    last_nonce: Option<u64>,
+    /// Unix time of the last poll that saw the nonce change
+    changed_at: u64,
+    /// Failed reads since the last successful one
+    failures: u32,
+}
+
+pub struct AdaptivePoll {
+    min: Duration,
+    max: Duration,
+    /// How long the nonce must be unchanged before the interval grows
+    idle_after: Duration,
+    /// Pending nonces that suspend polling (0 never does)
+    suspend_backlog: u64,
+    state: Mutex<PollState>,
+}
+
+impl AdaptivePoll {
+    pub fn new(min: Duration, max: Duration, idle_after: Duration, suspend_backlog: u64) -> Self {
+        let min = min.max(Duration::from_millis(1));
+        Self {
+            min,
+            max: max.max(min),
+            idle_after,
+            suspend_backlog,
+            state: Mutex::new(PollState {
+                current: min,
+                last_nonce: None,
+                changed_at: 0,
+                failures: 0,
+            }),
+        }
+    }
+
+    /// Whether `backlog` pending nonces are enough to skip polling
+    pub fn suspended(&self, backlog: u64) -> bool {
+        self.suspend_backlog > 0 && backlog >= self.suspend_backlog
+    }
+
+    /// Longest pause between polls
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// Pause before rechecking the backlog while polling is suspended
+    pub fn suspended_wait(&self) -> Duration {
+        self.min
+    }
+
+    /// Records the L1 nonce seen at `now` with `backlog` nonces pending and
+    /// returns the pause before the next poll
+    pub fn next(&self, nonce: u64, backlog: u64, now: u64) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        if state.failures > 0 {
+            // 读取恢复后回到最短间隔
+            state.failures = 0;
+            state.last_nonce = Some(nonce);
+            state.changed_at = now;
+            state.current = self.min;
+        } else if state.last_nonce != Some(nonce) || backlog > 0 {
+            // 有积压时不算空闲，保持最短间隔
+            state.last_nonce = Some(nonce);
+            state.changed_at = now;
+            state.current = self.min;
+        } else if now.saturating_sub(state.changed_at) >= self.idle_after.as_secs() {
+            state.current = (state.current * 2).min(self.max);
+        }
+        state.current
+    }
+
+    /// Records a failed read of the nonces and returns the pause before the
+    /// next poll with the failures in a row so far
+    pub fn failed(&self) -> (Duration, u32) {
+        let mut state = self.state.lock().unwrap();
+        state.failures = state.failures.saturating_add(1);
+        state.current = (state.current * 2).clamp(self.min, self.max);
+        (state.current, state.failures)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_errors_widen_the_interval_up_to_the_max_until_a_success() {
+        let poll = AdaptivePoll::new(
+            Duration::from_millis(100),
+            Duration::from_millis(500),
+            Duration::from_secs(60),
+            0,
+        );
+        assert_eq!(poll.next(7, 0, 0), Duration::from_millis(100));
+        assert_eq!(poll.failed(), (Duration::from_millis(200), 1));
+        assert_eq!(poll.failed(), (Duration::from_millis(400), 2));
+        assert_eq!(poll.failed(), (Duration::from_millis(500), 3));
+        assert_eq!(poll.failed(), (Duration::from_millis(500), 4));
+        // 同一个 nonce 的成功读取也回到最短间隔
+        assert_eq!(poll.next(7, 0, 1), Duration::from_millis(100));
+        assert_eq!(poll.failed(), (Duration::from_millis(200), 1));
+    }
+
+    #[test]
+    fn an_idle_nonce_widens_the_interval_and_a_change_resets_it() {
+        let poll = AdaptivePoll::new(
+            Duration::from_millis(100),
+            Duration::from_millis(300),
+            Duration::from_secs(10),
+            5,
+        );
+        assert_eq!(poll.next(1, 0, 0), Duration::from_millis(100));
+        assert_eq!(poll.next(1, 0, 5), Duration::from_millis(100));
+        assert_eq!(poll.next(1, 0, 10), Duration::from_millis(200));
+        assert_eq!(poll.next(1, 0, 11), Duration::from_millis(300));
+        assert_eq!(poll.next(2, 0, 12), Duration::from_millis(100));
+        assert!(!poll.suspended(4));
+        assert!(poll.suspended(5));
+    }
+}
@@ -0,0 +1,271 @@
+//! Priority ordering of pending transfers.
+//! Outside of `priority = "nonce"` the submitter looks at the first
+//! `priority_window` pending nonces, reads their amounts once, and relays the
+//! highest ranked ones first. This needs an L2 program that accepts
+//! out-of-order nonces.
+//!
+//! Nonces relayed ahead of the lowest pending nonce are persisted, and the
+//! queue only moves past a nonce once every nonce below it has a receipt
+//! (the L2 nonce account is not consulted), so a restart never skips a
+//! transfer. A transfer that has waited
+//! `priority_max_wait_secs` in the window outranks every transfer that has
+//! not, oldest first, so small transfers are not starved by large ones.
+
+use crate::{
+    state::StateStore,
+    submitter::{held_error, Submitted},
+    throughput::Allowance,
+    Relayer,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet},
+    sync::Mutex,
+    time::Duration,
+};
+
+/// State key holding the nonces relayed ahead of the completed prefix
+pub const RELAYED_AHEAD_KEY: &str = "relayed_ahead";
+
+/// Order in which pending transfers are relayed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueuePriority {
+    /// Consecutive nonces, lowest first
+    #[default]
+    Nonce,
+    /// Largest amount first
+    AmountDesc,
+    /// Longest waiting first
+    OldestFirst,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    amount: u64,
+    /// Unix time at which the nonce entered the window
+    first_seen: u64,
+}
+
+pub struct PrioritySelector {
+    priority: QueuePriority,
+    /// Pending nonces ranked per batch
+    window: usize,
+    /// Wait after which a transfer outranks every other ordering
+    max_wait: Duration,
+    candidates: Mutex<BTreeMap<u64, Candidate>>,
+    relayed_ahead: Mutex<BTreeSet<u64>>,
+}
+
+impl PrioritySelector {
+    pub fn load(
+        store: &StateStore,
+        priority: QueuePriority,
+        window: usize,
+        max_wait: Duration,
+    ) -> Result<Self> {
+        let relayed_ahead: BTreeSet<u64> = store.get(RELAYED_AHEAD_KEY)?.unwrap_or_default();
+        if !relayed_ahead.is_empty() {
+            tracing::info!(
+                "{} nonce(s) relayed ahead of the lowest pending nonce",
+                relayed_ahead.len()
+            );
+        }
+        Ok(Self {
+            priority,
+            window: window.max(1),
+            max_wait,
+            candidates: Mutex::new(BTreeMap::new()),
+            relayed_ahead: Mutex::new(relayed_ahead),
+        })
+    }
+
+    /// Nonces whose amount or relay ahead of the prefix is remembered
+    pub fn tracked(&self) -> usize {
+        self.candidates.lock().unwrap().len() + self.relayed_ahead.lock().unwrap().len()
+    }
+
+    fn is_relayed(&self, nonce: u64) -> bool {
+        self.relayed_ahead.lock().unwrap().contains(&nonce)
+    }
+
+    /// Nonces of `nonces` whose amount has not been read yet
+    fn unknown(&self, nonces: &[u64]) -> Vec<u64> {
+        let candidates = self.candidates.lock().unwrap();
+        nonces
+            .iter()
+            .copied()
+            .filter(|nonce| !candidates.contains_key(nonce))
+            .collect()
+    }
+
+    fn learn(&self, nonce: u64, amount: u64, now: u64) {
+        self.candidates.lock().unwrap().insert(
+            nonce,
+            Candidate {
+                amount,
+                first_seen: now,
+            },
+        );
+    }
+
+    /// Up to `max` of `nonces`, highest ranked first
+    fn select(&self, nonces: &[u64], max: usize, now: u64) -> Vec<u64> {
+        let candidates = self.candidates.lock().unwrap();
+        let mut ranked: Vec<(u64, Candidate)> = nonces
+            .iter()
+            .filter_map(|nonce| candidates.get(nonce).map(|candidate| (*nonce, *candidate)))
+            .collect();
+        ranked.sort_by_key(|(nonce, candidate)| {
+            let overdue = now.saturating_sub(candidate.first_seen) >= self.max_wait.as_secs();
+            // 超时的交易优先，按等待时间排序；其余按配置的顺序，nonce 决定平局
+            match (overdue, self.priority) {
+                (true, _) | (false, QueuePriority::OldestFirst) => {
+                    (!overdue, candidate.first_seen, Reverse(0), *nonce)
+                }
+                (false, QueuePriority::AmountDesc) => (true, 0, Reverse(candidate.amount), *nonce),
+                (false, QueuePriority::Nonce) => (true, 0, Reverse(0), *nonce),
+            }
+        });
+        ranked
+            .into_iter()
+            .take(max.max(1))
+            .map(|(nonce, _)| nonce)
+            .collect()
+    }
+
+    /// Records `nonces` as relayed ahead of the completed prefix
+    fn mark_relayed(&self, store: &StateStore, nonces: &[u64]) -> Result<()> {
+        let mut relayed_ahead = self.relayed_ahead.lock().unwrap();
+        relayed_ahead.extend(nonces);
+        store.put(RELAYED_AHEAD_KEY, &*relayed_ahead)
+    }
+
+    /// Drops the relayed nonces from `from` on that are consecutive and
+    /// returns the end of that run, the new lowest pending nonce
+    fn take_prefix(&self, store: &StateStore, from: u64) -> Result<u64> {
+        let mut relayed_ahead = self.relayed_ahead.lock().unwrap();
+        let mut end = from;
+        while relayed_ahead.contains(&end) {
+            end += 1;
+        }
+        let before = relayed_ahead.len();
+        *relayed_ahead = relayed_ahead.split_off(&end);
+        if relayed_ahead.len() != before {
+            store.put(RELAYED_AHEAD_KEY, &*relayed_ahead)?;
+        }
+        let mut candidates = self.candidates.lock().unwrap();
+        *candidates = candidates.split_off(&end);
+        Ok(end)
+    }
+}
+
+impl Relayer {
+    /// Relays the highest ranked pending nonces of the window. `retries` is
+    /// the number of batches that failed in a row before this one.
+    pub(crate) async fn submit_prioritized_batch(
+        &self,
+        selector: &PrioritySelector,
+        retries: u32,
+    ) -> Result<Submitted> {
+        // 乱序提交时 L2 nonce account 不代表连续完成的前缀，只按本地记录推进
+        self.advance_prefix(selector)?;
+
+        let now = self.clock.unix_timestamp();
+        let mut window = Vec::new();
+        for nonce in self.queue.peek_batch(selector.window) {
+            if !selector.is_relayed(nonce)
+                && !self.dead_letters.contains(nonce)
+                && self.watched.rewound_to().is_none_or(|l1_nonce| nonce < l1_nonce)
+                && self.queue.retry_at(nonce).is_none_or(|at| at <= now)
+                // 等待审批的大额转账和保留期内的转账不占用批次
+                && !self.awaiting_approval(nonce)?
+                && !self.in_quiet_period(nonce)?
+            {
+                window.push(nonce);
+            }
+        }
+        if window.is_empty() {
+            // 窗口内只剩死信、退避中、等待审批或处于保留期的 nonce
+            return Ok(Submitted::Held(self.submitter.retry_delay));
+        }
+
+        let unknown = selector.unknown(&window);
+        for chunk in unknown.chunks(self.fetch_chunk_size()) {
+            for fetched in self.fetch_transfers(chunk, retries).await? {
+                selector.learn(fetched.nonce, fetched.amount, now);
+            }
+        }
+        // 被跳过的未知类型消息只留下回执，直接记为已处理
+        let mut skipped = Vec::new();
+        for nonce in selector.unknown(&unknown) {
+            if self.already_relayed(nonce)? {
+                skipped.push(nonce);
+            }
+        }
+        selector.mark_relayed(&self.state, &skipped)?;
+        let mut batch = selector.select(&window, self.batch_size(), now);
+        if let Some(wait) = self.l2_pause_hold(batch.len() as u64).await? {
+            return Ok(Submitted::Held(wait));
+        }
+        // 限流时保留排名最高的 nonce
+        match self.throughput_allowance(batch.len() as u64)? {
+            Allowance::Relay(allowed) => batch.truncate(allowed as usize),
+            Allowance::Hold(wait) => return Ok(Submitted::Held(wait)),
+        }
+
+        tracing::debug!("Processing nonce change...");
+        tracing::info!(
+            "Relaying {} of {} pending nonces by {:?} priority: {:?}",
+            batch.len(),
+            window.len(),
+            selector.priority,
+            batch
+        );
+
+        self.ensure_wallet_funded().await?;
+        let result = self.process_nonces(&batch, retries).await;
+
+        // 失败的批次中已确认的交易同样记为已处理，避免重复提交
+        let mut landed = Vec::with_capacity(batch.len());
+        for nonce in &batch {
+            if self.already_relayed(*nonce)? {
+                landed.push(*nonce);
+            }
+        }
+        selector.mark_relayed(&self.state, &landed)?;
+        self.advance_prefix(selector)?;
+        if let Err(e) = result {
+            if let Some(held) = held_error(&e) {
+                tracing::info!("{}", held);
+                if landed.is_empty() {
+                    return Ok(Submitted::Held(self.submitter.retry_delay));
+                }
+                return Ok(Submitted::Relayed(landed.len() as u64));
+            }
+            if let Some(failed) = batch.iter().find(|nonce| !landed.contains(nonce)) {
+                self.record_nonce_failure(*failed, &e)?;
+            }
+            self.expire_l2_pause();
+            return Err(e);
+        }
+        Ok(Submitted::Relayed(landed.len() as u64))
+    }
+
+    /// Moves the queue past the relayed nonces at its front
+    fn advance_prefix(&self, selector: &PrioritySelector) -> Result<()> {
+        let Some(&from) = self.queue.peek_batch(1).first() else {
+            return Ok(());
+        };
+        let to = selector.take_prefix(&self.state, from)?;
+        if to > from {
+            if let Some(accounts) = &self.message_accounts {
+                accounts.mark_processed(&self.state, from, to)?;
+            }
+            self.queue.prune_below(&self.state, to)?;
+        }
+        Ok(())
+    }
+}
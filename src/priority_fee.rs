@@ -0,0 +1,183 @@
+//! Compute-unit prices of relay transactions.
+//! With `priority_fee_strategy` set, every relay transaction carries a
+//! `ComputeBudgetInstruction::set_compute_unit_price`, and with
+//! `compute_unit_limit` a `set_compute_unit_limit` as well, so congested
+//! leaders do not drop it. `static` pays `compute_unit_price_micro_lamports`;
+//! `dynamic` pays the `priority_fee_percentile` of `getRecentPrioritizationFees`
+//! for the L2 program and the nonce account, which every relay writes,
+//! re-read at most every `priority_fee_refresh_secs`, and never less than
+//! `compute_unit_price_micro_lamports` or more than
+//! `compute_unit_price_max_micro_lamports`. A failed read reuses the last
+//! price, or the floor before the first one.
+//!
+//! The compute-budget instructions follow the relay instructions, so
+//! `relay_message` stays the first instruction (after `AdvanceNonceAccount`
+//! in durable-nonce transactions); the runtime reads them
+//! wherever they are. The fee estimate of `getFeeForMessage` includes the
+//! priority fee.
+
+use crate::{config::RelayerConfig, Relayer};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_sdk::{compute_budget::ComputeBudgetInstruction, instruction::Instruction};
+use std::{
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+/// How the compute-unit price of a relay transaction is chosen
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PriorityFeeStrategy {
+    /// No compute-budget instructions
+    #[default]
+    None,
+    /// `compute_unit_price_micro_lamports`
+    Static,
+    /// From the recent prioritization fees, within the floor and the cap
+    Dynamic,
+}
+
+/// Compute-budget instructions of one transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ComputeBudget {
+    /// Micro-lamports per compute unit
+    pub unit_price: u64,
+    pub unit_limit: Option<u32>,
+}
+
+impl ComputeBudget {
+    pub fn instructions(&self) -> Vec<Instruction> {
+        let mut instructions = Vec::with_capacity(2);
+        if let Some(limit) = self.unit_limit {
+            instructions.push(ComputeBudgetInstruction::set_compute_unit_limit(limit));
+        }
+        instructions.push(ComputeBudgetInstruction::set_compute_unit_price(
+            self.unit_price,
+        ));
+        instructions
+    }
+}
+
+pub struct PriorityFees {
+    strategy: PriorityFeeStrategy,
+    floor: u64,
+    cap: u64,
+    percentile: u8,
+    unit_limit: Option<u32>,
+    refresh: Duration,
+    /// Last dynamic price and when it was read
+    price: Mutex<Option<(u64, SystemTime)>>,
+}
+
+impl PriorityFees {
+    /// None with the `none` strategy
+    pub fn new(
+        strategy: PriorityFeeStrategy,
+        floor: u64,
+        cap: u64,
+        percentile: u8,
+        unit_limit: Option<u32>,
+        refresh: Duration,
+    ) -> Result<Option<Self>> {
+        if strategy == PriorityFeeStrategy::None {
+            return Ok(None);
+        }
+        if percentile > 100 {
+            return Err(anyhow::anyhow!(
+                "priority_fee_percentile must be between 0 and 100, got {}",
+                percentile
+            ));
+        }
+        if strategy == PriorityFeeStrategy::Dynamic && cap < floor {
+            return Err(anyhow::anyhow!(
+                "compute_unit_price_max_micro_lamports ({}) is below compute_unit_price_micro_lamports ({})",
+                cap,
+                floor
+            ));
+        }
+        Ok(Some(Self {
+            strategy,
+            floor,
+            cap,
+            percentile,
+            unit_limit,
+            refresh,
+            price: Mutex::new(None),
+        }))
+    }
+
+    /// Prices of `config`, none with the `none` strategy
+    pub fn from_config(config: &RelayerConfig) -> Result<Option<Self>> {
+        Self::new(
+            config.priority_fee_strategy,
+            config.compute_unit_price_micro_lamports,
+            config.compute_unit_price_max_micro_lamports,
+            config.priority_fee_percentile,
+            config.compute_unit_limit,
+            Duration::from_secs(config.priority_fee_refresh_secs),
+        )
+    }
+
+    /// The `percentile` of `fees`, within the floor and the cap
+    fn choose(&self, mut fees: Vec<u64>) -> u64 {
+        fees.sort_unstable();
+        let index = (fees.len().saturating_sub(1) * self.percentile as usize) / 100;
+        fees.get(index)
+            .copied()
+            .unwrap_or_default()
+            .clamp(self.floor, self.cap)
+    }
+}
+
+impl Relayer {
+    /// Compute budget of the next relay transaction, none without a
+    /// `priority_fee_strategy`
+    pub(crate) async fn compute_budget(&self) -> Option<ComputeBudget> {
+        let fees = self.priority_fees.lock().unwrap().clone()?;
+        let unit_price = match fees.strategy {
+            PriorityFeeStrategy::Dynamic => match self.dynamic_unit_price(&fees).await {
+                Ok(price) => price,
+                Err(e) => {
+                    let last = fees.price.lock().unwrap().map(|(price, _)| price);
+                    let price = last.unwrap_or(fees.floor);
+                    tracing::warn!(
+                        "failed to read the prioritization fees, paying {} micro-lamports per CU: {}",
+                        price, e
+                    );
+                    price
+                }
+            },
+            _ => fees.floor,
+        };
+        self.metrics.compute_unit_price.set(unit_price);
+        Some(ComputeBudget {
+            unit_price,
+            unit_limit: fees.unit_limit,
+        })
+    }
+
+    async fn dynamic_unit_price(&self, fees: &PriorityFees) -> Result<u64> {
+        let now = self.clock.now();
+        if let Some((price, read_at)) = *fees.price.lock().unwrap() {
+            if now.duration_since(read_at).unwrap_or_default() < fees.refresh {
+                return Ok(price);
+            }
+        }
+        let recent = self
+            .l2_client
+            .get_recent_prioritization_fees(&[
+                *self.transaction_builder.program_id.pubkey(),
+                *self.transaction_builder.nonce_account.pubkey(),
+            ])
+            .await?;
+        let price = fees.choose(
+            recent
+                .into_iter()
+                .map(|fee| fee.prioritization_fee)
+                .collect(),
+        );
+        *fees.price.lock().unwrap() = Some((price, now));
+        Ok(price)
+    }
+}
@@ -0,0 +1,157 @@
+//! Program-account message discovery.
+//! Some L1 programs create one message account per transfer, flagged by a
+//! "pending" status byte, instead of PDAs derived from a nonce counter. In
+//! this mode the monitor finds those accounts with `getProgramAccounts` and
+//! the relayer reads transfers from the discovered addresses.
+//!
+//! Scans only fetch the nonce bytes of each account (`dataSlice`), keep the
+//! lowest `max_accounts_per_scan` unprocessed nonces, and leave reading the
+//! full accounts to the pipeline's `getMultipleAccounts` batches, so memory
+//! stays bounded on programs with many accounts.
+
+use crate::{state::StateStore, Relayer};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_account_decoder::{UiAccountEncoding, UiDataSliceConfig};
+use solana_client::{
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, RpcFilterType},
+};
+use solana_sdk::pubkey::Pubkey;
+use std::{collections::BTreeMap, sync::Mutex};
+
+/// State key holding the message accounts already relayed
+pub const PROCESSED_ACCOUNTS_KEY: &str = "processed_message_accounts";
+
+/// Layout of the message accounts (`[program_accounts]` section)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProgramAccountsConfig {
+    /// Offset of the message nonce (u64 LE)
+    pub nonce_offset: usize,
+    /// Offset of the status byte
+    #[serde(default = "default_status_offset")]
+    pub status_offset: usize,
+    /// Status byte value of messages waiting to be relayed
+    #[serde(default)]
+    pub pending_status: u8,
+    /// Only consider accounts of exactly this size
+    #[serde(default)]
+    pub data_size: Option<u64>,
+    /// Pending accounts taken from one scan, lowest nonces first
+    #[serde(default = "default_max_accounts_per_scan")]
+    pub max_accounts_per_scan: usize,
+}
+
+fn default_status_offset() -> usize {
+    86
+}
+
+fn default_max_accounts_per_scan() -> usize {
+    10_000
+}
+
+/// Message accounts discovered by the scans, keyed by nonce
+pub struct MessageAccounts {
+    settings: ProgramAccountsConfig,
+    discovered: Mutex<BTreeMap<u64, Pubkey>>,
+    /// Relayed accounts (base58) and their nonces
+    processed: Mutex<BTreeMap<String, u64>>,
+}
+
+impl MessageAccounts {
+    pub fn load(store: &StateStore, settings: ProgramAccountsConfig) -> Result<Self> {
+        Ok(Self {
+            settings,
+            discovered: Mutex::new(BTreeMap::new()),
+            processed: Mutex::new(store.get(PROCESSED_ACCOUNTS_KEY)?.unwrap_or_default()),
+        })
+    }
+
+    /// Address of the message account carrying `nonce`
+    pub fn address(&self, nonce: u64) -> Result<Pubkey> {
+        self.discovered
+            .lock()
+            .unwrap()
+            .get(&nonce)
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("No message account discovered yet for nonce {}", nonce))
+    }
+
+    /// Records the accounts of nonces `from..to` as relayed and forgets those
+    /// below `from`, which the L2 nonce account already covers
+    pub fn mark_processed(&self, store: &StateStore, from: u64, to: u64) -> Result<()> {
+        let mut discovered = self.discovered.lock().unwrap();
+        let mut processed = self.processed.lock().unwrap();
+        processed.retain(|_, nonce| *nonce >= from);
+        for (nonce, account) in discovered.range(from..to) {
+            processed.insert(account.to_string(), *nonce);
+        }
+        store.put(PROCESSED_ACCOUNTS_KEY, &*processed)?;
+        *discovered = discovered.split_off(&from);
+        Ok(())
+    }
+
+    fn filters(&self) -> Vec<RpcFilterType> {
+        let mut filters = vec![RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+            self.settings.status_offset,
+            &[self.settings.pending_status],
+        ))];
+        if let Some(size) = self.settings.data_size {
+            filters.push(RpcFilterType::DataSize(size));
+        }
+        filters
+    }
+}
+
+impl Relayer {
+    /// Scans the L1 program for pending message accounts and returns the end
+    /// of the consecutive nonce run starting at `relayed`, i.e. the nonce up
+    /// to which transfers can be queued
+    pub(crate) async fn scan_message_accounts(
+        &self,
+        accounts: &MessageAccounts,
+        relayed: u64,
+    ) -> Result<u64> {
+        let config = RpcProgramAccountsConfig {
+            filters: Some(accounts.filters()),
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                data_slice: Some(UiDataSliceConfig {
+                    offset: accounts.settings.nonce_offset,
+                    length: 8,
+                }),
+                commitment: Some(self.l1_client.commitment()),
+                min_context_slot: None,
+            },
+            with_context: None,
+        };
+        let found = self
+            .l1_client
+            .get_program_accounts_with_config(self.pda_manager.program_id().pubkey(), config)
+            .await
+            .map_err(|e| anyhow::anyhow!("Message account scan failed: {}", e))?;
+
+        let mut pending = BTreeMap::new();
+        {
+            let processed = accounts.processed.lock().unwrap();
+            for (address, account) in found {
+                let Ok(nonce) = account.data.as_slice().try_into().map(u64::from_le_bytes) else {
+                    tracing::info!("Skipping message account {}: no nonce in its data", address);
+                    continue;
+                };
+                if nonce >= relayed && !processed.contains_key(&address.to_string()) {
+                    pending.insert(nonce, address);
+                }
+            }
+        }
+        let limit = accounts.settings.max_accounts_per_scan.max(1);
+        let pending: BTreeMap<u64, Pubkey> = pending.into_iter().take(limit).collect();
+
+        let mut next = relayed;
+        while pending.contains_key(&next) {
+            next += 1;
+        }
+        accounts.discovered.lock().unwrap().extend(pending);
+        Ok(next)
+    }
+}
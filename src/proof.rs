@@ -0,0 +1,375 @@
+//! Signed relay proofs.
+//! When a transaction confirms, the key that paid for it signs the statement
+//! "nonce N of this source was relayed in L2 transaction S", which is stored
+//! in the receipt as `proof` for downstream consumers. `verify-proof` checks a
+//! proof against a relayer pubkey.
+//!
+//! Canonical encoding, version 1 (183 bytes), all integers little-endian:
+//!
+//! | Bytes | Field |
+//! |-------|-------|
+//! | 30 | `b"sol-bridge-relayer/relay-proof"` |
+//! | 1 | version (`1`) |
+//! | 32 | source account: the source's L1 watched account |
+//! | 8 | nonce (`u64`) |
+//! | 8 | amount in lamports (`u64`) |
+//! | 32 | recipient |
+//! | 64 | L2 transaction signature |
+//! | 8 | L2 slot the transaction confirmed in (`u64`) |
+//!
+//! Version 2 (215 bytes) adds the 32-byte message digest (`Info::digest`)
+//! after the recipient, so the proof names the L1 message itself; it is
+//! written whenever the digest is known, i.e. unless the transaction was
+//! coalesced. The ed25519 signature covers these bytes. A coalesced
+//! transaction has one proof for its first nonce and the merged amount.
+//!
+//! Test vector: source account `[1; 32]`, nonce 7, amount 1000000, recipient
+//! `[2; 32]`, L2 signature `[3; 64]`, slot 42 encode as
+//!
+//! ```text
+//! 736f6c2d6272696467652d72656c617965722f72656c61792d70726f6f6601
+//! 0101010101010101010101010101010101010101010101010101010101010101
+//! 070000000000000040420f0000000000
+//! 0202020202020202020202020202020202020202020202020202020202020202
+//! 0303030303030303030303030303030303030303030303030303030303030303
+//! 0303030303030303030303030303030303030303030303030303030303030303
+//! 2a00000000000000
+//! ```
+//!
+//! and signed by the keypair with seed `[4; 32]`
+//! (`EdmxWPmx2WH6WgFfTdu9xfkYf3k1g5wD1zccTVySEEh1`) give the signature
+//! `VcRwC8bFZqZLAHLhwsff6BC6qniLBrPiH1SLZeXwDymr8UaFyZ3sHS3PZQTwFPwFUH9noA39Fa3vWt1hvvaCUqx`.
+//! With message digest `[5; 32]` the same statement encodes in version 2 as
+//!
+//! ```text
+//! 736f6c2d6272696467652d72656c617965722f72656c61792d70726f6f6602
+//! 0101010101010101010101010101010101010101010101010101010101010101
+//! 070000000000000040420f0000000000
+//! 0202020202020202020202020202020202020202020202020202020202020202
+//! 0505050505050505050505050505050505050505050505050505050505050505
+//! 0303030303030303030303030303030303030303030303030303030303030303
+//! 0303030303030303030303030303030303030303030303030303030303030303
+//! 2a00000000000000
+//! ```
+//!
+//! with the signature
+//! `EjRFNhUDLMWLGcgp4vsoLRCyGFq4UvBAxThmFYARW8i9vArcbJRSYmwoMmqLjoVuCnvbXMy6VHcTQ7sBp9PshBy`.
+
+use crate::{inflight::InFlightTransfer, Relayer};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_sdk::{
+    hash::Hash,
+    pubkey::Pubkey,
+    signature::{Signature, Signer},
+};
+use std::str::FromStr;
+
+/// Prefix of the canonical encoding, separating proofs from other signed data
+const DOMAIN_TAG: &[u8] = b"sol-bridge-relayer/relay-proof";
+/// Version of the canonical encoding without a message digest
+pub const RELAY_PROOF_VERSION: u8 = 1;
+/// Version of the canonical encoding with the message digest
+pub const RELAY_PROOF_DIGEST_VERSION: u8 = 2;
+
+/// What a relay proof states
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RelayStatement {
+    pub source_account: Pubkey,
+    pub nonce: u64,
+    pub amount: u64,
+    pub recipient: Pubkey,
+    pub l2_signature: Signature,
+    pub slot: u64,
+    /// Digest of the relayed L1 message, none if coalesced
+    pub message_digest: Option<Hash>,
+}
+
+impl RelayStatement {
+    /// Version the statement is signed in: with the digest when it has one
+    pub fn version(&self) -> u8 {
+        if self.message_digest.is_some() {
+            RELAY_PROOF_DIGEST_VERSION
+        } else {
+            RELAY_PROOF_VERSION
+        }
+    }
+
+    /// Canonical encoding of the statement in `version`
+    pub fn encode(&self, version: u8) -> Result<Vec<u8>> {
+        let digest = match (version, self.message_digest) {
+            (RELAY_PROOF_VERSION, _) => None,
+            (RELAY_PROOF_DIGEST_VERSION, Some(digest)) => Some(digest),
+            (RELAY_PROOF_DIGEST_VERSION, None) => {
+                return Err(anyhow::anyhow!(
+                    "Relay proof version {} requires a message digest",
+                    version
+                ))
+            }
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Unsupported relay proof version {}",
+                    version
+                ))
+            }
+        };
+        let mut bytes = Vec::with_capacity(215);
+        bytes.extend_from_slice(DOMAIN_TAG);
+        bytes.push(version);
+        bytes.extend_from_slice(self.source_account.as_ref());
+        bytes.extend_from_slice(&self.nonce.to_le_bytes());
+        bytes.extend_from_slice(&self.amount.to_le_bytes());
+        bytes.extend_from_slice(self.recipient.as_ref());
+        if let Some(digest) = digest {
+            bytes.extend_from_slice(digest.as_ref());
+        }
+        bytes.extend_from_slice(self.l2_signature.as_ref());
+        bytes.extend_from_slice(&self.slot.to_le_bytes());
+        Ok(bytes)
+    }
+}
+
+/// A relay statement signed by the relayer, as stored in the receipt
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayProof {
+    pub version: u8,
+    /// L1 watched account of the source (base58)
+    pub source_account: String,
+    pub nonce: u64,
+    pub amount: u64,
+    /// Recipient (base58)
+    pub recipient: String,
+    pub l2_signature: String,
+    /// L2 slot the transaction confirmed in
+    pub slot: u64,
+    /// Digest of the relayed L1 message (base58), from version 2
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_digest: Option<String>,
+    /// Key that signed the proof (base58)
+    pub signer: String,
+    /// ed25519 signature over the canonical encoding (base58)
+    pub signature: String,
+}
+
+impl RelayProof {
+    /// Signs `statement` with `signer`
+    pub fn sign(statement: &RelayStatement, signer: &dyn Signer) -> Result<Self> {
+        let version = statement.version();
+        let signature = signer.sign_message(&statement.encode(version)?);
+        Ok(Self {
+            version,
+            source_account: statement.source_account.to_string(),
+            nonce: statement.nonce,
+            amount: statement.amount,
+            recipient: statement.recipient.to_string(),
+            l2_signature: statement.l2_signature.to_string(),
+            slot: statement.slot,
+            message_digest: statement.message_digest.map(|digest| digest.to_string()),
+            signer: signer.pubkey().to_string(),
+            signature: signature.to_string(),
+        })
+    }
+
+    pub fn statement(&self) -> Result<RelayStatement> {
+        let pubkey = |field: &str, value: &str| {
+            Pubkey::from_str(value)
+                .map_err(|e| anyhow::anyhow!("Invalid {} {} in relay proof: {}", field, value, e))
+        };
+        Ok(RelayStatement {
+            source_account: pubkey("source account", &self.source_account)?,
+            nonce: self.nonce,
+            amount: self.amount,
+            recipient: pubkey("recipient", &self.recipient)?,
+            l2_signature: Signature::from_str(&self.l2_signature).map_err(|e| {
+                anyhow::anyhow!(
+                    "Invalid L2 signature {} in relay proof: {}",
+                    self.l2_signature,
+                    e
+                )
+            })?,
+            slot: self.slot,
+            message_digest: self
+                .message_digest
+                .as_deref()
+                .map(|digest| {
+                    Hash::from_str(digest).map_err(|e| {
+                        anyhow::anyhow!("Invalid message digest {} in relay proof: {}", digest, e)
+                    })
+                })
+                .transpose()?,
+        })
+    }
+
+    /// Checks that `relayer` signed this proof
+    pub fn verify(&self, relayer: &Pubkey) -> Result<()> {
+        if self.signer != relayer.to_string() {
+            return Err(anyhow::anyhow!(
+                "Relay proof is signed by {}, not by {}",
+                self.signer,
+                relayer
+            ));
+        }
+        let message = self.statement()?.encode(self.version)?;
+        let signature = Signature::from_str(&self.signature)
+            .map_err(|e| anyhow::anyhow!("Invalid relay proof signature: {}", e))?;
+        if !signature.verify(relayer.as_ref(), &message) {
+            return Err(anyhow::anyhow!(
+                "Relay proof signature does not match its contents"
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Relayer {
+    /// Proof that `transfer` was relayed, signed by the key that paid for it
+    /// (the active key if that one is no longer configured)
+    pub(crate) fn relay_proof(&self, transfer: &InFlightTransfer, slot: u64) -> Result<RelayProof> {
+        let statement = RelayStatement {
+            source_account: *self.watched_account.pubkey(),
+            nonce: transfer.nonce,
+            amount: transfer.amount,
+            recipient: Pubkey::from_str(&transfer.to)
+                .map_err(|e| anyhow::anyhow!("Invalid recipient {}: {}", transfer.to, e))?,
+            l2_signature: Signature::from_str(&transfer.signature)
+                .map_err(|e| anyhow::anyhow!("Invalid signature {}: {}", transfer.signature, e))?,
+            slot,
+            message_digest: transfer
+                .digest
+                .as_deref()
+                .map(|digest| {
+                    Hash::from_str(digest)
+                        .map_err(|e| anyhow::anyhow!("Invalid message digest {}: {}", digest, e))
+                })
+                .transpose()?,
+        };
+        let keypair = [Some(self.signers.active()), self.signers.standby()]
+            .into_iter()
+            .flatten()
+            .find(|key| key.pubkey().to_string() == transfer.signer)
+            .unwrap_or_else(|| self.signers.active());
+        RelayProof::sign(&statement, keypair)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::signer::keypair::keypair_from_seed;
+
+    /// The statement of the test vector in the module documentation
+    fn statement(message_digest: Option<Hash>) -> RelayStatement {
+        RelayStatement {
+            source_account: Pubkey::new_from_array([1; 32]),
+            nonce: 7,
+            amount: 1_000_000,
+            recipient: Pubkey::new_from_array([2; 32]),
+            l2_signature: Signature::new(&[3; 64]),
+            slot: 42,
+            message_digest,
+        }
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn statements_encode_and_sign_as_documented() {
+        let signer = keypair_from_seed(&[4; 32]).unwrap();
+        assert_eq!(
+            signer.pubkey().to_string(),
+            "EdmxWPmx2WH6WgFfTdu9xfkYf3k1g5wD1zccTVySEEh1"
+        );
+
+        let v1 = statement(None);
+        let encoded = v1.encode(RELAY_PROOF_VERSION).unwrap();
+        assert_eq!(encoded.len(), 183);
+        assert_eq!(
+            hex(&encoded),
+            [
+                "736f6c2d6272696467652d72656c617965722f72656c61792d70726f6f6601",
+                "0101010101010101010101010101010101010101010101010101010101010101",
+                "070000000000000040420f0000000000",
+                "0202020202020202020202020202020202020202020202020202020202020202",
+                "0303030303030303030303030303030303030303030303030303030303030303",
+                "0303030303030303030303030303030303030303030303030303030303030303",
+                "2a00000000000000",
+            ]
+            .concat()
+        );
+        let proof = RelayProof::sign(&v1, &signer).unwrap();
+        assert_eq!(proof.version, RELAY_PROOF_VERSION);
+        assert_eq!(
+            proof.signature,
+            "VcRwC8bFZqZLAHLhwsff6BC6qniLBrPiH1SLZeXwDymr8UaFyZ3sHS3PZQTwFPwFUH9noA39Fa3vWt1hvvaCUqx"
+        );
+        proof.verify(&signer.pubkey()).unwrap();
+
+        let v2 = statement(Some(Hash::new_from_array([5; 32])));
+        let encoded = v2.encode(RELAY_PROOF_DIGEST_VERSION).unwrap();
+        assert_eq!(encoded.len(), 215);
+        assert_eq!(
+            hex(&encoded),
+            [
+                "736f6c2d6272696467652d72656c617965722f72656c61792d70726f6f6602",
+                "0101010101010101010101010101010101010101010101010101010101010101",
+                "070000000000000040420f0000000000",
+                "0202020202020202020202020202020202020202020202020202020202020202",
+                "0505050505050505050505050505050505050505050505050505050505050505",
+                "0303030303030303030303030303030303030303030303030303030303030303",
+                "0303030303030303030303030303030303030303030303030303030303030303",
+                "2a00000000000000",
+            ]
+            .concat()
+        );
+        let proof = RelayProof::sign(&v2, &signer).unwrap();
+        assert_eq!(proof.version, RELAY_PROOF_DIGEST_VERSION);
+        assert_eq!(
+            proof.signature,
+            "EjRFNhUDLMWLGcgp4vsoLRCyGFq4UvBAxThmFYARW8i9vArcbJRSYmwoMmqLjoVuCnvbXMy6VHcTQ7sBp9PshBy"
+        );
+        proof.verify(&signer.pubkey()).unwrap();
+    }
+
+    #[test]
+    fn tampered_proofs_fail_verification() {
+        let signer = keypair_from_seed(&[4; 32]).unwrap();
+        let proof =
+            RelayProof::sign(&statement(Some(Hash::new_from_array([5; 32]))), &signer).unwrap();
+        let tampered = [
+            RelayProof {
+                nonce: 8,
+                ..proof.clone()
+            },
+            RelayProof {
+                amount: 1_000_001,
+                ..proof.clone()
+            },
+            RelayProof {
+                recipient: Pubkey::new_unique().to_string(),
+                ..proof.clone()
+            },
+            RelayProof {
+                source_account: Pubkey::new_unique().to_string(),
+                ..proof.clone()
+            },
+            RelayProof {
+                slot: 43,
+                ..proof.clone()
+            },
+            RelayProof {
+                message_digest: Some(Hash::new_from_array([6; 32]).to_string()),
+                ..proof.clone()
+            },
+            RelayProof {
+                version: RELAY_PROOF_VERSION,
+                ..proof.clone()
+            },
+        ];
+        for proof in tampered {
+            assert!(proof.verify(&signer.pubkey()).is_err(), "{:?}", proof);
+        }
+        assert!(proof.verify(&Pubkey::new_unique()).is_err());
+    }
+}
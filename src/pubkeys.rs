@@ -0,0 +1,114 @@
+//! Role-tagged pubkeys.
+//! Accounts of the watched chain and of the chain transactions are sent to
+//! are both `Pubkey`, so passing one where the other is expected compiles;
+//! these newtypes make it a type error. They are tagged by role, not by
+//! chain: in a withdrawal bridge (`direction = "l2_to_l1"`) the source
+//! accounts live on L2 and the destination accounts on L1. The config's
+//! address fields deserialize straight into them; `From<Pubkey>` tags a bare
+//! key and `pubkey()` hands it back to RPC calls and derivations. Each
+//! serializes as its base58 string, like the config.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use solana_sdk::pubkey::{ParsePubkeyError, Pubkey};
+use std::{fmt, str::FromStr};
+
+macro_rules! chain_pubkey {
+    ($(#[$doc:meta])* $name:ident) => {
+        $(#[$doc])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub struct $name(Pubkey);
+
+        impl $name {
+            pub fn pubkey(&self) -> &Pubkey {
+                &self.0
+            }
+        }
+
+        impl From<Pubkey> for $name {
+            fn from(pubkey: Pubkey) -> Self {
+                Self(pubkey)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = ParsePubkeyError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                Pubkey::from_str(s).map(Self)
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(&self.0)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let text = String::deserialize(deserializer)?;
+                Self::from_str(&text).map_err(|e| {
+                    serde::de::Error::custom(format!("invalid pubkey {}: {}", text, e))
+                })
+            }
+        }
+    };
+}
+
+chain_pubkey!(
+    /// An account on the watched chain, e.g. the watched account or a
+    /// transfer-info PDA
+    SourceAccount
+);
+chain_pubkey!(
+    /// An account on the chain transactions are sent to, e.g. a source's
+    /// nonce account
+    DestinationAccount
+);
+chain_pubkey!(
+    /// The watched source program
+    SourceProgramId
+);
+chain_pubkey!(
+    /// The bridge program transactions call
+    DestinationProgramId
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_tagged_key_reads_and_prints_as_its_base58_string() {
+        let pubkey = Pubkey::new_unique();
+        let account = SourceAccount::from(pubkey);
+        assert_eq!(account.pubkey(), &pubkey);
+        assert_eq!(account.to_string(), pubkey.to_string());
+        assert_eq!(
+            DestinationProgramId::from_str(&pubkey.to_string()).unwrap(),
+            DestinationProgramId::from(pubkey)
+        );
+
+        let json = serde_json::to_string(&account).unwrap();
+        assert_eq!(json, format!("\"{}\"", pubkey));
+        assert_eq!(
+            serde_json::from_str::<SourceAccount>(&json).unwrap(),
+            account
+        );
+    }
+
+    #[test]
+    fn an_invalid_key_is_refused_with_its_text() {
+        assert!(DestinationAccount::from_str("not a pubkey").is_err());
+        let error = serde_json::from_str::<DestinationAccount>("\"not a pubkey\"")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("invalid pubkey not a pubkey"), "{}", error);
+    }
+}
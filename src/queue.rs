@@ -0,0 +1,371 @@
+//! Bounded pending-transfer queue.
+//! The monitor pushes newly observed nonces and the submitter pulls them, so a
+//! slow L2 never delays detection of new L1 nonces. The queue is persisted in
+//! the state store so pending work survives restarts.
+
+use crate::{
+    attempts::{AttemptRecord, FAILURE_HISTORY_LIMIT},
+    state::StateStore,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    ops::Range,
+    sync::Mutex,
+    time::Duration,
+};
+use tokio::sync::Notify;
+
+/// State key holding the in-memory part of the queue
+pub const PENDING_QUEUE_KEY: &str = "pending_queue";
+/// State key holding nonce ranges spilled to disk while the queue was full
+pub const PENDING_OVERFLOW_KEY: &str = "pending_overflow";
+
+/// What the monitor does when the pending queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Wait for the submitter to free space
+    Block,
+    /// Record the excess nonces on disk and load them back as space frees up
+    SpillToDisk,
+}
+
+/// Failed submission attempts of one pending nonce
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NonceRetry {
+    pub attempts: u32,
+    /// Unix time before which the nonce is not tried again
+    pub next_retry_at: u64,
+    pub last_error: String,
+    /// Most recent failed attempts, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failures: Vec<AttemptRecord>,
+}
+
+/// Exponential backoff between attempts of a failing nonce
+#[derive(Debug, Clone, Copy)]
+pub struct RetryBackoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl RetryBackoff {
+    /// No wait, for attempts that are safe to repeat at once
+    pub const IMMEDIATE: Self = Self {
+        initial: Duration::ZERO,
+        max: Duration::ZERO,
+    };
+
+    /// Wait after the `attempts`th failed attempt
+    pub fn delay(&self, attempts: u32) -> Duration {
+        let factor = 1u32
+            .checked_shl(attempts.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        self.initial.saturating_mul(factor).min(self.max)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct QueuedNonces {
+    /// Nonces waiting for submission, oldest first
+    pending: VecDeque<u64>,
+    /// Next nonce the monitor has not enqueued yet
+    next_nonce: u64,
+    /// Pending nonces that failed at least once
+    #[serde(default)]
+    retries: BTreeMap<u64, NonceRetry>,
+}
+
+/// A `[start, end)` range of nonces spilled to disk
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct NonceRange {
+    start: u64,
+    end: u64,
+}
+
+pub struct PendingQueue {
+    capacity: usize,
+    policy: OverflowPolicy,
+    queued: Mutex<QueuedNonces>,
+    overflow: Mutex<VecDeque<NonceRange>>,
+    not_empty: Notify,
+    not_full: Notify,
+}
+
+impl PendingQueue {
+    /// Restores the queue persisted in `store`
+    pub fn load(store: &StateStore, capacity: usize, policy: OverflowPolicy) -> Result<Self> {
+        let queued: QueuedNonces = store.get(PENDING_QUEUE_KEY)?.unwrap_or_default();
+        let overflow: VecDeque<NonceRange> = store.get(PENDING_OVERFLOW_KEY)?.unwrap_or_default();
+        if !queued.pending.is_empty() || !overflow.is_empty() {
+            tracing::info!(
+                "Restored pending queue: {} queued, {} spilled to disk",
+                queued.pending.len(),
+                overflow
+                    .iter()
+                    .map(|range| range.end - range.start)
+                    .sum::<u64>()
+            );
+        }
+
+        Ok(Self {
+            capacity: capacity.max(1),
+            policy,
+            queued: Mutex::new(queued),
+            overflow: Mutex::new(overflow),
+            not_empty: Notify::new(),
+            not_full: Notify::new(),
+        })
+    }
+
+    /// Enqueues the nonces in `from..to` that have not been enqueued before.
+    /// When the queue is full this either waits or spills to disk, depending
+    /// on the overflow policy.
+    /// Returns the nonces that were not enqueued before.
+    pub async fn push_range(&self, store: &StateStore, from: u64, to: u64) -> Result<Range<u64>> {
+        let mut pushed: Option<Range<u64>> = None;
+        loop {
+            let not_full = self.not_full.notified();
+            let range = self.push_available(store, from, to)?;
+            let start = pushed.map_or(range.start, |pushed| pushed.start);
+            pushed = Some(start..range.end.max(start));
+            if self.queued.lock().unwrap().next_nonce >= to {
+                return Ok(pushed.unwrap());
+            }
+            not_full.await;
+        }
+    }
+
+    /// Enqueues (or spills) the nonces in `from..to` that fit without waiting
+    /// and returns those that were not enqueued before
+    pub fn push_available(&self, store: &StateStore, from: u64, to: u64) -> Result<Range<u64>> {
+        let mut overflow = self.overflow.lock().unwrap();
+        let mut queued = self.queued.lock().unwrap();
+        let start = from.max(queued.next_nonce);
+        if start >= to {
+            return Ok(to..to);
+        }
+        let mut next = start;
+
+        if overflow.is_empty() {
+            let space = self.capacity.saturating_sub(queued.pending.len()) as u64;
+            let end = to.min(next + space);
+            queued.pending.extend(next..end);
+            next = end;
+        }
+
+        if next < to && self.policy == OverflowPolicy::SpillToDisk {
+            // 与上一段相邻时合并，避免每次轮询都新增一段
+            match overflow.back_mut() {
+                Some(last) if last.end == next => last.end = to,
+                _ => overflow.push_back(NonceRange {
+                    start: next,
+                    end: to,
+                }),
+            }
+            store.put(PENDING_OVERFLOW_KEY, &*overflow)?;
+            next = to;
+        }
+
+        queued.next_nonce = queued.next_nonce.max(next);
+        store.put(PENDING_QUEUE_KEY, &*queued)?;
+        drop((overflow, queued));
+        self.not_empty.notify_one();
+        Ok(start..next)
+    }
+
+    /// Waits until at least one nonce is pending
+    pub async fn wait_for_pending(&self) {
+        loop {
+            let not_empty = self.not_empty.notified();
+            if !self.queued.lock().unwrap().pending.is_empty() {
+                return;
+            }
+            not_empty.await;
+        }
+    }
+
+    /// Returns up to `max` consecutive nonces from the front of the queue without removing them
+    pub fn peek_batch(&self, max: usize) -> Vec<u64> {
+        let queued = self.queued.lock().unwrap();
+        let Some(&first) = queued.pending.front() else {
+            return Vec::new();
+        };
+        queued
+            .pending
+            .iter()
+            .copied()
+            .enumerate()
+            .take_while(|(i, nonce)| *nonce == first + *i as u64)
+            .map(|(_, nonce)| nonce)
+            .take(max.max(1))
+            .collect()
+    }
+
+    /// Up to `max` pending nonces from the front of the queue, gaps included
+    pub fn front(&self, max: usize) -> Vec<u64> {
+        let queued = self.queued.lock().unwrap();
+        queued.pending.iter().copied().take(max).collect()
+    }
+
+    /// Drops every nonce below `relayed` (already relayed on L2) and refills
+    /// the queue from the disk overflow
+    pub fn prune_below(&self, store: &StateStore, relayed: u64) -> Result<()> {
+        let mut overflow = self.overflow.lock().unwrap();
+        let mut queued = self.queued.lock().unwrap();
+
+        let before = queued.pending.len() + queued.retries.len();
+        queued.pending.retain(|nonce| *nonce >= relayed);
+        queued.retries = queued.retries.split_off(&relayed);
+        let mut overflow_changed = false;
+        while let Some(range) = overflow.front_mut() {
+            if range.end <= relayed {
+                overflow.pop_front();
+                overflow_changed = true;
+            } else {
+                if range.start < relayed {
+                    range.start = relayed;
+                    overflow_changed = true;
+                }
+                break;
+            }
+        }
+
+        while queued.pending.len() < self.capacity {
+            let Some(range) = overflow.front_mut() else {
+                break;
+            };
+            let space = (self.capacity - queued.pending.len()) as u64;
+            let end = range.end.min(range.start + space);
+            queued.pending.extend(range.start..end);
+            range.start = end;
+            if range.start >= range.end {
+                overflow.pop_front();
+            }
+            overflow_changed = true;
+        }
+
+        if overflow_changed {
+            store.put(PENDING_OVERFLOW_KEY, &*overflow)?;
+        }
+        if queued.pending.len() + queued.retries.len() != before || overflow_changed {
+            store.put(PENDING_QUEUE_KEY, &*queued)?;
+        }
+        if queued.pending.len() < self.capacity {
+            self.not_full.notify_one();
+        }
+
+        Ok(())
+    }
+
+    /// Records a failed attempt of `nonce` and schedules its next one,
+    /// keeping the `FAILURE_HISTORY_LIMIT` most recent `failure` records
+    pub fn record_failure(
+        &self,
+        store: &StateStore,
+        nonce: u64,
+        error: &str,
+        now: u64,
+        backoff: RetryBackoff,
+        failure: Option<AttemptRecord>,
+    ) -> Result<NonceRetry> {
+        let mut queued = self.queued.lock().unwrap();
+        let retry = queued.retries.entry(nonce).or_insert(NonceRetry {
+            attempts: 0,
+            next_retry_at: now,
+            last_error: String::new(),
+            failures: Vec::new(),
+        });
+        retry.attempts += 1;
+        retry.next_retry_at = now + backoff.delay(retry.attempts).as_secs();
+        retry.last_error = error.to_string();
+        retry.failures.extend(failure);
+        let excess = retry.failures.len().saturating_sub(FAILURE_HISTORY_LIMIT);
+        retry.failures.drain(..excess);
+        let retry = retry.clone();
+        store.put(PENDING_QUEUE_KEY, &*queued)?;
+        Ok(retry)
+    }
+
+    /// Unix time before which `nonce` must not be tried again, if it failed before
+    pub fn retry_at(&self, nonce: u64) -> Option<u64> {
+        self.queued
+            .lock()
+            .unwrap()
+            .retries
+            .get(&nonce)
+            .map(|retry| retry.next_retry_at)
+    }
+
+    /// Failed attempts of `nonce` so far
+    pub fn attempts(&self, nonce: u64) -> u32 {
+        self.queued
+            .lock()
+            .unwrap()
+            .retries
+            .get(&nonce)
+            .map_or(0, |retry| retry.attempts)
+    }
+
+    /// Recorded failed attempts of `nonce`, oldest first
+    pub fn failures(&self, nonce: u64) -> Vec<AttemptRecord> {
+        self.queued
+            .lock()
+            .unwrap()
+            .retries
+            .get(&nonce)
+            .map(|retry| retry.failures.clone())
+            .unwrap_or_default()
+    }
+
+    /// Forgets the failed attempts of `nonce`, so it is tried at once
+    pub fn clear_retry(&self, store: &StateStore, nonce: u64) -> Result<()> {
+        let mut queued = self.queued.lock().unwrap();
+        if queued.retries.remove(&nonce).is_some() {
+            store.put(PENDING_QUEUE_KEY, &*queued)?;
+        }
+        Ok(())
+    }
+
+    /// Failed attempts of every pending nonce that failed at least once
+    pub fn retries(&self) -> Vec<(u64, NonceRetry)> {
+        self.queued
+            .lock()
+            .unwrap()
+            .retries
+            .iter()
+            .map(|(nonce, retry)| (*nonce, retry.clone()))
+            .collect()
+    }
+
+    /// Number of pending nonces that failed at least once
+    pub fn retry_count(&self) -> usize {
+        self.queued.lock().unwrap().retries.len()
+    }
+
+    /// Lowest nonce that is not known to be relayed: every nonce below it has
+    /// left the queue
+    pub fn completed(&self) -> u64 {
+        let overflow = self.overflow.lock().unwrap();
+        let queued = self.queued.lock().unwrap();
+        queued
+            .pending
+            .front()
+            .into_iter()
+            .chain(overflow.front().map(|range| &range.start))
+            .copied()
+            .fold(queued.next_nonce, u64::min)
+    }
+
+    /// Returns (queued in memory, spilled to disk)
+    pub fn depth(&self) -> (u64, u64) {
+        let overflow = self.overflow.lock().unwrap();
+        let queued = self.queued.lock().unwrap();
+        (
+            queued.pending.len() as u64,
+            overflow.iter().map(|range| range.end - range.start).sum(),
+        )
+    }
+}
@@ -0,0 +1,249 @@
+//! Quiet period for new recipients.
+//! With `new_recipient_quiet_period_secs` set, the first transfer seen to a
+//! recipient is held for that long before its L2 transaction is built, and so
+//! is every other transfer to the recipient until the period ends; later
+//! transfers to a known recipient relay immediately. First sightings and
+//! holds live in the state store, so a restart does not cut the period short.
+//! During the period an operator can `cancel` the transfer: the relayer then
+//! records a receipt without a transaction (`cancelled`) and raises an alert.
+//! Like an approval, waiting does not count as a failed attempt.
+
+use crate::{
+    alerts, journal::JournalEvent, limits, pipeline::FetchedTransfer, receipts::Receipt,
+    state::StateStore, Relayer,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+const HOLDS_PREFIX: &str = "quiet/holds/";
+
+/// When a recipient was first seen
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FirstSeen {
+    /// Nonce of the first transfer to the recipient
+    pub nonce: u64,
+    pub seen_at: u64,
+}
+
+/// A transfer held in its recipient's quiet period
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuietHold {
+    pub nonce: u64,
+    pub amount: u64,
+    /// Recipient (base58)
+    pub to: String,
+    pub held_at: u64,
+    /// When the quiet period ends and the transfer is relayed
+    pub release_at: u64,
+}
+
+/// An operator's request to cancel a held transfer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cancellation {
+    pub reason: String,
+    pub requested_at: u64,
+}
+
+/// A transfer held until its recipient's quiet period ends
+#[derive(Debug)]
+pub struct InQuietPeriod {
+    pub nonce: u64,
+    pub to: String,
+    pub release_at: u64,
+}
+
+impl fmt::Display for InQuietPeriod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Nonce {} to new recipient {} is held until {}",
+            self.nonce, self.to, self.release_at
+        )
+    }
+}
+
+impl std::error::Error for InQuietPeriod {}
+
+fn recipient_key(to: &str) -> String {
+    format!("quiet/recipients/{}", to)
+}
+
+fn hold_key(nonce: u64) -> String {
+    format!("{}{}", HOLDS_PREFIX, nonce)
+}
+
+fn cancellation_key(nonce: u64) -> String {
+    format!("quiet/cancellations/{}", nonce)
+}
+
+/// Transfers currently held in a quiet period, with their cancellation
+/// requests, by nonce
+pub fn holds(store: &StateStore) -> Result<Vec<(QuietHold, Option<Cancellation>)>> {
+    let mut holds = Vec::new();
+    for key in store.list(HOLDS_PREFIX)? {
+        let Some(hold) = store.get::<QuietHold>(&key)? else {
+            continue;
+        };
+        let cancellation = store.get(&cancellation_key(hold.nonce))?;
+        holds.push((hold, cancellation));
+    }
+    holds.sort_by_key(|(hold, _)| hold.nonce);
+    Ok(holds)
+}
+
+/// Asks running relayers to cancel the held transfer of `nonce`; fails if it
+/// is not held or its quiet period has ended
+pub fn cancel(store: &StateStore, nonce: u64, reason: &str, now: u64) -> Result<QuietHold> {
+    let hold = store
+        .get::<QuietHold>(&hold_key(nonce))?
+        .ok_or_else(|| anyhow::anyhow!("Nonce {} is not held in a quiet period", nonce))?;
+    if now >= hold.release_at {
+        return Err(anyhow::anyhow!(
+            "The quiet period of nonce {} ended at {}; it may already be relayed",
+            nonce,
+            hold.release_at
+        ));
+    }
+    store.put(
+        &cancellation_key(nonce),
+        &Cancellation {
+            reason: reason.to_string(),
+            requested_at: now,
+        },
+    )?;
+    Ok(hold)
+}
+
+impl Relayer {
+    /// Fails with `InQuietPeriod` while the recipient of `fetched` is in its
+    /// quiet period; records the recipient the first time it is seen
+    pub(crate) fn check_quiet_period(&self, fetched: &FetchedTransfer) -> Result<()> {
+        let Some(period) = self.quiet_period else {
+            return Ok(());
+        };
+
+        let now = self.clock.unix_timestamp();
+        let to = fetched.to_address.to_string();
+        let first = match self.state.get::<FirstSeen>(&recipient_key(&to))? {
+            Some(first) => first,
+            None => {
+                let first = FirstSeen {
+                    nonce: fetched.nonce,
+                    seen_at: now,
+                };
+                self.state.put(&recipient_key(&to), &first)?;
+                first
+            }
+        };
+        let release_at = first.seen_at + period.as_secs();
+        let key = hold_key(fetched.nonce);
+        if now >= release_at {
+            self.state.remove(&key)?;
+            return Ok(());
+        }
+
+        let held = self.state.get::<QuietHold>(&key)?;
+        if held.is_none_or(|held| held.to != to || held.amount != fetched.amount) {
+            tracing::info!(
+                "Holding nonce {} ({} lamports to new recipient {}) until {}; `cancel {} --reason <reason>` cancels it",
+                fetched.nonce, fetched.amount, to, release_at, fetched.nonce
+            );
+            self.state.put(
+                &key,
+                &QuietHold {
+                    nonce: fetched.nonce,
+                    amount: fetched.amount,
+                    to: to.clone(),
+                    held_at: now,
+                    release_at,
+                },
+            )?;
+        }
+        Err(InQuietPeriod {
+            nonce: fetched.nonce,
+            to,
+            release_at,
+        }
+        .into())
+    }
+
+    /// Whether `nonce` is held in a quiet period without a cancellation
+    /// request, so it is not worth fetching
+    pub(crate) fn in_quiet_period(&self, nonce: u64) -> Result<bool> {
+        if self.quiet_period.is_none() {
+            return Ok(false);
+        }
+        let Some(hold) = self.state.get::<QuietHold>(&hold_key(nonce))? else {
+            return Ok(false);
+        };
+        Ok(self.clock.unix_timestamp() < hold.release_at
+            && self
+                .state
+                .get::<Cancellation>(&cancellation_key(nonce))?
+                .is_none())
+    }
+
+    /// Records `fetched` as cancelled if an operator cancelled its hold;
+    /// returns whether it was cancelled, now or before
+    pub(crate) fn cancel_held_transfer(&self, fetched: &FetchedTransfer) -> Result<bool> {
+        if self.quiet_period.is_none() {
+            return Ok(false);
+        }
+        // 同批次后面的 nonce 被扣留时，已取消的 nonce 会被再次读取
+        if Receipt::load(&self.state, fetched.nonce)?
+            .is_some_and(|receipt| receipt.cancelled.is_some())
+        {
+            return Ok(true);
+        }
+        let Some(cancellation) = self
+            .state
+            .get::<Cancellation>(&cancellation_key(fetched.nonce))?
+        else {
+            return Ok(false);
+        };
+        let to = fetched.to_address.to_string();
+        if self
+            .state
+            .get::<QuietHold>(&hold_key(fetched.nonce))?
+            .is_none()
+        {
+            // 保留期已结束并放行，取消请求作废
+            self.state.remove(&cancellation_key(fetched.nonce))?;
+            return Ok(false);
+        }
+
+        alerts::raise(&format!(
+            "Source {} nonce {} ({} lamports to new recipient {}) cancelled during its quiet period: {}",
+            self.source_id, fetched.nonce, fetched.amount, to, cancellation.reason
+        ));
+        Receipt::cancelled(
+            fetched,
+            cancellation.reason.clone(),
+            self.clock.unix_timestamp(),
+        )
+        .save(&self.state)?;
+        self.schedule_shipping(fetched.nonce);
+        self.attempts.finish(fetched.nonce);
+        self.journal_message(
+            Some(fetched.l1_slot),
+            Some(limits::message_type_of(fetched.token)),
+            JournalEvent::Skipped {
+                nonce: fetched.nonce,
+                reason: format!("cancelled: {}", cancellation.reason),
+            },
+        );
+        self.metrics.cancelled_transfers_total.inc();
+        // 首笔转账被取消的收款人仍算新收款人，下一笔重新计算保留期
+        if self
+            .state
+            .get::<FirstSeen>(&recipient_key(&to))?
+            .is_some_and(|first| first.nonce == fetched.nonce)
+        {
+            self.state.remove(&recipient_key(&to))?;
+        }
+        self.state.remove(&hold_key(fetched.nonce))?;
+        self.state.remove(&cancellation_key(fetched.nonce))?;
+        Ok(true)
+    }
+}
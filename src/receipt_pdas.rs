@@ -0,0 +1,396 @@
+//! Closing of L2 receipt PDAs.
+//! The L2 program keeps a receipt PDA per relayed nonce (seeds `seed` and the
+//! nonce, u64 LE) and lets the relayer close it once it is no longer needed,
+//! refunding its rent. With a `[receipt_pdas]` section, `close-receipts`,
+//! and `run` every `close_interval_secs` when set, walk the nonces from where
+//! the last run stopped. A nonce is closed once it is below the replay window
+//! (`replay_window_nonces`, the completed prefix without it) and its receipt
+//! was relayed more than `retention_days` and any `replay_window_days` ago.
+//! The walk stops at the first receipt too recent, so no receipt inside the
+//! replay window is ever closed; a nonce whose receipt was pruned or archived
+//! is past both windows already.
+//!
+//! Receipt PDAs are checked in chunks with `getMultipleAccounts`. Those still
+//! owned by the L2 program are closed with `close_receipt`, `batch_size` per
+//! transaction, refunding their rent to `collector`. Every closed nonce is
+//! recorded in the state (`closed_receipts/<nonce>`, with the transaction and
+//! the refund), kept when its receipt is pruned: the L2 program no longer
+//! remembers the nonce, so the relayer treats it as relayed from then on.
+
+use crate::{
+    config::ReceiptPdasConfig, inflight::TransactionOutcome, receipts::Receipt, state::StateStore,
+    Relayer,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    transaction::{Transaction, VersionedTransaction},
+};
+use std::{str::FromStr, time::Duration};
+
+/// Anchor discriminator of `close_receipt`
+pub(crate) const CLOSE_RECEIPT_DISCRIMINATOR: [u8; 8] = [126, 254, 244, 203, 124, 164, 134, 89];
+/// State key of the nonce below which every receipt PDA was checked
+pub const RECEIPT_PDAS_CHECKED_BELOW_KEY: &str = "receipt_pdas_checked_below";
+/// Addresses read by one `getMultipleAccounts`
+const CHECK_CHUNK: usize = 100;
+/// Close instructions that still fit in a packet
+const MAX_BATCH_SIZE: usize = 20;
+
+pub struct ReceiptPdas {
+    collector: Pubkey,
+    seed: String,
+    retention: Duration,
+    batch_size: usize,
+    /// Pause between closing runs of `run`; zero for none
+    interval: Duration,
+}
+
+impl ReceiptPdas {
+    pub fn from_config(config: &ReceiptPdasConfig) -> Result<Self> {
+        let collector = Pubkey::from_str(&config.collector).map_err(|e| {
+            anyhow::anyhow!("Invalid receipt_pdas.collector {}: {}", config.collector, e)
+        })?;
+        if config.batch_size == 0 || config.batch_size > MAX_BATCH_SIZE {
+            return Err(anyhow::anyhow!(
+                "receipt_pdas.batch_size must be between 1 and {}, got {}",
+                MAX_BATCH_SIZE,
+                config.batch_size
+            ));
+        }
+        Ok(Self {
+            collector,
+            seed: config.seed.clone(),
+            retention: Duration::from_secs(config.retention_days * 86_400),
+            batch_size: config.batch_size,
+            interval: Duration::from_secs(config.close_interval_secs),
+        })
+    }
+
+    /// Receipt PDA of `nonce` under `program_id`
+    pub fn address(&self, program_id: &Pubkey, nonce: u64) -> Pubkey {
+        Pubkey::find_program_address(&[self.seed.as_bytes(), &nonce.to_le_bytes()], program_id).0
+    }
+
+    /// `close_receipt` of the PDA of `nonce`: receipt (writable), collector
+    /// (writable), relayer (signer); data is the discriminator and the nonce
+    fn close_instruction(
+        &self,
+        program_id: &Pubkey,
+        address: Pubkey,
+        nonce: u64,
+        authority: Pubkey,
+    ) -> Instruction {
+        let mut data = CLOSE_RECEIPT_DISCRIMINATOR.to_vec();
+        data.extend_from_slice(&nonce.to_le_bytes());
+        Instruction::new_with_bytes(
+            *program_id,
+            &data,
+            vec![
+                AccountMeta::new(address, false),
+                AccountMeta::new(self.collector, false),
+                AccountMeta::new(authority, true),
+            ],
+        )
+    }
+}
+
+/// A receipt PDA the relayer closed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClosedReceipt {
+    /// Closing L2 transaction
+    pub signature: String,
+    pub closed_at: u64,
+    /// Lamports refunded to the collector
+    pub refund: u64,
+}
+
+impl ClosedReceipt {
+    fn key(nonce: u64) -> String {
+        format!("closed_receipts/{}", nonce)
+    }
+
+    pub fn load(store: &StateStore, nonce: u64) -> Result<Option<Self>> {
+        store.get(&Self::key(nonce))
+    }
+}
+
+/// Outcome of a closing run
+#[derive(Debug, Default)]
+pub struct CloseSummary {
+    /// Nonces whose receipt PDA was checked
+    pub checked: u64,
+    /// Receipt PDAs closed, or closeable on a dry run
+    pub closed: usize,
+    /// Lamports refunded, or refundable on a dry run
+    pub refunded: u64,
+    /// Nonce below which every receipt PDA has been checked
+    pub checked_below: u64,
+}
+
+impl Relayer {
+    /// Closes the receipt PDAs outside the retention window; with `dry_run`
+    /// only counts them, sending and saving nothing
+    pub(crate) async fn close_receipt_pdas(&self, dry_run: bool) -> Result<CloseSummary> {
+        let Some(pdas) = &self.receipt_pdas else {
+            return Err(anyhow::anyhow!(
+                "Closing receipt PDAs requires a [receipt_pdas] section"
+            ));
+        };
+        let program_id = *self.transaction_builder.program_id.pubkey();
+        let (nonce_floor, oldest_kept) = self.replay_window_bounds();
+        let retained = self
+            .clock
+            .unix_timestamp()
+            .saturating_sub(pdas.retention.as_secs());
+        let cutoff = oldest_kept.map_or(retained, |oldest_kept| oldest_kept.min(retained));
+
+        let mut summary = CloseSummary {
+            checked_below: self
+                .state
+                .get(RECEIPT_PDAS_CHECKED_BELOW_KEY)?
+                .unwrap_or_default(),
+            ..CloseSummary::default()
+        };
+        let mut nonce = summary.checked_below;
+        while nonce < nonce_floor {
+            let mut chunk = Vec::with_capacity(CHECK_CHUNK);
+            while chunk.len() < CHECK_CHUNK && nonce < nonce_floor {
+                // 回执仍在保留期内时停止，之后的 nonce 更新
+                if Receipt::load(&self.state, nonce)?
+                    .is_some_and(|receipt| receipt.relayed_at >= cutoff)
+                {
+                    break;
+                }
+                chunk.push(nonce);
+                nonce += 1;
+            }
+            let Some(&last) = chunk.last() else {
+                break;
+            };
+            let addresses: Vec<Pubkey> = chunk
+                .iter()
+                .map(|nonce| pdas.address(&program_id, *nonce))
+                .collect();
+            let accounts = self.l2_client.get_multiple_accounts(&addresses).await?;
+            let open: Vec<(u64, Pubkey, u64)> = chunk
+                .iter()
+                .zip(addresses)
+                .zip(accounts)
+                .filter_map(|((nonce, address), account)| {
+                    let account = account.filter(|account| account.owner == program_id)?;
+                    Some((*nonce, address, account.lamports))
+                })
+                .collect();
+            summary.checked += chunk.len() as u64;
+            if dry_run {
+                summary.closed += open.len();
+                summary.refunded += open.iter().map(|(_, _, lamports)| lamports).sum::<u64>();
+            } else {
+                for batch in open.chunks(pdas.batch_size) {
+                    self.close_batch(pdas, &program_id, batch, &mut summary)
+                        .await?;
+                }
+                self.state.put(RECEIPT_PDAS_CHECKED_BELOW_KEY, &(last + 1))?;
+            }
+            summary.checked_below = last + 1;
+            if chunk.len() < CHECK_CHUNK {
+                break;
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Closes the receipt PDAs of `batch` (nonce, address, lamports) in one
+    /// transaction and records them
+    async fn close_batch(
+        &self,
+        pdas: &ReceiptPdas,
+        program_id: &Pubkey,
+        batch: &[(u64, Pubkey, u64)],
+        summary: &mut CloseSummary,
+    ) -> Result<()> {
+        let payer = self.signers.active();
+        let authority = payer.pubkey();
+        let instructions: Vec<Instruction> = batch
+            .iter()
+            .map(|(nonce, address, _)| {
+                pdas.close_instruction(program_id, *address, *nonce, authority)
+            })
+            .collect();
+        let (recent_blockhash, last_valid_block_height) = self
+            .l2_client
+            .get_latest_blockhash_with_commitment(self.l2_client.commitment())
+            .await?;
+        let transaction: VersionedTransaction = Transaction::new_signed_with_payer(
+            &instructions,
+            Some(&authority),
+            &[payer],
+            recent_blockhash,
+        )
+        .into();
+        let signature = transaction.signatures[0];
+        match self
+            .send_and_confirm(&transaction, last_valid_block_height)
+            .await?
+        {
+            TransactionOutcome::Confirmed { .. } => {
+                let closed_at = self.clock.unix_timestamp();
+                for (nonce, _, lamports) in batch {
+                    let closed = ClosedReceipt {
+                        signature: signature.to_string(),
+                        closed_at,
+                        refund: *lamports,
+                    };
+                    self.state.put(&ClosedReceipt::key(*nonce), &closed)?;
+                    summary.closed += 1;
+                    summary.refunded += lamports;
+                    self.metrics.receipt_pdas_closed_total.inc();
+                    self.metrics
+                        .receipt_pda_refunds_lamports_total
+                        .inc_by(*lamports);
+                }
+                tracing::info!(
+                    "Closed {} receipt PDA(s) of nonces {}..={} ({})",
+                    batch.len(),
+                    batch[0].0,
+                    batch[batch.len() - 1].0,
+                    signature
+                );
+                Ok(())
+            }
+            TransactionOutcome::Failed(err) => Err(anyhow::anyhow!(
+                "Receipt PDA close {} failed: {}",
+                signature,
+                err
+            )),
+            TransactionOutcome::Expired => Err(anyhow::anyhow!(
+                "Receipt PDA close {} expired before landing",
+                signature
+            )),
+        }
+    }
+
+    /// Closes receipt PDAs every `close_interval_secs`; pends forever
+    /// without a `[receipt_pdas]` section or an interval
+    pub(crate) async fn close_receipt_pdas_periodically(&self) -> Result<()> {
+        let Some(interval) = self
+            .receipt_pdas
+            .as_ref()
+            .map(|pdas| pdas.interval)
+            .filter(|interval| !interval.is_zero())
+        else {
+            return futures::future::pending().await;
+        };
+        loop {
+            self.clock.sleep(interval).await;
+            // standby 不发送交易
+            if self.dry_run || self.is_standby().unwrap_or(true) {
+                continue;
+            }
+            match self.close_receipt_pdas(false).await {
+                Ok(summary) if summary.closed > 0 => tracing::info!(
+                    "Closed {} receipt PDA(s), {} lamports refunded",
+                    summary.closed,
+                    summary.refunded
+                ),
+                Ok(_) => {}
+                Err(e) => tracing::warn!("failed to close receipt PDAs: {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::testing::{self, Harness};
+    use solana_sdk::signature::{Keypair, Signer};
+
+    const RENT: u64 = 1_113_600;
+
+    fn pdas_harness(replay_window_nonces: Option<u64>, retention_days: u64) -> (Harness, Pubkey) {
+        let collector = Pubkey::new_unique();
+        let mut config = testing::config();
+        config.replay_window_nonces = replay_window_nonces;
+        config.receipt_pdas = Some(ReceiptPdasConfig {
+            collector: collector.to_string(),
+            seed: "receipt".to_string(),
+            retention_days,
+            batch_size: 2,
+            close_interval_secs: 0,
+        });
+        (Harness::new(config).unwrap(), collector)
+    }
+
+    fn open_pdas(harness: &Harness, relayer: &Relayer, nonces: &[u64]) -> Vec<Pubkey> {
+        let pdas = relayer.receipt_pdas.as_ref().unwrap();
+        let program_id = *relayer.transaction_builder.program_id.pubkey();
+        nonces
+            .iter()
+            .map(|nonce| {
+                let address = pdas.address(&program_id, *nonce);
+                harness.l2.set_receipt_pda(address, &program_id, RENT);
+                address
+            })
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn receipt_pdas_past_the_retention_are_closed_and_stay_relayed() {
+        let (harness, collector) = pdas_harness(None, 1);
+        let to = Keypair::new().pubkey();
+        harness.add_transfers(0..3, 1_000_000_000, &to);
+        let relayer = harness.relayer().await.unwrap();
+        relayer.run_once().await.unwrap();
+        // nonce 1 has no receipt PDA left to close
+        let old = open_pdas(&harness, &relayer, &[0, 2]);
+
+        let summary = relayer.close_receipt_pdas(false).await.unwrap();
+        assert_eq!((summary.closed, summary.checked_below), (0, 0));
+
+        tokio::time::advance(Duration::from_secs(2 * 86_400)).await;
+        harness.add_transfers(3..5, 1_000_000_000, &to);
+        relayer.run_once().await.unwrap();
+        let recent = open_pdas(&harness, &relayer, &[3, 4]);
+
+        let dry = relayer.close_receipt_pdas(true).await.unwrap();
+        assert_eq!((dry.closed, dry.refunded), (2, 2 * RENT));
+        assert_eq!(harness.l2.balance(&collector), 0);
+
+        let summary = relayer.close_receipt_pdas(false).await.unwrap();
+        assert_eq!(
+            (summary.checked, summary.closed, summary.checked_below),
+            (3, 2, 3)
+        );
+        assert_eq!(harness.l2.balance(&collector), 2 * RENT);
+        assert_eq!(harness.l2.balance(&old[0]), 0);
+        assert_eq!(harness.l2.balance(&recent[0]), RENT);
+        assert_eq!(relayer.metrics.receipt_pdas_closed_total.get(), 2);
+        let closed = ClosedReceipt::load(&relayer.state, 2).unwrap().unwrap();
+        assert_eq!(closed.refund, RENT);
+
+        // 本地回执被清理后，关闭记录仍防止重复中继
+        Receipt::remove(&relayer.state, 0).unwrap();
+        assert!(relayer.already_relayed(0).unwrap());
+        let again = relayer.close_receipt_pdas(false).await.unwrap();
+        assert_eq!((again.checked, again.closed), (0, 0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn receipt_pdas_inside_the_replay_window_are_kept() {
+        let (harness, collector) = pdas_harness(Some(2), 0);
+        harness.add_transfers(0..4, 1_000_000_000, &Keypair::new().pubkey());
+        let relayer = harness.relayer().await.unwrap();
+        relayer.run_once().await.unwrap();
+        let addresses = open_pdas(&harness, &relayer, &[0, 1, 2, 3]);
+
+        let summary = relayer.close_receipt_pdas(false).await.unwrap();
+        assert_eq!((summary.closed, summary.checked_below), (2, 2));
+        assert_eq!(harness.l2.balance(&collector), 2 * RENT);
+        assert_eq!(harness.l2.balance(&addresses[2]), RENT);
+        assert_eq!(harness.l2.balance(&addresses[3]), RENT);
+    }
+}
@@ -0,0 +1,529 @@
+//! Receipt sink.
+//! With a `[receipt_sink]` section, every receipt is also shipped off the
+//! host once it is committed to `state_dir`: to an HTTP endpoint (`kind =
+//! "http"`, a `POST` of `{"source", "receipts"}` per batch) or to an
+//! S3-compatible bucket (`kind = "s3"`, one JSON-lines object per source and
+//! hour of `relayed_at`, `{prefix}{source}/YYYY/MM/DD/HH.jsonl`, merged with
+//! the object already stored and signed with AWS Signature V4).
+//!
+//! Saving a receipt only queues its nonce; a background loop ships the queue
+//! every `flush_interval_secs` in batches of `batch_size`, retrying a failed
+//! batch with a backoff of up to `max_backoff_secs`, so relaying never waits
+//! on the sink. At most `queue_capacity` receipts wait; when the sink falls
+//! further behind the oldest are dropped and shipped again by a catch-up once
+//! the queue drains. The highest nonce below which every receipt has shipped
+//! is kept in `state_dir` (`receipt_sink`); a run starts by shipping the
+//! receipts above it, and `sink backfill` does the same from the command
+//! line. A receipt may be shipped more than once (enrichment saves it again),
+//! so the HTTP endpoint should key receipts by source and nonce.
+
+use crate::{
+    clock::{Clock, SystemClock},
+    config::{ReceiptSinkConfig, SinkKind},
+    receipts::Receipt,
+    report::civil_from_days,
+    secrets::Redacted,
+    state::StateStore,
+    Relayer,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_sdk::hash::{hash, hashv};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::Mutex,
+    time::Duration,
+};
+
+/// State key holding the shipping watermark
+pub const RECEIPT_SINK_KEY: &str = "receipt_sink";
+
+/// Shipping progress of the receipt sink
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SinkWatermark {
+    /// Every receipt up to this nonce has shipped
+    pub shipped_up_to: Option<u64>,
+    pub shipped_at: u64,
+}
+
+enum Target {
+    Http {
+        url: String,
+        token: Option<String>,
+    },
+    S3 {
+        endpoint: String,
+        bucket: String,
+        prefix: String,
+        region: String,
+        access_key_id: String,
+        secret_access_key: String,
+    },
+}
+
+/// A saved receipt waiting to ship
+struct Queued {
+    /// Order of the save; a receipt saved again is queued again
+    seq: u64,
+    nonce: u64,
+    queued_at: u64,
+}
+
+/// Receipts saved but not shipped yet
+#[derive(Default)]
+struct SinkQueue {
+    pending: VecDeque<Queued>,
+    next_seq: u64,
+    /// Lowest nonce dropped from the full queue since the last catch-up
+    dropped: Option<u64>,
+}
+
+impl SinkQueue {
+    /// Forgets the receipts queued up to `seq`
+    fn shipped(&mut self, seq: u64) {
+        self.pending.retain(|queued| queued.seq > seq);
+    }
+}
+
+pub struct ReceiptSink {
+    target: Target,
+    source: String,
+    http: reqwest::Client,
+    queue: Mutex<SinkQueue>,
+    capacity: usize,
+    batch_size: usize,
+    interval: Duration,
+    max_backoff: Duration,
+}
+
+impl ReceiptSink {
+    pub fn new(config: &ReceiptSinkConfig, source: &str) -> Result<Self> {
+        let required = |value: Option<&String>, field: &str| {
+            value.cloned().ok_or_else(|| {
+                anyhow::anyhow!("receipt_sink.{} is required for kind = \"s3\"", field)
+            })
+        };
+        let target = match config.kind {
+            SinkKind::Http => Target::Http {
+                url: config.url.expose().to_string(),
+                token: config.token.as_ref().map(|token| token.expose().clone()),
+            },
+            SinkKind::S3 => Target::S3 {
+                endpoint: config.url.expose().trim_end_matches('/').to_string(),
+                bucket: required(config.bucket.as_ref(), "bucket")?,
+                prefix: config.prefix.clone(),
+                region: config.region.clone(),
+                access_key_id: required(
+                    config.access_key_id.as_ref().map(Redacted::expose),
+                    "access_key_id",
+                )?,
+                secret_access_key: required(
+                    config.secret_access_key.as_ref().map(Redacted::expose),
+                    "secret_access_key",
+                )?,
+            },
+        };
+        Ok(Self {
+            target,
+            source: source.to_string(),
+            http: reqwest::Client::new(),
+            queue: Mutex::new(SinkQueue::default()),
+            capacity: config.queue_capacity.max(1),
+            batch_size: config.batch_size.max(1),
+            interval: Duration::from_secs(config.flush_interval_secs.max(1)),
+            max_backoff: Duration::from_secs(config.max_backoff_secs.max(1)),
+        })
+    }
+
+    /// Queues the receipt of `nonce`; returns the nonce dropped from the
+    /// full queue, if any
+    fn push(&self, nonce: u64, now: u64) -> Option<u64> {
+        let mut queue = self.queue.lock().unwrap();
+        let dropped = (queue.pending.len() >= self.capacity)
+            .then(|| queue.pending.pop_front())
+            .flatten()
+            .map(|queued| queued.nonce);
+        if let Some(nonce) = dropped {
+            queue.dropped = Some(queue.dropped.map_or(nonce, |lowest| lowest.min(nonce)));
+        }
+        let seq = queue.next_seq;
+        queue.next_seq += 1;
+        queue.pending.push_back(Queued {
+            seq,
+            nonce,
+            queued_at: now,
+        });
+        dropped
+    }
+
+    /// Receipts waiting and the age of the oldest, in seconds
+    fn lag(&self, now: u64) -> (usize, u64) {
+        let queue = self.queue.lock().unwrap();
+        let oldest = queue.pending.front().map_or(now, |queued| queued.queued_at);
+        (queue.pending.len(), now.saturating_sub(oldest))
+    }
+
+    /// Ships `receipts`; an error leaves them for a retry
+    pub async fn ship(&self, receipts: &[Receipt]) -> Result<()> {
+        if receipts.is_empty() {
+            return Ok(());
+        }
+        match &self.target {
+            Target::Http { url, token } => {
+                let body = serde_json::json!({ "source": self.source, "receipts": receipts });
+                let mut request = self.http.post(url).json(&body);
+                if let Some(token) = token {
+                    request = request.bearer_auth(token);
+                }
+                request
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map_err(|e| {
+                        anyhow::anyhow!("Receipt sink request failed: {}", e.without_url())
+                    })?;
+                Ok(())
+            }
+            Target::S3 { prefix, .. } => {
+                let mut hours: BTreeMap<u64, Vec<&Receipt>> = BTreeMap::new();
+                for receipt in receipts {
+                    hours
+                        .entry(receipt.relayed_at / 3_600)
+                        .or_default()
+                        .push(receipt);
+                }
+                for (hour, receipts) in hours {
+                    let (year, month, day) = civil_from_days((hour / 24) as i64);
+                    let key = format!(
+                        "{}{}/{:04}/{:02}/{:02}/{:02}.jsonl",
+                        prefix,
+                        self.source,
+                        year,
+                        month,
+                        day,
+                        hour % 24
+                    );
+                    self.merge_object(&key, &receipts).await?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Adds `receipts` to the hourly object `key`, replacing earlier copies
+    /// of the same nonces
+    async fn merge_object(&self, key: &str, receipts: &[&Receipt]) -> Result<()> {
+        let mut lines = BTreeMap::new();
+        if let Some(existing) = self.s3_request("GET", key, Vec::new()).await? {
+            for line in String::from_utf8_lossy(&existing).lines() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let nonce = serde_json::from_str::<serde_json::Value>(line)
+                    .ok()
+                    .and_then(|value| value["nonce"].as_u64())
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("Receipt sink object {} has an unreadable line", key)
+                    })?;
+                lines.insert(nonce, line.to_string());
+            }
+        }
+        for receipt in receipts {
+            lines.insert(receipt.nonce, serde_json::to_string(receipt)?);
+        }
+        let mut body = lines.into_values().collect::<Vec<_>>().join("\n");
+        body.push('\n');
+        self.s3_request("PUT", key, body.into_bytes()).await?;
+        Ok(())
+    }
+
+    /// Signed S3 request; the body of a GET, none if the object is missing
+    async fn s3_request(&self, method: &str, key: &str, body: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        let Target::S3 {
+            endpoint,
+            bucket,
+            region,
+            access_key_id,
+            secret_access_key,
+            ..
+        } = &self.target
+        else {
+            unreachable!("S3 request to an HTTP sink");
+        };
+        let url = reqwest::Url::parse(&format!("{}/{}/{}", endpoint, bucket, uri_encode(key)))?;
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+        // 签名必须使用真实时间
+        let now = SystemClock.unix_timestamp();
+        let (year, month, day) = civil_from_days((now / 86_400) as i64);
+        let date = format!("{:04}{:02}{:02}", year, month, day);
+        let seconds = now % 86_400;
+        let timestamp = format!(
+            "{}T{:02}{:02}{:02}Z",
+            date,
+            seconds / 3_600,
+            seconds % 3_600 / 60,
+            seconds % 60
+        );
+        let payload_hash = crate::decode::hex(hash(&body).as_ref());
+        let canonical = format!(
+            "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\nhost;x-amz-content-sha256;x-amz-date\n{}",
+            method,
+            url.path(),
+            host,
+            payload_hash,
+            timestamp,
+            payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            timestamp,
+            scope,
+            crate::decode::hex(hash(canonical.as_bytes()).as_ref())
+        );
+        let signing_key = [region.as_str(), "s3", "aws4_request"].iter().fold(
+            hmac_sha256(
+                format!("AWS4{}", secret_access_key).as_bytes(),
+                date.as_bytes(),
+            ),
+            |key, part| hmac_sha256(&key, part.as_bytes()),
+        );
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature={}",
+            access_key_id,
+            scope,
+            crate::decode::hex(&hmac_sha256(&signing_key, string_to_sign.as_bytes()))
+        );
+
+        let method = reqwest::Method::from_bytes(method.as_bytes())?;
+        let response = self
+            .http
+            .request(method.clone(), url)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", timestamp)
+            .header("authorization", authorization)
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| {
+                anyhow::anyhow!(
+                    "Receipt sink {} {} failed: {}",
+                    method,
+                    key,
+                    e.without_url()
+                )
+            })?;
+        if method == reqwest::Method::GET && response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let status = response.status();
+        if !status.is_success() {
+            return Err(anyhow::anyhow!(
+                "Receipt sink {} {} returned {}",
+                method,
+                key,
+                status
+            ));
+        }
+        Ok(Some(response.bytes().await?.to_vec()))
+    }
+}
+
+/// Percent-encoding of an object key, keeping `/` between segments
+fn uri_encode(key: &str) -> String {
+    key.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// HMAC-SHA256 (RFC 2104) over the SHA-256 of `solana_sdk`
+fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    if key.len() > block.len() {
+        block[..32].copy_from_slice(hash(key).as_ref());
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let inner_pad: Vec<u8> = block.iter().map(|byte| byte ^ 0x36).collect();
+    let outer_pad: Vec<u8> = block.iter().map(|byte| byte ^ 0x5c).collect();
+    let inner = hashv(&[&inner_pad, data]);
+    hashv(&[&outer_pad, inner.as_ref()]).to_bytes()
+}
+
+/// Ships every stored receipt above the watermark, or from nonce `from` on,
+/// advancing the watermark batch by batch; returns how many shipped
+pub async fn backfill(
+    sink: &ReceiptSink,
+    store: &StateStore,
+    from: Option<u64>,
+    now: u64,
+) -> Result<usize> {
+    let mut watermark: SinkWatermark = store.get(RECEIPT_SINK_KEY)?.unwrap_or_default();
+    let nonces: Vec<u64> = Receipt::stored_nonces(store)?
+        .into_iter()
+        .filter(|&nonce| match (from, watermark.shipped_up_to) {
+            (Some(from), _) => nonce >= from,
+            (None, Some(shipped)) => nonce > shipped,
+            (None, None) => true,
+        })
+        .collect();
+    let mut shipped = 0;
+    for chunk in nonces.chunks(sink.batch_size) {
+        let receipts = load_receipts(store, chunk)?;
+        sink.ship(&receipts).await?;
+        shipped += receipts.len();
+        let last = *chunk.last().expect("chunks are not empty");
+        watermark.shipped_up_to = Some(
+            watermark
+                .shipped_up_to
+                .map_or(last, |up_to| up_to.max(last)),
+        );
+        watermark.shipped_at = now;
+        store.put(RECEIPT_SINK_KEY, &watermark)?;
+    }
+    Ok(shipped)
+}
+
+/// Receipts of `nonces`, once per transaction; pruned ones are skipped
+fn load_receipts(store: &StateStore, nonces: &[u64]) -> Result<Vec<Receipt>> {
+    let mut receipts = BTreeMap::new();
+    for &nonce in nonces {
+        if let Some(receipt) = Receipt::load(store, nonce)? {
+            receipts.insert(receipt.nonce, receipt);
+        }
+    }
+    Ok(receipts.into_values().collect())
+}
+
+impl Relayer {
+    /// Queues the receipt of `nonce` for the sink, if one is configured
+    pub(crate) fn schedule_shipping(&self, nonce: u64) {
+        let Some(sink) = &self.receipt_sink else {
+            return;
+        };
+        if let Some(dropped) = sink.push(nonce, self.clock.unix_timestamp()) {
+            self.metrics.receipt_sink_dropped_total.inc();
+            tracing::warn!(
+                "receipt sink queue full, the receipt of nonce {} ships with the next catch-up",
+                dropped
+            );
+        }
+    }
+
+    /// Ships queued receipts to the sink; never returns
+    pub(crate) async fn ship_receipts(&self) -> Result<()> {
+        let Some(sink) = &self.receipt_sink else {
+            return futures::future::pending().await;
+        };
+        let mut catch_up = true;
+        let mut failures = 0u32;
+        loop {
+            self.clock.sleep(sink.interval).await;
+            let (queued, lag) = sink.lag(self.clock.unix_timestamp());
+            self.metrics.receipt_sink_queued.set(queued as u64);
+            self.metrics.receipt_sink_lag_seconds.set(lag);
+            // standby 的回执由 active 实例发送
+            if self.is_standby().unwrap_or(true) {
+                continue;
+            }
+            let result = if catch_up {
+                self.catch_up_sink(sink).await.map(|_| catch_up = false)
+            } else {
+                self.ship_queued(sink).await
+            };
+            match result {
+                Ok(()) => failures = 0,
+                Err(e) => {
+                    failures += 1;
+                    self.metrics.receipt_sink_failures_total.inc();
+                    let backoff = sink
+                        .interval
+                        .saturating_mul(2u32.saturating_pow(failures.min(16)))
+                        .min(sink.max_backoff);
+                    tracing::warn!(
+                        "receipt sink failed ({} in a row), retrying in {:?}: {}",
+                        failures,
+                        backoff,
+                        e
+                    );
+                    self.clock.sleep(backoff).await;
+                }
+            }
+            catch_up |= sink.queue.lock().unwrap().dropped.is_some();
+        }
+    }
+
+    /// Ships the receipts above the watermark, then clears the queue and the
+    /// dropped mark they cover
+    async fn catch_up_sink(&self, sink: &ReceiptSink) -> Result<()> {
+        let covered = {
+            let mut queue = sink.queue.lock().unwrap();
+            queue.dropped = None;
+            queue.next_seq.checked_sub(1)
+        };
+        let shipped = backfill(sink, &self.state, None, self.clock.unix_timestamp()).await?;
+        if shipped > 0 {
+            tracing::info!("Receipt sink caught up: shipped {} receipt(s)", shipped);
+        }
+        // 追赶已经覆盖了此前排队的回执
+        if let Some(seq) = covered {
+            sink.queue.lock().unwrap().shipped(seq);
+        }
+        Ok(())
+    }
+
+    /// Ships the queue in batches, advancing the watermark up to the lowest
+    /// receipt still waiting
+    async fn ship_queued(&self, sink: &ReceiptSink) -> Result<()> {
+        loop {
+            let (batch, last_seq): (Vec<u64>, u64) = {
+                let queue = sink.queue.lock().unwrap();
+                let batch: Vec<&Queued> = queue.pending.iter().take(sink.batch_size).collect();
+                let Some(last) = batch.last() else {
+                    return Ok(());
+                };
+                (batch.iter().map(|queued| queued.nonce).collect(), last.seq)
+            };
+            let receipts = load_receipts(&self.state, &batch)?;
+            sink.ship(&receipts).await?;
+            self.metrics
+                .receipt_sink_shipped_total
+                .inc_by(receipts.len() as u64);
+
+            let (waiting, dropped) = {
+                let mut queue = sink.queue.lock().unwrap();
+                queue.shipped(last_seq);
+                (
+                    queue.pending.iter().map(|queued| queued.nonce).min(),
+                    queue.dropped,
+                )
+            };
+            let highest = batch.iter().copied().max().expect("batch is not empty");
+            let ceiling = [waiting, dropped]
+                .into_iter()
+                .flatten()
+                .min()
+                .map_or(Some(highest), |lowest| lowest.checked_sub(1));
+            let mut watermark: SinkWatermark =
+                self.state.get(RECEIPT_SINK_KEY)?.unwrap_or_default();
+            if let Some(up_to) = ceiling.map(|ceiling| ceiling.min(highest)) {
+                if watermark
+                    .shipped_up_to
+                    .is_none_or(|shipped| up_to > shipped)
+                {
+                    watermark.shipped_up_to = Some(up_to);
+                }
+            }
+            watermark.shipped_at = self.clock.unix_timestamp();
+            self.state.put(RECEIPT_SINK_KEY, &watermark)?;
+        }
+    }
+}
@@ -0,0 +1,651 @@
+//! Relay receipts.
+//! One receipt per relayed nonce ties the L2 transaction back to the L1 slot
+//! at which the message was read, for dispute resolution.
+
+use crate::{
+    approval::Approval, attempts::AttemptRecord, confirmation_strategy::ConfirmationStrategy,
+    dlq::ResolutionKind, dust::DustPolicy, entries::EntryReceipt, inflight::InFlightTransfer,
+    models::message::MessageType, pipeline::FetchedTransfer, proof::RelayProof, state::StateStore,
+    PreparedTransfer,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Receipt {
+    pub nonce: u64,
+    /// L1 slot at which the message was read
+    pub l1_slot: u64,
+    pub amount: u64,
+    /// Recipient (base58)
+    pub to: String,
+    /// Confirmed L2 transaction; empty for a skipped message
+    pub signature: String,
+    pub relayed_at: u64,
+    /// Estimated L2 network fee of the transaction
+    #[serde(default)]
+    pub l2_fee: u64,
+    /// Estimated lamports the transaction takes out of the relayer wallet
+    #[serde(default)]
+    pub estimated_cost: u64,
+    /// Fee charged on L2, from the confirmed transaction's metadata
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actual_fee: Option<u64>,
+    /// Change of the relayer wallet balance caused by the transaction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actual_cost: Option<u64>,
+    /// Compute units the transaction consumed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compute_units: Option<u64>,
+    /// L2 slot the transaction landed in; set once the receipt is enriched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub l2_slot: Option<u64>,
+    /// Block time of `l2_slot`, when the cluster reports one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_time: Option<i64>,
+    /// Recipient balance before the transaction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recipient_pre_balance: Option<u64>,
+    /// Recipient balance after the transaction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recipient_post_balance: Option<u64>,
+    /// Times the transaction was re-sent before it landed
+    #[serde(default)]
+    pub rebroadcasts: u32,
+    /// Signer that paid for the transaction, empty in receipts of older versions
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub signer: String,
+    /// Bridge domain the transaction was built for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain: Option<u64>,
+    /// Nonces merged into the transaction, empty unless coalesced
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub coalesced: Vec<u64>,
+    /// Nonces relayed by the same transaction, each with its own receipt;
+    /// empty unless packed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub packed: Vec<u64>,
+    /// Raw type id of a message skipped as unknown instead of being relayed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skipped_message_type: Option<u8>,
+    /// L1 mint of a token or NFT message skipped since its mapping is disabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skipped_mint: Option<String>,
+    /// Approvals the transfer was relayed with, above `manual_approval_threshold`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub approvals: Vec<Approval>,
+    /// Relayer's signed statement that the nonce was relayed in `signature`;
+    /// absent from skipped and imported receipts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proof: Option<RelayProof>,
+    /// How an operator closed the dead-lettered nonce instead of relaying it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolved_as: Option<ResolutionKind>,
+    /// Failed attempts before the transfer landed, most recent last
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failures: Vec<AttemptRecord>,
+    /// Deadline of a transfer skipped because it had passed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expired_at: Option<u64>,
+    /// Reason an operator gave for cancelling the transfer in its quiet period
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cancelled: Option<String>,
+    /// L1 slot at which the cancellation registry listed the nonce, so it was
+    /// not relayed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cancelled_on_l1: Option<u64>,
+    /// L1 slot at which the registry listed a nonce of the transaction after
+    /// it was sent; the transfer landed anyway and needs reconciling
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cancelled_in_flight: Option<u64>,
+    /// L2 slot at which the program's processed-nonce accounts already
+    /// marked the nonce, so it was not sent again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub processed_on_l2: Option<u64>,
+    /// SHA-256 of the message's canonical encoding (`Info::digest`, base58);
+    /// absent when coalesced or not read from L1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+    /// L1 address that sent the transfer (base58), as passed to L2; absent
+    /// when coalesced from several senders, not carried by the message
+    /// source or with `relay_sender = false`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sender: Option<String>,
+    /// L2 mint paid out (base58); absent for native transfers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mint: Option<String>,
+    /// Token account paid (base58), when the recipient named one instead of
+    /// a wallet
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_account: Option<String>,
+    /// Strategy the transaction was confirmed under; none without
+    /// `[[confirmation_rules]]`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirmation: Option<ConfirmationStrategy>,
+    /// Recipient's finalized balance, read before a `safe` receipt is written
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finalized_recipient_balance: Option<u64>,
+    /// Rent the signer paid for the recipient token account the relay
+    /// transaction created
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_account_rent: Option<u64>,
+    /// Message type whose `[limits]` override applied to the relay
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limits: Option<MessageType>,
+    /// Dust policy a transfer below the dust threshold was recorded under
+    /// instead of being relayed: `skip` or `accumulate`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dust: Option<DustPolicy>,
+    /// Nonces of accumulated dust paid out with the transfer, its amount
+    /// included
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dust_nonces: Vec<u64>,
+    /// Receipts of the entries of a multi-entry PDA, by index; the nonce's
+    /// receipt is written once every entry has one, with their total amount
+    /// and the first entry's recipient
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub entries: Vec<EntryReceipt>,
+}
+
+impl Receipt {
+    /// Receipt of the confirmed in-flight transaction `transfer`
+    pub fn confirmed(transfer: &InFlightTransfer, relayed_at: u64) -> Self {
+        Self {
+            nonce: transfer.nonce,
+            l1_slot: transfer.l1_slot,
+            amount: transfer.amount,
+            to: transfer.to.clone(),
+            signature: transfer.signature.clone(),
+            relayed_at,
+            l2_fee: transfer.fee,
+            estimated_cost: transfer.estimated_cost,
+            actual_fee: None,
+            actual_cost: None,
+            compute_units: None,
+            l2_slot: None,
+            block_time: None,
+            recipient_pre_balance: None,
+            recipient_post_balance: None,
+            rebroadcasts: 0,
+            signer: transfer.signer.clone(),
+            domain: None,
+            coalesced: transfer.coalesced.clone(),
+            packed: transfer.packed.clone(),
+            skipped_message_type: None,
+            skipped_mint: None,
+            approvals: Vec::new(),
+            proof: None,
+            resolved_as: None,
+            failures: Vec::new(),
+            expired_at: None,
+            cancelled: None,
+            cancelled_on_l1: None,
+            cancelled_in_flight: None,
+            processed_on_l2: None,
+            digest: transfer.digest.clone(),
+            sender: transfer.sender.clone(),
+            mint: transfer.mint.clone(),
+            token_account: transfer.token_account.clone(),
+            confirmation: transfer.strategy,
+            finalized_recipient_balance: None,
+            token_account_rent: (transfer.token_account_rent > 0)
+                .then_some(transfer.token_account_rent),
+            limits: transfer.limits,
+            dust: None,
+            dust_nonces: transfer.dust.clone(),
+            entries: Vec::new(),
+        }
+    }
+
+    /// Receipt of a message of unknown type `type_id` that was not relayed
+    pub fn skipped(transfer: &FetchedTransfer, type_id: u8, recorded_at: u64) -> Self {
+        Self {
+            nonce: transfer.nonce,
+            l1_slot: transfer.l1_slot,
+            amount: transfer.amount,
+            to: transfer.to_address.to_string(),
+            signature: String::new(),
+            relayed_at: recorded_at,
+            l2_fee: 0,
+            estimated_cost: 0,
+            actual_fee: None,
+            actual_cost: None,
+            compute_units: None,
+            l2_slot: None,
+            block_time: None,
+            recipient_pre_balance: None,
+            recipient_post_balance: None,
+            rebroadcasts: 0,
+            signer: String::new(),
+            domain: None,
+            coalesced: Vec::new(),
+            packed: Vec::new(),
+            skipped_message_type: Some(type_id),
+            skipped_mint: None,
+            approvals: Vec::new(),
+            proof: None,
+            resolved_as: None,
+            failures: Vec::new(),
+            expired_at: None,
+            cancelled: None,
+            cancelled_on_l1: None,
+            cancelled_in_flight: None,
+            processed_on_l2: None,
+            digest: transfer.digest.map(|digest| digest.to_string()),
+            sender: transfer.sender.map(|sender| sender.to_string()),
+            mint: transfer.token.map(|token| token.l2_mint.to_string()),
+            token_account: None,
+            confirmation: None,
+            finalized_recipient_balance: None,
+            token_account_rent: None,
+            limits: None,
+            dust: None,
+            dust_nonces: Vec::new(),
+            entries: Vec::new(),
+        }
+    }
+
+    /// Receipt of a token or NFT message of L1 mint `mint`, whose mapping is
+    /// disabled, that was not relayed
+    pub fn skipped_mint(transfer: &FetchedTransfer, mint: &Pubkey, recorded_at: u64) -> Self {
+        Self {
+            skipped_message_type: None,
+            skipped_mint: Some(mint.to_string()),
+            ..Self::skipped(transfer, 0, recorded_at)
+        }
+    }
+
+    /// Receipt of a dust transfer recorded under `policy` instead of being
+    /// relayed
+    pub fn dust(transfer: &FetchedTransfer, policy: DustPolicy, recorded_at: u64) -> Self {
+        Self {
+            skipped_message_type: None,
+            dust: Some(policy),
+            ..Self::skipped(transfer, 0, recorded_at)
+        }
+    }
+
+    /// Receipt of a transfer not relayed because its deadline `expires_at`
+    /// had passed
+    pub(crate) fn expired(transfer: &PreparedTransfer, expires_at: u64, recorded_at: u64) -> Self {
+        Self {
+            nonce: transfer.nonce,
+            l1_slot: transfer.l1_slot,
+            amount: transfer.amount,
+            to: transfer.to_address.to_string(),
+            signature: String::new(),
+            relayed_at: recorded_at,
+            l2_fee: 0,
+            estimated_cost: 0,
+            actual_fee: None,
+            actual_cost: None,
+            compute_units: None,
+            l2_slot: None,
+            block_time: None,
+            recipient_pre_balance: None,
+            recipient_post_balance: None,
+            rebroadcasts: 0,
+            signer: String::new(),
+            domain: None,
+            coalesced: Vec::new(),
+            packed: Vec::new(),
+            skipped_message_type: None,
+            skipped_mint: None,
+            approvals: Vec::new(),
+            proof: None,
+            resolved_as: None,
+            failures: Vec::new(),
+            expired_at: Some(expires_at),
+            cancelled: None,
+            cancelled_on_l1: None,
+            cancelled_in_flight: None,
+            processed_on_l2: None,
+            digest: transfer.digest.map(|digest| digest.to_string()),
+            sender: transfer.sender.map(|sender| sender.to_string()),
+            mint: transfer.token.map(|token| token.l2_mint.to_string()),
+            token_account: None,
+            confirmation: None,
+            finalized_recipient_balance: None,
+            token_account_rent: None,
+            limits: None,
+            dust: None,
+            dust_nonces: Vec::new(),
+            entries: Vec::new(),
+        }
+    }
+
+    /// Receipt of a transfer not sent because the L2 program's processed-nonce
+    /// accounts marked it as processed when read at `l2_slot`
+    pub(crate) fn processed_on_l2(
+        transfer: &PreparedTransfer,
+        l2_slot: u64,
+        recorded_at: u64,
+    ) -> Self {
+        Self {
+            nonce: transfer.nonce,
+            l1_slot: transfer.l1_slot,
+            amount: transfer.amount,
+            to: transfer.to_address.to_string(),
+            signature: String::new(),
+            relayed_at: recorded_at,
+            l2_fee: 0,
+            estimated_cost: 0,
+            actual_fee: None,
+            actual_cost: None,
+            compute_units: None,
+            l2_slot: None,
+            block_time: None,
+            recipient_pre_balance: None,
+            recipient_post_balance: None,
+            rebroadcasts: 0,
+            signer: String::new(),
+            domain: None,
+            coalesced: Vec::new(),
+            packed: Vec::new(),
+            skipped_message_type: None,
+            skipped_mint: None,
+            approvals: Vec::new(),
+            proof: None,
+            resolved_as: None,
+            failures: Vec::new(),
+            expired_at: None,
+            cancelled: None,
+            cancelled_on_l1: None,
+            cancelled_in_flight: None,
+            processed_on_l2: Some(l2_slot),
+            digest: transfer.digest.map(|digest| digest.to_string()),
+            sender: transfer.sender.map(|sender| sender.to_string()),
+            mint: transfer.token.map(|token| token.l2_mint.to_string()),
+            token_account: None,
+            confirmation: None,
+            finalized_recipient_balance: None,
+            token_account_rent: None,
+            limits: None,
+            dust: None,
+            dust_nonces: Vec::new(),
+            entries: Vec::new(),
+        }
+    }
+
+    /// Receipt of a transfer an operator cancelled during its recipient's
+    /// quiet period, with the given `reason`
+    pub fn cancelled(transfer: &FetchedTransfer, reason: String, recorded_at: u64) -> Self {
+        Self {
+            nonce: transfer.nonce,
+            l1_slot: transfer.l1_slot,
+            amount: transfer.amount,
+            to: transfer.to_address.to_string(),
+            signature: String::new(),
+            relayed_at: recorded_at,
+            l2_fee: 0,
+            estimated_cost: 0,
+            actual_fee: None,
+            actual_cost: None,
+            compute_units: None,
+            l2_slot: None,
+            block_time: None,
+            recipient_pre_balance: None,
+            recipient_post_balance: None,
+            rebroadcasts: 0,
+            signer: String::new(),
+            domain: None,
+            coalesced: Vec::new(),
+            packed: Vec::new(),
+            skipped_message_type: None,
+            skipped_mint: None,
+            approvals: Vec::new(),
+            proof: None,
+            resolved_as: None,
+            failures: Vec::new(),
+            expired_at: None,
+            cancelled: Some(reason),
+            cancelled_on_l1: None,
+            cancelled_in_flight: None,
+            processed_on_l2: None,
+            digest: transfer.digest.map(|digest| digest.to_string()),
+            sender: transfer.sender.map(|sender| sender.to_string()),
+            mint: transfer.token.map(|token| token.l2_mint.to_string()),
+            token_account: None,
+            confirmation: None,
+            finalized_recipient_balance: None,
+            token_account_rent: None,
+            limits: None,
+            dust: None,
+            dust_nonces: Vec::new(),
+            entries: Vec::new(),
+        }
+    }
+
+    /// Receipt of a transfer not relayed because the L1 cancellation registry
+    /// listed it when read at `registry_slot`
+    pub fn cancelled_on_l1(
+        nonce: u64,
+        l1_slot: u64,
+        amount: u64,
+        to: String,
+        registry_slot: u64,
+        recorded_at: u64,
+    ) -> Self {
+        Self {
+            nonce,
+            l1_slot,
+            amount,
+            to,
+            signature: String::new(),
+            relayed_at: recorded_at,
+            l2_fee: 0,
+            estimated_cost: 0,
+            actual_fee: None,
+            actual_cost: None,
+            compute_units: None,
+            l2_slot: None,
+            block_time: None,
+            recipient_pre_balance: None,
+            recipient_post_balance: None,
+            rebroadcasts: 0,
+            signer: String::new(),
+            domain: None,
+            coalesced: Vec::new(),
+            packed: Vec::new(),
+            skipped_message_type: None,
+            skipped_mint: None,
+            approvals: Vec::new(),
+            proof: None,
+            resolved_as: None,
+            failures: Vec::new(),
+            expired_at: None,
+            cancelled: None,
+            cancelled_on_l1: Some(registry_slot),
+            cancelled_in_flight: None,
+            processed_on_l2: None,
+            digest: None,
+            sender: None,
+            mint: None,
+            token_account: None,
+            confirmation: None,
+            finalized_recipient_balance: None,
+            token_account_rent: None,
+            limits: None,
+            dust: None,
+            dust_nonces: Vec::new(),
+            entries: Vec::new(),
+        }
+    }
+
+    /// Receipt of a dead-lettered nonce an operator closed as `kind` without
+    /// relaying it; the transfer was not read, so amount and recipient are empty
+    pub fn resolved(nonce: u64, kind: ResolutionKind, resolved_at: u64) -> Self {
+        Self {
+            nonce,
+            l1_slot: 0,
+            amount: 0,
+            to: String::new(),
+            signature: String::new(),
+            relayed_at: resolved_at,
+            l2_fee: 0,
+            estimated_cost: 0,
+            actual_fee: None,
+            actual_cost: None,
+            compute_units: None,
+            l2_slot: None,
+            block_time: None,
+            recipient_pre_balance: None,
+            recipient_post_balance: None,
+            rebroadcasts: 0,
+            signer: String::new(),
+            domain: None,
+            coalesced: Vec::new(),
+            packed: Vec::new(),
+            skipped_message_type: None,
+            skipped_mint: None,
+            approvals: Vec::new(),
+            proof: None,
+            resolved_as: Some(kind),
+            failures: Vec::new(),
+            expired_at: None,
+            cancelled: None,
+            cancelled_on_l1: None,
+            cancelled_in_flight: None,
+            processed_on_l2: None,
+            digest: None,
+            sender: None,
+            mint: None,
+            token_account: None,
+            confirmation: None,
+            finalized_recipient_balance: None,
+            token_account_rent: None,
+            limits: None,
+            dust: None,
+            dust_nonces: Vec::new(),
+            entries: Vec::new(),
+        }
+    }
+
+    /// Receipt of a relay found in L2 transaction history by `import-history`;
+    /// the memo's L1 slot, domain, merged nonces and digest are filled in by
+    /// the caller
+    pub fn imported(
+        nonce: u64,
+        amount: u64,
+        to: String,
+        signature: String,
+        signer: String,
+        relayed_at: u64,
+    ) -> Self {
+        Self {
+            nonce,
+            l1_slot: 0,
+            amount,
+            to,
+            signature,
+            relayed_at,
+            l2_fee: 0,
+            estimated_cost: 0,
+            actual_fee: None,
+            actual_cost: None,
+            compute_units: None,
+            l2_slot: None,
+            block_time: None,
+            recipient_pre_balance: None,
+            recipient_post_balance: None,
+            rebroadcasts: 0,
+            signer,
+            domain: None,
+            coalesced: Vec::new(),
+            packed: Vec::new(),
+            skipped_message_type: None,
+            skipped_mint: None,
+            approvals: Vec::new(),
+            proof: None,
+            resolved_as: None,
+            failures: Vec::new(),
+            expired_at: None,
+            cancelled: None,
+            cancelled_on_l1: None,
+            cancelled_in_flight: None,
+            processed_on_l2: None,
+            digest: None,
+            sender: None,
+            mint: None,
+            token_account: None,
+            confirmation: None,
+            finalized_recipient_balance: None,
+            token_account_rent: None,
+            limits: None,
+            dust: None,
+            dust_nonces: Vec::new(),
+            entries: Vec::new(),
+        }
+    }
+
+    /// Whether the nonce was recorded without being relayed: as dust,
+    /// cancelled, expired, of a skipped type or mint, or closed by an
+    /// operator; a nonce the L2 program had already processed was relayed
+    pub fn is_skipped(&self) -> bool {
+        self.signature.is_empty() && self.processed_on_l2.is_none()
+    }
+
+    /// Whether the receipt has a transaction whose details were not read yet
+    pub fn needs_enrichment(&self) -> bool {
+        !self.signature.is_empty() && self.l2_slot.is_none()
+    }
+
+    fn key(nonce: u64) -> String {
+        format!("receipts/{}", nonce)
+    }
+
+    pub fn load(store: &StateStore, nonce: u64) -> Result<Option<Self>> {
+        store.get(&Self::key(nonce))
+    }
+
+    pub fn remove(store: &StateStore, nonce: u64) -> Result<()> {
+        store.remove(&Self::key(nonce))
+    }
+
+    /// Nonces with a stored receipt, ascending
+    pub fn stored_nonces(store: &StateStore) -> Result<Vec<u64>> {
+        let mut nonces: Vec<u64> = store
+            .list("receipts/")?
+            .iter()
+            .filter_map(|key| key.strip_prefix("receipts/")?.parse().ok())
+            .collect();
+        nonces.sort_unstable();
+        Ok(nonces)
+    }
+
+    /// Every receipt in `store`, once per transaction, ordered by nonce
+    pub fn all(store: &StateStore) -> Result<Vec<Self>> {
+        let mut receipts = BTreeMap::new();
+        for nonce in Self::stored_nonces(store)? {
+            // 列出后被删除的回执直接跳过
+            if let Some(receipt) = Self::load(store, nonce)? {
+                receipts.insert(receipt.nonce, receipt);
+            }
+        }
+        Ok(receipts.into_values().collect())
+    }
+
+    /// Saves the receipt under its nonce and, when coalesced, under every merged nonce
+    pub fn save(&self, store: &StateStore) -> Result<()> {
+        if self.coalesced.is_empty() {
+            return store.put(&Self::key(self.nonce), self);
+        }
+        for nonce in &self.coalesced {
+            store.put(&Self::key(*nonce), self)?;
+        }
+        Ok(())
+    }
+}
+
+/// Part of `total`, a packed transaction's fee, carried by the receipt of
+/// `nonce`: split evenly, the first packed nonce taking the remainder
+pub fn packed_share(total: u64, nonce: u64, packed: &[u64]) -> u64 {
+    let count = packed.len().max(1) as u64;
+    if packed.first() == Some(&nonce) {
+        total / count + total % count
+    } else {
+        total / count
+    }
+}
@@ -0,0 +1,289 @@
+//! Reconciliation scans.
+//! Walks a range of nonces on L1, checks every transfer-info PDA and whether
+//! L2 has already relayed it, and writes the discrepancies found to a file,
+//! ordered by L1 slot once the scan completes. A receipt that records the
+//! sender passed to L2 is checked against the PDA's sender.
+
+use crate::{clock::Clock, pda::PdaManager, receipts::Receipt, state::StateStore};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{account::Account, pubkey::Pubkey};
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+/// State key holding the progress of the resumable full scan
+pub const FULL_SCAN_PROGRESS_KEY: &str = "reconcile_full_progress";
+
+/// `getMultipleAccounts` accepts at most 100 addresses per request
+const MAX_ACCOUNTS_PER_REQUEST: usize = 100;
+const PROGRESS_LOG_EVERY: u64 = 10_000;
+
+/// Progress of a reconciliation scan, persisted after every chunk for full scans
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ScanProgress {
+    pub start_nonce: u64,
+    pub next_nonce: u64,
+    pub target_nonce: u64,
+    pub checked: u64,
+    pub relayed: u64,
+    pub pending: u64,
+    pub discrepancies: u64,
+    pub completed: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscrepancyKind {
+    /// The nonce is below the L1 counter but its transfer-info PDA does not exist
+    MissingPda,
+    /// The PDA exists but its data cannot be decoded
+    UndecodablePda,
+    /// The PDA decodes to a zero-amount transfer
+    ZeroAmount,
+    /// The sender passed to L2, as recorded on the receipt, is not the PDA's
+    SenderMismatch,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Discrepancy {
+    pub nonce: u64,
+    /// L1 slot of the message: from its relay receipt, else the slot it was checked at
+    #[serde(default)]
+    pub l1_slot: u64,
+    pub pda: String,
+    pub kind: DiscrepancyKind,
+    pub relayed_on_l2: bool,
+    pub detail: String,
+}
+
+pub struct ScanOptions {
+    /// Maximum number of L1 RPC requests per second
+    pub requests_per_second: u32,
+    /// Detailed discrepancies file (JSON lines)
+    pub output: PathBuf,
+    /// When set, progress is persisted under this key and resumed on the next run
+    pub progress_key: Option<&'static str>,
+}
+
+pub struct Reconciler<'a> {
+    l1_client: &'a RpcClient,
+    pda_manager: &'a PdaManager,
+    clock: &'a dyn Clock,
+    /// Nonces below this value have been relayed according to the L2 nonce account
+    l2_relayed_nonce: u64,
+}
+
+impl<'a> Reconciler<'a> {
+    pub fn new(
+        l1_client: &'a RpcClient,
+        pda_manager: &'a PdaManager,
+        clock: &'a dyn Clock,
+        l2_relayed_nonce: u64,
+    ) -> Self {
+        Self {
+            l1_client,
+            pda_manager,
+            clock,
+            l2_relayed_nonce,
+        }
+    }
+
+    /// Scans `[from, to)`, resuming saved progress when `options.progress_key` is set
+    pub async fn scan(
+        &self,
+        store: &StateStore,
+        from: u64,
+        to: u64,
+        options: &ScanOptions,
+    ) -> Result<ScanProgress> {
+        let saved = match options.progress_key {
+            Some(key) => store
+                .get::<ScanProgress>(key)?
+                .filter(|progress| !progress.completed),
+            None => None,
+        };
+
+        let (mut progress, mut output) = match saved {
+            Some(mut progress) => {
+                tracing::info!(
+                    "Resuming scan at nonce {} ({} checked so far)",
+                    progress.next_nonce,
+                    progress.checked
+                );
+                progress.target_nonce = progress.target_nonce.max(to);
+                (progress, open_append(&options.output)?)
+            }
+            None => {
+                let progress = ScanProgress {
+                    start_nonce: from,
+                    next_nonce: from,
+                    target_nonce: to,
+                    ..ScanProgress::default()
+                };
+                (progress, File::create(&options.output)?)
+            }
+        };
+
+        let min_request_interval =
+            Duration::from_secs_f64(1.0 / options.requests_per_second.max(1) as f64);
+        let mut last_logged = progress.next_nonce;
+
+        while progress.next_nonce < progress.target_nonce {
+            let started = self.clock.now();
+            let end = progress
+                .target_nonce
+                .min(progress.next_nonce + MAX_ACCOUNTS_PER_REQUEST as u64);
+            let nonces: Vec<u64> = (progress.next_nonce..end).collect();
+            let pdas: Vec<Pubkey> = nonces
+                .iter()
+                .map(|nonce| self.pda_manager.find_address(*nonce).0)
+                .collect();
+
+            let response = self
+                .l1_client
+                .get_multiple_accounts_with_commitment(&pdas, self.l1_client.commitment())
+                .await?;
+            let slot = response.context.slot;
+            for ((nonce, pda), account) in nonces.iter().zip(&pdas).zip(&response.value) {
+                let relayed_on_l2 = *nonce < self.l2_relayed_nonce;
+                if relayed_on_l2 {
+                    progress.relayed += 1;
+                } else {
+                    progress.pending += 1;
+                }
+
+                let receipt = Receipt::load(store, *nonce)?;
+                if let Some(mut discrepancy) = self.check(
+                    *nonce,
+                    pda,
+                    account.as_ref(),
+                    relayed_on_l2,
+                    receipt.as_ref(),
+                ) {
+                    discrepancy.l1_slot = receipt.map_or(slot, |receipt| receipt.l1_slot);
+                    serde_json::to_writer(&mut output, &discrepancy)?;
+                    output.write_all(b"\n")?;
+                    progress.discrepancies += 1;
+                }
+                progress.checked += 1;
+            }
+
+            output.flush()?;
+            progress.next_nonce = end;
+            if let Some(key) = options.progress_key {
+                output.sync_data()?;
+                store.put(key, &progress)?;
+            }
+
+            if progress.next_nonce - last_logged >= PROGRESS_LOG_EVERY {
+                tracing::info!(
+                    "Reconcile progress: {}/{} nonces, {} discrepancies",
+                    progress.next_nonce,
+                    progress.target_nonce,
+                    progress.discrepancies
+                );
+                last_logged = progress.next_nonce;
+            }
+
+            let elapsed = self.clock.elapsed(started);
+            if elapsed < min_request_interval {
+                self.clock.sleep(min_request_interval - elapsed).await;
+            }
+        }
+
+        drop(output);
+        sort_by_slot(&options.output)?;
+        progress.completed = true;
+        if let Some(key) = options.progress_key {
+            store.put(key, &progress)?;
+        }
+
+        Ok(progress)
+    }
+
+    fn check(
+        &self,
+        nonce: u64,
+        pda: &Pubkey,
+        account: Option<&Account>,
+        relayed_on_l2: bool,
+        receipt: Option<&Receipt>,
+    ) -> Option<Discrepancy> {
+        let (kind, detail) = match account {
+            None => (
+                DiscrepancyKind::MissingPda,
+                "Transfer-info PDA not found on L1".to_string(),
+            ),
+            Some(account) => match self.pda_manager.parse_transfer_info(&account.data) {
+                Err(e) => (DiscrepancyKind::UndecodablePda, e.to_string()),
+                Ok((0, to)) => (
+                    DiscrepancyKind::ZeroAmount,
+                    format!("Zero-amount transfer to {}", to),
+                ),
+                Ok(_) => {
+                    let recorded = receipt?.sender.as_deref()?;
+                    let sender = self
+                        .pda_manager
+                        .sender(&account.data)
+                        .map(|sender| sender.to_string())
+                        .unwrap_or_default();
+                    if recorded == sender {
+                        return None;
+                    }
+                    (
+                        DiscrepancyKind::SenderMismatch,
+                        format!(
+                            "Receipt records sender {} passed to L2, the PDA holds {}",
+                            recorded,
+                            if sender.is_empty() { "none" } else { &sender }
+                        ),
+                    )
+                }
+            },
+        };
+
+        Some(Discrepancy {
+            nonce,
+            l1_slot: 0,
+            pda: pda.to_string(),
+            kind,
+            relayed_on_l2,
+            detail,
+        })
+    }
+}
+
+fn open_append(path: &PathBuf) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| anyhow::anyhow!("Failed to open {}: {}", path.display(), e))
+}
+
+/// Rewrites the discrepancies file in chronological (L1 slot, nonce) order
+fn sort_by_slot(path: &PathBuf) -> Result<()> {
+    let file = File::open(path)
+        .map_err(|e| anyhow::anyhow!("Failed to open {}: {}", path.display(), e))?;
+    let mut discrepancies = BufReader::new(file)
+        .lines()
+        .filter(|line| !matches!(line, Ok(line) if line.is_empty()))
+        .map(|line| Ok(serde_json::from_str::<Discrepancy>(&line?)?))
+        .collect::<Result<Vec<_>>>()?;
+    discrepancies.sort_by_key(|discrepancy| (discrepancy.l1_slot, discrepancy.nonce));
+
+    let tmp = path.with_extension("jsonl.tmp");
+    let mut output = File::create(&tmp)?;
+    for discrepancy in &discrepancies {
+        serde_json::to_writer(&mut output, discrepancy)?;
+        output.write_all(b"\n")?;
+    }
+    output.sync_data()?;
+    std::fs::rename(&tmp, path)
+        .map_err(|e| anyhow::anyhow!("Failed to replace {}: {}", path.display(), e))
+}
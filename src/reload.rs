@@ -0,0 +1,379 @@
+//! Config reload on SIGHUP.
+//! `run` reloads its config file on SIGHUP, the same way it loaded it at
+//! startup (format, profile, `--bridge` and `--dry-run` included), and
+//! applies the fields below to the running relayers without a restart:
+//!
+//! - throughput limits: `max_transfers_per_minute`,
+//!   `max_transfers_per_minute_per_source`, a source's own
+//!   `max_transfers_per_minute` and `transfer_rate_burst`
+//! - L2 send rate: `max_l2_sends_per_second` and `l2_send_burst`
+//! - priority fees: `priority_fee_strategy`, `compute_unit_price_*`,
+//!   `priority_fee_percentile`, `priority_fee_refresh_secs` and
+//!   `compute_unit_limit`
+//! - the `[policy]` table
+//! - mint mappings: `[[mint_mappings]]` and the tables of
+//!   `mint_mappings_file`, which is read again; the decimals of every L2
+//!   mint are checked before the new mappings replace the running ones
+//!
+//! Every other field that changed is reported as needing a restart and keeps
+//! its running value until then. A config that fails to load or validate is
+//! rejected as a whole and the running one stays in place. Secrets are
+//! compared redacted, so a changed secret is not noticed.
+
+use crate::{
+    cli::{Cli, Command},
+    config::{ConfigFormat, ProcessRole, RelayerConfig},
+    priority_fee::PriorityFees,
+    token::MintMap,
+    Relayer,
+};
+use anyhow::Result;
+use serde_json::{Map, Value};
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Top-level fields a reload applies to the running relayers
+const LIVE_FIELDS: &[&str] = &[
+    "max_transfers_per_minute",
+    "max_transfers_per_minute_per_source",
+    "transfer_rate_burst",
+    "max_l2_sends_per_second",
+    "l2_send_burst",
+    "priority_fee_strategy",
+    "compute_unit_price_micro_lamports",
+    "compute_unit_price_max_micro_lamports",
+    "priority_fee_percentile",
+    "priority_fee_refresh_secs",
+    "compute_unit_limit",
+    "policy",
+    "mint_mappings",
+    "mint_mappings_file",
+];
+/// Fields of `LIVE_FIELDS` that rebuild the priority fees
+const PRIORITY_FEE_FIELDS: &[&str] = &[
+    "priority_fee_strategy",
+    "compute_unit_price_micro_lamports",
+    "compute_unit_price_max_micro_lamports",
+    "priority_fee_percentile",
+    "priority_fee_refresh_secs",
+    "compute_unit_limit",
+];
+/// Field of a `[[sources]]` table a reload applies
+const LIVE_SOURCE_FIELD: &str = "max_transfers_per_minute";
+
+/// Where the running config was loaded from, to load it again
+pub struct ConfigFile {
+    path: PathBuf,
+    format: Option<ConfigFormat>,
+    profile: Option<String>,
+    bridge: Option<String>,
+    dry_run: bool,
+    role: Option<ProcessRole>,
+}
+
+impl ConfigFile {
+    pub fn from_cli(cli: &Cli) -> Result<Self> {
+        let path = if cli.config == Path::new("-") {
+            cli.config.clone()
+        } else {
+            std::env::current_dir()?.join(&cli.config)
+        };
+        Ok(Self {
+            path,
+            format: cli.config_format,
+            profile: cli.profile.clone(),
+            bridge: cli.bridge.clone(),
+            dry_run: matches!(cli.command, Some(Command::Run { dry_run: true, .. })),
+            role: match cli.command {
+                Some(Command::Run { role, .. }) => role,
+                _ => None,
+            },
+        })
+    }
+
+    /// Path of the config file, `-` for stdin
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn load(&self) -> Result<RelayerConfig> {
+        tracing::info!("Loading config from: {}", self.path.display());
+        let mut config = RelayerConfig::load(&self.path, self.format, self.profile.as_deref())?;
+        if self.dry_run {
+            config.dry_run = true;
+            for bridge in &mut config.bridges {
+                bridge.dry_run = true;
+            }
+        }
+        if let Some(role) = self.role {
+            config.process_role = role;
+            for bridge in &mut config.bridges {
+                bridge.process_role = role;
+            }
+        }
+        match &self.bridge {
+            Some(name) => config.bridge(name),
+            None => Ok(config),
+        }
+    }
+
+    fn is_stdin(&self) -> bool {
+        self.path == Path::new("-")
+    }
+}
+
+/// Configs of the bridges `config` relays, itself without `[[bridges]]`
+fn bridge_configs(config: &RelayerConfig) -> Vec<&RelayerConfig> {
+    if config.bridges.is_empty() {
+        vec![config]
+    } else {
+        config.bridges.iter().collect()
+    }
+}
+
+fn find_bridge<'a>(config: &'a RelayerConfig, name: Option<&str>) -> Option<&'a RelayerConfig> {
+    bridge_configs(config)
+        .into_iter()
+        .find(|bridge| bridge.bridge.as_deref() == name)
+}
+
+fn is_live(field: &str) -> bool {
+    LIVE_FIELDS.contains(&field) || field == format!("sources.{}", LIVE_SOURCE_FIELD)
+}
+
+/// Removes the own limit of each `[[sources]]` table, returning them in order
+fn take_source_limits(config: &mut Map<String, Value>) -> Vec<Value> {
+    match config.get_mut("sources") {
+        Some(Value::Array(sources)) => sources
+            .iter_mut()
+            .map(|source| {
+                source
+                    .as_object_mut()
+                    .and_then(|source| source.remove(LIVE_SOURCE_FIELD))
+                    .unwrap_or(Value::Null)
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Fields that differ between `old` and `new`, as config keys
+fn changed_fields(old: &RelayerConfig, new: &RelayerConfig) -> Result<BTreeSet<String>> {
+    let table = |config: &RelayerConfig| -> Result<Map<String, Value>> {
+        match serde_json::from_str(&config.to_redacted_json()?)? {
+            Value::Object(table) => Ok(table),
+            _ => Err(anyhow::anyhow!("The config does not serialize to a table")),
+        }
+    };
+    let (mut old, mut new) = (table(old)?, table(new)?);
+    let mut changed = BTreeSet::new();
+    // source 自己的限速可以热更新，其余 [[sources]] 字段需要重启
+    if take_source_limits(&mut old) != take_source_limits(&mut new) {
+        changed.insert(format!("sources.{}", LIVE_SOURCE_FIELD));
+    }
+    for key in old.keys().chain(new.keys()) {
+        if key != "bridges" && old.get(key) != new.get(key) {
+            changed.insert(key.clone());
+        }
+    }
+    Ok(changed)
+}
+
+/// Fields of one reload, named `bridges.<name>.<field>` in `[[bridges]]` mode
+#[derive(Debug, Default)]
+pub struct ConfigChanges {
+    /// Applied to the running relayers
+    pub applied: BTreeSet<String>,
+    /// Kept at their running value until a restart
+    pub restart: BTreeSet<String>,
+}
+
+impl ConfigChanges {
+    /// Compares `new` with the config the relayers `started` with, for the
+    /// fields that need a restart, and with the config `applied` by the last
+    /// reload, for the live ones
+    fn between(
+        started: &RelayerConfig,
+        applied: &RelayerConfig,
+        new: &RelayerConfig,
+    ) -> Result<Self> {
+        let mut changes = Self::default();
+        if started.bridge_names() != new.bridge_names() {
+            changes.restart.insert("bridges".to_string());
+        }
+        for bridge in bridge_configs(new) {
+            let name = bridge.bridge.as_deref();
+            let (Some(started), Some(applied)) =
+                (find_bridge(started, name), find_bridge(applied, name))
+            else {
+                continue;
+            };
+            let field = |field: String| match name.filter(|_| !new.bridges.is_empty()) {
+                Some(name) => format!("bridges.{}.{}", name, field),
+                None => field,
+            };
+            changes.restart.extend(
+                changed_fields(started, bridge)?
+                    .into_iter()
+                    .filter(|field| !is_live(field))
+                    .map(field),
+            );
+            changes.applied.extend(
+                changed_fields(applied, bridge)?
+                    .into_iter()
+                    .filter(|field| is_live(field))
+                    .map(field),
+            );
+        }
+        Ok(changes)
+    }
+}
+
+impl Relayer {
+    /// Mint map of `config`, this relayer's bridge in the reloaded config,
+    /// with the decimals of its L2 mints checked
+    async fn reloaded_mint_map(&self, config: &RelayerConfig) -> Result<MintMap> {
+        let map = MintMap::new(
+            &config.mint_mappings,
+            self.transaction_builder.instruction_version,
+        )?;
+        map.check_decimals(&self.l2_client).await?;
+        Ok(map)
+    }
+
+    /// Applies the live fields of `config`, this relayer's bridge in the
+    /// reloaded config, and its checked `mint_map`; the priority fees are
+    /// rebuilt when `fees_changed`
+    fn apply_live_config(
+        &self,
+        config: &RelayerConfig,
+        fees_changed: bool,
+        mint_map: MintMap,
+    ) -> Result<()> {
+        let source_limit = config
+            .sources()?
+            .into_iter()
+            .find(|source| source.id == self.source_id)
+            .and_then(|source| source.max_transfers_per_minute)
+            .or(config.max_transfers_per_minute_per_source);
+        self.throughput.reconfigure(
+            config.max_transfers_per_minute,
+            source_limit,
+            config.transfer_rate_burst,
+        );
+        self.send_rate
+            .reconfigure(config.max_l2_sends_per_second, config.l2_send_burst);
+        *self.policy.lock().unwrap() = config.policy.clone().map(Arc::new);
+        let mut running = self.mint_map.lock().unwrap();
+        if **running != mint_map {
+            let (enabled, disabled) = mint_map.counts();
+            tracing::info!(
+                "Mint mappings of {} replaced: {} enabled, {} disabled",
+                self.name(),
+                enabled,
+                disabled
+            );
+            *running = Arc::new(mint_map);
+        }
+        drop(running);
+        if fees_changed {
+            *self.priority_fees.lock().unwrap() = PriorityFees::from_config(config)?.map(Arc::new);
+        }
+        Ok(())
+    }
+}
+
+/// Loads `file` again and applies its live fields to `relayers`, which run
+/// `started`; `applied` is the config of the last reload. Returns the
+/// reloaded config
+async fn reload(
+    relayers: &[Relayer],
+    started: &RelayerConfig,
+    applied: &RelayerConfig,
+    file: &ConfigFile,
+) -> Result<RelayerConfig> {
+    let new = file.load()?;
+    // 先校验所有 bridge，任何一个失败都整体拒绝
+    for bridge in bridge_configs(&new) {
+        bridge.sources()?;
+        PriorityFees::from_config(bridge)?;
+    }
+    let mut mint_maps = Vec::with_capacity(relayers.len());
+    for relayer in relayers {
+        mint_maps.push(match find_bridge(&new, relayer.bridge.as_deref()) {
+            Some(config) => Some(relayer.reloaded_mint_map(config).await?),
+            None => None,
+        });
+    }
+    let changes = ConfigChanges::between(started, applied, &new)?;
+    for (relayer, mint_map) in relayers.iter().zip(mint_maps) {
+        let (Some(config), Some(mint_map)) =
+            (find_bridge(&new, relayer.bridge.as_deref()), mint_map)
+        else {
+            continue;
+        };
+        let prefix = match relayer
+            .bridge
+            .as_deref()
+            .filter(|_| !new.bridges.is_empty())
+        {
+            Some(name) => format!("bridges.{}.", name),
+            None => String::new(),
+        };
+        let fees_changed = PRIORITY_FEE_FIELDS
+            .iter()
+            .any(|field| changes.applied.contains(&format!("{}{}", prefix, field)));
+        relayer.apply_live_config(config, fees_changed, mint_map)?;
+    }
+
+    let list = |fields: &BTreeSet<String>| fields.iter().cloned().collect::<Vec<_>>().join(", ");
+    if changes.applied.is_empty() && changes.restart.is_empty() {
+        tracing::info!("Config reloaded, nothing changed");
+    }
+    if !changes.applied.is_empty() {
+        tracing::info!("Config reloaded, applied: {}", list(&changes.applied));
+    }
+    if !changes.restart.is_empty() {
+        tracing::warn!(
+            "config changes need a restart and keep their running value until then: {}",
+            list(&changes.restart)
+        );
+    }
+    Ok(new)
+}
+
+/// Reloads the config of `relayers`, started with `config`, on every SIGHUP
+pub(crate) async fn reload_on_hangup(
+    relayers: &[Relayer],
+    config: &RelayerConfig,
+    file: &ConfigFile,
+) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::hangup()) {
+            Ok(mut hangup) => {
+                let mut applied = config.clone();
+                while hangup.recv().await.is_some() {
+                    tracing::info!("Received SIGHUP, reloading the config...");
+                    if file.is_stdin() {
+                        tracing::warn!("the config was read from stdin and cannot be reloaded");
+                        continue;
+                    }
+                    match reload(relayers, config, &applied, file).await {
+                        Ok(reloaded) => applied = reloaded,
+                        Err(e) => tracing::warn!(
+                            "config reload failed, keeping the running config: {:#}",
+                            e
+                        ),
+                    }
+                }
+            }
+            Err(e) => tracing::warn!("failed to listen for SIGHUP: {}", e),
+        }
+    }
+    futures::future::pending().await
+}
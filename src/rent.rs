@@ -0,0 +1,262 @@
+//! Rent-exemption helpers.
+//! Caches minimum rent-exempt balances per account size and checks that the
+//! relayer can cover a transfer before it is sent to L2.
+
+use crate::token::TokenTransfer;
+use anyhow::Result;
+use solana_client::{client_error::Result as ClientResult, nonblocking::rpc_client::RpcClient};
+use solana_sdk::{message::VersionedMessage, program_pack::Pack, pubkey::Pubkey};
+use std::{collections::HashMap, sync::Mutex};
+
+/// Data length of a plain system account (relayer wallet, native recipients)
+pub const SYSTEM_ACCOUNT_SIZE: usize = 0;
+
+/// Caches `get_minimum_balance_for_rent_exemption` results keyed by data length
+pub struct RentCache {
+    minimums: Mutex<HashMap<usize, u64>>,
+}
+
+impl RentCache {
+    pub fn new() -> Self {
+        Self {
+            minimums: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the minimum rent-exempt balance for an account of `data_len` bytes
+    pub async fn minimum_balance(&self, client: &RpcClient, data_len: usize) -> Result<u64> {
+        if let Some(lamports) = self.minimums.lock().unwrap().get(&data_len) {
+            return Ok(*lamports);
+        }
+
+        let lamports = client
+            .get_minimum_balance_for_rent_exemption(data_len)
+            .await
+            .map_err(|e| {
+                anyhow::anyhow!(
+                    "Failed to query rent exemption for {} bytes: {}",
+                    data_len,
+                    e
+                )
+            })?;
+        self.minimums.lock().unwrap().insert(data_len, lamports);
+        Ok(lamports)
+    }
+}
+
+/// Lamports the relayer wallet needs to send a single transfer
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TransferCost {
+    /// Amount moved out of the relayer wallet
+    pub amount: u64,
+    /// Network fee for the transaction message, base and priority fee as
+    /// reported by `getFeeForMessage`
+    pub fee: u64,
+    /// Rent for accounts created by the transaction
+    pub rent: u64,
+    /// Balance the relayer wallet must keep to stay rent-exempt
+    pub reserve: u64,
+}
+
+impl TransferCost {
+    /// Estimates the cost of a native transfer of `amount` lamports to `recipient`.
+    /// Fails if the recipient does not exist yet and `amount` would leave it
+    /// below the rent-exempt minimum.
+    pub async fn estimate_native(
+        client: &RpcClient,
+        rent_cache: &RentCache,
+        message: &VersionedMessage,
+        amount: u64,
+        recipient: &Pubkey,
+    ) -> Result<Self> {
+        let system_minimum = rent_cache
+            .minimum_balance(client, SYSTEM_ACCOUNT_SIZE)
+            .await?;
+
+        let recipient_exists = client
+            .get_account_with_commitment(recipient, client.commitment())
+            .await?
+            .value
+            .is_some();
+        if !recipient_exists && amount < system_minimum {
+            return Err(anyhow::anyhow!(
+                "Transfer of {} lamports would create recipient {} below the rent-exempt minimum of {} lamports",
+                amount,
+                recipient,
+                system_minimum
+            ));
+        }
+
+        let fee = fee_for_message(client, message)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to estimate transaction fee: {}", e))?;
+
+        Ok(Self {
+            amount,
+            fee,
+            rent: 0,
+            reserve: system_minimum,
+        })
+    }
+
+    /// Estimates the cost of a token transfer of `amount` base units of
+    /// `token` to `recipient`: no lamports move, but the recipient's token
+    /// account is paid for if it does not exist yet. Fails if the payer's
+    /// token account holds less than `amount`.
+    pub async fn estimate_token(
+        client: &RpcClient,
+        rent_cache: &RentCache,
+        message: &VersionedMessage,
+        token: &TokenTransfer,
+        amount: u64,
+        recipient: &Pubkey,
+    ) -> Result<Self> {
+        let payer = message
+            .static_account_keys()
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("Transaction has no payer"))?;
+        let held = token.balance_of(client, payer).await?.unwrap_or_default();
+        if held < amount {
+            return Err(anyhow::anyhow!(
+                "Insufficient token balance: token account {} holds {} of mint {}, need {}",
+                token.account_of(payer),
+                held,
+                token.l2_mint,
+                amount
+            ));
+        }
+
+        let rent = match token.recipient_balance(client, recipient).await? {
+            Some(_) => 0,
+            None => {
+                rent_cache
+                    .minimum_balance(client, spl_token::state::Account::LEN)
+                    .await?
+            }
+        };
+        let reserve = rent_cache
+            .minimum_balance(client, SYSTEM_ACCOUNT_SIZE)
+            .await?;
+        let fee = fee_for_message(client, message)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to estimate transaction fee: {}", e))?;
+
+        Ok(Self {
+            amount: 0,
+            fee,
+            rent,
+            reserve,
+        })
+    }
+
+    /// Lamports the transaction takes out of the wallet
+    pub fn spent(&self) -> u64 {
+        self.amount
+            .saturating_add(self.fee)
+            .saturating_add(self.rent)
+    }
+
+    /// Balance needed to send the transaction and keep the reserve
+    pub fn total(&self) -> u64 {
+        self.spent().saturating_add(self.reserve)
+    }
+
+    /// Errors with the exact breakdown if `balance` cannot cover this cost
+    pub fn ensure_covered(&self, balance: u64) -> Result<()> {
+        let total = self.total();
+        if balance < total {
+            return Err(anyhow::anyhow!(
+                "Insufficient relayer balance: have {} lamports, need {}, short by {} (amount {} + fee {} + rent {} + rent-exempt reserve {})",
+                balance,
+                total,
+                total - balance,
+                self.amount,
+                self.fee,
+                self.rent,
+                self.reserve
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// `getFeeForMessage` of a legacy or v0 message
+pub async fn fee_for_message(client: &RpcClient, message: &VersionedMessage) -> ClientResult<u64> {
+    match message {
+        VersionedMessage::Legacy(message) => client.get_fee_for_message(message).await,
+        VersionedMessage::V0(message) => client.get_fee_for_message(message).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_shortfall_names_every_part_of_the_cost() {
+        let cost = TransferCost {
+            amount: 1_000,
+            fee: 5_000,
+            rent: 2_039_280,
+            reserve: 890_880,
+        };
+        assert_eq!(cost.spent(), 2_045_280);
+        assert_eq!(cost.total(), 2_936_160);
+        cost.ensure_covered(2_936_160).unwrap();
+        let error = cost.ensure_covered(2_936_000).unwrap_err().to_string();
+        assert!(error.contains("short by 160"), "{}", error);
+        assert!(
+            error.contains("amount 1000 + fee 5000 + rent 2039280"),
+            "{}",
+            error
+        );
+    }
+
+    #[cfg(feature = "testing")]
+    mod fake_l2 {
+        use super::super::*;
+        use crate::testing::{FakeL2, FAKE_FEE};
+        use solana_sdk::{message::Message, rent::Rent, system_instruction};
+
+        fn message(payer: &Pubkey, recipient: &Pubkey, amount: u64) -> VersionedMessage {
+            VersionedMessage::Legacy(Message::new(
+                &[system_instruction::transfer(payer, recipient, amount)],
+                Some(payer),
+            ))
+        }
+
+        #[tokio::test]
+        async fn a_new_recipient_must_be_left_rent_exempt() {
+            let l2 = FakeL2::new(Pubkey::new_unique());
+            let client = l2.client();
+            let rent_cache = RentCache::new();
+            let (payer, recipient) = (Pubkey::new_unique(), Pubkey::new_unique());
+            let minimum = Rent::default().minimum_balance(SYSTEM_ACCOUNT_SIZE);
+
+            let short = message(&payer, &recipient, minimum - 1);
+            let error = TransferCost::estimate_native(
+                &client,
+                &rent_cache,
+                &short,
+                minimum - 1,
+                &recipient,
+            )
+            .await
+            .unwrap_err();
+            assert!(
+                error.to_string().contains("rent-exempt minimum"),
+                "{}",
+                error
+            );
+
+            // 已存在的收款账户可以收任意金额
+            l2.fund(&recipient, minimum);
+            let cost = TransferCost::estimate_native(&client, &rent_cache, &short, 1, &recipient)
+                .await
+                .unwrap();
+            assert_eq!(cost.fee, FAKE_FEE);
+            assert_eq!(cost.reserve, minimum);
+            assert_eq!(cost.total(), 1 + FAKE_FEE + minimum);
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Replay-protection window.
+//! Receipts are the relayer's record of which nonces landed on L2 and would
+//! otherwise grow forever. With `replay_window_nonces` and/or
+//! `replay_window_days` set, receipts of nonces that are outside every
+//! configured window are pruned every `replay_prune_interval_secs`. Receipts
+//! at or above the completed prefix of the persisted queue are never pruned.
+//!
+//! The pruned boundary is persisted before any receipt is removed, so a crash
+//! mid-prune leaves at worst receipts that the next run removes. A nonce below
+//! that boundary without a receipt is assumed relayed, or not relayed with
+//! `outside_replay_window = "assume_unprocessed"`.
+
+use crate::{receipt_pdas::ClosedReceipt, receipts::Receipt, state::StateStore, storage_buffer::StorageUnavailable, Relayer};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{sync::Mutex, time::Duration};
+
+/// State key holding the nonce below which receipts may have been pruned
+pub const RECEIPTS_PRUNED_BELOW_KEY: &str = "receipts_pruned_below";
+
+/// Idempotency answer for a nonce whose receipt may have been pruned
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutsideWindowPolicy {
+    /// Treat it as relayed and never send it again
+    #[default]
+    AssumeProcessed,
+    /// Treat it as not relayed, leaving the decision to the L2 program
+    AssumeUnprocessed,
+}
+
+pub struct ReplayWindow {
+    /// Receipts kept below the completed prefix
+    nonces: Option<u64>,
+    /// Age up to which receipts are kept
+    max_age: Option<Duration>,
+    /// Pause between prunes
+    interval: Duration,
+    policy: OutsideWindowPolicy,
+    pruned_below: Mutex<u64>,
+}
+
+impl ReplayWindow {
+    pub fn load(
+        store: &StateStore,
+        nonces: Option<u64>,
+        max_age: Option<Duration>,
+        interval: Duration,
+        policy: OutsideWindowPolicy,
+    ) -> Result<Self> {
+        Ok(Self {
+            nonces,
+            max_age,
+            interval,
+            policy,
+            pruned_below: Mutex::new(store.get(RECEIPTS_PRUNED_BELOW_KEY)?.unwrap_or_default()),
+        })
+    }
+
+    fn enabled(&self) -> bool {
+        self.nonces.is_some() || self.max_age.is_some()
+    }
+}
+
+impl Relayer {
+    /// Whether `nonce` landed on L2, according to its receipt or the record
+    /// of its closed receipt PDA or, once its receipt may have been pruned,
+    /// to `outside_replay_window`
+    pub(crate) fn already_relayed(&self, nonce: u64) -> Result<bool> {
+        // 已关闭的 L2 回执 PDA 不再防重放，只能由本地记录判断
+        if Receipt::load(&self.state, nonce)?.is_some()
+            || ClosedReceipt::load(&self.state, nonce)?.is_some()
+        {
+            return Ok(true);
+        }
+        let window = &self.replay_window;
+        Ok(nonce < *window.pruned_below.lock().unwrap()
+            && window.policy == OutsideWindowPolicy::AssumeProcessed)
+    }
+
+    /// Prunes receipts outside the replay window on schedule; pends forever
+    /// when no window is configured
+    pub(crate) async fn prune_replay_state(&self) -> Result<()> {
+        if !self.replay_window.enabled() {
+            return futures::future::pending().await;
+        }
+        loop {
+            let pruned = match self.prune_receipts() {
+                Ok(pruned) => pruned,
+                // 存储不可用时跳过本轮，下次再清理
+                Err(e) if e.is::<StorageUnavailable>() => {
+                    tracing::warn!("skipping the replay-window prune: {}", e);
+                    0
+                }
+                Err(e) => return Err(e),
+            };
+            if pruned > 0 {
+                tracing::info!(
+                    "Pruned {} receipt(s) outside the replay window (below nonce {})",
+                    pruned,
+                    self.replay_window.pruned_below.lock().unwrap()
+                );
+            }
+            self.clock.sleep(self.replay_window.interval).await;
+        }
+    }
+
+    /// The nonce below which receipts are outside `replay_window_nonces`
+    /// (the completed prefix without one) and the relay time before which
+    /// they are outside `replay_window_days`, if set
+    pub(crate) fn replay_window_bounds(&self) -> (u64, Option<u64>) {
+        let window = &self.replay_window;
+        let completed = self.queue.completed();
+        let nonce_floor = window
+            .nonces
+            .map_or(completed, |nonces| completed.saturating_sub(nonces));
+        let oldest_kept = window
+            .max_age
+            .map(|age| self.clock.unix_timestamp().saturating_sub(age.as_secs()));
+        (nonce_floor, oldest_kept)
+    }
+
+    /// Prunes receipts outside the replay window once, for `run_once`
+    pub(crate) fn prune_replay_window(&self) -> Result<usize> {
+        if !self.replay_window.enabled() {
+            return Ok(0);
+        }
+        self.prune_receipts()
+    }
+
+    /// Removes the receipts outside every configured window and returns how many
+    fn prune_receipts(&self) -> Result<usize> {
+        let window = &self.replay_window;
+        let (nonce_floor, oldest_kept) = self.replay_window_bounds();
+
+        let mut prunable = Vec::new();
+        for nonce in Receipt::stored_nonces(&self.state)? {
+            if nonce >= nonce_floor {
+                break;
+            }
+            if let Some(oldest_kept) = oldest_kept {
+                // 列出后被删除的回执视为可清理
+                let recent = Receipt::load(&self.state, nonce)?
+                    .is_some_and(|receipt| receipt.relayed_at >= oldest_kept);
+                if recent {
+                    continue;
+                }
+            }
+            prunable.push(nonce);
+        }
+        let Some(&highest) = prunable.last() else {
+            return Ok(0);
+        };
+
+        // 先持久化边界再删除，中途崩溃只会留下下次再删的回执
+        {
+            let mut pruned_below = window.pruned_below.lock().unwrap();
+            if highest + 1 > *pruned_below {
+                *pruned_below = highest + 1;
+                self.state.put(RECEIPTS_PRUNED_BELOW_KEY, &*pruned_below)?;
+            }
+        }
+        for nonce in &prunable {
+            Receipt::remove(&self.state, *nonce)?;
+        }
+        Ok(prunable.len())
+    }
+}
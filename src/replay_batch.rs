@@ -0,0 +1,519 @@
+//! Bulk replay.
+//! `replay-batch` (or `backfill`) relays a set of nonces again, e.g. dead
+//! letters after a layout fix or a historical range missed during an outage:
+//! a `--from`/`--to` range, the open dead letters (`--from-dlq`, within the
+//! range if one is given), or both, narrowed by `--filter type=<type>`. Each
+//! target goes through the same idempotency check as the submitter (receipt,
+//! replay window, L2 nonce account, `[l2_processed_nonces]` and in-flight
+//! transaction) and, unless already relayed, through the normal fetch →
+//! build → send pipeline, at most `--rate` nonces per second. Progress is
+//! saved in the state store after every nonce, so an interrupted replay
+//! resumes where it stopped; the outcome of every target is written to the
+//! summary file once the batch is done.
+//!
+//! The replay holds the replay lease, a state entry renewed while it runs.
+//! Relayers sharing the state hold their submissions while another process
+//! holds the lease, and check it again right before sending, so the two
+//! never submit at the same time. The replay first settles the transactions
+//! a running relayer left in flight. A lease that is not renewed expires
+//! after `LEASE_TTL`, e.g. when the replay was killed.
+
+use crate::{
+    dlq::{Resolution, ResolutionKind},
+    inflight::InFlightTransfer,
+    models::message::MessageType,
+    receipts::Receipt,
+    Relayer,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+/// State key holding the progress of the last replay batch
+pub const REPLAY_BATCH_PROGRESS_KEY: &str = "replay_batch_progress";
+/// State key holding the replay lease
+pub const REPLAY_LEASE_KEY: &str = "replay_lease";
+/// Time after which a lease that was not renewed expires
+pub(crate) const LEASE_TTL: Duration = Duration::from_secs(30);
+/// Longest wait between renewals of the lease
+const LEASE_RENEW_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Message filter of a replay batch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplayFilter {
+    /// Messages of this type under the source's `message_types`
+    Type(MessageType),
+    /// Messages with this raw type id
+    TypeId(u8),
+}
+
+impl ReplayFilter {
+    fn matches(self, kind: MessageType, id: u8) -> bool {
+        match self {
+            ReplayFilter::Type(wanted) => kind == wanted,
+            ReplayFilter::TypeId(wanted) => id == wanted,
+        }
+    }
+}
+
+/// Parses `type=<native|token|nft|raw id>`
+pub fn parse_filter(filter: &str) -> Result<ReplayFilter, String> {
+    let (key, value) = filter
+        .split_once('=')
+        .ok_or_else(|| format!("expected key=value, got {:?}", filter))?;
+    if key != "type" {
+        return Err(format!("unknown filter {:?} (expected type)", key));
+    }
+    match value {
+        "native" => Ok(ReplayFilter::Type(MessageType::Native)),
+        "token" => Ok(ReplayFilter::Type(MessageType::Token)),
+        "nft" => Ok(ReplayFilter::Type(MessageType::Nft)),
+        id => id.parse().map(ReplayFilter::TypeId).map_err(|_| {
+            format!(
+                "unknown message type {:?} (expected native, token, nft or a type id)",
+                id
+            )
+        }),
+    }
+}
+
+/// Nonces a replay batch targets, as given on the command line
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplaySelection {
+    pub from: Option<u64>,
+    /// End of the range (exclusive); the L1 nonce when none
+    pub to: Option<u64>,
+    pub from_dlq: bool,
+    pub filters: Vec<ReplayFilter>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplayOutcome {
+    Relayed,
+    /// Already relayed or closed, not sent again
+    Skipped,
+    Failed,
+}
+
+impl fmt::Display for ReplayOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ReplayOutcome::Relayed => "relayed",
+            ReplayOutcome::Skipped => "skipped",
+            ReplayOutcome::Failed => "failed",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayResult {
+    pub nonce: u64,
+    pub outcome: ReplayOutcome,
+    /// L2 signature, why the nonce was skipped or the error
+    pub detail: String,
+}
+
+/// Progress of a replay batch, persisted after every nonce
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReplayBatchProgress {
+    pub selection: ReplaySelection,
+    pub targets: Vec<u64>,
+    /// Outcome of each target processed so far, in order
+    pub results: Vec<ReplayResult>,
+    pub relayed: u64,
+    pub skipped: u64,
+    pub failed: u64,
+    pub started_at: u64,
+    pub finished_at: Option<u64>,
+}
+
+impl ReplayBatchProgress {
+    fn record(&mut self, result: ReplayResult) {
+        match result.outcome {
+            ReplayOutcome::Relayed => self.relayed += 1,
+            ReplayOutcome::Skipped => self.skipped += 1,
+            ReplayOutcome::Failed => self.failed += 1,
+        }
+        self.results.push(result);
+    }
+
+    /// Writes the progress to `path` as JSON
+    pub fn write_summary(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+            .map_err(|e| anyhow::anyhow!("Failed to write summary {}: {}", path.display(), e))
+    }
+}
+
+/// The replay lease, held by the replay batch that is submitting
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayLease {
+    /// Operator and process of the holder
+    pub holder: String,
+    /// Tells the holder's own lease from another process's
+    pub token: String,
+    pub expires_at: u64,
+}
+
+/// A send refused because another process holds the replay lease
+#[derive(Debug)]
+pub struct ReplayInProgress {
+    pub holder: String,
+}
+
+impl fmt::Display for ReplayInProgress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Holding submissions: {} holds the replay lease",
+            self.holder
+        )
+    }
+}
+
+impl std::error::Error for ReplayInProgress {}
+
+pub struct ReplayOptions {
+    pub selection: ReplaySelection,
+    /// Nonces submitted per second
+    pub rate: f64,
+    /// Operator recorded on the lease and on replayed dead letters
+    pub by: String,
+}
+
+impl Relayer {
+    /// The live replay lease of another process, if any
+    pub(crate) fn foreign_replay_lease(&self) -> Result<Option<ReplayLease>> {
+        let own = self.replay_lease.lock().unwrap().clone();
+        let now = self.clock.unix_timestamp();
+        Ok(self
+            .state
+            .get::<ReplayLease>(REPLAY_LEASE_KEY)?
+            .filter(|lease| lease.expires_at > now && Some(&lease.token) != own.as_ref()))
+    }
+
+    /// Wait before trying again while another process replays nonces
+    pub(crate) fn replay_lease_hold(&self) -> Result<Option<Duration>> {
+        let Some(lease) = self.foreign_replay_lease()? else {
+            return Ok(None);
+        };
+        tracing::info!(
+            "{}",
+            ReplayInProgress {
+                holder: lease.holder
+            }
+        );
+        Ok(Some(self.submitter.retry_delay))
+    }
+
+    /// Takes the replay lease for `holder`; fails while another process
+    /// holds it
+    fn acquire_replay_lease(&self, holder: &str) -> Result<()> {
+        if let Some(lease) = self.foreign_replay_lease()? {
+            return Err(anyhow::anyhow!(
+                "{} holds the replay lease until {}",
+                lease.holder,
+                lease.expires_at
+            ));
+        }
+        let token = format!(
+            "{}-{}",
+            std::process::id(),
+            self.clock
+                .now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos()
+        );
+        let lease = ReplayLease {
+            holder: holder.to_string(),
+            token: token.clone(),
+            expires_at: self.clock.unix_timestamp() + LEASE_TTL.as_secs(),
+        };
+        self.state.put(REPLAY_LEASE_KEY, &lease)?;
+        *self.replay_lease.lock().unwrap() = Some(token);
+        // 存储没有比较并交换，写后再读一次确认没有被同时抢占
+        if let Some(lease) = self.foreign_replay_lease()? {
+            return Err(anyhow::anyhow!(
+                "{} took the replay lease at the same time",
+                lease.holder
+            ));
+        }
+        Ok(())
+    }
+
+    /// Extends the lease held by this process; fails if it was lost
+    fn renew_replay_lease(&self) -> Result<()> {
+        let lease = self.state.get::<ReplayLease>(REPLAY_LEASE_KEY)?;
+        let own = self.replay_lease.lock().unwrap().clone();
+        let Some(mut lease) = lease.filter(|lease| Some(&lease.token) == own.as_ref()) else {
+            return Err(anyhow::anyhow!("Lost the replay lease"));
+        };
+        lease.expires_at = self.clock.unix_timestamp() + LEASE_TTL.as_secs();
+        self.state.put(REPLAY_LEASE_KEY, &lease)
+    }
+
+    /// Gives up the lease held by this process
+    fn release_replay_lease(&self) -> Result<()> {
+        let own = self.replay_lease.lock().unwrap().take();
+        let lease = self.state.get::<ReplayLease>(REPLAY_LEASE_KEY)?;
+        if lease.is_some_and(|lease| Some(&lease.token) == own.as_ref()) {
+            self.state.remove(REPLAY_LEASE_KEY)?;
+        }
+        Ok(())
+    }
+
+    /// Sleeps for `wait`, renewing the lease on the way
+    async fn wait_holding_lease(&self, wait: Duration) -> Result<()> {
+        let mut left = wait;
+        while !left.is_zero() {
+            let step = left.min(LEASE_RENEW_INTERVAL);
+            self.clock.sleep(step).await;
+            left -= step;
+            self.renew_replay_lease()?;
+        }
+        Ok(())
+    }
+
+    /// Nonces selected by `selection`, lowest first
+    async fn replay_targets(&self, selection: &ReplaySelection) -> Result<Vec<u64>> {
+        let range = match selection.from {
+            Some(from) => {
+                let to = match selection.to {
+                    Some(to) => to,
+                    None => self.read_nonces().await?.0,
+                };
+                Some(from..to)
+            }
+            None => None,
+        };
+        let targets: Vec<u64> = if selection.from_dlq {
+            self.dead_letters
+                .entries()
+                .iter()
+                .filter(|letter| letter.is_open())
+                .map(|letter| letter.nonce)
+                .filter(|nonce| range.as_ref().is_none_or(|range| range.contains(nonce)))
+                .collect()
+        } else {
+            range.map_or_else(Vec::new, Iterator::collect)
+        };
+        if selection.filters.is_empty() {
+            return Ok(targets);
+        }
+        if self.indexer.is_some() || self.message_accounts.is_some() {
+            return Err(anyhow::anyhow!(
+                "replay-batch filters only support the pda message source"
+            ));
+        }
+
+        let mut kept = Vec::with_capacity(targets.len());
+        for chunk in targets.chunks(self.fetch_chunk_size()) {
+            let pdas: Vec<_> = chunk
+                .iter()
+                .map(|nonce| self.pda_manager.find_address(*nonce).0)
+                .collect();
+            let accounts = self.l1_client.get_multiple_accounts(&pdas).await?;
+            for (nonce, account) in chunk.iter().zip(accounts) {
+                let Some(id) =
+                    account.and_then(|account| self.pda_manager.message_type_id(&account.data))
+                else {
+                    continue;
+                };
+                // 未配置映射时所有消息都按原生转账处理
+                let kind = if self.message_types.is_empty() {
+                    MessageType::Native
+                } else {
+                    self.message_types
+                        .get(&id)
+                        .copied()
+                        .unwrap_or(MessageType::Unknown(id))
+                };
+                if selection
+                    .filters
+                    .iter()
+                    .all(|filter| filter.matches(kind, id))
+                {
+                    kept.push(*nonce);
+                }
+            }
+        }
+        Ok(kept)
+    }
+
+    /// Why `nonce` must not be sent again, if it must not
+    async fn replay_skip_reason(&self, nonce: u64) -> Result<Option<String>> {
+        if let Some(receipt) = Receipt::load(&self.state, nonce)? {
+            return Ok(Some(if receipt.signature.is_empty() {
+                "recorded without a transaction".to_string()
+            } else {
+                format!("already relayed in {}", receipt.signature)
+            }));
+        }
+        if self.already_relayed(nonce)? {
+            return Ok(Some("outside the replay window".to_string()));
+        }
+        if self.dead_letters.closed_unrelayed(nonce) {
+            return Ok(Some("dead letter closed without relaying".to_string()));
+        }
+        // 按序模式下 L2 nonce account 以下的 nonce 均已处理
+        if self.priority.is_none() && nonce < self.read_l2_relayed_nonce().await? {
+            return Ok(Some("below the L2 nonce".to_string()));
+        }
+        if let Some(slot) = self.processed_on_l2(nonce).await? {
+            return Ok(Some(format!("processed on L2 at slot {}", slot)));
+        }
+        if self.resolve_in_flight(nonce).await? {
+            return Ok(Some("its in-flight transaction landed".to_string()));
+        }
+        Ok(None)
+    }
+
+    /// Relays `nonce` again unless it must not be sent
+    async fn replay_nonce(&self, nonce: u64, by: &str) -> Result<ReplayResult> {
+        let result = |outcome, detail: String| ReplayResult {
+            nonce,
+            outcome,
+            detail,
+        };
+        if let Some(reason) = self.replay_skip_reason(nonce).await? {
+            return Ok(result(ReplayOutcome::Skipped, reason));
+        }
+        // 与 `dlq resolve --as replayed` 相同：先关闭死信，落地后记录签名
+        if self.dead_letters.contains(nonce) {
+            let resolution = Resolution {
+                kind: ResolutionKind::Replayed,
+                by: by.to_string(),
+                at: self.clock.unix_timestamp(),
+                note: Some("replay-batch".to_string()),
+                l2_signature: None,
+                applied: false,
+            };
+            self.dead_letters.resolve(&self.state, nonce, resolution)?;
+        }
+        if let Err(e) = self.process_nonces(&[nonce], 0).await {
+            return Ok(result(ReplayOutcome::Failed, e.to_string()));
+        }
+        let Some(receipt) = Receipt::load(&self.state, nonce)? else {
+            return Ok(result(ReplayOutcome::Failed, "not relayed".to_string()));
+        };
+        if receipt.signature.is_empty() {
+            return Ok(result(
+                ReplayOutcome::Skipped,
+                "recorded without a transaction".to_string(),
+            ));
+        }
+        self.dead_letters
+            .link_replay(&self.state, nonce, &receipt.signature)?;
+        Ok(result(ReplayOutcome::Relayed, receipt.signature))
+    }
+
+    /// Runs the replay batch of `options`, resuming saved progress of the
+    /// same selection
+    pub(crate) async fn replay_batch(
+        &self,
+        options: &ReplayOptions,
+    ) -> Result<ReplayBatchProgress> {
+        let holder = format!(
+            "replay-batch of {} (pid {})",
+            options.by,
+            std::process::id()
+        );
+        self.acquire_replay_lease(&holder)?;
+        let result = self.replay_batch_holding_lease(options).await;
+        self.release_replay_lease()?;
+        result
+    }
+
+    async fn replay_batch_holding_lease(
+        &self,
+        options: &ReplayOptions,
+    ) -> Result<ReplayBatchProgress> {
+        let saved = self
+            .state
+            .get::<ReplayBatchProgress>(REPLAY_BATCH_PROGRESS_KEY)?
+            .filter(|progress| progress.finished_at.is_none());
+        let mut progress = match saved {
+            Some(progress) if progress.selection == options.selection => {
+                tracing::info!(
+                    "Resuming replay batch ({} of {} nonces processed)",
+                    progress.results.len(),
+                    progress.targets.len()
+                );
+                progress
+            }
+            Some(_) => {
+                return Err(anyhow::anyhow!(
+                    "An unfinished replay batch with another selection is saved; run it again or pass --restart"
+                ));
+            }
+            None => {
+                let targets = self.replay_targets(&options.selection).await?;
+                ReplayBatchProgress {
+                    selection: options.selection.clone(),
+                    targets,
+                    started_at: self.clock.unix_timestamp(),
+                    ..ReplayBatchProgress::default()
+                }
+            }
+        };
+        self.state.put(REPLAY_BATCH_PROGRESS_KEY, &progress)?;
+
+        // 运行中的实例可能留有在途交易，先等其结果，避免重复提交
+        let persisted: BTreeMap<u64, InFlightTransfer> = self
+            .state
+            .get(crate::inflight::IN_FLIGHT_KEY)?
+            .unwrap_or_default();
+        self.in_flight.reload(persisted);
+        self.recover_in_flight().await?;
+        self.ensure_wallet_funded().await?;
+
+        let interval = Duration::from_secs_f64(1.0 / options.rate);
+        let session_started = self.clock.now();
+        let resumed_at = progress.results.len();
+        let total = progress.targets.len();
+        for index in resumed_at..total {
+            let nonce = progress.targets[index];
+            self.renew_replay_lease()?;
+            let started = self.clock.now();
+            let result = self.replay_nonce(nonce, &options.by).await?;
+            tracing::info!(
+                "[{}/{}] Nonce {} {}: {}",
+                index + 1,
+                total,
+                nonce,
+                result.outcome,
+                result.detail
+            );
+            progress.record(result);
+            self.state.put(REPLAY_BATCH_PROGRESS_KEY, &progress)?;
+
+            let processed = (index + 1 - resumed_at) as u32;
+            let left = (total - index - 1) as u32;
+            let eta = self.clock.elapsed(session_started) / processed * left;
+            tracing::info!(
+                "Done {}, failed {}, skipped {}; {} left, ETA {}s",
+                progress.relayed,
+                progress.failed,
+                progress.skipped,
+                left,
+                eta.as_secs()
+            );
+            if left > 0 {
+                let elapsed = self.clock.elapsed(started);
+                self.wait_holding_lease(interval.saturating_sub(elapsed))
+                    .await?;
+            }
+        }
+
+        progress.finished_at = Some(self.clock.unix_timestamp());
+        self.state.put(REPLAY_BATCH_PROGRESS_KEY, &progress)?;
+        Ok(progress)
+    }
+}
@@ -0,0 +1,384 @@
+//! Volume and activity reports.
+//! Aggregates the receipts and dead letters of a state store per UTC day or
+//! hour, including the receipts `compact` moved into monthly archives. The
+//! store is only read, so a report can run next to a relayer that is writing
+//! to the same state directory.
+//!
+//! Relayed amounts are paid out in full on L2, so fees collected are zero and
+//! net volume equals gross volume; L2 fees are the fees charged on L2, or the
+//! estimate made when the transaction was built if the charged fee could not
+//! be read (zero in receipts of older versions), plus the fees of warm-up
+//! transactions.
+//!
+//! Volumes are in lamports for native transfers; token and NFT transfers are
+//! summed per L2 mint in its base units (`token_volume`), never mixed into
+//! the lamport figures.
+
+use crate::{
+    archive::Archive,
+    dlq::{DeadLetter, DEAD_LETTERS_KEY},
+    receipts::Receipt,
+    state::StateStore,
+    warmup::{WarmupCost, WARMUP_COSTS_KEY},
+};
+use anyhow::Result;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+    time::Duration,
+};
+
+/// Length of the report buckets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Granularity {
+    Day,
+    Hour,
+}
+
+impl Granularity {
+    fn seconds(self) -> u64 {
+        match self {
+            Granularity::Day => 86_400,
+            Granularity::Hour => 3_600,
+        }
+    }
+}
+
+/// Parses a `--last` span such as `30d`, `12h` or `90m`
+pub fn parse_span(span: &str) -> Result<Duration, String> {
+    // 按字符而非字节切分，非 ASCII 的单位也只会报错
+    let (count, unit) = span
+        .char_indices()
+        .last()
+        .map_or(("", ""), |(at, _)| span.split_at(at));
+    let count: u64 = count
+        .parse()
+        .map_err(|_| format!("invalid span {:?}, expected e.g. 30d, 12h or 90m", span))?;
+    let unit = match unit {
+        "d" => 86_400,
+        "h" => 3_600,
+        "m" => 60,
+        _ => return Err(format!("unknown unit in {:?}, expected d, h or m", span)),
+    };
+    Ok(Duration::from_secs(count.saturating_mul(unit)))
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Bucket {
+    /// Unix time at which the bucket starts
+    pub start: u64,
+    /// Relayed L1 transfers, counting each nonce of a coalesced transaction
+    pub transfers: u64,
+    /// Lamports of native transfers
+    pub gross_volume: u64,
+    pub fees_collected: u64,
+    pub net_volume: u64,
+    pub l2_fees: u64,
+    /// Fees of warm-up transactions, included in `l2_fees`
+    pub warmup_fees: u64,
+    /// Nonces dead-lettered in the bucket
+    pub failures: u64,
+    /// Dust transfers skipped or accumulated instead of being relayed
+    pub dust: u64,
+    pub unique_recipients: usize,
+    /// Base units relayed per L2 mint (base58)
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub token_volume: BTreeMap<String, u64>,
+    #[serde(skip)]
+    recipients: BTreeSet<String>,
+}
+
+impl Bucket {
+    fn add_receipt(&mut self, receipt: &Receipt) {
+        self.transfers += receipt.coalesced.len().max(1) as u64;
+        match &receipt.mint {
+            None => self.gross_volume = self.gross_volume.saturating_add(receipt.amount),
+            Some(mint) => {
+                let volume = self.token_volume.entry(mint.clone()).or_default();
+                *volume = volume.saturating_add(receipt.amount);
+            }
+        }
+        self.net_volume = self.gross_volume.saturating_sub(self.fees_collected);
+        self.l2_fees = self
+            .l2_fees
+            .saturating_add(receipt.actual_fee.unwrap_or(receipt.l2_fee));
+        self.recipients.insert(receipt.to.clone());
+        self.unique_recipients = self.recipients.len();
+    }
+
+    fn add_warmup(&mut self, cost: &WarmupCost) {
+        self.warmup_fees = self.warmup_fees.saturating_add(cost.fee);
+        self.l2_fees = self.l2_fees.saturating_add(cost.fee);
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub source: String,
+    pub granularity: Granularity,
+    /// Start of the reported range, when limited by `--last` or `--from`
+    pub since: Option<u64>,
+    /// End of the reported range (exclusive), when limited by `--to`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub until: Option<u64>,
+    pub buckets: Vec<Bucket>,
+    pub totals: Bucket,
+}
+
+impl Report {
+    /// Aggregates the receipts and dead letters in `store` recorded at or after
+    /// `since` and before `until`, together with the receipts compacted into
+    /// `archive`
+    pub fn build(
+        store: &StateStore,
+        archive: &Archive,
+        source: &str,
+        granularity: Granularity,
+        since: Option<u64>,
+        until: Option<u64>,
+    ) -> Result<Self> {
+        let in_range =
+            |at: u64| since.is_none_or(|since| at >= since) && until.is_none_or(|until| at < until);
+        let bucket_start = |at: u64| at - at % granularity.seconds();
+        let mut buckets: BTreeMap<u64, Bucket> = BTreeMap::new();
+        let mut totals = Bucket {
+            start: since.unwrap_or_default(),
+            ..Bucket::default()
+        };
+
+        // 中断的压缩可能让回执同时留在归档和状态中，按 nonce 去重
+        let mut receipts: BTreeMap<u64, Receipt> = archive
+            .receipts(store, since)?
+            .into_iter()
+            .map(|receipt| (receipt.nonce, receipt))
+            .collect();
+        receipts.extend(
+            Receipt::all(store)?
+                .into_iter()
+                .map(|receipt| (receipt.nonce, receipt)),
+        );
+        for receipt in receipts.into_values() {
+            // 跳过的未知类型或停用 mint 的消息、过期、取消或 L2 已处理的转账和运维关闭的死信没有在 L2 上转账
+            if receipt.dust.is_some() && in_range(receipt.relayed_at) {
+                let start = bucket_start(receipt.relayed_at);
+                buckets
+                    .entry(start)
+                    .or_insert_with(|| Bucket {
+                        start,
+                        ..Bucket::default()
+                    })
+                    .dust += 1;
+                totals.dust += 1;
+            }
+            if receipt.skipped_message_type.is_some()
+                || receipt.dust.is_some()
+                || receipt.skipped_mint.is_some()
+                || receipt.expired_at.is_some()
+                || receipt.cancelled.is_some()
+                || receipt.cancelled_on_l1.is_some()
+                || receipt.processed_on_l2.is_some()
+                || receipt.resolved_as.is_some()
+                || !in_range(receipt.relayed_at)
+            {
+                continue;
+            }
+            let start = bucket_start(receipt.relayed_at);
+            buckets
+                .entry(start)
+                .or_insert_with(|| Bucket {
+                    start,
+                    ..Bucket::default()
+                })
+                .add_receipt(&receipt);
+            totals.add_receipt(&receipt);
+        }
+
+        let warmups: Vec<WarmupCost> = store.get(WARMUP_COSTS_KEY)?.unwrap_or_default();
+        for cost in warmups.iter().filter(|cost| in_range(cost.confirmed_at)) {
+            let start = bucket_start(cost.confirmed_at);
+            buckets
+                .entry(start)
+                .or_insert_with(|| Bucket {
+                    start,
+                    ..Bucket::default()
+                })
+                .add_warmup(cost);
+            totals.add_warmup(cost);
+        }
+
+        let dead_letters: BTreeMap<u64, DeadLetter> =
+            store.get(DEAD_LETTERS_KEY)?.unwrap_or_default();
+        for letter in dead_letters.values() {
+            if !in_range(letter.recorded_at) {
+                continue;
+            }
+            let start = bucket_start(letter.recorded_at);
+            buckets
+                .entry(start)
+                .or_insert_with(|| Bucket {
+                    start,
+                    ..Bucket::default()
+                })
+                .failures += 1;
+            totals.failures += 1;
+        }
+
+        if totals.start == 0 {
+            totals.start = buckets.keys().next().copied().unwrap_or_default();
+        }
+        Ok(Self {
+            source: source.to_string(),
+            granularity,
+            since,
+            until,
+            buckets: buckets.into_values().collect(),
+            totals,
+        })
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn to_table(&self) -> String {
+        let mut table = String::new();
+        let _ = writeln!(
+            table,
+            "Source {} by {:?}{}{}",
+            self.source,
+            self.granularity,
+            self.since
+                .map(|since| format!(" since {}", format_time(since, Granularity::Hour)))
+                .unwrap_or_default(),
+            self.until
+                .map(|until| format!(" until {}", format_time(until, Granularity::Hour)))
+                .unwrap_or_default()
+        );
+        let _ = writeln!(
+            table,
+            "{:<16} {:>9} {:>20} {:>14} {:>20} {:>12} {:>8} {:>6} {:>10}",
+            "period",
+            "transfers",
+            "gross",
+            "fees",
+            "net",
+            "l2 fees",
+            "failures",
+            "dust",
+            "recipients"
+        );
+        let rows = self
+            .buckets
+            .iter()
+            .map(|bucket| (format_time(bucket.start, self.granularity), bucket))
+            .chain(std::iter::once(("total".to_string(), &self.totals)));
+        for (period, bucket) in rows {
+            let _ = writeln!(
+                table,
+                "{:<16} {:>9} {:>20} {:>14} {:>20} {:>12} {:>8} {:>6} {:>10}",
+                period,
+                bucket.transfers,
+                bucket.gross_volume,
+                bucket.fees_collected,
+                bucket.net_volume,
+                bucket.l2_fees,
+                bucket.failures,
+                bucket.dust,
+                bucket.unique_recipients
+            );
+        }
+        for (mint, volume) in &self.totals.token_volume {
+            let _ = writeln!(table, "total of mint {}: {} base units", mint, volume);
+        }
+        table
+    }
+}
+
+/// `2024-03-01` (day) or `2024-03-01 14:00` (hour), in UTC
+fn format_time(unix: u64, granularity: Granularity) -> String {
+    let days = (unix / 86_400) as i64;
+    let (year, month, day) = civil_from_days(days);
+    match granularity {
+        Granularity::Day => format!("{:04}-{:02}-{:02}", year, month, day),
+        Granularity::Hour => format!(
+            "{:04}-{:02}-{:02} {:02}:00",
+            year,
+            month,
+            day,
+            unix % 86_400 / 3_600
+        ),
+    }
+}
+
+/// Gregorian date of the day `days` after 1970-01-01
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receipt(nonce: u64, amount: u64, mint: Option<&str>) -> Receipt {
+        serde_json::from_value(serde_json::json!({
+            "nonce": nonce,
+            "l1_slot": 1,
+            "amount": amount,
+            "to": "recipient",
+            "signature": format!("signature-{}", nonce),
+            "relayed_at": 100,
+            "mint": mint,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn token_volume_is_kept_apart_from_lamports_per_mint() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = StateStore::open(dir.path()).unwrap();
+        for receipt in [
+            receipt(0, 1_000, None),
+            receipt(1, 7, Some("mint-a")),
+            receipt(2, u64::MAX, Some("mint-b")),
+            receipt(3, 5, Some("mint-b")),
+            receipt(4, 2_000, None),
+        ] {
+            receipt.save(&store).unwrap();
+        }
+        let archive = Archive::new(dir.path().join("archive"));
+        let report =
+            Report::build(&store, &archive, "default", Granularity::Day, None, None).unwrap();
+
+        assert_eq!(report.totals.transfers, 5);
+        assert_eq!(report.totals.gross_volume, 3_000);
+        assert_eq!(report.totals.net_volume, 3_000);
+        assert_eq!(
+            report.totals.token_volume,
+            BTreeMap::from([("mint-a".to_string(), 7), ("mint-b".to_string(), u64::MAX)])
+        );
+        assert!(report
+            .to_table()
+            .contains("total of mint mint-a: 7 base units"));
+    }
+
+    #[test]
+    fn spans_parse_and_non_ascii_units_are_refused() {
+        assert_eq!(parse_span("30d"), Ok(Duration::from_secs(30 * 86_400)));
+        assert_eq!(parse_span("12h"), Ok(Duration::from_secs(12 * 3_600)));
+        assert_eq!(parse_span("90m"), Ok(Duration::from_secs(90 * 60)));
+        for span in ["7日", "3µ", "µ", "", "d", "1.5h", "30s"] {
+            assert!(parse_span(span).is_err(), "{:?}", span);
+        }
+    }
+}
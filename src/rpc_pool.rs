@@ -0,0 +1,359 @@
+//! RPC endpoint failover.
+//! `l1_urls` and `l2_urls` list further endpoints of each cluster, tried in
+//! order after `l1_url` and `l2_url`. The pool is an `RpcSender`, so the
+//! relayer's single `RpcClient` per cluster sends every request through it:
+//! a request whose endpoint cannot be reached, answers with an HTTP error or
+//! reports itself unhealthy (`-32005`) is retried on the next endpoint, and
+//! the failed one is skipped for `rpc_failover_cooldown_secs`. After the
+//! cooldown it is probed with `getHealth` before it gets requests again, so
+//! the first endpoint takes the traffic back once it recovers. Any other
+//! answer, including a node behind `minContextSlot`, is returned as is.
+//!
+//! With `rpc_round_robin_reads`, reads rotate over the healthy endpoints;
+//! transactions always go to the first healthy one. Endpoints are named by
+//! their config field (`l2_urls[0]`), never by URL.
+//!
+//! `run` builds one pool per cluster and endpoint list: the sources of a
+//! bridge, and bridges reading the same endpoints, share its connections and
+//! failover state, each through its own `RpcClient` at its own commitment.
+
+// `ClientError` is dictated by the `RpcSender` interface
+#![allow(clippy::result_large_err)]
+
+use crate::{config::RelayerConfig, secrets::SecretUrl};
+use async_trait::async_trait;
+use serde_json::Value;
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind, Result as ClientResult},
+    nonblocking::rpc_client::RpcClient,
+    rpc_client::RpcClientConfig,
+    rpc_custom_error::JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY,
+    rpc_request::{RpcError, RpcRequest},
+    rpc_sender::{RpcSender, RpcTransportStats},
+};
+use solana_sdk::commitment_config::CommitmentConfig;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::time::Instant;
+
+/// One endpoint of a pool
+struct Endpoint {
+    /// Config field, e.g. `l1_urls[1]`
+    name: String,
+    sender: Box<dyn RpcSender + Send + Sync>,
+    /// Skipped until then after a failure
+    down_until: Mutex<Option<Instant>>,
+}
+
+/// Endpoints of one cluster, failed over in order
+pub struct RpcPool {
+    /// `L1` or `L2`, for the log lines
+    cluster: String,
+    endpoints: Vec<Endpoint>,
+    round_robin: bool,
+    cooldown: Duration,
+    next: AtomicUsize,
+}
+
+impl RpcPool {
+    pub fn new(cluster: &str, round_robin: bool, cooldown: Duration) -> Self {
+        Self {
+            cluster: cluster.to_string(),
+            endpoints: Vec::new(),
+            round_robin,
+            cooldown,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Adds an endpoint after the ones already added
+    pub fn with_endpoint(
+        mut self,
+        name: impl Into<String>,
+        sender: impl RpcSender + Send + Sync + 'static,
+    ) -> Self {
+        self.endpoints.push(Endpoint {
+            name: name.into(),
+            sender: Box::new(sender),
+            down_until: Mutex::new(None),
+        });
+        self
+    }
+
+    /// Pool of the HTTP endpoints `urls`, named by their config field
+    pub fn http<'a>(
+        cluster: &str,
+        urls: impl IntoIterator<Item = (String, &'a SecretUrl)>,
+        round_robin: bool,
+        cooldown: Duration,
+    ) -> Self {
+        urls.into_iter().fold(
+            Self::new(cluster, round_robin, cooldown),
+            |pool, (name, url)| {
+                let endpoint = HttpEndpoint(RpcClient::new(url.expose().to_string()));
+                #[cfg(feature = "chaos")]
+                let endpoint = crate::chaos::ChaosSender::new(name.clone(), endpoint);
+                pool.with_endpoint(name, endpoint)
+            },
+        )
+    }
+
+    /// An `RpcClient` sending through the pool
+    pub fn client(self, commitment: CommitmentConfig) -> RpcClient {
+        RpcClient::new_sender(self, RpcClientConfig::with_commitment(commitment))
+    }
+
+    /// Endpoint indexes in the order `request` tries them
+    fn order(&self, request: RpcRequest) -> Vec<usize> {
+        let count = self.endpoints.len();
+        let start = match request {
+            RpcRequest::SendTransaction | RpcRequest::RequestAirdrop => 0,
+            _ if self.round_robin => self.next.fetch_add(1, Ordering::Relaxed) % count,
+            _ => 0,
+        };
+        (0..count).map(|offset| (start + offset) % count).collect()
+    }
+
+    /// Whether `index` may take requests; an endpoint past its cooldown is
+    /// probed with `getHealth` first
+    async fn available(&self, index: usize) -> bool {
+        let endpoint = &self.endpoints[index];
+        let Some(down_until) = *endpoint.down_until.lock().unwrap() else {
+            return true;
+        };
+        if Instant::now() < down_until {
+            return false;
+        }
+        match endpoint
+            .sender
+            .send(RpcRequest::GetHealth, Value::Null)
+            .await
+        {
+            Ok(_) => {
+                *endpoint.down_until.lock().unwrap() = None;
+                tracing::info!(
+                    "{} endpoint {} is healthy again",
+                    self.cluster,
+                    endpoint.name
+                );
+                tracing::info!(cluster = %self.cluster, endpoint = %endpoint.name, "rpc endpoint recovered");
+                true
+            }
+            Err(_) => {
+                *endpoint.down_until.lock().unwrap() = Some(Instant::now() + self.cooldown);
+                false
+            }
+        }
+    }
+
+    fn mark_down(&self, index: usize, error: &ClientError) {
+        let endpoint = &self.endpoints[index];
+        *endpoint.down_until.lock().unwrap() = Some(Instant::now() + self.cooldown);
+        tracing::warn!(
+            "{} endpoint {} failed, skipped for {}s: {}",
+            self.cluster,
+            endpoint.name,
+            self.cooldown.as_secs(),
+            error
+        );
+        tracing::warn!(
+            cluster = %self.cluster,
+            endpoint = %endpoint.name,
+            error = %error,
+            "rpc endpoint failed over"
+        );
+    }
+}
+
+/// An HTTP endpoint; solana-client keeps its `HttpSender` private, so
+/// requests go through a plain client of the URL
+struct HttpEndpoint(RpcClient);
+
+#[async_trait]
+impl RpcSender for HttpEndpoint {
+    async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        self.0.send(request, params).await
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        self.0.get_transport_stats()
+    }
+
+    fn url(&self) -> String {
+        self.0.url()
+    }
+}
+
+/// A pool shared by several clients
+struct SharedPool(Arc<RpcPool>);
+
+#[async_trait]
+impl RpcSender for SharedPool {
+    async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        self.0.send(request, params).await
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        self.0.get_transport_stats()
+    }
+
+    fn url(&self) -> String {
+        self.0.url()
+    }
+}
+
+/// Cluster, endpoint names and URLs, round robin and cooldown of a pool
+type PoolKey = (String, Vec<(String, String)>, bool, Duration);
+
+/// Pools of the relayers of one process, keyed by their endpoints and
+/// failover settings
+#[derive(Default)]
+pub struct SharedPools {
+    pools: Mutex<HashMap<PoolKey, Arc<RpcPool>>>,
+}
+
+impl SharedPools {
+    /// Client of the watched chain's endpoints, sharing their pool
+    pub fn source_client(&self, config: &RelayerConfig) -> RpcClient {
+        let cluster = config.direction.source_chain();
+        self.shared(config, cluster, config.source_endpoints())
+            .client(config.l1_commitment.config())
+    }
+
+    /// Client of the endpoints transactions are sent to, sharing their pool
+    pub fn destination_client(&self, config: &RelayerConfig) -> RpcClient {
+        let cluster = config.direction.destination_chain();
+        self.shared(config, cluster, config.destination_endpoints())
+            .client(config.l2_commitment.config())
+    }
+
+    /// The pool of `endpoints`, built on first use
+    fn shared(
+        &self,
+        config: &RelayerConfig,
+        cluster: &str,
+        endpoints: Vec<(String, &SecretUrl)>,
+    ) -> SharedPool {
+        let cooldown = Duration::from_secs(config.rpc_failover_cooldown_secs);
+        let key = (
+            cluster.to_string(),
+            endpoints
+                .iter()
+                .map(|(name, url)| (name.clone(), url.expose().to_string()))
+                .collect(),
+            config.rpc_round_robin_reads,
+            cooldown,
+        );
+        let mut pools = self.pools.lock().unwrap();
+        let pool = pools
+            .entry(key)
+            .or_insert_with(|| Arc::new(pool(config, cluster, endpoints)));
+        SharedPool(pool.clone())
+    }
+}
+
+impl SharedPool {
+    /// An `RpcClient` sending through the shared pool
+    fn client(self, commitment: CommitmentConfig) -> RpcClient {
+        RpcClient::new_sender(self, RpcClientConfig::with_commitment(commitment))
+    }
+}
+
+/// Client of the watched chain's endpoints, `source_url` first
+pub fn source_client(config: &RelayerConfig) -> RpcClient {
+    let cluster = config.direction.source_chain();
+    pool(config, cluster, config.source_endpoints()).client(config.l1_commitment.config())
+}
+
+/// Client of the endpoints transactions are sent to, `destination_url` first
+pub fn destination_client(config: &RelayerConfig) -> RpcClient {
+    let cluster = config.direction.destination_chain();
+    pool(config, cluster, config.destination_endpoints()).client(config.l2_commitment.config())
+}
+
+/// Pool of `endpoints` with the failover settings of `config`
+pub fn pool(
+    config: &RelayerConfig,
+    cluster: &str,
+    endpoints: Vec<(String, &SecretUrl)>,
+) -> RpcPool {
+    RpcPool::http(
+        cluster,
+        endpoints,
+        config.rpc_round_robin_reads,
+        Duration::from_secs(config.rpc_failover_cooldown_secs),
+    )
+}
+
+/// Whether another endpoint may answer where this one failed
+fn should_fail_over(error: &ClientError) -> bool {
+    match error.kind() {
+        ClientErrorKind::Io(_) | ClientErrorKind::Reqwest(_) => true,
+        ClientErrorKind::RpcError(RpcError::RpcResponseError { code, .. }) => {
+            *code == JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY
+        }
+        _ => false,
+    }
+}
+
+#[async_trait]
+impl RpcSender for RpcPool {
+    async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        let order = self.order(request);
+        let mut available = Vec::with_capacity(order.len());
+        for index in order.iter().copied() {
+            if self.available(index).await {
+                available.push(index);
+            }
+        }
+        // 全部不可用时仍按顺序尝试，而不是直接失败
+        if available.is_empty() {
+            available = order;
+        }
+
+        let mut last_error = None;
+        for index in available {
+            match self.endpoints[index]
+                .sender
+                .send(request, params.clone())
+                .await
+            {
+                Err(e) if should_fail_over(&e) => {
+                    if self.endpoints.len() > 1 {
+                        self.mark_down(index, &e);
+                    }
+                    last_error = Some(e);
+                }
+                result => return result,
+            }
+        }
+        Err(last_error
+            .unwrap_or_else(|| ClientErrorKind::Custom("no RPC endpoints".to_string()).into()))
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        self.endpoints
+            .iter()
+            .map(|endpoint| endpoint.sender.get_transport_stats())
+            .fold(RpcTransportStats::default(), |total, stats| {
+                RpcTransportStats {
+                    request_count: total.request_count + stats.request_count,
+                    elapsed_time: total.elapsed_time + stats.elapsed_time,
+                    rate_limited_time: total.rate_limited_time + stats.rate_limited_time,
+                }
+            })
+    }
+
+    fn url(&self) -> String {
+        self.endpoints
+            .first()
+            .map(|endpoint| endpoint.sender.url())
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,196 @@
+//! Autoscaling signal.
+//! With `scale_target_drain_secs` set, every `scale_interval_secs` the
+//! relayer computes a scaling signal for an external scaler from the pending
+//! backlog (queued and spilled nonces) and the processing rate, an
+//! exponential moving average of the transfers relayed per second:
+//!
+//! - estimated drain time: backlog / rate
+//! - recommended replicas: backlog / (rate × target drain time), rounded up
+//!   and between 1 and `scale_max_replicas`, assuming each replica relays as
+//!   fast as this one; the maximum while there is a backlog but no measured
+//!   rate
+//!
+//! The signal is exported as metrics, served as JSON by the admin API's
+//! `GET /v1/scale` and, with `scale_webhook_url` set, POSTed as JSON whenever
+//! the recommendation changes.
+
+use crate::{webhooks, Relayer};
+use anyhow::Result;
+use serde::Serialize;
+use std::{fmt, sync::Mutex, time::Duration};
+
+/// Weight of the newest rate sample in the moving average
+const RATE_SMOOTHING: f64 = 0.3;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScaleSignal {
+    pub source: String,
+    pub at: u64,
+    /// Pending nonces, queued and spilled
+    pub backlog: u64,
+    /// Moving average of transfers relayed per second
+    pub rate_per_sec: f64,
+    /// Seconds to drain the backlog at `rate_per_sec`; none without a rate
+    pub drain_secs: Option<u64>,
+    pub recommended_replicas: u32,
+    pub target_drain_secs: u64,
+    pub max_replicas: u32,
+}
+
+impl fmt::Display for ScaleSignal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let drain = self
+            .drain_secs
+            .map_or("unknown".to_string(), |secs| format!("{}s", secs));
+        write!(
+            f,
+            "Scaling signal: {} replica(s) recommended for a backlog of {} at {:.2} transfers/s (drain {}, target {}s)",
+            self.recommended_replicas, self.backlog, self.rate_per_sec, drain, self.target_drain_secs
+        )
+    }
+}
+
+/// Replicas draining `backlog` within `target_secs` at `rate` transfers per
+/// second each
+fn recommend(backlog: u64, rate: f64, target_secs: u64, max_replicas: u32) -> u32 {
+    if backlog == 0 {
+        return 1;
+    }
+    if rate <= 0.0 {
+        return max_replicas;
+    }
+    let needed = (backlog as f64 / (rate * target_secs.max(1) as f64)).ceil();
+    (needed as u32).clamp(1, max_replicas.max(1))
+}
+
+#[derive(Default)]
+struct Samples {
+    rate: Option<f64>,
+    /// Relayed-transfers counter and time at the last sample
+    last: Option<(u64, u64)>,
+    recommended: Option<u32>,
+    /// The last signal computed
+    latest: Option<ScaleSignal>,
+}
+
+pub struct Scaler {
+    target_drain_secs: u64,
+    max_replicas: u32,
+    interval: Duration,
+    webhook_url: Option<String>,
+    samples: Mutex<Samples>,
+}
+
+impl Scaler {
+    pub fn new(
+        target_drain_secs: u64,
+        max_replicas: u32,
+        interval: Duration,
+        webhook_url: Option<String>,
+    ) -> Self {
+        Self {
+            target_drain_secs,
+            max_replicas: max_replicas.max(1),
+            interval,
+            webhook_url,
+            samples: Mutex::new(Samples::default()),
+        }
+    }
+
+    /// Folds the relayed-transfers counter `relayed` at `now` into the rate
+    /// and returns the signal, with whether the recommendation changed; none
+    /// until a first rate sample is taken
+    fn update(
+        &self,
+        source: &str,
+        backlog: u64,
+        relayed: u64,
+        now: u64,
+    ) -> Option<(ScaleSignal, bool)> {
+        let mut samples = self.samples.lock().unwrap();
+        let (last_relayed, last_at) = samples.last.unwrap_or((relayed, now));
+        let elapsed = now.saturating_sub(last_at);
+        if elapsed > 0 {
+            let sample = relayed.saturating_sub(last_relayed) as f64 / elapsed as f64;
+            samples.rate = Some(samples.rate.map_or(sample, |rate| {
+                RATE_SMOOTHING * sample + (1.0 - RATE_SMOOTHING) * rate
+            }));
+        }
+        if elapsed > 0 || samples.last.is_none() {
+            samples.last = Some((relayed, now));
+        }
+        // 第一次只记录基准，避免启动时没有速率就建议最大副本数
+        let rate = samples.rate?;
+        let drain_secs = if backlog == 0 {
+            Some(0)
+        } else if rate > 0.0 {
+            Some((backlog as f64 / rate).ceil() as u64)
+        } else {
+            None
+        };
+        let recommended = recommend(backlog, rate, self.target_drain_secs, self.max_replicas);
+        let changed = samples.recommended != Some(recommended);
+        samples.recommended = Some(recommended);
+        let signal = ScaleSignal {
+            source: source.to_string(),
+            at: now,
+            backlog,
+            rate_per_sec: rate,
+            drain_secs,
+            recommended_replicas: recommended,
+            target_drain_secs: self.target_drain_secs,
+            max_replicas: self.max_replicas,
+        };
+        samples.latest = Some(signal.clone());
+        Some((signal, changed))
+    }
+
+    /// The last signal computed, none before the first rate sample
+    #[cfg(feature = "admin")]
+    pub fn latest(&self) -> Option<ScaleSignal> {
+        self.samples.lock().unwrap().latest.clone()
+    }
+
+    /// Posts `signal` to the autoscaler webhook, if one is configured
+    async fn notify(&self, signal: &ScaleSignal) {
+        let Some(url) = &self.webhook_url else {
+            return;
+        };
+        webhooks::post_json(url, signal, "post the scaling signal").await;
+    }
+}
+
+impl Relayer {
+    /// Publishes the scaling signal on schedule; pends forever when no
+    /// target drain time is configured
+    pub(crate) async fn publish_scaling(&self) -> Result<()> {
+        let Some(scaler) = &self.scaler else {
+            return futures::future::pending().await;
+        };
+        loop {
+            let (queued, spilled) = self.queue.depth();
+            let update = scaler.update(
+                &self.source_id,
+                queued + spilled,
+                self.metrics.relayed_total.get(),
+                self.clock.unix_timestamp(),
+            );
+            if let Some((signal, changed)) = update {
+                self.metrics
+                    .processing_rate
+                    .set((signal.rate_per_sec * 60.0).round() as u64);
+                self.metrics
+                    .estimated_drain_seconds
+                    .set(signal.drain_secs.unwrap_or_default());
+                self.metrics
+                    .recommended_replicas
+                    .set(signal.recommended_replicas.into());
+                if changed {
+                    tracing::info!("{}", signal);
+                    scaler.notify(&signal).await;
+                }
+            }
+            self.clock.sleep(scaler.interval).await;
+        }
+    }
+}
@@ -0,0 +1,332 @@
+//! Environment interpolation and secret references in config values.
+//! String values may embed `${VAR}` (replaced by the environment variable,
+//! `$$` for a literal `$`) or consist of `file:/path` (replaced by the file's
+//! contents). Resolved values are remembered so they can be redacted from
+//! any output of the config.
+//!
+//! Fields that hold secrets whatever their source are typed `Redacted<T>`
+//! (shown as a placeholder) or `SecretUrl` (shown as scheme and host only), so
+//! `Debug`, `Display` and serialization of the config never print them; code
+//! that needs the value calls `expose`.
+
+use anyhow::Result;
+use config::{Value, ValueKind};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{env, fmt, fs};
+
+const REDACTED: &str = "<redacted>";
+
+/// Values resolved from the environment or secret files
+#[derive(Debug, Clone, Default)]
+pub struct Secrets {
+    values: Vec<String>,
+}
+
+impl Secrets {
+    /// Resolves every string inside the config value stored under `key`
+    pub fn resolve(&mut self, key: &str, value: &mut Value) -> Result<()> {
+        match &mut value.kind {
+            ValueKind::String(text) => *text = self.resolve_str(key, text)?,
+            ValueKind::Table(table) => {
+                for (name, value) in table.iter_mut() {
+                    self.resolve(&format!("{}.{}", key, name), value)?;
+                }
+            }
+            ValueKind::Array(items) => {
+                for (index, value) in items.iter_mut().enumerate() {
+                    self.resolve(&format!("{}[{}]", key, index), value)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn resolve_str(&mut self, key: &str, text: &str) -> Result<String> {
+        if let Some(path) = text.strip_prefix("file:") {
+            let secret = fs::read_to_string(path).map_err(|e| {
+                anyhow::anyhow!("Failed to read secret file {} for {}: {}", path, key, e)
+            })?;
+            let secret = secret.trim_end_matches(['\n', '\r']).to_string();
+            self.remember(&secret);
+            return Ok(secret);
+        }
+
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find('$') {
+            out.push_str(&rest[..start]);
+            rest = &rest[start + 1..];
+            if let Some(after) = rest.strip_prefix('$') {
+                out.push('$');
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix('{') {
+                let end = after.find('}').ok_or_else(|| {
+                    anyhow::anyhow!("Unterminated ${{...}} in the value of {}", key)
+                })?;
+                let name = &after[..end];
+                let value = env::var(name).map_err(|_| {
+                    anyhow::anyhow!(
+                        "Environment variable {} referenced by {} is not set",
+                        name,
+                        key
+                    )
+                })?;
+                self.remember(&value);
+                out.push_str(&value);
+                rest = &after[end + 1..];
+            } else {
+                out.push('$');
+            }
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+
+    fn remember(&mut self, secret: &str) {
+        if !secret.is_empty() && !self.values.iter().any(|value| value == secret) {
+            self.values.push(secret.to_string());
+        }
+    }
+
+    /// Also redacts the values `other` resolved
+    pub fn extend(&mut self, other: &Secrets) {
+        for value in &other.values {
+            self.remember(value);
+        }
+    }
+
+    /// `text` with every resolved value replaced by a placeholder
+    pub fn redact(&self, text: &str) -> String {
+        // 先替换较长的值，避免部分匹配留下残片
+        let mut values: Vec<_> = self.values.iter().collect();
+        values.sort_by_key(|value| std::cmp::Reverse(value.len()));
+        values.into_iter().fold(text.to_string(), |text, value| {
+            text.replace(value, REDACTED)
+        })
+    }
+}
+
+/// Config value that is never printed; `expose` returns it
+#[derive(Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct Redacted<T>(T);
+
+impl<T> Redacted<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> fmt::Debug for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl<T> fmt::Display for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl<T> Serialize for Redacted<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(REDACTED)
+    }
+}
+
+/// Endpoint URL that may carry an API key in its credentials, path or query.
+/// It is shown as its scheme and host; `expose` returns the full URL.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SecretUrl(String);
+
+impl SecretUrl {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self(url.into())
+    }
+
+    /// Checks that the URL is http(s) or ws(s) and has a host; the error
+    /// never quotes the URL
+    pub fn validate(&self) -> Result<()> {
+        let (scheme, rest) = self
+            .0
+            .split_once("://")
+            .ok_or_else(|| anyhow::anyhow!("missing scheme"))?;
+        if !matches!(scheme, "http" | "https" | "ws" | "wss") {
+            return Err(anyhow::anyhow!(
+                "scheme {} is not http, https, ws or wss",
+                scheme
+            ));
+        }
+        let authority = &rest[..rest.find(['/', '?', '#']).unwrap_or(rest.len())];
+        let host = authority
+            .rsplit_once('@')
+            .map_or(authority, |(_, host)| host);
+        if host.is_empty() {
+            return Err(anyhow::anyhow!("missing host"));
+        }
+        Ok(())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    /// The WebSocket endpoint of an RPC URL, as the Solana CLI derives it:
+    /// `ws`/`wss` for `http`/`https`, and the next port when one is given
+    pub fn websocket(&self) -> Self {
+        let Some((scheme, rest)) = self.0.split_once("://") else {
+            return self.clone();
+        };
+        let scheme = match scheme {
+            "http" => "ws",
+            "https" => "wss",
+            other => other,
+        };
+        let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+        let (authority, tail) = rest.split_at(end);
+        let authority = match authority
+            .rsplit_once(':')
+            .and_then(|(host, port)| Some((host, port.parse::<u16>().ok()?)))
+        {
+            Some((host, port)) => format!("{}:{}", host, port.saturating_add(1)),
+            None => authority.to_string(),
+        };
+        Self(format!("{}://{}{}", scheme, authority, tail))
+    }
+
+    /// `scheme://host`, with a placeholder for credentials and for any path or query
+    fn masked(&self) -> String {
+        let (scheme, rest) = self.0.split_once("://").unwrap_or(("", &self.0));
+        let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+        let (authority, tail) = rest.split_at(end);
+        let host = match authority.rsplit_once('@') {
+            Some((_, host)) => format!("{}@{}", REDACTED, host),
+            None => authority.to_string(),
+        };
+        let tail = if tail.is_empty() || tail == "/" {
+            tail.to_string()
+        } else {
+            format!("/{}", REDACTED)
+        };
+        format!("{}://{}{}", scheme, host, tail)
+    }
+}
+
+impl fmt::Debug for SecretUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.masked())
+    }
+}
+
+impl fmt::Display for SecretUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.masked())
+    }
+}
+
+impl Serialize for SecretUrl {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.masked())
+    }
+}
+
+impl<'de> Deserialize<'de> for SecretUrl {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn resolve(secrets: &mut Secrets, text: &str) -> Result<String> {
+        let mut value = Value::new(None, ValueKind::String(text.to_string()));
+        secrets.resolve("key", &mut value)?;
+        Ok(value.into_string()?)
+    }
+
+    #[test]
+    fn environment_references_are_resolved_and_redacted() {
+        env::set_var("SECRETS_TEST_TOKEN", "s3cr3t");
+        let mut secrets = Secrets::default();
+        let url = resolve(&mut secrets, "https://rpc/${SECRETS_TEST_TOKEN}?fee=$$5").unwrap();
+        assert_eq!(url, "https://rpc/s3cr3t?fee=$5");
+        assert_eq!(secrets.redact(&url), "https://rpc/<redacted>?fee=$5");
+        // 不认识的 $ 原样保留
+        assert_eq!(resolve(&mut secrets, "a$b").unwrap(), "a$b");
+
+        let error = resolve(&mut secrets, "${SECRETS_TEST_UNSET}").unwrap_err();
+        assert!(
+            error.to_string().contains("SECRETS_TEST_UNSET"),
+            "{}",
+            error
+        );
+        assert!(resolve(&mut secrets, "${SECRETS_TEST_TOKEN").is_err());
+    }
+
+    #[test]
+    fn secret_files_are_read_without_their_trailing_newline() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "hunter2").unwrap();
+        let mut secrets = Secrets::default();
+        let text = format!("file:{}", file.path().display());
+        assert_eq!(resolve(&mut secrets, &text).unwrap(), "hunter2");
+        assert_eq!(secrets.redact("pw=hunter2"), "pw=<redacted>");
+        assert!(resolve(&mut secrets, "file:/nonexistent/secret").is_err());
+    }
+
+    #[test]
+    fn the_longest_secret_is_redacted_first() {
+        let mut secrets = Secrets::default();
+        secrets.remember("abc");
+        let mut other = Secrets::default();
+        other.remember("abcdef");
+        secrets.extend(&other);
+        assert_eq!(secrets.redact("x abcdef abc"), "x <redacted> <redacted>");
+    }
+
+    #[test]
+    fn redacted_values_are_never_printed() {
+        let token = Redacted::new("api-key".to_string());
+        assert_eq!(format!("{:?} {}", token, token), "<redacted> <redacted>");
+        assert_eq!(serde_json::to_string(&token).unwrap(), "\"<redacted>\"");
+        assert_eq!(token.expose(), "api-key");
+    }
+
+    #[test]
+    fn urls_show_only_their_scheme_and_host() {
+        let url = SecretUrl::new("https://user:pw@rpc.example.com/v1/KEY?x=1");
+        assert_eq!(
+            url.to_string(),
+            "https://<redacted>@rpc.example.com/<redacted>"
+        );
+        assert_eq!(
+            SecretUrl::new("http://localhost:8899/").to_string(),
+            "http://localhost:8899/"
+        );
+        assert_eq!(
+            SecretUrl::new("http://localhost:8899/x")
+                .websocket()
+                .expose(),
+            "ws://localhost:8900/x"
+        );
+        assert_eq!(
+            SecretUrl::new("https://rpc/KEY").websocket().expose(),
+            "wss://rpc/KEY"
+        );
+        url.validate().unwrap();
+        assert!(SecretUrl::new("ftp://host").validate().is_err());
+        assert!(SecretUrl::new("https://user@/path").validate().is_err());
+        // 校验错误不引用 URL
+        let error = SecretUrl::new("KEY123").validate().unwrap_err();
+        assert!(!error.to_string().contains("KEY123"));
+    }
+}
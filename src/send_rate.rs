@@ -0,0 +1,91 @@
+//! L2 send rate limit.
+//! `max_l2_sends_per_second` caps the `sendTransaction` requests made to the
+//! L2 cluster, so catching up on a backlog does not get the relayer
+//! rate-limited by its RPC provider. The limit is a token bucket shared by all
+//! sources of a bridge, holding up to `l2_send_burst` sends; rebroadcasts of a
+//! transaction take a token like its first send. A transaction waiting for a
+//! token keeps its send slot (`send_concurrency`, `max_concurrent_submissions`),
+//! so the wait backs up through the pipeline into the pending queue, which
+//! drains as tokens come back. Nothing is dropped. A config reload (SIGHUP)
+//! changes the limit in place.
+
+use crate::Relayer;
+use std::{
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+struct BucketState {
+    /// Sends let through per second, unlimited when `None`
+    per_second: Option<f64>,
+    burst: f64,
+    /// Tokens left; negative when sends have taken tokens ahead of time
+    tokens: f64,
+    refilled_at: Option<SystemTime>,
+}
+
+pub struct SendRateLimit {
+    state: Mutex<BucketState>,
+}
+
+impl SendRateLimit {
+    /// Bucket letting `per_second` sends through, `burst` at once (unlimited
+    /// when unset or 0)
+    pub fn new(per_second: Option<u32>, burst: u32) -> Self {
+        let burst = burst.max(1) as f64;
+        Self {
+            state: Mutex::new(BucketState {
+                per_second: per_second.filter(|&limit| limit > 0).map(f64::from),
+                burst,
+                tokens: burst,
+                refilled_at: None,
+            }),
+        }
+    }
+
+    /// Replaces the limit, e.g. on a config reload; tokens taken ahead of
+    /// time stay taken
+    pub fn reconfigure(&self, per_second: Option<u32>, burst: u32) {
+        let mut state = self.state.lock().unwrap();
+        state.per_second = per_second.filter(|&limit| limit > 0).map(f64::from);
+        state.burst = burst.max(1) as f64;
+        state.tokens = state.tokens.min(state.burst);
+    }
+
+    /// Takes a token at `now`; returns how long to wait before sending.
+    /// Waiting sends take their tokens ahead of time, so they go out in the
+    /// order they asked
+    fn take(&self, now: SystemTime) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let Some(per_second) = state.per_second else {
+            state.tokens = state.burst;
+            state.refilled_at = None;
+            return Duration::ZERO;
+        };
+        if let Some(refilled_at) = state.refilled_at {
+            let elapsed = now.duration_since(refilled_at).unwrap_or_default();
+            state.tokens = (state.tokens + elapsed.as_secs_f64() * per_second).min(state.burst);
+        }
+        state.refilled_at = Some(now);
+        state.tokens -= 1.0;
+        if state.tokens >= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(-state.tokens / per_second)
+    }
+}
+
+impl Relayer {
+    /// Waits until the L2 send rate limit lets one more transaction through
+    pub(crate) async fn await_send_rate(&self) {
+        let wait = self.send_rate.take(self.clock.now());
+        if wait.is_zero() {
+            return;
+        }
+        tracing::debug!(wait_ms = wait.as_millis() as u64, "send rate limited");
+        self.metrics
+            .send_rate_limited_ms_total
+            .inc_by(wait.as_millis() as u64);
+        self.clock.sleep(wait).await;
+    }
+}
@@ -0,0 +1,298 @@
+//! Shadow comparison between relayer versions.
+//! `shadow-compare` builds, without sending anything, the L2 transaction of
+//! every nonce between the L2 and L1 nonces, as audit mode does, and writes
+//! the plan as JSON lines: per nonce the PDA, amount, recipient, estimated
+//! fee, each instruction's program, accounts and data, and the transaction
+//! fingerprint (`transaction::fingerprint`, independent of signatures and
+//! blockhash). The memo is built with L1 slot 0, since the slot a message is
+//! read at differs between instances, and unroutable recipients are recorded
+//! instead of dead-lettered.
+//!
+//! With `--against`, the plan is compared with one written by another build
+//! (a file, or an `http(s)://` URL serving it) and every divergence is
+//! logged. Nonces only one side planned are listed but are not divergences.
+
+use crate::{
+    decode, pda_recipient::Routing, pipeline::FetchedTransfer, rent, transaction, Relayer,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_sdk::transaction::VersionedTransaction;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{BufWriter, Write},
+    path::Path,
+};
+use tracing::info_span;
+
+/// An instruction of a planned transaction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlannedInstruction {
+    pub program_id: String,
+    /// Account keys, suffixed with ` (signer)` and ` (writable)`
+    pub accounts: Vec<String>,
+    /// Instruction data (hex)
+    pub data: String,
+}
+
+/// The transaction a build intends to send for one nonce
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TransactionPlan {
+    pub nonce: u64,
+    pub pda: String,
+    pub amount: u64,
+    pub recipient: String,
+    /// Network fee estimated by `getFeeForMessage`
+    pub fee: Option<u64>,
+    pub instructions: Vec<PlannedInstruction>,
+    pub fingerprint: String,
+    /// Why no transaction could be built
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl TransactionPlan {
+    fn instructions(transaction: &VersionedTransaction) -> Vec<PlannedInstruction> {
+        let message = &transaction.message;
+        let keys = message.static_account_keys();
+        message
+            .instructions()
+            .iter()
+            .map(|instruction| PlannedInstruction {
+                program_id: keys[instruction.program_id_index as usize].to_string(),
+                accounts: instruction
+                    .accounts
+                    .iter()
+                    .map(|&index| {
+                        let index = index as usize;
+                        // v0 交易中静态账户之后的序号来自 lookup table
+                        let mut account = keys
+                            .get(index)
+                            .map(|key| key.to_string())
+                            .unwrap_or_else(|| format!("lookup table account #{}", index));
+                        if message.is_signer(index) {
+                            account.push_str(" (signer)");
+                        }
+                        if message.is_maybe_writable(index) {
+                            account.push_str(" (writable)");
+                        }
+                        account
+                    })
+                    .collect(),
+                data: decode::hex(&instruction.data),
+            })
+            .collect()
+    }
+}
+
+/// A field on which two plans of the same nonce disagree
+#[derive(Debug, Serialize)]
+pub struct Divergence {
+    pub nonce: u64,
+    pub field: String,
+    pub ours: String,
+    pub theirs: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ShadowReport {
+    /// Nonces both plans contain
+    pub compared: usize,
+    pub only_ours: Vec<u64>,
+    pub only_theirs: Vec<u64>,
+    pub divergences: Vec<Divergence>,
+}
+
+/// Compares `ours` with `theirs` nonce by nonce
+pub fn compare(ours: &[TransactionPlan], theirs: &[TransactionPlan]) -> ShadowReport {
+    let theirs: BTreeMap<u64, &TransactionPlan> =
+        theirs.iter().map(|plan| (plan.nonce, plan)).collect();
+    let mut report = ShadowReport::default();
+    for plan in ours {
+        let Some(other) = theirs.get(&plan.nonce) else {
+            report.only_ours.push(plan.nonce);
+            continue;
+        };
+        report.compared += 1;
+        let mut diverge = |field: String, ours: String, theirs: String| {
+            if ours != theirs {
+                report.divergences.push(Divergence {
+                    nonce: plan.nonce,
+                    field,
+                    ours,
+                    theirs,
+                });
+            }
+        };
+        let describe = |value: &Option<String>| value.clone().unwrap_or_default();
+        diverge(
+            "error".into(),
+            describe(&plan.error),
+            describe(&other.error),
+        );
+        diverge("pda".into(), plan.pda.clone(), other.pda.clone());
+        diverge(
+            "amount".into(),
+            plan.amount.to_string(),
+            other.amount.to_string(),
+        );
+        diverge(
+            "recipient".into(),
+            plan.recipient.clone(),
+            other.recipient.clone(),
+        );
+        diverge(
+            "fee".into(),
+            format!("{:?}", plan.fee),
+            format!("{:?}", other.fee),
+        );
+        diverge(
+            "instructions".into(),
+            plan.instructions.len().to_string(),
+            other.instructions.len().to_string(),
+        );
+        for (index, (ours, theirs)) in plan
+            .instructions
+            .iter()
+            .zip(&other.instructions)
+            .enumerate()
+        {
+            diverge(
+                format!("instructions[{}].program_id", index),
+                ours.program_id.clone(),
+                theirs.program_id.clone(),
+            );
+            diverge(
+                format!("instructions[{}].accounts", index),
+                ours.accounts.join(", "),
+                theirs.accounts.join(", "),
+            );
+            diverge(
+                format!("instructions[{}].data", index),
+                ours.data.clone(),
+                theirs.data.clone(),
+            );
+        }
+        diverge(
+            "fingerprint".into(),
+            plan.fingerprint.clone(),
+            other.fingerprint.clone(),
+        );
+    }
+    let ours: Vec<u64> = ours.iter().map(|plan| plan.nonce).collect();
+    report.only_theirs = theirs
+        .keys()
+        .filter(|nonce| !ours.contains(nonce))
+        .copied()
+        .collect();
+    report
+}
+
+/// Writes `plans` to `path` as JSON lines
+pub fn write_plan(path: &Path, plans: &[TransactionPlan]) -> Result<()> {
+    let file = fs::File::create(path)
+        .map_err(|e| anyhow::anyhow!("Failed to create plan {}: {}", path.display(), e))?;
+    let mut out = BufWriter::new(file);
+    for plan in plans {
+        serde_json::to_writer(&mut out, plan)?;
+        out.write_all(b"\n")?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Reads the plan at `against`, a file or an `http(s)://` URL
+pub async fn load_plan(against: &str) -> Result<Vec<TransactionPlan>> {
+    let text = if against.starts_with("http://") || against.starts_with("https://") {
+        reqwest::get(against)
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| anyhow::anyhow!("Failed to fetch plan: {}", e.without_url()))?
+            .text()
+            .await?
+    } else {
+        fs::read_to_string(against)
+            .map_err(|e| anyhow::anyhow!("Failed to read plan {}: {}", against, e))?
+    };
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            serde_json::from_str(line)
+                .map_err(|e| anyhow::anyhow!("Invalid plan line in {}: {}", against, e))
+        })
+        .collect()
+}
+
+impl Relayer {
+    /// Builds, without sending, the transaction of every nonce between the
+    /// L2 and L1 nonces
+    pub(crate) async fn plan_transactions(&self) -> Result<Vec<TransactionPlan>> {
+        if self.indexer.is_some() || self.message_accounts.is_some() {
+            return Err(anyhow::anyhow!(
+                "shadow-compare only supports the pda message source"
+            ));
+        }
+        let (l1_watched_nonce, l2_nonce_status) = self.read_nonces().await?;
+        tracing::info!(
+            "Planning nonces {}..{} (nothing will be sent)",
+            l2_nonce_status,
+            l1_watched_nonce
+        );
+
+        let mut plans = Vec::new();
+        for nonce in l2_nonce_status..l1_watched_nonce {
+            let (pda, _) = self.pda_manager.find_address(nonce);
+            let mut plan = TransactionPlan {
+                nonce,
+                pda: pda.to_string(),
+                ..TransactionPlan::default()
+            };
+            let built = async {
+                let (amount, to_address, _) = self
+                    .pda_manager
+                    .get_transfer_info(&self.l1_client, &pda)
+                    .await?;
+                plan.amount = amount;
+                plan.recipient = to_address.to_string();
+                // 演练不写死信：无法路由的收款人只记录原因
+                if let Routing::Unroutable(reason) = self.recipient_routing(&to_address).await? {
+                    return Err(anyhow::anyhow!("unroutable recipient: {}", reason));
+                }
+                // 读取 L1 的 slot 因实例而异，memo 中固定为 0
+                self.build_transfer(FetchedTransfer {
+                    nonce,
+                    amount,
+                    to_address,
+                    l1_slot: 0,
+                    message_type: None,
+                    coalesced: Vec::new(),
+                    proof: None,
+                    expires_at: None,
+                    digest: None,
+                    sender: None,
+                    token: None,
+                    dust: Vec::new(),
+                    create_token_account: false,
+                    entry: None,
+                    span: info_span!("shadow_nonce", nonce),
+                })
+                .await
+            }
+            .await;
+            match built {
+                Ok(prepared) => {
+                    plan.fee =
+                        rent::fee_for_message(&self.l2_client, &prepared.transaction.message)
+                            .await
+                            .ok();
+                    plan.instructions = TransactionPlan::instructions(&prepared.transaction);
+                    plan.fingerprint = transaction::fingerprint(&prepared.transaction);
+                }
+                Err(e) => plan.error = Some(e.to_string()),
+            }
+            plans.push(plan);
+        }
+        Ok(plans)
+    }
+}
@@ -0,0 +1,187 @@
+//! Graceful shutdown.
+//! On SIGINT or SIGTERM the monitor stops enqueuing and the submitter stops
+//! taking nonces: a transfer not yet sent stays queued for the next start
+//! (`ShuttingDown`, held rather than failed), while the transaction being
+//! confirmed and the `safe` ones waiting for finality are given up to
+//! `shutdown_timeout_secs` to resolve. The buffered state writes and the
+//! journal backlog are then flushed and the relayer exits cleanly. A
+//! transaction still in flight at the timeout stays recorded and is resolved
+//! by the next start before anything is sent again.
+
+use crate::Relayer;
+use anyhow::Result;
+use std::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use tokio::sync::Notify;
+
+/// Whether a shutdown was requested, and how long draining may take
+pub struct Shutdown {
+    requested: AtomicBool,
+    notify: Notify,
+    /// How long in-flight transactions may take to resolve once requested
+    pub timeout: Duration,
+}
+
+impl Shutdown {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            requested: AtomicBool::new(false),
+            notify: Notify::new(),
+            timeout,
+        }
+    }
+
+    pub fn begin(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    pub fn requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Completes once a shutdown is requested
+    pub async fn wait(&self) {
+        loop {
+            // 先注册等待，避免错过检查期间的请求
+            let notified = self.notify.notified();
+            if self.requested() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// A transfer not sent because the relayer is shutting down; it stays queued
+#[derive(Debug)]
+pub struct ShuttingDown {
+    pub nonce: u64,
+}
+
+impl fmt::Display for ShuttingDown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Shutting down; nonce {} stays queued for the next start",
+            self.nonce
+        )
+    }
+}
+
+impl std::error::Error for ShuttingDown {}
+
+/// Waits for SIGINT or SIGTERM and returns its name
+pub async fn signalled() -> Result<&'static str> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate())
+            .map_err(|e| anyhow::anyhow!("Failed to listen for SIGTERM: {}", e))?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result.map(|()| "SIGINT").map_err(Into::into),
+            _ = terminate.recv() => Ok("SIGTERM"),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await?;
+        Ok("SIGINT")
+    }
+}
+
+impl Relayer {
+    /// Sleeps for `wait`, returning early once a shutdown is requested
+    pub(crate) async fn sleep_unless_stopping(&self, wait: Duration) {
+        tokio::select! {
+            () = self.clock.sleep(wait) => {}
+            () = self.shutdown.wait() => {}
+        }
+    }
+
+    /// Fails before `nonce` is sent once a shutdown is requested
+    pub(crate) fn check_shutdown(&self, nonce: u64) -> Result<()> {
+        if self.shutdown.requested() {
+            return Err(ShuttingDown { nonce }.into());
+        }
+        Ok(())
+    }
+
+    /// Relays until a shutdown is requested, then drains: waits for the
+    /// submitter to finish its batch and for landed transactions to finalize,
+    /// up to the shutdown timeout, and flushes the state
+    pub(crate) async fn relay_until_shutdown(&self) -> Result<()> {
+        // 停止请求之后才开始计时
+        let timed_out = async {
+            self.shutdown.wait().await;
+            tracing::info!(
+                "{}: shutting down, waiting up to {:?} for {} in-flight transaction(s)",
+                self.name(),
+                self.shutdown.timeout,
+                self.in_flight.len()
+            );
+            self.clock.sleep(self.shutdown.timeout).await;
+        };
+        let drained = async {
+            self.submit_pending().await?;
+            self.await_in_flight_landed().await;
+            Ok::<_, anyhow::Error>(())
+        };
+        tokio::select! {
+            biased;
+            () = timed_out => tracing::warn!(
+                "{}: {} transaction(s) still in flight after {:?}; the next start resolves them",
+                self.name(),
+                self.in_flight.len(),
+                self.shutdown.timeout
+            ),
+            result = drained => result?,
+        }
+        self.flush_state();
+        tracing::info!("{}: stopped", self.name());
+        Ok(())
+    }
+
+    /// Waits until no landed transaction is waiting to finalize. Entries that
+    /// never landed are left for the next start to resolve
+    async fn await_in_flight_landed(&self) {
+        loop {
+            let removed = self.in_flight.removed().notified();
+            if self
+                .in_flight
+                .all()
+                .iter()
+                .all(|transfer| transfer.landed_slot.is_none())
+            {
+                return;
+            }
+            removed.await;
+        }
+    }
+
+    /// Writes the state kept in memory while the storage or journal failed
+    fn flush_state(&self) {
+        if let Some(health) = &self.storage {
+            if let Err(e) = health.buffer.flush() {
+                tracing::warn!(
+                    "{}: {} buffered state write(s) were not flushed: {}",
+                    self.name(),
+                    health.buffer.depth().0,
+                    e
+                );
+            }
+        }
+        if let Some(journal) = &self.journal {
+            if let Err(e) = journal.flush_backlog() {
+                tracing::warn!(
+                    "{}: the journal backlog was not flushed: {}",
+                    self.name(),
+                    e
+                );
+            }
+        }
+    }
+}
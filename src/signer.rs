@@ -0,0 +1,183 @@
+//! Relayer signer keys and their rotation.
+//! `wallet_path` and, optionally, `secondary_wallet_path` name the two keys
+//! the relayer may sign with. Which of them is active is recorded in the
+//! state store, so `rotate-key` can promote the other one without editing the
+//! config or restarting: the running relayer picks the change up before its
+//! next batch. Transactions already signed by the previous key are tracked by
+//! signature and still resolve normally. The primary key may also live in a
+//! remote signing service (see `signing`).
+
+use crate::{rent::SYSTEM_ACCOUNT_SIZE, signing::RelayerSigner, state::StateStore, Relayer};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{message::Message, pubkey::Pubkey, signature::Signer};
+use std::{
+    fmt,
+    str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// State key holding the public key of the active signer
+pub const ACTIVE_SIGNER_KEY: &str = "active_signer";
+/// Receipts-store key holding the log of key rotations
+pub const ROTATIONS_KEY: &str = "receipts/rotations";
+
+/// One `rotate-key` run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rotation {
+    pub from: String,
+    pub to: String,
+    pub rotated_at: u64,
+}
+
+/// Primary and optional secondary signer, one of them active
+pub struct Signers {
+    keys: Vec<RelayerSigner>,
+    active: AtomicUsize,
+}
+
+/// Shows public keys only; `Keypair`'s own `Debug` would print the secret key
+impl fmt::Debug for Signers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Signers")
+            .field("active", &self.active().pubkey())
+            .field("standby", &self.standby().map(|key| key.pubkey()))
+            .finish()
+    }
+}
+
+impl Signers {
+    /// Activates the `primary` keypair until `refresh` finds another key
+    /// recorded
+    #[cfg(feature = "testing")]
+    pub fn new(
+        primary: solana_sdk::signature::Keypair,
+        secondary: Option<solana_sdk::signature::Keypair>,
+    ) -> Self {
+        Self::with_signers(
+            Box::new(primary),
+            secondary.map(|key| Box::new(key) as RelayerSigner),
+        )
+    }
+
+    /// Activates `primary` until `refresh` finds another key recorded
+    pub fn with_signers(primary: RelayerSigner, secondary: Option<RelayerSigner>) -> Self {
+        Self {
+            keys: [Some(primary), secondary].into_iter().flatten().collect(),
+            active: AtomicUsize::new(0),
+        }
+    }
+
+    /// The key new transactions are signed with
+    pub fn active(&self) -> &dyn Signer {
+        self.keys[self.active.load(Ordering::Relaxed)].as_ref()
+    }
+
+    /// The configured key that is not active, if any
+    pub fn standby(&self) -> Option<&dyn Signer> {
+        let active = self.active.load(Ordering::Relaxed);
+        self.keys
+            .iter()
+            .enumerate()
+            .find(|(index, _)| *index != active)
+            .map(|(_, key)| key.as_ref() as &dyn Signer)
+    }
+
+    /// Switches to the key recorded in `store`; returns true if it changed
+    pub fn refresh(&self, store: &StateStore) -> Result<bool> {
+        let Some(recorded) = store.get::<String>(ACTIVE_SIGNER_KEY)? else {
+            return Ok(false);
+        };
+        let pubkey = Pubkey::from_str(&recorded)
+            .map_err(|e| anyhow::anyhow!("Invalid active signer {}: {}", recorded, e))?;
+        let index = self
+            .keys
+            .iter()
+            .position(|key| key.pubkey() == pubkey)
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Active signer {} recorded in the state store is neither wallet_path nor secondary_wallet_path",
+                    pubkey
+                )
+            })?;
+        Ok(self.active.swap(index, Ordering::Relaxed) != index)
+    }
+
+    /// Checks that the standby key can take over, then records it as active
+    /// in every store of `stores` and logs the rotation next to its receipts
+    pub async fn rotate(
+        &self,
+        stores: &[StateStore],
+        l2_client: &RpcClient,
+        now: u64,
+    ) -> Result<Rotation> {
+        let from = self.active().pubkey();
+        let to = self
+            .standby()
+            .ok_or_else(|| anyhow::anyhow!("rotate-key requires secondary_wallet_path"))?
+            .pubkey();
+        if to == from {
+            return Err(anyhow::anyhow!(
+                "wallet_path and secondary_wallet_path are the same key {}",
+                to
+            ));
+        }
+
+        // 新 key 必须能支付手续费并保持免租金
+        let balance = l2_client.get_balance(&to).await?;
+        let reserve = l2_client
+            .get_minimum_balance_for_rent_exemption(SYSTEM_ACCOUNT_SIZE)
+            .await?;
+        let blockhash = l2_client.get_latest_blockhash().await?;
+        let fee = l2_client
+            .get_fee_for_message(&Message::new_with_blockhash(&[], Some(&to), &blockhash))
+            .await?;
+        if balance < reserve + fee {
+            return Err(anyhow::anyhow!(
+                "Secondary signer {} has {} lamports on L2, below the {} needed to stay rent-exempt and pay one fee",
+                to,
+                balance,
+                reserve + fee
+            ));
+        }
+
+        let rotation = Rotation {
+            from: from.to_string(),
+            to: to.to_string(),
+            rotated_at: now,
+        };
+        for store in stores {
+            let mut log: Vec<Rotation> = store.get(ROTATIONS_KEY)?.unwrap_or_default();
+            log.push(rotation.clone());
+            store.put(ROTATIONS_KEY, &log)?;
+            store.put(ACTIVE_SIGNER_KEY, &rotation.to)?;
+        }
+        Ok(rotation)
+    }
+}
+
+impl Relayer {
+    /// Picks up a rotation made by `rotate-key` and exports both keys'
+    /// balances; returns the active key's balance
+    pub(crate) async fn refresh_signers(&self) -> Result<u64> {
+        if self.signers.refresh(&self.state)? {
+            tracing::info!(
+                "Signer rotated: new transactions are signed by {}",
+                self.signers.active().pubkey()
+            );
+        }
+        let signer = self.signers.active().pubkey();
+        let active = self.l2_client.get_balance(&signer).await?;
+        self.observe_signer_balance(&signer, active);
+        let (_, reserved) = self
+            .in_flight
+            .reserved(&self.signers.active().pubkey().to_string(), 0);
+        self.metrics.balance_reserved.set(reserved);
+        if let Some(standby) = self.signers.standby() {
+            let standby = self.l2_client.get_balance(&standby.pubkey()).await?;
+            self.metrics.standby_signer_balance.set(standby);
+        }
+        Ok(active)
+    }
+}
@@ -0,0 +1,407 @@
+//! Signer backends.
+//! `signer_backend = "file"` (the default) signs with the keypair file at
+//! `wallet_path`. With `"remote"` the secret key never reaches the relayer:
+//! each message is posted to the signing service at `remote_signer_url`,
+//! which must hold the key of `remote_signer_pubkey`. The request is
+//! `{"pubkey": "<base58>", "message": "<base64>"}`, with
+//! `Authorization: Bearer <remote_signer_token>` when a token is set, and the
+//! service answers `{"signature": "<base58>"}`. A signature that does not
+//! verify against the configured key is refused. `secondary_wallet_path` is
+//! still a keypair file.
+//!
+//! Transactions are built and signed through `&dyn Signer`, so either
+//! backend signs relay, warm-up and proof messages alike.
+//!
+//! A keypair file may also be encrypted by `encrypt-keypair`: a JSON object
+//! holding the public key and the 64-byte keypair sealed with AES-256-GCM-SIV
+//! under a key derived from a passphrase with PBKDF2-HMAC-SHA256. The
+//! passphrase is read from the environment variable named by
+//! `keypair_passphrase_env`, or prompted for once per process on a terminal.
+//! A file asking for fewer than 600,000 or more than 10,000,000 PBKDF2
+//! rounds is refused, and the derived key and decrypted keypair bytes are
+//! zeroed once used.
+
+use crate::{
+    config::RelayerConfig,
+    secrets::{Redacted, SecretUrl},
+};
+use aes_gcm_siv::{
+    aead::{Aead, NewAead},
+    Aes256GcmSiv, Nonce,
+};
+use anyhow::Result;
+use hmac::Hmac;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{read_keypair, Keypair, Signature},
+    signer::{Signer, SignerError},
+};
+use std::{
+    fs,
+    io::{IsTerminal, Write},
+    str::FromStr,
+    sync::OnceLock,
+    time::Duration,
+};
+use zeroize::Zeroizing;
+
+/// PBKDF2 rounds of newly encrypted keypairs, and the fewest accepted
+const KDF_ITERATIONS: u32 = 600_000;
+/// Most PBKDF2 rounds accepted, so a file cannot stall the relayer's startup
+const MAX_KDF_ITERATIONS: u32 = 10_000_000;
+const KDF: &str = "pbkdf2-sha256";
+const CIPHER: &str = "aes-256-gcm-siv";
+
+/// Passphrase prompted for, reused by later reads of the process
+static PROMPTED_PASSPHRASE: OnceLock<Redacted<String>> = OnceLock::new();
+
+/// An encrypted keypair file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptedKeypair {
+    pub version: u8,
+    /// Public key of the sealed keypair, checked once it is opened
+    pub pubkey: String,
+    pub kdf: String,
+    pub iterations: u32,
+    /// Base64
+    pub salt: String,
+    pub cipher: String,
+    /// Base64
+    pub nonce: String,
+    /// Base64 of the sealed 64-byte keypair and its tag
+    pub ciphertext: String,
+}
+
+impl EncryptedKeypair {
+    /// Seals `keypair` under `passphrase`, with a fresh salt and nonce
+    pub fn seal(keypair: &Keypair, passphrase: &str) -> Result<Self> {
+        let mut salt = [0u8; 16];
+        let mut nonce = [0u8; 12];
+        getrandom::getrandom(&mut salt)
+            .and_then(|_| getrandom::getrandom(&mut nonce))
+            .map_err(|e| anyhow::anyhow!("No randomness for the keypair encryption: {}", e))?;
+        let cipher = cipher(passphrase, &salt, KDF_ITERATIONS)?;
+        let ciphertext = cipher
+            .encrypt(
+                &Nonce::from(nonce),
+                Zeroizing::new(keypair.to_bytes()).as_ref(),
+            )
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt the keypair"))?;
+        Ok(Self {
+            version: 1,
+            pubkey: keypair.pubkey().to_string(),
+            kdf: KDF.to_string(),
+            iterations: KDF_ITERATIONS,
+            salt: base64::encode(salt),
+            cipher: CIPHER.to_string(),
+            nonce: base64::encode(nonce),
+            ciphertext: base64::encode(ciphertext),
+        })
+    }
+
+    /// Opens the keypair with `passphrase`
+    pub fn open(&self, passphrase: &str) -> Result<Keypair> {
+        if self.version != 1 || self.kdf != KDF || self.cipher != CIPHER {
+            return Err(anyhow::anyhow!(
+                "unsupported encrypted keypair (version {}, {}, {})",
+                self.version,
+                self.kdf,
+                self.cipher
+            ));
+        }
+        if !(KDF_ITERATIONS..=MAX_KDF_ITERATIONS).contains(&self.iterations) {
+            return Err(anyhow::anyhow!(
+                "encrypted keypair asks for {} PBKDF2 rounds; between {} and {} are accepted",
+                self.iterations,
+                KDF_ITERATIONS,
+                MAX_KDF_ITERATIONS
+            ));
+        }
+        let decode = |field: &str, value: &str| {
+            base64::decode(value).map_err(|e| anyhow::anyhow!("invalid {}: {}", field, e))
+        };
+        let salt = decode("salt", &self.salt)?;
+        let nonce = decode("nonce", &self.nonce)?;
+        let ciphertext = decode("ciphertext", &self.ciphertext)?;
+        let nonce: [u8; 12] = nonce
+            .try_into()
+            .map_err(|nonce: Vec<u8>| anyhow::anyhow!("invalid nonce: {} bytes", nonce.len()))?;
+        let cipher = cipher(passphrase, &salt, self.iterations)?;
+        // 口令错误与文件被篡改都表现为认证失败
+        let bytes = cipher
+            .decrypt(&Nonce::from(nonce), ciphertext.as_ref())
+            .map(Zeroizing::new)
+            .map_err(|_| anyhow::anyhow!("wrong passphrase or corrupted keypair"))?;
+        let keypair = Keypair::from_bytes(&bytes)
+            .map_err(|e| anyhow::anyhow!("invalid decrypted keypair: {}", e))?;
+        if keypair.pubkey().to_string() != self.pubkey {
+            return Err(anyhow::anyhow!(
+                "decrypted keypair is {}, not {}",
+                keypair.pubkey(),
+                self.pubkey
+            ));
+        }
+        Ok(keypair)
+    }
+}
+
+/// Cipher keyed by `passphrase`; the derived key is zeroed once the cipher
+/// holds it
+fn cipher(passphrase: &str, salt: &[u8], iterations: u32) -> Result<Aes256GcmSiv> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    pbkdf2::pbkdf2::<Hmac<Sha256>>(passphrase.as_bytes(), salt, iterations, key.as_mut());
+    Aes256GcmSiv::new_from_slice(key.as_ref())
+        .map_err(|_| anyhow::anyhow!("invalid derived key length"))
+}
+
+/// Passphrase from the `env` variable, else prompted for on a terminal
+fn passphrase(env: &str) -> Result<Redacted<String>> {
+    if let Some(passphrase) = std::env::var(env).ok().filter(|value| !value.is_empty()) {
+        return Ok(Redacted::new(passphrase));
+    }
+    if let Some(passphrase) = PROMPTED_PASSPHRASE.get() {
+        return Ok(passphrase.clone());
+    }
+    if !std::io::stdin().is_terminal() {
+        return Err(anyhow::anyhow!(
+            "the keypair is encrypted: set {} to its passphrase",
+            env
+        ));
+    }
+    let passphrase = prompt("Keypair passphrase: ")?;
+    Ok(PROMPTED_PASSPHRASE.get_or_init(|| passphrase).clone())
+}
+
+fn prompt(text: &str) -> Result<Redacted<String>> {
+    let _ = std::io::stdout().flush();
+    Ok(Redacted::new(rpassword::prompt_password(text)?))
+}
+
+/// Reads a keypair file, plain JSON or encrypted; the passphrase of an
+/// encrypted one comes from `passphrase_env` or a prompt
+pub fn read_keypair_path(path: &str, passphrase_env: &str) -> Result<Keypair> {
+    let contents = fs::read_to_string(path)?;
+    if !contents.trim_start().starts_with('{') {
+        return read_keypair(&mut contents.as_bytes()).map_err(|e| anyhow::anyhow!("{}", e));
+    }
+    let sealed: EncryptedKeypair = serde_json::from_str(&contents)
+        .map_err(|e| anyhow::anyhow!("invalid encrypted keypair: {}", e))?;
+    sealed.open(passphrase(passphrase_env)?.expose())
+}
+
+/// Encrypts the keypair file at `input` to `output`, which must not exist;
+/// the passphrase comes from `passphrase_env` or is prompted for twice.
+/// Returns the public key
+pub fn encrypt_keypair_file(input: &str, output: &str, passphrase_env: &str) -> Result<Pubkey> {
+    let keypair = read_keypair_path(input, passphrase_env)
+        .map_err(|e| anyhow::anyhow!("Failed to read keypair file {}: {}", input, e))?;
+    let passphrase = match std::env::var(passphrase_env)
+        .ok()
+        .filter(|value| !value.is_empty())
+    {
+        Some(passphrase) => Redacted::new(passphrase),
+        None if std::io::stdin().is_terminal() => {
+            let passphrase = prompt("New passphrase: ")?;
+            if prompt("Repeat the passphrase: ")?.expose() != passphrase.expose() {
+                return Err(anyhow::anyhow!("The passphrases do not match"));
+            }
+            passphrase
+        }
+        None => {
+            return Err(anyhow::anyhow!(
+                "Set {} to the passphrase, or run on a terminal to be prompted",
+                passphrase_env
+            ))
+        }
+    };
+    if passphrase.expose().is_empty() {
+        return Err(anyhow::anyhow!("The passphrase must not be empty"));
+    }
+    let sealed = EncryptedKeypair::seal(&keypair, passphrase.expose())?;
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(output)
+        .map_err(|e| anyhow::anyhow!("Failed to create {}: {}", output, e))?;
+    file.write_all(serde_json::to_string_pretty(&sealed)?.as_bytes())?;
+    file.sync_all()?;
+    Ok(keypair.pubkey())
+}
+
+/// A signer key of either backend
+pub type RelayerSigner = Box<dyn Signer + Send + Sync>;
+
+/// Where the primary signer key lives
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignerBackend {
+    /// The keypair file at `wallet_path`
+    #[default]
+    File,
+    /// The HTTP signing service at `remote_signer_url`
+    Remote,
+}
+
+/// Signs by posting each message to an HTTP signing service
+pub struct RemoteSigner {
+    url: SecretUrl,
+    pubkey: Pubkey,
+    token: Option<Redacted<String>>,
+    timeout: Duration,
+}
+
+#[derive(Serialize)]
+struct SignRequest {
+    pubkey: String,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct SignResponse {
+    signature: String,
+}
+
+impl RemoteSigner {
+    pub fn new(
+        url: SecretUrl,
+        pubkey: Pubkey,
+        token: Option<Redacted<String>>,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            url,
+            pubkey,
+            token,
+            timeout,
+        }
+    }
+
+    async fn request(&self, message: &[u8]) -> Result<Signature> {
+        let mut request = reqwest::Client::builder()
+            .timeout(self.timeout)
+            .build()?
+            .post(self.url.expose())
+            .json(&SignRequest {
+                pubkey: self.pubkey.to_string(),
+                message: base64::encode(message),
+            });
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token.expose());
+        }
+        // 错误信息不包含 URL，避免泄露其中的凭据
+        let response = request
+            .send()
+            .await
+            .map_err(|e| anyhow::anyhow!("request failed: {}", e.without_url()))?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(anyhow::anyhow!("signing service answered {}", status));
+        }
+        let response: SignResponse = response
+            .json()
+            .await
+            .map_err(|e| anyhow::anyhow!("invalid response: {}", e.without_url()))?;
+        let signature = Signature::from_str(&response.signature)
+            .map_err(|e| anyhow::anyhow!("invalid signature {}: {}", response.signature, e))?;
+        if !signature.verify(self.pubkey.as_ref(), message) {
+            return Err(anyhow::anyhow!(
+                "signature {} does not verify against {}",
+                signature,
+                self.pubkey
+            ));
+        }
+        Ok(signature)
+    }
+}
+
+impl Signer for RemoteSigner {
+    fn try_pubkey(&self) -> Result<Pubkey, SignerError> {
+        Ok(self.pubkey)
+    }
+
+    fn try_sign_message(&self, message: &[u8]) -> Result<Signature, SignerError> {
+        // Signer 是同步接口：在单独的线程里用自己的运行时发请求，不阻塞也不依赖调用方的运行时
+        std::thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    tokio::runtime::Builder::new_current_thread()
+                        .enable_all()
+                        .build()
+                        .map_err(anyhow::Error::from)
+                        .and_then(|runtime| runtime.block_on(self.request(message)))
+                })
+                .join()
+        })
+        .map_err(|_| SignerError::Custom("remote signer thread panicked".to_string()))?
+        .map_err(|e| SignerError::Custom(format!("Remote signer: {}", e)))
+    }
+
+    fn is_interactive(&self) -> bool {
+        false
+    }
+}
+
+/// The primary signer of `signer_backend` and the optional secondary
+/// keypair; errors name the config field, never the path
+pub fn read_signers(config: &RelayerConfig) -> Result<(RelayerSigner, Option<RelayerSigner>)> {
+    let read = |field: &str, path: &Redacted<String>| -> Result<RelayerSigner> {
+        let keypair = read_keypair_path(path.expose(), &config.keypair_passphrase_env)
+            .map_err(|e| anyhow::anyhow!("Failed to read the keypair file of {}: {}", field, e))?;
+        Ok(Box::new(keypair))
+    };
+    let secondary = config
+        .secondary_wallet_path
+        .as_ref()
+        .map(|path| read("secondary_wallet_path", path))
+        .transpose()?;
+    let primary = match config.signer_backend {
+        SignerBackend::File => read("wallet_path", &config.wallet_path)?,
+        SignerBackend::Remote => {
+            let url = config.remote_signer_url.clone().ok_or_else(|| {
+                anyhow::anyhow!("signer_backend = \"remote\" requires remote_signer_url")
+            })?;
+            let pubkey = config.remote_signer_pubkey.as_deref().ok_or_else(|| {
+                anyhow::anyhow!("signer_backend = \"remote\" requires remote_signer_pubkey")
+            })?;
+            let pubkey = Pubkey::from_str(pubkey)
+                .map_err(|e| anyhow::anyhow!("Invalid remote_signer_pubkey {}: {}", pubkey, e))?;
+            Box::new(RemoteSigner::new(
+                url,
+                pubkey,
+                config.remote_signer_token.clone(),
+                Duration::from_millis(config.remote_signer_timeout_ms),
+            ))
+        }
+    };
+    Ok((primary, secondary))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sealed_with(iterations: u32) -> EncryptedKeypair {
+        EncryptedKeypair {
+            version: 1,
+            pubkey: Pubkey::new_unique().to_string(),
+            kdf: KDF.to_string(),
+            iterations,
+            salt: base64::encode([0u8; 16]),
+            cipher: CIPHER.to_string(),
+            nonce: base64::encode([0u8; 12]),
+            ciphertext: base64::encode([0u8; 80]),
+        }
+    }
+
+    #[test]
+    fn rounds_outside_the_bounds_are_refused_before_deriving() {
+        for iterations in [1, KDF_ITERATIONS - 1, MAX_KDF_ITERATIONS + 1, u32::MAX] {
+            let error = sealed_with(iterations).open("passphrase").unwrap_err();
+            assert!(error.to_string().contains("PBKDF2 rounds"), "{}", error);
+        }
+    }
+}
@@ -0,0 +1,392 @@
+//! Load simulation, enabled with the `testing` feature.
+//! `simulate-load` generates synthetic L1 traffic and relays it with the real
+//! monitor, queue, batching, retry and confirmation code against `FakeL1`,
+//! `FakeL2` and `FakeStorage`, on a paused clock so minutes of traffic run in
+//! seconds. The pipeline settings come from the config (batch size,
+//! concurrency, coalescing, throughput limits, ...); the fakes serve the PDA
+//! message source only. Latency runs in simulated time from the L1 nonce
+//! showing a transfer to its receipt, sampled every `SAMPLE_INTERVAL`. The
+//! traffic and the injected failures follow from the seed alone, so reports
+//! of two releases with the same seed and settings can be compared; latencies
+//! still vary by a few samples between runs.
+
+use crate::{
+    clock::TokioClock,
+    config::RelayerConfig,
+    merkle::MessageSource,
+    models::message::MessageType,
+    pda::PdaManager,
+    receipts::Receipt,
+    signer::Signers,
+    testing::{transfer_info_data, FakeL1, FakeL2, FakeStorage},
+    Relayer,
+};
+use anyhow::Result;
+use serde::Serialize;
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::time::Instant;
+
+/// Resolution of the latency measurement
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(10);
+/// Recipients the transfers cycle through
+const RECIPIENTS: usize = 64;
+/// Message-type ids written into the PDAs of a mixed load
+const TOKEN_TYPE_ID: u8 = 1;
+const NFT_TYPE_ID: u8 = 2;
+const UNKNOWN_TYPE_ID: u8 = 255;
+
+/// How transfers arrive on L1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ArrivalPattern {
+    /// Evenly spaced at `rate`
+    Steady,
+    /// Exponentially distributed gaps averaging `rate`
+    Poisson,
+}
+
+/// How transfer amounts are drawn between the minimum and maximum
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum AmountDistribution {
+    Uniform,
+    /// Uniform in the logarithm, so small transfers dominate
+    LogUniform,
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadProfile {
+    pub transfers: u64,
+    /// Mean arrivals per simulated second
+    pub rate: f64,
+    pub arrivals: ArrivalPattern,
+    pub amount_min: u64,
+    pub amount_max: u64,
+    pub amounts: AmountDistribution,
+    /// Fractions of token, NFT and unknown-type messages; the rest are native
+    pub token_ratio: f64,
+    pub nft_ratio: f64,
+    pub unknown_ratio: f64,
+    /// Fraction of `sendTransaction` calls that fail
+    pub send_failure_rate: f64,
+    /// Fraction of `sendTransaction` calls that are accepted but never land
+    pub drop_rate: f64,
+    /// L2 prioritization fee in micro-lamports per compute unit, as seen by
+    /// `batch_strategy = "fee_aware"`
+    pub prioritization_fee: u64,
+    /// Simulated time the backlog may take to drain after the last arrival
+    pub drain_timeout: Duration,
+    pub seed: u64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct LoadReport {
+    pub transfers: u64,
+    pub relayed: u64,
+    /// Unknown-type messages recorded without relaying
+    pub skipped: u64,
+    pub dead_lettered: u64,
+    /// Transfers without a receipt when the simulation stopped
+    pub pending: u64,
+    pub simulated_secs: f64,
+    pub wall_secs: f64,
+    /// Relayed transfers per simulated second
+    pub throughput: f64,
+    pub latency_p50_ms: u64,
+    pub latency_p99_ms: u64,
+    pub latency_max_ms: u64,
+    pub send_attempts: usize,
+    pub failed_batches: u64,
+    pub rebroadcasts: u64,
+    /// Lamports the wallet spent beyond the relayed amounts: fees and rent
+    pub fee_spend: u64,
+}
+
+impl LoadReport {
+    pub fn to_text(&self) -> String {
+        format!(
+            "Transfers: {} ({} relayed, {} skipped, {} dead-lettered, {} pending)\n\
+             Simulated time: {:.1}s (wall {:.1}s)\n\
+             Throughput: {:.2} transfers/s\n\
+             Latency: p50 {}ms, p99 {}ms, max {}ms\n\
+             Send attempts: {}, failed batches: {}, rebroadcasts: {}\n\
+             Fee spend: {} lamports\n",
+            self.transfers,
+            self.relayed,
+            self.skipped,
+            self.dead_lettered,
+            self.pending,
+            self.simulated_secs,
+            self.wall_secs,
+            self.throughput,
+            self.latency_p50_ms,
+            self.latency_p99_ms,
+            self.latency_max_ms,
+            self.send_attempts,
+            self.failed_batches,
+            self.rebroadcasts,
+            self.fee_spend
+        )
+    }
+}
+
+/// splitmix64, so runs are reproducible from the seed alone
+pub(crate) struct Rng(pub(crate) u64);
+
+impl Rng {
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// A transfer of the generated load
+struct Arrival {
+    /// Offset from the start of the simulation
+    at: Duration,
+    amount: u64,
+    type_id: u8,
+}
+
+fn generate(profile: &LoadProfile, rng: &mut Rng) -> Vec<Arrival> {
+    let (min, max) = (
+        profile.amount_min.max(1),
+        profile.amount_max.max(profile.amount_min.max(1)),
+    );
+    let mean_gap = 1.0 / profile.rate.max(f64::MIN_POSITIVE);
+    let mut at = 0.0;
+    (0..profile.transfers)
+        .map(|_| {
+            at += match profile.arrivals {
+                ArrivalPattern::Steady => mean_gap,
+                ArrivalPattern::Poisson => -mean_gap * (1.0 - rng.next_f64()).ln(),
+            };
+            let amount = match profile.amounts {
+                AmountDistribution::Uniform => min + (rng.next_f64() * (max - min) as f64) as u64,
+                AmountDistribution::LogUniform => {
+                    let (low, high) = ((min as f64).ln(), (max as f64).ln());
+                    (low + rng.next_f64() * (high - low)).exp() as u64
+                }
+            };
+            let pick = rng.next_f64();
+            let type_id = if pick < profile.token_ratio {
+                TOKEN_TYPE_ID
+            } else if pick < profile.token_ratio + profile.nft_ratio {
+                NFT_TYPE_ID
+            } else if pick < profile.token_ratio + profile.nft_ratio + profile.unknown_ratio {
+                UNKNOWN_TYPE_ID
+            } else {
+                0
+            };
+            Arrival {
+                at: Duration::from_secs_f64(at),
+                amount: amount.clamp(min, max),
+                type_id,
+            }
+        })
+        .collect()
+}
+
+/// What the generator observed while the relayer ran
+#[derive(Default)]
+struct Outcome {
+    relayed: u64,
+    skipped: u64,
+    dead_lettered: u64,
+    pending: u64,
+    relayed_amount: u64,
+    /// Arrival-to-receipt latencies in milliseconds
+    latencies: Vec<u64>,
+    /// When the last receipt was seen, from the start
+    last_resolved: Duration,
+}
+
+/// Value at fraction `p` of the sorted `values`
+fn percentile(values: &[u64], p: f64) -> u64 {
+    if values.is_empty() {
+        return 0;
+    }
+    let index = ((values.len() as f64 * p).ceil() as usize).clamp(1, values.len()) - 1;
+    values[index]
+}
+
+/// Simulates `profile` against the first source of `config` on a paused
+/// runtime of its own; blocks until the simulation ends
+pub fn run(config: &RelayerConfig, profile: &LoadProfile) -> Result<LoadReport> {
+    let started = std::time::Instant::now();
+    // 暂停的时钟只能用于单线程 runtime，在独立线程中运行
+    let mut report = std::thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .start_paused(true)
+                    .build()?
+                    .block_on(simulate(config, profile))
+            })
+            .join()
+            .map_err(|_| anyhow::anyhow!("Load simulation panicked"))?
+    })?;
+    report.wall_secs = started.elapsed().as_secs_f64();
+    Ok(report)
+}
+
+async fn simulate(config: &RelayerConfig, profile: &LoadProfile) -> Result<LoadReport> {
+    if config.message_source != MessageSource::Pda {
+        return Err(anyhow::anyhow!(
+            "simulate-load serves transfer-info PDAs only; set message_source = \"pda\""
+        ));
+    }
+    // 预设的发送失败按次数计，预热交易会打乱顺序
+    let mut config = config.clone();
+    config.warmup_transaction = false;
+    let config = &config;
+    let mut source = config.sources()?.remove(0);
+    if profile.token_ratio + profile.nft_ratio + profile.unknown_ratio > 0.0 {
+        source.message_types = BTreeMap::from([
+            ("0".to_string(), MessageType::Native),
+            (TOKEN_TYPE_ID.to_string(), MessageType::Token),
+            (NFT_TYPE_ID.to_string(), MessageType::Nft),
+        ]);
+    }
+    let mut rng = Rng(profile.seed);
+    let arrivals = generate(profile, &mut rng);
+
+    let watched = *source.watched_account.pubkey();
+    let l1 = FakeL1::new(watched);
+    let nonce_account = *source.nonce_account.pubkey();
+    let l2 = FakeL2::new(nonce_account);
+    l2.set_owner(nonce_account, *config.l2_program_id.pubkey());
+    let storage = FakeStorage::default();
+    let keypair = Keypair::new();
+    let wallet = keypair.pubkey();
+    let funds = u64::MAX / 4;
+    l2.fund(&wallet, funds);
+    l2.set_prioritization_fee(profile.prioritization_fee);
+    let recipients: Vec<Pubkey> = (0..RECIPIENTS).map(|_| Keypair::new().pubkey()).collect();
+    for recipient in &recipients {
+        l2.fund(recipient, 1_000_000_000);
+    }
+    // 按比例预先选定失败和丢弃的发送
+    for attempt in 1..=(profile.transfers as usize).saturating_mul(10) {
+        let pick = rng.next_f64();
+        if pick < profile.send_failure_rate {
+            l2.fail_send(attempt);
+        } else if pick < profile.send_failure_rate + profile.drop_rate {
+            l2.drop_send(attempt);
+        }
+    }
+    let pdas = PdaManager::new(
+        source.l1_program_id,
+        source.watched_account,
+        source.seeds.clone(),
+    );
+    let relayer = Relayer::with_clients(
+        config,
+        &source,
+        Signers::new(keypair, None),
+        l1.client(),
+        l2.client(),
+        storage.store(),
+        Arc::new(TokioClock::starting_at(SystemTime::UNIX_EPOCH)),
+    )
+    .await?;
+
+    let start = Instant::now();
+    let last_arrival = start + arrivals.last().map_or(Duration::ZERO, |a| a.at);
+    let mut outcome = Outcome::default();
+    let drive = async {
+        let mut next = 0;
+        let mut outstanding: BTreeMap<u64, Instant> = BTreeMap::new();
+        loop {
+            let now = Instant::now();
+            while let Some(arrival) = arrivals.get(next).filter(|a| start + a.at <= now) {
+                let mut data =
+                    transfer_info_data(arrival.amount, &recipients[next % recipients.len()]);
+                data[80] = arrival.type_id;
+                l1.set_pda_data(pdas.find_address(next as u64).0, data);
+                outstanding.insert(next as u64, now);
+                next += 1;
+            }
+            l1.set_nonce(next as u64);
+            outstanding.retain(|nonce, arrived| {
+                let Some(receipt) = Receipt::load(&relayer.state, *nonce).ok().flatten() else {
+                    return true;
+                };
+                if receipt.skipped_message_type.is_some()
+                    || receipt.skipped_mint.is_some()
+                    || receipt.expired_at.is_some()
+                {
+                    outcome.skipped += 1;
+                } else {
+                    outcome.relayed += 1;
+                    outcome.relayed_amount += arrivals[*nonce as usize].amount;
+                }
+                outcome.latencies.push((now - *arrived).as_millis() as u64);
+                outcome.last_resolved = now - start;
+                false
+            });
+            // 死信 nonce 会一直挡住队列，只剩死信时提前结束
+            let dead_lettered = outstanding
+                .keys()
+                .filter(|nonce| relayer.dead_letters.contains(**nonce))
+                .count();
+            if next == arrivals.len()
+                && (outstanding.len() == dead_lettered
+                    || now >= last_arrival + profile.drain_timeout)
+            {
+                outcome.dead_lettered = dead_lettered as u64;
+                outcome.pending = (outstanding.len() - dead_lettered) as u64;
+                return;
+            }
+            tokio::time::sleep(SAMPLE_INTERVAL).await;
+        }
+    };
+    tokio::select! {
+        result = relayer.monitor_and_relay() => {
+            return Err(result.err().unwrap_or_else(|| anyhow::anyhow!("Relayer stopped")));
+        }
+        () = drive => {}
+    }
+
+    outcome.latencies.sort_unstable();
+    let latencies = &outcome.latencies;
+    let simulated_secs = outcome.last_resolved.as_secs_f64();
+    Ok(LoadReport {
+        transfers: profile.transfers,
+        relayed: outcome.relayed,
+        skipped: outcome.skipped,
+        dead_lettered: outcome.dead_lettered,
+        pending: outcome.pending,
+        simulated_secs,
+        wall_secs: 0.0,
+        throughput: if simulated_secs > 0.0 {
+            outcome.relayed as f64 / simulated_secs
+        } else {
+            0.0
+        },
+        latency_p50_ms: percentile(latencies, 0.5),
+        latency_p99_ms: percentile(latencies, 0.99),
+        latency_max_ms: latencies.last().copied().unwrap_or_default(),
+        send_attempts: l2.send_attempts(),
+        failed_batches: relayer.metrics.failed_batches_total.get(),
+        rebroadcasts: relayer.metrics.rebroadcasts_total.get(),
+        fee_spend: funds
+            .saturating_sub(l2.balance(&wallet))
+            .saturating_sub(outcome.relayed_amount),
+    })
+}
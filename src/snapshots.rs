@@ -0,0 +1,261 @@
+//! Reconciliation snapshots.
+//! With `snapshot_interval_secs` set, the monitor records a snapshot every
+//! that many seconds from the same poll that drives it: the L1 slot and nonce
+//! of the watched-account read, the L2 nonce read with it, and the cumulative
+//! transfers, volume and L2 fees relayed up to that nonce. Each snapshot is
+//! one state file, `snapshots/<unix time>`, written atomically; snapshots
+//! older than `snapshot_retention_days` are pruned. `snapshots` prints them
+//! and the admin API serves them on `GET /v1/snapshots`.
+//!
+//! Totals are carried forward from the previous snapshot and extended by the
+//! receipts of the nonces relayed since, counting a coalesced receipt once.
+//! The first snapshot counts every stored receipt. A relayed nonce whose
+//! receipt is not written yet is looked up again at the next snapshot and
+//! then counted in `missing_receipts`. `relayed_volume` counts native
+//! transfers in lamports; token and NFT transfers are summed per L2 mint in
+//! `token_volume`.
+
+use crate::{receipts::Receipt, state::StateStore, Relayer};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, sync::Mutex, time::Duration};
+
+/// Prefix of the snapshot keys in the state store
+const SNAPSHOTS_PREFIX: &str = "snapshots/";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MetricsSnapshot {
+    pub taken_at: u64,
+    /// Context slot of the watched-account read; none in merkle and
+    /// program-account mode
+    pub l1_slot: Option<u64>,
+    pub l1_nonce: u64,
+    /// Next nonce the L2 nonce account expects
+    pub l2_nonce: u64,
+    pub last_relayed_nonce: Option<u64>,
+    /// Transfers relayed up to `l2_nonce`
+    pub relayed_count: u64,
+    /// Lamports of native transfers relayed up to `l2_nonce`
+    pub relayed_volume: u64,
+    /// Base units per L2 mint (base58) relayed up to `l2_nonce`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub token_volume: BTreeMap<String, u64>,
+    /// L2 fees spent up to `l2_nonce`, the charged fee or its estimate
+    pub l2_fees: u64,
+    /// Relayed nonces counted without a receipt
+    pub missing_receipts: u64,
+    /// Nonces below this are in the totals; above `l2_nonce` if L2 went back
+    pub counted_to: u64,
+    /// Relayed nonces whose receipt was not written yet, looked up again by
+    /// the next snapshot
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unreceipted: Vec<u64>,
+}
+
+impl MetricsSnapshot {
+    fn key(taken_at: u64) -> String {
+        // 补零使键按时间排序
+        format!("{}{:020}", SNAPSHOTS_PREFIX, taken_at)
+    }
+
+    /// Snapshots taken at or after `from`, oldest first
+    pub fn list(store: &StateStore, from: u64) -> Result<Vec<Self>> {
+        let mut snapshots = Vec::new();
+        for key in store.list(SNAPSHOTS_PREFIX)? {
+            let taken_at: u64 = key
+                .strip_prefix(SNAPSHOTS_PREFIX)
+                .and_then(|time| time.parse().ok())
+                .unwrap_or_default();
+            if taken_at < from {
+                continue;
+            }
+            if let Some(snapshot) = store.get(&key)? {
+                snapshots.push(snapshot);
+            }
+        }
+        Ok(snapshots)
+    }
+
+    /// Adds the receipt of `nonce` to the totals; false when it has none
+    fn count(&mut self, store: &StateStore, nonce: u64) -> Result<bool> {
+        let Some(receipt) = Receipt::load(store, nonce)? else {
+            return Ok(false);
+        };
+        // 合并交易的回执存于每个 nonce 下，只在其最后一个 nonce 处计入
+        if receipt.nonce == nonce && !receipt.signature.is_empty() {
+            self.relayed_count += 1;
+            match &receipt.mint {
+                None => self.relayed_volume = self.relayed_volume.saturating_add(receipt.amount),
+                Some(mint) => {
+                    let volume = self.token_volume.entry(mint.clone()).or_default();
+                    *volume = volume.saturating_add(receipt.amount);
+                }
+            }
+            self.l2_fees = self
+                .l2_fees
+                .saturating_add(receipt.actual_fee.unwrap_or(receipt.l2_fee));
+        }
+        Ok(true)
+    }
+}
+
+pub struct Snapshotter {
+    interval: Duration,
+    retention: Option<Duration>,
+    last: Mutex<Option<MetricsSnapshot>>,
+}
+
+impl Snapshotter {
+    /// Resumes from the newest snapshot in `store`
+    pub fn load(
+        store: &StateStore,
+        interval: Duration,
+        retention: Option<Duration>,
+    ) -> Result<Self> {
+        let last = match store.list(SNAPSHOTS_PREFIX)?.last() {
+            Some(key) => store.get(key)?,
+            None => None,
+        };
+        Ok(Self {
+            interval,
+            retention,
+            last: Mutex::new(last),
+        })
+    }
+
+    /// The snapshot of one poll at `now`, extending `previous`
+    fn take(
+        store: &StateStore,
+        previous: Option<&MetricsSnapshot>,
+        now: u64,
+        l1_slot: Option<u64>,
+        l1_nonce: u64,
+        l2_nonce: u64,
+    ) -> Result<MetricsSnapshot> {
+        let mut snapshot = MetricsSnapshot {
+            taken_at: now,
+            l1_slot,
+            l1_nonce,
+            l2_nonce,
+            last_relayed_nonce: l2_nonce.checked_sub(1),
+            counted_to: l2_nonce,
+            ..MetricsSnapshot::default()
+        };
+        let Some(previous) = previous else {
+            for nonce in Receipt::stored_nonces(store)? {
+                if nonce < l2_nonce {
+                    snapshot.count(store, nonce)?;
+                }
+            }
+            return Ok(snapshot);
+        };
+        snapshot.relayed_count = previous.relayed_count;
+        snapshot.relayed_volume = previous.relayed_volume;
+        snapshot.token_volume = previous.token_volume.clone();
+        snapshot.l2_fees = previous.l2_fees;
+        snapshot.missing_receipts = previous.missing_receipts;
+        for &nonce in &previous.unreceipted {
+            if !snapshot.count(store, nonce)? {
+                snapshot.missing_receipts += 1;
+            }
+        }
+        // L2 nonce 回退时不重复计入
+        snapshot.counted_to = previous.counted_to.max(l2_nonce);
+        for nonce in previous.counted_to..l2_nonce {
+            if !snapshot.count(store, nonce)? {
+                snapshot.unreceipted.push(nonce);
+            }
+        }
+        Ok(snapshot)
+    }
+
+    /// Removes the snapshots older than the retention at `now`
+    fn prune(&self, store: &StateStore, now: u64) -> Result<()> {
+        let Some(retention) = self.retention else {
+            return Ok(());
+        };
+        let cutoff = now.saturating_sub(retention.as_secs());
+        for key in store.list(SNAPSHOTS_PREFIX)? {
+            let taken_at: u64 = key
+                .strip_prefix(SNAPSHOTS_PREFIX)
+                .and_then(|time| time.parse().ok())
+                .unwrap_or_default();
+            if taken_at >= cutoff {
+                break;
+            }
+            store.remove(&key)?;
+        }
+        Ok(())
+    }
+}
+
+impl Relayer {
+    /// Records a snapshot of the poll that read `l1_nonce` and `l2_nonce`
+    /// once the interval has passed since the last one
+    pub(crate) fn record_snapshot(&self, l1_nonce: u64, l2_nonce: u64) -> Result<()> {
+        let Some(snapshotter) = &self.snapshots else {
+            return Ok(());
+        };
+        let now = self.clock.unix_timestamp();
+        let mut last = snapshotter.last.lock().unwrap();
+        if let Some(previous) = last.as_ref() {
+            if now.saturating_sub(previous.taken_at) < snapshotter.interval.as_secs() {
+                return Ok(());
+            }
+        }
+        let snapshot = Snapshotter::take(
+            &self.state,
+            last.as_ref(),
+            now,
+            self.watched.last_slot(),
+            l1_nonce,
+            l2_nonce,
+        )?;
+        self.state.put(&MetricsSnapshot::key(now), &snapshot)?;
+        *last = Some(snapshot);
+        snapshotter.prune(&self.state, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_receipts_are_summed_per_mint_apart_from_lamports() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = StateStore::open(dir.path()).unwrap();
+        for (nonce, amount, mint) in [
+            (0, u64::MAX, None),
+            (1, 1_000, None),
+            (2, 7, Some("mint-a")),
+            (3, 9, Some("mint-a")),
+        ] {
+            let receipt: Receipt = serde_json::from_value(serde_json::json!({
+                "nonce": nonce,
+                "l1_slot": 1,
+                "amount": amount,
+                "to": "recipient",
+                "signature": format!("signature-{}", nonce),
+                "relayed_at": 100,
+                "mint": mint,
+            }))
+            .unwrap();
+            receipt.save(&store).unwrap();
+        }
+
+        let first = Snapshotter::take(&store, None, 100, None, 3, 3).unwrap();
+        assert_eq!(first.relayed_count, 3);
+        assert_eq!(first.relayed_volume, u64::MAX);
+        assert_eq!(
+            first.token_volume,
+            BTreeMap::from([("mint-a".to_string(), 7)])
+        );
+        let next = Snapshotter::take(&store, Some(&first), 200, None, 4, 4).unwrap();
+        assert_eq!(next.relayed_volume, u64::MAX);
+        assert_eq!(
+            next.token_volume,
+            BTreeMap::from([("mint-a".to_string(), 16)])
+        );
+    }
+}
@@ -0,0 +1,231 @@
+//! Warm standby.
+//! With `role = "standby"` the relayer monitors L1 and queues new nonces like
+//! the active instance, but never submits. Every `standby_interval_secs` it
+//! drops the nonces L2 has relayed from its queue, mirrors the active
+//! instance's receipts from L2 history (as `import-history` does) and builds
+//! the transaction of the next pending nonce to check that it could relay it,
+//! simulating it when `standby_simulate` is set. `promote` makes running
+//! standbys active before their next check; the admin API's `POST
+//! /v1/promote` makes them active at once.
+//!
+//! A standby is ready to take over while its last check is at most
+//! `STALE_CHECKS` intervals old and its mirror is at most
+//! `standby_ready_max_lag` nonces behind L2; `GET /readyz` reports it.
+
+use crate::{alerts, history::ImportOptions, receipts::Receipt, Relayer};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// State key of the role set by `promote`, replacing the configured one
+pub const ROLE_KEY: &str = "role";
+/// State key holding the last standby check, for `status`
+pub const STANDBY_STATUS_KEY: &str = "standby_status";
+/// Standby intervals after which the last check no longer shows readiness
+#[cfg(feature = "admin")]
+const STALE_CHECKS: u64 = 3;
+
+/// Whether the relayer submits transactions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    #[default]
+    Active,
+    /// Mirrors state and checks transactions without submitting them
+    Standby,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct StandbySettings {
+    /// Pause between standby checks
+    pub interval: Duration,
+    /// Simulate the transaction built for the next pending nonce
+    pub simulate: bool,
+    /// Maximum L2 requests per second while mirroring receipts
+    pub mirror_rps: u32,
+    /// Nonces the mirror may be behind L2 while the standby is ready
+    #[cfg(feature = "admin")]
+    pub ready_max_lag: u64,
+}
+
+/// Last standby check
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StandbyStatus {
+    /// Next nonce the L2 nonce account expects, i.e. how far the active
+    /// instance has relayed
+    pub l2_nonce: u64,
+    /// One past the highest nonce with a mirrored receipt
+    pub mirrored_nonce: u64,
+    /// Nonces relayed by the active instance that have no mirrored receipt yet
+    pub lag: u64,
+    /// Nonces observed on L1 and not relayed yet
+    pub pending: u64,
+    /// Pending nonce whose transaction was built
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verified_nonce: Option<u64>,
+    /// Why building or simulating that transaction failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verify_error: Option<String>,
+    pub checked_at: u64,
+}
+
+/// Whether a source could take over, as `GET /readyz` reports it
+#[cfg(feature = "admin")]
+#[derive(Debug, Clone, Serialize)]
+pub struct Readiness {
+    pub role: Role,
+    pub ready: bool,
+    /// Last standby check; none for an active source or before the first
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub standby: Option<StandbyStatus>,
+}
+
+impl Relayer {
+    /// Whether the relayer is a standby; makes it active once `promote` has run
+    pub(crate) fn is_standby(&self) -> Result<bool> {
+        let mut role = self.role.lock().unwrap();
+        if *role == Role::Standby && self.state.get::<Role>(ROLE_KEY)? == Some(Role::Active) {
+            *role = Role::Active;
+            self.metrics.standby.set(0);
+            alerts::raise(&format!(
+                "Source {} promoted from standby to active; submitting from now on",
+                self.source_id
+            ));
+        }
+        Ok(*role == Role::Standby)
+    }
+
+    /// An active relayer is ready; a standby once its last check is recent
+    /// and its mirror within `standby_ready_max_lag` of L2
+    #[cfg(feature = "admin")]
+    pub(crate) fn readiness(&self) -> Result<Readiness> {
+        let role = *self.role.lock().unwrap();
+        if role == Role::Active {
+            return Ok(Readiness {
+                role,
+                ready: true,
+                standby: None,
+            });
+        }
+        let status: Option<StandbyStatus> = self.state.get(STANDBY_STATUS_KEY)?;
+        let fresh_for = self.standby.interval.as_secs().max(1) * STALE_CHECKS;
+        let now = self.clock.unix_timestamp();
+        let ready = status.as_ref().is_some_and(|status| {
+            now.saturating_sub(status.checked_at) <= fresh_for
+                && status.lag <= self.standby.ready_max_lag
+        });
+        Ok(Readiness {
+            role,
+            ready,
+            standby: status,
+        })
+    }
+
+    /// Makes the relayer active at once and for later starts, as `promote`
+    /// does; false when it was active already
+    #[cfg(feature = "admin")]
+    pub(crate) fn promote(&self, by: &str) -> Result<bool> {
+        let standby = *self.role.lock().unwrap() == Role::Standby;
+        self.state.put(ROLE_KEY, &Role::Active)?;
+        if standby {
+            tracing::info!("{}: promoted to active by {}", self.name(), by);
+        }
+        self.is_standby()?;
+        Ok(standby)
+    }
+
+    /// Runs standby checks until the relayer is promoted; returns at once
+    /// for an active relayer
+    pub(crate) async fn standby_until_promoted(&self) {
+        loop {
+            match self.is_standby() {
+                Ok(false) => return,
+                Ok(true) => {
+                    if let Err(e) = self.standby_check().await {
+                        tracing::warn!("Standby check failed: {}", e);
+                    }
+                }
+                Err(e) => tracing::warn!("Failed to read the role set by promote: {}", e),
+            }
+            self.clock.sleep(self.standby.interval).await;
+        }
+    }
+
+    /// Catches the standby's state up with what L2 shows the active instance
+    /// did, and checks the transaction of the next pending nonce
+    pub(crate) async fn standby_check(&self) -> Result<StandbyStatus> {
+        let l2_nonce = self.read_l2_relayed_nonce().await?;
+        self.queue.prune_below(&self.state, l2_nonce)?;
+        let options = ImportOptions {
+            address: *self.transaction_builder.program_id.pubkey(),
+            requests_per_second: self.standby.mirror_rps,
+        };
+        let mirrored = self.import_history(&options).await?;
+        let mirrored_nonce = Receipt::stored_nonces(&self.state)?
+            .last()
+            .map_or(0, |nonce| nonce + 1);
+
+        let verified_nonce = self.queue.peek_batch(1).first().copied();
+        let verify_error = match verified_nonce {
+            Some(nonce) => self
+                .verify_transfer(nonce)
+                .await
+                .err()
+                .map(|e| e.to_string()),
+            None => None,
+        };
+
+        let (queued, spilled) = self.queue.depth();
+        let status = StandbyStatus {
+            l2_nonce,
+            mirrored_nonce,
+            lag: l2_nonce.saturating_sub(mirrored_nonce),
+            pending: queued + spilled,
+            verified_nonce,
+            verify_error,
+            checked_at: self.clock.unix_timestamp(),
+        };
+        self.state.put(STANDBY_STATUS_KEY, &status)?;
+        self.metrics.standby_lag.set(status.lag);
+        self.record_queue_depth();
+        tracing::debug!(
+            "Standby: L2 at nonce {}, {} receipt(s) mirrored, {} behind, {} pending",
+            l2_nonce,
+            mirrored.imported,
+            status.lag,
+            status.pending
+        );
+        if let Some(error) = &status.verify_error {
+            tracing::warn!("Standby could not relay the next pending nonce: {}", error);
+        }
+        Ok(status)
+    }
+
+    /// Builds (and with `standby_simulate`, simulates) the transaction of
+    /// `nonce` without sending it
+    async fn verify_transfer(&self, nonce: u64) -> Result<()> {
+        let Some(fetched) = self.fetch_transfers(&[nonce], 0).await?.into_iter().next() else {
+            // 未知类型被跳过时没有可构建的交易
+            return Ok(());
+        };
+        let prepared = self.build_transfer(fetched).await?;
+        self.check_transfer_cost(&prepared).await?;
+        if !self.standby.simulate {
+            return Ok(());
+        }
+        let simulation = self
+            .l2_client
+            .simulate_transaction(&prepared.transaction)
+            .await?
+            .value;
+        match simulation.err {
+            Some(e) => Err(anyhow::anyhow!(
+                "Simulation of nonce {} failed: {} (logs: {})",
+                nonce,
+                e,
+                simulation.logs.unwrap_or_default().join("; ")
+            )),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,289 @@
+//! Local state persistence for the relayer.
+//! Each key is stored as its own JSON file inside the state directory so that
+//! the running relayer and CLI commands can share the store safely. A write
+//! goes to its own temporary file, which is fsynced and renamed over the
+//! value before the directory is fsynced, so a crash leaves either the old or
+//! the new value of a key.
+
+use anyhow::Result;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    fmt, fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+/// Numbers the temporary files of this process's writes
+static WRITE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// A stored value that no longer parses
+#[derive(Debug)]
+pub struct CorruptState {
+    pub key: String,
+    pub error: serde_json::Error,
+}
+
+impl fmt::Display for CorruptState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Corrupt state value {}: {}", self.key, self.error)
+    }
+}
+
+impl std::error::Error for CorruptState {}
+
+/// Raw key/value storage underneath a `StateStore`
+pub trait StorageBackend: Send + Sync {
+    /// Returns the bytes stored under `key`, if any
+    fn read(&self, key: &str) -> Result<Option<Vec<u8>>>;
+    /// Atomically replaces the bytes stored under `key`
+    fn write(&self, key: &str, data: &[u8]) -> Result<()>;
+    /// Replaces the bytes under `key` in the storage itself, never only in a
+    /// buffer; for values that must survive a crash, e.g. a record written
+    /// before a send
+    fn write_through(&self, key: &str, data: &[u8]) -> Result<()> {
+        self.write(key, data)
+    }
+    /// Removes `key`; removing a missing key is not an error
+    fn delete(&self, key: &str) -> Result<()>;
+    /// Keys starting with `prefix`, sorted
+    fn list(&self, prefix: &str) -> Result<Vec<String>>;
+}
+
+/// One `<key>.json` file per key inside a directory
+pub struct DirectoryStorage {
+    dir: PathBuf,
+}
+
+impl DirectoryStorage {
+    /// Opens (creating if needed) the directory at `dir`
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).map_err(|e| {
+            anyhow::anyhow!("Failed to create state directory {}: {}", dir.display(), e)
+        })?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+}
+
+impl StorageBackend for DirectoryStorage {
+    fn read(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let path = self.path(key);
+        if !path.exists() {
+            return Ok(None);
+        }
+        fs::read(&path)
+            .map(Some)
+            .map_err(|e| anyhow::anyhow!("Failed to read state file {}: {}", path.display(), e))
+    }
+
+    fn write(&self, key: &str, data: &[u8]) -> Result<()> {
+        let path = self.path(key);
+        // 每次写入用独立的临时文件，relayer 与 CLI 并发写入同一键时互不覆盖
+        let tmp = path.with_extension(format!(
+            "json.{}.{}.tmp",
+            std::process::id(),
+            WRITE_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let parent = path.parent().unwrap_or(&self.dir);
+        fs::create_dir_all(parent).map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to create state directory {}: {}",
+                parent.display(),
+                e
+            )
+        })?;
+        let written = fs::File::create(&tmp).and_then(|mut file| {
+            file.write_all(data)?;
+            file.sync_all()
+        });
+        if let Err(e) = written {
+            let _ = fs::remove_file(&tmp);
+            return Err(anyhow::anyhow!(
+                "Failed to write state file {}: {}",
+                tmp.display(),
+                e
+            ));
+        }
+        if let Err(e) = fs::rename(&tmp, &path) {
+            let _ = fs::remove_file(&tmp);
+            return Err(anyhow::anyhow!(
+                "Failed to replace state file {}: {}",
+                path.display(),
+                e
+            ));
+        }
+        // 目录同步后重命名才能在崩溃后保留
+        fs::File::open(parent)
+            .and_then(|dir| dir.sync_all())
+            .map_err(|e| {
+                anyhow::anyhow!("Failed to sync state directory {}: {}", parent.display(), e)
+            })
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        let path = self.path(key);
+        if path.exists() {
+            fs::remove_file(&path).map_err(|e| {
+                anyhow::anyhow!("Failed to remove state file {}: {}", path.display(), e)
+            })?;
+        }
+        Ok(())
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        let (dir, name) = prefix.rsplit_once('/').unwrap_or(("", prefix));
+        let path = self.dir.join(dir);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let entries = fs::read_dir(&path).map_err(|e| {
+            anyhow::anyhow!("Failed to list state directory {}: {}", path.display(), e)
+        })?;
+        let mut keys = Vec::new();
+        for entry in entries {
+            let file_name = entry?.file_name();
+            // 写入中的 .tmp 文件不是完整的值
+            let Some(stem) = file_name.to_str().and_then(|f| f.strip_suffix(".json")) else {
+                continue;
+            };
+            if stem.starts_with(name) {
+                keys.push(if dir.is_empty() {
+                    stem.to_string()
+                } else {
+                    format!("{}/{}", dir, stem)
+                });
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    }
+}
+
+pub struct StateStore {
+    backend: Arc<dyn StorageBackend>,
+    /// Namespace prepended to every key, e.g. `"source-a/"`
+    prefix: String,
+}
+
+impl StateStore {
+    /// Opens (creating if needed) the state directory at `dir`
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        Ok(Self::new(DirectoryStorage::open(dir)?))
+    }
+
+    /// Creates a store on top of `backend`
+    pub fn new(backend: impl StorageBackend + 'static) -> Self {
+        Self {
+            backend: Arc::new(backend),
+            prefix: String::new(),
+        }
+    }
+
+    /// Store sharing this backend whose keys live under `namespace/`
+    pub fn scoped(&self, namespace: &str) -> Self {
+        Self {
+            backend: self.backend.clone(),
+            prefix: format!("{}{}/", self.prefix, namespace),
+        }
+    }
+
+    /// The backend underneath this store
+    pub fn backend(&self) -> Arc<dyn StorageBackend> {
+        self.backend.clone()
+    }
+
+    /// Store with the same namespace on top of `backend`, e.g. one wrapping
+    /// this store's backend
+    pub fn with_backend(&self, backend: Arc<dyn StorageBackend>) -> Self {
+        Self {
+            backend,
+            prefix: self.prefix.clone(),
+        }
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+
+    /// Reads the value stored under `key`, if any
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        let Some(data) = self.backend.read(&self.key(key))? else {
+            return Ok(None);
+        };
+        let value = serde_json::from_slice(&data).map_err(|error| CorruptState {
+            key: key.to_string(),
+            error,
+        })?;
+        Ok(Some(value))
+    }
+
+    /// Atomically replaces the value stored under `key`
+    pub fn put<T: Serialize>(&self, key: &str, value: &T) -> Result<()> {
+        self.backend
+            .write(&self.key(key), &serde_json::to_vec_pretty(value)?)
+    }
+
+    /// Like `put`, but fails instead of buffering the value while the
+    /// storage is degraded
+    pub fn put_through<T: Serialize>(&self, key: &str, value: &T) -> Result<()> {
+        self.backend
+            .write_through(&self.key(key), &serde_json::to_vec_pretty(value)?)
+    }
+
+    /// Removes the value stored under `key`
+    pub fn remove(&self, key: &str) -> Result<()> {
+        self.backend.delete(&self.key(key))
+    }
+
+    /// Keys of this store starting with `prefix`, sorted
+    pub fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        Ok(self
+            .backend
+            .list(&self.key(prefix))?
+            .into_iter()
+            .filter_map(|key| key.strip_prefix(&self.prefix).map(str::to_string))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn concurrent_writes_of_a_key_leave_one_whole_value() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Arc::new(DirectoryStorage::open(dir.path()).unwrap());
+        let writers: Vec<_> = (0..8u8)
+            .map(|writer| {
+                let storage = storage.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..50 {
+                        storage.write("nested/key", &[writer; 64]).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let value = storage.read("nested/key").unwrap().unwrap();
+        assert_eq!(value.len(), 64);
+        assert!(value.iter().all(|byte| *byte == value[0]));
+        let files: Vec<_> = fs::read_dir(dir.path().join("nested"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(files, vec!["key.json"]);
+        assert_eq!(storage.list("nested/").unwrap(), vec!["nested/key"]);
+    }
+}
@@ -0,0 +1,551 @@
+//! State bundles.
+//! `state export` writes the whole state of a source — the pending queue,
+//! receipts, dead letters, replay and dedup bookkeeping, holds and in-flight
+//! signatures — to one JSON-lines file, e.g. to move a relayer to another
+//! host; zstd-compressed when its name ends in `.zst`. The first line is
+//! the manifest: format, version, source, entry count, the SHA-256 of the
+//! entry lines and the highest nonce with a receipt. Every other line is a
+//! state value or, base64-encoded, another file of the state directory such
+//! as a receipt archive.
+//!
+//! The export takes the replay lease, so relayers sharing the state hold
+//! their submissions until it is done, and waits `EXPORT_SETTLE` for a send
+//! that passed its lease check to be recorded in flight. The bundle is
+//! written to a temporary file, read back and verified before it is moved to
+//! its path.
+//!
+//! `state import` verifies the bundle and refuses a newer format, another
+//! source's bundle and, unless `--force`, a state with receipts above the
+//! bundle's. The entries are written to a staging directory next to the
+//! state, which then replaces it with a rename; the old state is kept as
+//! `<dir>.pre-import-<unix time>`. Relayers using the state must be stopped.
+//! A bundle with an entry whose key or path would land outside the state
+//! directory (absolute, or with `..`) is refused before anything is written.
+
+use crate::{
+    clock::Clock,
+    receipts::Receipt,
+    replay_batch::{ReplayLease, LEASE_TTL, REPLAY_LEASE_KEY},
+    state::StateStore,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_sdk::hash::hashv;
+use std::{
+    fs,
+    io::Write,
+    path::{Component, Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// `format` of every bundle
+pub const BUNDLE_FORMAT: &str = "sol-bridge-relayer/state-bundle";
+/// Version of the bundle layout written by this build
+pub const BUNDLE_VERSION: u32 = 1;
+/// Wait after taking the lease before the state is read
+const EXPORT_SETTLE: Duration = Duration::from_secs(2);
+/// zstd compression level of bundles
+const COMPRESSION_LEVEL: i32 = 19;
+/// Magic number that starts a zstd frame
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+/// First line of a bundle
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleManifest {
+    pub format: String,
+    pub version: u32,
+    pub source: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bridge: Option<String>,
+    pub created_at: u64,
+    /// Operator who exported the state
+    pub exported_by: String,
+    /// Number of entry lines
+    pub entries: u64,
+    /// SHA-256 of the entry lines (base58)
+    pub sha256: String,
+    /// Highest nonce with a stored receipt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_receipt_nonce: Option<u64>,
+}
+
+/// One entry line of a bundle
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BundleEntry {
+    /// A state value
+    State {
+        key: String,
+        value: serde_json::Value,
+    },
+    /// Another file of the state directory, e.g. a receipt archive
+    File { path: String, data: String },
+}
+
+/// Outcome of an import
+#[derive(Debug)]
+pub struct ImportReport {
+    pub manifest: BundleManifest,
+    /// Where the replaced state was moved; none if there was no state
+    pub backup: Option<PathBuf>,
+}
+
+/// Paths of the files under `dir`, relative and `/`-separated, sorted
+fn files(dir: &Path) -> Result<Vec<String>> {
+    fn walk(dir: &Path, prefix: &str, out: &mut Vec<String>) -> Result<()> {
+        let entries = fs::read_dir(dir).map_err(|e| {
+            anyhow::anyhow!("Failed to list state directory {}: {}", dir.display(), e)
+        })?;
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let path = format!("{}{}", prefix, name);
+            if entry.file_type()?.is_dir() {
+                walk(&entry.path(), &format!("{}/", path), out)?;
+            } else if !name.ends_with(".tmp") {
+                // 写入中的临时文件不是完整的值
+                out.push(path);
+            }
+        }
+        Ok(())
+    }
+    let mut out = Vec::new();
+    if dir.exists() {
+        walk(dir, "", &mut out)?;
+    }
+    out.sort();
+    Ok(out)
+}
+
+/// The live lease of another process in `store`, if any
+fn live_lease(store: &StateStore, now: u64) -> Result<Option<ReplayLease>> {
+    Ok(store
+        .get::<ReplayLease>(REPLAY_LEASE_KEY)?
+        .filter(|lease| lease.expires_at > now))
+}
+
+/// Highest nonce with a receipt in the state at `dir`
+fn last_receipt_nonce(dir: &Path) -> Result<Option<u64>> {
+    if !dir.exists() {
+        return Ok(None);
+    }
+    Ok(Receipt::stored_nonces(&StateStore::open(dir)?)?.pop())
+}
+
+/// Reads every entry of the state at `dir`, except the replay lease
+fn read_entries(dir: &Path) -> Result<Vec<BundleEntry>> {
+    let lease_file = format!("{}.json", REPLAY_LEASE_KEY);
+    let mut entries = Vec::new();
+    // 按键排序读取：in_flight 先于 receipts，读取间确认的转账两边都有，不会丢失
+    for path in files(dir)? {
+        if path == lease_file {
+            continue;
+        }
+        let data = fs::read(dir.join(&path))
+            .map_err(|e| anyhow::anyhow!("Failed to read state file {}: {}", path, e))?;
+        entries.push(match path.strip_suffix(".json") {
+            Some(key) => BundleEntry::State {
+                key: key.to_string(),
+                value: serde_json::from_slice(&data).map_err(|e| {
+                    anyhow::anyhow!("Corrupt state value {}: {}; not exporting it", key, e)
+                })?,
+            },
+            None => BundleEntry::File {
+                path,
+                data: base64::encode(&data),
+            },
+        });
+    }
+    Ok(entries)
+}
+
+/// Writes the state of `source` at `dir` to a bundle at `out`, holding the
+/// replay lease meanwhile
+pub async fn export(
+    dir: &Path,
+    out: &Path,
+    source: &str,
+    bridge: Option<&str>,
+    by: &str,
+    clock: &dyn Clock,
+) -> Result<BundleManifest> {
+    let store = StateStore::open(dir)?;
+    if let Some(lease) = live_lease(&store, clock.unix_timestamp())? {
+        return Err(anyhow::anyhow!(
+            "{} holds the replay lease until {}; export again once it is done",
+            lease.holder,
+            lease.expires_at
+        ));
+    }
+    let token = format!(
+        "{}-{}",
+        std::process::id(),
+        clock
+            .now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos()
+    );
+    store.put(
+        REPLAY_LEASE_KEY,
+        &ReplayLease {
+            holder: format!("state export by {}", by),
+            token: token.clone(),
+            expires_at: clock.unix_timestamp() + LEASE_TTL.as_secs(),
+        },
+    )?;
+    let result = async {
+        clock.sleep(EXPORT_SETTLE).await;
+        let lease = store.get::<ReplayLease>(REPLAY_LEASE_KEY)?;
+        if lease.is_none_or(|lease| lease.token != token) {
+            return Err(anyhow::anyhow!(
+                "Lost the replay lease while exporting the state"
+            ));
+        }
+        let last_receipt_nonce = last_receipt_nonce(dir)?;
+        let entries = read_entries(dir)?;
+        write(
+            out,
+            source,
+            bridge,
+            by,
+            clock.unix_timestamp(),
+            last_receipt_nonce,
+            &entries,
+        )
+    }
+    .await;
+
+    let lease = store.get::<ReplayLease>(REPLAY_LEASE_KEY)?;
+    if lease.is_some_and(|lease| lease.token == token) {
+        store.remove(REPLAY_LEASE_KEY)?;
+    }
+    result
+}
+
+fn write(
+    out: &Path,
+    source: &str,
+    bridge: Option<&str>,
+    by: &str,
+    now: u64,
+    last_receipt_nonce: Option<u64>,
+    entries: &[BundleEntry],
+) -> Result<BundleManifest> {
+    let mut lines = Vec::new();
+    for entry in entries {
+        serde_json::to_writer(&mut lines, entry)?;
+        lines.push(b'\n');
+    }
+    let manifest = BundleManifest {
+        format: BUNDLE_FORMAT.to_string(),
+        version: BUNDLE_VERSION,
+        source: source.to_string(),
+        bridge: bridge.map(str::to_string),
+        created_at: now,
+        exported_by: by.to_string(),
+        entries: entries.len() as u64,
+        sha256: hashv(&[&lines]).to_string(),
+        last_receipt_nonce,
+    };
+    let mut bundle = serde_json::to_vec(&manifest)?;
+    bundle.push(b'\n');
+    bundle.extend_from_slice(&lines);
+
+    let mut tmp = out.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    if out.extension().is_some_and(|extension| extension == "zst") {
+        bundle = zstd::stream::encode_all(&bundle[..], COMPRESSION_LEVEL)?;
+    }
+    fs::File::create(&tmp)
+        .and_then(|mut file| {
+            file.write_all(&bundle)?;
+            file.sync_all()
+        })
+        .map_err(|e| anyhow::anyhow!("Failed to write bundle {}: {}", tmp.display(), e))?;
+    // 替换前先读回校验
+    read(&tmp)?;
+    fs::rename(&tmp, out)
+        .map_err(|e| anyhow::anyhow!("Failed to move bundle to {}: {}", out.display(), e))?;
+    Ok(manifest)
+}
+
+/// Reads the bundle at `path`, compressed or not, and checks it against its
+/// manifest
+pub fn read(path: &Path) -> Result<(BundleManifest, Vec<BundleEntry>)> {
+    let mut bundle = fs::read(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read bundle {}: {}", path.display(), e))?;
+    // 按内容而非文件名判断是否压缩，改名后的包也能读取
+    if bundle.starts_with(&ZSTD_MAGIC) {
+        bundle = zstd::stream::decode_all(&bundle[..])
+            .map_err(|e| anyhow::anyhow!("Bundle {} does not decompress: {}", path.display(), e))?;
+    }
+    let (header, lines) = bundle.split_at(
+        bundle
+            .iter()
+            .position(|byte| *byte == b'\n')
+            .unwrap_or(bundle.len()),
+    );
+    let manifest: BundleManifest = serde_json::from_slice(header)
+        .map_err(|e| anyhow::anyhow!("Bundle {} has no valid manifest: {}", path.display(), e))?;
+    if manifest.format != BUNDLE_FORMAT {
+        return Err(anyhow::anyhow!(
+            "{} is not a state bundle (format {:?})",
+            path.display(),
+            manifest.format
+        ));
+    }
+    if manifest.version > BUNDLE_VERSION {
+        return Err(anyhow::anyhow!(
+            "Bundle {} has version {}, this build reads up to {}; upgrade the relayer",
+            path.display(),
+            manifest.version,
+            BUNDLE_VERSION
+        ));
+    }
+    let lines = lines.get(1..).unwrap_or_default();
+    let sha256 = hashv(&[lines]).to_string();
+    if sha256 != manifest.sha256 {
+        return Err(anyhow::anyhow!(
+            "Bundle {} has hash {}, its manifest records {}",
+            path.display(),
+            sha256,
+            manifest.sha256
+        ));
+    }
+    let entries = lines
+        .split(|byte| *byte == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(serde_json::from_slice)
+        .collect::<Result<Vec<BundleEntry>, _>>()
+        .map_err(|e| anyhow::anyhow!("Bundle {} holds an invalid entry: {}", path.display(), e))?;
+    if entries.len() as u64 != manifest.entries {
+        return Err(anyhow::anyhow!(
+            "Bundle {} holds {} entries, its manifest records {}",
+            path.display(),
+            entries.len(),
+            manifest.entries
+        ));
+    }
+    Ok((manifest, entries))
+}
+
+/// `dir` with `suffix` appended to its name
+fn sibling(dir: &Path, suffix: &str) -> PathBuf {
+    let mut name = dir.file_name().unwrap_or_default().to_owned();
+    name.push(suffix);
+    dir.with_file_name(name)
+}
+
+/// Path of the bundle file `relative` under `staging`; errors unless it is
+/// a relative path of plain components that stays there
+fn staged_path(staging: &Path, relative: &str) -> Result<PathBuf> {
+    let plain = !relative.is_empty()
+        && Path::new(relative)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+    let path = staging.join(relative);
+    if !plain || !path.starts_with(staging) {
+        return Err(anyhow::anyhow!(
+            "Bundle entry {:?} points outside the state directory; refusing to import",
+            relative
+        ));
+    }
+    Ok(path)
+}
+
+/// Checks that every entry of a bundle is written under `staging`
+fn check_entry_paths(staging: &Path, entries: &[BundleEntry]) -> Result<()> {
+    for entry in entries {
+        match entry {
+            BundleEntry::State { key, .. } => {
+                // 状态键按 `<key>.json` 写入状态目录
+                if key.contains("..") || key.starts_with('/') {
+                    return Err(anyhow::anyhow!(
+                        "Bundle state key {:?} points outside the state directory; refusing to import",
+                        key
+                    ));
+                }
+                staged_path(staging, &format!("{}.json", key))?;
+            }
+            BundleEntry::File { path, .. } => {
+                staged_path(staging, path)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the state of `source` at `dir` with the bundle at `path`
+pub fn import(
+    dir: &Path,
+    path: &Path,
+    source: &str,
+    force: bool,
+    now: u64,
+) -> Result<ImportReport> {
+    let (manifest, entries) = read(path)?;
+    if manifest.source != source {
+        return Err(anyhow::anyhow!(
+            "Bundle {} holds the state of source {}, not {}",
+            path.display(),
+            manifest.source,
+            source
+        ));
+    }
+    if dir.exists() {
+        if let Some(lease) = live_lease(&StateStore::open(dir)?, now)? {
+            return Err(anyhow::anyhow!(
+                "{} holds the replay lease until {}; the state is in use",
+                lease.holder,
+                lease.expires_at
+            ));
+        }
+    }
+    let current = last_receipt_nonce(dir)?;
+    if current > manifest.last_receipt_nonce && !force {
+        return Err(anyhow::anyhow!(
+            "The state in {} is newer than the bundle: receipts up to nonce {}, the bundle's up to {}; --force imports anyway",
+            dir.display(),
+            current.unwrap_or_default(),
+            manifest
+                .last_receipt_nonce
+                .map_or("none".to_string(), |nonce| nonce.to_string())
+        ));
+    }
+
+    let staging = sibling(dir, &format!(".import-{}", now));
+    check_entry_paths(&staging, &entries)?;
+    if staging.exists() {
+        fs::remove_dir_all(&staging).map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to clear staging directory {}: {}",
+                staging.display(),
+                e
+            )
+        })?;
+    }
+    let store = StateStore::open(&staging)?;
+    for entry in &entries {
+        match entry {
+            BundleEntry::State { key, value } => store.put(key, value)?,
+            BundleEntry::File { path, data } => {
+                let file = staged_path(&staging, path)?;
+                if let Some(parent) = file.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let data = base64::decode(data)
+                    .map_err(|e| anyhow::anyhow!("Bundle file {} is not base64: {}", path, e))?;
+                fs::write(&file, data)
+                    .map_err(|e| anyhow::anyhow!("Failed to write {}: {}", file.display(), e))?;
+            }
+        }
+    }
+    let written = files(&staging)?.len();
+    if written != entries.len() {
+        return Err(anyhow::anyhow!(
+            "Staging directory {} holds {} files, the bundle {} entries",
+            staging.display(),
+            written,
+            entries.len()
+        ));
+    }
+
+    let backup = dir
+        .exists()
+        .then(|| sibling(dir, &format!(".pre-import-{}", now)));
+    if let Some(backup) = &backup {
+        fs::rename(dir, backup).map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to move {} to {}: {}",
+                dir.display(),
+                backup.display(),
+                e
+            )
+        })?;
+    }
+    fs::rename(&staging, dir).map_err(|e| {
+        anyhow::anyhow!(
+            "Failed to move {} to {}: {}",
+            staging.display(),
+            dir.display(),
+            e
+        )
+    })?;
+    Ok(ImportReport { manifest, backup })
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::clock::TokioClock;
+
+    #[tokio::test(start_paused = true)]
+    async fn exported_state_imports_into_an_identical_state() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("state");
+        let store = StateStore::open(&dir).unwrap();
+        store.put("queue", &vec![4u64, 5]).unwrap();
+        store
+            .put("receipts/3", &serde_json::json!({"nonce": 3}))
+            .unwrap();
+        fs::create_dir_all(dir.join("archive")).unwrap();
+        fs::write(dir.join("archive/receipts-0.zst"), [1, 2, 3]).unwrap();
+        let clock = TokioClock::starting_at(SystemTime::UNIX_EPOCH);
+
+        let out = root.path().join("state.bundle.zst");
+        let manifest = export(&dir, &out, "default", None, "ops", &clock)
+            .await
+            .unwrap();
+        assert_eq!(
+            (manifest.entries, manifest.last_receipt_nonce),
+            (3, Some(3))
+        );
+
+        let moved = root.path().join("moved");
+        let report = import(&moved, &out, "default", false, 10).unwrap();
+        assert!(report.backup.is_none());
+        assert_eq!(read_entries(&moved).unwrap(), read_entries(&dir).unwrap());
+        assert!(import(&moved, &out, "other", false, 11).is_err());
+    }
+
+    #[test]
+    fn entries_outside_the_state_directory_are_refused() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("state");
+        let escapes = [
+            BundleEntry::File {
+                path: "../escaped".to_string(),
+                data: base64::encode(b"x"),
+            },
+            BundleEntry::File {
+                path: root.path().join("escaped").display().to_string(),
+                data: base64::encode(b"x"),
+            },
+            BundleEntry::State {
+                key: "../../escaped".to_string(),
+                value: serde_json::json!(1),
+            },
+            BundleEntry::State {
+                key: "/escaped".to_string(),
+                value: serde_json::json!(1),
+            },
+        ];
+        for (n, entry) in escapes.into_iter().enumerate() {
+            let out = root.path().join(format!("bundle-{}", n));
+            write(&out, "default", None, "ops", 0, None, &[entry]).unwrap();
+            let refused = import(&dir, &out, "default", false, n as u64)
+                .unwrap_err()
+                .to_string();
+            assert!(
+                refused.contains("outside the state directory"),
+                "{}",
+                refused
+            );
+        }
+        assert!(!root.path().join("escaped").exists());
+        assert!(!root.path().join("escaped.json").exists());
+        assert!(!dir.exists());
+    }
+}
@@ -0,0 +1,104 @@
+//! Step-driven operation.
+//! `Relayer::run_once` performs one monitor poll and relays whatever is ready
+//! without sleeping, for schedulers that drive the relayer themselves (and for
+//! tests). The long-running `run` keeps the monitor and the submitter as
+//! separate loops so a slow L2 never delays detection of new L1 nonces; both
+//! loops are built from the same steps.
+
+use crate::{journal::JournalEvent, submitter::Submitted, Relayer};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// Summary of one `run_once` iteration
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayOutcome {
+    pub source: String,
+    /// Nonce of the L1 watched account
+    pub l1_nonce: u64,
+    /// Next L1 nonce the L2 nonce account expects
+    pub l2_nonce: u64,
+    /// Nonces enqueued for the first time
+    pub observed: u64,
+    pub relayed: u64,
+    /// Failed batches; each records a failed attempt of one nonce
+    pub failed: u64,
+    /// Pending nonces left untouched because they are backing off or wait
+    /// behind a dead letter
+    pub skipped: u64,
+    /// Error of the failed batch, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Relayer {
+    /// Reads the nonces, enqueues new transfers and relays every batch that
+    /// is ready, stopping at the first failed batch. Never waits for queue
+    /// space, backoff or the coalescing window. Errors reading the nonces
+    /// (e.g. `WatchedAccountMissing`) are returned; batch failures are counted
+    /// in the outcome.
+    pub async fn run_once(&self) -> Result<RelayOutcome> {
+        self.recover_in_flight().await?;
+        let (l1_nonce, l2_nonce, queue_to) = self.poll_nonces().await?;
+        let enqueued = self.queue.push_available(&self.state, l2_nonce, queue_to)?;
+        if !enqueued.is_empty() {
+            self.journal(
+                None,
+                JournalEvent::Enqueued {
+                    from: enqueued.start,
+                    to: enqueued.end,
+                },
+            );
+        }
+        let mut outcome = RelayOutcome {
+            source: self.source_id.clone(),
+            l1_nonce,
+            l2_nonce,
+            observed: enqueued.end - enqueued.start,
+            ..RelayOutcome::default()
+        };
+
+        if self.is_standby()? {
+            let status = self.standby_check().await?;
+            outcome.skipped = status.pending;
+            self.record_queue_depth();
+            return Ok(outcome);
+        }
+
+        while self.queue.depth().0 > 0 {
+            match self.submit_next_batch(0).await {
+                Ok(Submitted::Relayed(0)) => break,
+                Ok(Submitted::Relayed(relayed)) => {
+                    self.metrics.relayed_total.inc_by(relayed);
+                    outcome.relayed += relayed;
+                }
+                Ok(Submitted::Held(_)) => {
+                    outcome.skipped = self.queue.depth().0;
+                    break;
+                }
+                Err(e) => {
+                    self.metrics.failed_batches_total.inc();
+                    self.notify_error(&e);
+                    outcome.failed += 1;
+                    outcome.error = Some(e.to_string());
+                    break;
+                }
+            }
+        }
+
+        // 只检查一次已落地的 safe 交易，未终局的留给下一次
+        if let Err(e) = self.finalize_landed().await {
+            tracing::warn!("failed to check landed transactions: {}", e);
+        }
+        self.enrich_scheduled().await;
+        // 发布失败的事件留在 outbox，下一次再发
+        if let Some(stream) = &self.event_stream {
+            if let Err(e) = self.publish_outbox(stream).await {
+                self.metrics.event_stream_failures_total.inc();
+                tracing::warn!("failed to publish relay events: {}", e);
+            }
+        }
+        self.prune_replay_window()?;
+        self.record_queue_depth();
+        Ok(outcome)
+    }
+}
@@ -0,0 +1,383 @@
+//! Degraded storage.
+//! When the state storage fails mid-run (a full disk, a lost network mount),
+//! up to `storage_buffer_capacity` writes and removals are kept in memory, in
+//! order, instead of failing the relayer. Reads are answered from the buffer
+//! first, so receipts and the pending queue written while degraded still
+//! answer the replay check. Every later write first flushes the buffer, so
+//! the storage sees the writes in the order they were made.
+//!
+//! Monitoring continues while degraded. Before each batch the submitter
+//! tries to flush; it holds submissions while the buffer (or the journal's
+//! backlog) is full, and a batch whose replay check cannot be answered from
+//! the buffer is held as well, not counted as failed. Degradation, a full
+//! buffer and recovery are alerted or logged, and exported as
+//! `relayer_storage_degraded`, `relayer_storage_buffered_writes` and
+//! `relayer_storage_degraded_seconds`. With `storage_buffer_capacity = 0`,
+//! storage errors fail as they did before. In-flight records are never only
+//! buffered: a send whose record cannot be written waits like a full buffer,
+//! since a restart could not recover a transaction it does not know of.
+
+use crate::{alerts, state::StorageBackend, Relayer};
+use anyhow::Result;
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// The state storage failed and the operation could not be buffered
+#[derive(Debug)]
+pub struct StorageUnavailable {
+    pub key: String,
+    pub cause: String,
+}
+
+impl fmt::Display for StorageUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "State storage unavailable ({}): {}",
+            self.key, self.cause
+        )
+    }
+}
+
+impl std::error::Error for StorageUnavailable {}
+
+/// A buffered write, or a removal when there is no data
+type Pending = (String, Option<Vec<u8>>);
+
+/// Storage backend keeping failed writes in a bounded in-memory buffer
+pub struct BufferedStorage {
+    inner: Arc<dyn StorageBackend>,
+    capacity: usize,
+    pending: Mutex<VecDeque<Pending>>,
+}
+
+impl BufferedStorage {
+    pub fn new(inner: Arc<dyn StorageBackend>, capacity: usize) -> Self {
+        Self {
+            inner,
+            capacity,
+            pending: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// (buffered operations, capacity)
+    pub fn depth(&self) -> (usize, usize) {
+        (self.pending.lock().unwrap().len(), self.capacity)
+    }
+
+    /// Writes the buffered operations in order; stops at the first failure,
+    /// keeping it and everything after it. Returns how many were written
+    pub fn flush(&self) -> Result<usize> {
+        Self::flush_locked(&self.inner, &mut self.pending.lock().unwrap())
+    }
+
+    fn flush_locked(
+        inner: &Arc<dyn StorageBackend>,
+        pending: &mut VecDeque<Pending>,
+    ) -> Result<usize> {
+        let mut flushed = 0;
+        while let Some((key, data)) = pending.front() {
+            match data {
+                Some(data) => inner.write(key, data)?,
+                None => inner.delete(key)?,
+            }
+            pending.pop_front();
+            flushed += 1;
+        }
+        Ok(flushed)
+    }
+
+    /// Applies `operation` after the buffered ones, buffering it when the
+    /// storage fails and there is room
+    fn apply(&self, key: &str, data: Option<&[u8]>) -> Result<()> {
+        let mut pending = self.pending.lock().unwrap();
+        // 先写出缓冲，保证存储按原顺序看到写入
+        let result = Self::flush_locked(&self.inner, &mut pending).and_then(|_| match data {
+            Some(data) => self.inner.write(key, data),
+            None => self.inner.delete(key),
+        });
+        let Err(e) = result else {
+            return Ok(());
+        };
+        if pending.len() >= self.capacity {
+            return Err(StorageUnavailable {
+                key: key.to_string(),
+                cause: format!("{} (write buffer full)", e),
+            }
+            .into());
+        }
+        pending.push_back((key.to_string(), data.map(<[u8]>::to_vec)));
+        Ok(())
+    }
+}
+
+impl StorageBackend for BufferedStorage {
+    fn read(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let buffered = self
+            .pending
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|(pending, _)| pending == key)
+            .map(|(_, data)| data.clone());
+        if let Some(data) = buffered {
+            return Ok(data);
+        }
+        self.inner.read(key).map_err(|e| {
+            StorageUnavailable {
+                key: key.to_string(),
+                cause: e.to_string(),
+            }
+            .into()
+        })
+    }
+
+    fn write(&self, key: &str, data: &[u8]) -> Result<()> {
+        self.apply(key, Some(data))
+    }
+
+    fn write_through(&self, key: &str, data: &[u8]) -> Result<()> {
+        let mut pending = self.pending.lock().unwrap();
+        Self::flush_locked(&self.inner, &mut pending)
+            .and_then(|_| self.inner.write(key, data))
+            .map_err(|e| {
+                StorageUnavailable {
+                    key: key.to_string(),
+                    cause: e.to_string(),
+                }
+                .into()
+            })
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.apply(key, None)
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        let mut keys = self.inner.list(prefix).map_err(|e| StorageUnavailable {
+            key: prefix.to_string(),
+            cause: e.to_string(),
+        })?;
+        for (key, data) in self.pending.lock().unwrap().iter() {
+            if !key.starts_with(prefix) {
+                continue;
+            }
+            keys.retain(|listed| listed != key);
+            if data.is_some() {
+                keys.push(key.clone());
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    }
+}
+
+/// Whether `error` comes from storage that is unavailable
+pub(crate) fn storage_unavailable(error: &anyhow::Error) -> Option<String> {
+    error
+        .downcast_ref::<StorageUnavailable>()
+        .map(ToString::to_string)
+}
+
+pub struct StorageHealth {
+    pub buffer: Arc<BufferedStorage>,
+    /// Unix time at which the storage first failed, and whether the full
+    /// buffer was alerted
+    degraded: Mutex<Option<(u64, bool)>>,
+}
+
+impl StorageHealth {
+    pub fn new(buffer: Arc<BufferedStorage>) -> Self {
+        Self {
+            buffer,
+            degraded: Mutex::new(None),
+        }
+    }
+}
+
+impl Relayer {
+    /// Wait before trying again while the storage is degraded and its buffer
+    /// or the journal backlog is full. Flushes the buffered writes first.
+    pub(crate) fn storage_hold(&self) -> Option<Duration> {
+        let health = self.storage.as_ref()?;
+        let now = self.clock.unix_timestamp();
+        let (buffered, capacity) = health.buffer.depth();
+        let (journal_backlog, journal_capacity) = self
+            .journal
+            .as_ref()
+            .map_or((0, 0), |journal| journal.backlog());
+        let mut degraded = health.degraded.lock().unwrap();
+
+        let journal = match &self.journal {
+            Some(journal) if journal_backlog > 0 => journal.flush_backlog(),
+            _ => Ok(()),
+        };
+        let failure = match (health.buffer.flush(), journal) {
+            (Ok(_), Ok(())) => None,
+            (Err(e), _) | (_, Err(e)) => Some(e.to_string()),
+        };
+        let Some(failure) = failure else {
+            if let Some((since, _)) = degraded.take() {
+                tracing::info!(
+                    "State storage available again after {}s; flushed {} buffered write(s)",
+                    now.saturating_sub(since),
+                    buffered
+                );
+            }
+            self.metrics.storage_degraded.set(0);
+            self.metrics.storage_buffered_writes.set(0);
+            self.metrics.storage_degraded_seconds.set(0);
+            return None;
+        };
+
+        let (since, alerted_full) = degraded.get_or_insert_with(|| {
+            alerts::raise(&format!(
+                "State storage degraded, buffering writes in memory: {}",
+                failure
+            ));
+            (now, false)
+        });
+        let (buffered, _) = health.buffer.depth();
+        let journal_backlog = self
+            .journal
+            .as_ref()
+            .map_or(0, |journal| journal.backlog().0);
+        self.metrics.storage_degraded.set(1);
+        self.metrics
+            .storage_buffered_writes
+            .set((buffered + journal_backlog) as u64);
+        self.metrics
+            .storage_degraded_seconds
+            .set(now.saturating_sub(*since));
+        let full =
+            buffered >= capacity || (journal_capacity > 0 && journal_backlog >= journal_capacity);
+        if !full {
+            return None;
+        }
+        if !*alerted_full {
+            *alerted_full = true;
+            alerts::raise(&format!(
+                "State storage write buffer full ({} writes, {} journal records); submissions are held until storage returns",
+                buffered, journal_backlog
+            ));
+        }
+        tracing::warn!("Holding submissions: state storage degraded ({})", failure);
+        Some(self.submitter.retry_delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        collections::BTreeMap,
+        sync::atomic::{AtomicBool, Ordering},
+    };
+
+    /// In-memory storage that fails every call while `down` is set
+    #[derive(Default)]
+    struct Flaky {
+        entries: Mutex<BTreeMap<String, Vec<u8>>>,
+        down: AtomicBool,
+    }
+
+    impl Flaky {
+        fn check(&self) -> Result<()> {
+            if self.down.load(Ordering::Relaxed) {
+                return Err(anyhow::anyhow!("disk full"));
+            }
+            Ok(())
+        }
+    }
+
+    impl StorageBackend for Flaky {
+        fn read(&self, key: &str) -> Result<Option<Vec<u8>>> {
+            self.check()?;
+            Ok(self.entries.lock().unwrap().get(key).cloned())
+        }
+
+        fn write(&self, key: &str, data: &[u8]) -> Result<()> {
+            self.check()?;
+            self.entries
+                .lock()
+                .unwrap()
+                .insert(key.to_string(), data.to_vec());
+            Ok(())
+        }
+
+        fn delete(&self, key: &str) -> Result<()> {
+            self.check()?;
+            self.entries.lock().unwrap().remove(key);
+            Ok(())
+        }
+
+        fn list(&self, prefix: &str) -> Result<Vec<String>> {
+            self.check()?;
+            Ok(self
+                .entries
+                .lock()
+                .unwrap()
+                .keys()
+                .filter(|key| key.starts_with(prefix))
+                .cloned()
+                .collect())
+        }
+    }
+
+    fn buffered(capacity: usize) -> (Arc<Flaky>, BufferedStorage) {
+        let inner = Arc::new(Flaky::default());
+        (inner.clone(), BufferedStorage::new(inner, capacity))
+    }
+
+    #[test]
+    fn writes_are_buffered_while_storage_is_down_and_flushed_in_order() {
+        let (inner, storage) = buffered(4);
+        storage.write("receipts/1", b"a").unwrap();
+        inner.down.store(true, Ordering::Relaxed);
+        storage.write("receipts/2", b"b").unwrap();
+        storage.write("receipts/2", b"c").unwrap();
+        storage.delete("receipts/1").unwrap();
+        assert_eq!(storage.depth(), (3, 4));
+        // 缓冲中的写入和删除优先应答读取
+        assert_eq!(storage.read("receipts/2").unwrap(), Some(b"c".to_vec()));
+        assert_eq!(storage.read("receipts/1").unwrap(), None);
+        assert!(storage.flush().is_err());
+
+        inner.down.store(false, Ordering::Relaxed);
+        assert_eq!(storage.list("receipts/").unwrap(), vec!["receipts/2"]);
+        assert_eq!(storage.flush().unwrap(), 3);
+        assert_eq!(inner.read("receipts/2").unwrap(), Some(b"c".to_vec()));
+        assert_eq!(inner.read("receipts/1").unwrap(), None);
+        assert_eq!(storage.depth().0, 0);
+    }
+
+    #[test]
+    fn a_full_buffer_and_write_through_fail_as_unavailable() {
+        let (inner, storage) = buffered(1);
+        inner.down.store(true, Ordering::Relaxed);
+        storage.write("a", b"1").unwrap();
+        let error = storage.write("b", b"2").unwrap_err();
+        assert!(storage_unavailable(&error).unwrap().contains("buffer full"));
+        let error = storage.write_through("inflight/1", b"3").unwrap_err();
+        assert!(storage_unavailable(&error).is_some());
+        assert!(storage_unavailable(&storage.read("c").unwrap_err()).is_some());
+
+        // 恢复后的下一次写入先写出缓冲
+        inner.down.store(false, Ordering::Relaxed);
+        storage.write("b", b"2").unwrap();
+        assert_eq!(inner.read("a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(storage.depth().0, 0);
+    }
+
+    #[test]
+    fn without_a_buffer_storage_errors_fail_at_once() {
+        let (inner, storage) = buffered(0);
+        inner.down.store(true, Ordering::Relaxed);
+        assert!(storage.write("a", b"1").is_err());
+    }
+}
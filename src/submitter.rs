@@ -0,0 +1,395 @@
+//! Submitter side of the relayer.
+//! Pulls nonces from the pending queue and relays them to L2 with its own
+//! pacing, retry delay and circuit breaker.
+
+use crate::{
+    alerts, anomaly::BreakerTripped, approval::AwaitingApproval, attempts::FailureClass,
+    cancellation::CancelledBeforeSend, context_slot::NodeBehind, journal::JournalEvent,
+    l2_limits::CapHeld, pda::SnapshotBehind, pda_gaps::PdaMissing, queue::RetryBackoff,
+    quiet_period::InQuietPeriod, replay_batch::ReplayInProgress, shutdown::ShuttingDown,
+    storage_buffer::storage_unavailable, throughput::Allowance, Relayer,
+};
+use anyhow::Result;
+use solana_client::client_error::ClientError;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy)]
+pub struct SubmitterSettings {
+    /// Nonces taken from the queue per batch
+    pub batch_size: usize,
+    /// Minimum pause between batches
+    pub interval: Duration,
+    /// Pause before retrying a failed batch
+    pub retry_delay: Duration,
+    /// Consecutive failed batches that open the circuit breaker
+    pub breaker_threshold: u32,
+    /// How long the circuit breaker stays open
+    pub breaker_cooldown: Duration,
+    /// Wait before a failed nonce is tried again
+    pub backoff: RetryBackoff,
+    /// Failed attempts after which a nonce is dead-lettered (0 never does)
+    pub max_attempts: u32,
+}
+
+/// What a submission batch did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Submitted {
+    /// Relayed this many nonces (none if nothing was pending)
+    Relayed(u64),
+    /// Sent nothing because the L1 or L2 program is paused, the L2 cluster is
+    /// unhealthy, the throughput limit is reached, a replay batch holds the
+    /// lease or the pending nonces are backing off, await approval or a quiet
+    /// period, were read in a stale L1 snapshot or from a lagging node, have
+    /// no transfer-info PDA yet or wait behind a dead letter; worth trying again after the given wait
+    Held(Duration),
+}
+
+/// A batch error that holds the nonce without counting as a failed attempt
+pub(crate) fn held_error(error: &anyhow::Error) -> Option<String> {
+    error
+        .downcast_ref::<AwaitingApproval>()
+        .map(ToString::to_string)
+        .or_else(|| {
+            error
+                .downcast_ref::<SnapshotBehind>()
+                .map(ToString::to_string)
+        })
+        .or_else(|| storage_unavailable(error))
+        .or_else(|| {
+            error
+                .downcast_ref::<ReplayInProgress>()
+                .map(ToString::to_string)
+        })
+        .or_else(|| error.downcast_ref::<CapHeld>().map(ToString::to_string))
+        .or_else(|| {
+            error
+                .downcast_ref::<BreakerTripped>()
+                .map(ToString::to_string)
+        })
+        .or_else(|| {
+            error
+                .downcast_ref::<InQuietPeriod>()
+                .map(ToString::to_string)
+        })
+        .or_else(|| {
+            error
+                .downcast_ref::<CancelledBeforeSend>()
+                .map(ToString::to_string)
+        })
+        .or_else(|| error.downcast_ref::<NodeBehind>().map(ToString::to_string))
+        .or_else(|| error.downcast_ref::<PdaMissing>().map(ToString::to_string))
+        .or_else(|| {
+            error
+                .downcast_ref::<ShuttingDown>()
+                .map(ToString::to_string)
+        })
+}
+
+impl Relayer {
+    /// Relays queued nonces until an unrecoverable error occurs or a shutdown
+    /// is requested
+    pub(crate) async fn submit_pending(&self) -> Result<()> {
+        self.standby_until_promoted().await;
+        if self.warmup {
+            self.warm_up().await?;
+        }
+        let mut consecutive_failures = 0u32;
+
+        loop {
+            tokio::select! {
+                () = self.queue.wait_for_pending() => {}
+                () = self.shutdown.wait() => {}
+            }
+            if self.shutdown.requested() {
+                return Ok(());
+            }
+            if let Some(batching) = &self.batching {
+                let decision = self.plan_batch(batching).await;
+                if !decision.wait.is_zero() {
+                    self.sleep_unless_stopping(decision.wait).await;
+                }
+            } else if let Some(coalescing) = &self.coalescing {
+                // 队列未满一批时等待窗口结束，让突发的转账进入同一批
+                if (self.queue.depth().0 as usize) < self.submitter.batch_size {
+                    self.sleep_unless_stopping(coalescing.window).await;
+                }
+            }
+            if self.shutdown.requested() {
+                return Ok(());
+            }
+
+            match self.submit_next_batch(consecutive_failures).await {
+                Ok(Submitted::Relayed(relayed)) => {
+                    consecutive_failures = 0;
+                    self.alert_failures(0, None);
+                    self.metrics.relayed_total.inc_by(relayed);
+                }
+                Ok(Submitted::Held(wait)) => {
+                    consecutive_failures = 0;
+                    self.alert_failures(0, None);
+                    self.sleep_unless_stopping(wait).await;
+                }
+                Err(e) => {
+                    consecutive_failures += 1;
+                    self.metrics.failed_batches_total.inc();
+                    self.alert_failures(consecutive_failures, Some(&e));
+                    self.notify_error(&e);
+                    tracing::warn!(
+                        "Submission batch failed ({} in a row): {}",
+                        consecutive_failures,
+                        e
+                    );
+
+                    if consecutive_failures >= self.submitter.breaker_threshold.max(1) {
+                        alerts::raise(&format!(
+                            "Circuit breaker open after {} consecutive failed batches, pausing submissions for {:?}: {}",
+                            consecutive_failures, self.submitter.breaker_cooldown, e
+                        ));
+                        self.sleep_unless_stopping(self.submitter.breaker_cooldown)
+                            .await;
+                        consecutive_failures = 0;
+                    } else {
+                        self.sleep_unless_stopping(self.submitter.retry_delay).await;
+                    }
+                }
+            }
+
+            self.record_queue_depth();
+            if !self.submitter.interval.is_zero() {
+                self.sleep_unless_stopping(self.submitter.interval).await;
+            }
+        }
+    }
+
+    /// Relays the next run of consecutive pending nonces.
+    /// `retries` is the number of batches that failed in a row before this one.
+    pub(crate) async fn submit_next_batch(&self, retries: u32) -> Result<Submitted> {
+        let balance = self.refresh_signers().await?;
+        self.ensure_min_balance(&self.signers.active().pubkey(), balance)?;
+        self.refresh_dead_letters()?;
+        if self.watched.paused() || self.manual_pause.paused() {
+            return Ok(Submitted::Held(self.submitter.retry_delay));
+        }
+        let tripped = self.anomaly.tripped(&self.state)?;
+        self.metrics
+            .anomaly_breaker_tripped
+            .set(tripped.is_some() as u64);
+        if tripped.is_some() {
+            return Ok(Submitted::Held(self.submitter.retry_delay));
+        }
+        if let Some(wait) = self.l2_health_hold().await {
+            return Ok(Submitted::Held(wait));
+        }
+        if let Some(wait) = self.l2_account_hold().await? {
+            return Ok(Submitted::Held(wait));
+        }
+        if let Some(wait) = self.storage_hold() {
+            return Ok(Submitted::Held(wait));
+        }
+        if let Some(wait) = self.replay_lease_hold()? {
+            return Ok(Submitted::Held(wait));
+        }
+        if let Some(selector) = &self.priority {
+            return self.submit_prioritized_batch(selector, retries).await;
+        }
+
+        // L2 nonce account 是已处理 nonce 的唯一依据
+        let relayed = self.read_l2_relayed_nonce().await?;
+        self.queue.prune_below(&self.state, relayed)?;
+
+        let batch = self.queue.peek_batch(self.batch_size());
+        let (Some(&from), Some(&last)) = (batch.first(), batch.last()) else {
+            return Ok(Submitted::Relayed(0));
+        };
+        let mut to = last + 1;
+
+        // 失败过的 nonce 在退避时间结束前不重试
+        if let Some(retry_at) = self.queue.retry_at(from) {
+            let now = self.clock.unix_timestamp();
+            if retry_at > now {
+                tracing::info!("Nonce {} backing off for {}s", from, retry_at - now);
+                return Ok(Submitted::Held(Duration::from_secs(retry_at - now)));
+            }
+        }
+
+        // 死信 nonce 之后的交易无法按序提交，停在死信处等待运维处理
+        if let Some(dead) = self.dead_letters.first_in(from, to) {
+            if dead == from {
+                return Ok(Submitted::Held(self.submitter.retry_delay));
+            }
+            to = dead;
+        }
+
+        // L1 nonce 回退时（可能是重组），不提交回退点之后的 nonce
+        if let Some(l1_nonce) = self.watched.rewound_to() {
+            if from >= l1_nonce {
+                tracing::info!(
+                    "L1 nonce went back to {}, holding nonces from {}",
+                    l1_nonce,
+                    from
+                );
+                return Ok(Submitted::Held(self.submitter.retry_delay));
+            }
+            to = to.min(l1_nonce);
+        }
+
+        if let Some(wait) = self.l2_pause_hold(to - from).await? {
+            return Ok(Submitted::Held(wait));
+        }
+        match self.throughput_allowance(to - from)? {
+            Allowance::Relay(allowed) => to = from + allowed,
+            Allowance::Hold(wait) => return Ok(Submitted::Held(wait)),
+        }
+
+        tracing::debug!("Processing nonce change...");
+        tracing::info!("Relaying nonces {}..{} from the pending queue", from, to);
+
+        self.ensure_wallet_funded().await?;
+        if let Err(e) = self.process_nonce_range(from, to, retries).await {
+            // 按序提交时，第一个未被 L2 处理的 nonce 就是失败的 nonce
+            let failed = self
+                .read_l2_relayed_nonce()
+                .await
+                .map_or(from, |relayed| relayed.clamp(from, to - 1));
+            if let Some(held) = held_error(&e) {
+                // 等待审批或 L1 快照落后不算失败，不消耗重试次数
+                tracing::info!("{}", held);
+                if failed == from {
+                    return Ok(Submitted::Held(self.submitter.retry_delay));
+                }
+                return self.complete_range(from, failed);
+            }
+            self.record_nonce_failure(failed, &e)?;
+            self.expire_l2_pause();
+            return Err(e);
+        }
+        self.complete_range(from, to)
+    }
+
+    /// Wait before trying again if the L2 program is paused, for a batch of
+    /// `batch_len` nonces
+    pub(crate) async fn l2_pause_hold(&self, batch_len: u64) -> Result<Option<Duration>> {
+        if !self.l2_paused(batch_len).await? {
+            return Ok(None);
+        }
+        let wait = self
+            .l2_pause
+            .as_ref()
+            .map_or(self.submitter.retry_delay, |watch| watch.interval());
+        tracing::info!("L2 program paused, holding {} nonce(s)", batch_len);
+        Ok(Some(wait))
+    }
+
+    /// Re-reads the L2 paused flag before the next batch; a failed batch may
+    /// have been rejected with `BridgePaused`
+    pub(crate) fn expire_l2_pause(&self) {
+        if let Some(watch) = &self.l2_pause {
+            watch.expire();
+        }
+    }
+
+    /// Moves the queue past `from..to`, which landed on L2
+    fn complete_range(&self, from: u64, to: u64) -> Result<Submitted> {
+        if let Some(accounts) = &self.message_accounts {
+            accounts.mark_processed(&self.state, from, to)?;
+        }
+        self.queue.prune_below(&self.state, to)?;
+        Ok(Submitted::Relayed(to - from))
+    }
+
+    /// Schedules the next attempt of a failed nonce and dead-letters it once
+    /// it has failed `max_attempts` times
+    pub(crate) fn record_nonce_failure(&self, nonce: u64, error: &anyhow::Error) -> Result<()> {
+        let failure = self.attempts.fail(
+            nonce,
+            self.queue.attempts(nonce) + 1,
+            &error.to_string(),
+            self.clock.now(),
+        );
+        let expires_at = failure.as_ref().and_then(|failure| failure.expires_at);
+        // L1 读取失败已在读取时计数，这里只统计 L2 请求
+        let on_l2 = failure
+            .as_ref()
+            .is_some_and(|failure| failure.class != FailureClass::Fetch);
+        if on_l2 && error.chain().any(|cause| cause.is::<ClientError>()) {
+            self.metrics.rpc_errors_total.inc("l2");
+        }
+        // 区块哈希过期的交易不会再落地，可以立即重建重发
+        let backoff = match &failure {
+            Some(failure) if failure.class == FailureClass::Expired => RetryBackoff::IMMEDIATE,
+            _ => self.submitter.backoff,
+        };
+        let retry = self.queue.record_failure(
+            &self.state,
+            nonce,
+            &error.to_string(),
+            self.clock.unix_timestamp(),
+            backoff,
+            failure,
+        )?;
+        if let Some(expires_at) = expires_at {
+            self.alert_near_deadline(nonce, expires_at, retry.attempts, error);
+        }
+        let max_attempts = self.submitter.max_attempts;
+        if max_attempts > 0 && retry.attempts >= max_attempts {
+            self.dead_letter(
+                nonce,
+                &format!("failed {} attempts, last: {}", retry.attempts, error),
+            )?;
+        } else {
+            tracing::info!(
+                "Nonce {} failed {} time(s), next attempt at {}",
+                nonce,
+                retry.attempts,
+                retry.next_retry_at
+            );
+            self.journal(
+                None,
+                JournalEvent::RetryScheduled {
+                    nonce,
+                    attempts: retry.attempts,
+                    next_retry_at: retry.next_retry_at,
+                    error: retry.last_error,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// Tops up the relayer wallet in dev mode
+    pub(crate) async fn ensure_wallet_funded(&self) -> Result<()> {
+        if let Some(funder) = &self.airdrop_funder {
+            funder
+                .ensure_funded(
+                    &self.l2_client,
+                    self.clock.as_ref(),
+                    &self.signers.active().pubkey(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Exports the queue depth and the sizes of the other long-lived
+    /// collections
+    pub(crate) fn record_queue_depth(&self) {
+        let (queued, spilled) = self.queue.depth();
+        self.metrics.queue_depth.set(queued);
+        self.metrics.queue_overflow.set(spilled);
+        self.metrics
+            .retries_tracked
+            .set(self.queue.retry_count() as u64);
+        self.metrics.in_flight.set(self.in_flight.len() as u64);
+        self.metrics
+            .dead_letters
+            .set(self.dead_letters.len() as u64);
+        self.metrics
+            .enrichment_pending
+            .set(self.enrichment.len() as u64);
+        self.metrics.priority_tracked.set(
+            self.priority
+                .as_ref()
+                .map_or(0, |selector| selector.tracked()) as u64,
+        );
+    }
+}
@@ -0,0 +1,128 @@
+//! WebSocket subscription to the watched account.
+//! With `l1_watch_mode = "subscribe"`, the watched account is followed with
+//! `accountSubscribe` on `l1_ws_url` (by default derived from `l1_url`), and
+//! every notification wakes the monitor at once instead of after the poll
+//! interval. The monitor still reads the nonces the same way, asking for at
+//! least the notified slot, and still polls every `poll_interval_max_ms` in
+//! case a notification is missed. When the connection drops or cannot be
+//! opened the monitor falls back to polling at the adaptive interval, and the
+//! subscription is retried after 1 second, doubling up to 30.
+
+use crate::{context_slot::account_config, secrets::SecretUrl, Relayer};
+use anyhow::Result;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use solana_client::nonblocking::pubsub_client::{PubsubClient, PubsubClientError};
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use tokio::sync::Notify;
+
+/// First pause before the subscription is opened again
+const RECONNECT_MIN: Duration = Duration::from_secs(1);
+/// Longest pause between attempts to open the subscription
+const RECONNECT_MAX: Duration = Duration::from_secs(30);
+
+/// How the monitor learns that the watched account changed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchMode {
+    /// Reads the account every poll interval
+    #[default]
+    Poll,
+    /// Reads it when notified over WebSocket, polling while disconnected
+    Subscribe,
+}
+
+/// The subscription endpoint and whether it currently delivers notifications
+pub struct AccountSubscription {
+    url: SecretUrl,
+    connected: AtomicBool,
+    changed: Notify,
+}
+
+impl AccountSubscription {
+    pub fn new(url: SecretUrl) -> Self {
+        Self {
+            url,
+            connected: AtomicBool::new(false),
+            changed: Notify::new(),
+        }
+    }
+}
+
+impl Relayer {
+    /// Waits out the poll `interval`, or until the watched account is
+    /// notified while the subscription is up
+    pub(crate) async fn wait_for_l1(&self, interval: Duration) {
+        match &self.subscription {
+            Some(subscription) if subscription.connected.load(Ordering::Relaxed) => {
+                tokio::select! {
+                    _ = subscription.changed.notified() => {}
+                    _ = self.clock.sleep(self.poll.max()) => {}
+                }
+            }
+            _ => self.clock.sleep(interval).await,
+        }
+    }
+
+    /// Keeps the subscription open, reopening it when it drops; never returns
+    pub(crate) async fn watch_l1_account(&self) -> Result<()> {
+        let Some(subscription) = &self.subscription else {
+            return futures::future::pending().await;
+        };
+        let mut backoff = RECONNECT_MIN;
+        loop {
+            let result = self.follow_l1_account(subscription).await;
+            // 断线或连接失败时唤醒 monitor，立即改为轮询
+            if subscription.connected.swap(false, Ordering::Relaxed) {
+                backoff = RECONNECT_MIN;
+            }
+            self.metrics.l1_subscription_connected.set(0);
+            self.metrics.l1_subscription_failures_total.inc();
+            subscription.changed.notify_one();
+            match result {
+                Ok(()) => tracing::warn!(
+                    "WebSocket subscription to the watched account closed; polling, reconnecting in {:?}",
+                    backoff
+                ),
+                Err(e) => tracing::warn!(
+                    "WebSocket subscription to the watched account failed: {:?}; polling, retrying in {:?}",
+                    e, backoff
+                ),
+            }
+            self.clock.sleep(backoff).await;
+            backoff = (backoff * 2).min(RECONNECT_MAX);
+        }
+    }
+
+    /// Subscribes once and forwards notifications until the stream ends
+    async fn follow_l1_account(
+        &self,
+        subscription: &AccountSubscription,
+    ) -> Result<(), PubsubClientError> {
+        let client = PubsubClient::new(subscription.url.expose()).await?;
+        let (mut notifications, _unsubscribe) = client
+            .account_subscribe(
+                self.watched_account.pubkey(),
+                Some(account_config(self.l1_client.commitment(), None)),
+            )
+            .await?;
+        subscription.connected.store(true, Ordering::Relaxed);
+        self.metrics.l1_subscription_connected.set(1);
+        tracing::info!(
+            "Subscribed to the watched account over {}",
+            subscription.url
+        );
+        while let Some(update) = notifications.next().await {
+            // 之后的读取至少要看到通知所在的 slot
+            self.context_slots.l1.observe(update.context.slot);
+            self.metrics.l1_subscription_notifications_total.inc();
+            subscription.changed.notify_one();
+        }
+        drop(notifications);
+        let _ = client.shutdown().await;
+        Ok(())
+    }
+}
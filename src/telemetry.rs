@@ -0,0 +1,426 @@
+//! Logging and distributed tracing setup.
+//! Log lines go through `tracing` fmt layers set by `[logging]`: stderr, a
+//! file or both, as text or one JSON object per line, filtered by `level` and
+//! per-module `modules` (or `RUST_LOG`, when set). The file is opened in
+//! append mode and opened again at the same path on SIGUSR1, so an external
+//! rotator can move it away and signal the relayer. A running relayer applies
+//! the filter set with `log-level`, read from the state store, within
+//! `LOG_LEVEL_POLL`. When an OTLP endpoint is configured, spans are
+//! additionally exported to it, in builds with the `telemetry` feature.
+
+use crate::{
+    clock::Clock,
+    config::{LogDestination, LogFormat, LoggingConfig, TracingConfig},
+    state::StateStore,
+};
+use anyhow::Result;
+#[cfg(feature = "telemetry")]
+use opentelemetry::KeyValue;
+#[cfg(feature = "telemetry")]
+use opentelemetry_otlp::WithExportConfig;
+#[cfg(feature = "telemetry")]
+use opentelemetry_sdk::{
+    trace::{self, Sampler},
+    Resource,
+};
+use serde_json::{json, Map, Value};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+use tracing::{
+    field::{Field, Visit},
+    Event, Subscriber,
+};
+use tracing_subscriber::{
+    filter::LevelFilter,
+    fmt::{
+        self,
+        format::Writer,
+        time::{FormatTime, SystemTime},
+        FmtContext, FormatEvent, FormatFields, FormattedFields, MakeWriter,
+    },
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    reload,
+    util::SubscriberInitExt,
+    EnvFilter, Layer, Registry,
+};
+
+/// State key holding the log filter set with `log-level`
+pub const LOG_LEVEL_KEY: &str = "log_level";
+
+/// How often a running relayer reads the `log-level` filter
+const LOG_LEVEL_POLL: Duration = Duration::from_secs(5);
+
+/// Flushes exported spans when dropped
+pub struct TelemetryGuard {
+    exporting: bool,
+    logging: LoggingConfig,
+    filter: reload::Handle<EnvFilter, Registry>,
+    /// Filter set at runtime, none for the startup filter
+    applied: Mutex<Option<String>>,
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        if self.exporting {
+            #[cfg(feature = "telemetry")]
+            opentelemetry::global::shutdown_tracer_provider();
+        }
+    }
+}
+
+impl TelemetryGuard {
+    /// Filter set at runtime, none while the startup filter applies
+    #[cfg(feature = "admin")]
+    pub fn runtime_filter(&self) -> Option<String> {
+        self.applied.lock().unwrap().clone()
+    }
+
+    /// Applies the `directives` filter, or the startup filter again when
+    /// none; returns whether the filter changed
+    pub fn apply(&self, directives: Option<&str>) -> Result<bool> {
+        let mut applied = self.applied.lock().unwrap();
+        if applied.as_deref() == directives {
+            return Ok(false);
+        }
+        let filter = match directives {
+            Some(directives) => parse_filter(directives)?,
+            None => startup_filter(&self.logging)?,
+        };
+        self.filter
+            .reload(filter)
+            .map_err(|e| anyhow::anyhow!("Failed to reload the log filter: {}", e))?;
+        *applied = directives.map(str::to_string);
+        Ok(true)
+    }
+
+    /// Applies the filter of `log-level` whenever it changes in `state`, and
+    /// the startup filter again once it is reset; never returns
+    pub async fn follow_log_level(&self, state: &StateStore, clock: &dyn Clock) {
+        let mut followed: Option<String> = None;
+        loop {
+            match state.get::<String>(LOG_LEVEL_KEY) {
+                // 只跟随存储中的变化，admin API 设置的过滤器同样写入存储
+                Ok(wanted) if wanted != followed => {
+                    match (self.apply(wanted.as_deref()), &wanted) {
+                        (Ok(false), _) => {}
+                        (Ok(true), Some(directives)) => {
+                            tracing::info!("Log filter set to {}", directives)
+                        }
+                        (Ok(true), None) => tracing::info!("Log filter restored"),
+                        (Err(e), _) => {
+                            tracing::warn!("failed to change the log filter: {:#}", e)
+                        }
+                    }
+                    followed = wanted;
+                }
+                Ok(_) => {}
+                Err(e) => tracing::warn!("failed to read the log filter: {}", e),
+            }
+            clock.sleep(LOG_LEVEL_POLL).await;
+        }
+    }
+}
+
+/// Parses an `EnvFilter` directive list, e.g. `info,sol_bridge_relayer::pipeline=debug`
+pub fn parse_filter(directives: &str) -> Result<EnvFilter> {
+    EnvFilter::try_new(directives)
+        .map_err(|e| anyhow::anyhow!("Invalid log filter {}: {}", directives, e))
+}
+
+/// Filter of `level` and `modules`
+fn configured_filter(config: &LoggingConfig) -> Result<EnvFilter> {
+    let level = |field: &str, level: &str| {
+        LevelFilter::from_str(level).map_err(|_| {
+            anyhow::anyhow!(
+                "Invalid {} {}: expected off, error, warn, info, debug or trace",
+                field,
+                level
+            )
+        })
+    };
+    let mut directives = vec![level("logging.level", &config.level)?.to_string()];
+    for (module, module_level) in &config.modules {
+        if module.is_empty() || module.contains([',', '=', '[', ' ']) {
+            return Err(anyhow::anyhow!(
+                "Invalid module {:?} in logging.modules",
+                module
+            ));
+        }
+        let module_level = level(&format!("logging.modules.{}", module), module_level)?;
+        directives.push(format!("{}={}", module, module_level));
+    }
+    parse_filter(&directives.join(","))
+}
+
+/// `RUST_LOG` when set, else the configured levels, which are checked either way
+fn startup_filter(config: &LoggingConfig) -> Result<EnvFilter> {
+    let configured = configured_filter(config)?;
+    Ok(EnvFilter::try_from_default_env().unwrap_or(configured))
+}
+
+/// Log file in append mode that can be opened again at the same path, for
+/// external rotation
+#[derive(Clone)]
+struct LogFile {
+    path: PathBuf,
+    file: Arc<Mutex<File>>,
+}
+
+impl LogFile {
+    fn open(path: PathBuf) -> Result<Self> {
+        let file = Self::open_file(&path)?;
+        Ok(Self {
+            path,
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    fn open_file(path: &PathBuf) -> Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| anyhow::anyhow!("Failed to open log file {}: {}", path.display(), e))
+    }
+
+    /// Opens the path again; lines keep going to the old file on failure
+    fn reopen(&self) -> Result<()> {
+        let file = Self::open_file(&self.path)?;
+        *self.file.lock().unwrap_or_else(PoisonError::into_inner) = file;
+        Ok(())
+    }
+}
+
+/// Holds the log file for one log line
+struct LogFileWriter<'a>(MutexGuard<'a, File>);
+
+impl Write for LogFileWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogFile {
+    type Writer = LogFileWriter<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        LogFileWriter(self.file.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+/// Fields of an event as JSON values
+#[derive(Default)]
+struct JsonFields(Map<String, Value>);
+
+impl Visit for JsonFields {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), json!(format!("{:?}", value)));
+    }
+}
+
+/// One JSON object per event: time, level, target, fields and the spans
+/// from the root, each with its formatted fields
+struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        let mut timestamp = String::new();
+        SystemTime.format_time(&mut Writer::new(&mut timestamp))?;
+        let mut fields = JsonFields::default();
+        event.record(&mut fields);
+        let spans: Vec<Value> = ctx
+            .event_scope()
+            .into_iter()
+            .flat_map(|scope| scope.from_root())
+            .map(|span| {
+                let extensions = span.extensions();
+                let fields = extensions
+                    .get::<FormattedFields<N>>()
+                    .map(|fields| fields.fields.as_str())
+                    .unwrap_or_default();
+                json!({ "name": span.name(), "fields": fields })
+            })
+            .collect();
+        let metadata = event.metadata();
+        // `log` 宏的事件以 log.* 字段带来真正的 target
+        let target = match fields.0.remove("log.target") {
+            Some(Value::String(target)) => target,
+            _ => metadata.target().to_string(),
+        };
+        for field in ["log.module_path", "log.file", "log.line"] {
+            fields.0.remove(field);
+        }
+        let line = json!({
+            "timestamp": timestamp,
+            "level": metadata.level().as_str(),
+            "target": target,
+            "fields": fields.0,
+            "spans": spans,
+        });
+        writeln!(writer, "{}", line)
+    }
+}
+
+/// Fmt layer of `format` writing to `writer`
+fn log_layer<W>(format: LogFormat, writer: W, ansi: bool) -> Box<dyn Layer<Registry> + Send + Sync>
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let layer = fmt::layer().with_writer(writer).with_ansi(ansi);
+    match format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer.event_format(JsonFormat).boxed(),
+    }
+}
+
+/// Layer exporting spans to `otlp_endpoint`, none when it is unset
+#[cfg(feature = "telemetry")]
+fn otlp_layer<S>(config: &TracingConfig) -> Result<Option<impl Layer<S>>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let Some(endpoint) = &config.otlp_endpoint else {
+        return Ok(None);
+    };
+    let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+        config.sample_ratio.clamp(0.0, 1.0),
+    )));
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(endpoint.expose()),
+        )
+        .with_trace_config(
+            trace::config()
+                .with_sampler(sampler)
+                .with_resource(Resource::new(vec![KeyValue::new(
+                    "service.name",
+                    config.service_name.clone(),
+                )])),
+        )
+        .install_batch(opentelemetry_sdk::runtime::Tokio)
+        .map_err(|e| anyhow::anyhow!("Failed to start OTLP exporter: {}", e))?;
+    Ok(Some(
+        tracing_opentelemetry::layer()
+            .with_tracer(tracer)
+            .with_filter(LevelFilter::INFO),
+    ))
+}
+
+/// Builds without the `telemetry` feature export nothing and refuse
+/// `otlp_endpoint`
+#[cfg(not(feature = "telemetry"))]
+fn otlp_layer(config: &TracingConfig) -> Result<Option<tracing_subscriber::layer::Identity>> {
+    match &config.otlp_endpoint {
+        Some(_) => Err(anyhow::anyhow!(
+            "tracing.otlp_endpoint requires a build with --features telemetry"
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Installs the global subscriber. Must be called from within the tokio runtime.
+/// Fails on an invalid `[logging]` section or a log file that cannot be opened.
+pub fn init(config: &TracingConfig, logging: &LoggingConfig) -> Result<TelemetryGuard> {
+    let (log_filter, filter) = reload::Layer::new(startup_filter(logging)?);
+    let file = match (logging.destination, &logging.path) {
+        (LogDestination::Stderr, _) => None,
+        (_, Some(path)) => Some(LogFile::open(PathBuf::from(path))?),
+        (_, None) => {
+            return Err(anyhow::anyhow!(
+                "logging.path is required with destination = \"file\" or \"both\""
+            ))
+        }
+    };
+    // 各层共用格式化后的 span 字段，只在仅输出到 stderr 时着色
+    let ansi = logging.destination == LogDestination::Stderr && logging.format == LogFormat::Text;
+    let mut log_layers = Vec::new();
+    if logging.destination != LogDestination::File {
+        log_layers.push(log_layer(logging.format, io::stderr, ansi));
+    }
+    if let Some(file) = &file {
+        log_layers.push(log_layer(logging.format, file.clone(), false));
+    }
+
+    let otel_layer = otlp_layer(config)?;
+    let exporting = otel_layer.is_some();
+
+    tracing_subscriber::registry()
+        .with(log_layers.with_filter(log_filter))
+        .with(otel_layer)
+        .try_init()
+        .map_err(|e| anyhow::anyhow!("Failed to install tracing subscriber: {}", e))?;
+
+    #[cfg(unix)]
+    if let Some(file) = file {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut signals = signal(SignalKind::user_defined1())
+            .map_err(|e| anyhow::anyhow!("Failed to listen for SIGUSR1: {}", e))?;
+        tokio::spawn(async move {
+            while signals.recv().await.is_some() {
+                // 外部轮转工具已移走旧文件，重新按路径打开
+                if let Err(e) = file.reopen() {
+                    tracing::warn!("{}", e);
+                }
+            }
+        });
+    }
+
+    if let Some(endpoint) = &config.otlp_endpoint {
+        tracing::info!(
+            "Exporting traces to {} (sample ratio {})",
+            endpoint,
+            config.sample_ratio
+        );
+    }
+
+    Ok(TelemetryGuard {
+        exporting,
+        logging: logging.clone(),
+        filter,
+        applied: Mutex::new(None),
+    })
+}
@@ -0,0 +1,424 @@
+//! End-to-end harness against `solana-test-validator`, enabled with the
+//! `testing` feature.
+//! `TestValidator` starts a validator on local ports with a `Fixture`
+//! preloaded: programs from `.so` files and accounts written as the JSON
+//! files `--account` takes, so L1 state (the watched account and the
+//! transfer-info PDAs, owned by the L1 program id) exists without running
+//! the L1 program. `e2e` builds the fixture of the first source of a config
+//! (on one validator serving both clusters, or one each), relays it with a
+//! `RelayerEngine` through the real RPC clients and checks the recipients'
+//! L2 balances. The L2 bridge program has to be deployed from a build of
+//! it: the relay instruction runs there.
+//!
+//! The same helpers test other configs from the embedding service:
+//!
+//! ```ignore
+//! let mut fixture = Fixture::default();
+//! fixture.program(l2_program_id, "target/deploy/l2_bridge.so");
+//! fixture.account(watched_account, l1_program_id, &2u64.to_le_bytes());
+//! let validator = TestValidator::start(&ValidatorOptions::default(), &fixture).await?;
+//! config.l1_url = SecretUrl::new(validator.rpc_url());
+//! config.l2_url = SecretUrl::new(validator.rpc_url());
+//! let engine = RelayerEngine::new(&config).await?;
+//! engine.run_once().await?;
+//! assert_eq!(validator.client().get_balance(&recipient).await?, 1_000_000_000);
+//! ```
+
+use crate::{
+    config::RelayerConfig,
+    engine::RelayerEngine,
+    merkle::MessageSource,
+    pda::PdaManager,
+    secrets::{Redacted, SecretUrl},
+    testing::transfer_info_data,
+};
+use anyhow::Result;
+use serde::Serialize;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    native_token::LAMPORTS_PER_SOL,
+    pubkey::Pubkey,
+    signature::{write_keypair_file, Keypair, Signer},
+    system_program,
+};
+use std::{
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant},
+};
+
+/// How long a validator may take to answer its first request
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+/// Ports each validator takes above its RPC port
+const PORTS_PER_VALIDATOR: u16 = 100;
+/// Size of the L2 nonce account the harness writes
+const NONCE_ACCOUNT_SIZE: usize = 24;
+/// Lamports of every preloaded data account, enough to be rent exempt
+const ACCOUNT_LAMPORTS: u64 = LAMPORTS_PER_SOL;
+/// Lamports the relayer's wallet starts with
+const WALLET_LAMPORTS: u64 = 1_000 * LAMPORTS_PER_SOL;
+
+/// Programs and accounts a validator starts with
+#[derive(Debug, Clone, Default)]
+pub struct Fixture {
+    programs: Vec<(Pubkey, PathBuf)>,
+    accounts: Vec<(Pubkey, Pubkey, u64, Vec<u8>)>,
+}
+
+impl Fixture {
+    /// Deploys the program built at `path` (a `.so`) at `program_id`
+    pub fn program(&mut self, program_id: Pubkey, path: impl Into<PathBuf>) -> &mut Self {
+        self.programs.push((program_id, path.into()));
+        self
+    }
+
+    /// Writes `data` at `address`, owned by `owner`
+    pub fn account(&mut self, address: Pubkey, owner: Pubkey, data: &[u8]) -> &mut Self {
+        self.accounts
+            .push((address, owner, ACCOUNT_LAMPORTS, data.to_vec()));
+        self
+    }
+
+    /// Credits `lamports` to the system account `address`
+    pub fn fund(&mut self, address: Pubkey, lamports: u64) -> &mut Self {
+        self.accounts
+            .push((address, system_program::id(), lamports, Vec::new()));
+        self
+    }
+}
+
+/// Where and how validators are started
+#[derive(Debug, Clone)]
+pub struct ValidatorOptions {
+    /// The `solana-test-validator` binary
+    pub binary: PathBuf,
+    /// RPC port of the first validator; the next ones start
+    /// `PORTS_PER_VALIDATOR` higher
+    pub rpc_port: u16,
+    /// Ledger and account files; a directory of its own per validator
+    pub work_dir: PathBuf,
+}
+
+impl Default for ValidatorOptions {
+    fn default() -> Self {
+        Self {
+            binary: PathBuf::from("solana-test-validator"),
+            rpc_port: 18_899,
+            work_dir: std::env::temp_dir()
+                .join(format!("sol-bridge-relayer-e2e-{}", std::process::id())),
+        }
+    }
+}
+
+/// A running `solana-test-validator`, stopped when dropped
+pub struct TestValidator {
+    child: Child,
+    rpc_port: u16,
+}
+
+impl TestValidator {
+    /// Starts a validator with `fixture` loaded on `options.rpc_port` and
+    /// waits until it answers
+    pub async fn start(options: &ValidatorOptions, fixture: &Fixture) -> Result<Self> {
+        let port = options.rpc_port;
+        let dir = options.work_dir.join(format!("validator-{}", port));
+        std::fs::create_dir_all(dir.join("accounts"))?;
+
+        let mut command = Command::new(&options.binary);
+        command
+            .arg("--ledger")
+            .arg(dir.join("ledger"))
+            .arg("--reset")
+            .arg("--quiet")
+            .args(["--bind-address", "127.0.0.1"])
+            .args(["--rpc-port", &port.to_string()])
+            .args(["--faucet-port", &(port + 2).to_string()])
+            .args(["--gossip-port", &(port + 3).to_string()])
+            .args([
+                "--dynamic-port-range",
+                &format!("{}-{}", port + 10, port + PORTS_PER_VALIDATOR - 1),
+            ]);
+        for (program_id, path) in &fixture.programs {
+            if !path.is_file() {
+                return Err(anyhow::anyhow!("Program {} not found", path.display()));
+            }
+            command
+                .arg("--bpf-program")
+                .arg(program_id.to_string())
+                .arg(path);
+        }
+        for (address, owner, lamports, data) in &fixture.accounts {
+            let file = dir.join("accounts").join(format!("{}.json", address));
+            write_account_file(&file, address, owner, *lamports, data)?;
+            command.arg("--account").arg(address.to_string()).arg(file);
+        }
+        let child = command
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| anyhow::anyhow!("Failed to start {}: {}", options.binary.display(), e))?;
+        let mut validator = Self {
+            child,
+            rpc_port: port,
+        };
+        validator.wait_ready().await?;
+        Ok(validator)
+    }
+
+    pub fn rpc_url(&self) -> String {
+        format!("http://127.0.0.1:{}", self.rpc_port)
+    }
+
+    /// An `RpcClient` of this validator at `confirmed`
+    pub fn client(&self) -> RpcClient {
+        RpcClient::new_with_commitment(self.rpc_url(), CommitmentConfig::confirmed())
+    }
+
+    async fn wait_ready(&mut self) -> Result<()> {
+        let client = self.client();
+        let started = Instant::now();
+        loop {
+            if let Some(status) = self.child.try_wait()? {
+                return Err(anyhow::anyhow!(
+                    "solana-test-validator on port {} exited with {}",
+                    self.rpc_port,
+                    status
+                ));
+            }
+            // 首个区块产出前 getSlot 也会失败
+            if client.get_slot().await.is_ok() {
+                return Ok(());
+            }
+            if started.elapsed() > STARTUP_TIMEOUT {
+                return Err(anyhow::anyhow!(
+                    "solana-test-validator on port {} did not answer within {:?}",
+                    self.rpc_port,
+                    STARTUP_TIMEOUT
+                ));
+            }
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+    }
+}
+
+impl Drop for TestValidator {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Writes an account in the `solana account --output json` format
+fn write_account_file(
+    path: &Path,
+    address: &Pubkey,
+    owner: &Pubkey,
+    lamports: u64,
+    data: &[u8],
+) -> Result<()> {
+    let account = serde_json::json!({
+        "pubkey": address.to_string(),
+        "account": {
+            "lamports": lamports,
+            "data": [base64::encode(data), "base64"],
+            "owner": owner.to_string(),
+            "executable": false,
+            "rentEpoch": 0,
+        },
+    });
+    std::fs::write(path, serde_json::to_vec_pretty(&account)?)
+        .map_err(|e| anyhow::anyhow!("Failed to write {}: {}", path.display(), e))
+}
+
+/// What `e2e` relays
+#[derive(Debug, Clone)]
+pub struct E2eOptions {
+    pub validator: ValidatorOptions,
+    /// Build of the L2 bridge program
+    pub l2_program: PathBuf,
+    /// Build of the L1 bridge program, deployed when given
+    pub l1_program: Option<PathBuf>,
+    /// Transfers waiting on L1
+    pub transfers: u64,
+    /// Lamports of every transfer
+    pub amount: u64,
+    /// Run L1 and L2 on validators of their own
+    pub two_validators: bool,
+    /// How long the transfers may take to land
+    pub timeout: Duration,
+}
+
+/// One transfer of an `e2e` run
+#[derive(Debug, Clone, Serialize)]
+pub struct E2eTransfer {
+    pub nonce: u64,
+    pub recipient: String,
+    pub expected: u64,
+    pub balance: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct E2eReport {
+    pub transfers: Vec<E2eTransfer>,
+    /// Relayed nonce the L2 nonce account ends at
+    pub l2_nonce: u64,
+    pub iterations: u64,
+    pub elapsed_secs: f64,
+    pub passed: bool,
+}
+
+impl E2eReport {
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for transfer in &self.transfers {
+            text.push_str(&format!(
+                "Nonce {}: {} has {} lamports, expected {}{}\n",
+                transfer.nonce,
+                transfer.recipient,
+                transfer.balance,
+                transfer.expected,
+                if transfer.balance == transfer.expected {
+                    ""
+                } else {
+                    " (mismatch)"
+                }
+            ));
+        }
+        text.push_str(&format!(
+            "L2 nonce {} after {} iteration(s) in {:.1}s: {}\n",
+            self.l2_nonce,
+            self.iterations,
+            self.elapsed_secs,
+            if self.passed { "passed" } else { "failed" }
+        ));
+        text
+    }
+}
+
+/// Relays `options.transfers` transfers of the first source of `config`
+/// through validators started for the run
+pub async fn run(config: &RelayerConfig, options: &E2eOptions) -> Result<E2eReport> {
+    if !config.bridges.is_empty() {
+        return Err(anyhow::anyhow!(
+            "e2e runs a single bridge; remove the [[bridges]] entries"
+        ));
+    }
+    if config.message_source != MessageSource::Pda {
+        return Err(anyhow::anyhow!(
+            "e2e writes transfer-info PDAs only; set message_source = \"pda\""
+        ));
+    }
+    let source = config.sources()?.remove(0);
+    let l1_program_id = *source.l1_program_id.pubkey();
+    let watched = *source.watched_account.pubkey();
+    let l2_program_id = *config.l2_program_id.pubkey();
+    let nonce_account = *source.nonce_account.pubkey();
+
+    let keypair = Keypair::new();
+    let recipients: Vec<Pubkey> = (0..options.transfers)
+        .map(|_| Keypair::new().pubkey())
+        .collect();
+    let pdas = PdaManager::new(source.l1_program_id, source.watched_account, source.seeds);
+    let mut l1 = Fixture::default();
+    if let Some(path) = &options.l1_program {
+        l1.program(l1_program_id, path);
+    }
+    l1.account(watched, l1_program_id, &options.transfers.to_le_bytes());
+    for (nonce, recipient) in recipients.iter().enumerate() {
+        l1.account(
+            pdas.find_address(nonce as u64).0,
+            l1_program_id,
+            &transfer_info_data(options.amount, recipient),
+        );
+    }
+    let mut l2 = Fixture::default();
+    l2.program(l2_program_id, &options.l2_program)
+        .account(nonce_account, l2_program_id, &[0; NONCE_ACCOUNT_SIZE])
+        .fund(keypair.pubkey(), WALLET_LAMPORTS);
+
+    std::fs::create_dir_all(&options.validator.work_dir)?;
+    let (l1_validator, l2_validator) = if options.two_validators {
+        let l1_validator = TestValidator::start(&options.validator, &l1).await?;
+        let l2_options = ValidatorOptions {
+            rpc_port: options.validator.rpc_port + PORTS_PER_VALIDATOR,
+            ..options.validator.clone()
+        };
+        (
+            Some(l1_validator),
+            TestValidator::start(&l2_options, &l2).await?,
+        )
+    } else {
+        l2.programs.extend(l1.programs);
+        l2.accounts.extend(l1.accounts);
+        (None, TestValidator::start(&options.validator, &l2).await?)
+    };
+    let l1_url = l1_validator.as_ref().unwrap_or(&l2_validator).rpc_url();
+
+    let wallet = options.validator.work_dir.join("wallet.json");
+    write_keypair_file(&keypair, &wallet)
+        .map_err(|e| anyhow::anyhow!("Failed to write {}: {}", wallet.display(), e))?;
+    let mut config = config.clone();
+    config.l1_url = SecretUrl::new(l1_url);
+    config.l2_url = SecretUrl::new(l2_validator.rpc_url());
+    config.l1_urls.clear();
+    config.l2_urls.clear();
+    config.l1_verify_urls.clear();
+    config.wallet_path = Redacted::new(wallet.display().to_string());
+    config.secondary_wallet_path = None;
+    // 账本每次都重置，旧的状态会让中继跳过这些 nonce
+    let state_dir = options.validator.work_dir.join("state");
+    if state_dir.exists() {
+        std::fs::remove_dir_all(&state_dir)?;
+    }
+    config.state_dir = state_dir.display().to_string();
+    config.dev_mode = false;
+
+    let engine = RelayerEngine::new(&config).await?;
+    let client = l2_validator.client();
+    let started = Instant::now();
+    let mut iterations = 0;
+    let mut balances = vec![0; recipients.len()];
+    loop {
+        iterations += 1;
+        if let Err(e) = engine.run_once().await {
+            tracing::warn!("iteration {} failed: {}", iterations, e);
+        }
+        for (balance, recipient) in balances.iter_mut().zip(&recipients) {
+            *balance = client.get_balance(recipient).await?;
+        }
+        if balances.iter().all(|balance| *balance >= options.amount)
+            || started.elapsed() > options.timeout
+        {
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    let data = client.get_account_data(&nonce_account).await?;
+    let l2_nonce = data
+        .get(8..16)
+        .map_or(0, |bytes| u64::from_le_bytes(bytes.try_into().unwrap()));
+
+    let transfers: Vec<E2eTransfer> = recipients
+        .iter()
+        .zip(balances)
+        .enumerate()
+        .map(|(nonce, (recipient, balance))| E2eTransfer {
+            nonce: nonce as u64,
+            recipient: recipient.to_string(),
+            expected: options.amount,
+            balance,
+        })
+        .collect();
+    let passed = transfers
+        .iter()
+        .all(|transfer| transfer.balance == transfer.expected)
+        && l2_nonce == options.transfers;
+    Ok(E2eReport {
+        transfers,
+        l2_nonce,
+        iterations,
+        elapsed_secs: started.elapsed().as_secs_f64(),
+        passed,
+    })
+}
@@ -0,0 +1,1560 @@
+//! In-memory test doubles, enabled with the `testing` feature.
+//! `FakeL1` and `FakeL2` are `RpcSender`s, so they plug into a regular
+//! `RpcClient` and the relayer code runs unchanged against them;
+//! `FakeStorage` is a HashMap-backed `StorageBackend` and `FakeAccountCache`
+//! an `AccountCache` serving accounts at scripted slots.
+//!
+//! Under `#[tokio::test(start_paused = true)]` with a `TokioClock`, retry
+//! delays and confirmation polling complete instantly.
+//!
+//! `Harness` wires the fakes to the first source of a config, e.g. the one
+//! `config` generates, and builds a `RelayerEngine` on them:
+//!
+//! ```
+//! # #[tokio::main(flavor = "current_thread", start_paused = true)]
+//! # async fn main() -> anyhow::Result<()> {
+//! use sol_bridge_relayer::testing::{self, Harness};
+//! use solana_sdk::signature::{Keypair, Signer};
+//!
+//! let harness = Harness::new(testing::config())?;
+//! // Two transfers are waiting on L1
+//! let recipient = Keypair::new().pubkey();
+//! harness.add_transfers(0..2, 1_000_000_000, &recipient);
+//! // Fail the first submission to exercise the retry path
+//! harness.l2.fail_send(1);
+//!
+//! let engine = harness.engine().await?;
+//! assert!(engine.run_once().await?[0].error.is_some());
+//! tokio::time::advance(std::time::Duration::from_secs(60)).await;
+//! engine.run_once().await?;
+//! assert_eq!(harness.l2.relayed_nonce(), 2);
+//! assert_eq!(harness.l2.balance(&recipient), 2_000_000_000);
+//! # Ok(())
+//! # }
+//! ```
+
+// `ClientError` is dictated by the `RpcSender` interface
+#![allow(clippy::result_large_err)]
+
+use crate::{
+    account_cache::{AccountCache, AccountCacheReader, CachedAccount},
+    clock::TokioClock,
+    config::{RelayerConfig, SourceConfig},
+    domain,
+    models::{
+        account::{TransferInfoVersion, DISCRIMINATOR_LEN},
+        message::TWO_COUNTER_STATUS_SIZE,
+    },
+    pda::PdaManager,
+    receipt_pdas::CLOSE_RECEIPT_DISCRIMINATOR,
+    signer::Signers,
+    state::{StateStore, StorageBackend},
+    transaction::InstructionVersion,
+    Relayer, RelayerEngine,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use solana_account_decoder::{UiAccount, UiAccountEncoding, UiDataSliceConfig};
+use solana_client::{
+    client_error::Result as ClientResult,
+    nonblocking::rpc_client::RpcClient,
+    rpc_client::RpcClientConfig,
+    rpc_custom_error::JSON_RPC_SERVER_ERROR_MIN_CONTEXT_SLOT_NOT_REACHED,
+    rpc_filter::RpcFilterType,
+    rpc_request::{RpcError, RpcRequest, RpcResponseErrorData},
+    rpc_sender::{RpcSender, RpcTransportStats},
+};
+use solana_sdk::{
+    account::{Account, AccountSharedData},
+    commitment_config::CommitmentConfig,
+    hash::{hash, Hash},
+    instruction::CompiledInstruction,
+    message::Message,
+    nonce,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    rent::Rent,
+    signature::{Keypair, Signature, Signer},
+    system_instruction::SystemInstruction,
+    system_program,
+    sysvar::{
+        self,
+        clock::{self, Clock},
+    },
+    transaction::Transaction,
+};
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+/// Size of an L1 transfer-info PDA
+const TRANSFER_INFO_SIZE: usize = 87;
+/// Size of the L2 nonce account
+const NONCE_ACCOUNT_SIZE: usize = 24;
+/// Fee charged per transaction by `FakeL2`
+pub const FAKE_FEE: u64 = 5_000;
+/// Compute units `FakeL2` reports for every relay transaction
+pub const FAKE_COMPUTE_UNITS: u64 = 1_500;
+/// Block heights a `FakeL2` blockhash stays valid for
+const BLOCKHASH_VALIDITY: u64 = 150;
+
+/// Encodes transfer-info PDA data in the L1 program's layout
+pub fn transfer_info_data(amount: u64, to: &Pubkey) -> Vec<u8> {
+    let mut data = vec![0u8; TRANSFER_INFO_SIZE];
+    data[40..72].copy_from_slice(to.as_ref());
+    data[72..80].copy_from_slice(&amount.to_le_bytes());
+    data
+}
+
+/// Encodes a multi-entry transfer-info PDA starting with `discriminator`
+/// that holds the native transfers `entries`, each an amount and a recipient
+pub fn transfer_entries_data(discriminator: [u8; 8], entries: &[(u64, Pubkey)]) -> Vec<u8> {
+    let mut data = discriminator.to_vec();
+    data.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+    for (amount, to) in entries {
+        let mut entry = transfer_info_data(*amount, to);
+        entry.resize(TransferInfoVersion::V3_SIZE, 0);
+        data.extend_from_slice(&entry[DISCRIMINATOR_LEN..]);
+    }
+    data
+}
+
+/// Encodes the PDA data of a token or NFT message of raw type `type_id`
+/// moving `amount` base units of L1 mint `mint`
+pub fn token_transfer_info_data(amount: u64, to: &Pubkey, type_id: u8, mint: &Pubkey) -> Vec<u8> {
+    let mut data = transfer_info_data(amount, to);
+    data[80] = type_id;
+    // 没有截止时间，铸币紧随 expires_at 之后
+    data.resize(95, 0);
+    data.extend_from_slice(mint.as_ref());
+    data
+}
+
+/// Packs an initialized SPL token account of `owner` holding `amount` of `mint`
+fn token_account_data(owner: &Pubkey, mint: &Pubkey, amount: u64) -> Vec<u8> {
+    let mut data = vec![0; spl_token::state::Account::LEN];
+    let account = spl_token::state::Account {
+        mint: *mint,
+        owner: *owner,
+        amount,
+        state: spl_token::state::AccountState::Initialized,
+        ..Default::default()
+    };
+    spl_token::state::Account::pack(account, &mut data).unwrap();
+    data
+}
+
+/// Encodes a bridge-config account of `domain`, not paused, with a version 1
+/// limits block at offset 17 (`l2_limits_offset = 17`)
+pub fn bridge_limits_data(
+    domain: u64,
+    max_per_transfer: u64,
+    period_cap: u64,
+    period_minted: u64,
+    period_ends_at: i64,
+) -> Vec<u8> {
+    let mut data = domain::config_data(domain);
+    data.push(0);
+    data.push(1);
+    data.extend_from_slice(&max_per_transfer.to_le_bytes());
+    data.extend_from_slice(&period_cap.to_le_bytes());
+    data.extend_from_slice(&period_minted.to_le_bytes());
+    data.extend_from_slice(&period_ends_at.to_le_bytes());
+    data
+}
+
+/// Encodes a watched account in the two-counter layout
+pub fn two_counter_status_data(in_nonce: u64, out_nonce: u64, paused: bool) -> Vec<u8> {
+    let mut data = vec![0u8; TWO_COUNTER_STATUS_SIZE];
+    data[8..16].copy_from_slice(&in_nonce.to_le_bytes());
+    data[16..24].copy_from_slice(&out_nonce.to_le_bytes());
+    data[24] = paused as u8;
+    data
+}
+
+/// Encodes transfer-info PDA data in the extended layout, with a deadline
+pub fn deadline_transfer_info_data(amount: u64, to: &Pubkey, expires_at: i64) -> Vec<u8> {
+    let mut data = transfer_info_data(amount, to);
+    data.extend_from_slice(&expires_at.to_le_bytes());
+    data
+}
+
+/// Encodes a cancellation registry in the list layout listing `nonces`,
+/// after an 8-byte discriminator (the default `offset`); serve it with
+/// `FakeL1::set_pda_data`
+pub fn cancellation_list_data(nonces: &[u64]) -> Vec<u8> {
+    let mut data = vec![0u8; 8];
+    data.extend_from_slice(&(nonces.len() as u32).to_le_bytes());
+    for nonce in nonces {
+        data.extend_from_slice(&nonce.to_le_bytes());
+    }
+    data
+}
+
+/// Encodes a cancellation registry in the bitmap layout starting at nonce
+/// `first` and marking `nonces`, after an 8-byte discriminator
+pub fn cancellation_bitmap_data(first: u64, nonces: &[u64]) -> Vec<u8> {
+    let len = nonces
+        .iter()
+        .map(|nonce| (nonce - first) / 8 + 1)
+        .max()
+        .unwrap_or_default() as usize;
+    let mut bits = vec![0u8; len];
+    for nonce in nonces {
+        let index = nonce - first;
+        bits[(index / 8) as usize] |= 1 << (index % 8);
+    }
+    let mut data = vec![0u8; 8];
+    data.extend_from_slice(&first.to_le_bytes());
+    data.extend_from_slice(&(len as u32).to_le_bytes());
+    data.extend_from_slice(&bits);
+    data
+}
+
+fn confirmed_client(sender: impl RpcSender + Send + Sync + 'static) -> RpcClient {
+    RpcClient::new_sender(
+        sender,
+        RpcClientConfig::with_commitment(CommitmentConfig::confirmed()),
+    )
+}
+
+fn with_context(slot: u64, value: Value) -> Value {
+    json!({ "context": { "slot": slot }, "value": value })
+}
+
+fn encode_account(pubkey: &Pubkey, lamports: u64, data: Vec<u8>) -> Value {
+    encode_owned_account(pubkey, &Pubkey::default(), lamports, data)
+}
+
+fn encode_owned_account(pubkey: &Pubkey, owner: &Pubkey, lamports: u64, data: Vec<u8>) -> Value {
+    let account = Account {
+        lamports,
+        data,
+        owner: *owner,
+        executable: false,
+        rent_epoch: 0,
+    };
+    json!(UiAccount::encode(
+        pubkey,
+        &account,
+        UiAccountEncoding::Base64,
+        None,
+        None
+    ))
+}
+
+fn pubkey_param(params: &Value, index: usize) -> ClientResult<Pubkey> {
+    params[index]
+        .as_str()
+        .and_then(|pubkey| Pubkey::from_str(pubkey).ok())
+        .ok_or_else(|| RpcError::ParseError(format!("pubkey at param {}", index)).into())
+}
+
+fn pubkeys_param(params: &Value) -> ClientResult<Vec<Pubkey>> {
+    Ok(params[0]
+        .as_array()
+        .ok_or_else(|| RpcError::ParseError("pubkey list".to_string()))?
+        .iter()
+        .map(|pubkey| {
+            pubkey
+                .as_str()
+                .and_then(|pubkey| Pubkey::from_str(pubkey).ok())
+                .ok_or_else(|| RpcError::ParseError("pubkey".to_string()))
+        })
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
+/// First instruction of `message` after a durable nonce's
+/// `AdvanceNonceAccount`
+/// Memos of `message` as a signature status lists them, `[<length>] <memo>`
+/// separated by `; `
+fn status_memo(message: &Message) -> Option<String> {
+    let memos: Vec<String> = message
+        .instructions
+        .iter()
+        .filter(|instruction| {
+            message.account_keys[instruction.program_id_index as usize] == spl_memo::id()
+        })
+        .map(|instruction| {
+            format!(
+                "[{}] {}",
+                instruction.data.len(),
+                String::from_utf8_lossy(&instruction.data)
+            )
+        })
+        .collect();
+    (!memos.is_empty()).then(|| memos.join("; "))
+}
+
+fn leading_instruction(message: &Message) -> Option<&CompiledInstruction> {
+    let mut instructions = message.instructions.iter();
+    let first = instructions.next()?;
+    let advances_nonce = message.account_keys.get(first.program_id_index as usize)
+        == Some(&system_program::id())
+        && bincode::deserialize::<SystemInstruction>(&first.data).ok()
+            == Some(SystemInstruction::AdvanceNonceAccount);
+    if advances_nonce {
+        instructions.next()
+    } else {
+        Some(first)
+    }
+}
+
+fn version() -> Value {
+    json!({ "solana-core": "1.14.29", "feature-set": null })
+}
+
+fn unsupported(fake: &str, request: RpcRequest) -> ClientResult<Value> {
+    Err(RpcError::ForUser(format!("{} does not support {}", fake, request)).into())
+}
+
+#[derive(Default)]
+struct FakeL1State {
+    watched_account: Pubkey,
+    scripted_nonces: VecDeque<u64>,
+    current_nonce: u64,
+    /// Paused flag, once the watched account is served in the two-counter layout
+    paused: Option<bool>,
+    /// Raw watched-account contents served instead of the nonce
+    watched_data: Option<Vec<u8>>,
+    pdas: HashMap<Pubkey, Vec<u8>>,
+    /// Slot each PDA was stored at
+    pda_slots: HashMap<Pubkey, u64>,
+    slot: u64,
+    /// Slots the answering node trails the cluster by
+    lag: u64,
+    /// Whether the lagging node refuses reads asking for a later slot
+    honors_min_context_slot: bool,
+}
+
+impl FakeL1State {
+    /// Slot the answering node is at
+    fn served_slot(&self) -> u64 {
+        self.slot.saturating_sub(self.lag)
+    }
+
+    /// Stored PDAs matching the filters of a `getProgramAccounts` request,
+    /// cut to its `dataSlice`; the program id is not checked
+    fn program_accounts(&self, params: &Value) -> ClientResult<Value> {
+        let filters: Vec<RpcFilterType> =
+            serde_json::from_value(params[1]["filters"].clone()).unwrap_or_default();
+        let slice: Option<UiDataSliceConfig> =
+            serde_json::from_value(params[1]["dataSlice"].clone()).ok();
+        let served = self.served_slot();
+        let accounts: Vec<Value> = self
+            .pdas
+            .iter()
+            .filter(|(pubkey, _)| {
+                self.pda_slots
+                    .get(pubkey)
+                    .is_none_or(|slot| *slot <= served)
+            })
+            .filter_map(|(pubkey, data)| {
+                let account = Account {
+                    lamports: 1,
+                    data: data.clone(),
+                    ..Account::default()
+                };
+                let shared = AccountSharedData::from(account.clone());
+                filters
+                    .iter()
+                    .all(|filter| filter.allows(&shared))
+                    .then(|| {
+                        json!({
+                            "pubkey": pubkey.to_string(),
+                            "account": UiAccount::encode(
+                                pubkey,
+                                &account,
+                                UiAccountEncoding::Base64,
+                                None,
+                                slice,
+                            ),
+                        })
+                    })
+            })
+            .collect();
+        Ok(json!(accounts))
+    }
+
+    fn account(&mut self, pubkey: &Pubkey) -> Value {
+        if *pubkey == self.watched_account {
+            if let Some(nonce) = self.scripted_nonces.pop_front() {
+                self.current_nonce = nonce;
+            }
+            let data = match (&self.watched_data, self.paused) {
+                (Some(data), _) => data.clone(),
+                (None, Some(paused)) => two_counter_status_data(0, self.current_nonce, paused),
+                (None, None) => self.current_nonce.to_le_bytes().to_vec(),
+            };
+            return encode_account(pubkey, 1, data);
+        }
+        // 落后的节点还没有之后写入的 PDA
+        let served = self.served_slot();
+        match self.pdas.get(pubkey) {
+            Some(data)
+                if self
+                    .pda_slots
+                    .get(pubkey)
+                    .is_none_or(|slot| *slot <= served) =>
+            {
+                encode_account(pubkey, 1, data.clone())
+            }
+            _ => Value::Null,
+        }
+    }
+
+    /// Refuses a read whose `minContextSlot` the answering node has not reached
+    fn check_min_context_slot(&self, config: &Value) -> ClientResult<()> {
+        let served = self.served_slot();
+        match config["minContextSlot"].as_u64() {
+            Some(min) if self.honors_min_context_slot && min > served => {
+                Err(RpcError::RpcResponseError {
+                    code: JSON_RPC_SERVER_ERROR_MIN_CONTEXT_SLOT_NOT_REACHED,
+                    message: "Minimum context slot has not been reached".to_string(),
+                    data: RpcResponseErrorData::Empty,
+                }
+                .into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Scripted L1 cluster serving the watched account and transfer-info PDAs
+#[derive(Clone)]
+pub struct FakeL1 {
+    state: Arc<Mutex<FakeL1State>>,
+}
+
+impl FakeL1 {
+    pub fn new(watched_account: Pubkey) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(FakeL1State {
+                watched_account,
+                ..FakeL1State::default()
+            })),
+        }
+    }
+
+    /// An `RpcClient` backed by this fake
+    pub fn client(&self) -> RpcClient {
+        confirmed_client(self.clone())
+    }
+
+    /// Queues NonceStatus values returned by successive reads of the watched
+    /// account; once exhausted the last value keeps being returned
+    pub fn script_nonces(&self, nonces: impl IntoIterator<Item = u64>) {
+        self.state.lock().unwrap().scripted_nonces.extend(nonces);
+    }
+
+    /// Serves the watched account in the two-counter layout from now on,
+    /// with the paused flag set to `paused`
+    pub fn set_paused(&self, paused: bool) {
+        self.state.lock().unwrap().paused = Some(paused);
+    }
+
+    /// Serves `data` as the watched account from now on, e.g. a layout the
+    /// relayer does not know; `None` goes back to serving the nonce
+    pub fn set_watched_data(&self, data: Option<Vec<u8>>) {
+        self.state.lock().unwrap().watched_data = data;
+    }
+
+    /// Stores raw contents of a transfer-info PDA or another L1 account, e.g.
+    /// a cancellation registry
+    pub fn set_pda_data(&self, pda: Pubkey, data: Vec<u8>) {
+        let mut state = self.state.lock().unwrap();
+        let slot = state.slot;
+        state.pda_slots.insert(pda, slot);
+        state.pdas.insert(pda, data);
+    }
+
+    /// Stores a well-formed transfer-info PDA
+    pub fn set_transfer_info(&self, pda: Pubkey, amount: u64, to: &Pubkey) {
+        self.set_pda_data(pda, transfer_info_data(amount, to));
+    }
+
+    pub fn remove_pda(&self, pda: &Pubkey) {
+        self.state.lock().unwrap().pdas.remove(pda);
+    }
+
+    /// Serves `nonce` as the NonceStatus from now on, dropping scripted values
+    pub fn set_nonce(&self, nonce: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.scripted_nonces.clear();
+        state.current_nonce = nonce;
+    }
+
+    /// Slot of the last request; every request advances it by one
+    pub fn slot(&self) -> u64 {
+        self.state.lock().unwrap().slot
+    }
+
+    /// Answers from now on like a secondary endpoint `slots` behind the
+    /// cluster: reads return that slot, and PDAs stored since are missing.
+    /// With `honors_min_context_slot`, a read asking for a later slot fails
+    /// with `-32016`; 0 slots catches up
+    pub fn lag_behind(&self, slots: u64, honors_min_context_slot: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.lag = slots;
+        state.honors_min_context_slot = honors_min_context_slot;
+    }
+}
+
+#[async_trait]
+impl RpcSender for FakeL1 {
+    async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        let mut state = self.state.lock().unwrap();
+        state.slot += 1;
+        let slot = state.served_slot();
+        match request {
+            RpcRequest::GetVersion => Ok(version()),
+            RpcRequest::GetSlot => Ok(json!(slot)),
+            RpcRequest::GetAccountInfo => {
+                state.check_min_context_slot(&params[1])?;
+                let pubkey = pubkey_param(&params, 0)?;
+                Ok(with_context(slot, state.account(&pubkey)))
+            }
+            RpcRequest::GetMultipleAccounts => {
+                state.check_min_context_slot(&params[1])?;
+                let pubkeys = pubkeys_param(&params)?;
+                let accounts: Vec<Value> =
+                    pubkeys.iter().map(|pubkey| state.account(pubkey)).collect();
+                Ok(with_context(slot, json!(accounts)))
+            }
+            RpcRequest::GetProgramAccounts => state.program_accounts(&params),
+            _ => unsupported("FakeL1", request),
+        }
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        RpcTransportStats::default()
+    }
+
+    fn url(&self) -> String {
+        "fake-l1".to_string()
+    }
+}
+
+#[derive(Default)]
+struct FakeL2State {
+    nonce_account: Pubkey,
+    relayed_nonce: u64,
+    balances: HashMap<Pubkey, u64>,
+    sent: Vec<Transaction>,
+    send_attempts: usize,
+    failing_sends: HashSet<usize>,
+    dropped_sends: HashSet<usize>,
+    landed: HashSet<Signature>,
+    /// Program-owned accounts other than the nonce account
+    accounts: HashMap<Pubkey, Vec<u8>>,
+    /// Owners of accounts not owned by the system program
+    owners: HashMap<Pubkey, Pubkey>,
+    /// Each landed relay transaction as sent, with the balances of its
+    /// accounts before and after it
+    landed_transactions: HashMap<Signature, (String, Vec<u64>, Vec<u64>)>,
+    /// Entries landed of each nonce relayed in v6 entries
+    landed_entries: HashMap<u64, HashSet<u8>>,
+    block_height: u64,
+    slot: u64,
+    instruction_version: InstructionVersion,
+    /// Prioritization fee reported by `getRecentPrioritizationFees`
+    prioritization_fee: u64,
+    /// Unix time of the clock sysvar; the system time when unset
+    unix_timestamp: Option<i64>,
+    /// Error `getHealth` reports; healthy when unset
+    unhealthy: Option<String>,
+    /// Slots the node's highest-known slot is ahead of its own
+    behind_slots: u64,
+    /// Landed transactions report `confirmed` instead of `finalized`
+    finality_held: bool,
+}
+
+impl FakeL2State {
+    fn account(&self, pubkey: &Pubkey) -> Value {
+        if *pubkey == clock::id() {
+            let unix_timestamp = self.unix_timestamp.unwrap_or_else(|| {
+                SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs() as i64
+            });
+            let clock = Clock {
+                slot: self.slot,
+                unix_timestamp,
+                ..Clock::default()
+            };
+            let data = bincode::serialize(&clock).unwrap();
+            return encode_owned_account(pubkey, &sysvar::id(), 1, data);
+        }
+        let owner = self.owners.get(pubkey).copied().unwrap_or_default();
+        if *pubkey == self.nonce_account {
+            let mut data = vec![0u8; NONCE_ACCOUNT_SIZE];
+            data[8..16].copy_from_slice(&self.relayed_nonce.to_le_bytes());
+            data[16..24].copy_from_slice(&self.relayed_nonce.to_le_bytes());
+            return encode_owned_account(pubkey, &owner, 1, data);
+        }
+        if let Some(data) = self.accounts.get(pubkey) {
+            let lamports = self.balances.get(pubkey).copied().unwrap_or(1);
+            return encode_owned_account(pubkey, &owner, lamports, data.clone());
+        }
+        match self.balances.get(pubkey) {
+            Some(lamports) => encode_owned_account(pubkey, &owner, *lamports, Vec::new()),
+            None => Value::Null,
+        }
+    }
+
+    /// Applies the associated-token-account creations and token transfers
+    /// of `transaction`, all or none; returns whether it had any
+    fn apply_token_instructions(&mut self, transaction: &Transaction) -> ClientResult<bool> {
+        let keys = &transaction.message.account_keys;
+        let key = |instruction: &CompiledInstruction, position: usize| {
+            instruction
+                .accounts
+                .get(position)
+                .and_then(|index| keys.get(*index as usize))
+                .copied()
+        };
+        let mut accounts = self.accounts.clone();
+        let mut created = Vec::new();
+        let mut any = false;
+        for instruction in &transaction.message.instructions {
+            let program = keys[instruction.program_id_index as usize];
+            if program == spl_associated_token_account::id() {
+                any = true;
+                // payer, associated account, wallet, mint
+                let (Some(address), Some(wallet), Some(mint)) = (
+                    key(instruction, 1),
+                    key(instruction, 2),
+                    key(instruction, 3),
+                ) else {
+                    continue;
+                };
+                if let Entry::Vacant(entry) = accounts.entry(address) {
+                    entry.insert(token_account_data(&wallet, &mint, 0));
+                    created.push(address);
+                }
+            } else if program == spl_token::id() {
+                any = true;
+                let Ok(spl_token::instruction::TokenInstruction::TransferChecked {
+                    amount, ..
+                }) = spl_token::instruction::TokenInstruction::unpack(&instruction.data)
+                else {
+                    continue;
+                };
+                // source, mint, destination, authority
+                let (Some(source), Some(destination)) = (key(instruction, 0), key(instruction, 2))
+                else {
+                    continue;
+                };
+                let read = |accounts: &HashMap<Pubkey, Vec<u8>>, address: &Pubkey| {
+                    accounts
+                        .get(address)
+                        .and_then(|data| spl_token::state::Account::unpack(data).ok())
+                        .ok_or_else(|| {
+                            RpcError::ForUser(format!(
+                                "FakeL2: token account {} does not exist",
+                                address
+                            ))
+                        })
+                };
+                let mut from = read(&accounts, &source)?;
+                if from.amount < amount {
+                    return Err(RpcError::ForUser(format!(
+                        "FakeL2: insufficient tokens, {} holds {}, needs {}",
+                        source, from.amount, amount
+                    ))
+                    .into());
+                }
+                from.amount -= amount;
+                accounts.insert(
+                    source,
+                    token_account_data(&from.owner, &from.mint, from.amount),
+                );
+                let mut to = read(&accounts, &destination)?;
+                to.amount += amount;
+                accounts.insert(
+                    destination,
+                    token_account_data(&to.owner, &to.mint, to.amount),
+                );
+            }
+        }
+        self.accounts = accounts;
+        for address in created {
+            self.owners.insert(address, spl_token::id());
+        }
+        Ok(any)
+    }
+
+    /// Removes the receipt PDAs `transaction` closes, crediting their
+    /// lamports to the collector
+    fn close_receipt_pdas(&mut self, transaction: &Transaction) {
+        let keys = &transaction.message.account_keys;
+        for instruction in &transaction.message.instructions {
+            if !instruction.data.starts_with(&CLOSE_RECEIPT_DISCRIMINATOR) {
+                continue;
+            }
+            // receipt, collector, authority
+            let (Some(receipt), Some(collector)) = (
+                instruction
+                    .accounts
+                    .first()
+                    .map(|index| keys[*index as usize]),
+                instruction
+                    .accounts
+                    .get(1)
+                    .map(|index| keys[*index as usize]),
+            ) else {
+                continue;
+            };
+            if self.accounts.remove(&receipt).is_some() {
+                self.owners.remove(&receipt);
+                let lamports = self.balances.remove(&receipt).unwrap_or(1);
+                *self.balances.entry(collector).or_default() += lamports;
+            }
+        }
+    }
+
+    fn send_transaction(&mut self, params: &Value) -> ClientResult<Value> {
+        self.send_attempts += 1;
+        if self.failing_sends.contains(&self.send_attempts) {
+            return Err(
+                RpcError::ForUser(format!("FakeL2 rejected send #{}", self.send_attempts)).into(),
+            );
+        }
+
+        let encoded = params[0]
+            .as_str()
+            .ok_or_else(|| RpcError::ParseError("encoded transaction".to_string()))?;
+        let bytes = base64::decode(encoded)
+            .map_err(|e| RpcError::ParseError(format!("base64 transaction: {}", e)))?;
+        let transaction: Transaction = bincode::deserialize(&bytes)
+            .map_err(|e| RpcError::ParseError(format!("transaction: {}", e)))?;
+        let signature = transaction.signatures[0];
+        // A dropped send is accepted but never lands; a re-send of a landed
+        // transaction is ignored, like the real cluster does
+        if self.dropped_sends.contains(&self.send_attempts) || self.landed.contains(&signature) {
+            return Ok(json!(signature.to_string()));
+        }
+
+        // Relay instruction data: discriminator, [version byte,] amount, nonce
+        let offset = match self.instruction_version {
+            InstructionVersion::V1 => 8,
+            InstructionVersion::V2
+            | InstructionVersion::V3
+            | InstructionVersion::V4
+            | InstructionVersion::V5
+            | InstructionVersion::V6 => 9,
+        };
+        // 打包交易中与第一条指令同一程序的指令都是 relay 指令
+        let relays: Vec<_> = match leading_instruction(&transaction.message) {
+            Some(first) => transaction
+                .message
+                .instructions
+                .iter()
+                .filter(|instruction| {
+                    instruction.program_id_index == first.program_id_index
+                        && instruction.data.len() >= offset + 16
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        if !relays.is_empty() {
+            // 代币转账的金额是代币数量，不移动 lamports
+            let token = self.apply_token_instructions(&transaction)?;
+            let version = self.instruction_version;
+            let transfers: Vec<_> = relays
+                .iter()
+                .map(|instruction| {
+                    let data = &instruction.data[offset..];
+                    let amount = u64::from_le_bytes(data[..8].try_into().unwrap());
+                    let nonce = u64::from_le_bytes(data[8..16].try_into().unwrap());
+                    let amount = if token { 0 } else { amount };
+                    let entry = version.decode_entry(&instruction.data);
+                    (amount, nonce, entry, instruction.accounts.get(2))
+                })
+                .collect();
+            let total: u64 = transfers.iter().map(|(amount, ..)| amount).sum();
+            let keys = &transaction.message.account_keys;
+            // 与真实集群一样，付款人不足以支付时拒绝
+            let payer_balance = keys
+                .first()
+                .and_then(|payer| self.balances.get(payer))
+                .copied()
+                .unwrap_or_default();
+            if payer_balance < total + FAKE_FEE {
+                return Err(RpcError::ForUser(format!(
+                    "FakeL2: insufficient funds, payer has {} lamports, needs {}",
+                    payer_balance,
+                    total + FAKE_FEE
+                ))
+                .into());
+            }
+            let balances = |state: &Self| -> Vec<u64> {
+                keys.iter()
+                    .map(|key| state.balances.get(key).copied().unwrap_or_default())
+                    .collect()
+            };
+            let pre_balances = balances(self);
+            for (amount, nonce, entry, recipient) in transfers {
+                // 多条目 nonce 的所有条目都落地后才算处理完
+                let done = match entry {
+                    Some((index, count)) if count > 1 => {
+                        let landed = self.landed_entries.entry(nonce).or_default();
+                        landed.insert(index);
+                        landed.len() >= usize::from(count)
+                    }
+                    _ => true,
+                };
+                if done {
+                    self.relayed_nonce = self.relayed_nonce.max(nonce + 1);
+                }
+                if let Some(recipient) = recipient.and_then(|index| keys.get(*index as usize)) {
+                    *self.balances.entry(*recipient).or_default() += amount;
+                }
+            }
+            if let Some(payer) = keys.first() {
+                let balance = self.balances.entry(*payer).or_default();
+                *balance = balance.saturating_sub(total + FAKE_FEE);
+            }
+            let post_balances = balances(self);
+            self.landed_transactions.insert(
+                signature,
+                (encoded.to_string(), pre_balances, post_balances),
+            );
+        }
+
+        self.close_receipt_pdas(&transaction);
+
+        self.landed.insert(signature);
+        self.sent.push(transaction);
+        Ok(json!(signature.to_string()))
+    }
+
+    fn transaction(&self, params: &Value) -> ClientResult<Value> {
+        let signature = params[0]
+            .as_str()
+            .and_then(|signature| Signature::from_str(signature).ok())
+            .ok_or_else(|| RpcError::ParseError("signature".to_string()))?;
+        let Some((encoded, pre_balances, post_balances)) = self.landed_transactions.get(&signature)
+        else {
+            return Ok(Value::Null);
+        };
+        Ok(json!({
+            "slot": self.slot,
+            "transaction": [encoded, "base64"],
+            "meta": {
+                "err": null,
+                "status": { "Ok": null },
+                "fee": FAKE_FEE,
+                "preBalances": pre_balances,
+                "postBalances": post_balances,
+                "computeUnitsConsumed": FAKE_COMPUTE_UNITS,
+            },
+            "blockTime": null,
+        }))
+    }
+
+    /// Landed transactions touching the address, newest first, paged by the
+    /// `before`, `until` and `limit` options
+    fn signatures_for_address(&self, params: &Value) -> ClientResult<Value> {
+        let address = pubkey_param(params, 0)?;
+        let option = |name: &str| {
+            params[1][name]
+                .as_str()
+                .and_then(|signature| Signature::from_str(signature).ok())
+        };
+        let (before, until) = (option("before"), option("until"));
+        let limit = params[1]["limit"].as_u64().unwrap_or(1000) as usize;
+        let statuses: Vec<Value> = self
+            .sent
+            .iter()
+            .rev()
+            .filter(|transaction| transaction.message.account_keys.contains(&address))
+            .skip_while(|transaction| {
+                before.is_some_and(|before| transaction.signatures[0] != before)
+            })
+            .skip(before.is_some() as usize)
+            .take_while(|transaction| Some(transaction.signatures[0]) != until)
+            .take(limit)
+            .map(|transaction| {
+                json!({
+                    "signature": transaction.signatures[0].to_string(),
+                    "slot": self.slot,
+                    "err": null,
+                    "memo": status_memo(&transaction.message),
+                    "blockTime": null,
+                    "confirmationStatus": "finalized",
+                })
+            })
+            .collect();
+        Ok(json!(statuses))
+    }
+
+    fn signature_statuses(&self, params: &Value) -> ClientResult<Value> {
+        let statuses: Vec<Value> = params[0]
+            .as_array()
+            .ok_or_else(|| RpcError::ParseError("signature list".to_string()))?
+            .iter()
+            .map(|signature| {
+                let landed = signature
+                    .as_str()
+                    .and_then(|signature| Signature::from_str(signature).ok())
+                    .map(|signature| self.landed.contains(&signature))
+                    .unwrap_or(false);
+                if landed {
+                    json!({
+                        "slot": self.slot,
+                        "confirmations": if self.finality_held { json!(1) } else { Value::Null },
+                        "status": { "Ok": null },
+                        "err": null,
+                        "confirmationStatus": if self.finality_held { "confirmed" } else { "finalized" },
+                    })
+                } else {
+                    Value::Null
+                }
+            })
+            .collect();
+        Ok(with_context(self.slot, json!(statuses)))
+    }
+}
+
+/// L2 cluster that records submitted transactions and advances the nonce
+/// account as relay transactions land
+#[derive(Clone)]
+pub struct FakeL2 {
+    state: Arc<Mutex<FakeL2State>>,
+}
+
+impl FakeL2 {
+    pub fn new(nonce_account: Pubkey) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(FakeL2State {
+                nonce_account,
+                ..FakeL2State::default()
+            })),
+        }
+    }
+
+    /// An `RpcClient` backed by this fake
+    pub fn client(&self) -> RpcClient {
+        confirmed_client(self.clone())
+    }
+
+    /// Serves `unix_timestamp` as the cluster time from now on
+    pub fn set_unix_timestamp(&self, unix_timestamp: i64) {
+        self.state.lock().unwrap().unix_timestamp = Some(unix_timestamp);
+    }
+
+    /// Credits `lamports` to `pubkey`
+    pub fn fund(&self, pubkey: &Pubkey, lamports: u64) {
+        *self
+            .state
+            .lock()
+            .unwrap()
+            .balances
+            .entry(*pubkey)
+            .or_default() += lamports;
+    }
+
+    /// Creates `owner`'s associated token account for `mint` holding `amount`
+    pub fn fund_token(&self, owner: &Pubkey, mint: &Pubkey, amount: u64) {
+        let address = spl_associated_token_account::get_associated_token_address(owner, mint);
+        let mut state = self.state.lock().unwrap();
+        state
+            .accounts
+            .insert(address, token_account_data(owner, mint, amount));
+        state.owners.insert(address, spl_token::id());
+    }
+
+    /// Balance of `owner`'s associated token account for `mint`, none while
+    /// it does not exist
+    pub fn token_balance(&self, owner: &Pubkey, mint: &Pubkey) -> Option<u64> {
+        let address = spl_associated_token_account::get_associated_token_address(owner, mint);
+        self.state
+            .lock()
+            .unwrap()
+            .accounts
+            .get(&address)
+            .and_then(|data| spl_token::state::Account::unpack(data).ok())
+            .map(|account| account.amount)
+    }
+
+    pub fn balance(&self, pubkey: &Pubkey) -> u64 {
+        self.state
+            .lock()
+            .unwrap()
+            .balances
+            .get(pubkey)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Makes the `n`th (1-based) `sendTransaction` call fail without landing
+    pub fn fail_send(&self, n: usize) {
+        self.state.lock().unwrap().failing_sends.insert(n);
+    }
+
+    /// Makes the `n`th (1-based) `sendTransaction` call succeed without landing,
+    /// as if a node dropped the transaction
+    pub fn drop_send(&self, n: usize) {
+        self.state.lock().unwrap().dropped_sends.insert(n);
+    }
+
+    /// Sets the prioritization fee (micro-lamports per compute unit) that
+    /// `getRecentPrioritizationFees` reports
+    pub fn set_prioritization_fee(&self, fee: u64) {
+        self.state.lock().unwrap().prioritization_fee = fee;
+    }
+
+    /// Sets the next nonce the L2 nonce account expects
+    pub fn set_relayed_nonce(&self, nonce: u64) {
+        self.state.lock().unwrap().relayed_nonce = nonce;
+    }
+
+    /// Parses relay instructions in the layout of `version`
+    pub fn use_instruction_version(&self, version: InstructionVersion) {
+        self.state.lock().unwrap().instruction_version = version;
+    }
+
+    pub fn relayed_nonce(&self) -> u64 {
+        self.state.lock().unwrap().relayed_nonce
+    }
+
+    /// Creates the config account of `program_id` advertising bridge `domain`
+    pub fn set_bridge_domain(&self, program_id: &Pubkey, domain: u64) {
+        let address = domain::config_address(program_id);
+        let mut state = self.state.lock().unwrap();
+        state.accounts.insert(address, domain::config_data(domain));
+        state.owners.insert(address, *program_id);
+    }
+
+    /// Stores raw data of a program-owned account, e.g. a bridge-config
+    /// account with its paused flag set
+    pub fn set_account_data(&self, address: Pubkey, data: Vec<u8>) {
+        self.state.lock().unwrap().accounts.insert(address, data);
+    }
+
+    /// Stores an initialized system nonce account at `address`, authorized
+    /// to `authority` and holding the durable nonce of `blockhash`; called
+    /// again, it advances the nonce as another process would
+    pub fn set_durable_nonce(&self, address: Pubkey, authority: Pubkey, blockhash: &Hash) {
+        let data = nonce::state::Data::new(
+            authority,
+            nonce::state::DurableNonce::from_blockhash(blockhash),
+            5_000,
+        );
+        let versions = nonce::state::Versions::new(nonce::State::Initialized(data));
+        let mut state = self.state.lock().unwrap();
+        state
+            .accounts
+            .insert(address, bincode::serialize(&versions).unwrap());
+        state.owners.insert(address, system_program::id());
+    }
+
+    /// Creates a receipt PDA of `program_id` holding `lamports`, to be closed
+    /// by `close_receipt`
+    pub fn set_receipt_pda(&self, address: Pubkey, program_id: &Pubkey, lamports: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.accounts.insert(address, vec![0; 16]);
+        state.owners.insert(address, *program_id);
+        state.balances.insert(address, lamports);
+    }
+
+    /// Makes `getHealth` fail with `message`, or succeed again with `None`
+    pub fn set_unhealthy(&self, message: Option<&str>) {
+        self.state.lock().unwrap().unhealthy = message.map(str::to_string);
+    }
+
+    /// Keeps landed transactions at `confirmed` until called with `false`
+    pub fn hold_finality(&self, held: bool) {
+        self.state.lock().unwrap().finality_held = held;
+    }
+
+    /// Makes `getMaxShredInsertSlot` report `slots` past the slot the node
+    /// is at, as if it fell behind the cluster
+    pub fn set_behind(&self, slots: u64) {
+        self.state.lock().unwrap().behind_slots = slots;
+    }
+
+    /// Makes `owner` the owner program of `address`, e.g. of a funded escrow
+    /// vault PDA
+    pub fn set_owner(&self, address: Pubkey, owner: Pubkey) {
+        self.state.lock().unwrap().owners.insert(address, owner);
+    }
+
+    /// Transactions that landed, in submission order
+    pub fn sent(&self) -> Vec<Transaction> {
+        self.state.lock().unwrap().sent.clone()
+    }
+
+    /// L1 nonces covered by each landed relay transaction, in submission
+    /// order: the memo's packed or merged nonces, or the nonce of the
+    /// instruction data
+    pub fn relayed_nonces(&self) -> Vec<Vec<u64>> {
+        let state = self.state.lock().unwrap();
+        let offset = match state.instruction_version {
+            InstructionVersion::V1 => 8,
+            InstructionVersion::V2
+            | InstructionVersion::V3
+            | InstructionVersion::V4
+            | InstructionVersion::V5
+            | InstructionVersion::V6 => 9,
+        };
+        state
+            .sent
+            .iter()
+            .filter_map(|transaction| {
+                let message = &transaction.message;
+                let data = &leading_instruction(message)?.data;
+                let nonce = u64::from_le_bytes(data.get(offset + 8..offset + 16)?.try_into().ok()?);
+                let merged = message
+                    .instructions
+                    .iter()
+                    .filter(|instruction| {
+                        message
+                            .account_keys
+                            .get(instruction.program_id_index as usize)
+                            == Some(&spl_memo::id())
+                    })
+                    .find_map(|memo| {
+                        let memo: Value = serde_json::from_slice(&memo.data).ok()?;
+                        let packed = serde_json::from_value::<Vec<u64>>(memo["packed"].clone());
+                        packed
+                            .or_else(|_| serde_json::from_value::<Vec<u64>>(memo["nonces"].clone()))
+                            .ok()
+                    });
+                Some(merged.unwrap_or_else(|| vec![nonce]))
+            })
+            .collect()
+    }
+
+    /// Number of `sendTransaction` calls, including failed ones
+    pub fn send_attempts(&self) -> usize {
+        self.state.lock().unwrap().send_attempts
+    }
+
+    /// Drops the record of landed transactions, keeping balances and the
+    /// nonce account, so long runs do not grow the fake itself
+    pub fn forget_landed(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.sent.clear();
+        state.landed.clear();
+        state.landed_transactions.clear();
+    }
+}
+
+#[async_trait]
+impl RpcSender for FakeL2 {
+    async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        let mut state = self.state.lock().unwrap();
+        state.slot += 1;
+        let slot = state.slot;
+        match request {
+            RpcRequest::GetVersion => Ok(version()),
+            RpcRequest::GetGenesisHash => Ok(json!(hash(b"fake-l2").to_string())),
+            RpcRequest::GetHealth => match &state.unhealthy {
+                Some(message) => Err(RpcError::ForUser(message.clone()).into()),
+                None => Ok(json!("ok")),
+            },
+            RpcRequest::GetSlot => Ok(json!(slot)),
+            RpcRequest::GetMaxShredInsertSlot => Ok(json!(slot + state.behind_slots)),
+            RpcRequest::GetAccountInfo => {
+                let pubkey = pubkey_param(&params, 0)?;
+                Ok(with_context(slot, state.account(&pubkey)))
+            }
+            RpcRequest::GetMultipleAccounts => {
+                let accounts: Vec<Value> = pubkeys_param(&params)?
+                    .iter()
+                    .map(|pubkey| state.account(pubkey))
+                    .collect();
+                Ok(with_context(slot, json!(accounts)))
+            }
+            RpcRequest::GetBalance => {
+                let pubkey = pubkey_param(&params, 0)?;
+                let balance = state.balances.get(&pubkey).copied().unwrap_or(0);
+                Ok(with_context(slot, json!(balance)))
+            }
+            RpcRequest::GetMinimumBalanceForRentExemption => {
+                let data_len = params[0].as_u64().unwrap_or(0) as usize;
+                Ok(json!(Rent::default().minimum_balance(data_len)))
+            }
+            RpcRequest::GetFeeForMessage => Ok(with_context(slot, json!(FAKE_FEE))),
+            RpcRequest::GetRecentPrioritizationFees => Ok(json!([{
+                "slot": slot,
+                "prioritizationFee": state.prioritization_fee,
+            }])),
+            RpcRequest::GetLatestBlockhash => {
+                let blockhash: Hash = hash(&state.block_height.to_le_bytes());
+                Ok(with_context(
+                    slot,
+                    json!({
+                        "blockhash": blockhash.to_string(),
+                        "lastValidBlockHeight": state.block_height + BLOCKHASH_VALIDITY,
+                    }),
+                ))
+            }
+            RpcRequest::GetBlockHeight => {
+                state.block_height += 1;
+                Ok(json!(state.block_height))
+            }
+            RpcRequest::SendTransaction => state.send_transaction(&params),
+            RpcRequest::SimulateTransaction => Ok(with_context(
+                slot,
+                json!({
+                    "err": null,
+                    "logs": [],
+                    "accounts": null,
+                    "unitsConsumed": FAKE_COMPUTE_UNITS,
+                    "returnData": null,
+                }),
+            )),
+            RpcRequest::GetSignatureStatuses => state.signature_statuses(&params),
+            RpcRequest::GetTransaction => state.transaction(&params),
+            RpcRequest::GetSignaturesForAddress => state.signatures_for_address(&params),
+            RpcRequest::RequestAirdrop => {
+                let pubkey = pubkey_param(&params, 0)?;
+                let lamports = params[1].as_u64().unwrap_or(0);
+                *state.balances.entry(pubkey).or_default() += lamports;
+                let signature = Signature::new_unique();
+                state.landed.insert(signature);
+                Ok(json!(signature.to_string()))
+            }
+            _ => unsupported("FakeL2", request),
+        }
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        RpcTransportStats::default()
+    }
+
+    fn url(&self) -> String {
+        "fake-l2".to_string()
+    }
+}
+
+#[derive(Default)]
+struct FakeAccountCacheState {
+    accounts: HashMap<Pubkey, CachedAccount>,
+    unavailable: bool,
+    reads: usize,
+}
+
+/// Account-index cache serving accounts at scripted slots, e.g. behind the
+/// `FakeL1` to exercise the RPC fallback
+#[derive(Clone, Default)]
+pub struct FakeAccountCache {
+    state: Arc<Mutex<FakeAccountCacheState>>,
+}
+
+impl FakeAccountCache {
+    /// Serves `data`, owned by `owner`, as `address` at `slot`
+    pub fn set_account(&self, address: Pubkey, slot: u64, owner: Pubkey, data: Vec<u8>) {
+        self.state
+            .lock()
+            .unwrap()
+            .accounts
+            .insert(address, CachedAccount { slot, owner, data });
+    }
+
+    pub fn remove_account(&self, address: &Pubkey) {
+        self.state.lock().unwrap().accounts.remove(address);
+    }
+
+    /// Fails every read while `unavailable` is set
+    pub fn set_unavailable(&self, unavailable: bool) {
+        self.state.lock().unwrap().unavailable = unavailable;
+    }
+
+    /// Reads served so far, failed ones included
+    pub fn reads(&self) -> usize {
+        self.state.lock().unwrap().reads
+    }
+
+    /// A reader of this cache for `Relayer::account_cache`
+    pub fn reader(&self, max_staleness_slots: u64) -> AccountCacheReader {
+        AccountCacheReader::new(Arc::new(self.clone()), max_staleness_slots, Duration::ZERO)
+    }
+}
+
+#[async_trait]
+impl AccountCache for FakeAccountCache {
+    async fn get_account(&self, address: &Pubkey) -> Result<Option<CachedAccount>> {
+        let mut state = self.state.lock().unwrap();
+        state.reads += 1;
+        if state.unavailable {
+            return Err(anyhow::anyhow!("FakeAccountCache is unavailable"));
+        }
+        Ok(state.accounts.get(address).cloned())
+    }
+}
+
+/// HashMap-backed storage for a `StateStore`
+#[derive(Clone, Default)]
+pub struct FakeStorage {
+    entries: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+}
+
+impl FakeStorage {
+    /// A `StateStore` sharing this storage
+    pub fn store(&self) -> StateStore {
+        StateStore::new(self.clone())
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self.entries.lock().unwrap().keys().cloned().collect();
+        keys.sort();
+        keys
+    }
+}
+
+impl StorageBackend for FakeStorage {
+    fn read(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.entries.lock().unwrap().get(key).cloned())
+    }
+
+    fn write(&self, key: &str, data: &[u8]) -> Result<()> {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), data.to_vec());
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.entries.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        Ok(self
+            .keys()
+            .into_iter()
+            .filter(|key| key.starts_with(prefix))
+            .collect())
+    }
+}
+
+/// A single-source config for the fakes, with fresh program and account ids
+pub fn config() -> RelayerConfig {
+    let id = || Pubkey::new_unique().to_string();
+    serde_json::from_value(json!({
+        "l1_url": "http://fake-l1",
+        "l2_url": "http://fake-l2",
+        "watched_account": id(),
+        "l1_program_id": id(),
+        "l2_program_id": id(),
+        "nonce_account": id(),
+    }))
+    .expect("fake config")
+}
+
+/// The fakes of the first source of a config: `l1` serves its watched
+/// account and PDAs, `l2` its nonce account, owned by the L2 program, and
+/// holds 100 SOL of the signer
+pub struct Harness {
+    pub config: RelayerConfig,
+    pub source: SourceConfig,
+    pub l1: FakeL1,
+    pub l2: FakeL2,
+    pub storage: FakeStorage,
+    keypair: Keypair,
+    pdas: PdaManager,
+}
+
+impl Harness {
+    pub fn new(config: RelayerConfig) -> Result<Self> {
+        let source = config.sources()?.remove(0);
+        let l1 = FakeL1::new(*source.watched_account.pubkey());
+        let l2 = FakeL2::new(*source.nonce_account.pubkey());
+        l2.set_owner(
+            *source.nonce_account.pubkey(),
+            *config.l2_program_id.pubkey(),
+        );
+        let keypair = Keypair::new();
+        l2.fund(&keypair.pubkey(), 100_000_000_000);
+        let pdas = PdaManager::new(
+            source.l1_program_id,
+            source.watched_account,
+            source.seeds.clone(),
+        );
+        Ok(Self {
+            config,
+            source,
+            l1,
+            l2,
+            storage: FakeStorage::default(),
+            keypair,
+            pdas,
+        })
+    }
+
+    /// The relayer's signer
+    pub fn signer(&self) -> Pubkey {
+        self.keypair.pubkey()
+    }
+
+    /// Transfer-info PDA of `nonce`
+    pub fn transfer_pda(&self, nonce: u64) -> Pubkey {
+        self.pdas.find_address(nonce).0
+    }
+
+    /// Stores a transfer of `amount` to `to` for every nonce of `nonces` and
+    /// moves the watched-account nonce past them
+    pub fn add_transfers(&self, nonces: std::ops::Range<u64>, amount: u64, to: &Pubkey) {
+        for nonce in nonces.clone() {
+            self.l1
+                .set_transfer_info(self.transfer_pda(nonce), amount, to);
+        }
+        self.l1.set_nonce(nonces.end);
+    }
+
+    /// An engine relaying the source through the fakes, on a tokio-driven
+    /// clock starting at the Unix epoch
+    pub async fn engine(&self) -> Result<RelayerEngine> {
+        Ok(RelayerEngine::from_relayers(vec![self.relayer().await?]))
+    }
+
+    pub(crate) async fn relayer(&self) -> Result<Relayer> {
+        Relayer::with_clients(
+            &self.config,
+            &self.source,
+            Signers::new(Keypair::from_bytes(&self.keypair.to_bytes())?, None),
+            self.l1.client(),
+            self.l2.client(),
+            self.storage.store(),
+            Arc::new(TokioClock::starting_at(SystemTime::UNIX_EPOCH)),
+        )
+        .await
+    }
+}
+
+/// Peak sizes of the relayer's long-lived collections during `soak`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SoakReport {
+    pub relayed: u64,
+    pub queue_depth: u64,
+    pub queue_overflow: u64,
+    pub retries_tracked: u64,
+    pub in_flight: u64,
+    pub enrichment_pending: u64,
+    pub priority_tracked: u64,
+    /// Keys in the state store
+    pub stored_keys: usize,
+}
+
+/// State keys other than receipts, e.g. the queue and in-flight set
+const SOAK_HOUSEKEEPING_KEYS: usize = 32;
+
+/// Relays `transfers` synthetic transfers of the first source of `config`
+/// through the fakes, `per_step` per `run_once`, and fails as soon as a
+/// collection outgrows its bound: the queue `queue_capacity`, in-flight
+/// transactions `send_concurrency`, the enrichment queue
+/// `receipt_enrich_queue_capacity`, priority ordering twice its window and
+/// the state store the receipts of the replay window (`per_step` nonces
+/// unless `replay_window_nonces` is set). Run it on a paused tokio runtime.
+pub async fn soak(config: &RelayerConfig, transfers: u64, per_step: u64) -> Result<SoakReport> {
+    let mut config = config.clone();
+    config.coalesce_window_ms = 0;
+    let window = *config.replay_window_nonces.get_or_insert(per_step.max(1));
+    let harness = Harness::new(config.clone())?;
+    let (l1, l2, storage) = (&harness.l1, &harness.l2, &harness.storage);
+    l2.fund(&harness.signer(), u64::MAX / 2);
+    // 收款人循环使用，预先创建避免租金检查
+    let recipients: Vec<Pubkey> = (0..16).map(|_| Pubkey::new_unique()).collect();
+    for recipient in &recipients {
+        l2.fund(recipient, 1_000_000_000);
+    }
+    let relayer = harness.relayer().await?;
+
+    let mut report = SoakReport::default();
+    let mut next = 0;
+    while next < transfers {
+        let end = transfers.min(next + per_step.max(1));
+        let step: Vec<Pubkey> = (next..end)
+            .map(|nonce| harness.transfer_pda(nonce))
+            .collect();
+        for (nonce, pda) in (next..end).zip(&step) {
+            let recipient = &recipients[nonce as usize % recipients.len()];
+            l1.set_transfer_info(*pda, 1_000_000, recipient);
+        }
+        l1.script_nonces([end]);
+        let outcome = relayer.run_once().await?;
+        if let Some(error) = outcome.error {
+            return Err(anyhow::anyhow!(
+                "Soak step {}..{} failed: {}",
+                next,
+                end,
+                error
+            ));
+        }
+        report.relayed += outcome.relayed;
+        for pda in &step {
+            l1.remove_pda(pda);
+        }
+        l2.forget_landed();
+
+        let metrics = &relayer.metrics;
+        let stored_keys = storage.keys().len();
+        let bounds = [
+            (metrics.queue_depth.get(), config.queue_capacity as u64),
+            (metrics.retries_tracked.get(), config.queue_capacity as u64),
+            (
+                metrics.in_flight.get(),
+                config.send_concurrency.max(1) as u64,
+            ),
+            (
+                metrics.enrichment_pending.get(),
+                config.receipt_enrich_queue_capacity as u64,
+            ),
+            (
+                metrics.priority_tracked.get(),
+                2 * config.priority_window.max(1) as u64,
+            ),
+            (
+                stored_keys as u64,
+                window + per_step + SOAK_HOUSEKEEPING_KEYS as u64,
+            ),
+        ];
+        report.queue_depth = report.queue_depth.max(metrics.queue_depth.get());
+        report.queue_overflow = report.queue_overflow.max(metrics.queue_overflow.get());
+        report.retries_tracked = report.retries_tracked.max(metrics.retries_tracked.get());
+        report.in_flight = report.in_flight.max(metrics.in_flight.get());
+        report.enrichment_pending = report
+            .enrichment_pending
+            .max(metrics.enrichment_pending.get());
+        report.priority_tracked = report.priority_tracked.max(metrics.priority_tracked.get());
+        report.stored_keys = report.stored_keys.max(stored_keys);
+        if let Some((size, bound)) = bounds.into_iter().find(|(size, bound)| size > bound) {
+            return Err(anyhow::anyhow!(
+                "Collection of {} entries outgrew its bound of {} after {} transfers: {:?}",
+                size,
+                bound,
+                end,
+                report
+            ));
+        }
+        next = end;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn long_lived_collections_stay_within_their_bounds() {
+        let mut config = config();
+        config.queue_capacity = 64;
+        config.receipt_enrich_queue_capacity = 8;
+        let report = soak(&config, 400, 20).await.unwrap();
+        assert_eq!(report.relayed, 400);
+        assert!(report.queue_depth <= 64, "{:?}", report);
+        assert!(report.enrichment_pending <= 8, "{:?}", report);
+        assert!(report.in_flight <= config.send_concurrency.max(1) as u64);
+    }
+}
@@ -0,0 +1,219 @@
+//! Relay throughput limit.
+//! Independently of RPC pacing, `max_transfers_per_minute` caps how fast value
+//! leaves the bridge across all sources, and
+//! `max_transfers_per_minute_per_source` (or a source's own
+//! `max_transfers_per_minute`) caps each source. Both are leaky buckets that
+//! hold up to `transfer_rate_burst` transfers. A batch is cut to what both
+//! allow now; the rest waits in the pending queue. Every submission goes
+//! through the limit, including the catch-up after `confirm-jump`.
+//!
+//! `throttle` overrides a limit in the state store; running relayers pick the
+//! change up before their next batch. A config reload (SIGHUP) replaces the
+//! configured limits, which apply where no override is set.
+
+use crate::Relayer;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+/// State key of the override of the limit shared by all sources, written to
+/// every source's store
+pub const GLOBAL_LIMIT_KEY: &str = "throughput_limit/global";
+/// State key of the override of the source's own limit
+pub const SOURCE_LIMIT_KEY: &str = "throughput_limit/source";
+
+/// Limit set with `throttle`, replacing the configured one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LimitOverride {
+    /// Transfers per minute; 0 lifts the limit
+    pub per_minute: u64,
+}
+
+struct BucketState {
+    per_minute: Option<u64>,
+    burst: u64,
+    /// Transfers in the bucket, leaking at `per_minute`
+    level: f64,
+    leaked_at: Option<SystemTime>,
+}
+
+pub struct LeakyBucket {
+    /// Limit from the config, replaced on reload
+    configured: Mutex<Option<u64>>,
+    state: Mutex<BucketState>,
+}
+
+impl LeakyBucket {
+    /// Bucket letting `per_minute` transfers through (unlimited when `None` or 0)
+    pub fn new(per_minute: Option<u64>, burst: u64) -> Self {
+        let per_minute = per_minute.filter(|&limit| limit > 0);
+        Self {
+            configured: Mutex::new(per_minute),
+            state: Mutex::new(BucketState {
+                per_minute,
+                burst: burst.max(1),
+                level: 0.0,
+                leaked_at: None,
+            }),
+        }
+    }
+
+    /// Applies `limit` (the configured limit when `None`); returns the new
+    /// limit if it changed
+    fn apply(&self, limit: Option<LimitOverride>) -> Option<Option<u64>> {
+        let per_minute = match limit {
+            Some(limit) => (limit.per_minute > 0).then_some(limit.per_minute),
+            None => *self.configured.lock().unwrap(),
+        };
+        let mut state = self.state.lock().unwrap();
+        if state.per_minute == per_minute {
+            return None;
+        }
+        state.per_minute = per_minute;
+        Some(per_minute)
+    }
+
+    /// Replaces the configured limit and burst; an override set with
+    /// `throttle` still takes precedence
+    pub fn reconfigure(&self, per_minute: Option<u64>, burst: u64) {
+        *self.configured.lock().unwrap() = per_minute.filter(|&limit| limit > 0);
+        self.state.lock().unwrap().burst = burst.max(1);
+    }
+
+    fn leak(&self, state: &mut BucketState, now: SystemTime) {
+        if let (Some(per_minute), Some(leaked_at)) = (state.per_minute, state.leaked_at) {
+            let elapsed = now.duration_since(leaked_at).unwrap_or_default();
+            state.level = (state.level - elapsed.as_secs_f64() * per_minute as f64 / 60.0).max(0.0);
+        }
+        state.leaked_at = Some(now);
+    }
+
+    /// Transfers that fit in the bucket now
+    fn room(&self, state: &BucketState) -> u64 {
+        match state.per_minute {
+            Some(_) => (state.burst as f64 - state.level).max(0.0).floor() as u64,
+            None => u64::MAX,
+        }
+    }
+
+    /// Wait until one more transfer fits
+    fn wait(&self, state: &BucketState) -> Duration {
+        match state.per_minute {
+            Some(per_minute) => {
+                let excess = (state.level - (state.burst - 1) as f64).max(0.0);
+                Duration::from_secs_f64(excess * 60.0 / per_minute as f64)
+            }
+            None => Duration::ZERO,
+        }
+    }
+}
+
+/// What the throughput limit allows for a batch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Allowance {
+    /// Relay up to this many transfers
+    Relay(u64),
+    /// Relay nothing before this wait
+    Hold(Duration),
+}
+
+/// Limit shared by all sources and the source's own limit
+pub struct Throughput {
+    global: Arc<LeakyBucket>,
+    source: LeakyBucket,
+}
+
+impl Throughput {
+    pub fn new(global: LeakyBucket, source: LeakyBucket) -> Self {
+        Self {
+            global: Arc::new(global),
+            source,
+        }
+    }
+
+    /// Makes this source draw from `global`, shared with the other sources
+    pub fn share_global(&mut self, global: &Arc<LeakyBucket>) {
+        self.global = global.clone();
+    }
+
+    /// Replaces the configured limits of both buckets
+    pub fn reconfigure(&self, global: Option<u64>, source: Option<u64>, burst: u64) {
+        self.global.reconfigure(global, burst);
+        self.source.reconfigure(source, burst);
+    }
+
+    /// Takes up to `wanted` transfers from both buckets
+    fn take(&self, wanted: u64, now: SystemTime) -> Allowance {
+        // 先锁全局桶再锁本 source 的桶，两者一起扣减
+        let mut global = self.global.state.lock().unwrap();
+        let mut source = self.source.state.lock().unwrap();
+        self.global.leak(&mut global, now);
+        self.source.leak(&mut source, now);
+        let allowed = wanted
+            .min(self.global.room(&global))
+            .min(self.source.room(&source));
+        if allowed == 0 {
+            return Allowance::Hold(self.global.wait(&global).max(self.source.wait(&source)));
+        }
+        for state in [&mut *global, &mut *source] {
+            if state.per_minute.is_some() {
+                state.level += allowed as f64;
+            }
+        }
+        Allowance::Relay(allowed)
+    }
+}
+
+fn describe(per_minute: Option<u64>) -> String {
+    per_minute.map_or("unlimited".to_string(), |limit| format!("{}/min", limit))
+}
+
+impl Relayer {
+    /// How many of `wanted` pending transfers the throughput limit lets
+    /// through now, after picking up `throttle` overrides. Transfers of a
+    /// batch that then fails still count against the limit.
+    pub(crate) fn throughput_allowance(&self, wanted: u64) -> Result<Allowance> {
+        if let Some(limit) = self
+            .throughput
+            .global
+            .apply(self.state.get(GLOBAL_LIMIT_KEY)?)
+        {
+            tracing::info!("Global throughput limit is now {}", describe(limit));
+        }
+        if let Some(limit) = self
+            .throughput
+            .source
+            .apply(self.state.get(SOURCE_LIMIT_KEY)?)
+        {
+            tracing::info!(
+                "Throughput limit of source {} is now {}",
+                self.source_id,
+                describe(limit)
+            );
+        }
+
+        let allowance = self.throughput.take(wanted, self.clock.now());
+        match allowance {
+            Allowance::Relay(allowed) if allowed < wanted => tracing::info!(
+                "Throughput limit lets {} of {} nonce(s) through",
+                allowed,
+                wanted
+            ),
+            Allowance::Hold(wait) => {
+                tracing::info!(
+                    "Throughput limit reached, holding {} nonce(s) for {:?}",
+                    wanted,
+                    wait
+                );
+                self.metrics
+                    .throttled_ms_total
+                    .inc_by(wait.as_millis() as u64);
+            }
+            Allowance::Relay(_) => {}
+        }
+        Ok(allowance)
+    }
+}
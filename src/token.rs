@@ -0,0 +1,748 @@
+//! Token and NFT transfers.
+//! A transfer-info PDA of a `token` or `nft` message carries its L1 mint in
+//! the 32 bytes after `expires_at` (bytes 95..127). `[[mint_mappings]]` maps
+//! each L1 mint to the L2 mint paid out, with its decimals, which are checked
+//! against the L2 mint account at startup and on every reload. Mappings may
+//! also come from `mint_mappings_file`, read again on SIGHUP. A message of a
+//! mint whose mapping has `enabled = false` is skipped with a receipt; one
+//! whose mint is not mapped is held with an alert under
+//! `unmapped_mint = "hold"` (the default) until a reload maps it, or
+//! dead-lettered under `"dead_letter"`. An NFT message of an amount other
+//! than 1 is dead-lettered. The L2 transaction still calls `relay_message`,
+//! which advances the nonce account and tells the L2 program the message
+//! type (so it needs `l2_instruction_version` 2 or later), followed by a
+//! `transfer_checked` of the amount, in the mint's base units, from the
+//! signer's associated token account to the recipient's. The signer must
+//! hold the tokens; the relayer never mints. Coalescing only merges token
+//! transfers of the same mint, and never NFTs.
+//!
+//! Before a transfer is built, the recipient's associated token account is
+//! looked up. When it is missing, `token_account_rent_payer` decides:
+//! under `relayer` (the default) the transaction creates it idempotently
+//! and the signer pays its rent, counted in the receipt and the
+//! `relayer_token_account_rent_lamports_total` metric; under `recipient`
+//! the transfer is dead-lettered until the recipient creates it, and an
+//! operator replays it. A recipient owned by the token program names a token
+//! account, which is paid into directly once it is checked to hold the L2
+//! mint and not be frozen; otherwise `token_account_mismatch` dead-letters
+//! the transfer (the default) or pays its owner's associated token account.
+//!
+//! `manual_approval_threshold` and `dual_read_threshold` are in lamports and
+//! apply to native transfers only. A mapping sets its own
+//! `approval_threshold` (none by default) and `dual_read_threshold` (0 by
+//! default) in the mint's base units.
+
+use crate::{
+    alerts,
+    models::message::MessageType,
+    pipeline::{FetchedTransfer, MessageCheck},
+    pubkeys::{DestinationAccount, SourceAccount},
+    transaction::InstructionVersion,
+    Relayer,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{
+    commitment_config::CommitmentConfig, instruction::Instruction, program_pack::Pack,
+    pubkey::Pubkey,
+};
+use spl_associated_token_account::{
+    get_associated_token_address, instruction::create_associated_token_account_idempotent,
+};
+use std::collections::HashMap;
+
+/// Most accounts one `getMultipleAccounts` call returns
+const MAX_ACCOUNTS_PER_REQUEST: usize = 100;
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// One `[[mint_mappings]]` table
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MintMapping {
+    pub l1_mint: SourceAccount,
+    pub l2_mint: DestinationAccount,
+    /// Decimals of the L2 mint, checked by `transfer_checked`
+    #[serde(default)]
+    pub decimals: u8,
+    /// Whether the mint is an NFT (decimals 0, transfers of exactly 1)
+    #[serde(default)]
+    pub nft: bool,
+    /// Whether messages of the mint are relayed; those of a disabled mint are
+    /// skipped with a receipt
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Transfers of the mint above this many base units need manual approval
+    /// while `manual_approval_threshold` is set (none when unset)
+    #[serde(default)]
+    pub approval_threshold: Option<u64>,
+    /// Transfers of the mint above this many base units are read again from
+    /// every `l1_verify_urls` provider
+    #[serde(default)]
+    pub dual_read_threshold: u64,
+    /// Transfers of the mint below this many base units are dust, handled by
+    /// `dust` (0: no check)
+    #[serde(default)]
+    pub dust_threshold: u64,
+}
+
+/// Handling of token and NFT messages whose L1 mint is not mapped
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnmappedMintPolicy {
+    /// Fail the batch with an alert until a reload maps the mint
+    #[default]
+    Hold,
+    /// Dead-letter the nonce
+    DeadLetter,
+}
+
+/// Who pays for a recipient's missing associated token account
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenAccountRentPayer {
+    /// The relay transaction creates it, paid by the signer
+    #[default]
+    Relayer,
+    /// The recipient must create it; the transfer is dead-lettered meanwhile
+    Recipient,
+}
+
+/// Handling of a recipient naming a token account of another mint, or a
+/// frozen one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenAccountMismatch {
+    /// Dead-letter the nonce
+    #[default]
+    DeadLetter,
+    /// Pay the associated token account of the named account's owner
+    Owner,
+}
+
+/// Why a token account named as recipient cannot be paid into
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenAccountError {
+    /// A token account of another mint
+    WrongMint {
+        account: Pubkey,
+        mint: Pubkey,
+        expected: Pubkey,
+        owner: Pubkey,
+    },
+    /// A token account frozen by its mint's freeze authority
+    Frozen { account: Pubkey, owner: Pubkey },
+    /// Owned by the token program, but no token account (a mint, a multisig)
+    NotATokenAccount { account: Pubkey },
+}
+
+impl TokenAccountError {
+    /// Owner of the named token account, none when it is no token account
+    pub fn owner(&self) -> Option<Pubkey> {
+        match self {
+            Self::WrongMint { owner, .. } | Self::Frozen { owner, .. } => Some(*owner),
+            Self::NotATokenAccount { .. } => None,
+        }
+    }
+}
+
+impl std::fmt::Display for TokenAccountError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::WrongMint {
+                account,
+                mint,
+                expected,
+                ..
+            } => write!(
+                f,
+                "recipient token account {} holds mint {}, not {}",
+                account, mint, expected
+            ),
+            Self::Frozen { account, .. } => {
+                write!(f, "recipient token account {} is frozen", account)
+            }
+            Self::NotATokenAccount { account } => write!(
+                f,
+                "recipient {} is owned by the token program but is no token account",
+                account
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TokenAccountError {}
+
+/// Token account a recipient names instead of a wallet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NamedAccount {
+    /// Paid into directly
+    Direct(Pubkey),
+    /// Refused; the associated token account of this owner is paid instead
+    Owner(Pubkey),
+}
+
+/// L2 side of a token or NFT transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenTransfer {
+    pub l2_mint: Pubkey,
+    pub decimals: u8,
+    pub nft: bool,
+    /// Token account the recipient names, none for a wallet
+    pub named: Option<NamedAccount>,
+    /// Base units above which a transfer needs manual approval
+    pub approval_threshold: Option<u64>,
+    /// Base units above which a read is confirmed by `l1_verify_urls`
+    pub dual_read_threshold: u64,
+    /// Base units below which a transfer is dust
+    pub dust_threshold: u64,
+}
+
+impl TokenTransfer {
+    /// Associated token account of `owner` for the L2 mint
+    pub fn account_of(&self, owner: &Pubkey) -> Pubkey {
+        get_associated_token_address(owner, &self.l2_mint)
+    }
+
+    /// Wallet whose associated token account is paid for `recipient`, none
+    /// when a named token account is paid directly
+    pub fn wallet_of(&self, recipient: &Pubkey) -> Option<Pubkey> {
+        match self.named {
+            None => Some(*recipient),
+            Some(NamedAccount::Owner(owner)) => Some(owner),
+            Some(NamedAccount::Direct(_)) => None,
+        }
+    }
+
+    /// Token account paid for `recipient`
+    pub fn recipient_account(&self, recipient: &Pubkey) -> Pubkey {
+        match self.named {
+            Some(NamedAccount::Direct(account)) => account,
+            _ => self.account_of(&self.wallet_of(recipient).unwrap_or(*recipient)),
+        }
+    }
+
+    /// Checks `data` of recipient `recipient`, owned by the token program, as
+    /// a token account of the L2 mint that is not frozen; fails with a
+    /// [`TokenAccountError`]
+    pub fn check_named(&self, recipient: &Pubkey, data: &[u8]) -> Result<()> {
+        let account = spl_token::state::Account::unpack(data).map_err(|_| {
+            TokenAccountError::NotATokenAccount {
+                account: *recipient,
+            }
+        })?;
+        if account.mint != self.l2_mint {
+            return Err(TokenAccountError::WrongMint {
+                account: *recipient,
+                mint: account.mint,
+                expected: self.l2_mint,
+                owner: account.owner,
+            }
+            .into());
+        }
+        if account.is_frozen() {
+            return Err(TokenAccountError::Frozen {
+                account: *recipient,
+                owner: account.owner,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Creates the recipient's token account if it is missing, then moves
+    /// `amount` to it from the payer's
+    pub fn instructions(
+        &self,
+        payer: &Pubkey,
+        recipient: &Pubkey,
+        amount: u64,
+    ) -> Vec<Instruction> {
+        vec![
+            self.setup_instruction(payer, recipient),
+            self.transfer_instruction(payer, recipient, amount),
+        ]
+    }
+
+    /// Idempotent creation of the recipient's token account, paid by `payer`
+    pub fn setup_instruction(&self, payer: &Pubkey, recipient: &Pubkey) -> Instruction {
+        create_associated_token_account_idempotent(
+            payer,
+            &self.wallet_of(recipient).unwrap_or(*recipient),
+            &self.l2_mint,
+            &spl_token::id(),
+        )
+    }
+
+    /// `transfer_checked` of `amount` from the payer's token account to the
+    /// recipient's
+    pub fn transfer_instruction(
+        &self,
+        payer: &Pubkey,
+        recipient: &Pubkey,
+        amount: u64,
+    ) -> Instruction {
+        spl_token::instruction::transfer_checked(
+            &spl_token::id(),
+            &self.account_of(payer),
+            &self.l2_mint,
+            &self.recipient_account(recipient),
+            payer,
+            &[],
+            amount,
+            self.decimals,
+        )
+        .expect("spl_token::id() is the token program")
+    }
+
+    /// Whether `recipient`'s token account exists
+    pub async fn account_exists(&self, client: &RpcClient, recipient: &Pubkey) -> Result<bool> {
+        Ok(client
+            .get_account_with_commitment(&self.recipient_account(recipient), client.commitment())
+            .await?
+            .value
+            .is_some())
+    }
+
+    /// Token balance of `owner`'s account, none while it does not exist
+    pub async fn balance_of(&self, client: &RpcClient, owner: &Pubkey) -> Result<Option<u64>> {
+        token_balance(client, owner, &self.l2_mint, client.commitment()).await
+    }
+
+    /// Token balance of the account paid for `recipient`, none while it does
+    /// not exist
+    pub async fn recipient_balance(
+        &self,
+        client: &RpcClient,
+        recipient: &Pubkey,
+    ) -> Result<Option<u64>> {
+        account_balance(
+            client,
+            &self.recipient_account(recipient),
+            recipient,
+            client.commitment(),
+        )
+        .await
+    }
+}
+
+/// Balance of `owner`'s associated token account for `mint` at
+/// `commitment`, none while it does not exist
+pub async fn token_balance(
+    client: &RpcClient,
+    owner: &Pubkey,
+    mint: &Pubkey,
+    commitment: CommitmentConfig,
+) -> Result<Option<u64>> {
+    let address = get_associated_token_address(owner, mint);
+    account_balance(client, &address, owner, commitment).await
+}
+
+/// Balance of token account `address` of `owner` at `commitment`, none
+/// while it does not exist
+pub async fn account_balance(
+    client: &RpcClient,
+    address: &Pubkey,
+    owner: &Pubkey,
+    commitment: CommitmentConfig,
+) -> Result<Option<u64>> {
+    let account = client
+        .get_account_with_commitment(address, commitment)
+        .await?
+        .value;
+    account
+        .map(|account| {
+            spl_token::state::Account::unpack(&account.data)
+                .map(|token| token.amount)
+                .map_err(|e| {
+                    anyhow::anyhow!(
+                        "Token account {} of {} does not decode: {}",
+                        address,
+                        owner,
+                        e
+                    )
+                })
+        })
+        .transpose()
+}
+
+/// The L2 mint of every mapped L1 mint
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct MintMap {
+    tokens: HashMap<Pubkey, TokenTransfer>,
+    /// L1 mints whose mapping is disabled
+    disabled: HashMap<Pubkey, TokenTransfer>,
+}
+
+impl MintMap {
+    /// Fails on an L1 mint mapped twice, an NFT with decimals, or mappings
+    /// under an instruction version without the message type
+    pub fn new(mappings: &[MintMapping], version: InstructionVersion) -> Result<Self> {
+        if !mappings.is_empty() && version == InstructionVersion::V1 {
+            return Err(anyhow::anyhow!(
+                "mint_mappings require l2_instruction_version 2 or later, which encodes the message type"
+            ));
+        }
+        let mut map = Self::default();
+        for mapping in mappings {
+            if mapping.nft && mapping.decimals != 0 {
+                return Err(anyhow::anyhow!(
+                    "NFT mint mapping of {} has {} decimals; NFT mints have none",
+                    mapping.l1_mint,
+                    mapping.decimals
+                ));
+            }
+            let token = TokenTransfer {
+                l2_mint: *mapping.l2_mint.pubkey(),
+                decimals: mapping.decimals,
+                nft: mapping.nft,
+                named: None,
+                approval_threshold: mapping.approval_threshold,
+                dual_read_threshold: mapping.dual_read_threshold,
+                dust_threshold: mapping.dust_threshold,
+            };
+            let l1_mint = *mapping.l1_mint.pubkey();
+            if map.tokens.contains_key(&l1_mint) || map.disabled.contains_key(&l1_mint) {
+                return Err(anyhow::anyhow!(
+                    "L1 mint {} is mapped more than once in mint_mappings",
+                    mapping.l1_mint
+                ));
+            }
+            if mapping.enabled {
+                map.tokens.insert(l1_mint, token);
+            } else {
+                map.disabled.insert(l1_mint, token);
+            }
+        }
+        Ok(map)
+    }
+
+    /// Fails when an L2 mint is missing, is no SPL token mint or has other
+    /// decimals than its mapping; disabled mappings are checked too
+    pub async fn check_decimals(&self, client: &RpcClient) -> Result<()> {
+        let tokens: Vec<&TokenTransfer> =
+            self.tokens.values().chain(self.disabled.values()).collect();
+        let mints: Vec<Pubkey> = tokens.iter().map(|token| token.l2_mint).collect();
+        for (tokens, mints) in tokens
+            .chunks(MAX_ACCOUNTS_PER_REQUEST)
+            .zip(mints.chunks(MAX_ACCOUNTS_PER_REQUEST))
+        {
+            let accounts = client
+                .get_multiple_accounts_with_commitment(mints, client.commitment())
+                .await?
+                .value;
+            for (token, account) in tokens.iter().zip(accounts) {
+                let account = account.ok_or_else(|| {
+                    anyhow::anyhow!("L2 mint {} of mint_mappings does not exist", token.l2_mint)
+                })?;
+                let mint = spl_token::state::Mint::unpack(&account.data).map_err(|e| {
+                    anyhow::anyhow!(
+                        "L2 mint {} of mint_mappings is no SPL token mint: {}",
+                        token.l2_mint,
+                        e
+                    )
+                })?;
+                if mint.decimals != token.decimals {
+                    return Err(anyhow::anyhow!(
+                        "L2 mint {} has {} decimals, but its mint mapping says {}",
+                        token.l2_mint,
+                        mint.decimals,
+                        token.decimals
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// L2 side of the enabled mapping of `l1_mint`
+    pub fn enabled(&self, l1_mint: &Pubkey) -> Option<&TokenTransfer> {
+        self.tokens.get(l1_mint)
+    }
+
+    /// Number of enabled and disabled mappings
+    pub fn counts(&self) -> (usize, usize) {
+        (self.tokens.len(), self.disabled.len())
+    }
+}
+
+impl Relayer {
+    /// Check of nonce `nonce`'s `kind` message of `amount` read from PDA
+    /// `data`: relayed with the L2 side of its mint, skipped when the mint's
+    /// mapping is disabled or held while it is unmapped; errors mean the
+    /// nonce must be dead-lettered
+    pub(crate) fn token_transfer(
+        &self,
+        nonce: u64,
+        kind: MessageType,
+        amount: u64,
+        data: &[u8],
+    ) -> Result<MessageCheck> {
+        let l1_mint = self.pda_manager.mint(data).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Nonce {} carries a {:?} message without a mint",
+                nonce,
+                kind
+            )
+        })?;
+        let map = self.mint_map.lock().unwrap().clone();
+        if map.disabled.contains_key(&l1_mint) {
+            return Ok(MessageCheck::SkipMint(l1_mint));
+        }
+        let Some(token) = map.tokens.get(&l1_mint).copied() else {
+            return match self.unmapped_mint {
+                UnmappedMintPolicy::Hold => {
+                    if self.held_mints.lock().unwrap().insert(l1_mint) {
+                        alerts::raise(&format!(
+                            "{}: nonce {} carries L1 mint {}, which has no mint mapping; relaying holds until a reload maps it",
+                            self.name(),
+                            nonce,
+                            l1_mint
+                        ));
+                    }
+                    Ok(MessageCheck::HoldMint(l1_mint))
+                }
+                UnmappedMintPolicy::DeadLetter => Err(anyhow::anyhow!(
+                    "Nonce {} carries L1 mint {}, which has no mint mapping",
+                    nonce,
+                    l1_mint
+                )),
+            };
+        };
+        if self.held_mints.lock().unwrap().remove(&l1_mint) {
+            alerts::resolve(&format!(
+                "{}: L1 mint {} is mapped to {}; relaying resumes",
+                self.name(),
+                l1_mint,
+                token.l2_mint
+            ));
+        }
+        // 消息类型与映射必须一致，避免把同质化代币当作 NFT 转出
+        if token.nft != (kind == MessageType::Nft) {
+            return Err(anyhow::anyhow!(
+                "Nonce {} carries a {:?} message, but L1 mint {} is mapped as {}",
+                nonce,
+                kind,
+                l1_mint,
+                if token.nft { "an NFT" } else { "a token" }
+            ));
+        }
+        if token.nft && amount != 1 {
+            return Err(anyhow::anyhow!(
+                "Nonce {} transfers {} of NFT mint {}; an NFT transfer moves exactly 1",
+                nonce,
+                amount,
+                l1_mint
+            ));
+        }
+        Ok(MessageCheck::Relay(Some(token)))
+    }
+
+    /// Counts a recipient token account created and paid for by the signer
+    pub(crate) fn record_token_account_rent(&self, rent: u64) {
+        self.metrics.token_accounts_created_total.inc();
+        self.metrics.token_account_rent_lamports_total.inc_by(rent);
+    }
+
+    /// Looks up the recipient's token account of a token or NFT `fetched`
+    /// and marks it for creation when missing; when recipients pay for their
+    /// own account, dead-letters the transfer instead. A recipient owned by
+    /// the token program names its token account, paid into directly once
+    /// checked
+    pub(crate) async fn check_token_account(&self, fetched: &mut FetchedTransfer) -> Result<()> {
+        let Some(mut token) = fetched.token else {
+            return Ok(());
+        };
+        if let Err(e) = self
+            .check_named_account(&mut token, &fetched.to_address)
+            .await
+        {
+            if e.downcast_ref::<TokenAccountError>().is_none() {
+                return Err(e);
+            }
+            let reason = e.to_string();
+            self.dead_letter_transfer(fetched, &reason)?;
+            return Err(anyhow::anyhow!(
+                "Nonce {} not relayed: {}",
+                fetched.nonce,
+                reason
+            ));
+        }
+        fetched.token = Some(token);
+        if matches!(token.named, Some(NamedAccount::Direct(_))) {
+            fetched.create_token_account = false;
+            return Ok(());
+        }
+        let exists = token
+            .account_exists(&self.l2_client, &fetched.to_address)
+            .await?;
+        fetched.create_token_account = !exists;
+        if exists || self.token_account_rent_payer == TokenAccountRentPayer::Relayer {
+            return Ok(());
+        }
+        let reason = format!(
+            "recipient {} has no token account {} for mint {}, and token_account_rent_payer = \"recipient\"; replay it once the account exists",
+            fetched.to_address,
+            token.recipient_account(&fetched.to_address),
+            token.l2_mint
+        );
+        self.dead_letter_transfer(fetched, &reason)?;
+        Err(anyhow::anyhow!(
+            "Nonce {} not relayed: {}",
+            fetched.nonce,
+            reason
+        ))
+    }
+
+    /// Sets `token.named` when `recipient` is owned by the token program;
+    /// fails with a [`TokenAccountError`] when that account cannot be paid
+    /// into and `token_account_mismatch` does not fall back to its owner
+    async fn check_named_account(
+        &self,
+        token: &mut TokenTransfer,
+        recipient: &Pubkey,
+    ) -> Result<()> {
+        let account = self
+            .l2_client
+            .get_account_with_commitment(recipient, self.l2_client.commitment())
+            .await?
+            .value;
+        // 钱包（或尚不存在的账户）照旧走关联代币账户
+        let Some(account) = account.filter(|account| account.owner == spl_token::id()) else {
+            return Ok(());
+        };
+        token.named = match token.check_named(recipient, &account.data) {
+            Ok(()) => Some(NamedAccount::Direct(*recipient)),
+            Err(e) => match (
+                self.token_account_mismatch,
+                e.downcast_ref::<TokenAccountError>()
+                    .and_then(TokenAccountError::owner),
+            ) {
+                (TokenAccountMismatch::Owner, Some(owner)) => {
+                    tracing::warn!(
+                        "{}; paying the associated token account of its owner {}",
+                        e,
+                        owner
+                    );
+                    Some(NamedAccount::Owner(owner))
+                }
+                _ => return Err(e),
+            },
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spl_token::state::AccountState;
+
+    fn mapping(l1_mint: Pubkey, enabled: bool) -> MintMapping {
+        MintMapping {
+            l1_mint: l1_mint.into(),
+            l2_mint: Pubkey::new_unique().into(),
+            decimals: 6,
+            nft: false,
+            enabled,
+            approval_threshold: None,
+            dual_read_threshold: 0,
+            dust_threshold: 0,
+        }
+    }
+
+    #[test]
+    fn disabled_mappings_are_kept_apart_and_still_unique() {
+        let (enabled, disabled) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let map = MintMap::new(
+            &[mapping(enabled, true), mapping(disabled, false)],
+            InstructionVersion::V2,
+        )
+        .unwrap();
+        assert!(map.tokens.contains_key(&enabled));
+        assert!(map.disabled.contains_key(&disabled));
+        assert_eq!(map.counts(), (1, 1));
+        // 停用的映射也不能与启用的重复
+        assert!(MintMap::new(
+            &[mapping(enabled, true), mapping(enabled, false)],
+            InstructionVersion::V2,
+        )
+        .is_err());
+        assert!(MintMap::new(&[mapping(enabled, true)], InstructionVersion::V1).is_err());
+    }
+
+    fn token_account(mint: Pubkey, owner: Pubkey, state: AccountState) -> Vec<u8> {
+        let mut data = vec![0; spl_token::state::Account::LEN];
+        spl_token::state::Account {
+            mint,
+            owner,
+            amount: 5,
+            state,
+            ..spl_token::state::Account::default()
+        }
+        .pack_into_slice(&mut data);
+        data
+    }
+
+    #[test]
+    fn a_named_token_account_is_checked_and_paid_directly() {
+        let (mint, owner, named) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let mut token = TokenTransfer {
+            l2_mint: mint,
+            decimals: 6,
+            nft: false,
+            named: None,
+            approval_threshold: None,
+            dual_read_threshold: 0,
+            dust_threshold: 0,
+        };
+        let check = |data: Vec<u8>| {
+            token
+                .check_named(&named, &data)
+                .err()
+                .map(|e| e.downcast::<TokenAccountError>().unwrap())
+        };
+        assert_eq!(
+            check(token_account(mint, owner, AccountState::Initialized)),
+            None
+        );
+        let other = Pubkey::new_unique();
+        assert_eq!(
+            check(token_account(other, owner, AccountState::Initialized)),
+            Some(TokenAccountError::WrongMint {
+                account: named,
+                mint: other,
+                expected: mint,
+                owner,
+            })
+        );
+        assert_eq!(
+            check(token_account(mint, owner, AccountState::Frozen)),
+            Some(TokenAccountError::Frozen {
+                account: named,
+                owner
+            })
+        );
+        // 铸币账户同属代币程序，但不是代币账户
+        let error = check(vec![0; spl_token::state::Mint::LEN]).unwrap();
+        assert_eq!(
+            error,
+            TokenAccountError::NotATokenAccount { account: named }
+        );
+        assert_eq!(error.owner(), None);
+
+        assert_eq!(token.recipient_account(&named), token.account_of(&named));
+        token.named = Some(NamedAccount::Direct(named));
+        assert_eq!(token.recipient_account(&named), named);
+        assert_eq!(token.wallet_of(&named), None);
+        token.named = Some(NamedAccount::Owner(owner));
+        assert_eq!(token.recipient_account(&named), token.account_of(&owner));
+        assert_eq!(token.wallet_of(&named), Some(owner));
+    }
+}
@@ -6,64 +6,1145 @@
  * @LastEditors: Yulin
  * @LastEditTime: 2024-11-20 22:20:50
  */
+use crate::{
+    inflight::TransactionOutcome,
+    instruction_accounts::InstructionAccounts,
+    lookup_tables::LookupTables,
+    pda_recipient::DepositRoute,
+    pipeline::FetchedTransfer,
+    priority_fee::ComputeBudget,
+    pubkeys::{DestinationAccount, DestinationProgramId},
+    Relayer,
+};
 use anyhow::Result;
-use solana_client::rpc_client::RpcClient;
+use borsh::BorshDeserialize;
+use serde::{Deserialize, Serialize};
+use solana_client::rpc_config::RpcSimulateTransactionConfig;
 use solana_sdk::{
-    instruction::{AccountMeta, Instruction},
+    compute_budget::{self, ComputeBudgetInstruction},
+    hash::Hash,
+    instruction::{AccountMeta, Instruction, InstructionError},
+    message::{v0, Message, VersionedMessage},
+    packet::PACKET_DATA_SIZE,
+    program_pack::Pack,
     pubkey::Pubkey,
+    signature::Signature,
     signer::Signer,
-    transaction::Transaction,
+    system_instruction,
+    transaction::{TransactionError, VersionedTransaction},
+};
+use std::sync::{
+    atomic::{AtomicBool, AtomicU32, Ordering},
+    Arc,
 };
 
+/// Largest serialized transaction accepted by the cluster
+pub const MAX_TRANSACTION_SIZE: usize = PACKET_DATA_SIZE;
+
+/// Most compute units one transaction may request
+const MAX_COMPUTE_UNIT_LIMIT: u32 = 1_400_000;
+
+/// Compute units of each instruction of a transaction without a
+/// `SetComputeUnitLimit`
+const DEFAULT_INSTRUCTION_COMPUTE_UNITS: u32 = 200_000;
+
+/// Anchor discriminator of `relay_message`
+const RELAY_MESSAGE_DISCRIMINATOR: [u8; 8] = [187, 90, 182, 138, 51, 248, 175, 98];
+
+/// Message type byte of v2 data when the message source carries none
+pub const UNKNOWN_MESSAGE_TYPE: u8 = u8::MAX;
+
+/// L2 programs and the instruction versions they accept
+const COMPATIBILITY: &[(&str, &[InstructionVersion])] = &[(
+    "8TDycJBTEsyWV2w7vQnTmrePBaHHJDdDxxV3vekir2gC",
+    &[InstructionVersion::V1],
+)];
+
+/// Layout of the `relay_message` instruction data (`l2_instruction_version`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "u8", into = "u8")]
+pub enum InstructionVersion {
+    /// discriminator, amount (u64 LE), nonce (u64 LE)
+    #[default]
+    V1,
+    /// discriminator, version byte (2), amount (u64 LE), nonce (u64 LE),
+    /// message type (u8, 255 when unknown), source nonce (u64 LE, the first
+    /// L1 nonce the transfer covers)
+    V2,
+    /// v2 fields with version byte 3, followed by the bridge domain (u64 LE)
+    V3,
+    /// v3 fields with version byte 4, followed by the transfer deadline
+    /// (i64 LE Unix time, 0 when the transfer has none)
+    V4,
+    /// v4 fields with version byte 5, followed by the L1 sender (32 bytes,
+    /// zeros when unknown or withheld)
+    V5,
+    /// v5 fields with version byte 6, followed by the entry index and the
+    /// entry count (u8 each, 0 and 1 for a single-entry PDA)
+    V6,
+}
+
+impl TryFrom<u8> for InstructionVersion {
+    type Error = String;
+
+    fn try_from(version: u8) -> Result<Self, Self::Error> {
+        match version {
+            1 => Ok(Self::V1),
+            2 => Ok(Self::V2),
+            3 => Ok(Self::V3),
+            4 => Ok(Self::V4),
+            5 => Ok(Self::V5),
+            6 => Ok(Self::V6),
+            other => Err(format!(
+                "unsupported L2 instruction version {} (expected 1 to 6)",
+                other
+            )),
+        }
+    }
+}
+
+impl From<InstructionVersion> for u8 {
+    fn from(version: InstructionVersion) -> Self {
+        match version {
+            InstructionVersion::V1 => 1,
+            InstructionVersion::V2 => 2,
+            InstructionVersion::V3 => 3,
+            InstructionVersion::V4 => 4,
+            InstructionVersion::V5 => 5,
+            InstructionVersion::V6 => 6,
+        }
+    }
+}
+
+impl InstructionVersion {
+    /// `relay_message` data for `transfer` in `domain` (only encoded from v3);
+    /// a Merkle proof is appended in every version
+    pub fn encode(self, transfer: &FetchedTransfer, domain: u64) -> Vec<u8> {
+        let mut data = Vec::with_capacity(50);
+        data.extend_from_slice(&RELAY_MESSAGE_DISCRIMINATOR);
+        match self {
+            Self::V1 => {
+                data.extend_from_slice(&transfer.amount.to_le_bytes());
+                data.extend_from_slice(&transfer.nonce.to_le_bytes());
+            }
+            Self::V2 | Self::V3 | Self::V4 | Self::V5 | Self::V6 => {
+                let source_nonce = transfer.coalesced.first().unwrap_or(&transfer.nonce);
+                data.push(u8::from(self));
+                data.extend_from_slice(&transfer.amount.to_le_bytes());
+                data.extend_from_slice(&transfer.nonce.to_le_bytes());
+                data.push(transfer.message_type.unwrap_or(UNKNOWN_MESSAGE_TYPE));
+                data.extend_from_slice(&source_nonce.to_le_bytes());
+                if self != Self::V2 {
+                    data.extend_from_slice(&domain.to_le_bytes());
+                }
+                if matches!(self, Self::V4 | Self::V5 | Self::V6) {
+                    let expires_at = transfer.expires_at.unwrap_or_default() as i64;
+                    data.extend_from_slice(&expires_at.to_le_bytes());
+                }
+                if matches!(self, Self::V5 | Self::V6) {
+                    data.extend_from_slice(transfer.sender.unwrap_or_default().as_ref());
+                }
+                if self == Self::V6 {
+                    let (index, count) = transfer
+                        .entry
+                        .map_or((0, 1), |entry| (entry.index, entry.count));
+                    data.extend_from_slice(&[index, count]);
+                }
+            }
+        }
+        if let Some(proof) = &transfer.proof {
+            proof.encode_into(&mut data);
+        }
+        data
+    }
+
+    /// Amount and nonce of `relay_message` data in this layout; `None` if the
+    /// data does not match it
+    pub fn decode(self, data: &[u8]) -> Option<(u64, u64)> {
+        let fields = data.strip_prefix(&RELAY_MESSAGE_DISCRIMINATOR)?;
+        let fields = match self {
+            Self::V1 => fields,
+            Self::V2 | Self::V3 | Self::V4 | Self::V5 | Self::V6 => {
+                fields.strip_prefix(&[u8::from(self)])?
+            }
+        };
+        let amount = u64::from_le_bytes(fields.get(..8)?.try_into().ok()?);
+        let nonce = u64::from_le_bytes(fields.get(8..16)?.try_into().ok()?);
+        Some((amount, nonce))
+    }
+
+    /// L1 sender of v5 or v6 `relay_message` data; `None` in other layouts or
+    /// when it was withheld
+    pub fn decode_sender(self, data: &[u8]) -> Option<Pubkey> {
+        if !matches!(self, Self::V5 | Self::V6) {
+            return None;
+        }
+        // 判别符、版本、金额、nonce、类型、源 nonce、域、截止时间之后
+        let field: [u8; 32] = data.get(50..82)?.try_into().ok()?;
+        let sender = Pubkey::from(field);
+        (sender != Pubkey::default()).then_some(sender)
+    }
+
+    /// Entry index and entry count of v6 `relay_message` data; `None` in
+    /// other layouts
+    pub fn decode_entry(self, data: &[u8]) -> Option<(u8, u8)> {
+        if self != Self::V6 {
+            return None;
+        }
+        Some((*data.get(82)?, *data.get(83)?))
+    }
+
+    /// Fails if `program_id` is known not to accept this version; unknown
+    /// programs are assumed to accept it
+    pub fn check_compatibility(self, program_id: &DestinationProgramId) -> Result<()> {
+        let program = program_id.to_string();
+        match COMPATIBILITY.iter().find(|(id, _)| *id == program) {
+            Some((_, versions)) if !versions.contains(&self) => Err(anyhow::anyhow!(
+                "L2 program {} does not accept instruction version {} (supported: {})",
+                program,
+                u8::from(self),
+                versions
+                    .iter()
+                    .map(|version| u8::from(*version).to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+            Some(_) => Ok(()),
+            None => {
+                tracing::info!(
+                    "L2 program {} is not in the compatibility table; assuming it accepts instruction version {}",
+                    program,
+                    u8::from(self)
+                );
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Whether instruction `data` is a `relay_message` call, in any version
+pub fn is_relay_message(data: &[u8]) -> bool {
+    data.starts_with(&RELAY_MESSAGE_DISCRIMINATOR)
+}
+
+/// SHA-256 (base58) of the transaction's message with the recent blockhash
+/// zeroed: equal for transactions making the same calls on the same
+/// accounts, whatever their signatures and blockhash
+pub fn fingerprint(transaction: &VersionedTransaction) -> String {
+    let mut message = transaction.message.clone();
+    message.set_recent_blockhash(Hash::default());
+    solana_sdk::hash::hash(&message.serialize()).to_string()
+}
+
+/// A relay transaction over the packet limit even without optional content
+#[derive(Debug)]
+pub struct TransactionTooLarge {
+    pub nonce: u64,
+    pub size: usize,
+}
+
+impl std::fmt::Display for TransactionTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Transaction for nonce {} is {} bytes without optional instructions, {} over the {}-byte limit",
+            self.nonce,
+            self.size,
+            self.size - MAX_TRANSACTION_SIZE,
+            MAX_TRANSACTION_SIZE
+        )
+    }
+}
+
+impl std::error::Error for TransactionTooLarge {}
+
+/// Size and compute needs of a built transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransactionEstimate {
+    /// Serialized size in bytes
+    pub size: usize,
+    /// Compute units the transaction may use: its `SetComputeUnitLimit`, or
+    /// the default of its instructions
+    pub unit_limit: u32,
+    /// Compute units consumed by a simulation; none until simulated
+    pub compute_units: Option<u64>,
+}
+
+impl TransactionEstimate {
+    pub fn of(transaction: &VersionedTransaction) -> Result<Self> {
+        Ok(Self {
+            size: serialized_size(transaction)?,
+            unit_limit: requested_unit_limit(&transaction.message),
+            compute_units: None,
+        })
+    }
+
+    pub fn simulated(self, compute_units: u64) -> Self {
+        Self {
+            compute_units: Some(compute_units),
+            ..self
+        }
+    }
+
+    /// The limit the transaction passes, if any
+    pub fn exceeded(&self) -> Option<String> {
+        if self.size > MAX_TRANSACTION_SIZE {
+            return Some(format!(
+                "{} bytes, over the {}-byte limit",
+                self.size, MAX_TRANSACTION_SIZE
+            ));
+        }
+        match self.compute_units {
+            Some(units) if units > u64::from(self.unit_limit) => Some(format!(
+                "{} compute units, over its limit of {}",
+                units, self.unit_limit
+            )),
+            _ => None,
+        }
+    }
+
+    /// How many of the `count` transfers packed in the transaction fit its
+    /// compute limit, at least one and fewer than `count`
+    pub fn fitting_transfers(&self, count: usize) -> usize {
+        let per_transfer = self
+            .compute_units
+            .unwrap_or_default()
+            .div_ceil(count as u64)
+            .max(1);
+        (u64::from(self.unit_limit) / per_transfer).clamp(1, count as u64 - 1) as usize
+    }
+}
+
+/// Compute-unit limit of `message`: that of its `SetComputeUnitLimit`, or the
+/// default of its other instructions, at most `MAX_COMPUTE_UNIT_LIMIT`
+fn requested_unit_limit(message: &VersionedMessage) -> u32 {
+    let mut instructions = 0u32;
+    for instruction in message.instructions() {
+        // 被调用的程序总在静态账户中
+        if message.static_account_keys()[instruction.program_id_index as usize]
+            != compute_budget::id()
+        {
+            instructions += 1;
+            continue;
+        }
+        if let Ok(ComputeBudgetInstruction::SetComputeUnitLimit(limit)) =
+            ComputeBudgetInstruction::try_from_slice(&instruction.data)
+        {
+            return limit.min(MAX_COMPUTE_UNIT_LIMIT);
+        }
+    }
+    instructions
+        .saturating_mul(DEFAULT_INSTRUCTION_COMPUTE_UNITS)
+        .min(MAX_COMPUTE_UNIT_LIMIT)
+}
+
 pub struct TransactionBuilder {
-    pub program_id: Pubkey,
-    pub nonce_account: Pubkey,
+    pub program_id: DestinationProgramId,
+    pub nonce_account: DestinationAccount,
+    pub instruction_version: InstructionVersion,
+    /// Bridge domain encoded in v3 data and in the memo
+    pub domain: Option<u64>,
+    /// Accounts of the `relay_message` instruction
+    pub accounts: InstructionAccounts,
+    /// System nonce account advanced first by every transaction, whose nonce
+    /// is then passed as the recent blockhash
+    pub durable_nonce: Option<DestinationAccount>,
+    /// Set while the durable nonce account is closed and transactions fall
+    /// back to a recent blockhash, leaving out `AdvanceNonceAccount`
+    pub durable_nonce_closed: Arc<AtomicBool>,
+    /// Tables v0 transactions are compiled against; legacy transactions
+    /// when none
+    pub lookup_tables: Option<Arc<LookupTables>>,
 }
 
 impl TransactionBuilder {
-    pub fn new(program_id: Pubkey, nonce_account: Pubkey) -> Self {
-        Self {
+    /// Fails if `program_id` does not accept `instruction_version`, or if a
+    /// bridge domain is given without version 3 or the other way round
+    pub fn new(
+        program_id: DestinationProgramId,
+        nonce_account: DestinationAccount,
+        instruction_version: InstructionVersion,
+        domain: Option<u64>,
+        accounts: InstructionAccounts,
+    ) -> Result<Self> {
+        instruction_version.check_compatibility(&program_id)?;
+        match (instruction_version, domain) {
+            (InstructionVersion::V3, None) => {
+                return Err(anyhow::anyhow!(
+                    "l2_instruction_version = 3 requires bridge_domain"
+                ))
+            }
+            (InstructionVersion::V1 | InstructionVersion::V2, Some(_)) => {
+                return Err(anyhow::anyhow!(
+                    "bridge_domain is only encoded by l2_instruction_version = 3"
+                ))
+            }
+            _ => {}
+        }
+        Ok(Self {
             program_id,
             nonce_account,
-        }
+            instruction_version,
+            domain,
+            accounts,
+            durable_nonce: None,
+            durable_nonce_closed: Arc::default(),
+            lookup_tables: None,
+        })
     }
 
+    /// `AdvanceNonceAccount`, which must be the first instruction of a
+    /// durable-nonce transaction
+    fn advance_nonce(&self, payer: &Pubkey) -> Option<Instruction> {
+        self.durable_nonce
+            .filter(|_| !self.durable_nonce_closed.load(Ordering::Relaxed))
+            .map(|account| system_instruction::advance_nonce_account(account.pubkey(), payer))
+    }
+
+    /// Builds the `relay_message` transaction for `transfer`, followed by the
+    /// recipient program's `deposit` instruction when routed through one, the
+    /// token transfer of a token or NFT message (after the creation of the
+    /// recipient's token account, when `create_token_account` is set), the
+    /// compute-budget instructions of `budget`, and a memo tying it to the L1 nonce and slot (and the merged nonces, if
+    /// coalesced). Optional content is dropped until the transaction fits in
+    /// a packet.
     pub fn build_transfer_transaction(
         &self,
-        amount: u64,
-        nonce: u64,
-        to_address: &Pubkey,
-        payer: &impl Signer,
-        client: &RpcClient,
-    ) -> Result<Transaction> {
-        let system_program = solana_sdk::system_program::id();
-
-        let accounts = vec![
-            AccountMeta::new(self.nonce_account, false),
-            AccountMeta::new(payer.pubkey(), true),
-            AccountMeta::new(*to_address, false),
-            AccountMeta::new_readonly(system_program, false),
-        ];
-
-        let mut instruction_data = Vec::with_capacity(24);
-        instruction_data.extend_from_slice(&[187, 90, 182, 138, 51, 248, 175, 98]);
-        instruction_data.extend_from_slice(&amount.to_le_bytes());
-        instruction_data.extend_from_slice(&nonce.to_le_bytes());
-
-        let instruction = Instruction {
-            program_id: self.program_id,
-            accounts,
-            data: instruction_data,
+        transfer: &FetchedTransfer,
+        deposit: Option<&DepositRoute>,
+        budget: Option<&ComputeBudget>,
+        payer: &dyn Signer,
+        recent_blockhash: Hash,
+    ) -> Result<VersionedTransaction> {
+        self.build_relay_transaction(transfer, deposit, budget, payer, recent_blockhash, true)
+    }
+
+    /// `build_transfer_transaction` without the creation of the recipient's
+    /// token account, sent before by `build_account_setup_transaction`
+    pub fn build_transfer_transaction_after_setup(
+        &self,
+        transfer: &FetchedTransfer,
+        budget: Option<&ComputeBudget>,
+        payer: &dyn Signer,
+        recent_blockhash: Hash,
+    ) -> Result<VersionedTransaction> {
+        self.build_relay_transaction(transfer, None, budget, payer, recent_blockhash, false)
+    }
+
+    /// Creation of the recipient's token account of a token or NFT
+    /// `transfer` on its own, with the compute-budget instructions of
+    /// `budget`; none for a native transfer or an existing account
+    pub fn build_account_setup_transaction(
+        &self,
+        transfer: &FetchedTransfer,
+        budget: Option<&ComputeBudget>,
+        payer: &dyn Signer,
+        recent_blockhash: Hash,
+    ) -> Option<Result<VersionedTransaction>> {
+        let token = transfer.token.filter(|_| transfer.create_token_account)?;
+        let mut instructions = vec![token.setup_instruction(&payer.pubkey(), &transfer.to_address)];
+        if let Some(budget) = budget {
+            instructions.extend(budget.instructions());
+        }
+        Some(self.sign(&instructions, payer, recent_blockhash))
+    }
+
+    fn build_relay_transaction(
+        &self,
+        transfer: &FetchedTransfer,
+        deposit: Option<&DepositRoute>,
+        budget: Option<&ComputeBudget>,
+        payer: &dyn Signer,
+        recent_blockhash: Hash,
+        account_setup: bool,
+    ) -> Result<VersionedTransaction> {
+        let mut required: Vec<_> = self.advance_nonce(&payer.pubkey()).into_iter().collect();
+        required.push(self.relay_instruction(transfer, &payer.pubkey()));
+        if let Some(route) = deposit {
+            required.push(deposit_instruction(route, transfer, &payer.pubkey()));
+        }
+        match &transfer.token {
+            Some(token) if account_setup && transfer.create_token_account => required
+                .extend(token.instructions(&payer.pubkey(), &transfer.to_address, transfer.amount)),
+            Some(token) => required.push(token.transfer_instruction(
+                &payer.pubkey(),
+                &transfer.to_address,
+                transfer.amount,
+            )),
+            None => {}
+        }
+        if let Some(budget) = budget {
+            required.extend(budget.instructions());
+        }
+
+        // 超出大小限制时依次去掉可选内容：先去掉 memo 中的 nonce 列表，再去掉整个 memo
+        let memos = [self.memo(transfer, true), self.memo(transfer, false)];
+        let candidates = memos
+            .iter()
+            .map(|memo| {
+                let mut instructions = required.clone();
+                instructions.push(spl_memo::build_memo(memo.as_bytes(), &[]));
+                instructions
+            })
+            .chain([required.clone()]);
+
+        let mut size = 0;
+        for (dropped, instructions) in candidates.enumerate() {
+            let transaction = self.sign(&instructions, payer, recent_blockhash)?;
+            size = serialized_size(&transaction)?;
+            if size <= MAX_TRANSACTION_SIZE {
+                if dropped > 0 {
+                    tracing::info!(
+                        "Dropped {} to fit the {}-byte limit ({} bytes)",
+                        if dropped == 1 {
+                            "the memo's nonce list"
+                        } else {
+                            "the memo"
+                        },
+                        MAX_TRANSACTION_SIZE,
+                        size
+                    );
+                }
+                return Ok(transaction);
+            }
+        }
+
+        Err(TransactionTooLarge {
+            nonce: transfer.nonce,
+            size,
+        }
+        .into())
+    }
+
+    /// Packs the `relay_message` instructions of as many of `transfers` as
+    /// fit, in order, into one transaction: each followed by its recipient
+    /// program's `deposit` instruction when routed through one, then the
+    /// compute-budget instructions of `budget` (whose limit is per transfer)
+    /// and a memo listing the packed nonces. Packing stops before the
+    /// transaction outgrows a packet or its compute-unit limit passes the
+    /// per-transaction maximum. Returns the transaction and how many
+    /// transfers it packs.
+    pub fn build_batch_transfer_transaction(
+        &self,
+        transfers: &[(&FetchedTransfer, Option<&DepositRoute>)],
+        budget: Option<&ComputeBudget>,
+        payer: &dyn Signer,
+        recent_blockhash: Hash,
+    ) -> Result<(VersionedTransaction, usize)> {
+        self.pack(transfers, budget, payer, recent_blockhash, |packed| {
+            self.packed_memo(packed)
+        })
+    }
+
+    /// Builds the transaction relaying the pending entries of one nonce,
+    /// packed like `build_batch_transfer_transaction` under a memo listing
+    /// their indexes; fails unless every entry fits
+    pub fn build_entries_transaction(
+        &self,
+        entries: &[(&FetchedTransfer, Option<&DepositRoute>)],
+        budget: Option<&ComputeBudget>,
+        payer: &dyn Signer,
+        recent_blockhash: Hash,
+    ) -> Result<VersionedTransaction> {
+        let (transaction, count) =
+            self.pack(entries, budget, payer, recent_blockhash, |packed| {
+                self.entries_memo(packed)
+            })?;
+        if count < entries.len() {
+            return Err(anyhow::anyhow!(
+                "Only {} of the {} entries of nonce {} fit in one transaction",
+                count,
+                entries.len(),
+                entries[0].0.nonce
+            ));
+        }
+        Ok(transaction)
+    }
+
+    /// Packs as many of `transfers` as fit, with the memo `memo` gives the
+    /// packed ones
+    fn pack(
+        &self,
+        transfers: &[(&FetchedTransfer, Option<&DepositRoute>)],
+        budget: Option<&ComputeBudget>,
+        payer: &dyn Signer,
+        recent_blockhash: Hash,
+        memo: impl Fn(&[(&FetchedTransfer, Option<&DepositRoute>)]) -> String,
+    ) -> Result<(VersionedTransaction, usize)> {
+        let mut packed = None;
+        let mut instructions: Vec<_> = self.advance_nonce(&payer.pubkey()).into_iter().collect();
+        for (index, (transfer, deposit)) in transfers.iter().enumerate() {
+            let count = index + 1;
+            let budget = budget.map(|budget| ComputeBudget {
+                unit_limit: budget
+                    .unit_limit
+                    .map(|limit| limit.saturating_mul(count as u32)),
+                ..*budget
+            });
+            if budget
+                .and_then(|budget| budget.unit_limit)
+                .is_some_and(|limit| limit > MAX_COMPUTE_UNIT_LIMIT)
+            {
+                break;
+            }
+            instructions.push(self.relay_instruction(transfer, &payer.pubkey()));
+            if let Some(route) = deposit {
+                instructions.push(deposit_instruction(route, transfer, &payer.pubkey()));
+            }
+            let mut candidate = instructions.clone();
+            if let Some(budget) = &budget {
+                candidate.extend(budget.instructions());
+            }
+            let memo = memo(&transfers[..count]);
+            candidate.push(spl_memo::build_memo(memo.as_bytes(), &[]));
+            // 签名不影响大小，逐个加入转账时先用未签名交易衡量
+            let size = serialized_size(&self.unsigned(&candidate, &payer.pubkey())?)?;
+            if size > MAX_TRANSACTION_SIZE {
+                if count == 1 {
+                    return Err(anyhow::anyhow!(
+                        "Transaction for nonce {} is {} bytes, over the {}-byte limit",
+                        transfer.nonce,
+                        size,
+                        MAX_TRANSACTION_SIZE
+                    ));
+                }
+                break;
+            }
+            packed = Some((candidate, count));
+        }
+
+        let (instructions, count) =
+            packed.ok_or_else(|| anyhow::anyhow!("No transfers to pack"))?;
+        let transaction = self.sign(&instructions, payer, recent_blockhash)?;
+        Ok((transaction, count))
+    }
+
+    /// Message of `instructions`: v0 over the lookup tables when configured,
+    /// legacy otherwise
+    fn compile(
+        &self,
+        instructions: &[Instruction],
+        payer: &Pubkey,
+        recent_blockhash: Hash,
+    ) -> Result<VersionedMessage> {
+        match &self.lookup_tables {
+            Some(tables) => Ok(VersionedMessage::V0(
+                v0::Message::try_compile(payer, instructions, &tables.accounts(), recent_blockhash)
+                    .map_err(|e| anyhow::anyhow!("Failed to compile v0 transaction: {}", e))?,
+            )),
+            None => Ok(VersionedMessage::Legacy(Message::new_with_blockhash(
+                instructions,
+                Some(payer),
+                &recent_blockhash,
+            ))),
+        }
+    }
+
+    /// Transaction of `instructions` signed by `payer`
+    fn sign(
+        &self,
+        instructions: &[Instruction],
+        payer: &dyn Signer,
+        recent_blockhash: Hash,
+    ) -> Result<VersionedTransaction> {
+        let message = self.compile(instructions, &payer.pubkey(), recent_blockhash)?;
+        VersionedTransaction::try_new(message, &[payer])
+            .map_err(|e| anyhow::anyhow!("Failed to sign transaction: {}", e))
+    }
+
+    /// Transaction of `instructions` with placeholder signatures, for sizing
+    fn unsigned(
+        &self,
+        instructions: &[Instruction],
+        payer: &Pubkey,
+    ) -> Result<VersionedTransaction> {
+        let message = self.compile(instructions, payer, Hash::default())?;
+        Ok(VersionedTransaction {
+            signatures: vec![
+                Signature::default();
+                usize::from(message.header().num_required_signatures)
+            ],
+            message,
+        })
+    }
+
+    /// The `relay_message` instruction of `transfer`
+    fn relay_instruction(&self, transfer: &FetchedTransfer, payer: &Pubkey) -> Instruction {
+        Instruction {
+            program_id: *self.program_id.pubkey(),
+            accounts: self.accounts.resolve(&self.nonce_account, payer, transfer),
+            data: self
+                .instruction_version
+                .encode(transfer, self.domain.unwrap_or_default()),
+        }
+    }
+
+    /// Memo of a packed transaction: the first nonce and its L1 slot, the
+    /// bridge domain when configured, and every packed nonce
+    fn packed_memo(&self, transfers: &[(&FetchedTransfer, Option<&DepositRoute>)]) -> String {
+        let first = transfers[0].0;
+        let mut memo = serde_json::json!({
+            "nonce": first.nonce,
+            "l1_slot": first.l1_slot,
+        });
+        if let Some(domain) = self.domain {
+            memo["domain"] = serde_json::json!(domain);
+        }
+        memo["packed"] = serde_json::json!(transfers
+            .iter()
+            .map(|(transfer, _)| transfer.nonce)
+            .collect::<Vec<_>>());
+        memo.to_string()
+    }
+
+    /// Memo of the transaction relaying entries of one nonce: the nonce and
+    /// its L1 slot, the bridge domain when configured, and the entry indexes
+    fn entries_memo(&self, entries: &[(&FetchedTransfer, Option<&DepositRoute>)]) -> String {
+        let first = entries[0].0;
+        let mut memo = serde_json::json!({
+            "nonce": first.nonce,
+            "l1_slot": first.l1_slot,
+        });
+        if let Some(domain) = self.domain {
+            memo["domain"] = serde_json::json!(domain);
+        }
+        memo["entries"] = serde_json::json!(entries
+            .iter()
+            .filter_map(|(transfer, _)| transfer.entry.map(|entry| entry.index))
+            .collect::<Vec<_>>());
+        memo.to_string()
+    }
+
+    /// Memo tying the transaction to the L1 nonce and slot (and the bridge
+    /// domain, when configured, the message digest, the L1 sender and the L2
+    /// mint of a token transfer); `with_nonces`
+    /// adds the merged nonces of a coalesced transfer
+    fn memo(&self, transfer: &FetchedTransfer, with_nonces: bool) -> String {
+        let mut memo = serde_json::json!({
+            "nonce": transfer.nonce,
+            "l1_slot": transfer.l1_slot,
+        });
+        if let Some(domain) = self.domain {
+            memo["domain"] = serde_json::json!(domain);
+        }
+        if let Some(digest) = transfer.digest {
+            memo["digest"] = serde_json::json!(digest.to_string());
+        }
+        if let Some(sender) = transfer.sender {
+            memo["from"] = serde_json::json!(sender.to_string());
+        }
+        if let Some(token) = &transfer.token {
+            memo["mint"] = serde_json::json!(token.l2_mint.to_string());
+        }
+        if with_nonces && !transfer.coalesced.is_empty() {
+            memo["nonces"] = serde_json::json!(transfer.coalesced);
+        }
+        memo.to_string()
+    }
+}
+
+fn serialized_size(transaction: &VersionedTransaction) -> Result<usize> {
+    bincode::serialized_size(transaction)
+        .map(|size| size as usize)
+        .map_err(|e| anyhow::anyhow!("Failed to serialize transaction: {}", e))
+}
+
+/// Deposit instruction of the program owning `transfer`'s recipient:
+/// accounts recipient (writable) and payer (signer), data discriminator,
+/// amount (u64 LE), nonce (u64 LE)
+fn deposit_instruction(
+    route: &DepositRoute,
+    transfer: &FetchedTransfer,
+    payer: &Pubkey,
+) -> Instruction {
+    let mut data = Vec::with_capacity(24);
+    data.extend_from_slice(&route.discriminator);
+    data.extend_from_slice(&transfer.amount.to_le_bytes());
+    data.extend_from_slice(&transfer.nonce.to_le_bytes());
+    Instruction {
+        program_id: route.program_id,
+        accounts: vec![
+            AccountMeta::new(transfer.to_address, false),
+            AccountMeta::new_readonly(*payer, true),
+        ],
+        data,
+    }
+}
+
+impl Relayer {
+    /// Estimate of `transaction`, with the compute units of a simulation when
+    /// `simulate_compute_units` is set. A transaction that ran out of compute
+    /// units is estimated one above its limit; a simulation failing
+    /// otherwise leaves them unknown
+    pub(crate) async fn estimate_transaction(
+        &self,
+        transaction: &VersionedTransaction,
+    ) -> Result<TransactionEstimate> {
+        let estimate = TransactionEstimate::of(transaction)?;
+        if !self.pipeline.simulate_compute_units || estimate.size > MAX_TRANSACTION_SIZE {
+            return Ok(estimate);
+        }
+        let config = RpcSimulateTransactionConfig {
+            commitment: Some(self.l2_client.commitment()),
+            ..RpcSimulateTransactionConfig::default()
         };
+        let simulation = self
+            .l2_client
+            .simulate_transaction_with_config(transaction, config)
+            .await?
+            .value;
+        Ok(match (simulation.err, simulation.units_consumed) {
+            (
+                Some(TransactionError::InstructionError(
+                    _,
+                    InstructionError::ComputationalBudgetExceeded,
+                )),
+                units,
+            ) => estimate.simulated(
+                units
+                    .unwrap_or_default()
+                    .max(u64::from(estimate.unit_limit) + 1),
+            ),
+            (Some(err), _) => {
+                tracing::warn!(
+                    "simulation of {} failed, compute units unknown: {}",
+                    transaction.signatures[0],
+                    err
+                );
+                estimate
+            }
+            (None, Some(units)) => estimate.simulated(units),
+            (None, None) => estimate,
+        })
+    }
 
-        let recent_blockhash = client.get_latest_blockhash()?;
-        let transaction = Transaction::new_signed_with_payer(
-            &[instruction],
-            Some(&payer.pubkey()),
-            &[payer],
+    /// Builds the transaction of `fetched`. A token or NFT transfer that
+    /// creates the recipient's token account and whose transaction would
+    /// pass a limit creates it in a transaction of its own first, and is
+    /// built without it
+    pub(crate) async fn build_within_limits(
+        &self,
+        fetched: &FetchedTransfer,
+        deposit: Option<&DepositRoute>,
+        budget: Option<&ComputeBudget>,
+        recent_blockhash: Hash,
+    ) -> Result<VersionedTransaction> {
+        let payer = self.signers.active();
+        let built = self.transaction_builder.build_transfer_transaction(
+            fetched,
+            deposit,
+            budget,
+            payer,
             recent_blockhash,
         );
-
+        let exceeded = match built {
+            Ok(transaction) => match self.estimate_transaction(&transaction).await?.exceeded() {
+                None => return Ok(transaction),
+                Some(exceeded) => exceeded,
+            },
+            Err(e) => match e.downcast_ref::<TransactionTooLarge>() {
+                Some(too_large) if fetched.create_token_account => format!(
+                    "{} bytes, over the {}-byte limit",
+                    too_large.size, MAX_TRANSACTION_SIZE
+                ),
+                _ => return Err(e),
+            },
+        };
+        if !fetched.create_token_account {
+            return Err(anyhow::anyhow!(
+                "Transaction for nonce {} needs {}",
+                fetched.nonce,
+                exceeded
+            ));
+        }
+        tracing::info!(
+            "Transaction needs {}; creating the recipient's token account first",
+            exceeded
+        );
+        self.send_account_setup(fetched, budget).await?;
+        self.metrics.transaction_splits_total.inc();
+        let transaction = self
+            .transaction_builder
+            .build_transfer_transaction_after_setup(fetched, budget, payer, recent_blockhash)?;
+        if let Some(exceeded) = self.estimate_transaction(&transaction).await?.exceeded() {
+            return Err(anyhow::anyhow!(
+                "Transaction for nonce {} needs {} even without creating the token account",
+                fetched.nonce,
+                exceeded
+            ));
+        }
         Ok(transaction)
     }
+
+    /// Sends the creation of `fetched`'s recipient token account and waits
+    /// for it to confirm; the creation is idempotent, so a retry sends it
+    /// again harmlessly
+    async fn send_account_setup(
+        &self,
+        fetched: &FetchedTransfer,
+        budget: Option<&ComputeBudget>,
+    ) -> Result<()> {
+        let (recent_blockhash, last_valid_block_height) = self
+            .l2_client
+            .get_latest_blockhash_with_commitment(self.l2_client.commitment())
+            .await?;
+        let transaction = self
+            .transaction_builder
+            .build_account_setup_transaction(
+                fetched,
+                budget,
+                self.signers.active(),
+                recent_blockhash,
+            )
+            .ok_or_else(|| anyhow::anyhow!("Nonce {} has no token account", fetched.nonce))??;
+        let signature = transaction.signatures[0];
+        if self.dry_run {
+            tracing::info!(
+                "Dry run: token account of {} not created; the simulation may fail without it",
+                fetched.to_address
+            );
+            return Ok(());
+        }
+        match self
+            .send_and_confirm(&transaction, last_valid_block_height)
+            .await?
+        {
+            TransactionOutcome::Confirmed { .. } => {
+                tracing::info!(
+                    "Token account of {} created by {}",
+                    fetched.to_address,
+                    signature
+                );
+                let rent = self
+                    .rent_cache
+                    .minimum_balance(&self.l2_client, spl_token::state::Account::LEN)
+                    .await?;
+                self.record_token_account_rent(rent);
+                Ok(())
+            }
+            TransactionOutcome::Failed(err) => Err(anyhow::anyhow!(
+                "Token account setup {} for nonce {} failed: {}",
+                signature,
+                fetched.nonce,
+                err
+            )),
+            TransactionOutcome::Expired => Err(anyhow::anyhow!(
+                "Token account setup {} for nonce {} expired before landing",
+                signature,
+                fetched.nonce
+            )),
+        }
+    }
+
+    /// Sends an auxiliary `transaction` (not a relay) and waits for its
+    /// outcome, re-sending it meanwhile
+    pub(crate) async fn send_and_confirm(
+        &self,
+        transaction: &VersionedTransaction,
+        last_valid_block_height: u64,
+    ) -> Result<TransactionOutcome> {
+        self.await_send_rate().await;
+        self.l2_client.send_transaction(transaction).await?;
+        let rebroadcasts = AtomicU32::new(0);
+        tokio::select! {
+            outcome = self.await_transaction(&transaction.signatures[0], last_valid_block_height, self.l2_client.commitment()) => outcome,
+            () = self.rebroadcast(transaction, &rebroadcasts) => {
+                unreachable!("rebroadcasting never completes")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{entries::TransferEntry, merkle::MerkleProof};
+    use solana_sdk::signature::Keypair;
+    use std::str::FromStr;
+
+    fn builder(version: InstructionVersion, domain: Option<u64>) -> TransactionBuilder {
+        TransactionBuilder::new(
+            Pubkey::new_unique().into(),
+            Pubkey::new_unique().into(),
+            version,
+            domain,
+            InstructionAccounts::default(),
+        )
+        .unwrap()
+    }
+
+    /// Memo of `transaction`, if it kept one
+    fn memo_of(transaction: &VersionedTransaction) -> Option<String> {
+        let keys = transaction.message.static_account_keys();
+        transaction
+            .message
+            .instructions()
+            .iter()
+            .find(|instruction| keys[instruction.program_id_index as usize] == spl_memo::id())
+            .map(|instruction| String::from_utf8(instruction.data.clone()).unwrap())
+    }
+
+    #[test]
+    fn a_long_nonce_list_is_dropped_from_the_memo_to_fit_a_packet() {
+        let builder = builder(InstructionVersion::V1, None);
+        let payer = Keypair::new();
+        let mut transfer = FetchedTransfer::native(100, 5, Pubkey::new_unique());
+        transfer.coalesced = (0..10).collect();
+        let transaction = builder
+            .build_transfer_transaction(&transfer, None, None, &payer, Hash::default())
+            .unwrap();
+        assert!(memo_of(&transaction).unwrap().contains("\"nonces\""));
+
+        transfer.coalesced = (1_000_000_000..1_000_000_200).collect();
+        let transaction = builder
+            .build_transfer_transaction(&transfer, None, None, &payer, Hash::default())
+            .unwrap();
+        let memo = memo_of(&transaction).unwrap();
+        assert!(!memo.contains("nonces"), "{}", memo);
+        assert!(memo.contains("\"nonce\":100"), "{}", memo);
+        assert!(serialized_size(&transaction).unwrap() <= MAX_TRANSACTION_SIZE);
+    }
+
+    #[test]
+    fn the_memo_goes_before_the_transaction_is_refused() {
+        let builder = builder(InstructionVersion::V1, None);
+        let payer = Keypair::new();
+        let mut transfer = FetchedTransfer::native(1, 5, Pubkey::new_unique());
+        let mut dropped_memo = false;
+        // 证明越长交易越大：先去掉 memo，最后才拒绝
+        for siblings in 0.. {
+            transfer.proof = Some(MerkleProof {
+                leaf_index: 0,
+                siblings: vec![[7; 32]; siblings],
+            });
+            match builder.build_transfer_transaction(&transfer, None, None, &payer, Hash::default())
+            {
+                Ok(transaction) => dropped_memo |= memo_of(&transaction).is_none(),
+                Err(error) => {
+                    let too_large = error.downcast_ref::<TransactionTooLarge>().unwrap();
+                    assert_eq!(too_large.nonce, 1);
+                    assert!(too_large.size > MAX_TRANSACTION_SIZE);
+                    break;
+                }
+            }
+        }
+        assert!(dropped_memo);
+    }
+
+    /// Transfer with every field a version may encode set
+    fn full_transfer() -> FetchedTransfer {
+        let mut transfer = FetchedTransfer::native(9, 0x0102_0304_0506_0708, Pubkey::new_unique());
+        transfer.message_type = Some(2);
+        transfer.coalesced = vec![5, 6, 7, 8, 9];
+        transfer.expires_at = Some(1_700_000_000);
+        transfer.sender = Some(Pubkey::new_from_array([0xab; 32]));
+        transfer
+    }
+
+    #[test]
+    fn every_version_encodes_its_golden_bytes() {
+        let transfer = full_transfer();
+        let discriminator = [187, 90, 182, 138, 51, 248, 175, 98];
+        let amount = [8, 7, 6, 5, 4, 3, 2, 1];
+        let nonce = [9, 0, 0, 0, 0, 0, 0, 0];
+        let source_nonce = [5, 0, 0, 0, 0, 0, 0, 0];
+        let domain = [0x11, 0, 0, 0, 0, 0, 0, 0];
+        let expires_at = [0x00, 0xf1, 0x53, 0x65, 0, 0, 0, 0];
+        let sender = [0xab; 32];
+        let v2_fields = |version: u8| {
+            [
+                &discriminator[..],
+                &[version],
+                &amount,
+                &nonce,
+                &[2],
+                &source_nonce,
+            ]
+            .concat()
+        };
+
+        assert_eq!(
+            InstructionVersion::V1.encode(&transfer, 0x11),
+            [&discriminator[..], &amount, &nonce].concat()
+        );
+        assert_eq!(InstructionVersion::V2.encode(&transfer, 0x11), v2_fields(2));
+        assert_eq!(
+            InstructionVersion::V3.encode(&transfer, 0x11),
+            [&v2_fields(3)[..], &domain].concat()
+        );
+        assert_eq!(
+            InstructionVersion::V4.encode(&transfer, 0x11),
+            [&v2_fields(4)[..], &domain, &expires_at].concat()
+        );
+        let v5 = InstructionVersion::V5.encode(&transfer, 0x11);
+        assert_eq!(
+            v5,
+            [&v2_fields(5)[..], &domain, &expires_at, &sender].concat()
+        );
+        assert_eq!(v5.len(), 82);
+        let mut entry = transfer;
+        entry.entry = Some(TransferEntry {
+            index: 2,
+            count: 3,
+            pending: 1,
+        });
+        assert_eq!(
+            InstructionVersion::V6.encode(&entry, 0x11),
+            [&v2_fields(6)[..], &domain, &expires_at, &sender, &[2, 3]].concat()
+        );
+    }
+
+    #[test]
+    fn missing_fields_encode_as_their_placeholders() {
+        let transfer = FetchedTransfer::native(3, 1, Pubkey::new_unique());
+        let data = InstructionVersion::V6.encode(&transfer, 0);
+        // 未知类型为 255，源 nonce 为自身，单条转账是 1 条中的第 0 条
+        assert_eq!(data[25], UNKNOWN_MESSAGE_TYPE);
+        assert_eq!(data[26..34], 3u64.to_le_bytes());
+        assert_eq!(data[42..50], [0; 8]);
+        assert_eq!(data[50..82], [0; 32]);
+        assert_eq!(InstructionVersion::V6.decode_sender(&data), None);
+        assert_eq!(InstructionVersion::V6.decode_entry(&data), Some((0, 1)));
+    }
+
+    #[test]
+    fn each_version_decodes_only_its_own_layout() {
+        let transfer = full_transfer();
+        for version in 1..=6 {
+            let version = InstructionVersion::try_from(version).unwrap();
+            let data = version.encode(&transfer, 0x11);
+            assert!(is_relay_message(&data));
+            assert_eq!(
+                version.decode(&data),
+                Some((transfer.amount, transfer.nonce)),
+                "{:?}",
+                version
+            );
+        }
+        let v3 = InstructionVersion::V3.encode(&transfer, 0x11);
+        assert_eq!(InstructionVersion::V2.decode(&v3), None);
+        assert_eq!(
+            InstructionVersion::V5.decode_sender(&InstructionVersion::V5.encode(&transfer, 0)),
+            transfer.sender
+        );
+        assert!(InstructionVersion::try_from(7).is_err());
+    }
+
+    #[test]
+    fn a_known_program_refuses_versions_it_does_not_accept() {
+        let program: DestinationProgramId =
+            Pubkey::from_str("8TDycJBTEsyWV2w7vQnTmrePBaHHJDdDxxV3vekir2gC")
+                .unwrap()
+                .into();
+        InstructionVersion::V1
+            .check_compatibility(&program)
+            .unwrap();
+        let error = InstructionVersion::V2
+            .check_compatibility(&program)
+            .unwrap_err();
+        assert!(error.to_string().contains("supported: 1"), "{}", error);
+        InstructionVersion::V5
+            .check_compatibility(&Pubkey::new_unique().into())
+            .unwrap();
+    }
 }
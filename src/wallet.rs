@@ -0,0 +1,223 @@
+//! Signer wallet balance.
+//! The signer pays the fee of every relay transaction on the chain it sends
+//! to (L2, or L1 for `direction = "l2_to_l1"`). Its balance is read before
+//! every batch and, in `run`, every `balance_check_interval_secs`, exported
+//! as `relayer_signer_balance`, and checked against:
+//!
+//! - `min_signer_balance`: below it no batch is started; the batch fails with
+//!   the balance and the shortfall instead of a rejected transaction, an
+//!   alert is raised once and `relayer_signer_balance_low` is 1
+//! - `top_up_threshold` (`min_signer_balance` when unset): below it
+//!   `top_up_command` is run, at most once per `top_up_cooldown_secs`
+//!
+//! The top-up command is a program and its arguments, run without a shell
+//! and never awaited by the relayer. It gets `RELAYER_SIGNER`,
+//! `RELAYER_BALANCE`, `RELAYER_THRESHOLD`, `RELAYER_CHAIN` and
+//! `RELAYER_SOURCE` in its environment; its exit status is logged and
+//! counted in `relayer_top_ups_total` or `relayer_top_up_failures_total`.
+
+use crate::{alerts, config::RelayerConfig, Relayer};
+use anyhow::Result;
+use solana_sdk::pubkey::Pubkey;
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+use tokio::process::Command;
+
+/// Longest a top-up command may run before it is killed
+const TOP_UP_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// A batch refused because the signer's balance is below `min_signer_balance`
+#[derive(Debug, Clone)]
+pub struct BalanceTooLow {
+    pub signer: Pubkey,
+    pub chain: &'static str,
+    pub balance: u64,
+    pub minimum: u64,
+}
+
+impl fmt::Display for BalanceTooLow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Signer {} holds {} lamports on {}, below min_signer_balance {} (short by {}); top it up before relaying resumes",
+            self.signer,
+            self.balance,
+            self.chain,
+            self.minimum,
+            self.minimum - self.balance
+        )
+    }
+}
+
+impl std::error::Error for BalanceTooLow {}
+
+struct TopUpHook {
+    command: Vec<String>,
+    threshold: u64,
+    cooldown: Duration,
+    /// When the command last started
+    started_at: Mutex<Option<SystemTime>>,
+    running: Arc<AtomicBool>,
+}
+
+/// Balance thresholds of the signer wallet
+pub struct BalanceGuard {
+    /// Chain the signer pays fees on
+    chain: &'static str,
+    interval: Duration,
+    minimum: Option<u64>,
+    top_up: Option<TopUpHook>,
+    /// Whether the balance is below `minimum`
+    low: AtomicBool,
+}
+
+impl BalanceGuard {
+    pub fn from_config(config: &RelayerConfig) -> Self {
+        let top_up = config
+            .top_up_threshold
+            .or(config.min_signer_balance)
+            .filter(|_| !config.top_up_command.is_empty())
+            .map(|threshold| TopUpHook {
+                command: config.top_up_command.clone(),
+                threshold,
+                cooldown: Duration::from_secs(config.top_up_cooldown_secs),
+                started_at: Mutex::new(None),
+                running: Arc::new(AtomicBool::new(false)),
+            });
+        Self {
+            chain: config.direction.destination_chain(),
+            interval: Duration::from_secs(config.balance_check_interval_secs),
+            minimum: config.min_signer_balance,
+            top_up,
+            low: AtomicBool::new(false),
+        }
+    }
+}
+
+impl Relayer {
+    /// Exports `balance` of the active signer, alerts when it drops below
+    /// `min_signer_balance` and starts the top-up command when it is below
+    /// its threshold
+    pub(crate) fn observe_signer_balance(&self, signer: &Pubkey, balance: u64) {
+        let guard = &self.balance_guard;
+        self.metrics.signer_balance.set(balance);
+        if let Some(minimum) = guard.minimum {
+            let low = balance < minimum;
+            self.metrics.signer_balance_low.set(low as u64);
+            if guard.low.swap(low, Ordering::Relaxed) != low {
+                if low {
+                    alerts::raise(&format!(
+                        "{}: signer {} holds {} lamports on {}, below min_signer_balance {}; relaying is stopped",
+                        self.name(),
+                        signer,
+                        balance,
+                        guard.chain,
+                        minimum
+                    ));
+                } else {
+                    alerts::resolve(&format!(
+                        "{}: signer {} holds {} lamports on {}, back above min_signer_balance {}",
+                        self.name(),
+                        signer,
+                        balance,
+                        guard.chain,
+                        minimum
+                    ));
+                }
+            }
+        }
+        if let Some(hook) = &guard.top_up {
+            if balance < hook.threshold {
+                self.start_top_up(hook, signer, balance);
+            }
+        }
+    }
+
+    /// Fails with `BalanceTooLow` when `balance` of the active signer is
+    /// below `min_signer_balance`
+    pub(crate) fn ensure_min_balance(&self, signer: &Pubkey, balance: u64) -> Result<()> {
+        match self.balance_guard.minimum {
+            Some(minimum) if balance < minimum => Err(BalanceTooLow {
+                signer: *signer,
+                chain: self.balance_guard.chain,
+                balance,
+                minimum,
+            }
+            .into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Runs the top-up command in the background unless it is running or
+    /// started within its cooldown
+    fn start_top_up(&self, hook: &TopUpHook, signer: &Pubkey, balance: u64) {
+        let now = self.clock.now();
+        {
+            let mut started_at = hook.started_at.lock().unwrap();
+            let cooling = started_at.is_some_and(|at| self.clock.elapsed(at) < hook.cooldown);
+            if cooling || hook.running.load(Ordering::Relaxed) {
+                return;
+            }
+            *started_at = Some(now);
+        }
+        tracing::info!(
+            "Signer {} holds {} lamports on {}, below top_up_threshold {}; running top_up_command",
+            signer,
+            balance,
+            self.balance_guard.chain,
+            hook.threshold
+        );
+        let mut command = Command::new(&hook.command[0]);
+        command
+            .args(&hook.command[1..])
+            .env("RELAYER_SIGNER", signer.to_string())
+            .env("RELAYER_BALANCE", balance.to_string())
+            .env("RELAYER_THRESHOLD", hook.threshold.to_string())
+            .env("RELAYER_CHAIN", self.balance_guard.chain)
+            .env("RELAYER_SOURCE", &self.source_id)
+            .kill_on_drop(true);
+        let running = hook.running.clone();
+        let metrics = self.metrics.clone();
+        running.store(true, Ordering::Relaxed);
+        tokio::spawn(async move {
+            let status = match tokio::time::timeout(TOP_UP_TIMEOUT, command.status()).await {
+                Ok(Ok(status)) if status.success() => Ok(()),
+                Ok(Ok(status)) => Err(format!("exited with {}", status)),
+                Ok(Err(e)) => Err(e.to_string()),
+                // 超时后丢弃子进程，kill_on_drop 会结束它
+                Err(_) => Err(format!("timed out after {:?}", TOP_UP_TIMEOUT)),
+            };
+            if status.is_ok() {
+                metrics.top_ups_total.inc();
+                tracing::info!("top_up_command finished");
+            }
+            if let Err(e) = status {
+                metrics.top_up_failures_total.inc();
+                tracing::warn!("top_up_command failed: {}", e);
+            }
+            running.store(false, Ordering::Relaxed);
+        });
+    }
+
+    /// Reads the active signer's balance every `balance_check_interval_secs`
+    pub(crate) async fn watch_signer_balance(&self) -> Result<()> {
+        let interval = self.balance_guard.interval;
+        if interval.is_zero() {
+            return futures::future::pending().await;
+        }
+        loop {
+            let signer = self.signers.active().pubkey();
+            match self.l2_client.get_balance(&signer).await {
+                Ok(balance) => self.observe_signer_balance(&signer, balance),
+                Err(e) => tracing::warn!("failed to read the signer balance: {}", e),
+            }
+            self.clock.sleep(interval).await;
+        }
+    }
+}
@@ -0,0 +1,226 @@
+//! Warm-up transaction.
+//! Before relaying, `run` sends every active source's signer a 1-lamport
+//! self-transfer, with a memo naming the source, through the stages a relay
+//! goes through: blockhash, the compute-unit price of `priority_fee_strategy`,
+//! fee estimate and balance check, the
+//! prioritization fee read of fee-aware batching, submission, and
+//! confirmation with rebroadcasts. Relaying starts once it confirms; a
+//! failure stops the relayer with the stage that failed and a hint. The fee
+//! of every warm-up is kept in `state_dir` (`warmup_costs`) and counted in
+//! the L2 fees of `report`. `warmup_transaction = false` skips it (and its
+//! fee); `run --once` and standbys never send it.
+
+use crate::{fees, inflight::TransactionOutcome, Relayer};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_sdk::{system_instruction, transaction::Transaction};
+use std::{fmt, sync::atomic::AtomicU32};
+
+/// State key holding the fees paid for warm-up transactions
+pub const WARMUP_COSTS_KEY: &str = "warmup_costs";
+
+/// Warm-ups kept in `warmup_costs`
+const WARMUP_COSTS_LIMIT: usize = 1000;
+
+/// Stage of the submission stack a warm-up went through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WarmupStage {
+    Blockhash,
+    FeeEstimate,
+    PriorityFee,
+    Send,
+    Confirm,
+}
+
+impl WarmupStage {
+    fn hint(self) -> &'static str {
+        match self {
+            WarmupStage::Blockhash => "check l2_url and that the L2 node is up",
+            WarmupStage::FeeEstimate => {
+                "fund the signer key, or check that the node answers getFeeForMessage"
+            }
+            WarmupStage::PriorityFee => {
+                "the node must answer getRecentPrioritizationFees for batch_strategy = \"fee_aware\""
+            }
+            WarmupStage::Send => {
+                "the node refused the transaction; check the signer key and the cluster l2_url points at"
+            }
+            WarmupStage::Confirm => {
+                "the transaction did not land; check the node's health and the rebroadcast settings"
+            }
+        }
+    }
+}
+
+impl fmt::Display for WarmupStage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            WarmupStage::Blockhash => "blockhash",
+            WarmupStage::FeeEstimate => "fee estimate",
+            WarmupStage::PriorityFee => "priority fee",
+            WarmupStage::Send => "send",
+            WarmupStage::Confirm => "confirm",
+        })
+    }
+}
+
+/// The warm-up transaction failed; relaying does not start
+#[derive(Debug)]
+pub struct WarmupFailed {
+    pub source: String,
+    pub stage: WarmupStage,
+    pub cause: String,
+}
+
+impl fmt::Display for WarmupFailed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Warm-up transaction of source {} failed at the {} stage: {} ({})",
+            self.source,
+            self.stage,
+            self.cause,
+            self.stage.hint()
+        )
+    }
+}
+
+impl std::error::Error for WarmupFailed {}
+
+/// Fee paid for one warm-up transaction
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WarmupCost {
+    pub signature: String,
+    pub fee: u64,
+    pub slot: u64,
+    pub confirmed_at: u64,
+}
+
+impl Relayer {
+    /// Sends the warm-up transaction and waits for it to confirm
+    pub(crate) async fn warm_up(&self) -> Result<WarmupCost> {
+        let failed = |stage, cause: String| WarmupFailed {
+            source: self.source_id.clone(),
+            stage,
+            cause,
+        };
+        let payer = self.signers.active();
+        tracing::info!(
+            "Sending the warm-up transaction of source {} from {}...",
+            self.source_id,
+            payer.pubkey()
+        );
+
+        let (recent_blockhash, last_valid_block_height) = self
+            .l2_client
+            .get_latest_blockhash_with_commitment(self.l2_client.commitment())
+            .await
+            .map_err(|e| failed(WarmupStage::Blockhash, e.to_string()))?;
+        let memo = serde_json::json!({ "warmup": self.source_id }).to_string();
+        let mut instructions = vec![
+            system_instruction::transfer(&payer.pubkey(), &payer.pubkey(), 1),
+            spl_memo::build_memo(memo.as_bytes(), &[]),
+        ];
+        // 与转发交易一样附带计算单元价格
+        if let Some(budget) = self.compute_budget().await {
+            tracing::debug!(
+                "Warm-up compute unit price: {} micro-lamports per CU",
+                budget.unit_price
+            );
+            instructions.extend(budget.instructions());
+        }
+        let transaction = Transaction::new_signed_with_payer(
+            &instructions,
+            Some(&payer.pubkey()),
+            &[payer],
+            recent_blockhash,
+        );
+
+        let fee = self
+            .l2_client
+            .get_fee_for_message(&transaction.message)
+            .await
+            .map_err(|e| failed(WarmupStage::FeeEstimate, e.to_string()))?;
+        let balance = self
+            .l2_client
+            .get_balance(&payer.pubkey())
+            .await
+            .map_err(|e| failed(WarmupStage::FeeEstimate, e.to_string()))?;
+        if balance < fee + 1 {
+            return Err(failed(
+                WarmupStage::FeeEstimate,
+                format!(
+                    "balance {} lamports does not cover the {} lamport fee",
+                    balance, fee
+                ),
+            )
+            .into());
+        }
+        tracing::info!(
+            "Warm-up estimated fee: {} lamports (balance {})",
+            fee,
+            balance
+        );
+        if let Some(batching) = &self.batching {
+            let price = self
+                .fee_price(batching)
+                .await
+                .map_err(|e| failed(WarmupStage::PriorityFee, e.to_string()))?;
+            tracing::info!(
+                "Warm-up prioritization fee: {} micro-lamports per CU",
+                price
+            );
+        }
+
+        let signature = transaction.signatures[0];
+        self.l2_client
+            .send_transaction(&transaction)
+            .await
+            .map_err(|e| failed(WarmupStage::Send, e.to_string()))?;
+        let rebroadcasts = AtomicU32::new(0);
+        let outcome = tokio::select! {
+            outcome = self.await_transaction(&signature, last_valid_block_height, self.l2_client.commitment()) => outcome,
+            () = self.rebroadcast(&transaction, &rebroadcasts) => {
+                unreachable!("rebroadcasting never completes")
+            }
+        }
+        .map_err(|e| failed(WarmupStage::Confirm, e.to_string()))?;
+        let slot = match outcome {
+            TransactionOutcome::Confirmed { slot } => slot,
+            TransactionOutcome::Failed(err) => {
+                return Err(failed(
+                    WarmupStage::Confirm,
+                    format!("transaction {} failed: {}", signature, err),
+                )
+                .into())
+            }
+            TransactionOutcome::Expired => {
+                return Err(failed(
+                    WarmupStage::Confirm,
+                    format!("transaction {} expired before landing", signature),
+                )
+                .into())
+            }
+        };
+
+        let cost = WarmupCost {
+            signature: signature.to_string(),
+            fee,
+            slot,
+            confirmed_at: self.clock.unix_timestamp(),
+        };
+        let mut costs: Vec<WarmupCost> = self.state.get(WARMUP_COSTS_KEY)?.unwrap_or_default();
+        costs.push(cost.clone());
+        let excess = costs.len().saturating_sub(WARMUP_COSTS_LIMIT);
+        costs.drain(..excess);
+        self.state.put(WARMUP_COSTS_KEY, &costs)?;
+        fees::record_warmup(&self.state, &cost)?;
+        tracing::info!(
+            "Warm-up transaction confirmed at slot {} ({} lamports): {}",
+            slot,
+            fee,
+            signature
+        );
+        Ok(cost)
+    }
+}
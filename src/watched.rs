@@ -0,0 +1,506 @@
+//! Watched-account availability.
+//! An RPC node may briefly report the watched account as missing, or the
+//! account may be closed and recreated. While it is missing the monitor keeps
+//! polling at a slower pace and alerts once it has been gone for too long.
+//! When it reappears its owner must match what was seen before. A nonce
+//! below the highest one seen, e.g. after an L1 reorg dropped the slots that
+//! raised it, is treated as a rewind: it is alerted with the slots of both
+//! reads, nothing new is queued and the queued nonces from the current one
+//! on are not submitted until L1 passes the highest nonce again. While the
+//! L1 program reports itself paused (two-counter layout) nothing is
+//! submitted.
+//!
+//! The layout is detected from the data size on the first read and kept in
+//! the state (`watched_layout`). When the L1 program reallocs the account the
+//! layout is detected again; a size of no known layout is never decoded, and
+//! relaying is held with an alert until the account is readable again.
+
+use crate::{
+    alerts, context_slot,
+    models::{
+        account,
+        message::{NonceStatus, WatchedLayout},
+    },
+    Relayer,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use std::{fmt, sync::Mutex, time::Duration};
+
+/// State key of the layout the watched account was last read in
+pub const WATCHED_LAYOUT_KEY: &str = "watched_layout";
+
+/// The watched account does not exist (or the RPC node does not know it yet)
+#[derive(Debug)]
+pub struct WatchedAccountMissing(pub Pubkey);
+
+impl fmt::Display for WatchedAccountMissing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Watched account {} not found", self.0)
+    }
+}
+
+impl std::error::Error for WatchedAccountMissing {}
+
+/// The watched account is owned by another program than when first read;
+/// stops the monitor rather than being retried
+#[derive(Debug)]
+pub struct WatchedOwnerChanged {
+    pub address: Pubkey,
+    pub previous: Pubkey,
+    pub owner: Pubkey,
+}
+
+impl fmt::Display for WatchedOwnerChanged {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Watched account {} changed owner from {} to {}",
+            self.address, self.previous, self.owner
+        )
+    }
+}
+
+impl std::error::Error for WatchedOwnerChanged {}
+
+/// Data size of the watched account and the layout it is read in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WatchedShape {
+    pub data_len: usize,
+    pub layout: WatchedLayout,
+}
+
+#[derive(Default)]
+struct Observed {
+    /// Owner of the account when it was first read
+    owner: Option<Pubkey>,
+    shape: Option<WatchedShape>,
+    /// Data size of a layout this relayer does not know; relaying is held
+    /// while it is set
+    unknown_size: Option<usize>,
+    highest_nonce: u64,
+    /// Context slot of the read that first showed `highest_nonce`
+    highest_slot: Option<u64>,
+    /// L1 nonce while it is below `highest_nonce`
+    rewound_to: Option<u64>,
+    /// Unix time at which the account was first reported missing
+    missing_since: Option<u64>,
+    alerted: bool,
+    /// Paused flag of the last read
+    paused: bool,
+    /// Context slot of the last read
+    slot: Option<u64>,
+}
+
+/// What one read of the watched account showed
+#[derive(Debug)]
+struct Observation {
+    nonce: u64,
+    paused: bool,
+    /// Shape the account is read in from now on, when it changed
+    reshaped: Option<WatchedShape>,
+    /// Whether this read started a rewind
+    rewound: bool,
+}
+
+pub struct WatchedAccount {
+    /// Poll interval while the account is missing
+    missing_poll: Duration,
+    /// How long the account may be missing before an alert is raised
+    alert_after: Duration,
+    /// Discriminator of the two-counter layout (`discriminators.watched`)
+    discriminator: Option<[u8; 8]>,
+    observed: Mutex<Observed>,
+}
+
+impl WatchedAccount {
+    /// `shape` is the one persisted by an earlier run, if any
+    pub fn new(
+        missing_poll: Duration,
+        alert_after: Duration,
+        discriminator: Option<[u8; 8]>,
+        shape: Option<WatchedShape>,
+    ) -> Self {
+        Self {
+            missing_poll,
+            alert_after,
+            discriminator,
+            observed: Mutex::new(Observed {
+                shape,
+                ..Observed::default()
+            }),
+        }
+    }
+
+    /// Whether relaying is held: the last read reported the L1 program as
+    /// paused or showed a layout this relayer does not know
+    pub fn paused(&self) -> bool {
+        let observed = self.observed.lock().unwrap();
+        observed.paused || observed.unknown_size.is_some()
+    }
+
+    /// L1 nonce while it is below the highest one seen; queued nonces from
+    /// it on are held
+    pub fn rewound_to(&self) -> Option<u64> {
+        self.observed.lock().unwrap().rewound_to
+    }
+
+    /// Context slot of the last successful read
+    pub fn last_slot(&self) -> Option<u64> {
+        self.observed.lock().unwrap().slot
+    }
+
+    /// Decodes watched-account data read elsewhere, e.g. from a verifier, in
+    /// the current layout; None when its size does not match
+    pub fn decode(&self, data: &[u8]) -> Option<NonceStatus> {
+        let layout = match self.observed.lock().unwrap().shape {
+            Some(shape) if shape.data_len == data.len() => shape.layout,
+            Some(_) => return None,
+            None => WatchedLayout::detect(data.len()).unwrap_or(WatchedLayout::Legacy),
+        };
+        self.check_discriminator(data, layout).ok()?;
+        NonceStatus::parse(data, layout).ok()
+    }
+
+    /// Checks the discriminator of data in the two-counter layout
+    fn check_discriminator(&self, data: &[u8], layout: WatchedLayout) -> Result<()> {
+        match &self.discriminator {
+            Some(expected) if layout == WatchedLayout::TwoCounter => {
+                account::check_discriminator(data, expected)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Decodes a successful read at `slot` and checks it against the earlier
+    /// ones. When the data size changes the layout is detected again; a size
+    /// of no known layout holds relaying at the highest nonce seen
+    fn observe(
+        &self,
+        address: &Pubkey,
+        owner: Pubkey,
+        data: &[u8],
+        slot: u64,
+    ) -> Result<Observation> {
+        let mut observed = self.observed.lock().unwrap();
+        let reappeared = observed.missing_since.take().is_some();
+        observed.alerted = false;
+
+        match observed.owner {
+            None => observed.owner = Some(owner),
+            Some(previous) if previous != owner => {
+                let changed = WatchedOwnerChanged {
+                    address: *address,
+                    previous,
+                    owner,
+                };
+                alerts::raise(&changed.to_string());
+                return Err(changed.into());
+            }
+            Some(_) => {}
+        }
+
+        let data_len = data.len();
+        let (shape, reshaped) = match observed.shape {
+            Some(shape) if shape.data_len == data_len => (shape, false),
+            None => {
+                // 过短的数据在下面解码时报错
+                let layout = WatchedLayout::detect(data_len).unwrap_or(WatchedLayout::Legacy);
+                (WatchedShape { data_len, layout }, true)
+            }
+            Some(shape) => match WatchedLayout::after_resize(data_len) {
+                Some(layout) => {
+                    tracing::info!(
+                        "Watched account {} resized from {} to {} bytes; reading it in the {} layout (was {})",
+                        address, shape.data_len, data_len, layout, shape.layout
+                    );
+                    (WatchedShape { data_len, layout }, true)
+                }
+                None => {
+                    // 未知布局不解码，停在已见过的最高 nonce
+                    if observed.unknown_size != Some(data_len) {
+                        observed.unknown_size = Some(data_len);
+                        alerts::raise(&format!(
+                            "Watched account {} resized from {} to {} bytes, a layout this relayer does not know; relaying is held at nonce {} until the relayer is upgraded",
+                            address, shape.data_len, data_len, observed.highest_nonce
+                        ));
+                    }
+                    return Ok(Observation {
+                        nonce: observed.highest_nonce,
+                        paused: true,
+                        reshaped: None,
+                        rewound: false,
+                    });
+                }
+            },
+        };
+        self.check_discriminator(data, shape.layout)
+            .map_err(|e| anyhow::anyhow!("Watched account {}: {}", address, e))?;
+        let status = NonceStatus::parse(data, shape.layout)?;
+        let nonce = status.nonce;
+        if observed.unknown_size.take().is_some() {
+            tracing::info!(
+                "Watched account {} is readable again ({} layout, {} bytes) at nonce {}",
+                address,
+                shape.layout,
+                data_len,
+                nonce
+            );
+        }
+        observed.shape = Some(shape);
+
+        if reappeared {
+            tracing::info!("Watched account {} is back at nonce {}", address, nonce);
+        }
+        // 低于已见过的最高 nonce 可能是 L1 重组，已入队的更高 nonce 暂不提交
+        let highest = observed.highest_nonce;
+        let rewound = nonce < highest && observed.rewound_to.is_none();
+        if rewound {
+            let seen_at = observed
+                .highest_slot
+                .map(|slot| format!(" (slot {})", slot))
+                .unwrap_or_default();
+            alerts::raise(&format!(
+                "Watched account {} went back from nonce {}{} to {} (slot {}), possibly an L1 reorg; nonces from {} are held until L1 passes {} again",
+                address, highest, seen_at, nonce, slot, nonce, highest
+            ));
+        }
+        if nonce < highest {
+            observed.rewound_to = Some(nonce);
+        } else if observed.rewound_to.take().is_some() {
+            alerts::resolve(&format!(
+                "Watched account {} reached nonce {} again (now {}, slot {}); held nonces are submitted",
+                address, highest, nonce, slot
+            ));
+        }
+        if nonce > highest {
+            observed.highest_nonce = nonce;
+            observed.highest_slot = Some(slot);
+        }
+
+        if status.paused != observed.paused {
+            observed.paused = status.paused;
+            if status.paused {
+                let in_nonce = status
+                    .in_nonce
+                    .map(|in_nonce| format!(", inbound nonce {}", in_nonce))
+                    .unwrap_or_default();
+                alerts::raise(&format!(
+                    "L1 program reports bridging paused (watched account {} at nonce {}{}); relaying is held until it resumes",
+                    address, nonce, in_nonce
+                ));
+            } else {
+                tracing::info!("L1 program resumed bridging at nonce {}", nonce);
+            }
+        }
+        Ok(Observation {
+            nonce,
+            paused: status.paused,
+            reshaped: reshaped.then_some(shape),
+            rewound,
+        })
+    }
+}
+
+impl Relayer {
+    /// Reads the nonce of the L1 watched account; fails with
+    /// `WatchedAccountMissing` if the account does not exist
+    pub(crate) async fn read_l1_nonce(&self) -> Result<u64> {
+        // 获取 L1 watched account 的 nonce，配置了账户缓存时先读缓存
+        let (owner, data, slot) = match self.cached_watched_account().await {
+            Some(cached) => (cached.owner, cached.data, cached.slot),
+            None => {
+                let response = context_slot::read_account(
+                    &self.l1_client,
+                    self.watched_account.pubkey(),
+                    self.context_slots.l1.get(),
+                )
+                .await
+                .map_err(|e| self.l1_read_error(e))?;
+                self.observe_l1_rpc_slot(response.context.slot);
+                self.context_slots.l1.observe(response.context.slot);
+                let account = response
+                    .value
+                    .ok_or(WatchedAccountMissing(*self.watched_account.pubkey()))?;
+                (account.owner, account.data, response.context.slot)
+            }
+        };
+        let observation =
+            self.watched
+                .observe(self.watched_account.pubkey(), owner, &data, slot)?;
+        self.watched.observed.lock().unwrap().slot = Some(slot);
+        if let Some(shape) = observation.reshaped {
+            self.state.put(WATCHED_LAYOUT_KEY, &shape)?;
+        }
+        self.metrics.l1_paused.set(observation.paused as u64);
+        if observation.rewound {
+            self.metrics.l1_rewinds_total.inc();
+        }
+        Ok(observation.nonce)
+    }
+
+    /// Logs a missing watched account, alerts once it has been missing for
+    /// too long, and waits for the slower poll interval
+    pub(crate) async fn watched_account_missing(&self, missing: &WatchedAccountMissing) {
+        let now = self.clock.unix_timestamp();
+        let alert = {
+            let mut observed = self.watched.observed.lock().unwrap();
+            let since = *observed.missing_since.get_or_insert(now);
+            let missing_for = now.saturating_sub(since);
+            tracing::warn!(
+                "{} (missing for {}s), polling every {:?}",
+                missing,
+                missing_for,
+                self.watched.missing_poll
+            );
+            let alert = !observed.alerted && missing_for >= self.watched.alert_after.as_secs();
+            observed.alerted |= alert;
+            alert.then_some(missing_for)
+        };
+
+        if let Some(missing_for) = alert {
+            alerts::raise(&format!("{} for {}s", missing, missing_for));
+        }
+        self.clock.sleep(self.watched.missing_poll).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::message::TWO_COUNTER_STATUS_SIZE;
+
+    fn watched(shape: Option<WatchedShape>) -> WatchedAccount {
+        WatchedAccount::new(Duration::from_secs(1), Duration::from_secs(60), None, shape)
+    }
+
+    fn legacy(nonce: u64, len: usize) -> Vec<u8> {
+        let mut data = nonce.to_le_bytes().to_vec();
+        data.resize(len, 0);
+        data
+    }
+
+    fn two_counter(out_nonce: u64) -> Vec<u8> {
+        let mut data = vec![0u8; TWO_COUNTER_STATUS_SIZE];
+        data[16..24].copy_from_slice(&out_nonce.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn the_layout_is_detected_once_and_again_after_a_realloc() {
+        let (address, owner) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let account = watched(None);
+        let first = account
+            .observe(&address, owner, &legacy(3, 16), 10)
+            .unwrap();
+        assert_eq!(first.nonce, 3);
+        assert_eq!(
+            first.reshaped,
+            Some(WatchedShape {
+                data_len: 16,
+                layout: WatchedLayout::Legacy
+            })
+        );
+        let same = account
+            .observe(&address, owner, &legacy(4, 16), 11)
+            .unwrap();
+        assert_eq!((same.nonce, same.reshaped), (4, None));
+
+        let resized = account
+            .observe(&address, owner, &two_counter(5), 12)
+            .unwrap();
+        assert_eq!(resized.nonce, 5);
+        assert_eq!(
+            resized.reshaped.map(|shape| shape.layout),
+            Some(WatchedLayout::TwoCounter)
+        );
+    }
+
+    #[test]
+    fn an_unknown_size_holds_relaying_at_the_highest_nonce() {
+        let (address, owner) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let account = watched(Some(WatchedShape {
+            data_len: 8,
+            layout: WatchedLayout::Legacy,
+        }));
+        account.observe(&address, owner, &legacy(7, 8), 1).unwrap();
+        // 未知长度的数据不解码，即使前 8 字节看起来像 nonce
+        let held = account
+            .observe(&address, owner, &legacy(900, 40), 2)
+            .unwrap();
+        assert_eq!(held.nonce, 7);
+        assert!(held.paused);
+        assert!(account.paused());
+        assert_eq!(
+            account.decode(&legacy(900, 40)).map(|status| status.nonce),
+            None
+        );
+
+        let back = account.observe(&address, owner, &legacy(8, 8), 3).unwrap();
+        assert_eq!(back.nonce, 8);
+        assert!(!account.paused());
+    }
+
+    #[test]
+    fn a_lower_nonce_is_a_rewind_until_l1_passes_the_highest_again() {
+        let (address, owner) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let account = watched(None);
+        account.observe(&address, owner, &legacy(10, 8), 1).unwrap();
+        assert!(
+            account
+                .observe(&address, owner, &legacy(8, 8), 2)
+                .unwrap()
+                .rewound
+        );
+        assert!(
+            !account
+                .observe(&address, owner, &legacy(9, 8), 3)
+                .unwrap()
+                .rewound
+        );
+        assert_eq!(account.rewound_to(), Some(9));
+        account.observe(&address, owner, &legacy(10, 8), 4).unwrap();
+        assert_eq!(account.rewound_to(), None);
+    }
+
+    #[test]
+    fn a_new_owner_is_refused() {
+        let address = Pubkey::new_unique();
+        let account = watched(None);
+        account
+            .observe(&address, Pubkey::new_unique(), &legacy(1, 8), 1)
+            .unwrap();
+        let error = account
+            .observe(&address, Pubkey::new_unique(), &legacy(1, 8), 2)
+            .unwrap_err();
+        assert!(error.to_string().contains("changed owner"), "{}", error);
+    }
+
+    #[cfg(feature = "testing")]
+    mod harness {
+        use crate::testing::{self, Harness};
+        use solana_sdk::signature::{Keypair, Signer};
+
+        #[tokio::test(start_paused = true)]
+        async fn relaying_is_held_while_l1_reports_bridging_paused() {
+            let harness = Harness::new(testing::config()).unwrap();
+            let recipient = Keypair::new().pubkey();
+            harness.add_transfers(0..2, 1_000_000, &recipient);
+            harness.l1.set_paused(true);
+            let relayer = harness.relayer().await.unwrap();
+
+            let outcome = relayer.run_once().await.unwrap();
+            assert_eq!(outcome.relayed, 0);
+            assert!(relayer.watched.paused());
+            assert_eq!(relayer.metrics.l1_paused.get(), 1);
+            assert_eq!(harness.l2.relayed_nonce(), 0);
+
+            harness.l1.set_paused(false);
+            relayer.run_once().await.unwrap();
+            assert!(!relayer.watched.paused());
+            assert_eq!(harness.l2.relayed_nonce(), 2);
+            assert_eq!(harness.l2.balance(&recipient), 2_000_000);
+        }
+    }
+}
@@ -0,0 +1,45 @@
+//! Relays one deposit end to end through `solana-test-validator`.
+//! Ignored by default: it needs the validator on `PATH` (or
+//! `E2E_VALIDATOR`) and a build of the L2 bridge program at
+//! `E2E_L2_PROGRAM` (default `target/deploy/l2_bridge.so`). `E2E_CONFIG`
+//! relays with a config file instead of a generated one.
+//!
+//! ```text
+//! E2E_L2_PROGRAM=../l2-bridge/target/deploy/l2_bridge.so \
+//!     cargo test --features testing --test e2e_validator -- --ignored
+//! ```
+
+use sol_bridge_relayer::{run_e2e, testing, E2eOptions, RelayerConfig, ValidatorOptions};
+use solana_sdk::native_token::LAMPORTS_PER_SOL;
+use std::{path::PathBuf, time::Duration};
+
+#[tokio::test]
+#[ignore = "starts solana-test-validator and needs a build of the L2 bridge program"]
+async fn relays_a_deposit_through_the_test_validator() {
+    let config = match std::env::var("E2E_CONFIG") {
+        Ok(path) => RelayerConfig::load(path, None, None).unwrap(),
+        Err(_) => testing::config(),
+    };
+    let l2_program = std::env::var("E2E_L2_PROGRAM").map_or_else(
+        |_| PathBuf::from("target/deploy/l2_bridge.so"),
+        PathBuf::from,
+    );
+    let mut validator = ValidatorOptions::default();
+    if let Ok(binary) = std::env::var("E2E_VALIDATOR") {
+        validator.binary = PathBuf::from(binary);
+    }
+    let options = E2eOptions {
+        validator,
+        l2_program,
+        l1_program: None,
+        transfers: 1,
+        amount: LAMPORTS_PER_SOL,
+        two_validators: false,
+        timeout: Duration::from_secs(120),
+    };
+
+    let report = run_e2e(&config, &options).await.unwrap();
+    assert!(report.passed, "{}", report.to_text());
+    assert_eq!(report.l2_nonce, 1);
+    assert_eq!(report.transfers[0].balance, LAMPORTS_PER_SOL);
+}
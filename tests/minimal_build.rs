@@ -0,0 +1,24 @@
+//! Smoke test of the minimal build, without the admin API, OTLP export and
+//! webhooks: the core pipeline relays through the test doubles.
+//!
+//! ```text
+//! cargo test --no-default-features --features testing --test minimal_build
+//! ```
+
+#![cfg(not(any(feature = "admin", feature = "telemetry", feature = "webhooks")))]
+
+use sol_bridge_relayer::testing::{self, Harness};
+use solana_sdk::signature::{Keypair, Signer};
+
+#[tokio::test(start_paused = true)]
+async fn the_core_pipeline_relays_without_optional_features() {
+    let harness = Harness::new(testing::config()).unwrap();
+    let recipient = Keypair::new().pubkey();
+    harness.add_transfers(0..2, 1_000_000_000, &recipient);
+
+    let engine = harness.engine().await.unwrap();
+    let outcomes = engine.run_once().await.unwrap();
+    assert!(outcomes.iter().all(|outcome| outcome.error.is_none()));
+    assert_eq!(harness.l2.relayed_nonce(), 2);
+    assert_eq!(harness.l2.balance(&recipient), 2_000_000_000);
+}